@@ -0,0 +1,514 @@
+use crate::connectivity::{self, Connectivity};
+use crate::conversations;
+use crate::db::{self, Db};
+use crate::hashes;
+use crate::http::Http;
+use crate::redaction::Redactor;
+use crate::usage::{self, Tokenizers};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+const OPENAI_CHAT_URL: &str = "https://api.openai.com/v1/chat/completions";
+const GEMINI_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+const OFFLINE: &str = "You're offline";
+// How long a cached answer is reused
+const CACHE_TTL_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChatMessage {
+    // "system", "user" or "assistant"
+    pub role: String,
+    pub content: String,
+}
+
+// Sampling settings for one request. Anything left unset falls back to the
+// conversation's stored overrides and then the app settings.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ModelParams {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub system_prompt: Option<String>,
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChatReply {
+    pub provider: String,
+    pub model: String,
+    pub text: String,
+    pub usage: Option<Usage>,
+    // Served from the response cache without calling the provider
+    #[serde(default)]
+    pub cached: bool,
+}
+
+fn error_message(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| v["error"]["message"].as_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| body.chars().take(300).collect())
+}
+
+async fn read_json(response: reqwest::Response, provider: &str) -> Result<Value, String> {
+    let status = response.status();
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("{} error ({}): {}", provider, status, error_message(&body)));
+    }
+    serde_json::from_str(&body).map_err(|e| format!("Invalid {} response: {}", provider, e))
+}
+
+async fn openai_chat(http: &Http, api_key: &str, model: &str, messages: &[ChatMessage], params: &ModelParams) -> Result<ChatReply, String> {
+    let mut body = json!({ "model": model, "messages": messages });
+    if let Some(temperature) = params.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = params.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if let Some(max_tokens) = params.max_tokens {
+        body["max_tokens"] = json!(max_tokens);
+    }
+    let response = http
+        .send("openai", "chat", |client| client.post(OPENAI_CHAT_URL).bearer_auth(api_key).json(&body))
+        .await?;
+    let value = read_json(response, "openai").await?;
+
+    Ok(ChatReply {
+        provider: "openai".to_string(),
+        model: value["model"].as_str().unwrap_or(model).to_string(),
+        text: value["choices"][0]["message"]["content"].as_str().unwrap_or_default().to_string(),
+        usage: value["usage"].as_object().map(|usage| Usage {
+            prompt_tokens: usage.get("prompt_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+            completion_tokens: usage.get("completion_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+        }),
+        cached: false,
+    })
+}
+
+async fn gemini_chat(http: &Http, api_key: &str, model: &str, messages: &[ChatMessage], params: &ModelParams) -> Result<ChatReply, String> {
+    // Gemini takes system prompts separately and calls the assistant "model"
+    let system: Vec<&str> = messages.iter().filter(|m| m.role == "system").map(|m| m.content.as_str()).collect();
+    let contents: Vec<Value> = messages
+        .iter()
+        .filter(|m| m.role != "system")
+        .map(|m| {
+            let role = if m.role == "assistant" { "model" } else { "user" };
+            json!({ "role": role, "parts": [{ "text": m.content }] })
+        })
+        .collect();
+
+    let mut body = json!({ "contents": contents });
+    if !system.is_empty() {
+        body["systemInstruction"] = json!({ "parts": [{ "text": system.join("\n\n") }] });
+    }
+    let mut config = serde_json::Map::new();
+    if let Some(temperature) = params.temperature {
+        config.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(top_p) = params.top_p {
+        config.insert("topP".to_string(), json!(top_p));
+    }
+    if let Some(max_tokens) = params.max_tokens {
+        config.insert("maxOutputTokens".to_string(), json!(max_tokens));
+    }
+    if !config.is_empty() {
+        body["generationConfig"] = Value::Object(config);
+    }
+
+    let url = format!("{}/{}:generateContent", GEMINI_URL, model);
+    let response = http
+        .send("gemini", "chat", |client| client.post(&url).query(&[("key", api_key)]).json(&body))
+        .await?;
+    let value = read_json(response, "gemini").await?;
+
+    let text = value["candidates"][0]["content"]["parts"]
+        .as_array()
+        .map(|parts| parts.iter().filter_map(|p| p["text"].as_str()).collect::<Vec<_>>().join(""))
+        .unwrap_or_default();
+    Ok(ChatReply {
+        provider: "gemini".to_string(),
+        model: model.to_string(),
+        text,
+        usage: value["usageMetadata"].as_object().map(|usage| Usage {
+            prompt_tokens: usage.get("promptTokenCount").and_then(|v| v.as_u64()).unwrap_or(0),
+            completion_tokens: usage.get("candidatesTokenCount").and_then(|v| v.as_u64()).unwrap_or(0),
+        }),
+        cached: false,
+    })
+}
+
+pub async fn chat(
+    http: &Http,
+    provider: &str,
+    model: &str,
+    api_key: &str,
+    messages: &[ChatMessage],
+    params: &ModelParams,
+) -> Result<ChatReply, String> {
+    if api_key.trim().is_empty() {
+        return Err(format!("No API key configured for {}", provider));
+    }
+    match provider {
+        "openai" => openai_chat(http, api_key, model, messages, params).await,
+        "gemini" => gemini_chat(http, api_key, model, messages, params).await,
+        other => Err(format!("Unknown AI provider: {}", other)),
+    }
+}
+
+// Whitespace differences shouldn't miss the cache
+fn normalize_prompt(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Same provider, model, sampling settings, prompt and attached files (by
+// content, so a renamed clip still hits) means the same answer
+fn cache_key(
+    provider: &str,
+    model: &str,
+    params: &ModelParams,
+    messages: &[ChatMessage],
+    context_hashes: &[String],
+) -> String {
+    let sampling = format!("{:?}/{:?}/{:?}", params.temperature, params.top_p, params.max_tokens);
+    let mut hasher = Sha256::new();
+    for part in [provider, model, &sampling] {
+        hasher.update(part.as_bytes());
+        hasher.update([0u8]);
+    }
+    for message in messages {
+        hasher.update(message.role.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(normalize_prompt(&message.content).as_bytes());
+        hasher.update([0u8]);
+    }
+    for hash in context_hashes {
+        hasher.update(hash.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+fn cached_reply(conn: &Connection, key: &str) -> Result<Option<ChatReply>, String> {
+    let reply: Option<String> = conn
+        .query_row(
+            "SELECT reply FROM ai_cache WHERE key = ?1 AND expires_at > ?2",
+            params![key, db::now()],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    Ok(reply.and_then(|json| serde_json::from_str::<ChatReply>(&json).ok()).map(|reply| ChatReply { cached: true, ..reply }))
+}
+
+fn store_reply(conn: &Connection, key: &str, reply: &ChatReply) -> Result<(), String> {
+    let now = db::now();
+    let json = serde_json::to_string(reply).map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM ai_cache WHERE expires_at <= ?1", params![now])
+        .map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT OR REPLACE INTO ai_cache (key, reply, created_at, expires_at) VALUES (?1, ?2, ?3, ?4)",
+        params![key, json, now, now + CACHE_TTL_SECONDS],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct ChatRequest {
+    pub provider: String,
+    pub model: String,
+    pub api_key: String,
+    pub messages: Vec<ChatMessage>,
+    pub params: ModelParams,
+    pub conversation_id: Option<String>,
+    pub context_paths: Vec<String>,
+    pub bypass_cache: bool,
+}
+
+// Fills in the conversation's stored parameters, and its system prompt when
+// the caller didn't send one
+pub fn prepare_messages(
+    conn: &Connection,
+    conversation_id: Option<&str>,
+    params: &ModelParams,
+    messages: &[ChatMessage],
+) -> Result<(ModelParams, Vec<ChatMessage>), String> {
+    let params = conversations::resolve(conn, conversation_id, params)?;
+    let mut messages = messages.to_vec();
+    if let Some(system_prompt) = &params.system_prompt {
+        if !messages.iter().any(|m| m.role == "system") {
+            messages.insert(0, ChatMessage { role: "system".to_string(), content: system_prompt.clone() });
+        }
+    }
+    Ok((params, messages))
+}
+
+// Answers from the cache when it can, otherwise asks the provider, then
+// caches the reply and adds its usage to the conversation's running total
+pub async fn run_chat(app: &AppHandle, request: &ChatRequest) -> Result<ChatReply, String> {
+    let db = app.state::<Db>();
+    let (params, messages) = prepare_messages(&db.lock(), request.conversation_id.as_deref(), &request.params, &request.messages)?;
+
+    // Context files can be long recordings; hashing one reads all of it
+    let hash_db = db.inner().clone();
+    let context_paths = request.context_paths.clone();
+    let mut context_hashes = tokio::task::spawn_blocking(move || {
+        context_paths
+            .iter()
+            .map(|path| hashes::content_hash(&hash_db, path))
+            .collect::<Result<Vec<_>, _>>()
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))??;
+    context_hashes.sort();
+    let key = cache_key(&request.provider, &request.model, &params, &messages, &context_hashes);
+
+    let prompt = request.messages.last().filter(|m| m.role == "user").map(|m| m.content.as_str());
+    if !request.bypass_cache {
+        if let Some(reply) = cached_reply(&db.lock(), &key)? {
+            if let Some(conversation_id) = &request.conversation_id {
+                conversations::record_exchange(&db.lock(), conversation_id, prompt, &request.context_paths, &reply)?;
+            }
+            return Ok(reply);
+        }
+    }
+    if !app.state::<Connectivity>().is_online() {
+        return Err(OFFLINE.to_string());
+    }
+
+    // Paths, names and emails are stripped on the way out; the cache key and
+    // the stored conversation keep the original text
+    let outbound = Redactor::load(&db.lock())?.redact_messages(&messages, &mut Vec::new());
+    let http = app.state::<Http>();
+    let reply = chat(&http, &request.provider, &request.model, &request.api_key, &outbound, &params).await?;
+    store_reply(&db.lock(), &key, &reply)?;
+
+    if let Some(conversation_id) = &request.conversation_id {
+        // Providers normally report usage; count locally when they don't
+        let tokenizers = app.state::<Tokenizers>();
+        let (prompt_tokens, completion_tokens) = match &reply.usage {
+            Some(usage) => (usage.prompt_tokens, usage.completion_tokens),
+            None => {
+                let answer = [ChatMessage { role: "assistant".to_string(), content: reply.text.clone() }];
+                (
+                    tokenizers.count(&request.model, &messages)?.0,
+                    tokenizers.count(&request.model, &answer)?.0,
+                )
+            }
+        };
+        let conn = db.lock();
+        usage::record(&conn, conversation_id, &reply, prompt_tokens, completion_tokens)?;
+        conversations::record_exchange(&conn, conversation_id, prompt, &request.context_paths, &reply)?;
+    }
+    Ok(reply)
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct QueuedRequest {
+    id: u64,
+    provider: String,
+    model: String,
+    conversation_id: Option<String>,
+    // "pending", "sending", "sent", "failed" or "cancelled"
+    status: &'static str,
+    error: Option<String>,
+    reply: Option<ChatReply>,
+    created_at: i64,
+    #[serde(skip)]
+    request: ChatRequest,
+}
+
+// Requests made while offline, sent in order once the connection returns.
+// Kept in memory only so API keys never touch the disk.
+#[derive(Default)]
+pub struct AiQueue {
+    requests: Mutex<Vec<QueuedRequest>>,
+    next_id: AtomicU64,
+    // Set while a flush is sending, so a second one doesn't start beside it
+    flushing: AtomicBool,
+}
+
+impl AiQueue {
+    fn lock(&self) -> MutexGuard<'_, Vec<QueuedRequest>> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn push(&self, app: &AppHandle, request: ChatRequest) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let queued = QueuedRequest {
+            id,
+            provider: request.provider.clone(),
+            model: request.model.clone(),
+            conversation_id: request.conversation_id.clone(),
+            status: "pending",
+            error: None,
+            reply: None,
+            created_at: db::now(),
+            request,
+        };
+        let _ = app.emit("ai-queue-updated", &queued);
+        self.lock().push(queued);
+        id
+    }
+
+    fn update(&self, app: &AppHandle, id: u64, change: impl FnOnce(&mut QueuedRequest)) {
+        let mut requests = self.lock();
+        if let Some(queued) = requests.iter_mut().find(|q| q.id == id) {
+            change(queued);
+            let _ = app.emit("ai-queue-updated", &*queued);
+        }
+    }
+
+    // Cancels requests not yet sent, whose conversations belong to a profile
+    // that's no longer open
+    pub fn cancel_pending(&self, app: &AppHandle) {
+        for queued in self.lock().iter_mut().filter(|q| q.status == "pending") {
+            queued.status = "cancelled";
+            let _ = app.emit("ai-queue-updated", &*queued);
+        }
+    }
+
+    // Marks the oldest pending request as sending and hands it over, under
+    // one lock so it can't be sent twice
+    fn claim_next(&self, app: &AppHandle) -> Option<(u64, ChatRequest)> {
+        let mut requests = self.lock();
+        let queued = requests.iter_mut().find(|q| q.status == "pending")?;
+        queued.status = "sending";
+        let _ = app.emit("ai-queue-updated", &*queued);
+        Some((queued.id, queued.request.clone()))
+    }
+}
+
+// Clears `flushing` when a flush ends, including by a panic partway through
+struct Flushing<'a>(&'a AtomicBool);
+
+impl Drop for Flushing<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+// Sends pending requests one at a time, stopping if the connection drops
+// again. Does nothing while another flush is still going.
+pub fn flush_queue(app: AppHandle) {
+    if app.state::<AiQueue>().flushing.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        let queue = app.state::<AiQueue>();
+        let offline = {
+            let _flushing = Flushing(&queue.flushing);
+            let mut offline = false;
+            while let Some((id, request)) = queue.claim_next(&app) {
+                match run_chat(&app, &request).await {
+                    Ok(reply) => queue.update(&app, id, |q| {
+                        q.status = "sent";
+                        q.reply = Some(reply);
+                    }),
+                    Err(e) => {
+                        if !connectivity::probe(&app).await {
+                            queue.update(&app, id, |q| q.status = "pending");
+                            connectivity::mark(&app, false);
+                            offline = true;
+                            break;
+                        }
+                        queue.update(&app, id, |q| {
+                            q.status = "failed";
+                            q.error = Some(e);
+                        });
+                    }
+                }
+            }
+            offline
+        };
+        // A request queued after the last check saw the flag still set and
+        // left itself to this flush
+        if !offline && queue.lock().iter().any(|q| q.status == "pending") {
+            flush_queue(app.clone());
+        }
+    });
+}
+
+// Proxies a chat request so keys stay out of browser network logs and every
+// call goes through the shared retry and rate-limit policy. Usage is added
+// to the conversation's running total when one is given, and its stored
+// parameter overrides fill in anything `params` leaves unset. Answers are
+// cached for a week unless `bypass_cache` is set; `context_paths` are the
+// clips attached to the prompt. Without a connection the request is queued
+// and its reply arrives later through "ai-queue-updated".
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn ai_chat(
+    app: AppHandle,
+    provider: String,
+    model: String,
+    api_key: String,
+    messages: Vec<ChatMessage>,
+    temperature: Option<f32>,
+    params: Option<ModelParams>,
+    conversation_id: Option<String>,
+    context_paths: Option<Vec<String>>,
+    bypass_cache: Option<bool>,
+) -> Result<ChatReply, String> {
+    let request = ChatRequest {
+        provider,
+        model,
+        api_key,
+        messages,
+        params: ModelParams {
+            temperature: temperature.or(params.as_ref().and_then(|p| p.temperature)),
+            ..params.unwrap_or_default()
+        },
+        conversation_id,
+        context_paths: context_paths.unwrap_or_default(),
+        bypass_cache: bypass_cache.unwrap_or(false),
+    };
+
+    let error = match run_chat(&app, &request).await {
+        Ok(reply) => return Ok(reply),
+        Err(e) => e,
+    };
+    // Refused up front as offline: queued even if the flag turns out to be
+    // stale, and the refresh that clears it sends the queue
+    if error == OFFLINE {
+        let id = app.state::<AiQueue>().push(&app, request);
+        connectivity::refresh(&app).await;
+        return Err(format!("{}; request {} will be sent when the connection returns", OFFLINE, id));
+    }
+    if connectivity::refresh(&app).await {
+        return Err(error);
+    }
+    let id = app.state::<AiQueue>().push(&app, request);
+    Err(format!("{}; request {} will be sent when the connection returns", OFFLINE, id))
+}
+
+#[tauri::command]
+pub async fn list_ai_queue(queue: State<'_, AiQueue>) -> Result<Vec<QueuedRequest>, String> {
+    Ok(queue.lock().clone())
+}
+
+#[tauri::command]
+pub async fn cancel_ai_request(app: AppHandle, queue: State<'_, AiQueue>, id: u64) -> Result<(), String> {
+    let pending = queue.lock().iter().any(|q| q.id == id && q.status == "pending");
+    if !pending {
+        return Err(format!("Request {} is not waiting to be sent", id));
+    }
+    queue.update(&app, id, |q| q.status = "cancelled");
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn clear_ai_queue(queue: State<'_, AiQueue>) -> Result<(), String> {
+    queue.lock().retain(|q| matches!(q.status, "pending" | "sending"));
+    Ok(())
+}
@@ -0,0 +1,249 @@
+use crate::http::Http;
+use crate::paths;
+use std::path::Path;
+use tauri::State;
+
+const TRANSCRIPTION_URL: &str = "https://api.openai.com/v1/audio/transcriptions";
+// Logged in the network audit and capped under this name
+const PURPOSE: &str = "lyric transcription";
+// OpenAI rejects uploads over 25 MB
+const MAX_UPLOAD: u64 = 25 * 1024 * 1024;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct TimedWord {
+    word: String,
+    start: f64,
+    end: f64,
+}
+
+#[derive(serde::Deserialize)]
+struct TranscriptionResponse {
+    #[serde(default)]
+    words: Vec<TimedWord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AlignedWord {
+    word: String,
+    start: f64,
+    end: f64,
+    // false when the timing was interpolated from neighbouring words
+    matched: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct AlignedLine {
+    text: String,
+    start: f64,
+    end: f64,
+    words: Vec<AlignedWord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct LyricTimeline {
+    lines: Vec<AlignedLine>,
+    lrc: String,
+    matched_ratio: f64,
+}
+
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric() || *c == '\'')
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+fn similarity(a: &str, b: &str) -> i32 {
+    if a == b {
+        return 3;
+    }
+    if a.is_empty() || b.is_empty() {
+        return -2;
+    }
+    // Whisper often drops or adds a trailing letter ("gonna"/"gon", "runnin'")
+    let common = a.chars().zip(b.chars()).take_while(|(x, y)| x == y).count();
+    if common >= 3 || (common >= 2 && a.len().min(b.len()) <= 3) {
+        1
+    } else {
+        -2
+    }
+}
+
+// Needleman-Wunsch between lyric words and recognized words. Returns, for
+// each lyric word, the index of the recognized word it lines up with.
+fn align_words(lyrics: &[String], recognized: &[String]) -> Vec<Option<usize>> {
+    const GAP: i32 = -1;
+    let (n, m) = (lyrics.len(), recognized.len());
+    let mut score = vec![vec![0i32; m + 1]; n + 1];
+    for (i, row) in score.iter_mut().enumerate() {
+        row[0] = i as i32 * GAP;
+    }
+    for (j, cell) in score[0].iter_mut().enumerate() {
+        *cell = j as i32 * GAP;
+    }
+
+    for i in 1..=n {
+        for j in 1..=m {
+            let diagonal = score[i - 1][j - 1] + similarity(&lyrics[i - 1], &recognized[j - 1]);
+            score[i][j] = diagonal.max(score[i - 1][j] + GAP).max(score[i][j - 1] + GAP);
+        }
+    }
+
+    let mut result = vec![None; n];
+    let (mut i, mut j) = (n, m);
+    while i > 0 && j > 0 {
+        let sim = similarity(&lyrics[i - 1], &recognized[j - 1]);
+        if score[i][j] == score[i - 1][j - 1] + sim {
+            if sim > 0 {
+                result[i - 1] = Some(j - 1);
+            }
+            i -= 1;
+            j -= 1;
+        } else if score[i][j] == score[i - 1][j] + GAP {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+    }
+    result
+}
+
+fn lrc_timestamp(seconds: f64) -> String {
+    let centis = (seconds.max(0.0) * 100.0).round() as u64;
+    format!("[{:02}:{:02}.{:02}]", centis / 6000, (centis / 100) % 60, centis % 100)
+}
+
+pub fn build_timeline(lyrics: &str, recognized: &[TimedWord]) -> LyricTimeline {
+    let lines: Vec<Vec<&str>> = lyrics
+        .lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .filter(|words| !words.is_empty())
+        .collect();
+
+    let lyric_words: Vec<String> = lines.iter().flatten().map(|w| normalize(w)).collect();
+    let recognized_words: Vec<String> = recognized.iter().map(|w| normalize(&w.word)).collect();
+    let mapping = align_words(&lyric_words, &recognized_words);
+
+    // Interpolate unmatched words between the nearest matched neighbours
+    let total_end = recognized.last().map(|w| w.end).unwrap_or(0.0);
+    let mut times: Vec<(f64, f64, bool)> = Vec::with_capacity(mapping.len());
+    let mut i = 0;
+    while i < mapping.len() {
+        if let Some(j) = mapping[i] {
+            times.push((recognized[j].start, recognized[j].end, true));
+            i += 1;
+            continue;
+        }
+        let run_start = i;
+        while i < mapping.len() && mapping[i].is_none() {
+            i += 1;
+        }
+        let gap_start = times.last().map(|t| t.1).unwrap_or(0.0);
+        let gap_end = mapping
+            .get(i)
+            .and_then(|m| m.map(|j| recognized[j].start))
+            .unwrap_or(total_end.max(gap_start));
+        let count = (i - run_start) as f64;
+        let step = (gap_end - gap_start).max(0.0) / count;
+        for k in 0..(i - run_start) {
+            let start = gap_start + step * k as f64;
+            times.push((start, start + step, false));
+        }
+    }
+
+    let mut aligned_lines = Vec::new();
+    let mut lrc = String::new();
+    let mut cursor = 0;
+    for words in &lines {
+        let mut aligned = Vec::new();
+        for word in words {
+            let (start, end, matched) = times[cursor];
+            aligned.push(AlignedWord { word: word.to_string(), start, end, matched });
+            cursor += 1;
+        }
+        let text = words.join(" ");
+        let start = aligned.first().map(|w| w.start).unwrap_or(0.0);
+        let end = aligned.last().map(|w| w.end).unwrap_or(start);
+        lrc.push_str(&format!("{}{}\n", lrc_timestamp(start), text));
+        aligned_lines.push(AlignedLine { text, start, end, words: aligned });
+    }
+
+    let matched = times.iter().filter(|t| t.2).count();
+    LyricTimeline {
+        lines: aligned_lines,
+        lrc,
+        matched_ratio: if times.is_empty() { 0.0 } else { matched as f64 / times.len() as f64 },
+    }
+}
+
+async fn transcribe_words(http: &Http, path: &Path, api_key: &str, language: Option<String>) -> Result<Vec<TimedWord>, String> {
+    let size = std::fs::metadata(path).map_err(|e| e.to_string())?.len();
+    if size > MAX_UPLOAD {
+        return Err("Vocal file is larger than 25 MB; export a compressed stem first".to_string());
+    }
+
+    let bytes = tokio::fs::read(path).await.map_err(|e| format!("Failed to read vocal file: {}", e))?;
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "vocal.wav".to_string());
+
+    // Rebuilt for every attempt since a multipart body can only be sent once
+    let form = || {
+        let mut form = reqwest::multipart::Form::new()
+            .text("model", "whisper-1")
+            .text("response_format", "verbose_json")
+            .text("timestamp_granularities[]", "word")
+            .part(
+                "file",
+                reqwest::multipart::Part::stream_with_length(http.throttled_body(bytes.clone(), PURPOSE), bytes.len() as u64)
+                    .file_name(file_name.clone()),
+            );
+        if let Some(language) = &language {
+            form = form.text("language", language.clone());
+        }
+        form
+    };
+
+    let response = http
+        .send("openai", PURPOSE, |client| client.post(TRANSCRIPTION_URL).bearer_auth(api_key).multipart(form()))
+        .await
+        .map_err(|e| format!("Transcription request failed: {}", e))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Transcription failed ({}): {}", status, body));
+    }
+
+    let parsed: TranscriptionResponse = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid transcription response: {}", e))?;
+    Ok(parsed.words)
+}
+
+// Aligns lyric text to a vocal stem. Word timings come from Whisper, either
+// passed in (local whisper.cpp output) or fetched from the OpenAI API.
+#[tauri::command]
+pub async fn align_lyrics(
+    http: State<'_, Http>,
+    path: String,
+    lyrics: String,
+    api_key: Option<String>,
+    language: Option<String>,
+    words: Option<Vec<TimedWord>>,
+) -> Result<LyricTimeline, String> {
+    let recognized = match words {
+        Some(words) => words,
+        None => {
+            let api_key = api_key.ok_or_else(|| "An OpenAI API key is required for transcription".to_string())?;
+            transcribe_words(&http, &paths::to_fs(&path), &api_key, language).await?
+        }
+    };
+
+    if recognized.is_empty() {
+        return Err("No words were recognized in the vocal file".to_string());
+    }
+    Ok(build_timeline(&lyrics, &recognized))
+}
@@ -0,0 +1,254 @@
+use crate::db::{self, Db};
+use crate::hashes;
+use crate::jobs::{Priority, Scheduler};
+use crate::paths;
+use crate::quarantine;
+use crate::tempo;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use tauri::{AppHandle, Manager, State};
+
+pub const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+// Krumhansl-Kessler key profiles
+const MAJOR_PROFILE: [f32; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f32; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+#[derive(Clone, serde::Serialize)]
+pub struct FileAnalysis {
+    pub path: String,
+    pub duration: f64,
+    pub bpm: Option<f64>,
+    pub key: Option<String>,
+    pub key_confidence: f64,
+}
+
+fn correlation(a: &[f32; 12], b: &[f32; 12]) -> f32 {
+    let mean_a = a.iter().sum::<f32>() / 12.0;
+    let mean_b = b.iter().sum::<f32>() / 12.0;
+    let (mut num, mut den_a, mut den_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        let (x, y) = (x - mean_a, y - mean_b);
+        num += x * y;
+        den_a += x * x;
+        den_b += y * y;
+    }
+    num / (den_a * den_b).sqrt().max(1e-9)
+}
+
+pub fn estimate_key(profile: &[f32; 12]) -> Option<(String, f64)> {
+    if profile.iter().sum::<f32>() <= 1e-6 {
+        return None;
+    }
+
+    let mut scores = Vec::with_capacity(24);
+    for tonic in 0..12 {
+        for (mode, template) in [("", &MAJOR_PROFILE), ("m", &MINOR_PROFILE)] {
+            let mut rotated = [0.0f32; 12];
+            for (i, slot) in rotated.iter_mut().enumerate() {
+                *slot = template[(i + 12 - tonic) % 12];
+            }
+            scores.push((format!("{}{}", NOTE_NAMES[tonic], mode), correlation(profile, &rotated)));
+        }
+    }
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    // Confidence is how clearly the winner beats the runner-up
+    let margin = (scores[0].1 - scores[1].1).max(0.0) as f64;
+    Some((scores[0].0.clone(), (margin * 5.0).min(1.0)))
+}
+
+// Loops are usually a whole number of bars, which pins the tempo down far
+// better than onset autocorrelation alone.
+fn snap_loop_bpm(estimate: Option<f64>, duration: f64) -> Option<f64> {
+    let candidates: Vec<f64> = [1.0, 2.0, 4.0, 8.0, 16.0]
+        .iter()
+        .map(|bars| 60.0 * bars * 4.0 / duration)
+        .filter(|bpm| (70.0..=180.0).contains(bpm))
+        .collect();
+
+    match estimate {
+        Some(estimate) => {
+            let best = candidates
+                .iter()
+                .copied()
+                .min_by(|a, b| (a - estimate).abs().total_cmp(&(b - estimate).abs()));
+            match best {
+                Some(bpm) if (bpm - estimate).abs() / estimate < 0.06 => Some(bpm),
+                _ => Some(estimate),
+            }
+        }
+        None => None,
+    }
+}
+
+// Streams the file, so multi-gigabyte live recordings analyze in bounded memory
+pub fn analyze_path(path: &Path) -> Result<FileAnalysis, String> {
+    let onsets = tempo::stream_onsets(path)?;
+    let duration = onsets.duration;
+
+    // Anything under a bar at 200 BPM is a one-shot without a meaningful tempo
+    let bpm = if duration >= 1.2 {
+        snap_loop_bpm(tempo::bpm_from_envelope(&onsets.envelope), duration).map(|b| (b * 100.0).round() / 100.0)
+    } else {
+        None
+    };
+    let key = estimate_key(&onsets.chroma);
+
+    Ok(FileAnalysis {
+        path: paths::display(path),
+        duration,
+        bpm,
+        key_confidence: key.as_ref().map(|k| k.1).unwrap_or(0.0),
+        key: key.map(|k| k.0),
+    })
+}
+
+pub fn store(conn: &Connection, hash: &str, analysis: &FileAnalysis) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR REPLACE INTO analysis_cache (hash, duration, bpm, key, key_confidence, analyzed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![hash, analysis.duration, analysis.bpm, analysis.key, analysis.key_confidence, db::now()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn load_by_hash(conn: &Connection, hash: &str, path: &str) -> Result<Option<FileAnalysis>, String> {
+    conn.query_row(
+        "SELECT duration, bpm, key, key_confidence FROM analysis_cache WHERE hash = ?1",
+        params![hash],
+        |row| {
+            Ok(FileAnalysis {
+                path: path.to_string(),
+                duration: row.get(0)?,
+                bpm: row.get(1)?,
+                key: row.get(2)?,
+                key_confidence: row.get(3)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+fn load_legacy(conn: &Connection, path: &str) -> Result<Option<FileAnalysis>, String> {
+    conn.query_row(
+        "SELECT path, duration, bpm, key, key_confidence FROM analysis WHERE path = ?1",
+        params![path],
+        |row| {
+            Ok(FileAnalysis {
+                path: row.get(0)?,
+                duration: row.get(1)?,
+                bpm: row.get(2)?,
+                key: row.get(3)?,
+                key_confidence: row.get(4)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+// Results are shared by every file with the same contents, so a moved or
+// duplicated sample is never analyzed twice. A file that can't be read
+// (offline volume) still answers from its old path-keyed row if it has one.
+pub fn load(db: &Db, path: &str) -> Result<Option<FileAnalysis>, String> {
+    match hashes::content_hash(db, path) {
+        Ok(hash) => load_by_hash(&db.lock(), &hash, path),
+        Err(_) => load_legacy(&db.lock(), path),
+    }
+}
+
+// Cached analysis, computing it on a miss
+pub fn get_or_analyze(db: &Db, path: &str) -> Result<FileAnalysis, String> {
+    let hash = hashes::content_hash(db, path)?;
+    if let Some(existing) = load_by_hash(&db.lock(), &hash, path)? {
+        return Ok(existing);
+    }
+    let analysis = quarantine::guard(db, path, || analyze_path(&paths::to_fs(path)))?;
+    store(&db.lock(), &hash, &analysis)?;
+    Ok(analysis)
+}
+
+// Moves rows from the old path-keyed table into the hash-keyed cache.
+// Files that are missing right now keep their row for a later launch.
+pub fn migrate_legacy(app: &AppHandle) {
+    let db = app.state::<Db>().inner().clone();
+    let legacy: Vec<String> = {
+        let conn = db.lock();
+        let mut stmt = match conn.prepare("SELECT path FROM analysis") {
+            Ok(stmt) => stmt,
+            Err(_) => return,
+        };
+        let paths: Vec<String> = match stmt.query_map([], |row| row.get(0)) {
+            Ok(rows) => rows.filter_map(|r| r.ok()).collect(),
+            Err(_) => return,
+        };
+        paths
+    };
+    if legacy.is_empty() {
+        return;
+    }
+
+    let label = format!("Migrate analysis for {} files", legacy.len());
+    app.state::<Scheduler>().submit("analysis", label, Priority::Background, move |ctx| {
+        let total = legacy.len() as f32;
+        for (i, path) in legacy.iter().enumerate() {
+            if ctx.is_cancelled() {
+                break;
+            }
+            ctx.progress(i as f32 / total, Some(path.clone()));
+            let hash = match hashes::content_hash(&db, path) {
+                Ok(hash) => hash,
+                Err(_) => continue,
+            };
+            let conn = db.lock();
+            if let Some(analysis) = load_legacy(&conn, path)? {
+                if load_by_hash(&conn, &hash, path)?.is_none() {
+                    store(&conn, &hash, &analysis)?;
+                }
+            }
+            conn.execute("DELETE FROM analysis WHERE path = ?1", params![path])
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    });
+}
+
+pub fn submit_analysis(db: &Db, scheduler: &Scheduler, paths: Vec<String>) -> u64 {
+    let db = db.clone();
+    let label = format!("Analyze {} files", paths.len());
+
+    scheduler.submit_on("analysis", label, Priority::Background, paths.clone(), move |ctx| {
+        let total = paths.len().max(1) as f32;
+        for (i, path) in paths.iter().enumerate() {
+            if ctx.is_cancelled() {
+                break;
+            }
+            ctx.progress(i as f32 / total, Some(path.clone()));
+            let hash = match hashes::content_hash(&db, path) {
+                Ok(hash) => hash,
+                Err(_) => continue,
+            };
+            if let Ok(analysis) = quarantine::guard(&db, path, || analyze_path(&paths::to_fs(path))) {
+                store(&db.lock(), &hash, &analysis)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[tauri::command]
+pub async fn analyze_files(db: State<'_, Db>, scheduler: State<'_, Scheduler>, paths: Vec<String>) -> Result<u64, String> {
+    Ok(submit_analysis(&db, &scheduler, paths))
+}
+
+#[tauri::command]
+pub async fn get_analysis(db: State<'_, Db>, path: String) -> Result<Option<FileAnalysis>, String> {
+    // Hashing a file that changed since it was last seen reads all of it
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || load(&db, &path))
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,276 @@
+use crate::db::Db;
+use crate::midi::{self, Note};
+use crate::paths;
+use crate::random::Rng;
+use crate::takes;
+use crate::theory;
+use std::fs;
+use tauri::State;
+
+const DEFAULT_BPM: f64 = 120.0;
+// Steps (arp notes or strokes) across all the chords
+const MAX_STEPS: usize = 4096;
+// Root octaves that keep some of a chord inside MIDI's range
+const OCTAVE_RANGE: std::ops::RangeInclusive<i32> = -1..=9;
+// At full humanize notes land up to this far off the grid, and this much
+// softer or harder
+const HUMANIZE_SECONDS: f64 = 0.015;
+const HUMANIZE_VELOCITY: f64 = 20.0;
+// Upstrokes catch the strings more lightly than downstrokes
+const UPSTROKE_VELOCITY: f32 = 0.85;
+
+fn default_chord_beats() -> f64 {
+    4.0
+}
+
+fn default_rate() -> f64 {
+    0.25
+}
+
+fn default_gate() -> f64 {
+    0.8
+}
+
+fn default_octaves() -> u8 {
+    1
+}
+
+fn default_velocity() -> u8 {
+    96
+}
+
+fn default_strum_ms() -> f64 {
+    12.0
+}
+
+// A chord held for `beats`
+#[derive(Clone, serde::Deserialize)]
+pub struct ChordSlot {
+    symbol: String,
+    #[serde(default = "default_chord_beats")]
+    beats: f64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArpStyle {
+    Up,
+    Down,
+    UpDown,
+    // Outside in: lowest, highest, second lowest, ...
+    Converge,
+    Random,
+    StrumDown,
+    StrumUp,
+    // Down on the beat, up on the offbeat
+    StrumAlternate,
+}
+
+const STYLES: [ArpStyle; 8] = [
+    ArpStyle::Up,
+    ArpStyle::Down,
+    ArpStyle::UpDown,
+    ArpStyle::Converge,
+    ArpStyle::Random,
+    ArpStyle::StrumDown,
+    ArpStyle::StrumUp,
+    ArpStyle::StrumAlternate,
+];
+
+impl ArpStyle {
+    fn strum(self) -> bool {
+        matches!(self, ArpStyle::StrumDown | ArpStyle::StrumUp | ArpStyle::StrumAlternate)
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct ArpSettings {
+    style: ArpStyle,
+    // Beats between steps (arps) or strokes (strums)
+    #[serde(default = "default_rate")]
+    rate: f64,
+    // Share of the step each note sounds for
+    #[serde(default = "default_gate")]
+    gate: f64,
+    // Octaves the chord is spread over, 1-4
+    #[serde(default = "default_octaves")]
+    octaves: u8,
+    // Of the chord root; 4 for arps, 3 for strums when not given
+    #[serde(default)]
+    octave: Option<i32>,
+    // 0-1: timing and velocity wander
+    #[serde(default)]
+    humanize: f64,
+    #[serde(default = "default_velocity")]
+    velocity: u8,
+    // Delay between strings within a strum
+    #[serde(default = "default_strum_ms")]
+    strum_ms: f64,
+    #[serde(default)]
+    seed: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Arpeggio {
+    notes: Vec<Note>,
+    beats: f64,
+    // Set when the result was written out
+    path: Option<String>,
+}
+
+// The chord's notes spread over `octaves`, lowest first
+fn pool(symbol: &str, octave: i32, octaves: u8) -> Result<Vec<u8>, String> {
+    let base = theory::parse_chord(symbol)?.notes(octave);
+    let mut notes: Vec<u8> = (0..octaves as u16)
+        .flat_map(|o| base.iter().map(move |n| *n as u16 + 12 * o))
+        .filter(|n| *n <= 127)
+        .map(|n| n as u8)
+        .collect();
+    notes.sort_unstable();
+    notes.dedup();
+    if notes.is_empty() {
+        return Err(format!("{} is out of MIDI range at octave {}", symbol, octave));
+    }
+    Ok(notes)
+}
+
+// Which pool note an arp plays on step `k`
+fn arp_index(style: ArpStyle, k: usize, n: usize, last: Option<usize>, rng: &mut Rng) -> usize {
+    match style {
+        ArpStyle::Down => n - 1 - k % n,
+        ArpStyle::UpDown => {
+            // Up and back without repeating the ends
+            let cycle = (2 * n).saturating_sub(2).max(1);
+            let p = k % cycle;
+            if p < n {
+                p
+            } else {
+                cycle - p
+            }
+        }
+        ArpStyle::Converge => {
+            let p = k % n;
+            if p % 2 == 0 {
+                p / 2
+            } else {
+                n - 1 - p / 2
+            }
+        }
+        ArpStyle::Random => {
+            let mut pick = rng.below(n);
+            if n > 1 && Some(pick) == last {
+                pick = (pick + 1 + rng.below(n - 1)) % n;
+            }
+            pick
+        }
+        _ => k % n,
+    }
+}
+
+fn generate(chords: &[ChordSlot], settings: &ArpSettings, bpm: f64) -> Result<(Vec<Note>, f64), String> {
+    let mut rng = Rng::new(settings.seed);
+    let humanize = settings.humanize.clamp(0.0, 1.0);
+    let beat_seconds = 60.0 / bpm;
+    let jitter = humanize * HUMANIZE_SECONDS / beat_seconds;
+    let octave = settings.octave.unwrap_or(if settings.style.strum() { 3 } else { 4 });
+    let human = |start: f64, velocity: f32, rng: &mut Rng| {
+        let start = (start + rng.signed() * jitter).max(0.0);
+        let velocity = (velocity as f64 + rng.signed() * humanize * HUMANIZE_VELOCITY).round().clamp(1.0, 127.0) as u8;
+        (start, velocity)
+    };
+
+    let mut notes = Vec::new();
+    let mut at = 0.0;
+    for slot in chords {
+        let pool = pool(&slot.symbol, octave, settings.octaves)?;
+        let steps = ((slot.beats / settings.rate) + 1e-6).floor().max(1.0) as usize;
+        let mut last = None;
+        for k in 0..steps {
+            let step_start = at + k as f64 * settings.rate;
+            let length = settings.rate * settings.gate;
+            if settings.style.strum() {
+                let down = match settings.style {
+                    ArpStyle::StrumDown => true,
+                    ArpStyle::StrumUp => false,
+                    _ => k % 2 == 0,
+                };
+                let strings: Vec<u8> = if down { pool.clone() } else { pool.iter().rev().copied().collect() };
+                let delay = settings.strum_ms / 1000.0 / beat_seconds;
+                let velocity = settings.velocity as f32 * if down { 1.0 } else { UPSTROKE_VELOCITY };
+                for (i, pitch) in strings.into_iter().enumerate() {
+                    let (start, velocity) = human(step_start + i as f64 * delay, velocity, &mut rng);
+                    // Every string stops together when the hand comes back
+                    let duration = (step_start + length - start).max(length * 0.25);
+                    notes.push(Note { pitch, velocity, start, duration, channel: 0 });
+                }
+            } else {
+                let index = arp_index(settings.style, k, pool.len(), last, &mut rng);
+                last = Some(index);
+                let (start, velocity) = human(step_start, settings.velocity as f32, &mut rng);
+                notes.push(Note { pitch: pool[index], velocity, start, duration: length, channel: 0 });
+            }
+        }
+        at += slot.beats;
+    }
+    Ok((notes, at))
+}
+
+#[tauri::command]
+pub async fn list_arp_styles() -> Result<Vec<ArpStyle>, String> {
+    Ok(STYLES.to_vec())
+}
+
+// Plays `chords` as an arpeggio or strum pattern. With `output_path` the
+// result is also written there as a MIDI file and added to the library; an
+// existing file is only replaced with `overwrite`.
+#[tauri::command]
+pub async fn generate_arpeggio(
+    db: State<'_, Db>,
+    chords: Vec<ChordSlot>,
+    settings: ArpSettings,
+    bpm: Option<f64>,
+    output_path: Option<String>,
+    overwrite: Option<bool>,
+) -> Result<Arpeggio, String> {
+    let bpm = bpm.unwrap_or(DEFAULT_BPM);
+    if !(20.0..=400.0).contains(&bpm) {
+        return Err("Tempo must be between 20 and 400 BPM".to_string());
+    }
+    if chords.is_empty() {
+        return Err("Give at least one chord".to_string());
+    }
+    if settings.rate.is_nan() || settings.rate <= 0.0 || chords.iter().any(|c| c.beats.is_nan() || c.beats <= 0.0) {
+        return Err("Rate and chord lengths must be positive".to_string());
+    }
+    if settings.gate.is_nan() || settings.gate <= 0.0 || settings.gate > 1.0 {
+        return Err("Gate must be above 0 and at most 1".to_string());
+    }
+    if !(1..=4).contains(&settings.octaves) {
+        return Err("Spread the chord over 1 to 4 octaves".to_string());
+    }
+    if settings.octave.map_or(false, |o| !OCTAVE_RANGE.contains(&o)) {
+        return Err(format!("Octave must be between {} and {}", OCTAVE_RANGE.start(), OCTAVE_RANGE.end()));
+    }
+    let steps: f64 = chords.iter().map(|c| (c.beats / settings.rate).floor().max(1.0)).sum();
+    if steps > MAX_STEPS as f64 {
+        return Err(format!("That's more than {} steps; use a slower rate or shorter chords", MAX_STEPS));
+    }
+    let (notes, beats) = generate(&chords, &settings, bpm)?;
+
+    let path = match output_path {
+        Some(output_path) => {
+            let destination = paths::to_fs(&output_path);
+            if destination.exists() && !overwrite.unwrap_or(false) {
+                return Err(format!("{} already exists", output_path));
+            }
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            midi::write_notes(&destination, &notes, bpm, 4)?;
+            takes::index(&db.lock(), &destination)?;
+            Some(paths::display(&destination))
+        }
+        None => None,
+    };
+    Ok(Arpeggio { notes, beats, path })
+}
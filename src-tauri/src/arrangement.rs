@@ -0,0 +1,276 @@
+use crate::collections::safe_file_name;
+use crate::db::Db;
+use crate::midi::{self, PPQ};
+use crate::paths;
+use crate::takes;
+use midly::num::u15;
+use midly::{Format, Header, MetaMessage, Smf, Timing, TrackEventKind};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+const BEATS_PER_BAR: u8 = 4;
+// Sections grow and shrink by whole phrases
+const PHRASE_BARS: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Genre {
+    Pop,
+    Rock,
+    HipHop,
+    Trap,
+    Edm,
+    House,
+    Techno,
+    DrumAndBass,
+    Lofi,
+    Ambient,
+}
+
+const GENRES: [Genre; 10] = [
+    Genre::Pop,
+    Genre::Rock,
+    Genre::HipHop,
+    Genre::Trap,
+    Genre::Edm,
+    Genre::House,
+    Genre::Techno,
+    Genre::DrumAndBass,
+    Genre::Lofi,
+    Genre::Ambient,
+];
+
+impl Genre {
+    fn bpm(self) -> f64 {
+        match self {
+            Genre::Pop => 110.0,
+            Genre::Rock => 120.0,
+            Genre::HipHop => 90.0,
+            Genre::Trap => 140.0,
+            Genre::Edm => 128.0,
+            Genre::House => 124.0,
+            Genre::Techno => 130.0,
+            Genre::DrumAndBass => 174.0,
+            Genre::Lofi => 80.0,
+            Genre::Ambient => 70.0,
+        }
+    }
+
+    // Sections and their lengths in bars at the genre's usual track length
+    fn template(self) -> &'static [(&'static str, u32)] {
+        match self {
+            Genre::Pop => &[
+                ("Intro", 4),
+                ("Verse", 8),
+                ("Pre-Chorus", 4),
+                ("Chorus", 8),
+                ("Verse", 8),
+                ("Pre-Chorus", 4),
+                ("Chorus", 8),
+                ("Bridge", 8),
+                ("Chorus", 8),
+                ("Outro", 4),
+            ],
+            Genre::Rock => &[
+                ("Intro", 8),
+                ("Verse", 16),
+                ("Chorus", 8),
+                ("Verse", 16),
+                ("Chorus", 8),
+                ("Solo", 16),
+                ("Chorus", 8),
+                ("Outro", 8),
+            ],
+            Genre::HipHop => &[
+                ("Intro", 4),
+                ("Verse", 16),
+                ("Hook", 8),
+                ("Verse", 16),
+                ("Hook", 8),
+                ("Bridge", 8),
+                ("Hook", 8),
+                ("Outro", 4),
+            ],
+            Genre::Trap => &[("Intro", 8), ("Hook", 16), ("Verse", 16), ("Hook", 16), ("Verse", 16), ("Hook", 16), ("Outro", 8)],
+            Genre::Edm => &[("Intro", 16), ("Build", 8), ("Drop", 16), ("Breakdown", 16), ("Build", 8), ("Drop", 16), ("Outro", 16)],
+            Genre::House => &[
+                ("Intro", 16),
+                ("Groove", 16),
+                ("Breakdown", 8),
+                ("Build", 8),
+                ("Drop", 16),
+                ("Groove", 16),
+                ("Outro", 16),
+            ],
+            Genre::Techno => &[("Intro", 16), ("Build", 16), ("Peak", 32), ("Breakdown", 16), ("Peak", 32), ("Outro", 16)],
+            Genre::DrumAndBass => &[
+                ("Intro", 32),
+                ("Build", 16),
+                ("Drop", 32),
+                ("Breakdown", 32),
+                ("Build", 16),
+                ("Drop", 32),
+                ("Outro", 32),
+            ],
+            Genre::Lofi => &[("Intro", 4), ("A", 16), ("B", 8), ("A", 16), ("B", 8), ("Outro", 4)],
+            Genre::Ambient => &[("Intro", 16), ("A", 32), ("B", 32), ("A", 32), ("Outro", 16)],
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct Section {
+    // "Verse 2"; numbered when a section comes back
+    name: String,
+    // 1-based, as DAWs count
+    start_bar: u32,
+    bars: u32,
+    start_seconds: f64,
+}
+
+#[derive(serde::Serialize)]
+pub struct Arrangement {
+    genre: Genre,
+    bpm: f64,
+    beats_per_bar: u8,
+    bars: u32,
+    seconds: f64,
+    sections: Vec<Section>,
+    // Set when written out
+    midi_path: Option<String>,
+    json_path: Option<String>,
+}
+
+// The template's bar counts stretched toward `target` bars, in whole phrases
+fn fit(template: &[(&'static str, u32)], target: Option<u32>) -> Vec<(&'static str, u32)> {
+    let mut sections = template.to_vec();
+    let Some(target) = target else { return sections };
+    let total: u32 = template.iter().map(|(_, bars)| bars).sum();
+    let scale = target as f64 / total as f64;
+    for (_, bars) in sections.iter_mut() {
+        let phrases = (*bars as f64 * scale / PHRASE_BARS as f64).round().max(1.0) as u32;
+        *bars = phrases * PHRASE_BARS;
+    }
+    // Rounding leaves it a phrase or two off: trim the longest sections, or
+    // grow the first section that comes back (the chorus, the drop)
+    let repeated = |name: &str| template.iter().filter(|(n, _)| *n == name).count() > 1;
+    for _ in 0..sections.len() * 4 {
+        let total: u32 = sections.iter().map(|(_, bars)| bars).sum();
+        if total >= target + PHRASE_BARS {
+            let Some(longest) = sections.iter_mut().filter(|(_, bars)| *bars > PHRASE_BARS).max_by_key(|(_, bars)| *bars) else { break };
+            longest.1 -= PHRASE_BARS;
+        } else if total + PHRASE_BARS <= target {
+            let grow = sections.iter().position(|(name, _)| repeated(name)).unwrap_or(sections.len() / 2);
+            let name = sections[grow].0;
+            // Its returns take turns growing
+            let Some(shortest) = sections.iter_mut().filter(|(n, _)| *n == name).min_by_key(|(_, bars)| *bars) else { break };
+            shortest.1 += PHRASE_BARS;
+        } else {
+            break;
+        }
+    }
+    sections
+}
+
+fn lay_out(sections: &[(&'static str, u32)], bpm: f64) -> Vec<Section> {
+    let bar_seconds = 60.0 / bpm * BEATS_PER_BAR as f64;
+    let mut seen: Vec<&str> = Vec::new();
+    let mut bar = 1;
+    sections
+        .iter()
+        .map(|(name, bars)| {
+            seen.push(*name);
+            let number = seen.iter().filter(|n| **n == *name).count();
+            let repeats = sections.iter().filter(|(n, _)| n == name).count() > 1;
+            let section = Section {
+                name: if repeats { format!("{} {}", name, number) } else { name.to_string() },
+                start_bar: bar,
+                bars: *bars,
+                start_seconds: (bar - 1) as f64 * bar_seconds,
+            };
+            bar += bars;
+            section
+        })
+        .collect()
+}
+
+// A tempo map and one marker per section, which DAWs import as locators
+fn write_markers(path: &Path, sections: &[Section], bpm: f64) -> Result<(), String> {
+    let mut events = vec![(0, midi::tempo_event(bpm)), (0, midi::time_signature_event(BEATS_PER_BAR, 4))];
+    for section in sections {
+        let tick = midi::beats_to_ticks((section.start_bar - 1) as f64 * BEATS_PER_BAR as f64);
+        events.push((tick, TrackEventKind::Meta(MetaMessage::Marker(section.name.as_bytes()))));
+    }
+    let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(u15::new(PPQ))));
+    smf.tracks.push(midi::to_track(events));
+    smf.save(path).map_err(|e| format!("Failed to write MIDI file: {}", e))
+}
+
+fn unique_path(dir: &Path, name: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.{}", name, extension));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{} {}.{}", name, n, extension));
+        n += 1;
+    }
+    path
+}
+
+#[tauri::command]
+pub async fn list_arrangement_genres() -> Result<Vec<Genre>, String> {
+    Ok(GENRES.to_vec())
+}
+
+// Lays out a song's sections for `genre`, stretched to about `length`
+// seconds when given, at `bpm` (the genre's usual tempo by default). With
+// `dest_dir` it is also written there as a MIDI marker track and as JSON.
+#[tauri::command]
+pub async fn generate_arrangement(
+    db: State<'_, Db>,
+    genre: Genre,
+    length: Option<f64>,
+    bpm: Option<f64>,
+    dest_dir: Option<String>,
+) -> Result<Arrangement, String> {
+    let bpm = bpm.unwrap_or(genre.bpm());
+    if !(20.0..=400.0).contains(&bpm) {
+        return Err("Tempo must be between 20 and 400 BPM".to_string());
+    }
+    let bar_seconds = 60.0 / bpm * BEATS_PER_BAR as f64;
+    let target = match length {
+        Some(seconds) if seconds.is_nan() || !(10.0..=3600.0).contains(&seconds) => {
+            return Err("Length must be between 10 seconds and an hour".to_string());
+        }
+        Some(seconds) => Some(((seconds / bar_seconds).round() as u32).max(PHRASE_BARS)),
+        None => None,
+    };
+    let sections = lay_out(&fit(genre.template(), target), bpm);
+    let bars: u32 = sections.iter().map(|s| s.bars).sum();
+    let mut arrangement = Arrangement {
+        genre,
+        bpm,
+        beats_per_bar: BEATS_PER_BAR,
+        bars,
+        seconds: bars as f64 * bar_seconds,
+        sections,
+        midi_path: None,
+        json_path: None,
+    };
+
+    if let Some(dir) = dest_dir {
+        let dir = paths::to_fs(&dir);
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let genre_name = serde_json::to_value(genre).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+        let name = safe_file_name(&format!("{} arrangement {} bpm", genre_name, bpm.round()));
+        let midi_path = unique_path(&dir, &name, "mid");
+        write_markers(&midi_path, &arrangement.sections, bpm)?;
+        takes::index(&db.lock(), &midi_path)?;
+        arrangement.midi_path = Some(paths::display(&midi_path));
+        let json_path = unique_path(&dir, &name, "json");
+        arrangement.json_path = Some(paths::display(&json_path));
+        let json = serde_json::to_string_pretty(&arrangement).map_err(|e| e.to_string())?;
+        fs::write(&json_path, json).map_err(|e| format!("Failed to write {}: {}", json_path.display(), e))?;
+    }
+    Ok(arrangement)
+}
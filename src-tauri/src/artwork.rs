@@ -0,0 +1,194 @@
+use crate::audio;
+use crate::paths;
+use crate::sandbox::Sandbox;
+use lofty::file::TaggedFileExt;
+use lofty::picture::{Picture, PictureType};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager, State};
+
+const MIN_SIZE: u32 = 16;
+const MAX_SIZE: u32 = 1024;
+// How much of a file to draw when its header doesn't say how long it is
+const WAVEFORM_SECONDS: f64 = 120.0;
+const FOLDER_ART: &[&str] = &["cover.jpg", "cover.png", "folder.jpg", "folder.png", "front.jpg", "artwork.jpg"];
+
+#[derive(serde::Serialize)]
+pub struct Artwork {
+    path: String,
+    source: String,
+    cached: bool,
+}
+
+fn cache_key(files: &[&Path], size: u32) -> Result<String, String> {
+    // Include size and mtime so edited files get fresh artwork
+    let mut hasher = Sha256::new();
+    for path in files {
+        let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update(metadata.len().to_le_bytes());
+        hasher.update(modified.to_le_bytes());
+    }
+    Ok(format!("{}_{}", &hex::encode(hasher.finalize())[..32], size))
+}
+
+fn embedded_picture(path: &Path) -> Option<Vec<u8>> {
+    let tagged = lofty::read_from_path(path).ok()?;
+    let pictures: Vec<&Picture> = tagged.tags().iter().flat_map(|tag| tag.pictures()).collect();
+    pictures
+        .iter()
+        .find(|p| p.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.first())
+        .map(|p| p.data().to_vec())
+}
+
+fn folder_art(path: &Path) -> Option<PathBuf> {
+    let dir = path.parent()?;
+    FOLDER_ART.iter().map(|name| dir.join(name)).find(|candidate| candidate.is_file())
+}
+
+fn write_thumbnail(bytes: &[u8], size: u32, output: &Path) -> Result<(), String> {
+    let image = image::load_from_memory(bytes).map_err(|e| format!("Failed to decode artwork: {}", e))?;
+    image
+        .thumbnail(size, size)
+        .save_with_format(output, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to write artwork: {}", e))
+}
+
+fn write_waveform(path: &Path, size: u32, output: &Path) -> Result<(), String> {
+    let width = size;
+    let height = (size / 2).max(MIN_SIZE / 2);
+    let mid = height as f32 / 2.0;
+
+    // Peaks per column, streamed so hour-long files never sit in memory
+    let format = audio::probe_format(path)?;
+    let seconds = format.duration.unwrap_or(WAVEFORM_SECONDS);
+    let total_frames = (seconds * format.sample_rate as f64).max(1.0);
+    let per_column = (total_frames / width as f64).max(1.0);
+    let mut columns = vec![0.0f32; width as usize];
+    let mut frame = 0u64;
+    audio::stream_file(path, |block, _, channels| {
+        let channels = channels.max(1);
+        for samples in block.chunks(channels) {
+            let column = (frame as f64 / per_column) as usize;
+            if column >= columns.len() {
+                return false;
+            }
+            let value = samples.iter().sum::<f32>() / channels as f32;
+            columns[column] = columns[column].max(value.abs());
+            frame += 1;
+        }
+        true
+    })?;
+
+    let mut image = image::RgbaImage::from_pixel(width, height, image::Rgba([24, 24, 32, 255]));
+    if frame == 0 {
+        image.save_with_format(output, image::ImageFormat::Png).map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    let peak = columns.iter().fold(0.0f32, |m, c| m.max(*c)).max(1e-6);
+    // Columns past the end of a file shorter than its header claimed stay empty
+    let drawn = ((frame as f64 / per_column).ceil() as usize).min(columns.len());
+    for (x, column) in columns.iter().take(drawn).enumerate() {
+        // Normalize so quiet one-shots are still visible
+        let extent = (column / peak * mid).max(0.5);
+        let top = (mid - extent).max(0.0) as u32;
+        let bottom = ((mid + extent) as u32).min(height - 1);
+        for y in top..=bottom {
+            image.put_pixel(x as u32, y, image::Rgba([96, 165, 250, 255]));
+        }
+    }
+
+    image.save_with_format(output, image::ImageFormat::Png).map_err(|e| e.to_string())
+}
+
+pub fn artwork_cache_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_cache_dir().map_err(|e| e.to_string())?.join("artwork");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+#[tauri::command]
+pub async fn get_artwork(app: AppHandle, sandbox: State<'_, Sandbox>, path: String, size: Option<u32>) -> Result<Artwork, String> {
+    let source_path = sandbox.check(&paths::to_fs(&path))?;
+    let size = size.unwrap_or(256).clamp(MIN_SIZE, MAX_SIZE);
+    let cache_dir = artwork_cache_dir(&app)?;
+
+    tokio::task::spawn_blocking(move || {
+        // Each source is cached under its own name, so a hit reports where
+        // the picture came from. Folder art is keyed on the image too, so
+        // adding or replacing one is picked up.
+        let key = cache_key(&[&source_path], size)?;
+        let art = folder_art(&source_path);
+        let embedded_path = cache_dir.join(format!("{}_embedded.png", key));
+        let folder_path = match &art {
+            Some(art) => Some(cache_dir.join(format!("{}_folder.png", cache_key(&[&source_path, art], size)?))),
+            None => None,
+        };
+        let waveform_path = cache_dir.join(format!("{}_wave.png", key));
+
+        // Without folder art the waveform is the fallback, so it only counts
+        // while there still is none
+        let candidates = [
+            (Some(&embedded_path), "embedded"),
+            (folder_path.as_ref(), "folder"),
+            (art.is_none().then_some(&waveform_path), "waveform"),
+        ];
+        for (cached, source) in candidates {
+            if let Some(cached) = cached.filter(|c| c.is_file()) {
+                return Ok(Artwork {
+                    path: paths::display(cached),
+                    source: source.to_string(),
+                    cached: true,
+                });
+            }
+        }
+
+        // A picture the image decoder can't read (BMP or TIFF covers) falls
+        // through to the next source rather than failing the lookup
+        let embedded = embedded_picture(&source_path).map_or(false, |bytes| write_thumbnail(&bytes, size, &embedded_path).is_ok());
+        if embedded {
+            return Ok(Artwork {
+                path: paths::display(&embedded_path),
+                source: "embedded".to_string(),
+                cached: false,
+            });
+        }
+
+        let folder = match (&art, &folder_path) {
+            (Some(art), Some(folder_path)) => fs::read(art).map_or(false, |bytes| write_thumbnail(&bytes, size, folder_path).is_ok()),
+            _ => false,
+        };
+        if let (true, Some(folder_path)) = (folder, &folder_path) {
+            return Ok(Artwork {
+                path: paths::display(folder_path),
+                source: "folder".to_string(),
+                cached: false,
+            });
+        }
+
+        write_waveform(&source_path, size, &waveform_path)?;
+        Ok(Artwork {
+            path: paths::display(&waveform_path),
+            source: "waveform".to_string(),
+            cached: false,
+        })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn clear_artwork_cache(app: AppHandle) -> Result<(), String> {
+    let dir = artwork_cache_dir(&app)?;
+    fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(())
+}
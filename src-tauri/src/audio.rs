@@ -0,0 +1,245 @@
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{
+    CodecParameters, DecoderOptions, CODEC_TYPE_AAC, CODEC_TYPE_MP1, CODEC_TYPE_MP2, CODEC_TYPE_MP3, CODEC_TYPE_NULL,
+    CODEC_TYPE_OPUS, CODEC_TYPE_VORBIS,
+};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+
+// Decoded PCM, interleaved f32 in -1.0..1.0
+pub struct AudioData {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub samples: Vec<f32>,
+}
+
+impl AudioData {
+    pub fn to_mono(&self) -> Vec<f32> {
+        if self.channels <= 1 {
+            return self.samples.clone();
+        }
+        self.samples
+            .chunks(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() / self.channels as f32)
+            .collect()
+    }
+}
+
+// What a file's header says, without decoding any audio
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: usize,
+    // None for lossy codecs, which have no fixed bit depth
+    pub bits_per_sample: Option<u32>,
+    pub duration: Option<f64>,
+}
+
+// Opens the first audio track. Gapless mode trims MP3/AAC encoder delay and
+// padding where the file records them, so sample 0 is the first real sample
+// and a time in seconds means the same thing here as in a DAW.
+fn open_track(path: &Path) -> Result<(Box<dyn FormatReader>, u32, CodecParameters), String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open audio file: {}", e))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let options = FormatOptions { enable_gapless: true, ..FormatOptions::default() };
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &options, &MetadataOptions::default())
+        .map_err(|e| format!("Unsupported audio format: {}", e))?;
+    let track = probed
+        .format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| "No audio track found".to_string())?;
+    let (track_id, params) = (track.id, track.codec_params.clone());
+    Ok((probed.format, track_id, params))
+}
+
+pub fn probe_format(path: &Path) -> Result<AudioFormat, String> {
+    let (_, _, params) = open_track(path)?;
+    let sample_rate = params.sample_rate.unwrap_or(44_100);
+    Ok(AudioFormat {
+        sample_rate,
+        channels: params.channels.map(|c| c.count()).unwrap_or(2),
+        bits_per_sample: params.bits_per_sample,
+        duration: params.n_frames.map(|frames| frames as f64 / sample_rate as f64),
+    })
+}
+
+// How far to trust positions and lengths for a file, so the UI can warn
+// about formats where they're approximate
+#[derive(serde::Serialize)]
+pub struct DecodeAccuracy {
+    codec: String,
+    lossy: bool,
+    // Frame count comes from the container or an encoder info header
+    // (Xing/LAME, MP4 sample table) rather than a guess from the bitrate
+    exact_length: bool,
+    // Encoder delay and padding are known and trimmed
+    gapless: bool,
+    // Seeks land on the exact sample: always true once decoded, since
+    // playback holds the whole file and partial reads seek accurately
+    sample_accurate_seek: bool,
+    warnings: Vec<String>,
+}
+
+pub fn decode_accuracy(path: &Path) -> Result<DecodeAccuracy, String> {
+    let (_, _, params) = open_track(path)?;
+    let codec = symphonia::default::get_codecs()
+        .get_codec(params.codec)
+        .map(|d| d.short_name.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let mp3 = [CODEC_TYPE_MP1, CODEC_TYPE_MP2, CODEC_TYPE_MP3].contains(&params.codec);
+    let lossy = mp3 || [CODEC_TYPE_AAC, CODEC_TYPE_VORBIS, CODEC_TYPE_OPUS].contains(&params.codec);
+    let gapless = !lossy || params.delay.is_some() || params.padding.is_some();
+    // MPEG streams carry no length of their own; symphonia estimates one
+    // from the first frame's bitrate unless an info header gives it
+    let exact_length = params.n_frames.is_some() && (!mp3 || params.delay.is_some());
+
+    let mut warnings = Vec::new();
+    if !exact_length {
+        warnings.push("Length is estimated from the bitrate until the file has been fully decoded".to_string());
+    }
+    if !gapless {
+        warnings.push("Encoder delay isn't recorded, so positions may sit a few milliseconds late against other software".to_string());
+    }
+    Ok(DecodeAccuracy { codec, lossy, exact_length, gapless, sample_accurate_seek: true, warnings })
+}
+
+// Decodes any format symphonia understands, handing interleaved blocks of
+// whole frames to `on_block` as they come off the disk so memory stays
+// bounded however long the file is. Return false from `on_block` to stop.
+// Gives back the sample rate and channel count of the last block.
+pub fn stream_file(path: &Path, on_block: impl FnMut(&[f32], u32, usize) -> bool) -> Result<(u32, usize), String> {
+    stream_from(path, 0.0, on_block)
+}
+
+// `stream_file` starting `start` seconds in. The seek is accurate rather than
+// coarse: for VBR MP3 that means walking frame headers instead of trusting
+// the Xing table of contents, and MP4 uses its sample table, so the first
+// block starts on the requested sample.
+pub fn stream_from(path: &Path, start: f64, mut on_block: impl FnMut(&[f32], u32, usize) -> bool) -> Result<(u32, usize), String> {
+    let (mut format, track_id, params) = open_track(path)?;
+    let mut sample_rate = params.sample_rate.unwrap_or(44_100);
+    let mut channels = params.channels.map(|c| c.count()).unwrap_or(2);
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions::default())
+        .map_err(|e| format!("Unsupported codec: {}", e))?;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+
+    // Frames to drop from the first decoded packets: a seek lands on the
+    // packet containing `start`, not on the sample itself
+    let mut skip_frames = 0u64;
+    if start > 0.0 {
+        let seeked = format
+            .seek(SeekMode::Accurate, SeekTo::Time { time: Time::from(start), track_id: Some(track_id) })
+            .map_err(|e| format!("Failed to seek: {}", e))?;
+        decoder.reset();
+        skip_frames = match params.time_base {
+            Some(tb) => {
+                let late = tb.calc_time(seeked.required_ts.saturating_sub(seeked.actual_ts));
+                ((late.seconds as f64 + late.frac) * sample_rate as f64).round() as u64
+            }
+            None => seeked.required_ts.saturating_sub(seeked.actual_ts),
+        };
+    }
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(format!("Failed to read audio: {}", e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                sample_rate = spec.rate;
+                channels = spec.channels.count();
+                // One buffer reused for every packet, regrown only if a
+                // packet is bigger than any before it
+                let fits = buffer.as_ref().map(|b| b.capacity() >= decoded.capacity() * channels).unwrap_or(false);
+                if !fits {
+                    buffer = Some(SampleBuffer::<f32>::new(decoded.capacity() as u64, spec));
+                }
+                let Some(buffer) = buffer.as_mut() else { continue };
+                buffer.copy_interleaved_ref(decoded);
+                let mut samples = buffer.samples();
+                if skip_frames > 0 {
+                    let skip = (skip_frames as usize).min(samples.len() / channels.max(1));
+                    samples = &samples[skip * channels..];
+                    skip_frames -= skip as u64;
+                }
+                if !samples.is_empty() && !on_block(samples, sample_rate, channels) {
+                    break;
+                }
+            }
+            // A corrupt frame shouldn't sink the whole file
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("Failed to decode audio: {}", e)),
+        }
+    }
+
+    Ok((sample_rate, channels))
+}
+
+// Decodes a whole file into memory. `max_seconds` stops early for callers
+// that only need the start of a file (thumbnails, previews); anything that
+// may see hour-long recordings should use `stream_file` instead.
+pub fn decode_file(path: &Path, max_seconds: Option<f64>) -> Result<AudioData, String> {
+    decode_range(path, 0.0, max_seconds)
+}
+
+// Up to `max_seconds` of audio from `start` seconds in, without decoding
+// what comes before
+pub fn decode_range(path: &Path, start: f64, max_seconds: Option<f64>) -> Result<AudioData, String> {
+    let mut samples = Vec::new();
+    let (sample_rate, channels) = stream_from(path, start, |block, rate, channels| {
+        samples.extend_from_slice(block);
+        match max_seconds {
+            Some(seconds) => {
+                let max = (seconds * rate as f64) as usize * channels;
+                if samples.len() >= max {
+                    samples.truncate(max);
+                    return false;
+                }
+                true
+            }
+            None => true,
+        }
+    })?;
+    Ok(AudioData { sample_rate, channels, samples })
+}
+
+// 24-bit PCM, which keeps everything 16-bit sources had plus headroom for
+// anything we processed
+pub fn write_wav(path: &Path, audio: &AudioData) -> Result<(), String> {
+    let spec = hound::WavSpec {
+        channels: audio.channels as u16,
+        sample_rate: audio.sample_rate,
+        bits_per_sample: 24,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec).map_err(|e| format!("Failed to create WAV: {}", e))?;
+    let scale = (1 << 23) as f32 - 1.0;
+    for sample in &audio.samples {
+        writer
+            .write_sample((sample.clamp(-1.0, 1.0) * scale) as i32)
+            .map_err(|e| format!("Failed to write WAV: {}", e))?;
+    }
+    writer.finalize().map_err(|e| format!("Failed to write WAV: {}", e))
+}
@@ -0,0 +1,398 @@
+use crate::db::Db;
+use crate::input;
+use crate::playback::{self, Playback};
+use crate::preroll::PreRoll;
+use crate::settings;
+use crate::takes::Recorder;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rusqlite::Connection;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+
+// Rates offered in the settings UI when the device supports them
+const COMMON_RATES: [u32; 6] = [44_100, 48_000, 88_200, 96_000, 176_400, 192_000];
+// A callback arriving this much later than its buffer's length after the
+// previous one means the device ran dry (or overflowed) in between
+const XRUN_SLACK: f64 = 1.5;
+// Loopback measurement: a click every half second for three seconds
+const CLICK_INTERVAL: f64 = 0.5;
+const MEASURE_SECONDS: f64 = 3.0;
+// An input sample this loud after a click is the click coming back
+const CLICK_THRESHOLD: f32 = 0.1;
+
+// What the user asked the audio engine for; None keeps the device default
+#[derive(Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AudioConfig {
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    // Frames per callback
+    #[serde(default)]
+    pub buffer_size: Option<u32>,
+}
+
+pub fn load(conn: &Connection) -> AudioConfig {
+    settings::get(conn, "audio.config").ok().flatten().unwrap_or_default()
+}
+
+// Hands the open profile's engine settings and output device to playback
+// after a profile switch; the pre-roll buffer picks them up when it reloads
+pub fn reload(app: &AppHandle) {
+    let (config, device) = {
+        let db = app.state::<Db>();
+        let conn = db.lock();
+        (load(&conn), settings::get::<String>(&conn, "playback.output_device").ok().flatten())
+    };
+    // An output that's gone or can't do them falls back as it does at launch
+    let playback = app.state::<Playback>();
+    let _ = playback.set_output_device(device);
+    let _ = playback.set_config(config);
+}
+
+// Counters an open stream's callback keeps up to date
+#[derive(Default)]
+pub struct StreamStats {
+    xruns: AtomicU64,
+    // Device-reported one-way latency of the latest callback
+    latency_us: AtomicU64,
+}
+
+// Lives in a stream's callback, watching the gaps between callbacks
+pub struct Monitor {
+    stats: Arc<StreamStats>,
+    rate: u32,
+    last: Option<cpal::StreamInstant>,
+}
+
+impl Monitor {
+    pub fn new(stats: Arc<StreamStats>, rate: u32) -> Monitor {
+        Monitor { stats, rate, last: None }
+    }
+
+    fn tick(&mut self, callback: cpal::StreamInstant, frames: usize, latency: Option<Duration>) {
+        if let Some(last) = self.last {
+            let expected = frames as f64 / self.rate.max(1) as f64;
+            if let Some(gap) = callback.duration_since(&last) {
+                if gap.as_secs_f64() > expected * XRUN_SLACK {
+                    self.stats.xruns.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        self.last = Some(callback);
+        if let Some(latency) = latency {
+            self.stats.latency_us.store(latency.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub fn output(&mut self, info: &cpal::OutputCallbackInfo, frames: usize) {
+        let stamp = info.timestamp();
+        self.tick(stamp.callback, frames, stamp.playback.duration_since(&stamp.callback));
+    }
+
+    pub fn input(&mut self, info: &cpal::InputCallbackInfo, frames: usize) {
+        let stamp = info.timestamp();
+        self.tick(stamp.callback, frames, stamp.callback.duration_since(&stamp.capture));
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct StreamInfo {
+    pub device: String,
+    pub sample_rate: u32,
+    // None when the driver picks
+    pub buffer_size: Option<u32>,
+    pub latency_ms: f64,
+    pub xruns: u64,
+}
+
+impl StreamInfo {
+    pub fn new(device: &str, sample_rate: u32, buffer_size: Option<u32>, stats: &StreamStats) -> StreamInfo {
+        StreamInfo {
+            device: device.to_string(),
+            sample_rate,
+            buffer_size,
+            latency_ms: stats.latency_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            xruns: stats.xruns.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct DeviceCapabilities {
+    device: String,
+    sample_rates: Vec<u32>,
+    min_buffer_size: Option<u32>,
+    max_buffer_size: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AudioStatus {
+    config: AudioConfig,
+    // None until something has played
+    output: Option<StreamInfo>,
+    // The input in use by recording or the pre-roll buffer, if any
+    input: Option<StreamInfo>,
+    output_device: Option<DeviceCapabilities>,
+    input_device: Option<DeviceCapabilities>,
+}
+
+#[derive(serde::Serialize)]
+pub struct LatencyMeasurement {
+    // Output to input through the loopback, what a recording lags by
+    round_trip_ms: f64,
+    clicks_heard: usize,
+    output_latency_ms: f64,
+    input_latency_ms: f64,
+}
+
+fn supported_ranges(device: &cpal::Device, output: bool) -> Result<Vec<cpal::SupportedStreamConfigRange>, String> {
+    let ranges = if output {
+        device.supported_output_configs().map(|r| r.collect())
+    } else {
+        device.supported_input_configs().map(|r| r.collect())
+    };
+    ranges.map_err(|e| format!("Failed to query audio device: {}", e))
+}
+
+// The stream config for `device` honouring `wanted`, closest to the
+// device's default layout. Errors name what the device can do instead.
+pub fn choose(device: &cpal::Device, output: bool, wanted: &AudioConfig) -> Result<(cpal::StreamConfig, cpal::SampleFormat), String> {
+    let default = if output { device.default_output_config() } else { device.default_input_config() }
+        .map_err(|e| format!("Failed to query audio device: {}", e))?;
+    let mut supported = default.clone();
+    if let Some(rate) = wanted.sample_rate {
+        supported = supported_ranges(device, output)?
+            .into_iter()
+            .filter(|r| r.min_sample_rate().0 <= rate && rate <= r.max_sample_rate().0)
+            .max_by_key(|r| (r.channels() == default.channels(), r.sample_format() == default.sample_format()))
+            .ok_or_else(|| format!("{} doesn't support {} Hz", device.name().unwrap_or_default(), rate))?
+            .with_sample_rate(cpal::SampleRate(rate));
+    }
+    let mut config = supported.config();
+    if let Some(frames) = wanted.buffer_size {
+        if let cpal::SupportedBufferSize::Range { min, max } = supported.buffer_size() {
+            if frames < *min || frames > *max {
+                return Err(format!("Buffer size must be between {} and {} frames", min, max));
+            }
+        }
+        config.buffer_size = cpal::BufferSize::Fixed(frames);
+    }
+    Ok((config, supported.sample_format()))
+}
+
+pub fn buffer_frames(config: &cpal::StreamConfig) -> Option<u32> {
+    match config.buffer_size {
+        cpal::BufferSize::Fixed(frames) => Some(frames),
+        cpal::BufferSize::Default => None,
+    }
+}
+
+fn capabilities(device: &cpal::Device, output: bool) -> Result<DeviceCapabilities, String> {
+    let ranges = supported_ranges(device, output)?;
+    let sample_rates = COMMON_RATES
+        .iter()
+        .copied()
+        .filter(|rate| ranges.iter().any(|r| r.min_sample_rate().0 <= *rate && *rate <= r.max_sample_rate().0))
+        .collect();
+    let sizes: Vec<(u32, u32)> = ranges
+        .iter()
+        .filter_map(|r| match r.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } => Some((*min, *max)),
+            cpal::SupportedBufferSize::Unknown => None,
+        })
+        .collect();
+    Ok(DeviceCapabilities {
+        device: device.name().unwrap_or_default(),
+        sample_rates,
+        min_buffer_size: sizes.iter().map(|s| s.0).min(),
+        max_buffer_size: sizes.iter().map(|s| s.1).max(),
+    })
+}
+
+// Emits a click every CLICK_INTERVAL on the default output while listening
+// on `input_device`, and times each click's return through a loopback
+// cable (or the interface's own loopback) using the streams' timestamps
+fn measure(input_device: Option<String>, config: AudioConfig) -> Result<LatencyMeasurement, String> {
+    let host = cpal::default_host();
+    let output = host.default_output_device().ok_or_else(|| "No audio output device".to_string())?;
+    let input = input::find_device(input_device.as_deref())?;
+    let (out_config, _) = choose(&output, true, &config)?;
+    let (in_config, _) = choose(&input, false, &config)?;
+    let (out_rate, out_channels) = (out_config.sample_rate.0, out_config.channels as usize);
+    let (in_rate, in_channels) = (in_config.sample_rate.0, in_config.channels as usize);
+
+    // When each click will leave the speakers, and when each loud input
+    // sample came in; sent over rather than pushed under a lock, so neither
+    // callback can be held up
+    let (emitted, clicks) = mpsc::channel::<cpal::StreamInstant>();
+    let (captured, heard) = mpsc::channel::<cpal::StreamInstant>();
+    let out_stats = Arc::new(StreamStats::default());
+    let in_stats = Arc::new(StreamStats::default());
+    let (failed_tx, failed_rx) = mpsc::channel::<String>();
+
+    let click_every = (CLICK_INTERVAL * out_rate as f64) as u64;
+    let mut written = 0u64;
+    let mut out_monitor = Monitor::new(out_stats.clone(), out_rate);
+    let out_failed = failed_tx.clone();
+    let out_stream = output
+        .build_output_stream(
+            &out_config,
+            move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+                out_monitor.output(info, data.len() / out_channels.max(1));
+                for (i, frame) in data.chunks_mut(out_channels.max(1)).enumerate() {
+                    let n = written + i as u64;
+                    let value = if n % click_every == 0 && n > 0 { 0.9 } else { 0.0 };
+                    frame.fill(value);
+                    if value > 0.0 {
+                        let offset = Duration::from_secs_f64(i as f64 / out_rate as f64);
+                        if let Some(at) = info.timestamp().playback.add(offset) {
+                            let _ = emitted.send(at);
+                        }
+                    }
+                }
+                written += (data.len() / out_channels.max(1)) as u64;
+            },
+            move |e| {
+                let _ = out_failed.send(e.to_string());
+            },
+            None,
+        )
+        .map_err(|e| format!("Failed to open audio output: {}", e))?;
+
+    let mut in_monitor = Monitor::new(in_stats.clone(), in_rate);
+    // Ignores the ringing after a detected click
+    let holdoff = (CLICK_INTERVAL / 2.0 * in_rate as f64) as usize;
+    let mut quiet_for = holdoff;
+    let in_stream = input
+        .build_input_stream(
+            &in_config,
+            move |data: &[f32], info: &cpal::InputCallbackInfo| {
+                in_monitor.input(info, data.len() / in_channels.max(1));
+                for (i, frame) in data.chunks(in_channels.max(1)).enumerate() {
+                    quiet_for += 1;
+                    if quiet_for > holdoff && frame.iter().any(|s| s.abs() > CLICK_THRESHOLD) {
+                        quiet_for = 0;
+                        let offset = Duration::from_secs_f64(i as f64 / in_rate as f64);
+                        if let Some(at) = info.timestamp().capture.add(offset) {
+                            let _ = captured.send(at);
+                        }
+                    }
+                }
+            },
+            move |e| {
+                let _ = failed_tx.send(e.to_string());
+            },
+            None,
+        )
+        .map_err(|e| format!("Failed to open audio input: {}", e))?;
+
+    in_stream.play().map_err(|e| format!("Failed to start audio input: {}", e))?;
+    out_stream.play().map_err(|e| format!("Failed to start audio output: {}", e))?;
+    if let Ok(e) = failed_rx.recv_timeout(Duration::from_secs_f64(MEASURE_SECONDS)) {
+        return Err(format!("Audio device error during measurement: {}", e));
+    }
+    drop(out_stream);
+    drop(in_stream);
+
+    // Each click pairs with the first loud input after it, if that comes
+    // before the next click
+    let clicks: Vec<cpal::StreamInstant> = clicks.try_iter().collect();
+    let heard: Vec<cpal::StreamInstant> = heard.try_iter().collect();
+    let mut delays = Vec::new();
+    for (i, click) in clicks.iter().enumerate() {
+        let before_next = |h: &cpal::StreamInstant| match clicks.get(i + 1) {
+            Some(next) => next.duration_since(h).is_some(),
+            None => true,
+        };
+        let returned = heard.iter().find(|h| h.duration_since(click).is_some() && before_next(h));
+        if let Some(delay) = returned.and_then(|h| h.duration_since(click)) {
+            delays.push(delay.as_secs_f64());
+        }
+    }
+    if delays.is_empty() {
+        return Err("No clicks came back; connect the output to the input (or enable loopback) and try again".to_string());
+    }
+    delays.sort_by(|a, b| a.total_cmp(b));
+    Ok(LatencyMeasurement {
+        round_trip_ms: delays[delays.len() / 2] * 1000.0,
+        clicks_heard: delays.len(),
+        output_latency_ms: out_stats.latency_us.load(Ordering::Relaxed) as f64 / 1000.0,
+        input_latency_ms: in_stats.latency_us.load(Ordering::Relaxed) as f64 / 1000.0,
+    })
+}
+
+// The configured rate and buffer size, what the open streams actually run
+// at with their latency and xrun counts, and what the devices support
+#[tauri::command]
+pub async fn get_audio_config(
+    db: State<'_, Db>,
+    playback: State<'_, Playback>,
+    preroll: State<'_, PreRoll>,
+    recorder: State<'_, Recorder>,
+) -> Result<AudioStatus, String> {
+    let config = load(&db.lock());
+    let output = playback.output_info();
+    let input = recorder.input_info().or_else(|| preroll.input_info());
+    let (output_device, input_device) = tokio::task::spawn_blocking(|| {
+        let host = cpal::default_host();
+        (
+            host.default_output_device().and_then(|d| capabilities(&d, true).ok()),
+            host.default_input_device().and_then(|d| capabilities(&d, false).ok()),
+        )
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?;
+    Ok(AudioStatus { config, output, input, output_device, input_device })
+}
+
+// Sets the engine's sample rate and buffer size (None for the device
+// default). Playback and the pre-roll buffer reopen with them at once;
+// recording picks them up from the next take.
+#[tauri::command]
+pub async fn set_audio_config(
+    app: AppHandle,
+    db: State<'_, Db>,
+    recorder: State<'_, Recorder>,
+    sample_rate: Option<u32>,
+    buffer_size: Option<u32>,
+) -> Result<AudioConfig, String> {
+    if recorder.input_info().is_some() {
+        return Err("Stop recording before changing audio settings".to_string());
+    }
+    let config = AudioConfig { sample_rate, buffer_size };
+    // Refuse what the default devices can't do before saving it
+    let check = config.clone();
+    tokio::task::spawn_blocking(move || {
+        let host = cpal::default_host();
+        if let Some(device) = host.default_output_device() {
+            choose(&device, true, &check)?;
+        }
+        if let Some(device) = host.default_input_device() {
+            choose(&device, false, &check)?;
+        }
+        Ok::<(), String>(())
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))??;
+
+    settings::set(&db.lock(), "audio.config", &serde_json::to_value(&config).map_err(|e| e.to_string())?)?;
+    let handle = app.clone();
+    playback::blocking(&app, move |playback| {
+        playback.set_config(config.clone())?;
+        handle.state::<PreRoll>().reopen(&config)?;
+        Ok(config)
+    })
+    .await
+}
+
+// Measures true round-trip latency with a loopback from the default output
+// to `input_device`. Takes a few seconds and clicks audibly.
+#[tauri::command]
+pub async fn measure_latency(db: State<'_, Db>, input_device: Option<String>) -> Result<LatencyMeasurement, String> {
+    let config = load(&db.lock());
+    tokio::task::spawn_blocking(move || measure(input_device, config))
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
+
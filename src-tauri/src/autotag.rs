@@ -0,0 +1,309 @@
+use crate::audio;
+use crate::db::{self, Db};
+use crate::dsp;
+use crate::jobs::{Priority, Scheduler};
+use crate::journal::{self, Operation};
+use crate::library;
+use crate::paths;
+use crate::quarantine;
+use rusqlite::params;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager, State};
+use tract_onnx::prelude::*;
+
+// Clips longer than this are classified on their first 30 s
+const CLASSIFY_SECONDS: f64 = 30.0;
+
+// Sidecar describing how to feed the classifier. Lives next to the model as
+// models/autotag.json so the model can be swapped without a rebuild.
+#[derive(serde::Deserialize)]
+struct ModelConfig {
+    sample_rate: u32,
+    n_fft: usize,
+    hop: usize,
+    n_mels: usize,
+    frames: usize,
+    #[serde(default = "default_threshold")]
+    threshold: f32,
+    // "sigmoid" (multi-label), "softmax" (best per group) or "none"
+    #[serde(default = "default_activation")]
+    activation: String,
+    labels: Vec<ModelLabel>,
+}
+
+#[derive(serde::Deserialize)]
+struct ModelLabel {
+    name: String,
+    #[serde(default)]
+    group: String,
+}
+
+fn default_threshold() -> f32 {
+    0.5
+}
+
+fn default_activation() -> String {
+    "sigmoid".to_string()
+}
+
+#[derive(serde::Serialize)]
+pub struct TagSuggestion {
+    id: i64,
+    path: String,
+    tag: String,
+    confidence: f64,
+    source: String,
+    status: String,
+}
+
+struct Classifier {
+    config: ModelConfig,
+    model: TypedRunnableModel<TypedModel>,
+}
+
+impl Classifier {
+    fn load(models_dir: &Path) -> Result<Option<Self>, String> {
+        let model_path = models_dir.join("autotag.onnx");
+        let config_path = models_dir.join("autotag.json");
+        if !model_path.is_file() || !config_path.is_file() {
+            return Ok(None);
+        }
+
+        let config: ModelConfig = serde_json::from_str(
+            &std::fs::read_to_string(&config_path).map_err(|e| e.to_string())?,
+        )
+        .map_err(|e| format!("Invalid autotag.json: {}", e))?;
+
+        let model = tract_onnx::onnx()
+            .model_for_path(&model_path)
+            .and_then(|m| m.with_input_fact(0, f32::fact([1, config.n_mels, config.frames]).into()))
+            .and_then(|m| m.into_optimized())
+            .and_then(|m| m.into_runnable())
+            .map_err(|e| format!("Failed to load classifier: {}", e))?;
+
+        Ok(Some(Classifier { config, model }))
+    }
+
+    fn classify(&self, mono: &[f32], sample_rate: u32) -> Result<Vec<(String, f32)>, String> {
+        let c = &self.config;
+        let samples = dsp::resample(mono, sample_rate, c.sample_rate);
+        let mel = dsp::log_mel_spectrogram(&samples, c.sample_rate, c.n_fft, c.hop, c.n_mels);
+
+        // Model expects [1, n_mels, frames]; pad short clips with silence
+        let silence = (1e-10f32).ln();
+        let input = tract_ndarray::Array3::from_shape_fn((1, c.n_mels, c.frames), |(_, m, f)| {
+            mel.get(f).map(|frame| frame[m]).unwrap_or(silence)
+        });
+
+        let outputs = self
+            .model
+            .run(tvec!(Tensor::from(input).into()))
+            .map_err(|e| format!("Classifier failed: {}", e))?;
+        let scores: Vec<f32> = outputs[0]
+            .to_array_view::<f32>()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .copied()
+            .collect();
+
+        let probabilities: Vec<f32> = match c.activation.as_str() {
+            "sigmoid" => scores.iter().map(|s| 1.0 / (1.0 + (-s).exp())).collect(),
+            "softmax" => {
+                let max = scores.iter().cloned().fold(f32::MIN, f32::max);
+                let exp: Vec<f32> = scores.iter().map(|s| (s - max).exp()).collect();
+                let sum: f32 = exp.iter().sum();
+                exp.iter().map(|e| e / sum).collect()
+            }
+            _ => scores,
+        };
+
+        let mut results = Vec::new();
+        if c.activation == "softmax" {
+            // Softmax models pick one label per group
+            let mut best: HashMap<&str, (usize, f32)> = HashMap::new();
+            for (i, label) in c.labels.iter().enumerate() {
+                let p = probabilities.get(i).copied().unwrap_or(0.0);
+                let entry = best.entry(label.group.as_str()).or_insert((i, p));
+                if p > entry.1 {
+                    *entry = (i, p);
+                }
+            }
+            for (_, (i, p)) in best {
+                if p >= c.threshold {
+                    results.push((c.labels[i].name.clone(), p));
+                }
+            }
+        } else {
+            for (label, p) in c.labels.iter().zip(&probabilities) {
+                if *p >= c.threshold {
+                    results.push((label.name.clone(), *p));
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+// Works without a model: short clips whose energy dies away are one-shots,
+// longer clips that are still sounding at the end are loops.
+fn heuristic_tags(mono: &[f32], sample_rate: u32, duration: f64) -> Vec<(String, f32)> {
+    let overall = dsp::rms(mono);
+    if overall <= 1e-5 {
+        return Vec::new();
+    }
+    let tail = dsp::rms(&mono[mono.len() - mono.len() / 10..]);
+    let head = dsp::rms(&mono[..(sample_rate as usize / 20).min(mono.len())]);
+    let sustain = tail / overall;
+
+    if duration < 2.5 && sustain < 0.3 {
+        let attack = (head / overall).min(2.0) / 2.0;
+        vec![("one-shot".to_string(), 0.6 + 0.35 * (1.0 - sustain) * attack.max(0.5))]
+    } else if duration >= 1.0 && sustain > 0.4 {
+        vec![("loop".to_string(), (0.5 + 0.4 * sustain.min(1.0)).min(0.95))]
+    } else {
+        Vec::new()
+    }
+}
+
+fn store_suggestions(db: &Db, path: &str, tags: &[(String, f32)], source: &str) -> Result<(), String> {
+    let conn = db.lock();
+    for (tag, confidence) in tags {
+        conn.execute(
+            "INSERT INTO tag_suggestions (path, tag, confidence, source, status, created_at)
+             VALUES (?1, ?2, ?3, ?4, 'pending', ?5)
+             ON CONFLICT(path, tag) DO UPDATE SET confidence = excluded.confidence, source = excluded.source
+             WHERE status = 'pending'",
+            params![path, tag, *confidence as f64, source, db::now()],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+pub fn models_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app.path().app_data_dir().map_err(|e| e.to_string())?.join("models"))
+}
+
+// Runs the classifier over `paths` in the background and queues the results
+// as pending suggestions. Returns the job id for the Jobs panel.
+#[tauri::command]
+pub async fn auto_tag(app: AppHandle, db: State<'_, Db>, scheduler: State<'_, Scheduler>, paths: Vec<String>) -> Result<u64, String> {
+    let db = db.inner().clone();
+    let models = models_dir(&app)?;
+    let label = format!("Auto-tag {} files", paths.len());
+
+    let id = scheduler.submit_on("analysis", label, Priority::Background, paths.clone(), move |ctx| {
+        let classifier = Classifier::load(&models)?;
+        let total = paths.len().max(1) as f32;
+
+        for (i, path) in paths.iter().enumerate() {
+            if ctx.is_cancelled() {
+                break;
+            }
+            ctx.progress(i as f32 / total, Some(path.clone()));
+
+            // Unreadable or crashing files are skipped rather than failing the batch
+            let tagged = quarantine::guard(&db, path, || -> Result<_, String> {
+                let decoded = audio::decode_file(&paths::to_fs(path), Some(CLASSIFY_SECONDS))?;
+                let mono = decoded.to_mono();
+                let duration = mono.len() as f64 / decoded.sample_rate as f64;
+                let heuristic = heuristic_tags(&mono, decoded.sample_rate, duration);
+                let model = match &classifier {
+                    Some(classifier) => Some(classifier.classify(&mono, decoded.sample_rate)?),
+                    None => None,
+                };
+                Ok((heuristic, model))
+            });
+            let (heuristic, model) = match tagged {
+                Ok(tags) => tags,
+                Err(_) => continue,
+            };
+
+            store_suggestions(&db, path, &heuristic, "heuristic")?;
+            if let Some(tags) = model {
+                store_suggestions(&db, path, &tags, "model")?;
+            }
+        }
+        Ok(())
+    });
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn list_tag_suggestions(
+    db: State<'_, Db>,
+    status: Option<String>,
+    min_confidence: Option<f64>,
+) -> Result<Vec<TagSuggestion>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, path, tag, confidence, source, status FROM tag_suggestions
+             WHERE status = ?1 AND confidence >= ?2
+             ORDER BY path, confidence DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let suggestions = stmt
+        .query_map(
+            params![status.unwrap_or_else(|| "pending".to_string()), min_confidence.unwrap_or(0.0)],
+            |row| {
+                Ok(TagSuggestion {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    tag: row.get(2)?,
+                    confidence: row.get(3)?,
+                    source: row.get(4)?,
+                    status: row.get(5)?,
+                })
+            },
+        )
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(suggestions)
+}
+
+// Accepting writes the tags through the journal so a bulk accept can be undone
+#[tauri::command]
+pub async fn review_tag_suggestions(db: State<'_, Db>, ids: Vec<i64>, accept: bool) -> Result<usize, String> {
+    let conn = db.lock();
+    let mut by_path: HashMap<String, Vec<String>> = HashMap::new();
+    for id in &ids {
+        let row: Option<(String, String)> = conn
+            .query_row(
+                "SELECT path, tag FROM tag_suggestions WHERE id = ?1 AND status = 'pending'",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .ok();
+        if let Some((path, tag)) = row {
+            by_path.entry(path).or_default().push(tag);
+        }
+    }
+
+    let status = if accept { "accepted" } else { "rejected" };
+    if accept {
+        let mut ops = Vec::new();
+        for (path, tags) in &by_path {
+            let before = library::tags_for(&conn, path)?;
+            let mut after: Vec<String> = before.iter().chain(tags.iter()).cloned().collect();
+            after.sort();
+            after.dedup();
+            if after != before {
+                ops.push(Operation::SetTags { path: path.clone(), before, after });
+            }
+        }
+        let label = format!("Accept suggested tags on {} files", ops.len());
+        journal::run(&conn, &label, ops)?;
+    }
+
+    for id in &ids {
+        conn.execute(
+            "UPDATE tag_suggestions SET status = ?1 WHERE id = ?2 AND status = 'pending'",
+            params![status, id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(by_path.values().map(|tags| tags.len()).sum())
+}
@@ -0,0 +1,207 @@
+use crate::db::Db;
+use crate::http::Http;
+use crate::jobs::Scheduler;
+use crate::settings;
+use chrono::{Local, NaiveTime, Timelike};
+use reqwest::Body;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+
+const SETTINGS_KEY: &str = "network.bandwidth";
+// Bodies are paced a chunk at a time
+const CHUNK: usize = 16 * 1024;
+// How often held-back job kinds are checked for their window opening
+const WINDOW_CHECK: Duration = Duration::from_secs(60);
+
+// A daily stretch of local time; one that ends before it starts runs past
+// midnight ("02:00"–"07:00" or "23:00"–"06:00")
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct TransferWindow {
+    start: String,
+    end: String,
+}
+
+impl TransferWindow {
+    fn minutes(time: &str) -> Result<u32, String> {
+        let time = NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| format!("Invalid time {}; use HH:MM", time))?;
+        Ok(time.hour() * 60 + time.minute())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        Self::minutes(&self.start)?;
+        Self::minutes(&self.end)?;
+        Ok(())
+    }
+
+    pub fn is_open(&self) -> bool {
+        let (Ok(start), Ok(end)) = (Self::minutes(&self.start), Self::minutes(&self.end)) else {
+            return true;
+        };
+        let now = Local::now();
+        let now = now.hour() * 60 + now.minute();
+        if start <= end {
+            (start..end).contains(&now)
+        } else {
+            now >= start || now < end
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!("{}–{}", self.start.trim(), self.end.trim())
+    }
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct BandwidthSettings {
+    // Cap on everything sent, in KB/s; unset is unlimited. Only uploads are
+    // paced; responses and downloads come in as fast as they arrive.
+    limit_kbps: Option<u64>,
+    // Caps for one kind of transfer ("lyric transcription"), within the
+    // global one
+    purpose_limits_kbps: HashMap<String, u64>,
+    // Job kinds ("sync", "download") that only start inside their window.
+    // Jobs the UI is waiting on start anyway.
+    windows: HashMap<String, TransferWindow>,
+}
+
+pub fn load(conn: &Connection) -> Result<BandwidthSettings, String> {
+    Ok(settings::get(conn, SETTINGS_KEY)?.unwrap_or_default())
+}
+
+pub fn windows(conn: &Connection) -> Result<HashMap<String, TransferWindow>, String> {
+    Ok(load(conn)?.windows)
+}
+
+// Token bucket allowing a second's worth of burst. Taking more than is
+// there runs it into debt, which is the wait before the next chunk.
+struct Bucket {
+    rate: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(kbps: u64) -> Self {
+        let rate = (kbps.max(1) * 1024) as f64;
+        Bucket { rate, available: rate, updated: Instant::now() }
+    }
+
+    fn take(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        self.available = (self.available + now.duration_since(self.updated).as_secs_f64() * self.rate).min(self.rate);
+        self.updated = now;
+        self.available -= bytes as f64;
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / self.rate)
+        }
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    global: Option<Bucket>,
+    purposes: HashMap<String, Bucket>,
+}
+
+// Shared by every outgoing request, so parallel uploads split the cap
+// between them
+#[derive(Default)]
+pub struct Limiter {
+    buckets: Mutex<Buckets>,
+}
+
+impl Limiter {
+    fn lock(&self) -> MutexGuard<'_, Buckets> {
+        self.buckets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn configure(&self, settings: &BandwidthSettings) {
+        *self.lock() = Buckets {
+            global: settings.limit_kbps.map(Bucket::new),
+            purposes: settings.purpose_limits_kbps.iter().map(|(purpose, kbps)| (purpose.clone(), Bucket::new(*kbps))).collect(),
+        };
+    }
+
+    fn is_limited(&self, purpose: &str) -> bool {
+        let buckets = self.lock();
+        buckets.global.is_some() || buckets.purposes.contains_key(purpose)
+    }
+
+    // How long to wait before sending `bytes` more; the stricter cap wins
+    fn reserve(&self, purpose: &str, bytes: usize) -> Duration {
+        let mut buckets = self.lock();
+        let global = buckets.global.as_mut().map(|b| b.take(bytes)).unwrap_or_default();
+        let own = buckets.purposes.get_mut(purpose).map(|b| b.take(bytes)).unwrap_or_default();
+        global.max(own)
+    }
+
+    // A request body that goes out no faster than the caps allow. Left as
+    // plain bytes when nothing applies.
+    pub fn body(self: &Arc<Self>, bytes: Vec<u8>, purpose: &str) -> Body {
+        if !self.is_limited(purpose) {
+            return Body::from(bytes);
+        }
+        let limiter = self.clone();
+        let purpose = purpose.to_string();
+        let chunks = futures_util::stream::unfold((bytes, 0), move |(bytes, offset)| {
+            let limiter = limiter.clone();
+            let purpose = purpose.clone();
+            async move {
+                if offset >= bytes.len() {
+                    return None;
+                }
+                let end = (offset + CHUNK).min(bytes.len());
+                tokio::time::sleep(limiter.reserve(&purpose, end - offset)).await;
+                let chunk = bytes[offset..end].to_vec();
+                Some((Ok::<_, std::io::Error>(chunk), (bytes, end)))
+            }
+        });
+        Body::wrap_stream(chunks)
+    }
+}
+
+// Starts held-back jobs when their window opens
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(WINDOW_CHECK);
+        loop {
+            interval.tick().await;
+            app.state::<Scheduler>().refresh_windows();
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_bandwidth_settings(db: State<'_, Db>) -> Result<BandwidthSettings, String> {
+    load(&db.lock())
+}
+
+// Saves the caps and windows and applies them straight away: to requests
+// from the next chunk on, and to queued jobs at once. The caps apply to
+// request bodies the app uploads, not to responses or downloads.
+#[tauri::command]
+pub async fn set_bandwidth_settings(
+    db: State<'_, Db>,
+    http: State<'_, Http>,
+    scheduler: State<'_, Scheduler>,
+    bandwidth: BandwidthSettings,
+) -> Result<BandwidthSettings, String> {
+    for window in bandwidth.windows.values() {
+        window.validate()?;
+    }
+    if bandwidth.limit_kbps == Some(0) || bandwidth.purpose_limits_kbps.values().any(|kbps| *kbps == 0) {
+        return Err("A bandwidth cap must be at least 1 KB/s; leave it empty for no cap".to_string());
+    }
+    let value = serde_json::to_value(&bandwidth).map_err(|e| e.to_string())?;
+    settings::set(&db.lock(), SETTINGS_KEY, &value)?;
+    http.configure()?;
+    scheduler.configure(&db);
+    Ok(bandwidth)
+}
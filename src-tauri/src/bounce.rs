@@ -0,0 +1,190 @@
+use crate::audio::{self, AudioData};
+use crate::collections::safe_file_name;
+use crate::db::Db;
+use crate::paths;
+use crate::playback::Playback;
+use crate::sequencer::Sequencer;
+use crate::takes;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, State};
+
+const BOUNCE_FOLDER: &str = "Bounces";
+const DEFAULT_RATE: u32 = 48000;
+const CHANNELS: usize = 2;
+const MAX_LOOPS: u32 = 64;
+// Voices still ringing this long after the pattern ends are cut off
+const MAX_TAIL_SECONDS: f64 = 30.0;
+// Bounds memory; twenty minutes of stereo at 192 kHz is under 2 GB
+const MAX_RENDER_SECONDS: f64 = 20.0 * 60.0;
+
+// What happens to sound still ringing when the last pass ends
+#[derive(Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tail {
+    // Keep rendering until it dies away
+    #[default]
+    Ring,
+    // Stop exactly at the end of the pattern
+    Cut,
+    // Mix it back over the start, so the file loops seamlessly
+    Wrap,
+}
+
+#[derive(serde::Serialize)]
+pub struct Bounce {
+    path: String,
+    // Per-lane files, in lane order, when stems were asked for
+    stems: Vec<String>,
+    seconds: f64,
+    // Peak of the mix before normalization, so the UI can warn about clipping
+    peak_db: f32,
+}
+
+fn render(mut sequencer: Sequencer, rate: u32, loops: u32, tail: Tail) -> Vec<f32> {
+    let frames = (sequencer.loop_frames(rate) * loops as f64).round() as usize;
+    let mut samples = Vec::with_capacity(frames * CHANNELS);
+    sequencer.start(rate);
+    for _ in 0..frames {
+        let mut frame = [0.0f32; CHANNELS];
+        sequencer.process(&mut frame, rate);
+        samples.extend_from_slice(&frame);
+    }
+    if tail == Tail::Cut {
+        return samples;
+    }
+
+    sequencer.let_ring();
+    let max_frames = (MAX_TAIL_SECONDS * rate as f64) as usize;
+    let mut ringing = Vec::new();
+    while sequencer.active() && ringing.len() < max_frames * CHANNELS {
+        let mut frame = [0.0f32; CHANNELS];
+        sequencer.process(&mut frame, rate);
+        ringing.extend_from_slice(&frame);
+    }
+    match tail {
+        Tail::Wrap if !samples.is_empty() => {
+            let len = samples.len();
+            for (i, value) in ringing.into_iter().enumerate() {
+                samples[i % len] += value;
+            }
+        }
+        _ => samples.extend(ringing),
+    }
+    samples
+}
+
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.wav", name));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{} {}.wav", name, n));
+        n += 1;
+    }
+    path
+}
+
+fn write(db: &Db, path: &Path, samples: Vec<f32>, rate: u32) -> Result<String, String> {
+    audio::write_wav(path, &AudioData { sample_rate: rate, channels: CHANNELS, samples })?;
+    takes::index(&db.lock(), path)?;
+    Ok(paths::display(path))
+}
+
+// Renders the sequencer's current pattern to a stereo WAV, `loops` passes
+// long, faster than real time. MIDI lanes play through the sampler's keymap.
+// `normalize` is a peak level in dBFS; stems get the mix's gain so they
+// still add up to it, and are padded to its length so they line up.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn bounce_pattern(
+    app: AppHandle,
+    db: State<'_, Db>,
+    playback: State<'_, Playback>,
+    name: Option<String>,
+    dest_dir: Option<String>,
+    loops: Option<u32>,
+    sample_rate: Option<u32>,
+    tail: Option<Tail>,
+    normalize: Option<f32>,
+    stems: Option<bool>,
+) -> Result<Bounce, String> {
+    let loops = loops.unwrap_or(1);
+    if loops == 0 || loops > MAX_LOOPS {
+        return Err(format!("Bounce 1 to {} passes of the pattern", MAX_LOOPS));
+    }
+    let rate = sample_rate.unwrap_or(DEFAULT_RATE);
+    if !(8000..=192000).contains(&rate) {
+        return Err(format!("Unsupported sample rate {}", rate));
+    }
+    if let Some(level) = normalize {
+        if level.is_nan() || level > 0.0 {
+            return Err("Normalize to a peak level of 0 dBFS or below".to_string());
+        }
+    }
+    let tail = tail.unwrap_or_default();
+
+    let keymap = playback.with_sampler(|sampler| sampler.offline());
+    let (mix, lanes) = playback.with_sequencer(|sequencer| {
+        let lanes: Vec<(String, Sequencer)> = match stems {
+            Some(true) => sequencer
+                .lane_names()
+                .into_iter()
+                .enumerate()
+                .map(|(i, name)| (name, sequencer.offline(&keymap, Some(i))))
+                .collect(),
+            _ => Vec::new(),
+        };
+        (sequencer.offline(&keymap, None), lanes)
+    });
+    if mix.lane_names().is_empty() {
+        return Err("The pattern has no lanes to bounce".to_string());
+    }
+    // A step length that isn't positive and finite leaves nothing to render
+    let loop_frames = mix.loop_frames(rate);
+    if !loop_frames.is_finite() || loop_frames < 1.0 {
+        return Err("The pattern's step length must be positive".to_string());
+    }
+    if loop_frames * loops as f64 / rate as f64 > MAX_RENDER_SECONDS {
+        return Err(format!("A bounce can be at most {} minutes long", MAX_RENDER_SECONDS / 60.0));
+    }
+
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let mut samples = render(mix, rate, loops, tail);
+        let mut rendered_stems: Vec<(String, Vec<f32>)> =
+            lanes.into_iter().map(|(name, lane)| (name, render(lane, rate, loops, tail))).collect();
+        for (_, stem) in &mut rendered_stems {
+            stem.resize(samples.len(), 0.0);
+        }
+
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        if let Some(level) = normalize {
+            if peak > 0.0 {
+                let gain = 10f32.powf(level / 20.0) / peak;
+                samples.iter_mut().for_each(|s| *s *= gain);
+                for (_, stem) in &mut rendered_stems {
+                    stem.iter_mut().for_each(|s| *s *= gain);
+                }
+            }
+        }
+
+        let dir = match dest_dir {
+            Some(dir) => paths::to_fs(&dir),
+            None => takes::recordings_folder(&app, &db.lock())?.join(BOUNCE_FOLDER),
+        };
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let name = match name {
+            Some(name) => safe_file_name(&name),
+            None => format!("Pattern {}", chrono::Local::now().format("%Y-%m-%d %H%M%S")),
+        };
+        let seconds = samples.len() as f64 / (CHANNELS * rate as usize) as f64;
+        let path = write(&db, &unique_path(&dir, &name), samples, rate)?;
+        let stems = rendered_stems
+            .into_iter()
+            .map(|(lane, stem)| write(&db, &unique_path(&dir, &format!("{} - {}", name, safe_file_name(&lane))), stem, rate))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Bounce { path, stems, seconds, peak_db: 20.0 * peak.max(1e-9).log10() })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,250 @@
+use crate::analysis;
+use crate::audio::{self, AudioData};
+use crate::dsp;
+use crate::jobs::{Priority, Scheduler};
+use crate::loopinfo;
+use crate::paths;
+use std::fs;
+use tauri::State;
+
+// Finer than the tempo tracker: hits in a fill can be 30 ms apart
+const ANALYSIS_RATE: u32 = 22_050;
+const N_FFT: usize = 1024;
+const HOP: usize = 256;
+// Start a little before the detected onset so the attack isn't shaved
+const PRE_ROLL: f64 = 0.005;
+const FADE_OUT: f64 = 0.01;
+const MIN_LENGTH: f64 = 0.03;
+// A hit ends when it has decayed this far below its own peak
+const TAIL_FLOOR_DB: f32 = -50.0;
+// Tempos a grid is laid out from; loop metadata outside this is ignored
+const BPM_RANGE: std::ops::RangeInclusive<f64> = 20.0..=400.0;
+
+#[derive(serde::Serialize)]
+pub struct ChoppedHit {
+    path: String,
+    index: usize,
+    start: f64,
+    duration: f64,
+    // "kick", "snare", "hat" or "hit"
+    class: &'static str,
+    peak_db: f32,
+    // Bar.beat.sixteenth from the loop's tempo, when it has one
+    position: Option<String>,
+}
+
+// Seconds per grid step for "1/4", "1/8", "1/16" or "1/32"
+fn grid_seconds(grid: &str, bpm: f64) -> Result<f64, String> {
+    let division: f64 = match grid {
+        "1/4" => 4.0,
+        "1/8" => 8.0,
+        "1/16" => 16.0,
+        "1/32" => 32.0,
+        other => return Err(format!("Unknown grid: {}", other)),
+    };
+    Ok(60.0 / bpm * 4.0 / division)
+}
+
+// 1-based musical position of `seconds` in 4/4, e.g. "2.3.1"
+fn position(seconds: f64, bpm: f64) -> String {
+    let sixteenths = (seconds * bpm / 60.0 * 4.0).round() as u64;
+    format!("{}.{}.{}", sixteenths / 16 + 1, sixteenths / 4 % 4 + 1, sixteenths % 4 + 1)
+}
+
+// Frames in the onset envelope that stand out from their surroundings
+fn pick_onsets(envelope: &[f32], sensitivity: f32, min_gap_frames: usize) -> Vec<usize> {
+    let mean = envelope.iter().sum::<f32>() / envelope.len().max(1) as f32;
+    let variance = envelope.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / envelope.len().max(1) as f32;
+    // Higher sensitivity lowers the bar
+    let threshold = mean + variance.sqrt() * (2.0 - sensitivity.clamp(0.0, 1.0) * 1.5);
+
+    let mut onsets: Vec<usize> = Vec::new();
+    for i in 0..envelope.len() {
+        let lo = i.saturating_sub(3);
+        let hi = (i + 4).min(envelope.len());
+        let is_peak = envelope[lo..hi].iter().all(|v| *v <= envelope[i]);
+        if envelope[i] > threshold && is_peak && onsets.last().map(|last| i - last >= min_gap_frames).unwrap_or(true) {
+            onsets.push(i);
+        }
+    }
+    onsets
+}
+
+// Rough drum class from the first 100 ms: where the energy sits and how bright it is
+fn classify(mono: &[f32], sample_rate: u32) -> &'static str {
+    let length = ((sample_rate as f64 * 0.1) as usize).min(mono.len());
+    let n_fft = 2048;
+    let spectra = dsp::stft_magnitudes(&mono[..length], n_fft, n_fft);
+    let bin_hz = sample_rate as f32 / n_fft as f32;
+
+    let (mut total, mut low, mut weighted) = (0.0f32, 0.0f32, 0.0f32);
+    for spectrum in &spectra {
+        for (bin, magnitude) in spectrum.iter().enumerate() {
+            let energy = magnitude * magnitude;
+            let hz = bin as f32 * bin_hz;
+            total += energy;
+            weighted += energy * hz;
+            if hz < 150.0 {
+                low += energy;
+            }
+        }
+    }
+    if total <= 1e-9 {
+        return "hit";
+    }
+    let centroid = weighted / total;
+    let low_ratio = low / total;
+    if low_ratio > 0.5 && centroid < 1500.0 {
+        "kick"
+    } else if centroid > 6000.0 && low_ratio < 0.05 {
+        "hat"
+    } else if (1500.0..=6000.0).contains(&centroid) && low_ratio < 0.3 {
+        "snare"
+    } else {
+        "hit"
+    }
+}
+
+// Where the hit starting at `start` has died away, capped at `limit`
+fn tail_end(mono: &[f32], start: usize, limit: usize, sample_rate: u32) -> usize {
+    let window = (sample_rate as usize / 100).max(1);
+    let hit = &mono[start..limit];
+    let peak = hit.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    let floor = peak * 10f32.powf(TAIL_FLOOR_DB / 20.0);
+    let mut end = start;
+    for (i, chunk) in hit.chunks(window).enumerate() {
+        if dsp::rms(chunk) < floor && i > 0 {
+            break;
+        }
+        end = start + (i + 1) * window;
+    }
+    end.min(limit)
+}
+
+fn cut(source: &AudioData, start: usize, end: usize) -> AudioData {
+    let channels = source.channels.max(1);
+    let mut samples = source.samples[start * channels..end * channels].to_vec();
+    let fade = ((FADE_OUT * source.sample_rate as f64) as usize).min(end - start);
+    let frames = end - start;
+    for frame in 0..fade {
+        let gain = frame as f32 / fade as f32;
+        for channel in 0..channels {
+            samples[(frames - 1 - frame) * channels + channel] *= gain;
+        }
+    }
+    AudioData { sample_rate: source.sample_rate, channels, samples }
+}
+
+// Onset times in seconds, each with the strength of its spectral jump
+pub fn transients(mono: &[f32], sample_rate: u32, sensitivity: f32, min_gap_ms: u32) -> Vec<(f64, f32)> {
+    let analysis = dsp::resample(mono, sample_rate, ANALYSIS_RATE);
+    let spectra = dsp::stft_magnitudes(&analysis, N_FFT, HOP);
+    let envelope = dsp::onset_envelope(&spectra, None);
+
+    let frame_seconds = HOP as f64 / ANALYSIS_RATE as f64;
+    let min_gap = (min_gap_ms as f64 / 1000.0 / frame_seconds).max(1.0) as usize;
+    pick_onsets(&envelope, sensitivity, min_gap)
+        .into_iter()
+        .map(|frame| (frame as f64 * frame_seconds, envelope[frame]))
+        .collect()
+}
+
+fn transient_starts(mono: &[f32], sample_rate: u32, sensitivity: f32, min_gap_ms: u32) -> Vec<usize> {
+    let rate = sample_rate as f64;
+    transients(mono, sample_rate, sensitivity, min_gap_ms)
+        .into_iter()
+        .map(|(time, _)| ((time - PRE_ROLL).max(0.0) * rate) as usize)
+        .filter(|start| *start < mono.len())
+        .collect()
+}
+
+// Splits a recording into one file per hit in `dest_dir`. `grid` ("1/8",
+// "1/16", ...) slices a loop on its beat grid instead of at transients,
+// using the tempo in its ACID/Apple Loops metadata (or `bpm`, or analysis).
+// Names carry the slice's metadata: <stem>[_<bpm>bpm]_<nn>_<class>[_<bar.beat.16th>].wav
+#[tauri::command]
+pub async fn chop_sample(
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    dest_dir: String,
+    sensitivity: Option<f32>,
+    min_gap_ms: Option<u32>,
+    grid: Option<String>,
+    bpm: Option<f64>,
+) -> Result<Vec<ChoppedHit>, String> {
+    if bpm.map_or(false, |b| !BPM_RANGE.contains(&b)) {
+        return Err(format!("Tempo must be between {} and {} BPM", BPM_RANGE.start(), BPM_RANGE.end()));
+    }
+    let label = format!("Chop {}", path);
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |ctx| {
+            let fs_path = paths::to_fs(&path);
+            let source = audio::decode_file(&fs_path, None)?;
+            let mono = source.to_mono();
+            let rate = source.sample_rate as f64;
+            let mut bpm = bpm.or_else(|| loopinfo::read(&fs_path).and_then(|info| info.bpm).filter(|b| BPM_RANGE.contains(b)));
+            // Only worth a full analysis pass when the grid depends on it
+            if grid.is_some() && bpm.is_none() {
+                bpm = analysis::analyze_path(&fs_path).ok().and_then(|a| a.bpm).filter(|b| BPM_RANGE.contains(b));
+            }
+
+            let starts: Vec<usize> = match &grid {
+                Some(grid) => {
+                    let bpm = bpm.ok_or_else(|| "No tempo found for grid slicing; pass a BPM".to_string())?;
+                    let step = grid_seconds(grid, bpm)? * rate;
+                    (0u64..).map(|i| (i as f64 * step).round() as usize).take_while(|start| *start < mono.len()).collect()
+                }
+                None => transient_starts(&mono, source.sample_rate, sensitivity.unwrap_or(0.5), min_gap_ms.unwrap_or(50)),
+            };
+            if starts.is_empty() {
+                return Err("No hits found; try a higher sensitivity".to_string());
+            }
+
+            let dest = paths::to_fs(&dest_dir);
+            fs::create_dir_all(&dest).map_err(|e| e.to_string())?;
+            let stem = fs_path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| "chop".to_string());
+
+            let mut hits = Vec::new();
+            for (i, &start) in starts.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    break;
+                }
+                ctx.progress(i as f32 / starts.len() as f32, None);
+                let limit = starts.get(i + 1).copied().unwrap_or(mono.len());
+                // Grid slices keep their full step so they line up when played back in order
+                let end = if grid.is_some() { limit } else { tail_end(&mono, start, limit, source.sample_rate) };
+                if ((end - start) as f64) < MIN_LENGTH * rate {
+                    continue;
+                }
+
+                let class = classify(&mono[start..end], source.sample_rate);
+                let index = hits.len() + 1;
+                let at = bpm.map(|bpm| position(start as f64 / rate, bpm));
+                let mut name = stem.clone();
+                if let Some(bpm) = bpm {
+                    name.push_str(&format!("_{}bpm", (bpm * 100.0).round() / 100.0));
+                }
+                name.push_str(&format!("_{:02}_{}", index, class));
+                if let Some(at) = &at {
+                    name.push_str(&format!("_{}", at));
+                }
+                let output = dest.join(format!("{}.wav", name));
+                audio::write_wav(&output, &cut(&source, start, end))?;
+                let peak = mono[start..end].iter().fold(0.0f32, |m, s| m.max(s.abs()));
+                hits.push(ChoppedHit {
+                    path: paths::display(&output),
+                    index,
+                    start: start as f64 / rate,
+                    duration: (end - start) as f64 / rate,
+                    class,
+                    peak_db: 20.0 * peak.max(1e-6).log10(),
+                    position: at,
+                });
+            }
+            Ok(hits)
+        })
+        .await
+}
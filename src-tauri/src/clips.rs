@@ -0,0 +1,92 @@
+use crate::audio;
+use crate::dsp;
+use crate::paths;
+use base64::Engine;
+use flacenc::component::BitRepr;
+use flacenc::error::Verify;
+
+// Speech and music models resample to 16 kHz mono internally anyway
+const CLIP_SAMPLE_RATE: u32 = 16_000;
+const DEFAULT_SECONDS: f64 = 30.0;
+const MAX_SECONDS: f64 = 300.0;
+// Inline audio parts are capped well below the providers' request limits
+const DEFAULT_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(serde::Serialize)]
+pub struct PreparedClip {
+    mime: &'static str,
+    // Base64, ready for an inline_data / input_audio part
+    data: String,
+    start: f64,
+    duration: f64,
+    sample_rate: u32,
+    bytes: usize,
+    // Shortened further than asked to fit under `max_bytes`
+    truncated: bool,
+}
+
+fn encode_flac(samples: &[f32]) -> Result<Vec<u8>, String> {
+    let pcm: Vec<i32> = samples
+        .iter()
+        .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i32)
+        .collect();
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, e)| format!("Invalid FLAC settings: {:?}", e))?;
+    let source = flacenc::source::MemSource::from_samples(&pcm, 1, 16, CLIP_SAMPLE_RATE as usize);
+    let stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
+        .map_err(|e| format!("Failed to encode FLAC: {:?}", e))?;
+
+    let mut sink = flacenc::bitsink::ByteSink::new();
+    stream.write(&mut sink).map_err(|e| format!("Failed to encode FLAC: {:?}", e))?;
+    Ok(sink.as_slice().to_vec())
+}
+
+// Cuts `seconds` of audio starting at `start` out of a file, downmixed to
+// mono 16 kHz FLAC for a multimodal model. Clips that would still exceed
+// `max_bytes` are shortened until they fit.
+#[tauri::command]
+pub async fn prepare_audio_for_ai(
+    path: String,
+    start: Option<f64>,
+    seconds: Option<f64>,
+    max_bytes: Option<usize>,
+) -> Result<PreparedClip, String> {
+    let start = start.unwrap_or(0.0).max(0.0);
+    let seconds = seconds.unwrap_or(DEFAULT_SECONDS).clamp(0.5, MAX_SECONDS);
+    let max_bytes = max_bytes.unwrap_or(DEFAULT_MAX_BYTES).max(16 * 1024);
+
+    tokio::task::spawn_blocking(move || {
+        let decoded = audio::decode_range(&paths::to_fs(&path), start, Some(seconds))?;
+        let mono = dsp::resample(&decoded.to_mono(), decoded.sample_rate, CLIP_SAMPLE_RATE);
+        let mut clip = &mono[..];
+        if clip.is_empty() {
+            return Err(format!("{} is shorter than {:.1}s", path, start));
+        }
+
+        let mut truncated = false;
+        let mut encoded = encode_flac(clip)?;
+        // FLAC's ratio varies with the material, so re-encode rather than guess
+        while encoded.len() > max_bytes {
+            let keep = (clip.len() as f64 * max_bytes as f64 / encoded.len() as f64 * 0.95) as usize;
+            if keep < CLIP_SAMPLE_RATE as usize / 2 {
+                return Err(format!("Can't fit half a second of audio in {} bytes", max_bytes));
+            }
+            clip = &clip[..keep];
+            encoded = encode_flac(clip)?;
+            truncated = true;
+        }
+
+        Ok(PreparedClip {
+            mime: "audio/flac",
+            bytes: encoded.len(),
+            data: base64::engine::general_purpose::STANDARD.encode(&encoded),
+            start,
+            duration: clip.len() as f64 / CLIP_SAMPLE_RATE as f64,
+            sample_rate: CLIP_SAMPLE_RATE,
+            truncated,
+        })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,142 @@
+use crate::collections::safe_file_name;
+use crate::db::{self, Db};
+use crate::hashes;
+use crate::jobs::{Priority, Scheduler};
+use crate::library;
+use crate::paths;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+const MANIFEST: &str = "manifest.json";
+
+#[derive(serde::Serialize)]
+struct ManifestItem {
+    // Name inside the folder
+    file: String,
+    original: String,
+    // "library" for indexed files, "generated" for clips and renders that
+    // never were
+    origin: &'static str,
+    hash: Option<String>,
+    bpm: Option<String>,
+    key: Option<String>,
+    tags: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct Manifest {
+    name: String,
+    collected_at: i64,
+    items: Vec<ManifestItem>,
+    // Asked for but not found on disk
+    missing: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Collected {
+    path: String,
+    manifest: String,
+    files: usize,
+    missing: Vec<String>,
+}
+
+fn attribute(conn: &Connection, path: &str, attribute: &str) -> Result<Option<String>, String> {
+    conn.query_row(
+        "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = ?2",
+        params![path, attribute],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+fn indexed(conn: &Connection, path: &str) -> Result<bool, String> {
+    conn.query_row("SELECT 1 FROM files WHERE path = ?1", params![path], |_| Ok(()))
+        .optional()
+        .map(|found| found.is_some())
+        .map_err(|e| e.to_string())
+}
+
+// `file_name` in `folder`, numbered if another item already took it
+fn resolve_name(folder: &Path, file_name: &str, taken: &mut HashSet<String>) -> PathBuf {
+    let name = Path::new(file_name);
+    let stem = name.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = name.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let mut candidate = file_name.to_string();
+    let mut n = 2;
+    while taken.contains(&candidate.to_lowercase()) || folder.join(&candidate).exists() {
+        candidate = format!("{} {}{}", stem, n, extension);
+        n += 1;
+    }
+    taken.insert(candidate.to_lowercase());
+    folder.join(candidate)
+}
+
+// Copies library items and generated clips into one folder under `dest`,
+// with a manifest of where each came from and its BPM, key and tags, so a
+// sketch can be zipped up and sent on with nothing missing
+#[tauri::command]
+pub async fn collect_and_save(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    files: Vec<String>,
+    dest: String,
+    name: String,
+) -> Result<Collected, String> {
+    let folder_name = safe_file_name(&name);
+    if folder_name.is_empty() {
+        return Err("The folder needs a name".to_string());
+    }
+    let folder = paths::to_fs(&dest).join(&folder_name);
+    if folder.exists() {
+        return Err(format!("{} already exists", paths::display(&folder)));
+    }
+    let db = db.inner().clone();
+    let label = format!("Collect {}", name);
+
+    scheduler
+        .run("export", label, Priority::Interactive, move |ctx| {
+            let mut seen = HashSet::new();
+            let files: Vec<String> = files.into_iter().filter(|f| seen.insert(f.clone())).collect();
+            let (present, missing): (Vec<String>, Vec<String>) = files.into_iter().partition(|f| paths::to_fs(f).is_file());
+            fs::create_dir_all(&folder).map_err(|e| format!("Failed to create {}: {}", folder.display(), e))?;
+
+            let mut taken = HashSet::from([MANIFEST.to_string()]);
+            let mut items = Vec::new();
+            let total = present.len().max(1) as f32;
+            for (i, path) in present.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    let _ = fs::remove_dir_all(&folder);
+                    return Err("Collect cancelled".to_string());
+                }
+                ctx.progress(i as f32 / total, Some(path.clone()));
+                let source = paths::to_fs(path);
+                let target = resolve_name(&folder, &paths::file_name(&source), &mut taken);
+                fs::copy(&source, &target).map_err(|e| format!("Failed to copy {}: {}", path, e))?;
+
+                let hash = hashes::content_hash(&db, path).ok();
+                let conn = db.lock();
+                let origin = if indexed(&conn, path)? { "library" } else { "generated" };
+                items.push(ManifestItem {
+                    file: paths::file_name(&target),
+                    original: path.clone(),
+                    origin,
+                    hash,
+                    bpm: attribute(&conn, path, "bpm")?,
+                    key: attribute(&conn, path, "key")?,
+                    tags: library::tags_for(&conn, path)?,
+                });
+            }
+
+            let files = items.len();
+            let manifest = Manifest { name, collected_at: db::now(), items, missing: missing.clone() };
+            let manifest_path = folder.join(MANIFEST);
+            let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+            fs::write(&manifest_path, json).map_err(|e| format!("Failed to write {}: {}", manifest_path.display(), e))?;
+            Ok(Collected { path: paths::display(&folder), manifest: paths::display(&manifest_path), files, missing })
+        })
+        .await
+}
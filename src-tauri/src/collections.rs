@@ -0,0 +1,204 @@
+use crate::db::{self, Db};
+use crate::jobs::{Priority, Scheduler};
+use crate::paths;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::Path;
+use tauri::State;
+
+#[derive(serde::Serialize)]
+pub struct Collection {
+    id: i64,
+    name: String,
+    // "user" for crates made in the app, otherwise the app it was imported from
+    source: String,
+    item_count: i64,
+    created_at: i64,
+}
+
+pub fn upsert_collection(conn: &Connection, name: &str, source: &str) -> Result<i64, String> {
+    conn.execute(
+        "INSERT INTO collections (name, source, created_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(name, source) DO NOTHING",
+        params![name, source, db::now()],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.query_row(
+        "SELECT id FROM collections WHERE name = ?1 AND source = ?2",
+        params![name, source],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+// Replaces the contents of a collection, keeping the given order
+pub fn set_items(conn: &Connection, collection_id: i64, paths: &[String]) -> Result<(), String> {
+    conn.execute("DELETE FROM collection_items WHERE collection_id = ?1", params![collection_id])
+        .map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare("INSERT OR IGNORE INTO collection_items (collection_id, path, position) VALUES (?1, ?2, ?3)")
+        .map_err(|e| e.to_string())?;
+    for (position, path) in paths.iter().enumerate() {
+        stmt.execute(params![collection_id, path, position as i64])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+pub fn items(conn: &Connection, collection_id: i64) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare("SELECT path FROM collection_items WHERE collection_id = ?1 ORDER BY position")
+        .map_err(|e| e.to_string())?;
+    let paths = stmt
+        .query_map(params![collection_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(paths)
+}
+
+#[tauri::command]
+pub async fn list_collections(db: State<'_, Db>) -> Result<Vec<Collection>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare(
+            "SELECT c.id, c.name, c.source, c.created_at,
+                    (SELECT COUNT(*) FROM collection_items i WHERE i.collection_id = c.id)
+             FROM collections c ORDER BY c.source, c.name",
+        )
+        .map_err(|e| e.to_string())?;
+    let collections = stmt
+        .query_map([], |row| {
+            Ok(Collection {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                source: row.get(2)?,
+                created_at: row.get(3)?,
+                item_count: row.get(4)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(collections)
+}
+
+#[derive(serde::Serialize)]
+pub struct CollectionExport {
+    path: String,
+    files: usize,
+    // Items whose file is missing or on an offline drive
+    skipped: usize,
+}
+
+fn collection_name(conn: &Connection, collection_id: i64) -> Result<String, String> {
+    conn.query_row("SELECT name FROM collections WHERE id = ?1", params![collection_id], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Collection {} not found", collection_id))
+}
+
+// Collection names may contain folder separators ("Sets / Friday")
+pub fn safe_file_name(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') { '-' } else { c })
+        .collect();
+    cleaned.trim().trim_matches('.').to_string()
+}
+
+fn duration_of(conn: &Connection, path: &str) -> Option<f64> {
+    conn.query_row(
+        "SELECT a.duration FROM file_hashes h JOIN analysis_cache a ON a.hash = h.hash WHERE h.path = ?1",
+        params![path],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+fn link_or_copy(source: &Path, target: &Path, symlink: bool) -> std::io::Result<()> {
+    if symlink {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(source, target);
+        // Windows only allows symlinks with developer mode on; a hard link
+        // works on the same drive and a copy works everywhere
+        #[cfg(windows)]
+        {
+            if std::os::windows::fs::symlink_file(source, target).is_ok() || fs::hard_link(source, target).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+    fs::copy(source, target).map(|_| ())
+}
+
+// Writes a collection out as an M3U8 playlist ("m3u8") or as a folder of
+// "copies" or "symlinks" named so they sort in collection order.
+#[tauri::command]
+pub async fn export_collection(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    collection_id: i64,
+    format: String,
+    dest: String,
+) -> Result<CollectionExport, String> {
+    let (name, items) = {
+        let conn = db.lock();
+        (collection_name(&conn, collection_id)?, items(&conn, collection_id)?)
+    };
+    let db = db.inner().clone();
+    let dest = paths::to_fs(&dest);
+    let label = format!("Export {}", name);
+
+    scheduler
+        .run("export", label, Priority::Interactive, move |ctx| {
+            let present: Vec<&String> = items.iter().filter(|p| paths::to_fs(p).is_file()).collect();
+            let skipped = items.len() - present.len();
+
+            match format.as_str() {
+                "m3u8" => {
+                    let output = if dest.is_dir() {
+                        dest.join(format!("{}.m3u8", safe_file_name(&name)))
+                    } else {
+                        dest.clone()
+                    };
+                    let conn = db.lock();
+                    let mut playlist = String::from("#EXTM3U\n");
+                    playlist.push_str(&format!("#PLAYLIST:{}\n", name));
+                    for path in &present {
+                        let title = Path::new(path.as_str())
+                            .file_stem()
+                            .map(|s| s.to_string_lossy().to_string())
+                            .unwrap_or_default();
+                        let seconds = duration_of(&conn, path).map(|d| d.round() as i64).unwrap_or(-1);
+                        playlist.push_str(&format!("#EXTINF:{},{}\n{}\n", seconds, title, path));
+                    }
+                    fs::write(&output, playlist).map_err(|e| e.to_string())?;
+                    Ok(CollectionExport { path: paths::display(&output), files: present.len(), skipped })
+                }
+                "copies" | "symlinks" => {
+                    let folder = dest.join(safe_file_name(&name));
+                    fs::create_dir_all(&folder).map_err(|e| e.to_string())?;
+                    let width = present.len().to_string().len().max(2);
+                    let total = present.len().max(1) as f32;
+                    for (i, path) in present.iter().enumerate() {
+                        if ctx.is_cancelled() {
+                            return Err("Export cancelled".to_string());
+                        }
+                        ctx.progress(i as f32 / total, Some(path.to_string()));
+                        let source = paths::to_fs(path);
+                        let file_name = paths::file_name(&source);
+                        let target = folder.join(format!("{:0width$} - {}", i + 1, file_name, width = width));
+                        if fs::symlink_metadata(&target).is_ok() {
+                            fs::remove_file(&target).map_err(|e| e.to_string())?;
+                        }
+                        link_or_copy(&source, &target, format == "symlinks")
+                            .map_err(|e| format!("Failed to export {}: {}", file_name, e))?;
+                    }
+                    Ok(CollectionExport { path: paths::display(&folder), files: present.len(), skipped })
+                }
+                other => Err(format!("Unknown export format: {}", other)),
+            }
+        })
+        .await
+}
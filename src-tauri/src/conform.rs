@@ -0,0 +1,230 @@
+use crate::analysis::{self, NOTE_NAMES};
+use crate::audio;
+use crate::db::Db;
+use crate::derived;
+use crate::jobs::{Priority, Scheduler};
+use crate::loopinfo;
+use crate::paths;
+use crate::quarantine;
+use crate::reconcile;
+use crate::stretch::{self, Algorithm};
+use rusqlite::{params, OptionalExtension};
+use tauri::State;
+
+// Written alongside the source's own attributes so the origin is clear
+const SOURCE: &str = "conform";
+// Below this, analysis is guessing; drum loops land here
+const MIN_KEY_CONFIDENCE: f64 = 0.15;
+
+#[derive(serde::Serialize)]
+pub struct ConformedFile {
+    source: String,
+    // None when this file couldn't be conformed; see `error`
+    path: Option<String>,
+    // What the source was taken to be, after any half/double-time choice
+    from: Option<String>,
+    // Length multiplier for tempo, semitones for key
+    amount: Option<f64>,
+    error: Option<String>,
+}
+
+fn resolved(db: &Db, path: &str, attribute: &str) -> Result<Option<String>, String> {
+    db.lock()
+        .query_row(
+            "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = ?2",
+            params![path, attribute],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())
+}
+
+// The library's BPM when it has one, then the tempo the loop was authored
+// at, then analysis
+fn source_bpm(db: &Db, path: &str) -> Result<Option<f64>, String> {
+    if let Some(bpm) = resolved(db, path, "bpm")?.and_then(|v| v.parse::<f64>().ok()) {
+        return Ok(Some(bpm));
+    }
+    if let Some(bpm) = loopinfo::read(&paths::to_fs(path)).and_then(|info| info.bpm) {
+        return Ok(Some(bpm));
+    }
+    Ok(analysis::get_or_analyze(db, path)?.bpm)
+}
+
+// A 70 BPM loop going into a 140 BPM song is already at tempo in double
+// time; stretch from whichever reading is closest to the target
+fn nearest_reading(bpm: f64, target: f64) -> f64 {
+    [bpm / 2.0, bpm, bpm * 2.0]
+        .into_iter()
+        .min_by(|a, b| (a / target).ln().abs().total_cmp(&(b / target).ln().abs()))
+        .unwrap_or(bpm)
+}
+
+fn format_bpm(bpm: f64) -> String {
+    let rounded = (bpm * 100.0).round() / 100.0;
+    format!("{}", rounded)
+}
+
+fn conform_tempo_one(
+    db: &Db,
+    path: &str,
+    target_bpm: f64,
+    algorithm: Algorithm,
+    dest_dir: Option<&str>,
+) -> Result<(String, f64, f64), String> {
+    let bpm = source_bpm(db, path)?.ok_or_else(|| "No tempo found".to_string())?;
+    let from = nearest_reading(bpm, target_bpm);
+    let ratio = from / target_bpm;
+    let decoded = quarantine::guard(db, path, || audio::decode_file(&paths::to_fs(path), None))?;
+    let mut stretched = stretch::time_stretch(&decoded, ratio, algorithm);
+    stretch::limit_peaks(&mut stretched);
+
+    let mut attributes = vec![("bpm", format_bpm(target_bpm))];
+    // Repitching moves the key along with the tempo
+    if algorithm != Algorithm::Repitch {
+        if let Some(key) = resolved(db, path, "key")? {
+            attributes.push(("key", key));
+        }
+    }
+    let suffix = format!("{}bpm", format_bpm(target_bpm));
+    let written = derived::write(db, path, dest_dir, &suffix, &stretched, SOURCE, &attributes)?;
+    Ok((written, from, ratio))
+}
+
+// The library's key, else analysis when it's reasonably sure
+fn source_key(db: &Db, path: &str) -> Result<Option<String>, String> {
+    if let Some(key) = resolved(db, path, "key")? {
+        return Ok(Some(key));
+    }
+    let analyzed = analysis::get_or_analyze(db, path)?;
+    Ok(analyzed.key.filter(|_| analyzed.key_confidence >= MIN_KEY_CONFIDENCE))
+}
+
+// Semitones from `from` to `to`, -5..=6, taking the shortest way round. A
+// major loop going into a minor key (or the reverse) aims for the relative
+// key, so an A minor loop needs no change for a song in C major.
+fn key_interval(from: (usize, bool), to: (usize, bool)) -> i32 {
+    let target = match (from.1, to.1) {
+        (true, false) => (to.0 + 9) % 12,
+        (false, true) => (to.0 + 3) % 12,
+        _ => to.0,
+    };
+    let up = (target as i32 - from.0 as i32).rem_euclid(12);
+    if up > 6 {
+        up - 12
+    } else {
+        up
+    }
+}
+
+fn conform_key_one(
+    db: &Db,
+    path: &str,
+    target: (usize, bool),
+    algorithm: Algorithm,
+    dest_dir: Option<&str>,
+) -> Result<(String, String, i32), String> {
+    let key = source_key(db, path)?.ok_or_else(|| "No clear key; is this a melodic loop?".to_string())?;
+    let from = reconcile::parse_key(&key).ok_or_else(|| format!("Can't read key {}", key))?;
+    let semitones = key_interval(from, target);
+    let decoded = quarantine::guard(db, path, || audio::decode_file(&paths::to_fs(path), None))?;
+    let mut shifted = stretch::pitch_shift(&decoded, semitones as f64, algorithm);
+    stretch::limit_peaks(&mut shifted);
+
+    // The loop keeps its own mode; only the tonic moves
+    let tonic = NOTE_NAMES[(from.0 as i32 + semitones).rem_euclid(12) as usize];
+    let new_key = format!("{}{}", tonic, if from.1 { "m" } else { "" });
+    let mut attributes = vec![("key", new_key.clone())];
+    if let Some(bpm) = resolved(db, path, "bpm")? {
+        attributes.push(("bpm", bpm));
+    }
+    let written = derived::write(db, path, dest_dir, &new_key, &shifted, SOURCE, &attributes)?;
+    Ok((written, key, semitones))
+}
+
+// Pitch-shifts melodic loops from their key to `target_key` (or its
+// relative major/minor), by the smallest interval, keeping their length.
+// Each is written as "<name> <key>.wav" with the new key set. `algorithm`
+// is "quality" (default) or "fast".
+#[tauri::command]
+pub async fn conform_key(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    paths: Vec<String>,
+    target_key: String,
+    algorithm: Option<String>,
+    dest_dir: Option<String>,
+) -> Result<Vec<ConformedFile>, String> {
+    let target = reconcile::parse_key(&target_key).ok_or_else(|| format!("Unknown key: {}", target_key))?;
+    let algorithm = Algorithm::parse(algorithm.as_deref().unwrap_or("quality"))?;
+    if algorithm == Algorithm::Repitch {
+        return Err("Repitching would change the tempo; use \"quality\" or \"fast\"".to_string());
+    }
+    let db = db.inner().clone();
+    let label = format!("Conform {} files to {}", paths.len(), target_key);
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |ctx| {
+            let mut results = Vec::new();
+            for (i, path) in paths.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    return Err("Cancelled".to_string());
+                }
+                ctx.progress(i as f32 / paths.len() as f32, Some(path.clone()));
+                results.push(match conform_key_one(&db, path, target, algorithm, dest_dir.as_deref()) {
+                    Ok((written, from, semitones)) => ConformedFile {
+                        source: path.clone(),
+                        path: Some(written),
+                        from: Some(from),
+                        amount: Some(semitones as f64),
+                        error: None,
+                    },
+                    Err(e) => ConformedFile { source: path.clone(), path: None, from: None, amount: None, error: Some(e) },
+                });
+            }
+            Ok(results)
+        })
+        .await
+}
+
+// Time-stretches loops to `target_bpm` and writes each as a new WAV named
+// "<name> <bpm>bpm.wav" next to the original (or in `dest_dir`), with its
+// BPM set and the original's key and tags carried over. `algorithm` is
+// "fast" (default, best for drums), "quality" (pads, vocals) or "repitch".
+#[tauri::command]
+pub async fn conform_tempo(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    paths: Vec<String>,
+    target_bpm: f64,
+    algorithm: Option<String>,
+    dest_dir: Option<String>,
+) -> Result<Vec<ConformedFile>, String> {
+    if !(20.0..=400.0).contains(&target_bpm) {
+        return Err(format!("Target tempo {} is out of range", target_bpm));
+    }
+    let algorithm = Algorithm::parse(algorithm.as_deref().unwrap_or("fast"))?;
+    let db = db.inner().clone();
+    let label = format!("Conform {} files to {} BPM", paths.len(), format_bpm(target_bpm));
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |ctx| {
+            let mut results = Vec::new();
+            for (i, path) in paths.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    return Err("Cancelled".to_string());
+                }
+                ctx.progress(i as f32 / paths.len() as f32, Some(path.clone()));
+                results.push(match conform_tempo_one(&db, path, target_bpm, algorithm, dest_dir.as_deref()) {
+                    Ok((written, from, ratio)) => ConformedFile {
+                        source: path.clone(),
+                        path: Some(written),
+                        from: Some(format_bpm(from)),
+                        amount: Some(ratio),
+                        error: None,
+                    },
+                    Err(e) => ConformedFile { source: path.clone(), path: None, from: None, amount: None, error: Some(e) },
+                });
+            }
+            Ok(results)
+        })
+        .await
+}
@@ -0,0 +1,77 @@
+use crate::ai;
+use crate::http::Http;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+
+// Any one of these answering means we're online. The AI endpoints come
+// first since they're what actually matters.
+const PROBE_HOSTS: &[&str] = &["api.openai.com:443", "generativelanguage.googleapis.com:443", "1.1.1.1:443"];
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+pub struct Connectivity {
+    online: AtomicBool,
+}
+
+impl Default for Connectivity {
+    fn default() -> Self {
+        Connectivity { online: AtomicBool::new(true) }
+    }
+}
+
+impl Connectivity {
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::SeqCst)
+    }
+}
+
+// Behind a proxy the AI hosts can't be reached directly, so the proxy
+// answering is as close as the check gets
+pub async fn probe(app: &AppHandle) -> bool {
+    let proxy = app.state::<Http>().proxy_address();
+    let hosts: Vec<&str> = match &proxy {
+        Some(address) => vec![address.as_str()],
+        None => PROBE_HOSTS.to_vec(),
+    };
+    for host in hosts {
+        if let Ok(Ok(_)) = tokio::time::timeout(PROBE_TIMEOUT, tokio::net::TcpStream::connect(host)).await {
+            return true;
+        }
+    }
+    false
+}
+
+// Records a connectivity result, telling the UI when it changes and
+// sending queued AI requests when the connection comes back
+pub fn mark(app: &AppHandle, online: bool) {
+    let was_online = app.state::<Connectivity>().online.swap(online, Ordering::SeqCst);
+    if was_online != online {
+        let _ = app.emit("connectivity-changed", online);
+        if online {
+            ai::flush_queue(app.clone());
+        }
+    }
+}
+
+pub async fn refresh(app: &AppHandle) -> bool {
+    let online = probe(app).await;
+    mark(app, online);
+    online
+}
+
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            refresh(&app).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_connectivity(app: AppHandle) -> Result<bool, String> {
+    Ok(refresh(&app).await)
+}
@@ -0,0 +1,230 @@
+use crate::db::Db;
+use crate::library;
+use crate::midi_features;
+use crate::tuning;
+use rusqlite::{params, Connection};
+use tauri::State;
+
+const DEFAULT_MAX_CHARS: usize = 12_000;
+// Past this a single transcription is never worth more of the budget
+const MAX_ITEM_CHARS: usize = 4_000;
+const FILTERED: &str = "[filtered]";
+
+// Phrases that read as instructions to the model rather than data about a
+// file. Matched case-insensitively anywhere in the text.
+const SUSPICIOUS: &[&str] = &[
+    "ignore previous instructions",
+    "ignore all previous",
+    "ignore the above",
+    "ignore your instructions",
+    "disregard previous",
+    "disregard all previous",
+    "disregard the above",
+    "forget your instructions",
+    "forget previous instructions",
+    "new instructions:",
+    "system prompt",
+    "you are now",
+    "pretend to be",
+    "jailbreak",
+    "developer mode",
+    "do anything now",
+];
+
+// Chat-template tokens and role prefixes that could fake a turn boundary
+const MARKERS: &[&str] = &["<|im_start|>", "<|im_end|>", "<|endoftext|>", "<|system|>", "<|user|>", "<|assistant|>", "[inst]", "[/inst]", "<<sys>>", "<</sys>>"];
+const ROLE_PREFIXES: &[&str] = &["system:", "assistant:", "user:", "### instruction", "### system"];
+
+#[derive(Clone, serde::Deserialize)]
+pub struct ContextItem {
+    // Where the text came from: "filename", "tags", "transcription", ...
+    pub label: String,
+    pub text: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct PromptContext {
+    // Ready to drop into a prompt; every item is fenced in <data> tags
+    text: String,
+    // Labels of items that had instruction-like content removed
+    flagged: Vec<String>,
+    truncated: bool,
+}
+
+// Replaces every case-insensitive occurrence of `needle`. ASCII lowercasing
+// keeps byte offsets lined up with the original.
+fn replace_ignore_case(text: &str, needle: &str, with: &str) -> (String, bool) {
+    let lower = text.to_ascii_lowercase();
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, _) in lower.match_indices(needle) {
+        if start < last {
+            continue;
+        }
+        out.push_str(&text[last..start]);
+        out.push_str(with);
+        last = start + needle.len();
+    }
+    out.push_str(&text[last..]);
+    (out, last > 0)
+}
+
+// Strips invisible and control characters, neutralizes anything that looks
+// like an instruction or a role switch, and escapes the fence characters.
+// Returns the cleaned text and whether anything suspicious was removed.
+pub fn sanitize(text: &str) -> (String, bool) {
+    let mut cleaned: String = text
+        .chars()
+        .filter(|c| {
+            // Zero-width and bidi overrides can hide text from the user but not the model
+            !matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}' | '\u{FEFF}')
+        })
+        .map(|c| if c.is_control() && c != '\n' { ' ' } else { c })
+        .collect();
+
+    let mut flagged = false;
+    for needle in SUSPICIOUS.iter().chain(MARKERS) {
+        let (replaced, hit) = replace_ignore_case(&cleaned, needle, FILTERED);
+        cleaned = replaced;
+        flagged |= hit;
+    }
+
+    let lines: Vec<String> = cleaned
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim_start().to_ascii_lowercase();
+            match ROLE_PREFIXES.iter().find(|p| trimmed.starts_with(*p)) {
+                Some(prefix) => {
+                    flagged = true;
+                    let rest = &line.trim_start()[prefix.len()..];
+                    format!("{}{}", FILTERED, rest)
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect();
+
+    let escaped = lines
+        .join("\n")
+        .replace("```", "'''")
+        .replace('<', "‹")
+        .replace('>', "›");
+    let collapsed = escaped.split(' ').filter(|s| !s.is_empty()).collect::<Vec<_>>().join(" ");
+    (collapsed.trim().to_string(), flagged)
+}
+
+// Keeps the start and end, which is where names, hooks and sign-offs live
+pub fn truncate_middle(text: &str, max_chars: usize) -> String {
+    let count = text.chars().count();
+    if count <= max_chars {
+        return text.to_string();
+    }
+    if max_chars < 8 {
+        return text.chars().take(max_chars).collect();
+    }
+    let head = (max_chars - 3) * 2 / 3;
+    let tail = max_chars - 3 - head;
+    let start: String = text.chars().take(head).collect();
+    let end: String = text.chars().skip(count - tail).collect();
+    format!("{} … {}", start.trim_end(), end.trim_start())
+}
+
+// Splits `budget` across items so short ones keep everything and only the
+// longest get cut (water-filling)
+fn allocate(lengths: &[usize], budget: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..lengths.len()).collect();
+    order.sort_by_key(|&i| lengths[i]);
+    let mut allowed = vec![0; lengths.len()];
+    let mut remaining = budget;
+    for (position, &i) in order.iter().enumerate() {
+        let share = remaining / (order.len() - position);
+        allowed[i] = lengths[i].min(share);
+        remaining -= allowed[i];
+    }
+    allowed
+}
+
+pub fn build(items: &[ContextItem], max_chars: usize) -> PromptContext {
+    let mut flagged = Vec::new();
+    let cleaned: Vec<(String, String)> = items
+        .iter()
+        .map(|item| {
+            let (label, _) = sanitize(&item.label);
+            let (text, hit) = sanitize(&item.text);
+            if hit {
+                flagged.push(label.clone());
+            }
+            (label, truncate_middle(&text, MAX_ITEM_CHARS))
+        })
+        .filter(|(_, text)| !text.is_empty())
+        .collect();
+
+    // Tags and wrapper lines count against the budget too
+    let overhead: usize = cleaned.iter().map(|(label, _)| label.chars().count() + 24).sum();
+    let lengths: Vec<usize> = cleaned.iter().map(|(_, text)| text.chars().count()).collect();
+    let allowed = allocate(&lengths, max_chars.saturating_sub(overhead));
+
+    let mut truncated = items.iter().any(|item| item.text.chars().count() > MAX_ITEM_CHARS);
+    let mut text = String::new();
+    for ((label, body), (&length, &allow)) in cleaned.iter().zip(lengths.iter().zip(&allowed)) {
+        if allow == 0 {
+            truncated = true;
+            continue;
+        }
+        truncated |= allow < length;
+        text.push_str(&format!("<data source=\"{}\">\n{}\n</data>\n", label, truncate_middle(body, allow)));
+    }
+    PromptContext { text, flagged, truncated }
+}
+
+fn file_items(conn: &Connection, path: &str) -> Result<Vec<ContextItem>, String> {
+    let name = std::path::Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
+    let mut items = vec![ContextItem { label: "filename".to_string(), text: name.clone() }];
+
+    let tags = library::tags_for(conn, path)?;
+    if !tags.is_empty() {
+        items.push(ContextItem { label: format!("{} tags", name), text: tags.join(", ") });
+    }
+    let mut stmt = conn
+        .prepare("SELECT key, value FROM file_metadata WHERE path = ?1 ORDER BY key")
+        .map_err(|e| e.to_string())?;
+    let metadata = stmt
+        .query_map(params![path], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    for (key, value) in metadata {
+        items.push(ContextItem { label: format!("{} {}", name, key), text: value });
+    }
+    if let Some(summary) = tuning::summary_for(conn, path)? {
+        items.push(ContextItem { label: format!("{} tuning", name), text: summary });
+    }
+    if let Some(summary) = midi_features::summary_for(conn, path)? {
+        items.push(ContextItem { label: format!("{} midi features", name), text: summary });
+    }
+    Ok(items)
+}
+
+// Builds the file-derived part of a prompt: names, tags and metadata for
+// `paths` plus any `extra` text (transcriptions, lyrics) from the frontend,
+// all sanitized and fitted into `max_chars`
+#[tauri::command]
+pub async fn build_ai_context(
+    db: State<'_, Db>,
+    paths: Vec<String>,
+    extra: Option<Vec<ContextItem>>,
+    max_chars: Option<usize>,
+) -> Result<PromptContext, String> {
+    let mut items = Vec::new();
+    {
+        let conn = db.lock();
+        for path in &paths {
+            items.extend(file_items(&conn, path)?);
+        }
+    }
+    items.extend(extra.unwrap_or_default());
+    Ok(build(&items, max_chars.unwrap_or(DEFAULT_MAX_CHARS)))
+}
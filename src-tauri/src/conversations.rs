@@ -0,0 +1,378 @@
+use crate::ai::{ChatReply, ModelParams};
+use crate::db::{self, Db};
+use crate::paths;
+use crate::sandbox;
+use crate::settings;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use tauri::State;
+
+#[derive(serde::Serialize)]
+pub struct ConversationParams {
+    // What the conversation sets itself; unset fields follow the settings
+    overrides: ModelParams,
+    // What the proxy will actually send
+    effective: ModelParams,
+}
+
+pub fn overrides(conn: &Connection, conversation_id: &str) -> Result<ModelParams, String> {
+    conn.query_row(
+        "SELECT temperature, top_p, max_tokens, system_prompt FROM conversation_params WHERE conversation_id = ?1",
+        params![conversation_id],
+        |row| {
+            Ok(ModelParams {
+                temperature: row.get::<_, Option<f64>>(0)?.map(|v| v as f32),
+                top_p: row.get::<_, Option<f64>>(1)?.map(|v| v as f32),
+                max_tokens: row.get(2)?,
+                system_prompt: row.get(3)?,
+            })
+        },
+    )
+    .optional()
+    .map(|params| params.unwrap_or_default())
+    .map_err(|e| e.to_string())
+}
+
+fn defaults(conn: &Connection) -> Result<ModelParams, String> {
+    Ok(ModelParams {
+        temperature: settings::get(conn, "ai.temperature")?,
+        top_p: settings::get(conn, "ai.top_p")?,
+        max_tokens: settings::get(conn, "ai.max_tokens")?,
+        system_prompt: settings::get(conn, "ai.system_prompt")?,
+    })
+}
+
+// Values given with the request win, then the conversation's overrides,
+// then the app-wide defaults
+pub fn resolve(conn: &Connection, conversation_id: Option<&str>, request: &ModelParams) -> Result<ModelParams, String> {
+    let stored = match conversation_id {
+        Some(id) => overrides(conn, id)?,
+        None => ModelParams::default(),
+    };
+    let defaults = defaults(conn)?;
+    Ok(ModelParams {
+        temperature: request.temperature.or(stored.temperature).or(defaults.temperature),
+        top_p: request.top_p.or(stored.top_p).or(defaults.top_p),
+        max_tokens: request.max_tokens.or(stored.max_tokens).or(defaults.max_tokens),
+        system_prompt: request.system_prompt.clone().or(stored.system_prompt).or(defaults.system_prompt),
+    })
+}
+
+#[tauri::command]
+pub async fn get_conversation_params(db: State<'_, Db>, conversation_id: String) -> Result<ConversationParams, String> {
+    let conn = db.lock();
+    Ok(ConversationParams {
+        overrides: overrides(&conn, &conversation_id)?,
+        effective: resolve(&conn, Some(&conversation_id), &ModelParams::default())?,
+    })
+}
+
+fn check(params: &ModelParams) -> Result<(), String> {
+    if let Some(temperature) = params.temperature {
+        if !(0.0..=2.0).contains(&temperature) {
+            return Err("Temperature must be between 0 and 2".to_string());
+        }
+    }
+    if let Some(top_p) = params.top_p {
+        if !(0.0..=1.0).contains(&top_p) {
+            return Err("top_p must be between 0 and 1".to_string());
+        }
+    }
+    if params.max_tokens == Some(0) {
+        return Err("max_tokens must be at least 1".to_string());
+    }
+    Ok(())
+}
+
+// An "ai.*" default gets the same checks as a conversation's override, so a
+// bad value is refused when it's saved rather than failing every request
+pub fn check_setting(key: &str, value: &Value) -> Result<(), String> {
+    if value.is_null() {
+        return Ok(());
+    }
+    let invalid = |e: serde_json::Error| format!("Setting {} is invalid: {}", key, e);
+    let mut params = ModelParams::default();
+    match key {
+        "ai.temperature" => params.temperature = Some(serde_json::from_value(value.clone()).map_err(invalid)?),
+        "ai.top_p" => params.top_p = Some(serde_json::from_value(value.clone()).map_err(invalid)?),
+        "ai.max_tokens" => params.max_tokens = Some(serde_json::from_value(value.clone()).map_err(invalid)?),
+        "ai.system_prompt" => params.system_prompt = Some(serde_json::from_value(value.clone()).map_err(invalid)?),
+        _ => {}
+    }
+    check(&params)
+}
+
+// Replaces the conversation's overrides; fields left out go back to the defaults
+#[tauri::command]
+pub async fn set_conversation_params(db: State<'_, Db>, conversation_id: String, params: ModelParams) -> Result<ConversationParams, String> {
+    check(&params)?;
+
+    let conn = db.lock();
+    conn.execute(
+        "INSERT INTO conversation_params (conversation_id, temperature, top_p, max_tokens, system_prompt, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(conversation_id) DO UPDATE SET temperature = excluded.temperature, top_p = excluded.top_p,
+             max_tokens = excluded.max_tokens, system_prompt = excluded.system_prompt, updated_at = excluded.updated_at",
+        params![
+            conversation_id,
+            params.temperature.map(f64::from),
+            params.top_p.map(f64::from),
+            params.max_tokens,
+            params.system_prompt,
+            db::now()
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(ConversationParams {
+        overrides: overrides(&conn, &conversation_id)?,
+        effective: resolve(&conn, Some(&conversation_id), &ModelParams::default())?,
+    })
+}
+
+#[derive(serde::Serialize)]
+pub struct StoredMessage {
+    id: i64,
+    role: String,
+    content: String,
+    // Files that went with the message: clips, screenshots, generated MIDI
+    attachments: Vec<String>,
+    model: Option<String>,
+    created_at: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct ConversationExport {
+    path: String,
+    messages: usize,
+    attachments: usize,
+    // Attachments that no longer exist on disk
+    missing: Vec<String>,
+}
+
+pub fn add_message(
+    conn: &Connection,
+    conversation_id: &str,
+    role: &str,
+    content: &str,
+    attachments: &[String],
+    model: Option<&str>,
+) -> Result<(), String> {
+    let attachments = serde_json::to_string(attachments).map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO conversation_messages (conversation_id, role, content, attachments, model, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![conversation_id, role, content, attachments, model, db::now()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// The newest user turn and the reply to it; earlier turns were recorded
+// when they were sent
+pub fn record_exchange(
+    conn: &Connection,
+    conversation_id: &str,
+    prompt: Option<&str>,
+    context_paths: &[String],
+    reply: &ChatReply,
+) -> Result<(), String> {
+    if let Some(prompt) = prompt {
+        add_message(conn, conversation_id, "user", prompt, context_paths, None)?;
+    }
+    add_message(conn, conversation_id, "assistant", &reply.text, &[], Some(&reply.model))
+}
+
+pub fn messages(conn: &Connection, conversation_id: &str) -> Result<Vec<StoredMessage>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, role, content, attachments, model, created_at FROM conversation_messages
+             WHERE conversation_id = ?1 ORDER BY id",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![conversation_id], |row| {
+            let attachments: String = row.get(3)?;
+            Ok(StoredMessage {
+                id: row.get(0)?,
+                role: row.get(1)?,
+                content: row.get(2)?,
+                attachments: serde_json::from_str(&attachments).unwrap_or_default(),
+                model: row.get(4)?,
+                created_at: row.get(5)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(rows)
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn attachment_kind(path: &Path) -> &'static str {
+    let mut header = [0u8; 16];
+    let read = fs::File::open(path).and_then(|mut f| f.read(&mut header)).unwrap_or(0);
+    let mime = sandbox::detect_mime(&header[..read], path);
+    if mime.starts_with("image/") {
+        "image"
+    } else if mime == "audio/midi" {
+        "midi"
+    } else if mime.starts_with("audio/") {
+        "audio"
+    } else {
+        "file"
+    }
+}
+
+// Copies an attachment into the bundle under a name no other attachment has
+fn copy_attachment(source: &Path, folder: &Path, used: &mut HashSet<String>) -> Result<String, String> {
+    let name = paths::file_name(source);
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) => (stem.to_string(), format!(".{}", ext)),
+        None => (name.clone(), String::new()),
+    };
+    let mut candidate = name.clone();
+    let mut n = 2;
+    while !used.insert(candidate.to_lowercase()) {
+        candidate = format!("{} ({}){}", stem, n, ext);
+        n += 1;
+    }
+    fs::copy(source, folder.join(&candidate)).map_err(|e| format!("Failed to copy {}: {}", name, e))?;
+    Ok(format!("attachments/{}", candidate))
+}
+
+fn markdown_attachment(kind: &str, name: &str, link: &str) -> String {
+    // Spaces would break a bare Markdown link target
+    let link = link.replace(' ', "%20");
+    match kind {
+        "image" => format!("![{}]({})", name, link),
+        _ => format!("[{}]({})", name, link),
+    }
+}
+
+fn html_attachment(kind: &str, name: &str, link: &str) -> String {
+    let (name, link) = (html_escape(name), html_escape(link));
+    match kind {
+        "image" => format!("<img src=\"{}\" alt=\"{}\">", link, name),
+        "audio" => format!("<figure><audio controls src=\"{}\"></audio><figcaption>{}</figcaption></figure>", link, name),
+        _ => format!("<a href=\"{}\" download>{}</a>", link, name),
+    }
+}
+
+// Saves a file (screenshot, clip, rendered MIDI) into the conversation's
+// history so it shows up in exports
+#[tauri::command]
+pub async fn attach_to_conversation(
+    db: State<'_, Db>,
+    conversation_id: String,
+    paths: Vec<String>,
+    note: Option<String>,
+) -> Result<(), String> {
+    add_message(&db.lock(), &conversation_id, "user", note.as_deref().unwrap_or(""), &paths, None)
+}
+
+#[tauri::command]
+pub async fn get_conversation(db: State<'_, Db>, conversation_id: String) -> Result<Vec<StoredMessage>, String> {
+    messages(&db.lock(), &conversation_id)
+}
+
+// Writes `path` as a folder holding conversation.md or index.html plus an
+// attachments/ folder, so the whole thing can be zipped and shared
+#[tauri::command]
+pub async fn export_conversation(db: State<'_, Db>, id: String, format: String, path: String) -> Result<ConversationExport, String> {
+    if format != "markdown" && format != "html" {
+        return Err(format!("Unknown export format: {}", format));
+    }
+    let history = messages(&db.lock(), &id)?;
+    if history.is_empty() {
+        return Err(format!("Conversation {} has no messages", id));
+    }
+
+    let folder = paths::to_fs(&path);
+    let attachments_dir = folder.join("attachments");
+    fs::create_dir_all(&attachments_dir).map_err(|e| e.to_string())?;
+
+    let mut used = HashSet::new();
+    let mut missing = Vec::new();
+    let mut copied = 0;
+    let mut body = String::new();
+    for message in &history {
+        let heading = match (&message.role[..], &message.model) {
+            ("assistant", Some(model)) => format!("Assistant ({})", model),
+            ("assistant", None) => "Assistant".to_string(),
+            ("system", _) => "System".to_string(),
+            _ => "You".to_string(),
+        };
+        let mut parts = Vec::new();
+        for attachment in &message.attachments {
+            let source = paths::to_fs(attachment);
+            if !source.is_file() {
+                missing.push(attachment.clone());
+                continue;
+            }
+            let link = copy_attachment(&source, &attachments_dir, &mut used)?;
+            let name = paths::file_name(&source);
+            let kind = attachment_kind(&source);
+            copied += 1;
+            parts.push(if format == "html" {
+                html_attachment(kind, &name, &link)
+            } else {
+                markdown_attachment(kind, &name, &link)
+            });
+        }
+
+        if format == "html" {
+            body.push_str(&format!(
+                "<section class=\"{}\"><h2>{}</h2><div class=\"content\">{}</div>{}</section>\n",
+                html_escape(&message.role),
+                html_escape(&heading),
+                html_escape(&message.content),
+                parts.join("\n")
+            ));
+        } else {
+            body.push_str(&format!("## {}\n\n{}\n\n", heading, message.content.trim()));
+            for part in parts {
+                body.push_str(&format!("{}\n\n", part));
+            }
+        }
+    }
+
+    let (file_name, document) = if format == "html" {
+        (
+            "index.html",
+            format!(
+                "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title><style>\n\
+                 body {{ font-family: system-ui, sans-serif; max-width: 48rem; margin: 2rem auto; padding: 0 1rem; }}\n\
+                 section {{ border-bottom: 1px solid #ddd; padding: 1rem 0; }}\n\
+                 .content {{ white-space: pre-wrap; }}\n\
+                 .assistant h2 {{ color: #5b4bd6; }}\n\
+                 img {{ max-width: 100%; }}\n\
+                 </style></head><body>\n<h1>{}</h1>\n{}</body></html>\n",
+                html_escape(&id),
+                html_escape(&id),
+                body
+            ),
+        )
+    } else {
+        ("conversation.md", format!("# {}\n\n{}", id, body))
+    };
+    let output = folder.join(file_name);
+    fs::write(&output, document).map_err(|e| e.to_string())?;
+
+    Ok(ConversationExport {
+        path: paths::display(&output),
+        messages: history.len(),
+        attachments: copied,
+        missing,
+    })
+}
@@ -0,0 +1,187 @@
+use crate::db::Db;
+use crate::export;
+use crate::journal::{self, Operation};
+use crate::library;
+use crate::paths;
+use rusqlite::Connection;
+use std::fs;
+use std::path::Path;
+use tauri::State;
+
+// Kept in file_metadata so they follow moves and go through undo
+const LICENSE: &str = "license";
+const SOURCE_URL: &str = "source_url";
+const ATTRIBUTION: &str = "attribution";
+
+// SPDX identifiers for the licenses sounds are usually shared under, with
+// the name credits spell out
+const KNOWN_LICENSES: &[(&str, &str)] = &[
+    ("CC0-1.0", "Creative Commons Zero 1.0 (public domain)"),
+    ("CC-BY-3.0", "Creative Commons Attribution 3.0"),
+    ("CC-BY-4.0", "Creative Commons Attribution 4.0"),
+    ("CC-BY-SA-4.0", "Creative Commons Attribution-ShareAlike 4.0"),
+    ("CC-BY-NC-3.0", "Creative Commons Attribution-NonCommercial 3.0"),
+    ("CC-BY-NC-4.0", "Creative Commons Attribution-NonCommercial 4.0"),
+    ("CC-BY-NC-SA-4.0", "Creative Commons Attribution-NonCommercial-ShareAlike 4.0"),
+];
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LicenseInfo {
+    // An SPDX identifier for the common ones, or anything else ("Royalty free")
+    license: Option<String>,
+    source_url: Option<String>,
+    // Who to credit, as they asked to be credited
+    attribution: Option<String>,
+}
+
+// Fields left out (or null) keep what each file has; an empty one clears it
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct LicenseUpdate {
+    license: Option<String>,
+    source_url: Option<String>,
+    attribution: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct CreditsExport {
+    path: String,
+    credited: usize,
+    // Selected files with nothing recorded
+    uncredited: usize,
+}
+
+fn license_info(conn: &Connection, path: &str) -> Result<LicenseInfo, String> {
+    Ok(LicenseInfo {
+        license: library::metadata_value(conn, path, LICENSE)?,
+        source_url: library::metadata_value(conn, path, SOURCE_URL)?,
+        attribution: library::metadata_value(conn, path, ATTRIBUTION)?,
+    })
+}
+
+// Known identifiers in their usual case, so "cc-by-4.0" and "CC-BY-4.0"
+// credit the same way
+fn normalize_license(license: &str) -> String {
+    KNOWN_LICENSES
+        .iter()
+        .find(|(id, _)| id.eq_ignore_ascii_case(license))
+        .map(|(id, _)| id.to_string())
+        .unwrap_or_else(|| license.to_string())
+}
+
+fn license_name(license: &str) -> String {
+    KNOWN_LICENSES
+        .iter()
+        .find(|(id, _)| *id == license)
+        .map(|(id, name)| format!("{} ({})", name, id))
+        .unwrap_or_else(|| license.to_string())
+}
+
+fn to_text(rows: &[(String, LicenseInfo)]) -> String {
+    let mut out = String::from("Credits\n");
+    for (path, info) in rows {
+        out.push_str(&format!("\n{}\n", paths::file_name(Path::new(path))));
+        if let Some(attribution) = &info.attribution {
+            out.push_str(&format!("  by {}\n", attribution));
+        }
+        if let Some(license) = &info.license {
+            out.push_str(&format!("  {}\n", license_name(license)));
+        }
+        if let Some(url) = &info.source_url {
+            out.push_str(&format!("  {}\n", url));
+        }
+    }
+    out
+}
+
+fn to_csv(rows: &[(String, LicenseInfo)]) -> String {
+    let mut out = String::from("name,path,license,attribution,source_url\r\n");
+    for (path, info) in rows {
+        let fields = [
+            paths::file_name(Path::new(path)),
+            path.clone(),
+            info.license.clone().unwrap_or_default(),
+            info.attribution.clone().unwrap_or_default(),
+            info.source_url.clone().unwrap_or_default(),
+        ];
+        let line: Vec<String> = fields.iter().map(|f| export::csv_field(f)).collect();
+        out.push_str(&line.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+#[tauri::command]
+pub async fn get_license(db: State<'_, Db>, path: String) -> Result<LicenseInfo, String> {
+    license_info(&db.lock(), &path)
+}
+
+// Sets the license, source and attribution on each file as one undoable
+// step. Fields not given are left as they are; empty ones clear what was
+// there.
+#[tauri::command]
+pub async fn set_license(db: State<'_, Db>, paths: Vec<String>, info: LicenseUpdate) -> Result<(), String> {
+    let clean = |value: String| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+    let fields: Vec<(&str, Option<String>)> = [
+        (LICENSE, info.license.map(|l| clean(l).map(|l| normalize_license(&l)))),
+        (SOURCE_URL, info.source_url.map(clean)),
+        (ATTRIBUTION, info.attribution.map(clean)),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key, value?)))
+    .collect();
+    let conn = db.lock();
+
+    let mut ops = Vec::new();
+    for path in &paths {
+        for (key, value) in &fields {
+            let before = library::metadata_value(&conn, path, key)?;
+            if before != *value {
+                ops.push(Operation::SetMetadata { path: path.clone(), key: key.to_string(), before, after: value.clone() });
+            }
+        }
+    }
+    let label = format!("Set license on {} files", paths.len());
+    journal::run(&conn, &label, ops)
+}
+
+// Writes credits for the selected files that have any license or
+// attribution recorded, as plain text to paste into release notes or as CSV
+// ("text" or "csv"; by default from the file's extension)
+#[tauri::command]
+pub async fn export_credits(
+    db: State<'_, Db>,
+    selection: Vec<String>,
+    path: String,
+    format: Option<String>,
+) -> Result<CreditsExport, String> {
+    let output = paths::to_fs(&path);
+    let format = format.unwrap_or_else(|| {
+        let csv = output.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+        if csv { "csv" } else { "text" }.to_string()
+    });
+    let rows = {
+        let conn = db.lock();
+        let mut rows = Vec::new();
+        for file in &selection {
+            let info = license_info(&conn, file)?;
+            if info.license.is_some() || info.attribution.is_some() || info.source_url.is_some() {
+                rows.push((file.clone(), info));
+            }
+        }
+        rows
+    };
+    let content = match format.as_str() {
+        "text" => to_text(&rows).into_bytes(),
+        // Leading BOM so Excel opens the CSV as UTF-8
+        "csv" => [&[0xEF, 0xBB, 0xBF][..], to_csv(&rows).as_bytes()].concat(),
+        other => return Err(format!("Unknown credits format: {}", other)),
+    };
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::write(&output, content).map_err(|e| e.to_string())?;
+    Ok(CreditsExport { path: paths::display(&output), credited: rows.len(), uncredited: selection.len() - rows.len() })
+}
@@ -0,0 +1,1010 @@
+use crate::encryption;
+use crate::http::Http;
+use crate::jobs::Scheduler;
+use crate::paths;
+use crate::roots::Watchers;
+use crate::sandbox::Sandbox;
+use rusqlite::{params, Connection, OpenFlags, Transaction};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS markers (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        path TEXT NOT NULL,
+        time REAL NOT NULL,
+        text TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_markers_path ON markers(path, time);
+
+    CREATE TABLE IF NOT EXISTS file_tags (
+        path TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (path, tag)
+    );
+    CREATE INDEX IF NOT EXISTS idx_file_tags_tag ON file_tags(tag);
+
+    CREATE TABLE IF NOT EXISTS file_metadata (
+        path TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (path, key)
+    );
+
+    CREATE TABLE IF NOT EXISTS tag_suggestions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        path TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        confidence REAL NOT NULL,
+        source TEXT NOT NULL,
+        status TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        UNIQUE (path, tag)
+    );
+    CREATE INDEX IF NOT EXISTS idx_tag_suggestions_status ON tag_suggestions(status);
+
+    CREATE TABLE IF NOT EXISTS tempo_maps (
+        path TEXT NOT NULL,
+        beats_per_bar INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        analyzed_at INTEGER NOT NULL,
+        PRIMARY KEY (path, beats_per_bar)
+    );
+
+    -- Path-keyed results from before analysis_cache; drained by analysis::migrate_legacy
+    CREATE TABLE IF NOT EXISTS analysis (
+        path TEXT PRIMARY KEY,
+        duration REAL NOT NULL,
+        bpm REAL,
+        key TEXT,
+        key_confidence REAL NOT NULL DEFAULT 0,
+        analyzed_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS analysis_cache (
+        hash TEXT PRIMARY KEY,
+        duration REAL NOT NULL,
+        bpm REAL,
+        key TEXT,
+        key_confidence REAL NOT NULL DEFAULT 0,
+        analyzed_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS file_hashes (
+        path TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        modified INTEGER NOT NULL,
+        hash TEXT NOT NULL,
+        hashed_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_file_hashes_hash ON file_hashes(hash);
+
+    CREATE TABLE IF NOT EXISTS attribute_values (
+        path TEXT NOT NULL,
+        attribute TEXT NOT NULL,
+        source TEXT NOT NULL,
+        value TEXT NOT NULL,
+        confidence REAL NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (path, attribute, source)
+    );
+
+    CREATE TABLE IF NOT EXISTS attribute_resolution (
+        path TEXT NOT NULL,
+        attribute TEXT NOT NULL,
+        value TEXT NOT NULL,
+        source TEXT NOT NULL,
+        conflict INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (path, attribute)
+    );
+    CREATE INDEX IF NOT EXISTS idx_attribute_resolution_conflict ON attribute_resolution(conflict);
+
+    CREATE TABLE IF NOT EXISTS library_roots (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        path TEXT NOT NULL UNIQUE,
+        name TEXT NOT NULL,
+        scan_interval_minutes INTEGER,
+        watch INTEGER NOT NULL DEFAULT 0,
+        follow_symlinks INTEGER NOT NULL DEFAULT 1,
+        last_scan_at INTEGER,
+        scan_started_at INTEGER,
+        volume_kind TEXT NOT NULL DEFAULT 'local',
+        created_at INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS scan_checkpoints (
+        root_id INTEGER PRIMARY KEY,
+        last_dir TEXT NOT NULL,
+        stamp INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        root_id INTEGER,
+        name TEXT NOT NULL,
+        file_type TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        modified INTEGER NOT NULL,
+        indexed_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_files_root ON files(root_id);
+    CREATE INDEX IF NOT EXISTS idx_files_name ON files(name);
+
+    CREATE TABLE IF NOT EXISTS allowed_roots (
+        path TEXT PRIMARY KEY
+    );
+
+    CREATE TABLE IF NOT EXISTS journal (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        label TEXT NOT NULL,
+        operations TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        undo_seq INTEGER
+    );
+";
+
+struct Migration {
+    name: &'static str,
+    apply: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+// Applied in order; the database's user_version is how many have run.
+// Never edit a shipped migration, append a new one instead.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        name: "initial schema",
+        apply: |tx| tx.execute_batch(SCHEMA),
+    },
+    Migration {
+        name: "library root symlink and volume columns",
+        apply: |tx| {
+            // Databases created before these columns existed skipped them in
+            // CREATE TABLE IF NOT EXISTS
+            add_column(tx, "library_roots", "follow_symlinks", "INTEGER NOT NULL DEFAULT 1")?;
+            add_column(tx, "library_roots", "volume_kind", "TEXT NOT NULL DEFAULT 'local'")
+        },
+    },
+    Migration {
+        name: "collections",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE collections (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    created_at INTEGER NOT NULL,
+                    UNIQUE (name, source)
+                );
+                CREATE TABLE collection_items (
+                    collection_id INTEGER NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+                    path TEXT NOT NULL,
+                    position INTEGER NOT NULL,
+                    PRIMARY KEY (collection_id, path)
+                );
+                CREATE INDEX idx_collection_items_path ON collection_items(path);",
+            )
+        },
+    },
+    Migration {
+        name: "quarantine",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE quarantine (
+                    path TEXT PRIMARY KEY,
+                    reason TEXT NOT NULL,
+                    crashes INTEGER NOT NULL DEFAULT 0,
+                    quarantined_at INTEGER,
+                    updated_at INTEGER NOT NULL
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "quarantine decode failures",
+        apply: |tx| {
+            add_column(tx, "quarantine", "kind", "TEXT NOT NULL DEFAULT 'crash'")?;
+            add_column(tx, "quarantine", "failures", "INTEGER NOT NULL DEFAULT 0")
+        },
+    },
+    Migration {
+        name: "ai usage",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE ai_usage (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    conversation_id TEXT NOT NULL,
+                    provider TEXT NOT NULL,
+                    model TEXT NOT NULL,
+                    prompt_tokens INTEGER NOT NULL,
+                    completion_tokens INTEGER NOT NULL,
+                    cost REAL NOT NULL,
+                    created_at INTEGER NOT NULL
+                );
+                CREATE INDEX idx_ai_usage_conversation ON ai_usage(conversation_id);",
+            )
+        },
+    },
+    Migration {
+        name: "ai response cache",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE ai_cache (
+                    key TEXT PRIMARY KEY,
+                    reply TEXT NOT NULL,
+                    created_at INTEGER NOT NULL,
+                    expires_at INTEGER NOT NULL
+                );
+                CREATE INDEX idx_ai_cache_expires ON ai_cache(expires_at);",
+            )
+        },
+    },
+    Migration {
+        name: "settings and conversation params",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE settings (
+                    key TEXT PRIMARY KEY,
+                    value TEXT NOT NULL,
+                    updated_at INTEGER NOT NULL
+                );
+                CREATE TABLE conversation_params (
+                    conversation_id TEXT PRIMARY KEY,
+                    temperature REAL,
+                    top_p REAL,
+                    max_tokens INTEGER,
+                    system_prompt TEXT,
+                    updated_at INTEGER NOT NULL
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "conversation messages",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE conversation_messages (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    conversation_id TEXT NOT NULL,
+                    role TEXT NOT NULL,
+                    content TEXT NOT NULL,
+                    attachments TEXT NOT NULL DEFAULT '[]',
+                    model TEXT,
+                    created_at INTEGER NOT NULL
+                );
+                CREATE INDEX idx_conversation_messages_conversation ON conversation_messages(conversation_id, id);",
+            )
+        },
+    },
+    Migration {
+        name: "audio formats",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE audio_formats (
+                    path TEXT PRIMARY KEY,
+                    size INTEGER NOT NULL,
+                    modified INTEGER NOT NULL,
+                    sample_rate INTEGER NOT NULL,
+                    channels INTEGER NOT NULL,
+                    bits_per_sample INTEGER,
+                    duration REAL
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "root ignore patterns",
+        apply: |tx| add_column(tx, "library_roots", "ignore_patterns", "TEXT NOT NULL DEFAULT ''"),
+    },
+    Migration {
+        name: "scheduled tasks",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE scheduled_tasks (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    action TEXT NOT NULL,
+                    params TEXT NOT NULL DEFAULT '{}',
+                    schedule TEXT NOT NULL,
+                    only_when_idle INTEGER NOT NULL DEFAULT 1,
+                    enabled INTEGER NOT NULL DEFAULT 1,
+                    next_run_at INTEGER,
+                    last_run_at INTEGER,
+                    created_at INTEGER NOT NULL
+                );
+                CREATE TABLE scheduled_runs (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    task_id INTEGER NOT NULL,
+                    trigger TEXT NOT NULL,
+                    status TEXT NOT NULL,
+                    message TEXT,
+                    job_ids TEXT NOT NULL DEFAULT '[]',
+                    started_at INTEGER NOT NULL,
+                    finished_at INTEGER
+                );
+                CREATE INDEX idx_scheduled_runs_task ON scheduled_runs(task_id, id);",
+            )
+        },
+    },
+    Migration {
+        name: "tuning reports",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE tuning_reports (
+                    path TEXT PRIMARY KEY,
+                    key TEXT NOT NULL,
+                    data TEXT NOT NULL,
+                    summary TEXT NOT NULL,
+                    analyzed_at INTEGER NOT NULL
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "noise profiles",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE noise_profiles (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    start_seconds REAL NOT NULL,
+                    end_seconds REAL NOT NULL,
+                    sample_rate INTEGER NOT NULL,
+                    -- Mean magnitude per FFT bin, JSON
+                    spectrum TEXT NOT NULL,
+                    created_at INTEGER NOT NULL
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "take lanes",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE take_lanes (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    folder TEXT NOT NULL,
+                    keeper_take_id INTEGER,
+                    created_at INTEGER NOT NULL
+                );
+                CREATE TABLE takes (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    lane_id INTEGER NOT NULL REFERENCES take_lanes(id) ON DELETE CASCADE,
+                    number INTEGER NOT NULL,
+                    path TEXT NOT NULL UNIQUE,
+                    length REAL NOT NULL,
+                    -- 1-5 stars, NULL until rated
+                    rating INTEGER,
+                    rejected INTEGER NOT NULL DEFAULT 0,
+                    created_at INTEGER NOT NULL,
+                    UNIQUE (lane_id, number)
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "midi features",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE midi_features (
+                    path TEXT PRIMARY KEY,
+                    data TEXT NOT NULL,
+                    summary TEXT NOT NULL,
+                    analyzed_at INTEGER NOT NULL
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "midi chords",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE midi_chords (
+                    path TEXT PRIMARY KEY,
+                    -- The file's modified time when detected; a newer file is detected again
+                    modified INTEGER NOT NULL,
+                    data TEXT NOT NULL,
+                    analyzed_at INTEGER NOT NULL
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "melody lines",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE melody_lines (
+                    path TEXT PRIMARY KEY,
+                    modified INTEGER NOT NULL,
+                    data TEXT NOT NULL,
+                    analyzed_at INTEGER NOT NULL
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "midi renders",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE midi_renders (
+                    midi_hash TEXT NOT NULL,
+                    soundfont_hash TEXT NOT NULL,
+                    sample_rate INTEGER NOT NULL,
+                    output TEXT NOT NULL,
+                    seconds REAL NOT NULL,
+                    rendered_at INTEGER NOT NULL,
+                    PRIMARY KEY (midi_hash, soundfont_hash, sample_rate)
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "sysex dumps",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE sysex_dumps (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    -- The synth it came from, as the user names it
+                    model TEXT,
+                    -- From the ID in the first message
+                    manufacturer TEXT,
+                    path TEXT NOT NULL UNIQUE,
+                    messages INTEGER NOT NULL,
+                    bytes INTEGER NOT NULL,
+                    created_at INTEGER NOT NULL
+                );
+                CREATE TABLE sysex_tags (
+                    dump_id INTEGER NOT NULL REFERENCES sysex_dumps(id) ON DELETE CASCADE,
+                    tag TEXT NOT NULL,
+                    PRIMARY KEY (dump_id, tag)
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "plugin presets",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE plugin_presets (
+                    path TEXT PRIMARY KEY,
+                    -- The file extension: fxp, fxb, vstpreset or aupreset
+                    format TEXT NOT NULL,
+                    plugin TEXT,
+                    plugin_id TEXT,
+                    preset_name TEXT,
+                    modified INTEGER NOT NULL,
+                    indexed_at INTEGER NOT NULL
+                );
+                CREATE INDEX plugin_presets_plugin ON plugin_presets (plugin COLLATE NOCASE);",
+            )
+        },
+    },
+    Migration {
+        name: "pack provenance",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE pack_provenance (
+                    path TEXT PRIMARY KEY,
+                    pack TEXT NOT NULL,
+                    vendor TEXT,
+                    -- The archive it was extracted from, if it came in one
+                    archive TEXT,
+                    imported_at INTEGER NOT NULL
+                );
+                CREATE INDEX pack_provenance_vendor ON pack_provenance (vendor COLLATE NOCASE, imported_at);",
+            )
+        },
+    },
+    Migration {
+        name: "root read-only flag",
+        apply: |tx| add_column(tx, "library_roots", "read_only", "INTEGER NOT NULL DEFAULT 0"),
+    },
+    Migration {
+        name: "network audit",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE network_audit (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    at INTEGER NOT NULL,
+                    provider TEXT NOT NULL,
+                    -- What the request was for: chat, lyric transcription
+                    purpose TEXT NOT NULL,
+                    method TEXT NOT NULL,
+                    endpoint TEXT NOT NULL,
+                    payload_bytes INTEGER,
+                    status INTEGER,
+                    error TEXT,
+                    duration_ms INTEGER NOT NULL
+                );
+                CREATE INDEX network_audit_at ON network_audit (at);",
+            )
+        },
+    },
+    Migration {
+        name: "midi features modified time",
+        // Rows from before this match no file's time, so they're redone
+        apply: |tx| add_column(tx, "midi_features", "modified", "INTEGER NOT NULL DEFAULT 0"),
+    },
+    Migration {
+        name: "tuning reports modified time",
+        apply: |tx| add_column(tx, "tuning_reports", "modified", "INTEGER NOT NULL DEFAULT 0"),
+    },
+    Migration {
+        name: "file sub-kinds",
+        apply: |tx| {
+            add_column(tx, "files", "sub_kind", "TEXT")?;
+            // Impulse responses were their own type for a while
+            tx.execute_batch("UPDATE files SET file_type = 'audio', sub_kind = 'ir' WHERE file_type = 'ir'")
+        },
+    },
+    Migration {
+        name: "tempo maps modified time",
+        apply: |tx| add_column(tx, "tempo_maps", "modified", "INTEGER NOT NULL DEFAULT 0"),
+    },
+];
+
+fn add_column(tx: &Transaction, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    let exists: bool = tx.query_row(
+        &format!("SELECT COUNT(*) > 0 FROM pragma_table_info('{}') WHERE name = ?1", table),
+        params![column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        tx.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))?;
+    }
+    Ok(())
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.len() as i64
+}
+
+fn schema_version(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+fn has_tables(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        [],
+        |row| row.get(0),
+    )
+}
+
+// Snapshot of the database as it was before an upgrade, next to the original
+fn backup_before_migrate(conn: &Connection, path: &Path, version: i64) -> Result<PathBuf, String> {
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let backup = path.with_file_name(format!("{}.v{}-{}.bak", file_name, version, now()));
+    conn.execute("VACUUM INTO ?1", params![backup.to_string_lossy()])
+        .map_err(|e| format!("Failed to back up database before migrating: {}", e))?;
+    Ok(backup)
+}
+
+fn migrate(conn: &mut Connection, path: &Path) -> Result<(), String> {
+    let version = schema_version(conn).map_err(|e| e.to_string())?;
+    if version > latest_version() {
+        return Err(format!(
+            "Library database is schema version {} but this build only knows {}; update the app",
+            version,
+            latest_version()
+        ));
+    }
+    if version == latest_version() {
+        return Ok(());
+    }
+
+    if has_tables(conn).map_err(|e| e.to_string())? {
+        backup_before_migrate(conn, path, version)?;
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        (migration.apply)(&tx).map_err(|e| format!("Migration {} ({}) failed: {}", index + 1, migration.name, e))?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)
+            .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct TableCount {
+    pub name: String,
+    pub rows: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct DbInfo {
+    pub path: String,
+    pub schema_version: i64,
+    pub latest_version: i64,
+    pub size_bytes: u64,
+    pub tables: Vec<TableCount>,
+}
+
+fn connect(path: &Path) -> Result<Connection, String> {
+    connect_with(path, encryption::stored_key(path).as_deref())
+}
+
+// Opens with `key` rather than the keychain's, for a file just put in place
+fn connect_with(path: &Path, key: Option<&str>) -> Result<Connection, String> {
+    let mut conn = connect_unmigrated(path, key)?;
+    migrate(&mut conn, path)?;
+    Ok(conn)
+}
+
+fn connect_unmigrated(path: &Path, key: Option<&str>) -> Result<Connection, String> {
+    let conn = Connection::open(path).map_err(|e| format!("Failed to open database: {}", e))?;
+    // An encrypted library has to be keyed before anything reads it
+    if let Some(key) = key {
+        conn.pragma_update(None, "key", key).map_err(|e| e.to_string())?;
+    }
+    conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")
+        .map_err(|e| encryption::open_error(path, e))?;
+    Ok(conn)
+}
+
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn remove_sidecars(path: &Path) -> Result<(), String> {
+    for suffix in ["-wal", "-shm"] {
+        let file = sidecar(path, suffix);
+        if file.exists() {
+            std::fs::remove_file(&file).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+// Rejects anything that isn't an intact library database this build can open
+fn validate_backup(source: &Path, key: Option<&str>) -> Result<(), String> {
+    let conn = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Cannot open backup: {}", e))?;
+    // Backups of an encrypted library carry its passphrase
+    if let Some(key) = key {
+        conn.pragma_update(None, "key", key).map_err(|e| e.to_string())?;
+    }
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+        .map_err(|e| format!("Not a library backup: {}", e))?;
+    if integrity != "ok" {
+        return Err(format!("Backup is corrupt: {}", integrity));
+    }
+
+    let version = schema_version(&conn).map_err(|e| e.to_string())?;
+    if version > latest_version() {
+        return Err(format!(
+            "Backup is schema version {} but this build only knows {}; update the app first",
+            version,
+            latest_version()
+        ));
+    }
+    let is_library: bool = conn
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'files'",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if !is_library {
+        return Err("File is not a library backup".to_string());
+    }
+    Ok(())
+}
+
+// Shared handle to the library database. Cloning is cheap so background
+// tasks can take their own copy into spawn_blocking.
+#[derive(Clone)]
+pub struct Db {
+    conn: Arc<Mutex<Connection>>,
+    // Shared too, since switching profiles points every handle at another file
+    path: Arc<Mutex<PathBuf>>,
+}
+
+impl Db {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+
+        Ok(Db {
+            conn: Arc::new(Mutex::new(connect(path)?)),
+            path: Arc::new(Mutex::new(path.to_path_buf())),
+        })
+    }
+
+    // Opens the database as it is, for a library another instance has
+    // open: that one may be an older build the migrations would break.
+    // switch_to the same path migrates it once it's free.
+    pub fn open_unmigrated(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+
+        Ok(Db {
+            conn: Arc::new(Mutex::new(connect_unmigrated(path, encryption::stored_key(path).as_deref())?)),
+            path: Arc::new(Mutex::new(path.to_path_buf())),
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.path.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // Closes this database and opens the one at `path` in its place, for
+    // every handle. The current one stays open if the new one can't be.
+    pub fn switch_to(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let next = connect(path)?;
+        let mut conn = self.lock();
+        *conn = next;
+        *self.path.lock().unwrap_or_else(|e| e.into_inner()) = path.to_path_buf();
+        Ok(())
+    }
+
+    // Consistent snapshot of the live database; safe while other writers run
+    pub fn backup_to(&self, dest: &Path) -> Result<u64, String> {
+        if dest == self.path() {
+            return Err("Backup destination is the live database".to_string());
+        }
+        // VACUUM INTO refuses to overwrite
+        if dest.exists() {
+            std::fs::remove_file(dest).map_err(|e| e.to_string())?;
+        }
+        self.lock()
+            .execute("VACUUM INTO ?1", params![dest.to_string_lossy()])
+            .map_err(|e| format!("Backup failed: {}", e))?;
+        std::fs::metadata(dest).map(|m| m.len()).map_err(|e| e.to_string())
+    }
+
+    // Replaces the database with `source`, keeping a copy of the current one
+    // next to it. Returns the path of that copy.
+    pub fn restore_from(&self, source: &Path) -> Result<PathBuf, String> {
+        let path = self.path();
+        // The header says whether the backup is encrypted. If it is, it was
+        // made with a passphrase this library has had, which has to be the
+        // one in the keychain now.
+        let current_key = encryption::stored_key(&path);
+        let backup_key = if encryption::is_encrypted(source) {
+            Some(current_key.clone().ok_or("The backup is encrypted, and the keychain has no passphrase to open it")?)
+        } else {
+            None
+        };
+        validate_backup(source, backup_key.as_deref())?;
+
+        let mut conn = self.lock();
+        let version = schema_version(&conn).map_err(|e| e.to_string())?;
+        let previous = backup_before_migrate(&conn, &path, version)?;
+
+        // Swap in a throwaway connection so the file is closed while it's replaced
+        let placeholder = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        let old = std::mem::replace(&mut *conn, placeholder);
+        if let Err((old, e)) = old.close() {
+            *conn = old;
+            return Err(e.to_string());
+        }
+
+        // Older backups are upgraded to the current schema on the way in
+        let restored = remove_sidecars(&path)
+            .and_then(|_| std::fs::copy(source, &path).map_err(|e| e.to_string()))
+            .and_then(|_| connect_with(&path, backup_key.as_deref()));
+        match restored {
+            Ok(restored) => *conn = restored,
+            Err(e) => {
+                // Put the snapshot back so the app keeps a working database
+                let _ = remove_sidecars(&path);
+                let _ = std::fs::copy(&previous, &path);
+                *conn = connect(&path)?;
+                return Err(format!("Restore failed: {}", e));
+            }
+        }
+        drop(conn);
+
+        // Back to the library's current setting: a plain backup into an
+        // encrypted library is encrypted again, and the other way round. The
+        // keychain follows the restored file first so a failed rewrite
+        // leaves the two in step.
+        if backup_key != current_key {
+            encryption::store_key(&path, backup_key.as_deref())?;
+            self.set_encryption(current_key.as_deref())
+                .map_err(|e| format!("Restored, but the library's encryption couldn't be put back: {}", e))?;
+        }
+        Ok(previous)
+    }
+
+    // Rewrites the database encrypted with `key`, or unencrypted with None,
+    // and keeps the keychain in step. The rewritten copy is made beside the
+    // current file, which is only removed once the copy has opened.
+    pub fn set_encryption(&self, key: Option<&str>) -> Result<(), String> {
+        let path = self.path();
+        let rewritten = sidecar(&path, ".rekey");
+        let original = sidecar(&path, ".original");
+        let _ = std::fs::remove_file(&rewritten);
+
+        let mut conn = self.lock();
+        let export = |conn: &Connection| -> rusqlite::Result<()> {
+            let version = schema_version(conn)?;
+            // An empty key attaches an unencrypted database
+            conn.execute("ATTACH DATABASE ?1 AS rekeyed KEY ?2", params![rewritten.to_string_lossy(), key.unwrap_or("")])?;
+            conn.query_row("SELECT sqlcipher_export('rekeyed')", [], |_| Ok(()))?;
+            // sqlcipher_export copies the tables but not the schema version
+            conn.pragma_update(Some("rekeyed"), "user_version", version)?;
+            conn.execute("DETACH DATABASE rekeyed", [])?;
+            Ok(())
+        };
+        if let Err(e) = export(&conn) {
+            let _ = conn.execute("DETACH DATABASE rekeyed", []);
+            let _ = std::fs::remove_file(&rewritten);
+            return Err(format!("Failed to rewrite the database: {}", e));
+        }
+
+        let placeholder = Connection::open_in_memory().map_err(|e| e.to_string())?;
+        let old = std::mem::replace(&mut *conn, placeholder);
+        if let Err((old, e)) = old.close() {
+            *conn = old;
+            let _ = std::fs::remove_file(&rewritten);
+            return Err(e.to_string());
+        }
+
+        let previous_key = encryption::stored_key(&path);
+        let swapped = remove_sidecars(&path)
+            .and_then(|_| std::fs::rename(&path, &original).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&rewritten, &path).map_err(|e| e.to_string()))
+            .and_then(|_| encryption::store_key(&path, key))
+            .and_then(|_| connect(&path));
+        match swapped {
+            Ok(next) => {
+                *conn = next;
+                let _ = std::fs::remove_file(&original);
+                Ok(())
+            }
+            Err(e) => {
+                // Back to the file and passphrase as they were
+                let _ = encryption::store_key(&path, previous_key.as_deref());
+                if original.exists() {
+                    let _ = std::fs::remove_file(&path);
+                    let _ = std::fs::rename(&original, &path);
+                }
+                let _ = std::fs::remove_file(&rewritten);
+                *conn = connect(&path)?;
+                Err(format!("Failed to change encryption: {}", e))
+            }
+        }
+    }
+
+    // Rebuilds the file to reclaim space left by deleted rows. Returns the
+    // size before and after.
+    pub fn vacuum(&self) -> Result<(u64, u64), String> {
+        let before = self.size_on_disk();
+        self.lock()
+            .execute_batch("PRAGMA wal_checkpoint(TRUNCATE); VACUUM; PRAGMA optimize;")
+            .map_err(|e| format!("Vacuum failed: {}", e))?;
+        Ok((before, self.size_on_disk()))
+    }
+
+    pub fn size_on_disk(&self) -> u64 {
+        // The WAL holds recent writes until the next checkpoint
+        let path = self.path();
+        [path.clone(), sidecar(&path, "-wal")]
+            .iter()
+            .filter_map(|p| std::fs::metadata(p).ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Connection> {
+        // A panic while holding the lock doesn't leave SQLite in a bad state
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[tauri::command]
+pub async fn get_db_info(db: tauri::State<'_, Db>) -> Result<DbInfo, String> {
+    let conn = db.lock();
+    let schema_version = schema_version(&conn).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name")
+        .map_err(|e| e.to_string())?;
+    let names = stmt
+        .query_map([], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+    drop(stmt);
+    let mut tables = Vec::with_capacity(names.len());
+    for name in names {
+        let rows = conn
+            .query_row(&format!("SELECT COUNT(*) FROM \"{}\"", name), [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        tables.push(TableCount { name, rows });
+    }
+
+    drop(conn);
+
+    Ok(DbInfo {
+        path: db.path().to_string_lossy().to_string(),
+        schema_version,
+        latest_version: latest_version(),
+        size_bytes: db.size_on_disk(),
+        tables,
+    })
+}
+
+#[derive(serde::Serialize)]
+pub struct BackupResult {
+    pub path: String,
+    pub size_bytes: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct VacuumResult {
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+// Restore and vacuum rewrite the database file, so no job may be mid-write.
+// Queued jobs wait until the operation is done.
+pub fn exclusive<T>(scheduler: &Scheduler, operation: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    scheduler.pause()?;
+    let result = operation();
+    scheduler.resume();
+    result
+}
+
+#[tauri::command]
+pub async fn backup_database(db: tauri::State<'_, Db>, dest: String) -> Result<BackupResult, String> {
+    let dest = paths::to_fs(&dest);
+    let size_bytes = db.backup_to(&dest)?;
+    Ok(BackupResult {
+        path: paths::display(&dest),
+        size_bytes,
+    })
+}
+
+#[tauri::command]
+pub async fn restore_database(
+    app: AppHandle,
+    db: tauri::State<'_, Db>,
+    scheduler: tauri::State<'_, Scheduler>,
+    src: String,
+) -> Result<BackupResult, String> {
+    let source = paths::to_fs(&src);
+    let previous = exclusive(&scheduler, || db.restore_from(&source))?;
+
+    // Roots and granted folders come from the restored database now
+    app.state::<Sandbox>().reset(&db)?;
+    app.state::<Watchers>().reload(&db)?;
+    let _ = app.state::<Http>().configure();
+    let _ = app.emit("library-restored", ());
+
+    // Where the pre-restore copy went, in case the user wants it back
+    Ok(BackupResult {
+        size_bytes: std::fs::metadata(&previous).map(|m| m.len()).unwrap_or(0),
+        path: paths::display(&previous),
+    })
+}
+
+#[tauri::command]
+pub async fn vacuum_database(db: tauri::State<'_, Db>, scheduler: tauri::State<'_, Scheduler>) -> Result<VacuumResult, String> {
+    let (size_before, size_after) = exclusive(&scheduler, || db.vacuum())?;
+    Ok(VacuumResult { size_before, size_after })
+}
@@ -0,0 +1,136 @@
+use crate::audio::{self, AudioData};
+use crate::db::Db;
+use crate::paths;
+use crate::settings;
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+use tauri::State;
+
+// Memory for decoded previews unless "playback.cache_mb" says otherwise
+const DEFAULT_BUDGET_MB: usize = 256;
+// Longer files are songs and stems, played once rather than flicked through
+const MAX_CACHED_SECONDS: f64 = 60.0;
+
+struct Entry {
+    path: String,
+    // A file rewritten in place (an edit, a re-render) must decode again
+    size: u64,
+    modified: Option<SystemTime>,
+    audio: Arc<AudioData>,
+}
+
+impl Entry {
+    fn bytes(&self) -> usize {
+        self.audio.samples.len() * std::mem::size_of::<f32>()
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    // Least recently played at the front
+    entries: VecDeque<Entry>,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct DecodeCacheStats {
+    entries: usize,
+    bytes: usize,
+    budget: usize,
+    hits: u64,
+    misses: u64,
+}
+
+// Decoded PCM of recently previewed short samples, so browsing back and
+// forth through a drum folder replays instantly instead of decoding again.
+// Evicts least recently played first once over budget.
+#[derive(Clone, Default)]
+pub struct DecodeCache {
+    inner: Arc<Mutex<Inner>>,
+}
+
+fn budget(db: &Db) -> usize {
+    let mb: usize = settings::get(&db.lock(), "playback.cache_mb").ok().flatten().unwrap_or(DEFAULT_BUDGET_MB);
+    mb * 1024 * 1024
+}
+
+impl DecodeCache {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn decode(&self, db: &Db, path: &str) -> Result<Arc<AudioData>, String> {
+        let fs_path = paths::to_fs(path);
+        let metadata = fs::metadata(&fs_path).map_err(|e| format!("Failed to open audio file: {}", e))?;
+        let (size, modified) = (metadata.len(), metadata.modified().ok());
+
+        {
+            let mut inner = self.lock();
+            let found = inner.entries.iter().position(|e| e.path == path);
+            if let Some(entry) = found.and_then(|index| inner.entries.remove(index)) {
+                if entry.size == size && entry.modified == modified {
+                    let audio = entry.audio.clone();
+                    inner.entries.push_back(entry);
+                    inner.hits += 1;
+                    return Ok(audio);
+                }
+                inner.bytes -= entry.bytes();
+            }
+            inner.misses += 1;
+        }
+
+        // Decoded outside the lock so a long file doesn't hold up other previews
+        let audio = Arc::new(audio::decode_file(&fs_path, None)?);
+        let seconds = audio.samples.len() as f64 / audio.channels.max(1) as f64 / audio.sample_rate.max(1) as f64;
+        if seconds > MAX_CACHED_SECONDS {
+            return Ok(audio);
+        }
+        let budget = budget(db);
+        let entry = Entry { path: path.to_string(), size, modified, audio: audio.clone() };
+        if entry.bytes() > budget {
+            return Ok(audio);
+        }
+
+        let mut inner = self.lock();
+        // Two previews of the same file can race; keep the newer decode
+        if let Some(index) = inner.entries.iter().position(|e| e.path == path) {
+            if let Some(old) = inner.entries.remove(index) {
+                inner.bytes -= old.bytes();
+            }
+        }
+        inner.bytes += entry.bytes();
+        inner.entries.push_back(entry);
+        while inner.bytes > budget {
+            match inner.entries.pop_front() {
+                Some(old) => inner.bytes -= old.bytes(),
+                None => break,
+            }
+        }
+        Ok(audio)
+    }
+}
+
+#[tauri::command]
+pub async fn get_decode_cache_stats(db: State<'_, Db>, cache: State<'_, DecodeCache>) -> Result<DecodeCacheStats, String> {
+    let budget = budget(&db);
+    let inner = cache.lock();
+    Ok(DecodeCacheStats {
+        entries: inner.entries.len(),
+        bytes: inner.bytes,
+        budget,
+        hits: inner.hits,
+        misses: inner.misses,
+    })
+}
+
+#[tauri::command]
+pub async fn clear_decode_cache(cache: State<'_, DecodeCache>) -> Result<(), String> {
+    let mut inner = cache.lock();
+    inner.entries.clear();
+    inner.bytes = 0;
+    Ok(())
+}
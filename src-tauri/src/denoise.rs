@@ -0,0 +1,286 @@
+use crate::audio::{self, AudioData};
+use crate::db::{self, Db};
+use crate::derived;
+use crate::dsp;
+use crate::jobs::{Priority, Scheduler};
+use crate::paths;
+use crate::quarantine;
+use rusqlite::{params, OptionalExtension};
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use tauri::State;
+
+const SOURCE: &str = "denoise";
+// 2048 at 44.1 kHz: 21 Hz bins, fine enough to leave tonal material alone
+const N_FFT: usize = 2048;
+const HOP: usize = N_FFT / 4;
+// Less than this and the average noise spectrum is mostly chance
+const MIN_PROFILE_SECONDS: f64 = 0.25;
+// At full amount: subtract twice the profile, cut at most 24 dB per bin
+const MAX_OVERSUBTRACT: f32 = 1.0;
+const MAX_REDUCTION_DB: f32 = 24.0;
+// Share of the previous frame's gain kept; smooths the warbly "musical
+// noise" plain spectral subtraction leaves behind
+const GAIN_SMOOTHING: f32 = 0.5;
+
+#[derive(serde::Deserialize)]
+pub struct Region {
+    start: f64,
+    end: f64,
+}
+
+#[derive(serde::Serialize)]
+pub struct NoiseProfile {
+    id: i64,
+    name: String,
+    source: String,
+    start: f64,
+    end: f64,
+    sample_rate: u32,
+    created_at: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct DenoisedFile {
+    source: String,
+    // None when this file failed; see `error`
+    path: Option<String>,
+    error: Option<String>,
+}
+
+// Mean magnitude per bin over every frame of `signal`
+fn average_spectrum(signal: &[f32]) -> Vec<f32> {
+    let mut sum = vec![0.0f64; N_FFT / 2 + 1];
+    let mut frames = 0usize;
+    let mut on_frame = |spectrum: &[f32]| {
+        for (total, magnitude) in sum.iter_mut().zip(spectrum) {
+            *total += *magnitude as f64;
+        }
+        frames += 1;
+    };
+    let mut stft = dsp::Stft::new(N_FFT, HOP);
+    stft.push(signal, &mut on_frame);
+    stft.finish(&mut on_frame);
+    sum.into_iter().map(|total| (total / frames.max(1) as f64) as f32).collect()
+}
+
+// The profile's spectrum at another sample rate's bin spacing
+fn profile_at(spectrum: &[f32], profile_rate: u32, sample_rate: u32) -> Vec<f32> {
+    if profile_rate == sample_rate {
+        return spectrum.to_vec();
+    }
+    let scale = sample_rate as f32 / profile_rate as f32;
+    (0..N_FFT / 2 + 1)
+        .map(|k| {
+            let pos = k as f32 * scale;
+            let i = pos.floor() as usize;
+            let frac = pos - i as f32;
+            let a = spectrum.get(i).copied().unwrap_or(0.0);
+            let b = spectrum.get(i + 1).copied().unwrap_or(a);
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+// Spectral subtraction: each bin is turned down by how much of it the noise
+// profile accounts for, never below the reduction floor, with the gains
+// smoothed over time. `amount` runs 0 (untouched) to 1 (strongest).
+fn subtract(signal: &[f32], noise: &[f32], amount: f32) -> Vec<f32> {
+    let bins = N_FFT / 2 + 1;
+    let oversubtract = 1.0 + MAX_OVERSUBTRACT * amount;
+    let floor = 10f32.powf(-MAX_REDUCTION_DB * amount / 20.0);
+    let window = dsp::hann(N_FFT);
+    let mut planner = FftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(N_FFT);
+    let inverse = planner.plan_fft_inverse(N_FFT);
+
+    let mut out = vec![0.0f32; signal.len() + N_FFT];
+    let mut norm = vec![0.0f32; signal.len() + N_FFT];
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); N_FFT];
+    let mut gains = vec![1.0f32; bins];
+    let mut pos = 0;
+    while pos < signal.len() {
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            *slot = Complex::new(signal.get(pos + i).copied().unwrap_or(0.0) * window[i], 0.0);
+        }
+        forward.process(&mut buffer);
+        for (k, gain) in gains.iter_mut().enumerate() {
+            let magnitude = buffer[k].norm().max(1e-12);
+            let target = (1.0 - oversubtract * noise[k] / magnitude).max(floor);
+            *gain = GAIN_SMOOTHING * *gain + (1.0 - GAIN_SMOOTHING) * target;
+            buffer[k] *= *gain;
+        }
+        // Mirror image, so the inverse transform comes out real
+        for k in 1..N_FFT / 2 {
+            buffer[N_FFT - k] = buffer[k].conj();
+        }
+        inverse.process(&mut buffer);
+        for (i, w) in window.iter().enumerate() {
+            out[pos + i] += buffer[i].re / N_FFT as f32 * w;
+            norm[pos + i] += w * w;
+        }
+        pos += HOP;
+    }
+    for (sample, weight) in out.iter_mut().zip(&norm) {
+        if *weight > 1e-3 {
+            *sample /= weight;
+        }
+    }
+    out.truncate(signal.len());
+    out
+}
+
+pub fn denoise_audio(audio: &AudioData, noise: &[f32], profile_rate: u32, amount: f32) -> AudioData {
+    let channels = audio.channels.max(1);
+    let noise = profile_at(noise, profile_rate, audio.sample_rate);
+    let processed: Vec<Vec<f32>> = (0..channels)
+        .map(|c| {
+            let signal: Vec<f32> = audio.samples.iter().skip(c).step_by(channels).copied().collect();
+            subtract(&signal, &noise, amount)
+        })
+        .collect();
+    let frames = processed.iter().map(|c| c.len()).min().unwrap_or(0);
+    let mut samples = Vec::with_capacity(frames * channels);
+    for i in 0..frames {
+        for channel in &processed {
+            samples.push(channel[i]);
+        }
+    }
+    AudioData { sample_rate: audio.sample_rate, channels, samples }
+}
+
+fn load_profile(db: &Db, id: i64) -> Result<(Vec<f32>, u32), String> {
+    let (spectrum, sample_rate): (String, u32) = db
+        .lock()
+        .query_row("SELECT spectrum, sample_rate FROM noise_profiles WHERE id = ?1", params![id], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Noise profile {} not found", id))?;
+    let spectrum: Vec<f32> = serde_json::from_str(&spectrum).map_err(|e| e.to_string())?;
+    if spectrum.len() != N_FFT / 2 + 1 {
+        return Err("Noise profile is from an incompatible version".to_string());
+    }
+    Ok((spectrum, sample_rate))
+}
+
+fn denoise_one(db: &Db, path: &str, noise: &[f32], profile_rate: u32, amount: f32, dest_dir: Option<&str>) -> Result<String, String> {
+    let decoded = quarantine::guard(db, path, || audio::decode_file(&paths::to_fs(path), None))?;
+    let cleaned = denoise_audio(&decoded, noise, profile_rate, amount);
+    derived::write(db, path, dest_dir, "denoised", &cleaned, SOURCE, &[])
+}
+
+// Learns what the noise in `region` of `path` sounds like (hiss, hum, room
+// tone between takes) and saves it for `denoise`. The region should hold
+// nothing but the noise.
+#[tauri::command]
+pub async fn capture_noise_profile(
+    db: State<'_, Db>,
+    path: String,
+    region: Region,
+    name: Option<String>,
+) -> Result<NoiseProfile, String> {
+    let length = region.end - region.start;
+    if region.start < 0.0 || length < MIN_PROFILE_SECONDS {
+        return Err(format!("Pick at least {} seconds of noise", MIN_PROFILE_SECONDS));
+    }
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let decoded = quarantine::guard(&db, &path, || audio::decode_range(&paths::to_fs(&path), region.start, Some(length)))?;
+        let spectrum = average_spectrum(&decoded.to_mono());
+        let name = name.unwrap_or_else(|| {
+            let file = std::path::Path::new(&path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+            format!("{} {:.1}-{:.1}s", file, region.start, region.end)
+        });
+        let json = serde_json::to_string(&spectrum).map_err(|e| e.to_string())?;
+        let created_at = db::now();
+        let conn = db.lock();
+        conn.execute(
+            "INSERT INTO noise_profiles (name, source, start_seconds, end_seconds, sample_rate, spectrum, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![name, path, region.start, region.end, decoded.sample_rate, json, created_at],
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(NoiseProfile {
+            id: conn.last_insert_rowid(),
+            name,
+            source: path,
+            start: region.start,
+            end: region.end,
+            sample_rate: decoded.sample_rate,
+            created_at,
+        })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn list_noise_profiles(db: State<'_, Db>) -> Result<Vec<NoiseProfile>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, name, source, start_seconds, end_seconds, sample_rate, created_at
+             FROM noise_profiles ORDER BY created_at DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let profiles = stmt
+        .query_map([], |row| {
+            Ok(NoiseProfile {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                source: row.get(2)?,
+                start: row.get(3)?,
+                end: row.get(4)?,
+                sample_rate: row.get(5)?,
+                created_at: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(profiles)
+}
+
+#[tauri::command]
+pub async fn delete_noise_profile(db: State<'_, Db>, id: i64) -> Result<(), String> {
+    db.lock()
+        .execute("DELETE FROM noise_profiles WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Cleans every file in `paths` with a captured noise profile and writes
+// each as "<name> denoised.wav". `amount` is 0-1 (default 0.6); higher
+// removes more noise at the risk of a watery sound.
+#[tauri::command]
+pub async fn denoise(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    paths: Vec<String>,
+    profile: i64,
+    amount: Option<f32>,
+    dest_dir: Option<String>,
+) -> Result<Vec<DenoisedFile>, String> {
+    let amount = amount.unwrap_or(0.6).clamp(0.0, 1.0);
+    let (noise, profile_rate) = load_profile(&db, profile)?;
+    let db = db.inner().clone();
+    let label = format!("Denoise {} files", paths.len());
+    scheduler
+        .run("conversion", label, Priority::Normal, move |ctx| {
+            let mut results = Vec::new();
+            for (i, path) in paths.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    return Err("Cancelled".to_string());
+                }
+                ctx.progress(i as f32 / paths.len() as f32, Some(path.clone()));
+                results.push(match denoise_one(&db, path, &noise, profile_rate, amount, dest_dir.as_deref()) {
+                    Ok(written) => DenoisedFile { source: path.clone(), path: Some(written), error: None },
+                    Err(e) => DenoisedFile { source: path.clone(), path: None, error: Some(e) },
+                });
+            }
+            Ok(results)
+        })
+        .await
+}
@@ -0,0 +1,112 @@
+use crate::audio::{self, AudioData};
+use crate::db::{self, Db};
+use crate::library;
+use crate::paths;
+use crate::reconcile;
+use crate::roots;
+use crate::scanner;
+use rusqlite::{params, OptionalExtension};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// "<stem> <suffix>.wav" in `dir`, numbered if that's taken
+pub fn output_path(dir: &Path, stem: &str, suffix: &str) -> PathBuf {
+    output_path_with(dir, stem, suffix, "wav")
+}
+
+fn output_path_with(dir: &Path, stem: &str, suffix: &str, extension: &str) -> PathBuf {
+    let mut candidate = dir.join(format!("{} {}.{}", stem, suffix, extension));
+    let mut n = 2;
+    while candidate.exists() {
+        candidate = dir.join(format!("{} {} {}.{}", stem, suffix, n, extension));
+        n += 1;
+    }
+    candidate
+}
+
+// BPM and key of `source`, for copies that don't change either
+pub fn carried_attributes(db: &Db, source: &str) -> Result<Vec<(&'static str, String)>, String> {
+    let conn = db.lock();
+    let mut attributes = Vec::new();
+    for attribute in ["bpm", "key"] {
+        let value: Option<String> = conn
+            .query_row(
+                "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = ?2",
+                params![source, attribute],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if let Some(value) = value {
+            attributes.push((attribute, value));
+        }
+    }
+    Ok(attributes)
+}
+
+// Where a processed copy of `source` goes: next to it or into `dest_dir`
+fn place(db: &Db, source: &str, dest_dir: Option<&str>, suffix: &str, extension: &str) -> Result<PathBuf, String> {
+    let source_fs = paths::to_fs(source);
+    let dir = match dest_dir {
+        Some(dir) => paths::to_fs(dir),
+        None => source_fs.parent().map(Path::to_path_buf).ok_or_else(|| format!("No folder for {}", source))?,
+    };
+    roots::ensure_writable(&db.lock(), &[&paths::display(&dir)])?;
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let stem = source_fs
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "audio".to_string());
+    Ok(output_path_with(&dir, &stem, suffix, extension))
+}
+
+// Indexes a written copy, carries over the source's tags and records
+// `attributes` against it as coming from `origin`
+fn register(db: &Db, source: &str, output: &Path, origin: &str, attributes: &[(&str, String)]) -> Result<String, String> {
+    let display = paths::display(output);
+    let conn = db.lock();
+    if let Some(file) = scanner::scanned_file(output, false)? {
+        let root_id = roots::root_containing(&conn, &display)?;
+        library::upsert_file(&conn, root_id, &file, db::now())?;
+    }
+    for tag in library::tags_for(&conn, source)? {
+        conn.execute("INSERT OR IGNORE INTO file_tags (path, tag) VALUES (?1, ?2)", params![display, tag])
+            .map_err(|e| e.to_string())?;
+    }
+    for (attribute, value) in attributes {
+        reconcile::import_value(&conn, &display, attribute, origin, value)?;
+    }
+    Ok(display)
+}
+
+// Writes a processed copy of `source` (a stretch, a repair, a render) next
+// to it or into `dest_dir`, indexes it, carries over the source's tags and
+// records `attributes` (bpm, key) against it as coming from `origin`
+pub fn write(
+    db: &Db,
+    source: &str,
+    dest_dir: Option<&str>,
+    suffix: &str,
+    audio: &AudioData,
+    origin: &str,
+    attributes: &[(&str, String)],
+) -> Result<String, String> {
+    let output = place(db, source, dest_dir, suffix, "wav")?;
+    audio::write_wav(&output, audio)?;
+    register(db, source, &output, origin, attributes)
+}
+
+// The same for an edited MIDI file; `save` writes it to the path it's given
+pub fn write_midi(
+    db: &Db,
+    source: &str,
+    dest_dir: Option<&str>,
+    suffix: &str,
+    save: impl FnOnce(&Path) -> Result<(), String>,
+    origin: &str,
+    attributes: &[(&str, String)],
+) -> Result<String, String> {
+    let output = place(db, source, dest_dir, suffix, "mid")?;
+    save(&output)?;
+    register(db, source, &output, origin, attributes)
+}
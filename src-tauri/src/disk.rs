@@ -0,0 +1,225 @@
+use crate::audio;
+use crate::db::Db;
+use crate::jobs::{JobContext, Priority, Scheduler};
+use crate::paths;
+use crate::scanner::AUDIO_EXTENSIONS;
+use rusqlite::{params, OptionalExtension};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use tauri::State;
+
+const LARGEST_FILES: usize = 25;
+
+#[derive(serde::Serialize)]
+pub struct UsageNode {
+    name: String,
+    path: String,
+    bytes: u64,
+    files: u64,
+    // Biggest first; empty below the requested depth
+    children: Vec<UsageNode>,
+}
+
+#[derive(Default, serde::Serialize)]
+pub struct UsageBucket {
+    label: String,
+    bytes: u64,
+    files: u64,
+}
+
+#[derive(serde::Serialize)]
+pub struct LargeFile {
+    path: String,
+    bytes: u64,
+    // "24-bit / 96 kHz stereo" for audio, None otherwise
+    format: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DiskUsage {
+    tree: UsageNode,
+    by_type: Vec<UsageBucket>,
+    by_format: Vec<UsageBucket>,
+    largest: Vec<LargeFile>,
+}
+
+fn modified_secs(metadata: &fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// Sample rate, channels and bit depth from the file header, remembered
+// against size and mtime like content hashes are
+pub fn audio_format(db: &Db, path: &str) -> Result<audio::AudioFormat, String> {
+    let metadata = fs::metadata(paths::to_fs(path)).map_err(|e| e.to_string())?;
+    let (size, modified) = (metadata.len() as i64, modified_secs(&metadata));
+    let cached = db
+        .lock()
+        .query_row(
+            "SELECT sample_rate, channels, bits_per_sample, duration FROM audio_formats
+             WHERE path = ?1 AND size = ?2 AND modified = ?3",
+            params![path, size, modified],
+            |row| {
+                Ok(audio::AudioFormat {
+                    sample_rate: row.get(0)?,
+                    channels: row.get::<_, i64>(1)? as usize,
+                    bits_per_sample: row.get(2)?,
+                    duration: row.get(3)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(format) = cached {
+        return Ok(format);
+    }
+
+    let format = audio::probe_format(&paths::to_fs(path))?;
+    db.lock()
+        .execute(
+            "INSERT OR REPLACE INTO audio_formats (path, size, modified, sample_rate, channels, bits_per_sample, duration)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![path, size, modified, format.sample_rate, format.channels as i64, format.bits_per_sample, format.duration],
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(format)
+}
+
+fn format_label(format: &audio::AudioFormat) -> String {
+    let rate = format!("{} kHz", format.sample_rate as f64 / 1000.0);
+    let channels = match format.channels {
+        1 => "mono".to_string(),
+        2 => "stereo".to_string(),
+        n => format!("{} ch", n),
+    };
+    match format.bits_per_sample {
+        Some(bits) => format!("{}-bit / {} {}", bits, rate, channels),
+        None => format!("{} {}", rate, channels),
+    }
+}
+
+struct Totals {
+    by_type: HashMap<String, UsageBucket>,
+    by_format: HashMap<String, UsageBucket>,
+    largest: Vec<LargeFile>,
+    seen: u64,
+}
+
+fn add(buckets: &mut HashMap<String, UsageBucket>, label: String, bytes: u64) {
+    let bucket = buckets.entry(label.clone()).or_insert_with(|| UsageBucket { label, ..Default::default() });
+    bucket.bytes += bytes;
+    bucket.files += 1;
+}
+
+fn walk(db: &Db, ctx: &JobContext, dir: &Path, depth: usize, max_depth: usize, totals: &mut Totals) -> Result<UsageNode, String> {
+    let mut node = UsageNode {
+        name: paths::file_name(dir),
+        path: paths::display(dir),
+        bytes: 0,
+        files: 0,
+        children: Vec::new(),
+    };
+    // Unreadable folders (permissions, a drive going away) count as empty
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(node),
+    };
+    for entry in entries.flatten() {
+        if ctx.is_cancelled() {
+            return Err("Cancelled".to_string());
+        }
+        let path = entry.path();
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        // Links would count their target twice, or loop forever
+        if metadata.file_type().is_symlink() {
+            continue;
+        }
+        if metadata.is_dir() {
+            let child = walk(db, ctx, &path, depth + 1, max_depth, totals)?;
+            node.bytes += child.bytes;
+            node.files += child.files;
+            if depth < max_depth {
+                node.children.push(child);
+            }
+            continue;
+        }
+
+        let bytes = metadata.len();
+        node.bytes += bytes;
+        node.files += 1;
+        totals.seen += 1;
+        if totals.seen % 500 == 0 {
+            ctx.progress(0.0, Some(format!("{} files", totals.seen)));
+        }
+
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+        add(&mut totals.by_type, if ext.is_empty() { "other".to_string() } else { ext.clone() }, bytes);
+        let display = paths::display(&path);
+        let format = if AUDIO_EXTENSIONS.contains(&ext.as_str()) {
+            audio_format(db, &display).ok().map(|f| format_label(&f))
+        } else {
+            None
+        };
+        if let Some(label) = &format {
+            add(&mut totals.by_format, label.clone(), bytes);
+        }
+
+        if totals.largest.len() < LARGEST_FILES || totals.largest.last().map(|l| bytes > l.bytes).unwrap_or(true) {
+            totals.largest.push(LargeFile { path: display, bytes, format });
+            totals.largest.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+            totals.largest.truncate(LARGEST_FILES);
+        }
+    }
+    node.children.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    Ok(node)
+}
+
+fn sorted(buckets: HashMap<String, UsageBucket>) -> Vec<UsageBucket> {
+    let mut buckets: Vec<UsageBucket> = buckets.into_values().collect();
+    buckets.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    buckets
+}
+
+// Where the space under `root` goes: a folder tree for a treemap (down to
+// `max_depth` levels), totals per file type and per audio format, and the
+// biggest individual files. Counts everything on disk, not just indexed files.
+#[tauri::command]
+pub async fn analyze_disk_usage(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    root: String,
+    max_depth: Option<usize>,
+) -> Result<DiskUsage, String> {
+    let db = db.inner().clone();
+    let dir = paths::to_fs(&root);
+    if !dir.is_dir() {
+        return Err(format!("Not a folder: {}", root));
+    }
+    let label = format!("Disk usage of {}", paths::file_name(&dir));
+    scheduler
+        .run("scan", label, Priority::Interactive, move |ctx| {
+            let mut totals = Totals {
+                by_type: HashMap::new(),
+                by_format: HashMap::new(),
+                largest: Vec::new(),
+                seen: 0,
+            };
+            let tree = walk(&db, ctx, &dir, 0, max_depth.unwrap_or(4), &mut totals)?;
+            Ok(DiskUsage {
+                tree,
+                by_type: sorted(totals.by_type),
+                by_format: sorted(totals.by_format),
+                largest: totals.largest,
+            })
+        })
+        .await
+}
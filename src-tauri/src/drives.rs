@@ -0,0 +1,108 @@
+use crate::db::Db;
+use crate::jobs::Scheduler;
+use crate::roots::{self, Watchers};
+use std::collections::HashMap;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::task::JoinHandle;
+
+// Polled rather than hooked into each OS's mount notifications; a few
+// seconds is quick enough to notice a drive being plugged in
+const CHECK_INTERVAL: Duration = Duration::from_secs(3);
+// A share that's gone away can hang a directory listing
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, serde::Serialize)]
+struct RootStatus {
+    id: i64,
+    name: String,
+    online: bool,
+}
+
+// Lists every root at once, so one hung share doesn't hold up the rest. A
+// listing still going at its timeout is kept in `pending`, and its root
+// counts as offline without another listing piling up behind it.
+async fn check_all(roots: &[(i64, String, String)], pending: &mut HashMap<String, JoinHandle<bool>>) -> HashMap<String, bool> {
+    pending.retain(|_, listing| !listing.is_finished());
+    let listings: Vec<(String, JoinHandle<bool>)> = roots
+        .iter()
+        .filter(|(_, _, path)| !pending.contains_key(path))
+        .map(|(_, _, path)| {
+            let root = path.clone();
+            (path.clone(), tokio::task::spawn_blocking(move || roots::is_online(&root)))
+        })
+        .collect();
+    let results = futures_util::future::join_all(listings.into_iter().map(|(path, mut listing)| async move {
+        let online = matches!(tokio::time::timeout(CHECK_TIMEOUT, &mut listing).await, Ok(Ok(true)));
+        (path, online, listing)
+    }))
+    .await;
+    let mut online = HashMap::new();
+    for (path, is_online, listing) in results {
+        if !listing.is_finished() {
+            pending.insert(path.clone(), listing);
+        }
+        online.insert(path, is_online);
+    }
+    online
+}
+
+fn load(db: &Db) -> Result<Vec<(i64, String, String)>, String> {
+    let conn = db.lock();
+    let mut stmt = conn.prepare("SELECT id, name, path FROM library_roots").map_err(|e| e.to_string())?;
+    let roots = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(roots)
+}
+
+// Watches library roots on removable drives and shares come and go. Each
+// change is sent to the UI as "root-online-changed"; jobs on a root that
+// went away wait in the queue until it's back, and a returning root gets its
+// watcher back and a catch-up scan.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let db = app.state::<Db>().inner().clone();
+        // By path, so another profile's root with the same id isn't taken
+        // for this one coming back
+        let mut known: HashMap<String, bool> = HashMap::new();
+        let mut pending = HashMap::new();
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Ok(roots) = load(&db) else { continue };
+            let checked = check_all(&roots, &mut pending).await;
+            let mut changed = known.len() != roots.len();
+            let mut offline = Vec::new();
+            for (id, name, path) in &roots {
+                let online = checked.get(path).copied().unwrap_or(false);
+                if !online {
+                    offline.push(path.clone());
+                }
+                let before = known.insert(path.clone(), online);
+                if before == Some(online) {
+                    continue;
+                }
+                changed = true;
+                // The first look at a root is just where it starts
+                if before.is_none() {
+                    continue;
+                }
+                let watchers = app.state::<Watchers>();
+                if online {
+                    let _ = roots::reconnected(&db, &app.state::<Scheduler>(), &watchers, *id);
+                } else {
+                    watchers.remove(*id);
+                }
+                let _ = app.emit("root-online-changed", RootStatus { id: *id, name: name.clone(), online });
+            }
+            known.retain(|known_path, _| roots.iter().any(|(_, _, path)| path == known_path));
+            if changed {
+                app.state::<Scheduler>().set_offline(offline);
+            }
+        }
+    });
+}
@@ -0,0 +1,402 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+
+pub fn hann(size: usize) -> Vec<f32> {
+    (0..size)
+        .map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / size as f32).cos())
+        .collect()
+}
+
+// Linear-interpolating resampler; fine for analysis, not for playback
+pub fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from as f64 / to as f64;
+    let out_len = (samples.len() as f64 / ratio) as usize;
+    (0..out_len)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let idx = pos as usize;
+            let frac = (pos - idx as f64) as f32;
+            let a = samples[idx.min(samples.len() - 1)];
+            let b = samples[(idx + 1).min(samples.len() - 1)];
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+// `resample` for input that arrives in blocks, producing exactly what
+// resampling the whole signal at once would. Holds on to the one or two
+// input samples the next output still needs.
+pub struct Resampler {
+    ratio: f64,
+    // Input samples already dropped from `pending`
+    consumed: u64,
+    produced: u64,
+    pending: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32) -> Self {
+        Resampler { ratio: from as f64 / to as f64, consumed: 0, produced: 0, pending: Vec::new() }
+    }
+
+    fn emit(&mut self, available: u64, out: &mut Vec<f32>) {
+        let last = self.pending.len().saturating_sub(1);
+        while self.produced < available {
+            let pos = self.produced as f64 * self.ratio;
+            let idx = (pos as u64 - self.consumed) as usize;
+            let frac = (pos - pos.floor()) as f32;
+            let a = self.pending[idx.min(last)];
+            let b = self.pending[(idx + 1).min(last)];
+            out.push(a + (b - a) * frac);
+            self.produced += 1;
+        }
+    }
+
+    pub fn push(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        self.pending.extend_from_slice(input);
+        // Outputs whose right-hand neighbour has arrived, never more than the
+        // input so far could produce on its own
+        let end = self.consumed + self.pending.len() as u64;
+        let ready = ((end.saturating_sub(1) as f64 / self.ratio).ceil() as u64)
+            .min((end as f64 / self.ratio) as u64)
+            .max(self.produced);
+        self.emit(ready, out);
+        let needed = ((self.produced as f64 * self.ratio) as u64).saturating_sub(self.consumed) as usize;
+        let drop = needed.min(self.pending.len());
+        self.pending.drain(..drop);
+        self.consumed += drop as u64;
+    }
+
+    // Flushes the tail once the input has ended
+    pub fn finish(&mut self, out: &mut Vec<f32>) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let total = self.consumed + self.pending.len() as u64;
+        self.emit((total as f64 / self.ratio) as u64, out);
+        self.pending.clear();
+    }
+}
+
+// `stft_magnitudes` for input that arrives in blocks: only the current
+// window is kept, and each spectrum goes to the callback instead of a Vec
+pub struct Stft {
+    n_fft: usize,
+    hop: usize,
+    window: Vec<f32>,
+    fft: std::sync::Arc<dyn rustfft::Fft<f32>>,
+    buffer: Vec<Complex<f32>>,
+    pending: Vec<f32>,
+}
+
+impl Stft {
+    pub fn new(n_fft: usize, hop: usize) -> Self {
+        Stft {
+            n_fft,
+            hop,
+            window: hann(n_fft),
+            fft: FftPlanner::<f32>::new().plan_fft_forward(n_fft),
+            buffer: vec![Complex::new(0.0, 0.0); n_fft],
+            pending: Vec::new(),
+        }
+    }
+
+    fn frame(&mut self, on_frame: &mut impl FnMut(&[f32])) {
+        for (i, slot) in self.buffer.iter_mut().enumerate() {
+            let sample = self.pending.get(i).copied().unwrap_or(0.0);
+            *slot = Complex::new(sample * self.window[i], 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+        let magnitudes: Vec<f32> = self.buffer[..self.n_fft / 2 + 1].iter().map(|c| c.norm()).collect();
+        on_frame(&magnitudes);
+        let hop = self.hop.min(self.pending.len());
+        self.pending.drain(..hop);
+    }
+
+    pub fn push(&mut self, samples: &[f32], mut on_frame: impl FnMut(&[f32])) {
+        self.pending.extend_from_slice(samples);
+        while self.pending.len() >= self.n_fft {
+            self.frame(&mut on_frame);
+        }
+    }
+
+    // Zero-pads the last windows, as stft_magnitudes does at the end
+    pub fn finish(&mut self, mut on_frame: impl FnMut(&[f32])) {
+        while !self.pending.is_empty() {
+            self.frame(&mut on_frame);
+        }
+    }
+}
+
+// Magnitude spectra, one Vec of n_fft/2 + 1 bins per hop
+pub fn stft_magnitudes(samples: &[f32], n_fft: usize, hop: usize) -> Vec<Vec<f32>> {
+    let window = hann(n_fft);
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(n_fft);
+    let mut frames = Vec::new();
+    let mut buffer = vec![Complex::new(0.0, 0.0); n_fft];
+
+    let mut start = 0;
+    while start < samples.len() {
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            let sample = samples.get(start + i).copied().unwrap_or(0.0);
+            *slot = Complex::new(sample * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+        frames.push(buffer[..n_fft / 2 + 1].iter().map(|c| c.norm()).collect());
+        start += hop;
+    }
+    frames
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+// Triangular mel filters over the n_fft/2 + 1 FFT bins
+pub fn mel_filterbank(n_mels: usize, n_fft: usize, sample_rate: u32) -> Vec<Vec<f32>> {
+    let bins = n_fft / 2 + 1;
+    let max_mel = hz_to_mel(sample_rate as f32 / 2.0);
+    let points: Vec<f32> = (0..n_mels + 2)
+        .map(|i| mel_to_hz(max_mel * i as f32 / (n_mels + 1) as f32))
+        .map(|hz| hz * n_fft as f32 / sample_rate as f32)
+        .collect();
+
+    (0..n_mels)
+        .map(|m| {
+            let (left, center, right) = (points[m], points[m + 1], points[m + 2]);
+            (0..bins)
+                .map(|b| {
+                    let b = b as f32;
+                    if b <= left || b >= right {
+                        0.0
+                    } else if b <= center {
+                        (b - left) / (center - left).max(1e-6)
+                    } else {
+                        (right - b) / (right - center).max(1e-6)
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn log_mel_spectrogram(samples: &[f32], sample_rate: u32, n_fft: usize, hop: usize, n_mels: usize) -> Vec<Vec<f32>> {
+    let filters = mel_filterbank(n_mels, n_fft, sample_rate);
+    stft_magnitudes(samples, n_fft, hop)
+        .iter()
+        .map(|spectrum| {
+            filters
+                .iter()
+                .map(|filter| {
+                    let energy: f32 = filter.iter().zip(spectrum).map(|(w, m)| w * m * m).sum();
+                    (energy + 1e-10).ln()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+// Integrated loudness in dB: mean power over 400 ms blocks, ignoring
+// silence and quiet passages the way EBU R128 gating does. There's no
+// K-weighting, so it's for matching levels, not for reporting LUFS.
+pub fn gated_loudness(samples: &[f32], sample_rate: u32) -> f32 {
+    let block = (sample_rate as usize * 2 / 5).max(1);
+    let powers: Vec<f32> = samples
+        .chunks(block)
+        .map(|chunk| chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32)
+        .filter(|p| 10.0 * p.max(1e-12).log10() > -70.0)
+        .collect();
+    if powers.is_empty() {
+        return -70.0;
+    }
+    let mean = powers.iter().sum::<f32>() / powers.len() as f32;
+    let relative_gate = mean / 10.0;
+    let loud: Vec<f32> = powers.into_iter().filter(|p| *p >= relative_gate).collect();
+    let gated = loud.iter().sum::<f32>() / loud.len().max(1) as f32;
+    10.0 * gated.max(1e-12).log10()
+}
+
+// Transposed direct form II biquad (RBJ cookbook designs). Keeps state, so
+// each channel needs its own.
+#[derive(Clone)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad { b0: b[0] / a[0], b1: b[1] / a[0], b2: b[2] / a[0], a1: a[1] / a[0], a2: a[2] / a[0], z1: 0.0, z2: 0.0 }
+    }
+
+    pub fn high_pass(sample_rate: u32, freq: f64, q: f64) -> Self {
+        let w0 = std::f64::consts::TAU * freq / sample_rate as f64;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        Biquad::new([(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+    }
+
+    pub fn low_pass(sample_rate: u32, freq: f64, q: f64) -> Self {
+        let w0 = std::f64::consts::TAU * freq / sample_rate as f64;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        Biquad::new([(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+    }
+
+    pub fn peaking(sample_rate: u32, freq: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = std::f64::consts::TAU * freq / sample_rate as f64;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        Biquad::new([1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a], [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a])
+    }
+
+    pub fn low_shelf(sample_rate: u32, freq: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = std::f64::consts::TAU * freq / sample_rate as f64;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        let root = 2.0 * a.sqrt() * alpha;
+        Biquad::new(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + root),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - root),
+            ],
+            [(a + 1.0) + (a - 1.0) * cos + root, -2.0 * ((a - 1.0) + (a + 1.0) * cos), (a + 1.0) + (a - 1.0) * cos - root],
+        )
+    }
+
+    pub fn high_shelf(sample_rate: u32, freq: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = std::f64::consts::TAU * freq / sample_rate as f64;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        let root = 2.0 * a.sqrt() * alpha;
+        Biquad::new(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + root),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - root),
+            ],
+            [(a + 1.0) - (a - 1.0) * cos + root, 2.0 * ((a - 1.0) - (a + 1.0) * cos), (a + 1.0) - (a - 1.0) * cos - root],
+        )
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let x = x as f64;
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y as f32
+    }
+}
+
+// Full linear convolution (signal length + IR length - 1) by FFT
+// overlap-add, so multi-second reverb IRs stay quick
+pub fn convolve(signal: &[f32], ir: &[f32]) -> Vec<f32> {
+    if signal.is_empty() || ir.is_empty() {
+        return Vec::new();
+    }
+    let n = (2 * ir.len()).next_power_of_two().max(1024);
+    let block = n - ir.len() + 1;
+    let mut planner = FftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(n);
+    let inverse = planner.plan_fft_inverse(n);
+
+    let mut ir_spectrum: Vec<Complex<f32>> = ir.iter().map(|s| Complex::new(*s, 0.0)).collect();
+    ir_spectrum.resize(n, Complex::new(0.0, 0.0));
+    forward.process(&mut ir_spectrum);
+
+    let mut out = vec![0.0f32; signal.len() + ir.len() - 1];
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); n];
+    for (index, chunk) in signal.chunks(block).enumerate() {
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            *slot = Complex::new(chunk.get(i).copied().unwrap_or(0.0), 0.0);
+        }
+        forward.process(&mut buffer);
+        for (value, h) in buffer.iter_mut().zip(&ir_spectrum) {
+            *value *= h;
+        }
+        inverse.process(&mut buffer);
+        let start = index * block;
+        for (slot, value) in out[start..].iter_mut().zip(&buffer[..chunk.len() + ir.len() - 1]) {
+            *slot += value.re / n as f32;
+        }
+    }
+    out
+}
+
+// ITU-R BS.1770 K-weighting: a shelf for the head's effect on what reaches
+// the ear, then a high-pass that ignores sub rumble. Needed for real LUFS.
+pub fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    [
+        Biquad::high_shelf(sample_rate, 1681.974450955533, 0.7071752369554196, 3.999843853973347),
+        Biquad::high_pass(sample_rate, 38.13547087602444, 0.5003270373238773),
+    ]
+}
+
+// Onset strength per hop: summed positive change in log magnitude (spectral
+// flux). `max_bin` restricts it to low bins for kick-weighted envelopes.
+pub fn onset_envelope(spectra: &[Vec<f32>], max_bin: Option<usize>) -> Vec<f32> {
+    let mut envelope = Vec::with_capacity(spectra.len());
+    let mut previous: Option<Vec<f32>> = None;
+    for spectrum in spectra {
+        let bins = max_bin.unwrap_or(spectrum.len()).min(spectrum.len());
+        let log = log_compress(&spectrum[..bins]);
+        envelope.push(previous.as_ref().map(|prev| spectral_flux(&log, prev)).unwrap_or(0.0));
+        previous = Some(log);
+    }
+    remove_onset_floor(&envelope)
+}
+
+pub fn log_compress(spectrum: &[f32]) -> Vec<f32> {
+    spectrum.iter().map(|m| (1.0 + 100.0 * m).ln()).collect()
+}
+
+// Summed rise in log magnitude from one frame to the next, over the bins
+// both have
+pub fn spectral_flux(log: &[f32], previous: &[f32]) -> f32 {
+    log.iter().zip(previous).map(|(a, b)| (a - b).max(0.0)).sum()
+}
+
+// Remove the slowly varying floor so sustained material doesn't read as onsets
+pub fn remove_onset_floor(envelope: &[f32]) -> Vec<f32> {
+    let window = 16;
+    (0..envelope.len())
+        .map(|i| {
+            let lo = i.saturating_sub(window);
+            let hi = (i + window + 1).min(envelope.len());
+            let mean = envelope[lo..hi].iter().sum::<f32>() / (hi - lo) as f32;
+            (envelope[i] - mean).max(0.0)
+        })
+        .collect()
+}
+
+// Adds one spectrum's energy to a 12-bin pitch-class profile, C = 0
+pub fn add_chroma(profile: &mut [f32; 12], spectrum: &[f32], n_fft: usize, sample_rate: u32) {
+    let bin_hz = sample_rate as f32 / n_fft as f32;
+    for (bin, magnitude) in spectrum.iter().enumerate().skip(1) {
+        let freq = bin as f32 * bin_hz;
+        if !(55.0..=5000.0).contains(&freq) {
+            continue;
+        }
+        let midi = 69.0 + 12.0 * (freq / 440.0).log2();
+        let class = (midi.round() as i32).rem_euclid(12) as usize;
+        profile[class] += magnitude * magnitude;
+    }
+}
@@ -0,0 +1,320 @@
+use crate::db::Db;
+use crate::hashes;
+use crate::jobs::{Priority, Scheduler};
+use crate::journal::{self, Operation};
+use crate::library;
+use crate::paths;
+use crate::roots;
+use rusqlite::params;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, MAIN_SEPARATOR};
+use tauri::State;
+
+#[derive(Clone, serde::Serialize)]
+pub struct DuplicateFolderGroup {
+    fingerprint: String,
+    file_count: usize,
+    // Per copy; deleting all but one frees this times (copies - 1)
+    total_bytes: i64,
+    folders: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct FolderCleanup {
+    removed: Vec<String>,
+    // Files the index didn't know about, moved into the kept folder first
+    carried: Vec<String>,
+    freed_bytes: i64,
+    // Tags that only the removed copies had, now on the kept files
+    tags_merged: usize,
+}
+
+// Every indexed file under each folder, as (path relative to the folder, size)
+fn folder_contents(files: &[(String, i64)], root: Option<&Path>) -> HashMap<String, Vec<(String, i64)>> {
+    let mut folders: HashMap<String, Vec<(String, i64)>> = HashMap::new();
+    for (path, size) in files {
+        let file = Path::new(path);
+        for ancestor in file.ancestors().skip(1) {
+            if root.map(|r| !ancestor.starts_with(r)).unwrap_or(false) || ancestor.parent().is_none() {
+                break;
+            }
+            if let Ok(relative) = file.strip_prefix(ancestor) {
+                folders
+                    .entry(paths::display(ancestor))
+                    .or_default()
+                    .push((paths::display(relative), *size));
+            }
+        }
+    }
+    folders
+}
+
+fn fingerprint<'a>(entries: impl Iterator<Item = (&'a str, String)>) -> String {
+    let mut sorted: Vec<(&str, String)> = entries.collect();
+    sorted.sort();
+    let mut hasher = Sha256::new();
+    for (relative, value) in sorted {
+        // Separators normalized so a pack on a Windows drive matches its Mac copy
+        hasher.update(relative.replace('\\', "/").as_bytes());
+        hasher.update([0]);
+        hasher.update(value.as_bytes());
+        hasher.update([b'\n']);
+    }
+    hex::encode(hasher.finalize())
+}
+
+// Fingerprint over the contents of every file in the folder, so two copies
+// only match when every file is byte-identical and in the same place
+fn content_fingerprint(db: &Db, folder: &str, contents: &[(String, i64)]) -> Result<String, String> {
+    let mut entries = Vec::with_capacity(contents.len());
+    for (relative, _) in contents {
+        let full = paths::display(&Path::new(folder).join(relative));
+        entries.push((relative.as_str(), hashes::content_hash(db, &full)?));
+    }
+    Ok(fingerprint(entries.into_iter()))
+}
+
+fn indexed_files(db: &Db, under: Option<&str>) -> Result<Vec<(String, i64)>, String> {
+    let conn = db.lock();
+    let prefix = under.map(|u| format!("{}{}", u.trim_end_matches(MAIN_SEPARATOR), MAIN_SEPARATOR)).unwrap_or_default();
+    let mut stmt = conn
+        .prepare("SELECT path, size FROM files WHERE substr(path, 1, ?2) = ?1")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![prefix, prefix.chars().count() as i64], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<(String, i64)>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(rows)
+}
+
+// Finds folders that hold the same files in the same layout, such as one
+// pack unzipped twice in different places. Layout and sizes narrow the
+// candidates; content hashes confirm them. Only the outermost duplicated
+// folder is reported, not every matching subfolder inside it.
+#[tauri::command]
+pub async fn find_duplicate_folders(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    root: Option<String>,
+    min_files: Option<usize>,
+) -> Result<Vec<DuplicateFolderGroup>, String> {
+    let db = db.inner().clone();
+    let min_files = min_files.unwrap_or(3).max(1);
+    scheduler
+        .run("scan", "Find duplicate folders", Priority::Interactive, move |ctx| {
+            let files = indexed_files(&db, root.as_deref())?;
+            let folders = folder_contents(&files, root.as_deref().map(Path::new));
+
+            let mut by_layout: HashMap<String, Vec<&String>> = HashMap::new();
+            for (folder, contents) in &folders {
+                if contents.len() >= min_files {
+                    let layout = fingerprint(contents.iter().map(|(r, s)| (r.as_str(), s.to_string())));
+                    by_layout.entry(layout).or_default().push(folder);
+                }
+            }
+            let candidates: Vec<Vec<&String>> = by_layout.into_values().filter(|g| g.len() > 1).collect();
+
+            let total = candidates.len().max(1);
+            let mut groups: Vec<DuplicateFolderGroup> = Vec::new();
+            for (i, candidate) in candidates.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    return Err("Cancelled".to_string());
+                }
+                ctx.progress(i as f32 / total as f32, Some(candidate[0].clone()));
+                let mut by_content: BTreeMap<String, Vec<String>> = BTreeMap::new();
+                for folder in candidate {
+                    // Unreadable copies (offline drive) can't be confirmed
+                    if let Ok(fp) = content_fingerprint(&db, folder, &folders[*folder]) {
+                        by_content.entry(fp).or_default().push((*folder).clone());
+                    }
+                }
+                for (fp, mut members) in by_content {
+                    if members.len() < 2 {
+                        continue;
+                    }
+                    members.sort();
+                    let contents = &folders[&members[0]];
+                    groups.push(DuplicateFolderGroup {
+                        fingerprint: fp,
+                        file_count: contents.len(),
+                        total_bytes: contents.iter().map(|c| c.1).sum(),
+                        folders: members,
+                    });
+                }
+            }
+
+            // A group whose folders all sit inside the folders of one other
+            // group is just part of that bigger duplicate
+            let group_of: HashMap<&str, usize> = groups
+                .iter()
+                .enumerate()
+                .flat_map(|(i, g)| g.folders.iter().map(move |f| (f.as_str(), i)))
+                .collect();
+            let nested: HashSet<usize> = (0..groups.len())
+                .filter(|&i| {
+                    let parents: HashSet<Option<usize>> = groups[i]
+                        .folders
+                        .iter()
+                        .map(|f| Path::new(f).parent().and_then(|p| group_of.get(paths::display(p).as_str()).copied()))
+                        .collect();
+                    parents.len() == 1 && parents.iter().next().copied().flatten().is_some()
+                })
+                .collect();
+            let mut outermost: Vec<DuplicateFolderGroup> = groups
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !nested.contains(i))
+                .map(|(_, g)| g.clone())
+                .collect();
+            outermost.sort_by(|a, b| (b.total_bytes * (b.folders.len() as i64 - 1)).cmp(&(a.total_bytes * (a.folders.len() as i64 - 1))));
+            Ok(outermost)
+        })
+        .await
+}
+
+// Files on disk the index doesn't know about (project files, stems), relative
+// to `folder`. The fingerprint can't vouch for them, so they're carried into
+// the kept folder rather than removed with their copy.
+fn unindexed_files(dir: &Path, folder: &Path, known: &HashSet<String>, found: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            unindexed_files(&path, folder, known, found)?;
+        } else if let Ok(relative) = path.strip_prefix(folder) {
+            let relative = paths::display(relative);
+            if !known.contains(&relative) {
+                found.push(relative);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn link_folder(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn link_folder(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+// Puts a link to `target` where `folder` was. The link is made beside it
+// first and the folder only deleted once the link has taken its place, so a
+// failure part way leaves the copy where it was.
+fn replace_with_link(target: &Path, folder: &Path) -> Result<(), String> {
+    let name = paths::file_name(folder);
+    let link = folder.with_file_name(format!(".{}.link", name));
+    let old = folder.with_file_name(format!(".{}.removing", name));
+    link_folder(target, &link).map_err(|e| format!("Failed to link {}: {}", folder.display(), e))?;
+    if let Err(e) = fs::rename(folder, &old) {
+        let _ = fs::remove_file(&link).or_else(|_| fs::remove_dir(&link));
+        return Err(format!("Failed to move {} aside: {}", folder.display(), e));
+    }
+    if let Err(e) = fs::rename(&link, folder) {
+        let _ = fs::rename(&old, folder);
+        let _ = fs::remove_file(&link).or_else(|_| fs::remove_dir(&link));
+        return Err(format!("Failed to link {}: {}", folder.display(), e));
+    }
+    fs::remove_dir_all(&old).map_err(|e| format!("Linked {}, but failed to remove the old copy at {}: {}", folder.display(), old.display(), e))
+}
+
+// Keeps `keep` and gets rid of the other copies: "delete" removes them,
+// "link" replaces each with a link to the kept folder so projects that
+// reference the old location still open. Every copy is re-checked against
+// the kept one first, and tags from the removed copies are carried over.
+// Files the library doesn't index (project files, stems) are moved into the
+// kept folder at the same place, so nothing outside the duplicate is lost.
+#[tauri::command]
+pub async fn resolve_duplicate_folders(
+    db: State<'_, Db>,
+    keep: String,
+    remove: Vec<String>,
+    action: String,
+) -> Result<FolderCleanup, String> {
+    if action != "delete" && action != "link" {
+        return Err(format!("Unknown cleanup action: {}", action));
+    }
+    if remove.iter().any(|r| r == &keep || Path::new(&keep).starts_with(r) || Path::new(r).starts_with(&keep)) {
+        return Err("The kept folder can't be inside or contain a removed one".to_string());
+    }
+    // Refused before anything changes if any removed folder is read-only
+    roots::ensure_writable(&db.lock(), &remove.iter().map(String::as_str).collect::<Vec<_>>())?;
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let files = indexed_files(&db, Some(&keep))?;
+        let kept_contents = folder_contents(&files, Some(Path::new(&keep))).remove(&keep).unwrap_or_default();
+        if kept_contents.is_empty() {
+            return Err(format!("No indexed files in {}", keep));
+        }
+        let kept_fp = content_fingerprint(&db, &keep, &kept_contents)?;
+
+        let mut carry = Vec::new();
+        let mut carried = Vec::new();
+        let mut claimed = HashSet::new();
+        for folder in &remove {
+            let files = indexed_files(&db, Some(folder))?;
+            let contents = folder_contents(&files, Some(Path::new(folder))).remove(folder).unwrap_or_default();
+            if content_fingerprint(&db, folder, &contents)? != kept_fp {
+                return Err(format!("{} no longer matches {}; rescan before cleaning up", folder, keep));
+            }
+            let known: HashSet<String> = contents.into_iter().map(|c| c.0).collect();
+            let mut extra = Vec::new();
+            let fs_folder = paths::to_fs(folder);
+            unindexed_files(&fs_folder, &fs_folder, &known, &mut extra).map_err(|e| e.to_string())?;
+            for relative in extra {
+                let to = paths::display(&Path::new(&keep).join(&relative));
+                // Two different files can't both land in the same place
+                if paths::to_fs(&to).exists() || !claimed.insert(to.clone()) {
+                    return Err(format!("{} has {} that would clash with {}; move it first", folder, relative, to));
+                }
+                carry.push(Operation::Move { from: paths::display(&Path::new(folder).join(&relative)), to: to.clone() });
+                carried.push(to);
+            }
+        }
+        if !carry.is_empty() {
+            roots::ensure_writable(&db.lock(), &[&keep])?;
+        }
+
+        let mut ops = Vec::new();
+        {
+            let conn = db.lock();
+            for (relative, _) in &kept_contents {
+                let kept_path = paths::display(&Path::new(&keep).join(relative));
+                let before = library::tags_for(&conn, &kept_path)?;
+                let mut after = before.clone();
+                for folder in &remove {
+                    after.extend(library::tags_for(&conn, &paths::display(&Path::new(folder).join(relative)))?);
+                }
+                after.sort();
+                after.dedup();
+                if after != before {
+                    ops.push(Operation::SetTags { path: kept_path, before, after });
+                }
+            }
+        }
+        let tags_merged = ops.len();
+        journal::run(&db.lock(), &format!("Merge tags into {}", keep), ops)?;
+        journal::run(&db.lock(), &format!("Carry unindexed files into {}", keep), carry)?;
+
+        let freed_bytes: i64 = kept_contents.iter().map(|c| c.1).sum::<i64>() * remove.len() as i64;
+        let mut removed = Vec::new();
+        for folder in &remove {
+            let fs_path = paths::to_fs(folder);
+            if action == "link" {
+                replace_with_link(&paths::to_fs(&keep), &fs_path)?;
+            } else {
+                fs::remove_dir_all(&fs_path).map_err(|e| format!("Failed to remove {}: {}", folder, e))?;
+            }
+            library::remove_path(&db.lock(), folder)?;
+            removed.push(folder.clone());
+        }
+        Ok(FolderCleanup { removed, carried, freed_bytes, tags_merged })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,181 @@
+use crate::db::{self, Db};
+use crate::jobs::Scheduler;
+use crate::paths;
+use keyring::Entry;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+// Secrets live in the OS keychain (Keychain, Credential Manager, Secret
+// Service) under this service; database passphrases have one entry per path
+pub const KEYCHAIN_SERVICE: &str = "music-organizer-assistant";
+const MIN_PASSPHRASE_LEN: usize = 8;
+// First bytes of every unencrypted SQLite file; SQLCipher's are random
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+#[derive(serde::Serialize)]
+pub struct EncryptionStatus {
+    encrypted: bool,
+    // Without a keychain there's nowhere to keep the passphrase
+    keychain_available: bool,
+    // Pre-upgrade and pre-restore snapshots beside the database written
+    // before it was encrypted, for the user to delete if they want
+    unencrypted_copies: Vec<String>,
+}
+
+fn entry(database: &Path) -> keyring::Result<Entry> {
+    Entry::new(KEYCHAIN_SERVICE, &paths::display(database))
+}
+
+// No keychain at all (a Linux box without a secret service) reads as no
+// passphrase, so unencrypted libraries open as they always have
+pub fn stored_key(database: &Path) -> Option<String> {
+    entry(database).ok()?.get_password().ok()
+}
+
+// Saves the passphrase for `database`, or forgets it with None
+pub fn store_key(database: &Path, key: Option<&str>) -> Result<(), String> {
+    let entry = entry(database).map_err(|e| format!("The OS keychain isn't available: {}", e))?;
+    match key {
+        Some(key) => entry
+            .set_password(key)
+            .map_err(|e| format!("Failed to save the passphrase to the keychain: {}", e)),
+        None => match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Failed to remove the passphrase from the keychain: {}", e)),
+        },
+    }
+}
+
+fn keychain_available(database: &Path) -> bool {
+    entry(database).is_ok_and(|entry| matches!(entry.get_password(), Ok(_) | Err(keyring::Error::NoEntry)))
+}
+
+// Explains a database that SQLite can't read because of its key
+pub fn open_error(database: &Path, e: rusqlite::Error) -> String {
+    if e.sqlite_error_code() != Some(rusqlite::ErrorCode::NotADatabase) {
+        return e.to_string();
+    }
+    match stored_key(database) {
+        Some(_) => format!("The passphrase in the keychain doesn't unlock {}", paths::display(database)),
+        None => format!(
+            "{} is encrypted, and this machine's keychain doesn't have its passphrase",
+            paths::display(database)
+        ),
+    }
+}
+
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    match fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(()) => header != SQLITE_HEADER,
+        // Too short to be either; an empty file is a library not yet written
+        Err(_) => false,
+    }
+}
+
+// The .bak snapshots next to the database that are still readable without
+// a passphrase
+fn unencrypted_copies(database: &Path) -> Vec<PathBuf> {
+    let Some(dir) = database.parent() else { return Vec::new() };
+    let prefix = format!("{}.v", paths::file_name(database));
+    let mut copies: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    let name = paths::file_name(path);
+                    name.starts_with(&prefix) && name.ends_with(".bak") && !is_encrypted(path)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    copies.sort();
+    copies
+}
+
+fn status(db: &Db) -> EncryptionStatus {
+    let path = db.path();
+    let encrypted = is_encrypted(&path);
+    EncryptionStatus {
+        encrypted,
+        keychain_available: keychain_available(&path),
+        unencrypted_copies: if encrypted {
+            unencrypted_copies(&path).iter().map(|p| paths::display(p)).collect()
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!("The passphrase needs at least {} characters", MIN_PASSPHRASE_LEN));
+    }
+    Ok(())
+}
+
+// Asking for the current passphrase keeps someone at an unlocked machine
+// from quietly changing or removing it
+fn check_current(db: &Db, current: &str) -> Result<(), String> {
+    if !is_encrypted(&db.path()) {
+        return Err("The library isn't encrypted".to_string());
+    }
+    if stored_key(&db.path()).as_deref() != Some(current) {
+        return Err("That isn't the library's passphrase".to_string());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_database_encryption(db: State<'_, Db>) -> Result<EncryptionStatus, String> {
+    Ok(status(&db))
+}
+
+// Encrypts the open library, AI conversations included, and saves the
+// passphrase to the OS keychain so it opens without asking on this machine.
+// Another machine needs the passphrase in its own keychain.
+#[tauri::command]
+pub async fn encrypt_database(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    passphrase: String,
+) -> Result<EncryptionStatus, String> {
+    check_passphrase(&passphrase)?;
+    if is_encrypted(&db.path()) {
+        return Err("The library is already encrypted".to_string());
+    }
+    if !keychain_available(&db.path()) {
+        return Err("There's no OS keychain to keep the passphrase in".to_string());
+    }
+    db::exclusive(&scheduler, || db.set_encryption(Some(&passphrase)))?;
+    Ok(status(&db))
+}
+
+#[tauri::command]
+pub async fn change_database_passphrase(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    current: String,
+    passphrase: String,
+) -> Result<EncryptionStatus, String> {
+    check_current(&db, &current)?;
+    check_passphrase(&passphrase)?;
+    db::exclusive(&scheduler, || db.set_encryption(Some(&passphrase)))?;
+    Ok(status(&db))
+}
+
+// Rewrites the library unencrypted and removes its passphrase from the
+// keychain
+#[tauri::command]
+pub async fn decrypt_database(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    current: String,
+) -> Result<EncryptionStatus, String> {
+    check_current(&db, &current)?;
+    db::exclusive(&scheduler, || db.set_encryption(None))?;
+    Ok(status(&db))
+}
@@ -0,0 +1,146 @@
+use crate::db::Db;
+use crate::jobs::{Priority, Scheduler};
+use crate::library::{self, LibraryQuery};
+use crate::paths;
+use crate::roots;
+use crate::sandbox::Sandbox;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use tauri::State;
+
+#[derive(serde::Serialize)]
+struct ExportRow {
+    path: String,
+    name: String,
+    file_type: String,
+    size: i64,
+    duration: Option<f64>,
+    bpm: Option<String>,
+    key: Option<String>,
+    tags: Vec<String>,
+    rating: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ExportResult {
+    path: String,
+    rows: usize,
+}
+
+// A value the user settled in the conflicts panel, if any
+fn resolved(conn: &Connection, path: &str, attribute: &str) -> Result<Option<String>, String> {
+    conn.query_row(
+        "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = ?2",
+        params![path, attribute],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+// Only hashes already on record are used; exporting shouldn't read every
+// file on disk just to find its analysis.
+fn cached_analysis(conn: &Connection, path: &str) -> Result<Option<(f64, Option<f64>, Option<String>)>, String> {
+    conn.query_row(
+        "SELECT a.duration, a.bpm, a.key FROM file_hashes h
+         JOIN analysis_cache a ON a.hash = h.hash
+         WHERE h.path = ?1",
+        params![path],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_csv(rows: &[ExportRow]) -> String {
+    let mut out = String::from("path,name,type,size,duration,bpm,key,tags,rating\r\n");
+    for row in rows {
+        let fields = [
+            row.path.clone(),
+            row.name.clone(),
+            row.file_type.clone(),
+            row.size.to_string(),
+            row.duration.map(|d| format!("{:.3}", d)).unwrap_or_default(),
+            row.bpm.clone().unwrap_or_default(),
+            row.key.clone().unwrap_or_default(),
+            row.tags.join("; "),
+            row.rating.clone().unwrap_or_default(),
+        ];
+        let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&line.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// A full-library export reads every file's rows and can take a while, so it
+// runs as a job. The output has to land inside the library's folders and
+// not on a read-only root.
+#[tauri::command]
+pub async fn export_metadata(
+    sandbox: State<'_, Sandbox>,
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    query: LibraryQuery,
+    format: String,
+    path: String,
+) -> Result<ExportResult, String> {
+    if format != "csv" && format != "json" {
+        return Err(format!("Unknown export format: {}", format));
+    }
+    let output = sandbox.check_dest(&paths::to_fs(&path))?;
+    roots::ensure_writable(&db.lock(), &[&paths::display(&output)])?;
+    let db = db.inner().clone();
+
+    scheduler
+        .run("export", "Export metadata", Priority::Interactive, move |_| {
+            let rows = {
+                let conn = db.lock();
+                let mut rows = Vec::new();
+                for entry in library::find_files(&conn, &query, -1)? {
+                    let analysis = cached_analysis(&conn, &entry.path)?;
+                    let bpm = resolved(&conn, &entry.path, "bpm")?
+                        .or_else(|| analysis.as_ref().and_then(|a| a.1).map(|b| b.to_string()));
+                    let key = resolved(&conn, &entry.path, "key")?.or_else(|| analysis.as_ref().and_then(|a| a.2.clone()));
+                    rows.push(ExportRow {
+                        tags: library::tags_for(&conn, &entry.path)?,
+                        rating: library::metadata_value(&conn, &entry.path, "rating")?,
+                        duration: analysis.map(|a| a.0),
+                        bpm,
+                        key,
+                        path: entry.path,
+                        name: entry.name,
+                        file_type: entry.file_type,
+                        size: entry.size,
+                    });
+                }
+                rows
+            };
+
+            let bytes = if format == "csv" {
+                // Leading BOM so Excel opens the CSV as UTF-8
+                [&[0xEF, 0xBB, 0xBF][..], to_csv(&rows).as_bytes()].concat()
+            } else {
+                serde_json::to_string_pretty(&rows).map_err(|e| e.to_string())?.into_bytes()
+            };
+
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fs::write(&output, bytes).map_err(|e| e.to_string())?;
+
+            Ok(ExportResult {
+                path: paths::display(&output),
+                rows: rows.len(),
+            })
+        })
+        .await
+}
@@ -0,0 +1,320 @@
+use crate::audio::{self, AudioData};
+use crate::db::Db;
+use crate::derived;
+use crate::dsp::{self, Biquad};
+use crate::jobs::{Priority, Scheduler};
+use crate::paths;
+use crate::quarantine;
+use tauri::State;
+
+const SOURCE: &str = "fx";
+// Lookahead lets the limiter turn down before a peak arrives instead of
+// clipping its leading edge
+const LIMITER_LOOKAHEAD_MS: f32 = 5.0;
+// Longest reverb tail kept after the dry signal ends
+const MAX_TAIL_SECONDS: f64 = 10.0;
+
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BandKind {
+    Peak,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct EqBand {
+    kind: BandKind,
+    freq: f64,
+    #[serde(default)]
+    gain_db: f64,
+    #[serde(default = "default_q")]
+    q: f64,
+}
+
+fn default_q() -> f64 {
+    0.707
+}
+
+// One stage of a chain. Stored and passed as JSON, e.g.
+// {"type": "compressor", "threshold_db": -18, "ratio": 4}
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Effect {
+    Eq {
+        bands: Vec<EqBand>,
+    },
+    Compressor {
+        threshold_db: f32,
+        ratio: f32,
+        #[serde(default = "default_attack")]
+        attack_ms: f32,
+        #[serde(default = "default_release")]
+        release_ms: f32,
+        #[serde(default)]
+        makeup_db: f32,
+    },
+    // Convolution with an impulse response file; `mix` is the wet share
+    Reverb {
+        ir: String,
+        mix: f32,
+        #[serde(default)]
+        pre_delay_ms: f32,
+    },
+    Limiter {
+        ceiling_db: f32,
+        #[serde(default = "default_release")]
+        release_ms: f32,
+    },
+    Gain {
+        db: f32,
+    },
+}
+
+fn default_attack() -> f32 {
+    10.0
+}
+
+fn default_release() -> f32 {
+    100.0
+}
+
+#[derive(serde::Serialize)]
+pub struct RenderedFile {
+    source: String,
+    // None when this file failed; see `error`
+    path: Option<String>,
+    error: Option<String>,
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+// One-pole smoothing coefficient for a time constant
+fn coefficient(ms: f32, sample_rate: u32) -> f32 {
+    (-1.0 / (ms.max(0.01) / 1000.0 * sample_rate as f32)).exp()
+}
+
+fn deinterleave(audio: &AudioData) -> Vec<Vec<f32>> {
+    let channels = audio.channels.max(1);
+    (0..channels)
+        .map(|c| audio.samples.iter().skip(c).step_by(channels).copied().collect())
+        .collect()
+}
+
+fn interleave(channels: &[Vec<f32>]) -> Vec<f32> {
+    let frames = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    let mut samples = Vec::with_capacity(frames * channels.len());
+    for i in 0..frames {
+        for channel in channels {
+            samples.push(channel[i]);
+        }
+    }
+    samples
+}
+
+fn band_filter(band: &EqBand, sample_rate: u32) -> Biquad {
+    // Keep filters below Nyquist whatever the chain asks for
+    let freq = band.freq.clamp(10.0, sample_rate as f64 * 0.45);
+    match band.kind {
+        BandKind::Peak => Biquad::peaking(sample_rate, freq, band.q, band.gain_db),
+        BandKind::LowShelf => Biquad::low_shelf(sample_rate, freq, band.q, band.gain_db),
+        BandKind::HighShelf => Biquad::high_shelf(sample_rate, freq, band.q, band.gain_db),
+        BandKind::LowPass => Biquad::low_pass(sample_rate, freq, band.q),
+        BandKind::HighPass => Biquad::high_pass(sample_rate, freq, band.q),
+    }
+}
+
+fn eq(channels: &mut [Vec<f32>], bands: &[EqBand], sample_rate: u32) {
+    for channel in channels {
+        let mut filters: Vec<Biquad> = bands.iter().map(|b| band_filter(b, sample_rate)).collect();
+        for sample in channel.iter_mut() {
+            *sample = filters.iter_mut().fold(*sample, |x, f| f.process(x));
+        }
+    }
+}
+
+// Feed-forward, stereo-linked: every channel gets the same gain, taken
+// from the loudest, so the image doesn't wander
+fn compress(channels: &mut [Vec<f32>], sample_rate: u32, threshold_db: f32, ratio: f32, attack_ms: f32, release_ms: f32, makeup_db: f32) {
+    let frames = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    let (attack, release) = (coefficient(attack_ms, sample_rate), coefficient(release_ms, sample_rate));
+    let ratio = ratio.max(1.0);
+    let makeup = db_to_gain(makeup_db);
+    let mut reduction_db = 0.0f32;
+    for i in 0..frames {
+        let level = channels.iter().fold(0.0f32, |m, c| m.max(c[i].abs()));
+        let level_db = 20.0 * level.max(1e-6).log10();
+        let over = (level_db - threshold_db).max(0.0);
+        let target = over - over / ratio;
+        let coeff = if target > reduction_db { attack } else { release };
+        reduction_db = coeff * reduction_db + (1.0 - coeff) * target;
+        let gain = db_to_gain(-reduction_db) * makeup;
+        for channel in channels.iter_mut() {
+            channel[i] *= gain;
+        }
+    }
+}
+
+// Lookahead brickwall: the gain needed for the loudest sample in the next
+// few milliseconds is applied straight away, then recovers at `release_ms`
+fn limit(channels: &mut [Vec<f32>], sample_rate: u32, ceiling_db: f32, release_ms: f32) {
+    let frames = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    let ceiling = db_to_gain(ceiling_db.min(0.0));
+    let lookahead = (LIMITER_LOOKAHEAD_MS / 1000.0 * sample_rate as f32) as usize;
+    let release = coefficient(release_ms, sample_rate);
+    let needed: Vec<f32> = (0..frames)
+        .map(|i| {
+            let peak = channels.iter().fold(0.0f32, |m, c| m.max(c[i].abs()));
+            if peak > ceiling {
+                ceiling / peak
+            } else {
+                1.0
+            }
+        })
+        .collect();
+    let mut gain = 1.0f32;
+    for i in 0..frames {
+        let target = needed[i..(i + lookahead + 1).min(frames)].iter().fold(1.0f32, |m, g| m.min(*g));
+        gain = if target < gain { target } else { release * gain + (1.0 - release) * target };
+        for channel in channels.iter_mut() {
+            channel[i] = (channel[i] * gain).clamp(-ceiling, ceiling);
+        }
+    }
+}
+
+// Loads an IR at `sample_rate`, scaled to unit energy so the wet signal
+// comes out about as loud as the dry
+pub fn load_ir(path: &str, sample_rate: u32) -> Result<Vec<Vec<f32>>, String> {
+    let decoded = audio::decode_file(&paths::to_fs(path), None)?;
+    let mut channels: Vec<Vec<f32>> = deinterleave(&decoded)
+        .iter()
+        .map(|c| dsp::resample(c, decoded.sample_rate, sample_rate))
+        .collect();
+    let energy: f32 = channels.iter().map(|c| c.iter().map(|s| s * s).sum::<f32>()).sum::<f32>() / channels.len().max(1) as f32;
+    if energy <= 0.0 {
+        return Err(format!("Impulse response {} is silent", path));
+    }
+    let scale = 1.0 / energy.sqrt();
+    for channel in &mut channels {
+        channel.iter_mut().for_each(|s| *s *= scale);
+    }
+    Ok(channels)
+}
+
+// Dry/wet convolution; each channel uses the matching IR channel (a mono
+// IR serves them all). The result grows by the reverb tail.
+pub fn reverb(channels: &mut [Vec<f32>], ir: &[Vec<f32>], mix: f32, pre_delay: usize) {
+    let mix = mix.clamp(0.0, 1.0);
+    for (c, channel) in channels.iter_mut().enumerate() {
+        let response = &ir[c.min(ir.len() - 1)];
+        let wet = dsp::convolve(channel, response);
+        let length = wet.len() + pre_delay;
+        let mut out: Vec<f32> = channel.iter().map(|s| s * (1.0 - mix)).collect();
+        out.resize(length, 0.0);
+        for (slot, value) in out[pre_delay..].iter_mut().zip(&wet) {
+            *slot += value * mix;
+        }
+        *channel = out;
+    }
+}
+
+// A file heard through an IR, for auditioning spaces. A mono sample
+// through a stereo IR comes out stereo, as it would in the room.
+pub fn with_ir(audio: &AudioData, ir: &[Vec<f32>], mix: f32) -> AudioData {
+    let mut channels = deinterleave(audio);
+    if channels.len() == 1 && ir.len() > 1 {
+        channels = vec![channels[0].clone(); ir.len()];
+    }
+    reverb(&mut channels, ir, mix, 0);
+    AudioData { sample_rate: audio.sample_rate, channels: channels.len(), samples: interleave(&channels) }
+}
+
+pub fn apply_chain(audio: &AudioData, chain: &[Effect]) -> Result<AudioData, String> {
+    let sample_rate = audio.sample_rate;
+    let mut channels = deinterleave(audio);
+    let dry_frames = channels.first().map(|c| c.len()).unwrap_or(0);
+    for effect in chain {
+        match effect {
+            Effect::Eq { bands } => eq(&mut channels, bands, sample_rate),
+            Effect::Compressor { threshold_db, ratio, attack_ms, release_ms, makeup_db } => {
+                compress(&mut channels, sample_rate, *threshold_db, *ratio, *attack_ms, *release_ms, *makeup_db)
+            }
+            Effect::Reverb { ir, mix, pre_delay_ms } => {
+                let response = load_ir(ir, sample_rate)?;
+                let pre_delay = (pre_delay_ms.max(0.0) / 1000.0 * sample_rate as f32) as usize;
+                reverb(&mut channels, &response, *mix, pre_delay);
+            }
+            Effect::Limiter { ceiling_db, release_ms } => limit(&mut channels, sample_rate, *ceiling_db, *release_ms),
+            Effect::Gain { db } => {
+                let gain = db_to_gain(*db);
+                channels.iter_mut().flatten().for_each(|s| *s *= gain);
+            }
+        }
+    }
+    // Trim the reverb tail where it has died away, and never past the cap
+    let cap = dry_frames + (MAX_TAIL_SECONDS * sample_rate as f64) as usize;
+    let audible = channels
+        .iter()
+        .map(|c| c.iter().rposition(|s| s.abs() > 1e-4).map(|i| i + 1).unwrap_or(0))
+        .max()
+        .unwrap_or(0);
+    let frames = audible.max(dry_frames).min(cap);
+    for channel in &mut channels {
+        channel.truncate(frames);
+    }
+    Ok(AudioData { sample_rate, channels: audio.channels.max(1), samples: interleave(&channels) })
+}
+
+fn render_one(db: &Db, path: &str, chain: &[Effect], suffix: &str, dest_dir: Option<&str>) -> Result<String, String> {
+    let decoded = quarantine::guard(db, path, || audio::decode_file(&paths::to_fs(path), None))?;
+    let rendered = apply_chain(&decoded, chain)?;
+    let attributes = derived::carried_attributes(db, path)?;
+    derived::write(db, path, dest_dir, suffix, &rendered, SOURCE, &attributes)
+}
+
+// Runs `chain` (EQ, compressor, convolution reverb, limiter, gain, in the
+// order given) over each file and writes "<name> <suffix>.wav", default
+// suffix "fx". The chain is checked before anything is rendered.
+#[tauri::command]
+pub async fn render_fx(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    paths: Vec<String>,
+    chain: Vec<Effect>,
+    suffix: Option<String>,
+    dest_dir: Option<String>,
+) -> Result<Vec<RenderedFile>, String> {
+    if chain.is_empty() {
+        return Err("The effect chain is empty".to_string());
+    }
+    for effect in &chain {
+        if let Effect::Reverb { ir, .. } = effect {
+            if !paths::to_fs(ir).exists() {
+                return Err(format!("Impulse response not found: {}", ir));
+            }
+        }
+    }
+    let suffix = suffix.unwrap_or_else(|| "fx".to_string());
+    let db = db.inner().clone();
+    let label = format!("Render effects on {} files", paths.len());
+    scheduler
+        .run("conversion", label, Priority::Normal, move |ctx| {
+            let mut results = Vec::new();
+            for (i, path) in paths.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    return Err("Cancelled".to_string());
+                }
+                ctx.progress(i as f32 / paths.len() as f32, Some(path.clone()));
+                results.push(match render_one(&db, path, &chain, &suffix, dest_dir.as_deref()) {
+                    Ok(written) => RenderedFile { source: path.clone(), path: Some(written), error: None },
+                    Err(e) => RenderedFile { source: path.clone(), path: None, error: Some(e) },
+                });
+            }
+            Ok(results)
+        })
+        .await
+}
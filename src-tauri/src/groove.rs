@@ -0,0 +1,250 @@
+use crate::analysis;
+use crate::audio;
+use crate::chop;
+use crate::db::Db;
+use crate::derived;
+use crate::jobs::{Priority, Scheduler};
+use crate::loopinfo;
+use crate::midi;
+use crate::paths;
+use midly::num::u7;
+use midly::{MidiMessage, Smf, TrackEventKind};
+use rusqlite::{params, OptionalExtension};
+use std::path::Path;
+use tauri::State;
+
+const SOURCE: &str = "groove";
+// 16ths
+const DEFAULT_GRID: f64 = 0.25;
+// One bar of 4/4
+const DEFAULT_BEATS: f64 = 4.0;
+const MAX_STEPS: usize = 256;
+
+// The feel of a reference loop, step by step over one cycle of the grid:
+// how late or early its hits land and how hard they are
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Groove {
+    source: String,
+    // Step length in beats
+    grid: f64,
+    // Beats off the straight grid; positive is late (swung)
+    offsets: Vec<f64>,
+    // Against the loop's average hit, so 1.2 is an accent
+    velocities: Vec<f32>,
+    // Hits heard on each step; steps with none stay straight
+    hits: Vec<u32>,
+}
+
+struct Hit {
+    beat: f64,
+    strength: f32,
+}
+
+fn build(source: &str, hits: &[Hit], grid: f64, steps: usize) -> Result<Groove, String> {
+    if hits.is_empty() {
+        return Err(format!("No hits found in {}", source));
+    }
+    let mut offsets = vec![0.0; steps];
+    let mut strengths = vec![0.0f32; steps];
+    let mut counts = vec![0u32; steps];
+    for hit in hits {
+        let step = (hit.beat / grid).round();
+        let slot = (step as i64).rem_euclid(steps as i64) as usize;
+        offsets[slot] += hit.beat - step * grid;
+        strengths[slot] += hit.strength;
+        counts[slot] += 1;
+    }
+    let average = hits.iter().map(|h| h.strength).sum::<f32>() / hits.len() as f32;
+    let velocities = strengths
+        .iter()
+        .zip(&counts)
+        .map(|(total, count)| if *count == 0 || average <= 0.0 { 1.0 } else { total / *count as f32 / average })
+        .collect();
+    for (offset, count) in offsets.iter_mut().zip(&counts) {
+        *offset /= (*count).max(1) as f64;
+    }
+    Ok(Groove { source: source.to_string(), grid, offsets, velocities, hits: counts })
+}
+
+fn resolved_bpm(db: &Db, path: &str) -> Option<f64> {
+    db.lock()
+        .query_row(
+            "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = 'bpm'",
+            params![path],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .ok()
+        .flatten()
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|bpm| *bpm > 0.0)
+}
+
+fn midi_hits(path: &Path) -> Result<Vec<Hit>, String> {
+    let file = midi::read(path)?;
+    Ok(file.notes().iter().map(|n| Hit { beat: n.start, strength: n.velocity as f32 }).collect())
+}
+
+// Audio is read from its transients, placed in beats by the loop's tempo;
+// the loop is taken to start on the beat
+fn audio_hits(db: &Db, path: &str, bpm: Option<f64>) -> Result<Vec<Hit>, String> {
+    let fs_path = paths::to_fs(path);
+    let bpm = bpm
+        .filter(|b| *b > 0.0)
+        .or_else(|| resolved_bpm(db, path))
+        .or_else(|| loopinfo::read(&fs_path).and_then(|info| info.bpm))
+        .or_else(|| analysis::analyze_path(&fs_path).ok().and_then(|a| a.bpm))
+        .ok_or_else(|| "No tempo found for this loop; pass a BPM".to_string())?;
+    let decoded = audio::decode_file(&fs_path, None)?;
+    let mono = decoded.to_mono();
+    Ok(chop::transients(&mono, decoded.sample_rate, 0.5, 30)
+        .into_iter()
+        .map(|(time, strength)| Hit { beat: time * bpm / 60.0, strength })
+        .collect())
+}
+
+fn is_midi(path: &str) -> bool {
+    let lower = path.to_lowercase();
+    lower.ends_with(".mid") || lower.ends_with(".midi")
+}
+
+// Reads the timing and accents of a MIDI file or audio loop into a groove
+// `beats` long (one 4/4 bar by default) on a `grid` of beats (0.25 for 16ths)
+#[tauri::command]
+pub async fn extract_groove(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    grid: Option<f64>,
+    beats: Option<f64>,
+    bpm: Option<f64>,
+) -> Result<Groove, String> {
+    let grid = grid.unwrap_or(DEFAULT_GRID);
+    let beats = beats.unwrap_or(DEFAULT_BEATS);
+    if grid.is_nan() || grid <= 0.0 || beats.is_nan() || beats < grid {
+        return Err("The grid must be positive and no longer than the groove".to_string());
+    }
+    let steps = (beats / grid).round() as usize;
+    if steps > MAX_STEPS {
+        return Err(format!("A groove has at most {} steps", MAX_STEPS));
+    }
+    let db = db.inner().clone();
+    let label = format!("Extract groove {}", path);
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |_| {
+            let hits = if is_midi(&path) { midi_hits(&paths::to_fs(&path))? } else { audio_hits(&db, &path, bpm)? };
+            build(&path, &hits, grid, steps)
+        })
+        .await
+}
+
+// How far to move a note starting at `beat`, and what to scale its velocity by
+fn shift(groove: &Groove, beat: f64, strength: f64) -> (f64, f32) {
+    let step = (beat / groove.grid).round();
+    let slot = (step as i64).rem_euclid(groove.offsets.len() as i64) as usize;
+    // Toward the groove's position for this step: a quantize with feel
+    let target = step * groove.grid + groove.offsets[slot];
+    let velocity = 1.0 + (groove.velocities[slot] - 1.0) * strength as f32;
+    ((target - beat) * strength, velocity)
+}
+
+// Writes a copy of a MIDI file with its notes pulled onto the groove's
+// timing and accents. `strength` 0-1 (default 1) is how far: at 1 every note
+// lands on the groove. Note lengths and everything that isn't a note are kept;
+// in MPE files each note's own bends and pressure move with it.
+#[tauri::command]
+pub async fn apply_groove(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    midi_path: String,
+    groove: Groove,
+    strength: Option<f64>,
+    expression: Option<midi::Expression>,
+    dest_dir: Option<String>,
+) -> Result<String, String> {
+    if groove.offsets.is_empty() || groove.offsets.len() != groove.velocities.len() || groove.grid.is_nan() || groove.grid <= 0.0 {
+        return Err("That groove is malformed".to_string());
+    }
+    let strength = strength.unwrap_or(1.0).clamp(0.0, 1.0);
+    let db = db.inner().clone();
+    let label = format!("Apply groove to {}", midi_path);
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |_| {
+            let bytes = std::fs::read(paths::to_fs(&midi_path)).map_err(|e| format!("Failed to read {}: {}", midi_path, e))?;
+            let smf = Smf::parse(&bytes).map_err(|e| format!("Failed to parse MIDI file: {}", e))?;
+            let ppq = midi::ticks_per_beat(&smf)?;
+            let sources: Vec<_> = smf.tracks.iter().map(|t| midi::absolute(t)).collect();
+            let members = midi::mpe_channels(&sources);
+            let mut tracks = Vec::with_capacity(sources.len());
+            for mut events in sources {
+                midi::apply_expression(&mut events, expression.unwrap_or_default());
+                // Shifts of sounding notes by channel and key, so each note-off
+                // moves with its note-on
+                let mut open: Vec<(u8, u8, u32, i64)> = Vec::new();
+                // Where member-channel notes sounded and how far they moved
+                let mut spans: Vec<(u8, u32, u32, i64)> = Vec::new();
+                for (tick, kind) in events.iter_mut() {
+                    let TrackEventKind::Midi { channel, message } = kind else { continue };
+                    let moved = match message {
+                        MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                            let (beats, scale) = shift(&groove, *tick as f64 / ppq, strength);
+                            let ticks = (beats * ppq).round() as i64;
+                            *vel = u7::new((vel.as_int() as f32 * scale).round().clamp(1.0, 127.0) as u8);
+                            open.push((channel.as_int(), key.as_int(), *tick, ticks));
+                            ticks
+                        }
+                        MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                            match open.iter().position(|o| o.0 == channel.as_int() && o.1 == key.as_int()) {
+                                Some(i) => {
+                                    let (channel, _, start, ticks) = open.remove(i);
+                                    if members.contains(&channel) {
+                                        spans.push((channel, start, *tick, ticks));
+                                    }
+                                    ticks
+                                }
+                                None => 0,
+                            }
+                        }
+                        _ => continue,
+                    };
+                    *tick = (*tick as i64 + moved).max(0) as u32;
+                }
+                // The bend set just before a member-channel note, since the
+                // previous note on its channel let go, belongs to that note
+                let mut leads: Vec<(usize, i64)> = Vec::new();
+                for (c, start, _, ticks) in &spans {
+                    let released = spans.iter().filter(|(other, _, end, _)| other == c && end <= start).map(|s| s.2).max();
+                    let lead = events.iter().rposition(|(tick, kind)| {
+                        tick < start
+                            && released.map_or(true, |r| *tick > r)
+                            && matches!(kind, TrackEventKind::Midi { channel, message: MidiMessage::PitchBend { .. } } if channel.as_int() == *c)
+                    });
+                    if let Some(i) = lead {
+                        leads.push((i, *ticks));
+                    }
+                }
+                // Per-note expression sent while a member-channel note sounds
+                // stays with the note
+                for (i, (tick, kind)) in events.iter_mut().enumerate() {
+                    if !midi::is_expression(kind) {
+                        continue;
+                    }
+                    if let Some((_, ticks)) = leads.iter().find(|(lead, _)| *lead == i) {
+                        *tick = (*tick as i64 + ticks).max(0) as u32;
+                        continue;
+                    }
+                    let TrackEventKind::Midi { channel, .. } = kind else { continue };
+                    let channel = channel.as_int();
+                    if let Some((.., ticks)) = spans.iter().find(|(c, start, end, _)| *c == channel && *start <= *tick && *tick <= *end) {
+                        *tick = (*tick as i64 + ticks).max(0) as u32;
+                    }
+                }
+                tracks.push(midi::to_track(events));
+            }
+            let edited = Smf { header: smf.header, tracks };
+            let save = |path: &Path| edited.save(path).map_err(|e| format!("Failed to write MIDI file: {}", e));
+            let attributes = derived::carried_attributes(&db, &midi_path)?;
+            derived::write_midi(&db, &midi_path, dest_dir.as_deref(), "groove", save, SOURCE, &attributes)
+        })
+        .await
+}
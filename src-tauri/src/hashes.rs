@@ -0,0 +1,59 @@
+use crate::db::{self, Db};
+use crate::paths;
+use rusqlite::{params, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+fn hash_file(path: &Path) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// SHA-256 of the file contents. Hashing a long recording takes a while, so
+// the result is remembered against size and mtime and only redone when the
+// file changes.
+pub fn content_hash(db: &Db, path: &str) -> Result<String, String> {
+    let fs_path = paths::to_fs(path);
+    let metadata = std::fs::metadata(&fs_path).map_err(|e| e.to_string())?;
+    let size = metadata.len() as i64;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let cached: Option<String> = db
+        .lock()
+        .query_row(
+            "SELECT hash FROM file_hashes WHERE path = ?1 AND size = ?2 AND modified = ?3",
+            params![path, size, modified],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(hash) = cached {
+        return Ok(hash);
+    }
+
+    let hash = hash_file(&fs_path)?;
+    db.lock()
+        .execute(
+            "INSERT OR REPLACE INTO file_hashes (path, size, modified, hash, hashed_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![path, size, modified, hash, db::now()],
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(hash)
+}
@@ -0,0 +1,278 @@
+use crate::bandwidth::{self, Limiter};
+use crate::db::Db;
+use crate::network_audit::{self, Outbound};
+use crate::proxy;
+use reqwest::{Body, Client, ClientBuilder, RequestBuilder, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::State;
+use tokio::sync::Semaphore;
+
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(20);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Consecutive failures before a provider's circuit opens, and how long it stays open
+const FAILURE_THRESHOLD: u32 = 5;
+const OPEN_DURATION: Duration = Duration::from_secs(30);
+// Shown in the status bar before anything has been sent
+const KNOWN_PROVIDERS: &[&str] = &["openai", "gemini"];
+
+fn default_concurrency(provider: &str) -> usize {
+    match provider {
+        "openai" | "gemini" => 4,
+        _ => 2,
+    }
+}
+
+#[derive(Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    last_error: Option<String>,
+    last_success_at: Option<i64>,
+}
+
+struct Provider {
+    permits: Arc<Semaphore>,
+    limit: usize,
+    breaker: Mutex<Breaker>,
+}
+
+impl Provider {
+    fn breaker(&self) -> MutexGuard<'_, Breaker> {
+        self.breaker.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record_success(&self) {
+        let mut breaker = self.breaker();
+        breaker.consecutive_failures = 0;
+        breaker.open_until = None;
+        breaker.last_success_at = Some(crate::db::now());
+    }
+
+    fn record_failure(&self, error: String) {
+        let mut breaker = self.breaker();
+        breaker.consecutive_failures += 1;
+        breaker.last_error = Some(error);
+        // A failed probe after the circuit reopens trips it again straight away
+        if breaker.consecutive_failures >= FAILURE_THRESHOLD {
+            breaker.open_until = Some(Instant::now() + OPEN_DURATION);
+        }
+    }
+
+    fn open_for(&self) -> Option<Duration> {
+        self.breaker()
+            .open_until
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct ProviderHealth {
+    provider: String,
+    // "healthy", "degraded" (recent failures) or "down" (circuit open)
+    status: &'static str,
+    consecutive_failures: u32,
+    in_flight: usize,
+    max_concurrent: usize,
+    last_error: Option<String>,
+    last_success_at: Option<i64>,
+    retry_in_seconds: Option<u64>,
+}
+
+// Shared client for every outbound AI call. Each provider gets its own
+// concurrency cap and circuit breaker so one flaky API doesn't stall the rest.
+pub struct Http {
+    // Rebuilt when the proxy settings change; requests already under way
+    // finish on the old one
+    client: Mutex<Client>,
+    // host:port of the configured proxy
+    proxy: Mutex<Option<String>>,
+    bandwidth: Arc<Limiter>,
+    providers: Mutex<HashMap<String, Arc<Provider>>>,
+    // Proxy settings come from here, and each request is logged here on its
+    // way out
+    db: Db,
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// Exponential backoff with up to 25% jitter so parallel retries spread out
+fn backoff(attempt: u32) -> Duration {
+    let base = BASE_BACKOFF.saturating_mul(1 << (attempt - 1).min(10)).min(MAX_BACKOFF);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    base + base.mul_f64((nanos % 1000) as f64 / 4000.0)
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+// Timeouts every client gets, the fallback one included
+fn builder() -> ClientBuilder {
+    Client::builder().timeout(REQUEST_TIMEOUT).connect_timeout(CONNECT_TIMEOUT)
+}
+
+impl Http {
+    pub fn new(db: Db) -> Self {
+        let http = Http {
+            client: Mutex::new(builder().build().unwrap_or_default()),
+            proxy: Mutex::new(None),
+            bandwidth: Arc::new(Limiter::default()),
+            providers: Mutex::new(HashMap::new()),
+            db,
+        };
+        // A missing certificate file shouldn't stop the app starting; the
+        // settings screen reports it when saved again
+        let _ = http.configure();
+        http
+    }
+
+    // Builds the client from the proxy and certificate settings, and sets
+    // the bandwidth caps
+    pub fn configure(&self) -> Result<(), String> {
+        let (settings, caps) = {
+            let conn = self.db.lock();
+            (proxy::load(&conn)?, bandwidth::load(&conn)?)
+        };
+        self.bandwidth.configure(&caps);
+        let client = proxy::apply(builder(), &settings, proxy::stored_password(&self.db.path()).as_deref())?
+            .build()
+            .map_err(|e| format!("Failed to set up the network client: {}", e))?;
+        *self.client.lock().unwrap_or_else(|e| e.into_inner()) = client;
+        *self.proxy.lock().unwrap_or_else(|e| e.into_inner()) = proxy::address(&settings);
+        Ok(())
+    }
+
+    // For bodies built before the request, like a multipart file part,
+    // which send can't pace once they're wrapped up in the form
+    pub fn throttled_body(&self, bytes: Vec<u8>, purpose: &str) -> Body {
+        self.bandwidth.body(bytes, purpose)
+    }
+
+    pub fn proxy_address(&self) -> Option<String> {
+        self.proxy.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn provider(&self, name: &str) -> Arc<Provider> {
+        let mut providers = self.providers.lock().unwrap_or_else(|e| e.into_inner());
+        providers
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                let limit = default_concurrency(name);
+                Arc::new(Provider {
+                    permits: Arc::new(Semaphore::new(limit)),
+                    limit,
+                    breaker: Mutex::new(Breaker::default()),
+                })
+            })
+            .clone()
+    }
+
+    // Sends a request built by `build`, retrying 429s, 5xx responses and
+    // network errors with backoff. `build` runs once per attempt because
+    // multipart bodies can't be cloned. Other error statuses come back as
+    // the response for the caller to report. Every attempt is written to the
+    // network audit log under `purpose`.
+    pub async fn send<F>(&self, provider: &str, purpose: &str, build: F) -> Result<Response, String>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let state = self.provider(provider);
+        if let Some(wait) = state.open_for() {
+            return Err(format!(
+                "{} is unavailable after repeated failures; retrying in {}s",
+                provider,
+                wait.as_secs() + 1
+            ));
+        }
+        let _permit = state.permits.acquire().await.map_err(|e| e.to_string())?;
+        let client = self.client.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let mut request = build(&client).build().map_err(|e| format!("{} request failed: {}", provider, e))?;
+            let outbound = Outbound::describe(provider, purpose, &request);
+            // Plain bodies are paced here; the explicit length keeps them
+            // from going out chunked
+            if let Some(bytes) = request.body().and_then(|body| body.as_bytes()).map(<[u8]>::to_vec) {
+                request.headers_mut().insert(reqwest::header::CONTENT_LENGTH, bytes.len().into());
+                *request.body_mut() = Some(self.bandwidth.body(bytes, purpose));
+            }
+            let started = Instant::now();
+            let result = client.execute(request).await;
+            network_audit::record(&self.db, &outbound, &result, started.elapsed());
+            let wait = match result {
+                Ok(response) if response.status().is_success() => {
+                    state.record_success();
+                    return Ok(response);
+                }
+                Ok(response) if is_retryable(response.status()) => {
+                    state.record_failure(format!("HTTP {}", response.status()));
+                    let wait = retry_after(&response).unwrap_or_else(|| backoff(attempt));
+                    if attempt >= MAX_ATTEMPTS || wait > MAX_BACKOFF || state.open_for().is_some() {
+                        return Ok(response);
+                    }
+                    wait
+                }
+                // Bad keys and malformed requests say nothing about provider health
+                Ok(response) => return Ok(response),
+                Err(e) if e.is_timeout() || e.is_connect() || e.is_request() => {
+                    state.record_failure(e.to_string());
+                    if attempt >= MAX_ATTEMPTS || state.open_for().is_some() {
+                        return Err(format!("{} request failed: {}", provider, e));
+                    }
+                    backoff(attempt)
+                }
+                Err(e) => return Err(format!("{} request failed: {}", provider, e)),
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    pub fn health(&self) -> Vec<ProviderHealth> {
+        for name in KNOWN_PROVIDERS {
+            self.provider(name);
+        }
+        let providers = self.providers.lock().unwrap_or_else(|e| e.into_inner());
+        let mut health: Vec<ProviderHealth> = providers
+            .iter()
+            .map(|(name, provider)| {
+                let open_for = provider.open_for();
+                let breaker = provider.breaker();
+                ProviderHealth {
+                    provider: name.clone(),
+                    status: match (open_for, breaker.consecutive_failures) {
+                        (Some(_), _) => "down",
+                        (None, 0) => "healthy",
+                        (None, _) => "degraded",
+                    },
+                    consecutive_failures: breaker.consecutive_failures,
+                    in_flight: provider.limit - provider.permits.available_permits(),
+                    max_concurrent: provider.limit,
+                    last_error: breaker.last_error.clone(),
+                    last_success_at: breaker.last_success_at,
+                    retry_in_seconds: open_for.map(|d| d.as_secs() + 1),
+                }
+            })
+            .collect();
+        health.sort_by(|a, b| a.provider.cmp(&b.provider));
+        health
+    }
+}
+
+#[tauri::command]
+pub async fn get_provider_health(http: State<'_, Http>) -> Result<Vec<ProviderHealth>, String> {
+    Ok(http.health())
+}
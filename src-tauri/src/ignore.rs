@@ -0,0 +1,138 @@
+use std::fs;
+use std::path::Path;
+
+// Read from the top of a library root, in the spirit of .gitignore
+pub const IGNORE_FILE: &str = ".aistudioignore";
+
+struct Rule {
+    // Split on '/', so "**" can stand for any number of folders
+    segments: Vec<String>,
+    negated: bool,
+    dir_only: bool,
+    // A pattern with a slash in it is matched from the root; one without
+    // matches a file or folder name at any depth
+    anchored: bool,
+}
+
+// Ignore rules for one library root. Later rules win, so "!Keep" after
+// "Backups" brings a folder back, as in git.
+#[derive(Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+// Glob match within one path segment: '*', '?' and [a-z] / [!abc] classes
+fn segment_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len()).any(|skip| segment_match(&pattern[1..], &text[skip..])),
+        Some('?') => !text.is_empty() && segment_match(&pattern[1..], &text[1..]),
+        Some('[') => {
+            let close = match pattern.iter().skip(1).position(|c| *c == ']') {
+                Some(i) => i + 1,
+                None => return text.first() == Some(&'[') && segment_match(&pattern[1..], &text[1..]),
+            };
+            let Some(&c) = text.first() else { return false };
+            let class = &pattern[1..close];
+            let (negate, class) = match class.first() {
+                Some('!') | Some('^') => (true, &class[1..]),
+                _ => (false, class),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    matched |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            matched != negate && segment_match(&pattern[close + 1..], &text[1..])
+        }
+        Some('\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && segment_match(&pattern[2..], &text[1..]),
+        Some(p) => text.first() == Some(p) && segment_match(&pattern[1..], &text[1..]),
+    }
+}
+
+fn segments_match(pattern: &[String], path: &[String]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some(p) if p == "**" => (0..=path.len()).any(|skip| segments_match(&pattern[1..], &path[skip..])),
+        Some(p) => {
+            !path.is_empty()
+                && segment_match(&p.chars().collect::<Vec<_>>(), &path[0].chars().collect::<Vec<_>>())
+                && segments_match(&pattern[1..], &path[1..])
+        }
+    }
+}
+
+impl IgnoreRules {
+    pub fn parse(text: &str) -> IgnoreRules {
+        let mut rules = Vec::new();
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let dir_only = line.ends_with('/');
+            let line = line.trim_end_matches('/');
+            let anchored = line.contains('/');
+            let segments: Vec<String> = line.trim_start_matches('/').split('/').map(|s| s.to_lowercase()).collect();
+            if segments.iter().all(|s| s.is_empty()) {
+                continue;
+            }
+            rules.push(Rule { segments, negated, dir_only, anchored });
+        }
+        IgnoreRules { rules }
+    }
+
+    // Rules for a root: its ignore file followed by the patterns saved in
+    // the root's settings, which therefore take precedence
+    pub fn load(root: &Path, patterns: &str) -> IgnoreRules {
+        let from_file = fs::read_to_string(root.join(IGNORE_FILE)).unwrap_or_default();
+        let mut rules = IgnoreRules::parse(&from_file);
+        rules.rules.extend(IgnoreRules::parse(patterns).rules);
+        rules
+    }
+
+    // `relative` is the path below the root. Matching ignores case, since
+    // the same pack lands on case-insensitive and case-sensitive drives.
+    pub fn is_ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        let parts: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+            .collect();
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let matched = if rule.anchored {
+                segments_match(&rule.segments, &parts)
+            } else {
+                parts.last().map(|name| segments_match(&rule.segments, std::slice::from_ref(name))).unwrap_or(false)
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+
+    // True if `relative` or any folder above it is ignored; for paths that
+    // arrive one at a time (watcher events) rather than from a walk
+    pub fn is_ignored_path(&self, relative: &Path, is_dir: bool) -> bool {
+        let mut ancestors: Vec<&Path> = relative.ancestors().filter(|a| !a.as_os_str().is_empty()).collect();
+        ancestors.reverse();
+        ancestors
+            .iter()
+            .enumerate()
+            .any(|(i, a)| self.is_ignored(a, is_dir || i + 1 < ancestors.len()))
+    }
+}
@@ -0,0 +1,119 @@
+use crate::paths;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use std::io::Cursor;
+use std::process::Command;
+
+const DEFAULT_MAX_DIM: u32 = 1536;
+// PNG keeps small text in screenshots sharp; past this size JPEG is used instead
+const MAX_PNG_BYTES: usize = 1536 * 1024;
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(serde::Serialize)]
+pub struct PreparedImage {
+    mime: &'static str,
+    // Base64, ready for an inline_data / image_url part
+    data: String,
+    width: u32,
+    height: u32,
+    bytes: usize,
+}
+
+fn clipboard_image() -> Result<Vec<u8>, String> {
+    let temp = std::env::temp_dir().join(format!("clipboard-{}.png", std::process::id()));
+
+    // The path travels as data, never spliced into script source, so quotes
+    // in the temp folder or user name can't break the script
+    let output = if cfg!(target_os = "macos") {
+        Command::new("osascript")
+            .args([
+                "-e",
+                "on run argv",
+                "-e",
+                "set png to (the clipboard as «class PNGf»)",
+                "-e",
+                "set f to open for access POSIX file (item 1 of argv) with write permission",
+                "-e",
+                "write png to f",
+                "-e",
+                "close access f",
+                "-e",
+                "end run",
+            ])
+            .arg(&temp)
+            .output()
+    } else if cfg!(target_os = "windows") {
+        // -Command folds trailing arguments into the script text, so the
+        // path goes through the environment instead
+        Command::new("powershell")
+            .args([
+                "-Command",
+                "Add-Type -AssemblyName System.Windows.Forms; $img = [System.Windows.Forms.Clipboard]::GetImage(); if ($img) { $img.Save($env:CLIPBOARD_PNG, [System.Drawing.Imaging.ImageFormat]::Png) }",
+            ])
+            .env("CLIPBOARD_PNG", &temp)
+            .output()
+    } else {
+        // xclip writes the image straight to stdout
+        let output = Command::new("xclip")
+            .args(["-selection", "clipboard", "-t", "image/png", "-o"])
+            .output()
+            .map_err(|e| format!("Failed to access clipboard: {}", e))?;
+        if output.status.success() && !output.stdout.is_empty() {
+            return Ok(output.stdout);
+        }
+        return Err("The clipboard doesn't contain an image".to_string());
+    };
+
+    output.map_err(|e| format!("Failed to access clipboard: {}", e))?;
+    let bytes = std::fs::read(&temp).map_err(|_| "The clipboard doesn't contain an image".to_string());
+    let _ = std::fs::remove_file(&temp);
+    bytes
+}
+
+fn encode(image: &DynamicImage) -> Result<(&'static str, Vec<u8>), String> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+    if png.len() <= MAX_PNG_BYTES {
+        return Ok(("image/png", png));
+    }
+
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+        .encode_image(&image.to_rgb8())
+        .map_err(|e| e.to_string())?;
+    Ok(("image/jpeg", jpeg))
+}
+
+// Loads an image file, or the clipboard when `source` is "clipboard", scales
+// it to fit `max_dim` and re-encodes it for a vision model request
+#[tauri::command]
+pub async fn prepare_image_for_ai(source: String, max_dim: Option<u32>) -> Result<PreparedImage, String> {
+    let bytes = if source == "clipboard" {
+        clipboard_image()?
+    } else {
+        std::fs::read(paths::to_fs(&source)).map_err(|e| format!("Failed to read image: {}", e))?
+    };
+    let image = image::load_from_memory(&bytes).map_err(|e| format!("Failed to decode image: {}", e))?;
+
+    let max_dim = max_dim.unwrap_or(DEFAULT_MAX_DIM).max(64);
+    let (width, height) = image.dimensions();
+    let image = if width > max_dim || height > max_dim {
+        image.resize(max_dim, max_dim, FilterType::Lanczos3)
+    } else {
+        image
+    };
+
+    let (mime, encoded) = encode(&image)?;
+    let (width, height) = image.dimensions();
+    Ok(PreparedImage {
+        mime,
+        bytes: encoded.len(),
+        data: base64::engine::general_purpose::STANDARD.encode(&encoded),
+        width,
+        height,
+    })
+}
@@ -0,0 +1,580 @@
+use crate::analysis::NOTE_NAMES;
+use crate::collections;
+use crate::db::{self, Db};
+use crate::jobs::{JobContext, Priority, Scheduler};
+use crate::paths;
+use crate::reconcile;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+#[derive(Default)]
+struct ImportedTrack {
+    // Where the other app thinks the file is; the first one found in the index wins
+    locations: Vec<String>,
+    bpm: Option<f64>,
+    key: Option<String>,
+    cues: Vec<(f64, String)>,
+}
+
+struct ImportedPlaylist {
+    name: String,
+    // Indices into ImportedCollection::tracks
+    tracks: Vec<usize>,
+}
+
+#[derive(Default)]
+struct ImportedCollection {
+    tracks: Vec<ImportedTrack>,
+    playlists: Vec<ImportedPlaylist>,
+}
+
+#[derive(Default, serde::Serialize)]
+pub struct ImportSummary {
+    source: String,
+    tracks: usize,
+    matched: usize,
+    cues: usize,
+    playlists: usize,
+}
+
+fn attributes(element: &BytesStart) -> HashMap<String, String> {
+    element
+        .attributes()
+        .filter_map(|a| a.ok())
+        .filter_map(|a| {
+            let key = String::from_utf8_lossy(a.key.as_ref()).to_string();
+            a.unescape_value().ok().map(|value| (key, value.to_string()))
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    let hex = |b: u8| (b as char).to_digit(16);
+    while i < bytes.len() {
+        // An escape right at the end still has its two digits at i+1 and i+2
+        if bytes[i] == b'%' && i + 3 <= bytes.len() {
+            if let (Some(high), Some(low)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                out.push((high * 16 + low) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+fn cue_label(name: Option<&String>, hotcue: i32) -> String {
+    // Traktor writes "n.n." for cues nobody named
+    match name.map(|n| n.trim()).filter(|n| !n.is_empty() && *n != "n.n.") {
+        Some(name) => name.to_string(),
+        None if hotcue >= 0 => format!("Hot Cue {}", (b'A' + (hotcue % 26) as u8) as char),
+        None => "Cue".to_string(),
+    }
+}
+
+// Playlists inside folders keep the folder path in their name
+fn playlist_name(folders: &[String], name: &str) -> String {
+    folders
+        .iter()
+        .map(|f| f.as_str())
+        .filter(|f| !f.is_empty())
+        .chain(std::iter::once(name))
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+// "file://localhost/Users/me/Music/a%20b.mp3" or ".../C:/Music/a.mp3"
+fn rekordbox_location(url: &str) -> String {
+    let path = url
+        .strip_prefix("file://localhost")
+        .or_else(|| url.strip_prefix("file://"))
+        .unwrap_or(url);
+    let path = percent_decode(path);
+    let bytes = path.as_bytes();
+    if bytes.len() > 2 && bytes[0] == b'/' && bytes[2] == b':' {
+        path[1..].replace('/', "\\")
+    } else {
+        path
+    }
+}
+
+fn parse_rekordbox(xml: &str) -> Result<ImportedCollection, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut collection = ImportedCollection::default();
+    let mut by_id: HashMap<String, usize> = HashMap::new();
+    let mut by_location: HashMap<String, usize> = HashMap::new();
+    let mut in_collection = false;
+    let mut current_track: Option<usize> = None;
+    // One entry per open NODE: true for playlists, false for folders
+    let mut nodes: Vec<bool> = Vec::new();
+    let mut folders: Vec<String> = Vec::new();
+    // The playlist being filled, and whether it refers to tracks by location
+    let mut playlist: Option<(ImportedPlaylist, bool)> = None;
+
+    loop {
+        let event = reader.read_event().map_err(|e| format!("Invalid rekordbox XML: {}", e))?;
+        let (element, is_empty) = match &event {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(e) => {
+                match e.name().as_ref() {
+                    b"COLLECTION" => in_collection = false,
+                    b"TRACK" => current_track = None,
+                    b"NODE" => match nodes.pop() {
+                        Some(true) => collection.playlists.extend(playlist.take().map(|p| p.0)),
+                        Some(false) => {
+                            folders.pop();
+                        }
+                        None => {}
+                    },
+                    _ => {}
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let attrs = attributes(element);
+        match element.name().as_ref() {
+            b"COLLECTION" => in_collection = !is_empty,
+            b"TRACK" if in_collection => {
+                let index = collection.tracks.len();
+                if let Some(id) = attrs.get("TrackID") {
+                    by_id.insert(id.clone(), index);
+                }
+                if let Some(location) = attrs.get("Location") {
+                    by_location.insert(location.clone(), index);
+                }
+                collection.tracks.push(ImportedTrack {
+                    locations: attrs.get("Location").map(|l| rekordbox_location(l)).into_iter().collect(),
+                    bpm: attrs.get("AverageBpm").and_then(|b| b.parse::<f64>().ok()).filter(|b| *b > 0.0),
+                    key: attrs.get("Tonality").and_then(|k| reconcile::normalize_key(k)),
+                    cues: Vec::new(),
+                });
+                if !is_empty {
+                    current_track = Some(index);
+                }
+            }
+            b"POSITION_MARK" => {
+                let start = attrs.get("Start").and_then(|s| s.parse::<f64>().ok());
+                if let (Some(index), Some(start)) = (current_track, start) {
+                    // Num is the hot cue slot, -1 for memory cues
+                    let slot = attrs.get("Num").and_then(|n| n.parse().ok()).unwrap_or(-1);
+                    collection.tracks[index].cues.push((start, cue_label(attrs.get("Name"), slot)));
+                }
+            }
+            b"NODE" => {
+                let name = attrs.get("Name").cloned().unwrap_or_default();
+                let is_playlist = attrs.get("Type").map(|t| t == "1").unwrap_or(false);
+                if is_playlist {
+                    let list = ImportedPlaylist { name: playlist_name(&folders, &name), tracks: Vec::new() };
+                    if is_empty {
+                        collection.playlists.push(list);
+                    } else {
+                        let by_location_keys = attrs.get("KeyType").map(|t| t == "1").unwrap_or(false);
+                        playlist = Some((list, by_location_keys));
+                        nodes.push(true);
+                    }
+                } else if !is_empty {
+                    // The implicit ROOT folder isn't part of playlist names
+                    folders.push(if name == "ROOT" { String::new() } else { name });
+                    nodes.push(false);
+                }
+            }
+            b"TRACK" => {
+                if let Some((list, by_location_keys)) = playlist.as_mut() {
+                    let index = attrs.get("Key").and_then(|key| {
+                        if *by_location_keys {
+                            by_location.get(key)
+                        } else {
+                            by_id.get(key)
+                        }
+                    });
+                    list.tracks.extend(index.copied());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(collection)
+}
+
+// Traktor stores MUSICAL_KEY as 0-11 for C..B major and 12-23 for minor
+fn traktor_key(value: &str) -> Option<String> {
+    let value: usize = value.parse().ok()?;
+    if value > 23 {
+        return None;
+    }
+    Some(format!("{}{}", NOTE_NAMES[value % 12], if value >= 12 { "m" } else { "" }))
+}
+
+// DIR is "/:Users/:me/:Music/:". On Windows VOLUME is the drive letter; on
+// macOS it is a disk name, which is either the boot disk or under /Volumes.
+fn traktor_locations(volume: &str, dir: &str, file: &str) -> Vec<String> {
+    let dir = dir.replace("/:", "/");
+    if volume.len() == 2 && volume.ends_with(':') {
+        vec![format!("{}{}{}", volume, dir, file).replace('/', "\\")]
+    } else {
+        vec![format!("{}{}", dir, file), format!("/Volumes/{}{}{}", volume, dir, file)]
+    }
+}
+
+// Fills in one child element of a collection ENTRY. Returns the key
+// playlists use for the track when the element is its LOCATION.
+fn read_traktor_field(track: &mut ImportedTrack, name: &[u8], attrs: &HashMap<String, String>) -> Option<String> {
+    match name {
+        b"LOCATION" => {
+            let get = |name: &str| attrs.get(name).map(|v| v.as_str()).unwrap_or("");
+            track.locations = traktor_locations(get("VOLUME"), get("DIR"), get("FILE"));
+            return Some(format!("{}{}{}", get("VOLUME"), get("DIR"), get("FILE")));
+        }
+        b"TEMPO" => {
+            track.bpm = attrs.get("BPM").and_then(|b| b.parse::<f64>().ok()).filter(|b| *b > 0.0);
+        }
+        b"MUSICAL_KEY" => {
+            if let Some(key) = attrs.get("VALUE").and_then(|v| traktor_key(v)) {
+                track.key = Some(key);
+            }
+        }
+        b"INFO" => {
+            if track.key.is_none() {
+                track.key = attrs.get("KEY").and_then(|k| reconcile::normalize_key(k));
+            }
+        }
+        b"CUE_V2" => {
+            // Type 4 is a beatgrid anchor, not a cue
+            let is_grid = attrs.get("TYPE").map(|t| t == "4").unwrap_or(false);
+            let start = attrs.get("START").and_then(|s| s.parse::<f64>().ok());
+            if let (false, Some(start)) = (is_grid, start) {
+                let slot = attrs.get("HOTCUE").and_then(|n| n.parse().ok()).unwrap_or(-1);
+                track.cues.push((start / 1000.0, cue_label(attrs.get("NAME"), slot)));
+            }
+        }
+        _ => {}
+    }
+    None
+}
+
+fn parse_traktor(xml: &str) -> Result<ImportedCollection, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut collection = ImportedCollection::default();
+    // Playlists refer to tracks by VOLUME + DIR + FILE
+    let mut by_key: HashMap<String, usize> = HashMap::new();
+    let mut in_collection = false;
+    let mut current_track: Option<usize> = None;
+    let mut nodes: Vec<bool> = Vec::new();
+    let mut folders: Vec<String> = Vec::new();
+    let mut playlist: Option<ImportedPlaylist> = None;
+
+    loop {
+        let event = reader.read_event().map_err(|e| format!("Invalid Traktor NML: {}", e))?;
+        let (element, is_empty) = match &event {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(e) => {
+                match e.name().as_ref() {
+                    b"COLLECTION" => in_collection = false,
+                    b"ENTRY" => current_track = None,
+                    b"NODE" => match nodes.pop() {
+                        Some(true) => collection.playlists.extend(playlist.take()),
+                        Some(false) => {
+                            folders.pop();
+                        }
+                        None => {}
+                    },
+                    _ => {}
+                }
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let attrs = attributes(element);
+        match element.name().as_ref() {
+            b"COLLECTION" => in_collection = !is_empty,
+            b"ENTRY" if in_collection => {
+                collection.tracks.push(ImportedTrack::default());
+                if !is_empty {
+                    current_track = Some(collection.tracks.len() - 1);
+                }
+            }
+            b"NODE" if !is_empty => {
+                let name = attrs.get("NAME").cloned().unwrap_or_default();
+                if attrs.get("TYPE").map(|t| t == "PLAYLIST").unwrap_or(false) {
+                    playlist = Some(ImportedPlaylist { name: playlist_name(&folders, &name), tracks: Vec::new() });
+                    nodes.push(true);
+                } else {
+                    folders.push(if name == "$ROOT" { String::new() } else { name });
+                    nodes.push(false);
+                }
+            }
+            b"PRIMARYKEY" => {
+                if let (Some(list), Some(key)) = (playlist.as_mut(), attrs.get("KEY")) {
+                    list.tracks.extend(by_key.get(key).copied());
+                }
+            }
+            name => {
+                if let Some(index) = current_track {
+                    if let Some(key) = read_traktor_field(&mut collection.tracks[index], name, &attrs) {
+                        by_key.insert(key, index);
+                    }
+                }
+            }
+        }
+    }
+    Ok(collection)
+}
+
+// Serato crates are a flat list of 4-byte tags, each followed by a
+// big-endian length; "otrk" entries nest a "ptrk" with the track path.
+fn serato_tags(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut tags = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        let start = pos + 8;
+        let end = start.saturating_add(len).min(data.len());
+        tags.push((&data[pos..pos + 4], &data[start..end]));
+        pos = end;
+    }
+    tags
+}
+
+fn utf16_be(data: &[u8]) -> String {
+    let units: Vec<u16> = data.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+    String::from_utf16_lossy(&units)
+}
+
+// Only crates are read: Serato keeps BPM, key and cues inside each audio
+// file's own tags rather than in the library folder.
+fn parse_serato(path: &Path) -> Result<ImportedCollection, String> {
+    let crate_files: Vec<PathBuf> = if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        let subcrates = path.join("Subcrates");
+        let dir = if subcrates.is_dir() { subcrates } else { path.to_path_buf() };
+        let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+            .map_err(|e| e.to_string())?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map(|e| e.eq_ignore_ascii_case("crate")).unwrap_or(false))
+            .collect();
+        files.sort();
+        files
+    };
+    if crate_files.is_empty() {
+        return Err("No Serato crates found".to_string());
+    }
+
+    // Track paths are relative to the root of the drive holding _Serato_
+    let volume_root = path
+        .ancestors()
+        .find(|p| p.file_name().map(|n| n == "_Serato_").unwrap_or(false))
+        .and_then(|p| p.parent())
+        .map(|p| p.to_path_buf());
+
+    let mut collection = ImportedCollection::default();
+    let mut by_path: HashMap<String, usize> = HashMap::new();
+    for crate_file in crate_files {
+        let data = fs::read(&crate_file).map_err(|e| e.to_string())?;
+        let name = crate_file
+            .file_stem()
+            .map(|s| s.to_string_lossy().replace("%%", " / "))
+            .unwrap_or_default();
+        let mut list = ImportedPlaylist { name, tracks: Vec::new() };
+
+        for (tag, body) in serato_tags(&data) {
+            if tag != b"otrk" {
+                continue;
+            }
+            for (inner, value) in serato_tags(body) {
+                if inner != b"ptrk" {
+                    continue;
+                }
+                let relative = utf16_be(value);
+                let index = *by_path.entry(relative.clone()).or_insert_with(|| {
+                    let mut locations = vec![format!("/{}", relative.trim_start_matches('/'))];
+                    if let Some(root) = &volume_root {
+                        locations.insert(0, root.join(&relative).to_string_lossy().to_string());
+                    }
+                    collection.tracks.push(ImportedTrack { locations, ..ImportedTrack::default() });
+                    collection.tracks.len() - 1
+                });
+                list.tracks.push(index);
+            }
+        }
+        collection.playlists.push(list);
+    }
+    Ok(collection)
+}
+
+fn match_file(conn: &Connection, locations: &[String]) -> Result<Option<String>, String> {
+    for location in locations {
+        let location = paths::display(Path::new(location));
+        let found = conn
+            .query_row("SELECT path FROM files WHERE path = ?1", params![location], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if found.is_some() {
+            return Ok(found);
+        }
+    }
+
+    // The library may have moved since the other app last saw it, so fall
+    // back to file name plus parent folder when that is unambiguous
+    let location = match locations.first() {
+        Some(location) => location.replace('\\', "/"),
+        None => return Ok(None),
+    };
+    let mut parts = location.rsplit('/');
+    let name = paths::display(Path::new(parts.next().unwrap_or("")));
+    let tail = format!("/{}/{}", parts.next().unwrap_or(""), name);
+    let mut stmt = conn
+        .prepare("SELECT path FROM files WHERE name = ?1")
+        .map_err(|e| e.to_string())?;
+    let candidates = stmt
+        .query_map(params![name], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let mut matches = candidates.into_iter().filter(|p| p.replace('\\', "/").ends_with(&tail));
+    match (matches.next(), matches.next()) {
+        (Some(path), None) => Ok(Some(path)),
+        _ => Ok(None),
+    }
+}
+
+// Skips cues that were already imported on an earlier run
+fn insert_cue(conn: &Connection, path: &str, time: f64, text: &str) -> Result<bool, String> {
+    let existing: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM markers WHERE path = ?1 AND abs(time - ?2) < 0.01",
+            params![path, time],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if existing > 0 {
+        return Ok(false);
+    }
+    conn.execute(
+        "INSERT INTO markers (path, time, text, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![path, time, text, db::now()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+fn merge(conn: &Connection, ctx: &JobContext, source: &str, collection: &ImportedCollection) -> Result<ImportSummary, String> {
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let mut summary = ImportSummary {
+        source: source.to_string(),
+        tracks: collection.tracks.len(),
+        ..ImportSummary::default()
+    };
+
+    let total = collection.tracks.len().max(1) as f32;
+    let mut matched = Vec::with_capacity(collection.tracks.len());
+    for (i, track) in collection.tracks.iter().enumerate() {
+        if ctx.is_cancelled() {
+            return Err("Import cancelled".to_string());
+        }
+        if i % 200 == 0 {
+            ctx.progress(i as f32 / total, Some("Matching files".to_string()));
+        }
+
+        let path = match_file(&tx, &track.locations)?;
+        if let Some(path) = &path {
+            summary.matched += 1;
+            if let Some(bpm) = track.bpm {
+                let bpm = (bpm * 100.0).round() / 100.0;
+                reconcile::import_value(&tx, path, "bpm", source, &bpm.to_string())?;
+            }
+            if let Some(key) = &track.key {
+                reconcile::import_value(&tx, path, "key", source, key)?;
+            }
+            for (time, text) in &track.cues {
+                if insert_cue(&tx, path, *time, text)? {
+                    summary.cues += 1;
+                }
+            }
+        }
+        matched.push(path);
+    }
+
+    for playlist in &collection.playlists {
+        let paths: Vec<String> = playlist.tracks.iter().filter_map(|i| matched[*i].clone()).collect();
+        let id = collections::upsert_collection(&tx, &playlist.name, source)?;
+        collections::set_items(&tx, id, &paths)?;
+        summary.playlists += 1;
+    }
+
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(summary)
+}
+
+fn detect_format(path: &Path) -> Result<String, String> {
+    if path.is_dir() {
+        return Ok("serato".to_string());
+    }
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "xml" => Ok("rekordbox".to_string()),
+        "nml" => Ok("traktor".to_string()),
+        "crate" => Ok("serato".to_string()),
+        _ => Err("Can't tell which app this library came from".to_string()),
+    }
+}
+
+// Reads a DJ library export and merges it into the index
+pub fn import(db: &Db, ctx: &JobContext, source_path: &Path, format: &str) -> Result<ImportSummary, String> {
+    let read = || fs::read_to_string(source_path).map_err(|e| e.to_string());
+    let collection = match format {
+        "rekordbox" => parse_rekordbox(&read()?)?,
+        "traktor" => parse_traktor(&read()?)?,
+        "serato" => parse_serato(source_path)?,
+        other => return Err(format!("Unknown library format: {}", other)),
+    };
+    let conn = db.lock();
+    merge(&conn, ctx, format, &collection)
+}
+
+pub fn resolve_format(path: &Path, format: Option<String>) -> Result<String, String> {
+    match format {
+        Some(format) => Ok(format),
+        None => detect_format(path),
+    }
+}
+
+// Merges cue points, BPM, key and playlists from a rekordbox XML export,
+// a Traktor collection.nml or a Serato _Serato_ folder into the index.
+// Only files already in the library are touched.
+#[tauri::command]
+pub async fn import_dj_library(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    format: Option<String>,
+) -> Result<ImportSummary, String> {
+    let source_path = paths::to_fs(&path);
+    let format = resolve_format(&source_path, format)?;
+    let db = db.inner().clone();
+    let label = format!("Import {} library", format);
+
+    scheduler
+        .run("import", label, Priority::Interactive, move |ctx| import(&db, ctx, &source_path, &format))
+        .await
+}
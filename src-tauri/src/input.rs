@@ -0,0 +1,132 @@
+use crate::audio_config::{self, AudioConfig, Monitor, StreamInfo, StreamStats};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SizedSample};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+
+// An open audio input. cpal streams aren't Send, so, like playback's
+// output, the stream lives on its own thread; dropping this stops it.
+pub struct InputStream {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub device: String,
+    // None when the driver picks
+    pub buffer_size: Option<u32>,
+    // Set by the stream's error callback (interface unplugged)
+    failed: Arc<AtomicBool>,
+    stats: Arc<StreamStats>,
+    _stop: mpsc::Sender<()>,
+}
+
+impl InputStream {
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    pub fn info(&self) -> StreamInfo {
+        StreamInfo::new(&self.device, self.sample_rate, self.buffer_size, &self.stats)
+    }
+}
+
+pub fn find_device(name: Option<&str>) -> Result<cpal::Device, String> {
+    let host = cpal::default_host();
+    match name {
+        None => host.default_input_device().ok_or_else(|| "No audio input device".to_string()),
+        Some(name) => host
+            .input_devices()
+            .map_err(|e| format!("Failed to list audio inputs: {}", e))?
+            .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+            .ok_or_else(|| format!("Audio input {} not found", name)),
+    }
+}
+
+fn build<T: SizedSample>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut on_data: impl FnMut(&[f32]) + Send + 'static,
+    failed: Arc<AtomicBool>,
+    stats: Arc<StreamStats>,
+) -> Result<cpal::Stream, String>
+where
+    f32: FromSample<T>,
+{
+    let channels = (config.channels as usize).max(1);
+    let mut monitor = Monitor::new(stats, config.sample_rate.0);
+    let mut converted = Vec::new();
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], info: &cpal::InputCallbackInfo| {
+                monitor.input(info, data.len() / channels);
+                converted.clear();
+                converted.extend(data.iter().map(|s| s.to_sample::<f32>()));
+                on_data(&converted);
+            },
+            move |_| failed.store(true, Ordering::SeqCst),
+            None,
+        )
+        .map_err(|e| format!("Failed to open audio input: {}", e))
+}
+
+// Opens `device` (default input when None) at the engine's configured rate
+// and buffer size and hands interleaved f32 blocks to `on_data` on the
+// audio thread, which must not block
+pub fn open(device: Option<&str>, wanted: &AudioConfig, on_data: impl FnMut(&[f32]) + Send + 'static) -> Result<InputStream, String> {
+    let (ready_tx, ready_rx) = mpsc::channel::<Result<(cpal::StreamConfig, String), String>>();
+    let (stop_tx, stop_rx) = mpsc::channel::<()>();
+    let failed = Arc::new(AtomicBool::new(false));
+    let stats = Arc::new(StreamStats::default());
+    let (stream_failed, stream_stats) = (failed.clone(), stats.clone());
+    let device_name = device.map(str::to_string);
+    let wanted = wanted.clone();
+    std::thread::spawn(move || {
+        let opened = (|| -> Result<(cpal::Stream, cpal::StreamConfig, String), String> {
+            let device = find_device(device_name.as_deref())?;
+            let name = device.name().unwrap_or_default();
+            let (config, format) = audio_config::choose(&device, false, &wanted)?;
+            let stream = match format {
+                cpal::SampleFormat::I16 => build::<i16>(&device, &config, on_data, stream_failed, stream_stats),
+                cpal::SampleFormat::I32 => build::<i32>(&device, &config, on_data, stream_failed, stream_stats),
+                cpal::SampleFormat::U16 => build::<u16>(&device, &config, on_data, stream_failed, stream_stats),
+                _ => build::<f32>(&device, &config, on_data, stream_failed, stream_stats),
+            }?;
+            stream.play().map_err(|e| format!("Failed to start audio input: {}", e))?;
+            Ok((stream, config, name))
+        })();
+        match opened {
+            Ok((stream, config, name)) => {
+                let _ = ready_tx.send(Ok((config, name)));
+                // Keeps the stream alive until the sender is dropped
+                let _ = stop_rx.recv();
+                drop(stream);
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+            }
+        }
+    });
+
+    let (config, device) = ready_rx.recv().map_err(|_| "Audio input thread exited".to_string())??;
+    Ok(InputStream {
+        sample_rate: config.sample_rate.0,
+        channels: config.channels as usize,
+        buffer_size: audio_config::buffer_frames(&config),
+        device,
+        failed,
+        stats,
+        _stop: stop_tx,
+    })
+}
+
+#[tauri::command]
+pub async fn list_input_devices() -> Result<Vec<String>, String> {
+    tokio::task::spawn_blocking(|| {
+        let devices = cpal::default_host()
+            .input_devices()
+            .map_err(|e| format!("Failed to list audio inputs: {}", e))?;
+        Ok(devices.filter_map(|d| d.name().ok()).collect())
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,425 @@
+use crate::audio::{self, AudioData};
+use crate::jobs::{Priority, Scheduler};
+use crate::loopinfo;
+use crate::paths;
+use crate::pitch;
+use crate::scanner::AUDIO_EXTENSIONS;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+// Formats every sampler we target reads directly; anything else is decoded
+// and written out as WAV
+const NATIVE_EXTENSIONS: &[&str] = &["wav", "flac", "aif", "aiff"];
+
+#[derive(serde::Deserialize)]
+pub struct KitPad {
+    path: String,
+    note: u8,
+    // Hits sharing a group cut each other off (open/closed hats)
+    choke_group: Option<u32>,
+    volume_db: Option<f32>,
+}
+
+// One sample's place on the keyboard, independent of the output format
+pub struct Region {
+    // Relative to the preset file
+    pub sample: String,
+    pub root: u8,
+    pub lo_key: u8,
+    pub hi_key: u8,
+    pub lo_vel: u8,
+    pub hi_vel: u8,
+    // (position, length) within a round-robin cycle
+    pub round_robin: Option<(u32, u32)>,
+    // Sample frames, when the file carries a sustain loop
+    pub loop_points: Option<(u64, u64)>,
+    pub one_shot: bool,
+    pub choke_group: Option<u32>,
+    pub volume_db: f32,
+    pub duration: f64,
+}
+
+#[derive(serde::Serialize)]
+pub struct BuiltInstrument {
+    path: String,
+    samples_dir: String,
+    regions: usize,
+    // Sources left out, with the reason
+    skipped: Vec<String>,
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unique_name(stem: &str, ext: &str, used: &mut HashSet<String>) -> String {
+    let mut name = format!("{}.{}", stem, ext);
+    let mut n = 2;
+    while !used.insert(name.to_lowercase()) {
+        name = format!("{} ({}).{}", stem, n, ext);
+        n += 1;
+    }
+    name
+}
+
+fn duration_of(audio: &AudioData) -> f64 {
+    audio.samples.len() as f64 / audio.channels.max(1) as f64 / audio.sample_rate as f64
+}
+
+// Puts a copy of `source` (already decoded as `decoded`) in `samples_dir`
+// and returns its file name there
+pub fn import_sample(source: &Path, decoded: &AudioData, samples_dir: &Path, used: &mut HashSet<String>) -> Result<String, String> {
+    let stem = source
+        .file_stem()
+        .map(|s| paths::display(Path::new(s)))
+        .unwrap_or_else(|| "sample".to_string());
+    let ext = source
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if NATIVE_EXTENSIONS.contains(&ext.as_str()) {
+        let name = unique_name(&stem, &ext, used);
+        fs::copy(source, samples_dir.join(&name)).map_err(|e| format!("Failed to copy {}: {}", stem, e))?;
+        Ok(name)
+    } else {
+        let name = unique_name(&stem, "wav", used);
+        audio::write_wav(&samples_dir.join(&name), decoded)?;
+        Ok(name)
+    }
+}
+
+pub fn to_sfz(regions: &[Region]) -> String {
+    let mut out = String::from("// Generated by Music Organizer Assistant\n\n<control>\ndefault_path=\n\n");
+    for region in regions {
+        out.push_str("<region>");
+        out.push_str(&format!(" sample={}", region.sample.replace('\\', "/")));
+        out.push_str(&format!(" lokey={} hikey={} pitch_keycenter={}", region.lo_key, region.hi_key, region.root));
+        if region.lo_vel > 1 || region.hi_vel < 127 {
+            out.push_str(&format!(" lovel={} hivel={}", region.lo_vel, region.hi_vel));
+        }
+        if let Some((position, length)) = region.round_robin {
+            out.push_str(&format!(" seq_length={} seq_position={}", length, position + 1));
+        }
+        if let Some((start, end)) = region.loop_points {
+            out.push_str(&format!(" loop_mode=loop_sustain loop_start={} loop_end={}", start, end));
+        } else if region.one_shot {
+            out.push_str(" loop_mode=one_shot");
+        }
+        if let Some(group) = region.choke_group {
+            out.push_str(&format!(" group={} off_by={} off_mode=fast", group, group));
+        }
+        if region.volume_db != 0.0 {
+            out.push_str(&format!(" volume={:.1}", region.volume_db));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+pub fn to_dspreset(regions: &[Region]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<DecentSampler minVersion=\"1.0.0\">\n  <groups>\n");
+    // DecentSampler does round robins per group, so each cycle position gets one
+    let mut positions: Vec<Option<(u32, u32)>> = regions.iter().map(|r| r.round_robin).collect();
+    positions.sort();
+    positions.dedup();
+    for position in positions {
+        let group_attrs = match position {
+            Some((seq, length)) => format!(" seqMode=\"round_robin\" seqLength=\"{}\" seqPosition=\"{}\"", length, seq + 1),
+            None => String::new(),
+        };
+        out.push_str(&format!("    <group{}>\n", group_attrs));
+        for region in regions.iter().filter(|r| r.round_robin == position) {
+            let mut attrs = format!(
+                "path=\"{}\" rootNote=\"{}\" loNote=\"{}\" hiNote=\"{}\" loVel=\"{}\" hiVel=\"{}\"",
+                xml_escape(&region.sample.replace('\\', "/")),
+                region.root,
+                region.lo_key,
+                region.hi_key,
+                region.lo_vel.max(1),
+                region.hi_vel
+            );
+            if let Some((start, end)) = region.loop_points {
+                attrs.push_str(&format!(" loopEnabled=\"true\" loopStart=\"{}\" loopEnd=\"{}\"", start, end));
+            } else if region.one_shot {
+                // Holding the release open for the whole sample plays it out like a drum pad
+                attrs.push_str(&format!(" ampEnvRelease=\"{:.3}\"", region.duration.max(0.01)));
+            }
+            if let Some(group) = region.choke_group {
+                attrs.push_str(&format!(" tags=\"choke{0}\" silencedByTags=\"choke{0}\" silencingMode=\"fast\"", group));
+            }
+            if region.volume_db != 0.0 {
+                attrs.push_str(&format!(" volume=\"{:.1}dB\"", region.volume_db));
+            }
+            out.push_str(&format!("      <sample {}/>\n", attrs));
+        }
+        out.push_str("    </group>\n");
+    }
+    out.push_str("  </groups>\n</DecentSampler>\n");
+    out
+}
+
+// The preset file and the folder its samples go in, side by side so the
+// preset can use relative paths and the pair can be moved together
+pub fn output_paths(out_path: &str, format: &str) -> Result<(PathBuf, PathBuf), String> {
+    let ext = match format {
+        "sfz" => "sfz",
+        "decentsampler" => "dspreset",
+        other => return Err(format!("Unknown instrument format: {}", other)),
+    };
+    let preset = paths::to_fs(out_path).with_extension(ext);
+    let stem = preset
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Instrument".to_string());
+    let samples_dir = preset.with_file_name(format!("{} Samples", stem));
+    Ok((preset, samples_dir))
+}
+
+pub fn write_preset(preset: &Path, samples_dir: &Path, format: &str, regions: &[Region]) -> Result<BuiltInstrument, String> {
+    let content = if format == "sfz" { to_sfz(regions) } else { to_dspreset(regions) };
+    fs::write(preset, content).map_err(|e| e.to_string())?;
+    Ok(BuiltInstrument {
+        path: paths::display(preset),
+        samples_dir: paths::display(samples_dir),
+        regions: regions.len(),
+        skipped: Vec::new(),
+    })
+}
+
+// Turns one-shots assigned to MIDI notes into a playable drum kit. `format`
+// is "sfz" or "decentsampler"; samples are copied next to the preset.
+#[tauri::command]
+pub async fn build_kit(
+    scheduler: State<'_, Scheduler>,
+    mapping: Vec<KitPad>,
+    out_path: String,
+    format: String,
+) -> Result<BuiltInstrument, String> {
+    if mapping.is_empty() {
+        return Err("The kit has no pads".to_string());
+    }
+    if let Some(pad) = mapping.iter().find(|p| p.note > 127) {
+        return Err(format!("Note {} for {} is out of range", pad.note, pad.path));
+    }
+    let (preset, samples_dir) = output_paths(&out_path, &format)?;
+    let sample_folder = paths::file_name(&samples_dir);
+
+    let label = format!("Build kit {}", paths::file_name(&preset));
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |ctx| {
+            fs::create_dir_all(&samples_dir).map_err(|e| e.to_string())?;
+            let mut used = HashSet::new();
+            let mut regions = Vec::with_capacity(mapping.len());
+            for (i, pad) in mapping.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    return Err("Cancelled".to_string());
+                }
+                ctx.progress(i as f32 / mapping.len() as f32, Some(pad.path.clone()));
+                let source = paths::to_fs(&pad.path);
+                let decoded = audio::decode_file(&source, None)?;
+                let name = import_sample(&source, &decoded, &samples_dir, &mut used)?;
+                regions.push(Region {
+                    sample: format!("{}/{}", sample_folder, name),
+                    root: pad.note,
+                    lo_key: pad.note,
+                    hi_key: pad.note,
+                    lo_vel: 1,
+                    hi_vel: 127,
+                    round_robin: None,
+                    loop_points: None,
+                    one_shot: true,
+                    choke_group: pad.choke_group,
+                    volume_db: pad.volume_db.unwrap_or(0.0),
+                    duration: duration_of(&decoded),
+                });
+            }
+            write_preset(&preset, &samples_dir, &format, &regions)
+        })
+        .await
+}
+
+// Loudness steps bigger than this start a new velocity layer; anything
+// closer is another take of the same dynamic (a round robin)
+const LAYER_GAP_DB: f32 = 3.0;
+const MIN_PITCH_CONFIDENCE: f32 = 0.3;
+
+// Unity note and first sustain loop from a WAV file's "smpl" chunk, which
+// most sample editors write when a loop has been set
+fn wav_sampler_info(path: &Path) -> (Option<u8>, Option<(u64, u64)>) {
+    let data = match loopinfo::find_chunk(path, b"smpl") {
+        Some(data) if data.len() >= 36 => data,
+        _ => return (None, None),
+    };
+    let word = |at: usize| data.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    let unity = word(12).filter(|n| *n > 0 && *n < 128).map(|n| n as u8);
+    let loop_points = match (word(28), word(36 + 8), word(36 + 12)) {
+        (Some(count), Some(start), Some(end)) if count > 0 && end > start => Some((start as u64, end as u64)),
+        _ => None,
+    };
+    (unity, loop_points)
+}
+
+// A sample that made it through analysis
+struct NoteSample {
+    source: PathBuf,
+    root: u8,
+    loudness: f32,
+    loop_points: Option<(u64, u64)>,
+}
+
+// Each root covers the keys up to halfway to its neighbours
+fn key_ranges(roots: &[u8]) -> Vec<(u8, u8)> {
+    roots
+        .iter()
+        .enumerate()
+        .map(|(i, &root)| {
+            let lo = if i == 0 { 0 } else { (roots[i - 1] as u16 + root as u16) / 2 + 1 };
+            let hi = roots.get(i + 1).map(|&next| (root as u16 + next as u16) / 2).unwrap_or(127);
+            (lo as u8, hi as u8)
+        })
+        .collect()
+}
+
+// Splits one note's takes, quietest first, into velocity layers of round robins
+fn layers(mut takes: Vec<NoteSample>) -> Vec<Vec<NoteSample>> {
+    takes.sort_by(|a, b| a.loudness.total_cmp(&b.loudness));
+    let mut layers: Vec<Vec<NoteSample>> = Vec::new();
+    for take in takes {
+        match layers.last_mut() {
+            Some(layer) if take.loudness - layer[0].loudness <= LAYER_GAP_DB => layer.push(take),
+            _ => layers.push(vec![take]),
+        }
+    }
+    layers
+}
+
+// Builds a playable instrument from a folder of sampled notes: roots come
+// from the file's sampler chunk or pitch detection, notes are spread across
+// the keyboard, and repeated takes of a note become velocity layers or round
+// robins depending on how far apart their levels are
+#[tauri::command]
+pub async fn build_instrument(
+    scheduler: State<'_, Scheduler>,
+    folder: String,
+    out_path: String,
+    format: String,
+) -> Result<BuiltInstrument, String> {
+    let (preset, samples_dir) = output_paths(&out_path, &format)?;
+    let sample_folder = paths::file_name(&samples_dir);
+    let mut sources: Vec<PathBuf> = fs::read_dir(paths::to_fs(&folder))
+        .map_err(|e| format!("Failed to read folder: {}", e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .map(|e| AUDIO_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+                .unwrap_or(false)
+        })
+        .collect();
+    sources.sort();
+    if sources.is_empty() {
+        return Err("No audio files in that folder".to_string());
+    }
+
+    let label = format!("Build instrument {}", paths::file_name(&preset));
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |ctx| {
+            let mut skipped = Vec::new();
+            let mut by_root: BTreeMap<u8, Vec<NoteSample>> = BTreeMap::new();
+            for (i, source) in sources.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    return Err("Cancelled".to_string());
+                }
+                ctx.progress(i as f32 / sources.len() as f32 * 0.5, Some(paths::display(source)));
+                let (unity, loop_points) = wav_sampler_info(source);
+                let root = match unity {
+                    Some(note) => note,
+                    None => match pitch::detect(source) {
+                        Ok(Some((pitch, confidence))) if confidence >= MIN_PITCH_CONFIDENCE => pitch.round().clamp(0.0, 127.0) as u8,
+                        Ok(_) => {
+                            skipped.push(format!("{}: no clear pitch", paths::display(source)));
+                            continue;
+                        }
+                        Err(e) => {
+                            skipped.push(format!("{}: {}", paths::display(source), e));
+                            continue;
+                        }
+                    },
+                };
+                let decoded = match audio::decode_file(source, Some(3.0)) {
+                    Ok(decoded) => decoded,
+                    Err(e) => {
+                        skipped.push(format!("{}: {}", paths::display(source), e));
+                        continue;
+                    }
+                };
+                let peak = decoded.samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+                by_root.entry(root).or_default().push(NoteSample {
+                    source: source.clone(),
+                    root,
+                    loudness: 20.0 * peak.max(1e-6).log10(),
+                    loop_points,
+                });
+            }
+            if by_root.is_empty() {
+                return Err("None of the samples could be read with a detectable pitch".to_string());
+            }
+
+            fs::create_dir_all(&samples_dir).map_err(|e| e.to_string())?;
+            let roots: Vec<u8> = by_root.keys().copied().collect();
+            let ranges = key_ranges(&roots);
+            let total = by_root.values().map(|v| v.len()).sum::<usize>();
+            let mut used = HashSet::new();
+            let mut regions = Vec::with_capacity(total);
+            for ((_, takes), (lo_key, hi_key)) in by_root.into_iter().zip(ranges) {
+                let layers = layers(takes);
+                let count = layers.len() as u32;
+                for (layer_index, layer) in layers.iter().enumerate() {
+                    let layer_index = layer_index as u32;
+                    let lo_vel = (1 + layer_index * 127 / count) as u8;
+                    let hi_vel = ((layer_index + 1) * 127 / count) as u8;
+                    for (position, take) in layer.iter().enumerate() {
+                        if ctx.is_cancelled() {
+                            return Err("Cancelled".to_string());
+                        }
+                        ctx.progress(0.5 + regions.len() as f32 / total as f32 * 0.5, Some(paths::display(&take.source)));
+                        let decoded = match audio::decode_file(&take.source, None) {
+                            Ok(decoded) => decoded,
+                            Err(e) => {
+                                skipped.push(format!("{}: {}", paths::display(&take.source), e));
+                                continue;
+                            }
+                        };
+                        let name = import_sample(&take.source, &decoded, &samples_dir, &mut used)?;
+                        regions.push(Region {
+                            sample: format!("{}/{}", sample_folder, name),
+                            root: take.root,
+                            lo_key,
+                            hi_key,
+                            lo_vel,
+                            hi_vel,
+                            round_robin: (layer.len() > 1).then_some((position as u32, layer.len() as u32)),
+                            loop_points: take.loop_points,
+                            one_shot: false,
+                            choke_group: None,
+                            volume_db: 0.0,
+                            duration: duration_of(&decoded),
+                        });
+                    }
+                }
+            }
+
+            if regions.is_empty() {
+                return Err("None of the samples could be read".to_string());
+            }
+            let mut built = write_preset(&preset, &samples_dir, &format, &regions)?;
+            built.skipped = skipped;
+            Ok(built)
+        })
+        .await
+}
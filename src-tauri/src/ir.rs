@@ -0,0 +1,98 @@
+use crate::audio;
+use crate::db::Db;
+use crate::paths;
+use crate::quarantine;
+use std::path::Path;
+use tauri::State;
+
+// Folder and file name words that mark impulse responses; IR packs are
+// almost always named this way ("Bricasti IRs", "Cab Impulses", "Hall IR")
+const IR_WORDS: &[&str] = &["ir", "irs", "impulse", "impulses", "impulse_response", "impulse_responses"];
+// Longer than this it's a recording, whatever the folder says
+const MAX_IR_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(serde::Serialize)]
+pub struct IrInfo {
+    path: String,
+    length: f64,
+    sample_rate: u32,
+    channels: usize,
+    // Time to decay 60 dB, from the Schroeder curve; None if the IR is too
+    // short or noisy to show a clean decay
+    rt60: Option<f64>,
+    // Silence before the direct sound
+    pre_delay_ms: f64,
+}
+
+// Whether a WAV is an impulse response going by where it lives and what
+// it's called; the scanner can't afford to open every file
+pub fn looks_like_ir(path: &Path) -> bool {
+    if std::fs::metadata(path).map(|m| m.len() > MAX_IR_BYTES).unwrap_or(false) {
+        return false;
+    }
+    let text = path.to_string_lossy().to_lowercase();
+    text.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .any(|word| IR_WORDS.contains(&word))
+        || text.contains("impulse response")
+}
+
+// Reverb time by Schroeder backward integration: the energy remaining
+// after each point, in dB, fitted between -5 and -25 dB (T20) and
+// extrapolated to 60 dB. Falls back to -5..-15 dB (T10) for short tails.
+fn rt60(signal: &[f32], sample_rate: u32) -> Option<f64> {
+    let mut remaining = vec![0.0f64; signal.len()];
+    let mut total = 0.0f64;
+    for (slot, sample) in remaining.iter_mut().zip(signal).rev() {
+        total += (*sample as f64).powi(2);
+        *slot = total;
+    }
+    if total <= 0.0 {
+        return None;
+    }
+    let curve: Vec<f64> = remaining.iter().map(|e| 10.0 * (e / total).max(1e-12).log10()).collect();
+    let fit = |top: f64, bottom: f64| -> Option<f64> {
+        let points: Vec<(f64, f64)> = curve
+            .iter()
+            .enumerate()
+            .filter(|(_, db)| **db <= top && **db >= bottom)
+            .map(|(i, db)| (i as f64 / sample_rate as f64, *db))
+            .collect();
+        if points.len() < 2 || curve.last().map(|last| *last > bottom).unwrap_or(true) {
+            return None;
+        }
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_db = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let covariance: f64 = points.iter().map(|p| (p.0 - mean_t) * (p.1 - mean_db)).sum();
+        let variance: f64 = points.iter().map(|p| (p.0 - mean_t).powi(2)).sum();
+        let slope = covariance / variance.max(1e-12);
+        (slope < 0.0).then(|| -60.0 / slope)
+    };
+    fit(-5.0, -25.0).or_else(|| fit(-5.0, -15.0))
+}
+
+pub fn info(path: &str) -> Result<IrInfo, String> {
+    let decoded = audio::decode_file(&paths::to_fs(path), None)?;
+    let mono = decoded.to_mono();
+    let peak = mono.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    // Direct sound: first sample within 20 dB of the peak
+    let onset = mono.iter().position(|s| s.abs() >= peak * 0.1).unwrap_or(0);
+    let rate = decoded.sample_rate.max(1) as f64;
+    Ok(IrInfo {
+        path: path.to_string(),
+        length: mono.len() as f64 / rate,
+        sample_rate: decoded.sample_rate,
+        channels: decoded.channels,
+        rt60: rt60(&mono[onset..], decoded.sample_rate).map(|t| (t * 100.0).round() / 100.0),
+        pre_delay_ms: onset as f64 / rate * 1000.0,
+    })
+}
+
+// Length, channel layout, RT60 estimate and pre-delay of an impulse response
+#[tauri::command]
+pub async fn get_ir_info(db: State<'_, Db>, path: String) -> Result<IrInfo, String> {
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || quarantine::guard(&db, &path, || info(&path)))
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
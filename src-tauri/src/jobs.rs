@@ -0,0 +1,685 @@
+use crate::bandwidth::{self, TransferWindow};
+use crate::db::Db;
+use crate::settings;
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tauri::{AppHandle, Emitter, State};
+
+// Finished jobs kept around so the Jobs panel can show recent history
+const FINISHED_HISTORY: usize = 200;
+// Kinds whose workers run at lowered OS priority unless "jobs.low_priority"
+// says otherwise; these are the ones that can starve a DAW's audio thread
+const LOW_PRIORITY_KINDS: &[&str] = &["analysis", "conversion", "separation"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Background,
+    Normal,
+    // The UI is waiting on the result
+    Interactive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Completed | JobState::Failed | JobState::Cancelled)
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct JobInfo {
+    id: u64,
+    kind: String,
+    label: String,
+    priority: Priority,
+    state: JobState,
+    progress: f32,
+    message: Option<String>,
+    error: Option<String>,
+    created_at: i64,
+    started_at: Option<i64>,
+    finished_at: Option<i64>,
+}
+
+type Task = Box<dyn FnOnce(&JobContext) -> Result<(), String> + Send>;
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+// Runs `work`, turning a panic into Err(message) so one bad file (a
+// corrupt FLAC tripping a decoder bug) can't take down the whole batch.
+pub fn isolate<T>(work: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(work)).map_err(panic_message)
+}
+
+struct Pending {
+    id: u64,
+    priority: Priority,
+    // Files the job works on; it stays queued while any is on an offline root
+    needs: Vec<String>,
+    task: Task,
+}
+
+// Highest priority first, then first-come first-served
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Pending {}
+
+// The offline root one of `needs` is on, if any
+fn offline_root(offline: &[String], needs: &[String]) -> Option<String> {
+    offline
+        .iter()
+        .find(|root| needs.iter().any(|path| std::path::Path::new(path).starts_with(root)))
+        .cloned()
+}
+
+struct Pool {
+    limit: usize,
+    running: usize,
+    queue: BinaryHeap<Pending>,
+}
+
+fn default_limit(kind: &str) -> usize {
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+    match kind {
+        // Leave a core free for the UI and any running DAW
+        "analysis" | "conversion" => cores.saturating_sub(1).max(1),
+        "download" => 3,
+        // Network shares and spinning drives are scanned one at a time.
+        // Stem separation models use every core on their own.
+        "sync" | "slow-scan" | "separation" => 1,
+        _ => 2,
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    jobs: HashMap<u64, JobInfo>,
+    cancel_flags: HashMap<u64, Arc<AtomicBool>>,
+    pools: HashMap<String, Pool>,
+    // Temporary ceilings below the pool limit (0 holds the queue), set by
+    // power management. Interactive jobs are exempt.
+    caps: HashMap<String, usize>,
+    low_priority: HashSet<String>,
+    // Library roots whose drive is unplugged or unmounted
+    offline: Vec<String>,
+    // Kinds that only start within a time of day, set in the bandwidth
+    // settings. Interactive jobs are exempt.
+    windows: HashMap<String, TransferWindow>,
+}
+
+impl Registry {
+    fn pool(&mut self, kind: &str) -> &mut Pool {
+        self.pools.entry(kind.to_string()).or_insert_with(|| Pool {
+            limit: default_limit(kind),
+            running: 0,
+            queue: BinaryHeap::new(),
+        })
+    }
+
+    fn prune_history(&mut self) {
+        let mut finished: Vec<u64> = self
+            .jobs
+            .values()
+            .filter(|job| job.state.is_finished())
+            .map(|job| job.id)
+            .collect();
+        if finished.len() > FINISHED_HISTORY {
+            finished.sort_unstable();
+            for id in &finished[..finished.len() - FINISHED_HISTORY] {
+                self.jobs.remove(id);
+            }
+        }
+    }
+}
+
+// Handed to every running job for progress reporting and cancellation checks
+pub struct JobContext {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+    scheduler: Scheduler,
+}
+
+impl JobContext {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(AtomicOrdering::Relaxed)
+    }
+
+    pub fn progress(&self, fraction: f32, message: Option<String>) {
+        let fraction = fraction.clamp(0.0, 1.0);
+        self.scheduler.update(self.id, |job| {
+            // Only emit on whole-percent changes so big batches don't flood the UI
+            let changed = (job.progress * 100.0) as u32 != (fraction * 100.0) as u32 || job.message != message;
+            job.progress = fraction;
+            job.message = message;
+            changed
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct Scheduler {
+    registry: Arc<Mutex<Registry>>,
+    next_id: Arc<AtomicU64>,
+    paused: Arc<AtomicBool>,
+    app: AppHandle,
+}
+
+impl Scheduler {
+    pub fn new(app: AppHandle) -> Self {
+        let registry = Registry {
+            low_priority: LOW_PRIORITY_KINDS.iter().map(|k| k.to_string()).collect(),
+            ..Registry::default()
+        };
+        Scheduler {
+            registry: Arc::new(Mutex::new(registry)),
+            next_id: Arc::new(AtomicU64::new(1)),
+            paused: Arc::new(AtomicBool::new(false)),
+            app,
+        }
+    }
+
+    // Applies the saved worker limits ("jobs.limits", per kind), the kinds
+    // that run at low priority ("jobs.low_priority") and the transfer windows.
+    // A setting that doesn't parse is left at its default rather than
+    // keeping the app from starting.
+    pub fn configure(&self, db: &Db) {
+        let conn = db.lock();
+        let limits: HashMap<String, usize> = settings::get(&conn, "jobs.limits").ok().flatten().unwrap_or_default();
+        let low_priority: Option<Vec<String>> = settings::get(&conn, "jobs.low_priority").ok().flatten();
+        let windows = bandwidth::windows(&conn).unwrap_or_default();
+        drop(conn);
+
+        let mut registry = self.lock();
+        // Kinds whose window closed or went away, or whose limit changed,
+        // may have jobs to start or hold back now
+        let mut kinds: Vec<String> = registry.pools.keys().chain(registry.windows.keys()).chain(windows.keys()).cloned().collect();
+        registry.windows = windows;
+        for (kind, limit) in limits {
+            registry.pool(&kind).limit = limit.max(1);
+            kinds.push(kind);
+        }
+        if let Some(kinds) = low_priority {
+            registry.low_priority = kinds.into_iter().collect();
+        }
+        drop(registry);
+        kinds.sort();
+        kinds.dedup();
+        for kind in kinds {
+            self.pump(&kind);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn emit(&self, job: &JobInfo) {
+        let _ = self.app.emit("job-updated", job);
+    }
+
+    // Queues a task on the worker pool for `kind` and returns its job id.
+    // Tasks run on blocking threads and should poll `ctx.is_cancelled()`.
+    pub fn submit<F>(&self, kind: &str, label: impl Into<String>, priority: Priority, task: F) -> u64
+    where
+        F: FnOnce(&JobContext) -> Result<(), String> + Send + 'static,
+    {
+        self.submit_on(kind, label, priority, Vec::new(), task)
+    }
+
+    // Like `submit`, for a job reading or writing `needs`. It waits in the
+    // queue while any of them is on a root that's offline, and starts once
+    // the drive is back.
+    pub fn submit_on<F>(&self, kind: &str, label: impl Into<String>, priority: Priority, needs: Vec<String>, task: F) -> u64
+    where
+        F: FnOnce(&JobContext) -> Result<(), String> + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, AtomicOrdering::Relaxed);
+        let info = JobInfo {
+            id,
+            kind: kind.to_string(),
+            label: label.into(),
+            priority,
+            state: JobState::Queued,
+            progress: 0.0,
+            message: None,
+            error: None,
+            created_at: crate::db::now(),
+            started_at: None,
+            finished_at: None,
+        };
+
+        {
+            let mut registry = self.lock();
+            registry.jobs.insert(id, info.clone());
+            registry.cancel_flags.insert(id, Arc::new(AtomicBool::new(false)));
+            registry.pool(kind).queue.push(Pending { id, priority, needs, task: Box::new(task) });
+        }
+
+        self.emit(&info);
+        self.pump(kind);
+        id
+    }
+
+    // Like `submit`, but waits for the job and hands back its result
+    pub async fn run<T, F>(&self, kind: &str, label: impl Into<String>, priority: Priority, task: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&JobContext) -> Result<T, String> + Send + 'static,
+    {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.submit(kind, label, priority, move |ctx| {
+            let result = task(ctx);
+            let status = result.as_ref().map(|_| ()).map_err(|e| e.clone());
+            let _ = tx.send(result);
+            status
+        });
+        rx.await.map_err(|_| "Job was cancelled".to_string())?
+    }
+
+    // Stops new jobs from starting. Fails, leaving the scheduler running, if
+    // any job is already in progress.
+    pub fn pause(&self) -> Result<(), String> {
+        let registry = self.lock();
+        let mut running: Vec<&JobInfo> = registry.jobs.values().filter(|job| job.state == JobState::Running).collect();
+        if !running.is_empty() {
+            running.sort_by_key(|job| job.id);
+            let labels: Vec<&str> = running.iter().map(|job| job.label.as_str()).collect();
+            return Err(format!("Still running: {}; wait for them or cancel them in the Jobs panel first", labels.join(", ")));
+        }
+        // Set under the registry lock so no pump can slip a job in between
+        self.paused.store(true, AtomicOrdering::SeqCst);
+        Ok(())
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, AtomicOrdering::SeqCst);
+        let kinds: Vec<String> = self.lock().pools.keys().cloned().collect();
+        for kind in kinds {
+            self.pump(&kind);
+        }
+    }
+
+    fn pump(&self, kind: &str) {
+        let mut ready = Vec::new();
+        let mut waiting = Vec::new();
+        let mut held = Vec::new();
+        {
+            let mut registry = self.lock();
+            if self.paused.load(AtomicOrdering::SeqCst) {
+                return;
+            }
+            let started_at = crate::db::now();
+            // Outside its window a kind is held like a cap of 0
+            let closed = registry.windows.get(kind).filter(|window| !window.is_open()).map(|window| window.describe());
+            let cap = if closed.is_some() { Some(0) } else { registry.caps.get(kind).copied() };
+            let offline = registry.offline.clone();
+            let pool = registry.pool(kind);
+            let mut parked = Vec::new();
+            while pool.running < pool.limit {
+                let over_cap = cap.map(|cap| pool.running >= cap).unwrap_or(false);
+                if over_cap && pool.queue.peek().map(|p| p.priority < Priority::Interactive).unwrap_or(true) {
+                    break;
+                }
+                match pool.queue.pop() {
+                    Some(pending) => match offline_root(&offline, &pending.needs) {
+                        Some(root) => {
+                            waiting.push((pending.id, root));
+                            parked.push(pending);
+                        }
+                        None => {
+                            pool.running += 1;
+                            ready.push(pending);
+                        }
+                    },
+                    None => break,
+                }
+            }
+            pool.queue.extend(parked);
+            if let Some(window) = &closed {
+                for pending in pool.queue.iter().filter(|p| p.priority < Priority::Interactive) {
+                    held.push((pending.id, window.clone()));
+                }
+            }
+            for pending in &ready {
+                if let Some(job) = registry.jobs.get_mut(&pending.id) {
+                    job.state = JobState::Running;
+                    job.started_at = Some(started_at);
+                    job.message = None;
+                }
+            }
+        }
+
+        let held = held.into_iter().map(|(id, window)| (id, format!("Waiting for the {} transfer window", window)));
+        let waiting = waiting.into_iter().map(|(id, root)| (id, format!("Waiting for {} to be reconnected", root)));
+        for (id, message) in waiting.chain(held) {
+            let message = Some(message);
+            self.update(id, |job| {
+                let changed = job.message != message;
+                job.message = message;
+                changed
+            });
+        }
+        for pending in ready {
+            self.start(kind.to_string(), pending);
+        }
+    }
+
+    // Replaces the set of offline roots. Jobs waiting on a root that's back
+    // start; ones on a root that's gone stay queued.
+    pub fn set_offline(&self, roots: Vec<String>) {
+        let kinds: Vec<String> = {
+            let mut registry = self.lock();
+            registry.offline = roots;
+            registry.pools.keys().cloned().collect()
+        };
+        for kind in kinds {
+            self.pump(&kind);
+        }
+    }
+
+    // Starts jobs held for a window that has opened since
+    pub fn refresh_windows(&self) {
+        let kinds: Vec<String> = self.lock().windows.keys().cloned().collect();
+        for kind in kinds {
+            self.pump(&kind);
+        }
+    }
+
+    fn start(&self, kind: String, pending: Pending) {
+        let id = pending.id;
+        let cancelled = self
+            .lock()
+            .cancel_flags
+            .get(&id)
+            .cloned()
+            .unwrap_or_default();
+        let low_priority = self.lock().low_priority.contains(&kind);
+        let ctx = JobContext { id, cancelled, scheduler: self.clone() };
+        self.update(id, |_| true);
+
+        let scheduler = self.clone();
+        tauri::async_runtime::spawn(async move {
+            let task = pending.task;
+            let outcome = if low_priority {
+                // A thread of its own, since a lowered priority can't always
+                // be raised again before handing a pooled thread back
+                let (tx, rx) = tokio::sync::oneshot::channel();
+                let spawned = std::thread::Builder::new().name(format!("{}-job", kind)).spawn(move || {
+                    crate::priority::lower_current_thread();
+                    let _ = tx.send(isolate(|| task(&ctx)));
+                });
+                match spawned {
+                    Ok(_) => rx.await.map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                }
+            } else {
+                tauri::async_runtime::spawn_blocking(move || isolate(|| task(&ctx)))
+                    .await
+                    .map_err(|e| e.to_string())
+            };
+            // finish() frees the pool slot either way, so the next queued
+            // job gets a fresh worker even after a crash
+            let result = match outcome {
+                Ok(Ok(result)) => result,
+                Ok(Err(panic)) => Err(format!("Job panicked: {}", panic)),
+                Err(e) => Err(format!("Job panicked: {}", e)),
+            };
+            scheduler.finish(&kind, id, result);
+        });
+    }
+
+    fn finish(&self, kind: &str, id: u64, result: Result<(), String>) {
+        let info = {
+            let mut registry = self.lock();
+            let was_cancelled = registry
+                .cancel_flags
+                .remove(&id)
+                .map(|flag| flag.load(AtomicOrdering::Relaxed))
+                .unwrap_or(false);
+
+            let pool = registry.pool(kind);
+            pool.running = pool.running.saturating_sub(1);
+
+            let info = registry.jobs.get_mut(&id).map(|job| {
+                job.finished_at = Some(crate::db::now());
+                match (&result, was_cancelled) {
+                    (_, true) => job.state = JobState::Cancelled,
+                    (Ok(()), false) => {
+                        job.state = JobState::Completed;
+                        job.progress = 1.0;
+                    }
+                    (Err(e), false) => {
+                        job.state = JobState::Failed;
+                        job.error = Some(e.clone());
+                    }
+                }
+                job.clone()
+            });
+            registry.prune_history();
+            info
+        };
+
+        if let Some(info) = info {
+            self.emit(&info);
+        }
+        self.pump(kind);
+    }
+
+    // Applies `change` to a job and emits it if the closure reports a change
+    fn update<F>(&self, id: u64, change: F)
+    where
+        F: FnOnce(&mut JobInfo) -> bool,
+    {
+        let info = {
+            let mut registry = self.lock();
+            match registry.jobs.get_mut(&id) {
+                Some(job) if change(job) => Some(job.clone()),
+                _ => None,
+            }
+        };
+        if let Some(info) = info {
+            self.emit(&info);
+        }
+    }
+
+    pub fn cancel(&self, id: u64) -> bool {
+        let info = {
+            let mut registry = self.lock();
+            let (state, kind) = match registry.jobs.get(&id) {
+                Some(job) => (job.state, job.kind.clone()),
+                None => return false,
+            };
+
+            match state {
+                JobState::Queued => {
+                    let pool = registry.pool(&kind);
+                    let queue = std::mem::take(&mut pool.queue);
+                    pool.queue = queue.into_iter().filter(|p| p.id != id).collect();
+                    registry.cancel_flags.remove(&id);
+                    registry.jobs.get_mut(&id).map(|job| {
+                        job.state = JobState::Cancelled;
+                        job.finished_at = Some(crate::db::now());
+                        job.clone()
+                    })
+                }
+                JobState::Running => {
+                    // The job notices on its next is_cancelled() check
+                    if let Some(flag) = registry.cancel_flags.get(&id) {
+                        flag.store(true, AtomicOrdering::Relaxed);
+                    }
+                    None
+                }
+                _ => return false,
+            }
+        };
+
+        if let Some(info) = info {
+            self.emit(&info);
+        }
+        true
+    }
+
+    // Cancels everything still waiting to start, for when the work it was
+    // queued for no longer applies
+    pub fn cancel_queued(&self) {
+        let queued: Vec<u64> = self.lock().jobs.values().filter(|job| job.state == JobState::Queued).map(|job| job.id).collect();
+        for id in queued {
+            self.cancel(id);
+        }
+    }
+
+    // Replaces every concurrency cap and starts whatever the new caps allow
+    pub fn set_caps(&self, caps: HashMap<String, usize>) {
+        let kinds: Vec<String> = {
+            let mut registry = self.lock();
+            registry.caps = caps;
+            registry.pools.keys().cloned().collect()
+        };
+        for kind in kinds {
+            self.pump(&kind);
+        }
+    }
+
+    // State and error of one job, while it's still in the history
+    pub fn status(&self, id: u64) -> Option<(JobState, Option<String>)> {
+        self.lock().jobs.get(&id).map(|job| (job.state, job.error.clone()))
+    }
+
+    // True while anything the user started (not background work) is queued or running
+    pub fn has_foreground_work(&self) -> bool {
+        self.lock()
+            .jobs
+            .values()
+            .any(|job| job.priority > Priority::Background && !job.state.is_finished())
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self.lock().jobs.values().cloned().collect();
+        jobs.sort_by(|a, b| b.id.cmp(&a.id));
+        jobs
+    }
+}
+
+#[tauri::command]
+pub async fn list_jobs(scheduler: State<'_, Scheduler>) -> Result<Vec<JobInfo>, String> {
+    Ok(scheduler.list())
+}
+
+#[tauri::command]
+pub async fn cancel_job(scheduler: State<'_, Scheduler>, id: u64) -> Result<(), String> {
+    if scheduler.cancel(id) {
+        Ok(())
+    } else {
+        Err(format!("Job {} is not running or queued", id))
+    }
+}
+
+#[tauri::command]
+pub async fn clear_finished_jobs(scheduler: State<'_, Scheduler>) -> Result<(), String> {
+    scheduler.lock().jobs.retain(|_, job| !job.state.is_finished());
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct JobSettings {
+    // Worker threads per job kind, for every kind seen so far
+    limits: HashMap<String, usize>,
+    low_priority: Vec<String>,
+    cores: usize,
+}
+
+#[tauri::command]
+pub async fn get_job_settings(scheduler: State<'_, Scheduler>) -> Result<JobSettings, String> {
+    let mut registry = scheduler.lock();
+    for kind in LOW_PRIORITY_KINDS {
+        registry.pool(kind);
+    }
+    let mut low_priority: Vec<String> = registry.low_priority.iter().cloned().collect();
+    low_priority.sort();
+    Ok(JobSettings {
+        limits: registry.pools.iter().map(|(kind, pool)| (kind.clone(), pool.limit)).collect(),
+        low_priority,
+        cores: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2),
+    })
+}
+
+// Changes how many workers a kind may use and saves it for next launch
+#[tauri::command]
+pub async fn set_job_concurrency(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    kind: String,
+    limit: usize,
+) -> Result<(), String> {
+    if limit == 0 {
+        return Err("Concurrency limit must be at least 1".to_string());
+    }
+    {
+        let conn = db.lock();
+        let mut limits: HashMap<String, usize> = settings::get(&conn, "jobs.limits")?.unwrap_or_default();
+        limits.insert(kind.clone(), limit);
+        settings::set(&conn, "jobs.limits", &serde_json::json!(limits))?;
+    }
+    scheduler.lock().pool(&kind).limit = limit;
+    scheduler.pump(&kind);
+    Ok(())
+}
+
+// Whether jobs of `kind` run at lowered OS priority. Applies to jobs that
+// start after the change.
+#[tauri::command]
+pub async fn set_job_low_priority(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    kind: String,
+    enabled: bool,
+) -> Result<(), String> {
+    let kinds: Vec<String> = {
+        let mut registry = scheduler.lock();
+        if enabled {
+            registry.low_priority.insert(kind);
+        } else {
+            registry.low_priority.remove(&kind);
+        }
+        registry.low_priority.iter().cloned().collect()
+    };
+    settings::set(&db.lock(), "jobs.low_priority", &serde_json::json!(kinds))
+}
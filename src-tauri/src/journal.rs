@@ -0,0 +1,300 @@
+use crate::db::{self, Db};
+use crate::paths;
+use crate::roots;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::{Path, MAIN_SEPARATOR};
+use tauri::State;
+
+// Tables (and their path column) that reference files by path. Moves and
+// renames carry these rows along so markers, tags and metadata follow the file.
+const PATH_TABLES: &[(&str, &str)] = &[
+    ("files", "path"),
+    ("markers", "path"),
+    ("file_tags", "path"),
+    ("file_metadata", "path"),
+    ("tag_suggestions", "path"),
+    ("tempo_maps", "path"),
+    ("analysis", "path"),
+    ("file_hashes", "path"),
+    ("attribute_values", "path"),
+    ("attribute_resolution", "path"),
+    ("collection_items", "path"),
+    ("quarantine", "path"),
+    ("audio_formats", "path"),
+    ("tuning_reports", "path"),
+    ("takes", "path"),
+    ("midi_features", "path"),
+    ("midi_chords", "path"),
+    ("melody_lines", "path"),
+    ("plugin_presets", "path"),
+    ("pack_provenance", "path"),
+    ("sysex_dumps", "path"),
+    ("noise_profiles", "source"),
+    ("take_lanes", "folder"),
+];
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Operation {
+    Move { from: String, to: String },
+    SetTags { path: String, before: Vec<String>, after: Vec<String> },
+    SetMetadata { path: String, key: String, before: Option<String>, after: Option<String> },
+}
+
+impl Operation {
+    pub fn inverse(&self) -> Operation {
+        match self {
+            Operation::Move { from, to } => Operation::Move { from: to.clone(), to: from.clone() },
+            Operation::SetTags { path, before, after } => Operation::SetTags {
+                path: path.clone(),
+                before: after.clone(),
+                after: before.clone(),
+            },
+            Operation::SetMetadata { path, key, before, after } => Operation::SetMetadata {
+                path: path.clone(),
+                key: key.clone(),
+                before: after.clone(),
+                after: before.clone(),
+            },
+        }
+    }
+
+    pub fn apply(&self, conn: &Connection) -> Result<(), String> {
+        match self {
+            Operation::Move { from, to } => {
+                move_on_disk(&paths::to_fs(from), &paths::to_fs(to))?;
+                repoint_rows(conn, from, to).map_err(|e| e.to_string())
+            }
+            Operation::SetTags { path, after, .. } => {
+                conn.execute("DELETE FROM file_tags WHERE path = ?1", params![path])
+                    .map_err(|e| e.to_string())?;
+                for tag in after {
+                    conn.execute("INSERT OR IGNORE INTO file_tags (path, tag) VALUES (?1, ?2)", params![path, tag])
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            }
+            Operation::SetMetadata { path, key, after, .. } => {
+                match after {
+                    Some(value) => conn.execute(
+                        "INSERT OR REPLACE INTO file_metadata (path, key, value) VALUES (?1, ?2, ?3)",
+                        params![path, key, value],
+                    ),
+                    None => conn.execute(
+                        "DELETE FROM file_metadata WHERE path = ?1 AND key = ?2",
+                        params![path, key],
+                    ),
+                }
+                .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+        }
+    }
+}
+
+fn move_on_disk(from: &Path, to: &Path) -> Result<(), String> {
+    if !from.exists() {
+        return Err(format!("File no longer exists: {}", from.display()));
+    }
+    if to.exists() {
+        return Err(format!("Destination already exists: {}", to.display()));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    if fs::rename(from, to).is_err() {
+        // rename() can't cross volumes; fall back to copy + delete for files
+        if from.is_dir() {
+            return Err(format!("Cannot move folder across volumes: {}", from.display()));
+        }
+        fs::copy(from, to).map_err(|e| format!("Failed to copy file: {}", e))?;
+        fs::remove_file(from).map_err(|e| format!("Failed to remove original: {}", e))?;
+    }
+    Ok(())
+}
+
+fn repoint_rows(conn: &Connection, from: &str, to: &str) -> rusqlite::Result<()> {
+    let from_prefix = format!("{}{}", from.trim_end_matches(MAIN_SEPARATOR), MAIN_SEPARATOR);
+    let to_prefix = format!("{}{}", to.trim_end_matches(MAIN_SEPARATOR), MAIN_SEPARATOR);
+    let prefix_len = from_prefix.chars().count() as i64;
+
+    for (table, column) in PATH_TABLES {
+        conn.execute(
+            &format!("UPDATE OR REPLACE {0} SET {1} = ?2 WHERE {1} = ?1", table, column),
+            params![from, to],
+        )?;
+        // Children of a moved folder
+        conn.execute(
+            &format!(
+                "UPDATE OR REPLACE {0} SET {1} = ?2 || substr({1}, ?3 + 1) WHERE substr({1}, 1, ?3) = ?1",
+                table, column
+            ),
+            params![from_prefix, to_prefix, prefix_len],
+        )?;
+    }
+    Ok(())
+}
+
+// Applies operations in order; if one fails, the ones already applied are
+// reverted so a batch never ends up half done.
+fn apply_all(conn: &Connection, ops: &[Operation]) -> Result<(), String> {
+    let moved: Vec<&str> = ops
+        .iter()
+        .filter_map(|op| match op {
+            Operation::Move { from, to } => Some([from.as_str(), to.as_str()]),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    roots::ensure_writable(conn, &moved)?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    for (i, op) in ops.iter().enumerate() {
+        if let Err(e) = op.apply(&tx) {
+            for done in ops[..i].iter().rev() {
+                let _ = done.inverse().apply(&tx);
+            }
+            return Err(e);
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())
+}
+
+// Applies a batch of operations and records it as a single undoable entry.
+// Recording a new entry discards anything on the redo stack.
+pub fn run(conn: &Connection, label: &str, ops: Vec<Operation>) -> Result<(), String> {
+    if ops.is_empty() {
+        return Ok(());
+    }
+
+    apply_all(conn, &ops)?;
+
+    let json = serde_json::to_string(&ops).map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM journal WHERE undo_seq IS NOT NULL", [])
+        .map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO journal (label, operations, created_at) VALUES (?1, ?2, ?3)",
+        params![label, json, db::now()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct JournalEntry {
+    id: i64,
+    label: String,
+    operation_count: usize,
+    created_at: i64,
+    undone: bool,
+}
+
+fn load_entry(conn: &Connection, sql: &str) -> Result<Option<(JournalEntry, Vec<Operation>)>, String> {
+    let row = conn
+        .query_row(sql, [], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, Option<i64>>(4)?,
+            ))
+        })
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    match row {
+        Some((id, label, json, created_at, undo_seq)) => {
+            let ops: Vec<Operation> = serde_json::from_str(&json)
+                .map_err(|e| format!("Corrupt journal entry {}: {}", id, e))?;
+            let entry = JournalEntry {
+                id,
+                label,
+                operation_count: ops.len(),
+                created_at,
+                undone: undo_seq.is_some(),
+            };
+            Ok(Some((entry, ops)))
+        }
+        None => Ok(None),
+    }
+}
+
+#[tauri::command]
+pub async fn undo_last(db: State<'_, Db>) -> Result<Option<JournalEntry>, String> {
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = db.lock();
+        let (mut entry, ops) = match load_entry(
+            &conn,
+            "SELECT id, label, operations, created_at, undo_seq FROM journal WHERE undo_seq IS NULL ORDER BY id DESC LIMIT 1",
+        )? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        let inverse: Vec<Operation> = ops.iter().rev().map(Operation::inverse).collect();
+        apply_all(&conn, &inverse)?;
+
+        conn.execute(
+            "UPDATE journal SET undo_seq = (SELECT COALESCE(MAX(undo_seq), 0) + 1 FROM journal) WHERE id = ?1",
+            params![entry.id],
+        )
+        .map_err(|e| e.to_string())?;
+        entry.undone = true;
+        Ok(Some(entry))
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn redo(db: State<'_, Db>) -> Result<Option<JournalEntry>, String> {
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = db.lock();
+        let (mut entry, ops) = match load_entry(
+            &conn,
+            "SELECT id, label, operations, created_at, undo_seq FROM journal WHERE undo_seq IS NOT NULL ORDER BY undo_seq DESC LIMIT 1",
+        )? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+
+        apply_all(&conn, &ops)?;
+
+        conn.execute("UPDATE journal SET undo_seq = NULL WHERE id = ?1", params![entry.id])
+            .map_err(|e| e.to_string())?;
+        entry.undone = false;
+        Ok(Some(entry))
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn list_journal(db: State<'_, Db>, limit: Option<u32>) -> Result<Vec<JournalEntry>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare("SELECT id, label, operations, created_at, undo_seq FROM journal ORDER BY id DESC LIMIT ?1")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![limit.unwrap_or(50)], |row| {
+            let json: String = row.get(2)?;
+            let count = serde_json::from_str::<Vec<serde_json::Value>>(&json)
+                .map(|ops| ops.len())
+                .unwrap_or(0);
+            Ok(JournalEntry {
+                id: row.get(0)?,
+                label: row.get(1)?,
+                operation_count: count,
+                created_at: row.get(3)?,
+                undone: row.get::<_, Option<i64>>(4)?.is_some(),
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(rows)
+}
@@ -0,0 +1,317 @@
+use crate::db::Db;
+use crate::journal::{self, Operation};
+use crate::paths;
+use crate::presets;
+use crate::quarantine;
+use crate::roots;
+use crate::scanner::ScannedFile;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::path::{Path, MAIN_SEPARATOR};
+use tauri::State;
+
+#[derive(serde::Deserialize)]
+pub struct MoveRequest {
+    from: String,
+    to: String,
+}
+
+pub fn tags_for(conn: &Connection, path: &str) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare("SELECT tag FROM file_tags WHERE path = ?1 ORDER BY tag")
+        .map_err(|e| e.to_string())?;
+    let tags = stmt
+        .query_map(params![path], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(tags)
+}
+
+pub fn metadata_value(conn: &Connection, path: &str, key: &str) -> Result<Option<String>, String> {
+    conn.query_row(
+        "SELECT value FROM file_metadata WHERE path = ?1 AND key = ?2",
+        params![path, key],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .into_iter()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+#[tauri::command]
+pub async fn move_files(db: State<'_, Db>, moves: Vec<MoveRequest>) -> Result<(), String> {
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let label = if moves.len() == 1 {
+            format!("Move {}", moves[0].from)
+        } else {
+            format!("Move {} files", moves.len())
+        };
+        let ops = moves
+            .into_iter()
+            .map(|m| Operation::Move { from: m.from, to: m.to })
+            .collect();
+        journal::run(&db.lock(), &label, ops)
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn rename_file(db: State<'_, Db>, path: String, new_name: String) -> Result<String, String> {
+    if new_name.is_empty() || new_name.contains('/') || new_name.contains('\\') {
+        return Err("Invalid file name".to_string());
+    }
+    let target = paths::display(&Path::new(&path).with_file_name(&new_name));
+
+    let label = format!("Rename {} to {}", path, new_name);
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        journal::run(&db.lock(), &label, vec![Operation::Move { from: path, to: target.clone() }])?;
+        Ok(target)
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn get_tags(db: State<'_, Db>, path: String) -> Result<Vec<String>, String> {
+    tags_for(&db.lock(), &path)
+}
+
+#[tauri::command]
+pub async fn set_tags(db: State<'_, Db>, paths: Vec<String>, tags: Vec<String>) -> Result<(), String> {
+    let tags = normalize_tags(tags);
+    let conn = db.lock();
+
+    let mut ops = Vec::new();
+    for path in paths {
+        let before = tags_for(&conn, &path)?;
+        if before != tags {
+            ops.push(Operation::SetTags { path, before, after: tags.clone() });
+        }
+    }
+    let label = format!("Set tags on {} files", ops.len());
+    journal::run(&conn, &label, ops)
+}
+
+#[tauri::command]
+pub async fn add_tags(db: State<'_, Db>, paths: Vec<String>, tags: Vec<String>) -> Result<(), String> {
+    let tags = normalize_tags(tags);
+    let conn = db.lock();
+
+    let mut ops = Vec::new();
+    for path in paths {
+        let before = tags_for(&conn, &path)?;
+        let after = normalize_tags(before.iter().chain(tags.iter()).cloned().collect());
+        if before != after {
+            ops.push(Operation::SetTags { path, before, after });
+        }
+    }
+    let label = format!("Tag {} files", ops.len());
+    journal::run(&conn, &label, ops)
+}
+
+#[tauri::command]
+pub async fn get_metadata(db: State<'_, Db>, path: String) -> Result<HashMap<String, String>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare("SELECT key, value FROM file_metadata WHERE path = ?1")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![path], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<HashMap<String, String>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(rows)
+}
+
+#[tauri::command]
+pub async fn set_metadata(db: State<'_, Db>, paths: Vec<String>, key: String, value: Option<String>) -> Result<(), String> {
+    let conn = db.lock();
+
+    let mut ops = Vec::new();
+    for path in paths {
+        let before = metadata_value(&conn, &path, &key)?;
+        if before != value {
+            ops.push(Operation::SetMetadata { path, key: key.clone(), before, after: value.clone() });
+        }
+    }
+    let label = format!("Set {} on {} files", key, ops.len());
+    journal::run(&conn, &label, ops)
+}
+
+pub fn upsert_file(conn: &Connection, root_id: Option<i64>, file: &ScannedFile, stamp: i64) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO files (path, root_id, name, file_type, sub_kind, size, modified, indexed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(path) DO UPDATE SET root_id = excluded.root_id, name = excluded.name,
+             file_type = excluded.file_type, sub_kind = excluded.sub_kind, size = excluded.size,
+             modified = excluded.modified, indexed_at = excluded.indexed_at",
+        params![file.path, root_id, file.name, file.file_type, file.sub_kind, file.size as i64, file.modified, stamp],
+    )
+    .map_err(|e| e.to_string())?;
+    if file.file_type == "preset" {
+        presets::record(conn, &file.path, file.modified)?;
+    }
+    Ok(())
+}
+
+// Drops a file, or everything under a folder, from the index
+pub fn remove_path(conn: &Connection, path: &str) -> Result<(), String> {
+    let prefix = format!("{}{}", path.trim_end_matches(MAIN_SEPARATOR), MAIN_SEPARATOR);
+    conn.execute(
+        "DELETE FROM files WHERE path = ?1 OR substr(path, 1, ?3) = ?2",
+        params![path, prefix, prefix.chars().count() as i64],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct LibraryEntry {
+    pub path: String,
+    pub name: String,
+    pub file_type: String,
+    // "ir" for impulse responses
+    pub sub_kind: Option<String>,
+    pub size: i64,
+    pub modified: i64,
+    pub root_id: Option<i64>,
+    // false when the file lives on a root that isn't mounted right now
+    pub online: bool,
+}
+
+#[derive(Default, serde::Deserialize)]
+pub struct LibraryQuery {
+    text: Option<String>,
+    file_type: Option<String>,
+    sub_kind: Option<String>,
+    root_id: Option<i64>,
+    tag: Option<String>,
+    // Presets made for this plugin
+    plugin: Option<String>,
+    // Files from imported packs: by vendor, by pack, and imported within a
+    // range of Unix times
+    vendor: Option<String>,
+    pack: Option<String>,
+    imported_after: Option<i64>,
+    imported_before: Option<i64>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+// Files matching `query`. Without an explicit limit at most `default_limit`
+// rows come back; -1 means all of them.
+pub fn find_files(conn: &Connection, query: &LibraryQuery, default_limit: i64) -> Result<Vec<LibraryEntry>, String> {
+    let online = roots::online_map(conn)?;
+    let limit = query.limit.map(i64::from).unwrap_or(default_limit);
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT path, name, file_type, size, modified, root_id, sub_kind FROM files
+             WHERE (?1 IS NULL OR name LIKE '%' || ?1 || '%')
+               AND (?2 IS NULL OR file_type = ?2)
+               AND (?3 IS NULL OR root_id = ?3)
+               AND (?4 IS NULL OR path IN (SELECT path FROM file_tags WHERE tag = ?4))
+               AND (?7 IS NULL OR path IN (SELECT path FROM plugin_presets WHERE plugin = ?7 COLLATE NOCASE))
+               AND (?8 IS NULL OR path IN (SELECT path FROM pack_provenance WHERE vendor = ?8 COLLATE NOCASE))
+               AND (?9 IS NULL OR path IN (SELECT path FROM pack_provenance WHERE pack = ?9 COLLATE NOCASE))
+               AND (?10 IS NULL OR path IN (SELECT path FROM pack_provenance WHERE imported_at >= ?10))
+               AND (?11 IS NULL OR path IN (SELECT path FROM pack_provenance WHERE imported_at < ?11))
+               AND (?12 IS NULL OR sub_kind = ?12)
+             ORDER BY name
+             LIMIT ?5 OFFSET ?6",
+        )
+        .map_err(|e| e.to_string())?;
+    let entries = stmt
+        .query_map(
+            params![
+                query.text,
+                query.file_type,
+                query.root_id,
+                query.tag,
+                limit,
+                query.offset.unwrap_or(0),
+                query.plugin,
+                query.vendor,
+                query.pack,
+                query.imported_after,
+                query.imported_before,
+                query.sub_kind
+            ],
+            |row| {
+                let root_id: Option<i64> = row.get(5)?;
+                Ok(LibraryEntry {
+                    path: row.get(0)?,
+                    name: row.get(1)?,
+                    file_type: row.get(2)?,
+                    sub_kind: row.get(6)?,
+                    size: row.get(3)?,
+                    modified: row.get(4)?,
+                    online: root_id.map(|id| online.get(&id).copied().unwrap_or(false)).unwrap_or(true),
+                    root_id,
+                })
+            },
+        )
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(entries)
+}
+
+#[tauri::command]
+pub async fn query_library(db: State<'_, Db>, query: LibraryQuery) -> Result<Vec<LibraryEntry>, String> {
+    find_files(&db.lock(), &query, 500)
+}
+
+#[derive(serde::Serialize)]
+pub struct LibraryStats {
+    files: i64,
+    total_size: i64,
+    by_type: HashMap<String, i64>,
+    roots: i64,
+    analyzed: i64,
+    tagged: i64,
+    // Files that failed to decode or crashed analysis; see list_quarantined
+    quarantined: i64,
+}
+
+#[tauri::command]
+pub async fn get_library_stats(db: State<'_, Db>) -> Result<LibraryStats, String> {
+    let conn = db.lock();
+    let count = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0)).map_err(|e| e.to_string());
+
+    let mut stmt = conn
+        .prepare("SELECT file_type, COUNT(*) FROM files GROUP BY file_type")
+        .map_err(|e| e.to_string())?;
+    let by_type = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<HashMap<String, i64>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(LibraryStats {
+        files: count("SELECT COUNT(*) FROM files")?,
+        total_size: count("SELECT COALESCE(SUM(size), 0) FROM files")?,
+        by_type,
+        roots: count("SELECT COUNT(*) FROM library_roots")?,
+        analyzed: count(
+            "SELECT COUNT(*) FROM files f JOIN file_hashes h ON h.path = f.path
+             JOIN analysis_cache a ON a.hash = h.hash",
+        )?,
+        tagged: count("SELECT COUNT(DISTINCT path) FROM file_tags")?,
+        quarantined: quarantine::quarantined_count(&conn)?,
+    })
+}
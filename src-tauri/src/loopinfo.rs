@@ -0,0 +1,110 @@
+use crate::audio;
+use crate::paths;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Largest chunk body we bother reading; loop and sampler chunks are tiny
+const MAX_CHUNK: u64 = 4096;
+
+// Tempo and length a loop was authored at, as stored by ACID-style WAV
+// editors ("acid" chunk) or Apple Loops AIFFs ("basc" chunk)
+#[derive(Clone, serde::Serialize)]
+pub struct LoopInfo {
+    pub bpm: Option<f64>,
+    pub beats: Option<u32>,
+    pub root_note: Option<u8>,
+    // (numerator, denominator)
+    pub meter: Option<(u16, u16)>,
+    pub one_shot: bool,
+    // "acid" or "apple_loops"
+    pub source: &'static str,
+}
+
+// Body of the first chunk called `id` in a RIFF/WAVE or FORM/AIFF file.
+// RIFF sizes are little-endian and AIFF sizes big-endian; both pad chunks
+// to an even length.
+pub fn find_chunk(path: &Path, id: &[u8; 4]) -> Option<Vec<u8>> {
+    let read = || -> std::io::Result<Option<Vec<u8>>> {
+        let mut file = fs::File::open(path)?;
+        let mut header = [0u8; 12];
+        file.read_exact(&mut header)?;
+        let big_endian = match (&header[0..4], &header[8..12]) {
+            (b"RIFF", b"WAVE") => false,
+            (b"FORM", b"AIFF") | (b"FORM", b"AIFC") => true,
+            _ => return Ok(None),
+        };
+        let mut chunk = [0u8; 8];
+        while file.read_exact(&mut chunk).is_ok() {
+            let bytes = [chunk[4], chunk[5], chunk[6], chunk[7]];
+            let size = if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) } as u64;
+            if &chunk[0..4] == id {
+                let mut data = vec![0u8; size.min(MAX_CHUNK) as usize];
+                file.read_exact(&mut data)?;
+                return Ok(Some(data));
+            }
+            file.seek(SeekFrom::Current((size + size % 2) as i64))?;
+        }
+        Ok(None)
+    };
+    read().ok().flatten()
+}
+
+fn acid(path: &Path) -> Option<LoopInfo> {
+    let data = find_chunk(path, b"acid")?;
+    if data.len() < 24 {
+        return None;
+    }
+    let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    let flags = u32_at(0);
+    let root = u16_at(4);
+    let beats = u32_at(12);
+    let tempo = f32::from_le_bytes([data[20], data[21], data[22], data[23]]) as f64;
+    Some(LoopInfo {
+        bpm: (tempo > 0.0 && tempo.is_finite()).then_some(tempo),
+        beats: (beats > 0).then_some(beats),
+        // Bit 2 says the root note is set
+        root_note: (flags & 0x02 != 0 && root < 128).then_some(root as u8),
+        meter: Some((u16_at(18), u16_at(16))).filter(|(n, d)| *n > 0 && *d > 0),
+        one_shot: flags & 0x01 != 0,
+        source: "acid",
+    })
+}
+
+// Apple Loops store beats but not tempo; the tempo follows from the length
+fn apple_loops(path: &Path) -> Option<LoopInfo> {
+    let data = find_chunk(path, b"basc")?;
+    if data.len() < 18 {
+        return None;
+    }
+    let u16_at = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+    let beats = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    let root = u16_at(8);
+    let loop_type = u16_at(16);
+    let duration = audio::probe_format(path).ok().and_then(|f| f.duration).filter(|d| *d > 0.0);
+    let bpm = match duration {
+        Some(seconds) if beats > 0 => Some(beats as f64 * 60.0 / seconds),
+        _ => None,
+    };
+    Some(LoopInfo {
+        bpm,
+        beats: (beats > 0).then_some(beats),
+        root_note: (root > 0 && root < 128).then_some(root as u8),
+        meter: Some((u16_at(12), u16_at(14))).filter(|(n, d)| *n > 0 && *d > 0),
+        // Loop type 1 is a loop, 0 a one-shot
+        one_shot: loop_type == 0,
+        source: "apple_loops",
+    })
+}
+
+pub fn read(path: &Path) -> Option<LoopInfo> {
+    acid(path).or_else(|| apple_loops(path))
+}
+
+#[tauri::command]
+pub async fn get_loop_info(path: String) -> Result<Option<LoopInfo>, String> {
+    tokio::task::spawn_blocking(move || read(&paths::to_fs(&path)))
+        .await
+        .map_err(|e| format!("Task failed: {}", e))
+}
@@ -1,18 +1,130 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod ai;
+mod alignment;
+mod analysis;
+mod arp;
+mod arrangement;
+mod artwork;
+mod audio;
+mod audio_config;
+mod autotag;
+mod bandwidth;
+mod bounce;
+mod chop;
+mod clips;
+mod collect;
+mod collections;
+mod conform;
+mod connectivity;
+mod context;
+mod conversations;
+mod credits;
+mod db;
+mod decode_cache;
+mod denoise;
+mod derived;
+mod disk;
+mod drives;
+mod dsp;
+mod duplicates;
+mod encryption;
+mod export;
+mod fx;
+mod groove;
+mod hashes;
+mod ignore;
+mod http;
+mod images;
+mod imports;
+mod input;
+mod instruments;
+mod ir;
+mod jobs;
+mod journal;
+mod library;
+mod loopinfo;
+mod maintenance;
+mod markers;
+mod melody;
+mod midi;
+mod midi_capture;
+mod midi_clock;
+mod midi_features;
+mod midi_learn;
+mod midi_ports;
+mod midi_remap;
+mod midi_tools;
+mod network_audit;
+mod packs;
+mod paths;
+mod pitch;
+mod playback;
+mod power;
+mod preroll;
+mod presets;
+mod preview_fx;
+mod priority;
+mod profiles;
+mod progressions;
+mod proxy;
+mod project_skeleton;
+mod quarantine;
+mod random;
+mod reconcile;
+mod redaction;
+mod reference;
+mod rename;
+mod repair;
+mod roots;
+mod sampler;
+mod sandbox;
+mod scanner;
+mod sequencer;
+mod settings;
+mod sheet;
+mod soundfont;
+mod stereo;
+mod stretch;
+mod structured;
+mod sysex;
+mod takes;
+mod tempo;
+mod tempo_map;
+mod theory;
+mod topline;
+mod tuning;
+mod uploads;
+mod usage;
+mod variation;
+mod velocity;
+mod volumes;
+mod workspace;
+
+use tauri::Manager;
+
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+// Watchers, drive checks, maintenance and the other work that writes to
+// the library, started once this instance holds its lock
+fn start_background(app: &tauri::AppHandle) -> Result<(), String> {
+    roots::start(app)?;
+    drives::start(app);
+    analysis::migrate_legacy(app);
+    maintenance::start(app);
+    Ok(())
+}
+
 #[tauri::command]
 async fn save_file(path: String, content: Vec<u8>) -> Result<(), String> {
     use std::fs;
-    use std::path::Path;
     
-    let path = Path::new(&path);
+    let path = paths::to_fs(&path);
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).map_err(|e| e.to_string())?;
     }
@@ -77,21 +189,15 @@ async fn get_clipboard_text() -> Result<String, String> {
     }
 }
 
-#[derive(serde::Serialize)]
-struct ScannedFile {
-    name: String,
-    path: String,
-    file_type: String,
-    size: u64,
-}
-
 #[tauri::command]
-async fn scan_directory_for_audio_files(directory_path: String) -> Result<Vec<ScannedFile>, String> {
-    use std::fs;
-    use std::path::Path;
-    use tokio::task;
+async fn scan_directory_for_audio_files(
+    scheduler: tauri::State<'_, jobs::Scheduler>,
+    sandbox: tauri::State<'_, sandbox::Sandbox>,
+    db: tauri::State<'_, db::Db>,
+    directory_path: String,
+) -> Result<Vec<scanner::ScannedFile>, String> {
     
-    let path = Path::new(&directory_path);
+    let path = paths::to_fs(&directory_path);
     if !path.exists() {
         return Err("Directory does not exist".to_string());
     }
@@ -100,63 +206,318 @@ async fn scan_directory_for_audio_files(directory_path: String) -> Result<Vec<Sc
         return Err("Path is not a directory".to_string());
     }
     
-    let audio_extensions = ["wav", "mp3", "aiff", "flac", "m4a", "aac", "ogg", "wma"];
-    let midi_extensions = ["mid", "midi"];
+    // Folders the user scans become readable through read_file
+    sandbox.allow(&db, &path)?;
     
-    // Run the scanning in a separate thread to prevent blocking the main thread
-    let result = task::spawn_blocking(move || {
+    // Run the scan on the job scheduler so it shows up in the Jobs panel and can be cancelled
+    let label = format!("Scan {}", directory_path);
+    scheduler.run("scan", label, jobs::Priority::Interactive, move |ctx| {
         let mut audio_files = Vec::new();
-        
-        fn scan_recursive(dir: &Path, audio_files: &mut Vec<ScannedFile>, audio_extensions: &[&str], midi_extensions: &[&str]) -> Result<(), String> {
-            let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))?;
-            
-            for entry in entries {
-                let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
-                let path = entry.path();
-                
-                if path.is_dir() {
-                    // Limit recursion depth to prevent excessive scanning
-                    if audio_files.len() < 10000 { // Reasonable limit to prevent UI blocking
-                        scan_recursive(&path, audio_files, audio_extensions, midi_extensions)?;
-                    }
-                } else if path.is_file() {
-                    if let Some(extension) = path.extension() {
-                        let ext_str = extension.to_string_lossy().to_lowercase();
-                        
-                        if audio_extensions.contains(&ext_str.as_str()) || midi_extensions.contains(&ext_str.as_str()) {
-                            let metadata = fs::metadata(&path).map_err(|e| format!("Failed to get file metadata: {}", e))?;
-                            let file_size = metadata.len();
-                            
-                            let file_type = if audio_extensions.contains(&ext_str.as_str()) {
-                                "audio".to_string()
-                            } else {
-                                "midi".to_string()
-                            };
-                            
-                            audio_files.push(ScannedFile {
-                                name: path.file_name().unwrap().to_string_lossy().to_string(),
-                                path: path.to_string_lossy().to_string(),
-                                file_type,
-                                size: file_size,
-                            });
-                        }
-                    }
-                }
-            }
-            
+        // Reasonable limit to prevent UI blocking
+        let options = scanner::ScanOptions {
+            limit: Some(10000),
+            ignore: ignore::IgnoreRules::load(&path, ""),
+            ..scanner::ScanOptions::default()
+        };
+        scanner::scan(&path, &options, ctx, &mut |file| {
+            audio_files.push(file);
             Ok(())
-        }
-        
-        scan_recursive(&path, &mut audio_files, &audio_extensions, &midi_extensions)?;
+        })?;
         Ok(audio_files)
-    }).await.map_err(|e| format!("Task failed: {}", e))?;
-    
-    result
+    }).await
 }
 
 fn main() {
     tauri::Builder::default()
-        .invoke_handler(tauri::generate_handler![greet, save_file, capture_screenshot, get_clipboard_text, scan_directory_for_audio_files])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .setup(|app| {
+            let data_dir = app.path().app_data_dir()?;
+            let cache_dir = app.path().app_cache_dir()?;
+            let database = profiles::database_path(&data_dir, &profiles::active(&data_dir));
+            let lock = workspace::WorkspaceLock::acquire(&database);
+            let db = if lock.is_held() { db::Db::open(&database)? } else { db::Db::open_unmigrated(&database)? };
+            let sandbox = sandbox::Sandbox::load(&db, vec![data_dir, cache_dir])?;
+            let scheduler = jobs::Scheduler::new(app.handle().clone());
+            scheduler.configure(&db);
+            app.manage(http::Http::new(db.clone()));
+            app.manage(db);
+            app.manage(lock);
+            app.manage(sandbox);
+            app.manage(scheduler);
+            app.manage(uploads::Uploads::default());
+            app.manage(usage::Tokenizers::default());
+            app.manage(connectivity::Connectivity::default());
+            app.manage(ai::AiQueue::default());
+            app.manage(playback::Playback::default());
+            app.manage(decode_cache::DecodeCache::default());
+            app.manage(roots::Watchers::default());
+            app.manage(maintenance::Presence::default());
+            app.manage(power::Power::default());
+            app.manage(takes::Recorder::default());
+            app.manage(preroll::PreRoll::default());
+            app.manage(midi_capture::MidiCapture::default());
+            app.manage(midi_clock::MidiClock::default());
+            app.manage(midi_learn::MidiLearn::default());
+            app.manage(sysex::SysexLibrarian::default());
+            app.manage(sampler::SamplerInputs::default());
+            workspace::start(app.handle())?;
+            bandwidth::start(app.handle());
+            connectivity::start(app.handle());
+            playback::start(app.handle());
+            power::start(app.handle());
+            preroll::reload(app.handle());
+            midi_capture::reload(app.handle());
+            midi_learn::reload(app.handle());
+            sampler::reload(app.handle());
+            Ok(())
+        })
+        .on_window_event(|window, event| {
+            if let tauri::WindowEvent::Focused(focused) = event {
+                window.state::<maintenance::Presence>().set_focused(*focused);
+            }
+        })
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            db::get_db_info,
+            db::backup_database,
+            db::restore_database,
+            db::vacuum_database,
+            maintenance::list_scheduled_tasks,
+            maintenance::save_scheduled_task,
+            maintenance::delete_scheduled_task,
+            maintenance::run_scheduled_task,
+            maintenance::list_scheduled_runs,
+            maintenance::preview_schedule,
+            save_file,
+            uploads::begin_save,
+            uploads::append_chunk,
+            uploads::finish_save,
+            uploads::abort_save,
+            uploads::save_file_from_path,
+            sandbox::read_file,
+            playback::play_file,
+            playback::pause_playback,
+            playback::resume_playback,
+            playback::stop_playback,
+            playback::seek_playback,
+            playback::set_playback_volume,
+            playback::get_playback_state,
+            playback::ab_compare,
+            playback::ab_switch,
+            playback::get_decode_accuracy,
+            decode_cache::get_decode_cache_stats,
+            decode_cache::clear_decode_cache,
+            artwork::get_artwork,
+            artwork::clear_artwork_cache,
+            alignment::align_lyrics,
+            ai::ai_chat,
+            structured::generate_structured,
+            context::build_ai_context,
+            images::prepare_image_for_ai,
+            clips::prepare_audio_for_ai,
+            ai::list_ai_queue,
+            ai::cancel_ai_request,
+            ai::clear_ai_queue,
+            connectivity::get_connectivity,
+            http::get_provider_health,
+            usage::estimate_tokens,
+            usage::get_conversation_usage,
+            conversations::get_conversation_params,
+            conversations::set_conversation_params,
+            conversations::get_conversation,
+            conversations::attach_to_conversation,
+            conversations::export_conversation,
+            settings::get_settings,
+            settings::set_setting,
+            autotag::auto_tag,
+            autotag::list_tag_suggestions,
+            autotag::review_tag_suggestions,
+            tempo::analyze_tempo_map,
+            tuning::analyze_tuning,
+            reference::compare_to_reference,
+            stereo::analyze_stereo,
+            repair::analyze_clipping,
+            repair::repair_audio,
+            denoise::capture_noise_profile,
+            denoise::list_noise_profiles,
+            denoise::delete_noise_profile,
+            denoise::denoise,
+            fx::render_fx,
+            ir::get_ir_info,
+            playback::preview_with_ir,
+            playback::set_preview_fx,
+            playback::get_preview_fx,
+            playback::list_output_devices,
+            playback::set_output_device,
+            input::list_input_devices,
+            takes::start_take,
+            takes::stop_take,
+            takes::list_take_lanes,
+            takes::get_take_lane,
+            takes::rate_take,
+            takes::promote_take,
+            takes::delete_rejected_takes,
+            takes::export_keeper,
+            preroll::start_preroll,
+            preroll::stop_preroll,
+            preroll::get_preroll_status,
+            preroll::capture_last,
+            midi_ports::list_midi_inputs,
+            midi_capture::start_midi_capture,
+            midi_capture::stop_midi_capture,
+            midi_capture::get_midi_capture_status,
+            midi_capture::capture_recent_midi,
+            midi_ports::list_midi_outputs,
+            midi_clock::start_midi_clock,
+            midi_clock::stop_midi_clock,
+            midi_clock::set_midi_clock_tempo,
+            midi_clock::send_midi_transport,
+            midi_clock::get_midi_clock_status,
+            midi_learn::set_midi_control_ports,
+            midi_learn::get_midi_control_ports,
+            midi_learn::list_midi_mappings,
+            midi_learn::learn_midi_mapping,
+            midi_learn::cancel_midi_learn,
+            midi_learn::delete_midi_mapping,
+            sequencer::set_pattern,
+            sequencer::start_sequencer,
+            sequencer::stop_sequencer,
+            sequencer::set_bpm,
+            sequencer::get_sequencer_state,
+            sampler::set_sampler_keymap,
+            sampler::get_sampler_keymap,
+            sampler::set_sampler_inputs,
+            sampler::get_sampler_inputs,
+            sampler::sampler_note,
+            bounce::bounce_pattern,
+            theory::list_scales,
+            theory::get_scale_notes,
+            theory::get_chord_notes,
+            theory::analyze_progression,
+            theory::suggest_next_chords,
+            groove::extract_groove,
+            groove::apply_groove,
+            arp::list_arp_styles,
+            arp::generate_arpeggio,
+            variation::vary_melody,
+            arrangement::list_arrangement_genres,
+            arrangement::generate_arrangement,
+            topline::generate_vocal_rhythm,
+            velocity::preview_velocity_curve,
+            velocity::apply_velocity_curve,
+            midi_tools::merge_midi,
+            midi_tools::split_midi,
+            midi_features::analyze_midi_features,
+            progressions::detect_midi_chords,
+            progressions::search_progressions,
+            melody::search_melody,
+            soundfont::render_midi_preview,
+            soundfont::render_collection_previews,
+            soundfont::get_midi_previews,
+            tempo_map::get_tempo_map,
+            tempo_map::edit_tempo_map,
+            midi_remap::remap_midi,
+            sysex::start_sysex_receive,
+            sysex::get_sysex_status,
+            sysex::stop_sysex_receive,
+            sysex::save_sysex_dump,
+            sysex::list_sysex_dumps,
+            sysex::update_sysex_dump,
+            sysex::delete_sysex_dump,
+            sysex::send_sysex_dump,
+            presets::list_preset_plugins,
+            project_skeleton::list_project_templates,
+            project_skeleton::create_project_skeleton,
+            collect::collect_and_save,
+            credits::get_license,
+            credits::set_license,
+            credits::export_credits,
+            packs::import_pack,
+            packs::set_pack_provenance,
+            packs::list_packs,
+            profiles::list_profiles,
+            profiles::create_profile,
+            profiles::switch_profile,
+            profiles::delete_profile,
+            workspace::get_workspace_lock,
+            workspace::take_over_workspace,
+            encryption::get_database_encryption,
+            encryption::encrypt_database,
+            encryption::change_database_passphrase,
+            encryption::decrypt_database,
+            redaction::get_redaction_settings,
+            redaction::set_redaction_settings,
+            redaction::preview_ai_request,
+            network_audit::get_network_audit,
+            proxy::get_proxy_settings,
+            proxy::set_proxy_settings,
+            bandwidth::get_bandwidth_settings,
+            bandwidth::set_bandwidth_settings,
+            audio_config::get_audio_config,
+            audio_config::set_audio_config,
+            audio_config::measure_latency,
+            pitch::detect_root_note,
+            chop::chop_sample,
+            loopinfo::get_loop_info,
+            conform::conform_tempo,
+            conform::conform_key,
+            instruments::build_kit,
+            instruments::build_instrument,
+            analysis::analyze_files,
+            analysis::get_analysis,
+            reconcile::parse_filename_metadata,
+            reconcile::reconcile_metadata,
+            reconcile::list_metadata_conflicts,
+            reconcile::resolve_metadata_conflict,
+            capture_screenshot,
+            get_clipboard_text,
+            scan_directory_for_audio_files,
+            sheet::export_sheet,
+            markers::add_marker,
+            markers::list_markers,
+            markers::update_marker,
+            markers::delete_marker,
+            library::move_files,
+            library::rename_file,
+            rename::preview_rename,
+            rename::apply_rename,
+            library::get_tags,
+            library::set_tags,
+            library::add_tags,
+            library::get_metadata,
+            library::set_metadata,
+            library::query_library,
+            library::get_library_stats,
+            quarantine::list_quarantined,
+            quarantine::retry_quarantined,
+            export::export_metadata,
+            collections::list_collections,
+            collections::export_collection,
+            duplicates::find_duplicate_folders,
+            duplicates::resolve_duplicate_folders,
+            disk::analyze_disk_usage,
+            imports::import_dj_library,
+            roots::list_library_roots,
+            roots::add_library_root,
+            roots::update_library_root,
+            roots::remove_library_root,
+            roots::scan_library_root,
+            journal::undo_last,
+            journal::redo,
+            journal::list_journal,
+            jobs::list_jobs,
+            jobs::cancel_job,
+            jobs::clear_finished_jobs,
+            jobs::set_job_concurrency,
+            jobs::get_job_settings,
+            jobs::set_job_low_priority,
+            power::get_power_status,
+            power::set_power_override,
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                app.state::<workspace::WorkspaceLock>().release();
+            }
+        });
 }
@@ -0,0 +1,549 @@
+use crate::analysis;
+use crate::db::{self, Db};
+use crate::imports;
+use crate::jobs::{JobState, Priority, Scheduler};
+use crate::paths;
+use crate::roots;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use rusqlite::{params, Connection};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+const TICK: Duration = Duration::from_secs(30);
+// The window has to have been in the background this long to count as idle
+const IDLE_AFTER_SECS: i64 = 5 * 60;
+const ACTIONS: &[&str] = &["rescan", "analysis_backfill", "vacuum", "sync"];
+const DEFAULT_BACKFILL_LIMIT: i64 = 500;
+
+// Whether the user is looking at the app. Updated from window focus events;
+// a window minimized or hidden to the tray is unfocused.
+pub struct Presence {
+    focused: AtomicBool,
+    since: AtomicI64,
+}
+
+impl Default for Presence {
+    fn default() -> Self {
+        Presence { focused: AtomicBool::new(true), since: AtomicI64::new(db::now()) }
+    }
+}
+
+impl Presence {
+    pub fn set_focused(&self, focused: bool) {
+        if self.focused.swap(focused, Ordering::SeqCst) != focused {
+            self.since.store(db::now(), Ordering::SeqCst);
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        !self.focused.load(Ordering::SeqCst) && db::now() - self.since.load(Ordering::SeqCst) >= IDLE_AFTER_SECS
+    }
+}
+
+// Five-field cron expression (minute hour day-of-month month day-of-week)
+// with *, lists, ranges and steps, plus @hourly/@daily/@weekly/@monthly.
+// Times are local.
+struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_field(text: &str, min: u32, max: u32) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max as usize + 1];
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| format!("Invalid step in {}", part))?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format!("Invalid step in {}", part));
+        }
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((lo, hi)) = range.split_once('-') {
+            let lo = lo.parse::<u32>().map_err(|_| format!("Invalid range {}", range))?;
+            let hi = hi.parse::<u32>().map_err(|_| format!("Invalid range {}", range))?;
+            (lo, hi)
+        } else {
+            let value = range.parse::<u32>().map_err(|_| format!("Invalid value {}", range))?;
+            // "5/15" means from 5 every 15
+            (value, if part.contains('/') { max } else { value })
+        };
+        if lo < min || hi > max || lo > hi {
+            return Err(format!("{} is outside {}-{}", range, min, max));
+        }
+        for value in (lo..=hi).step_by(step as usize) {
+            allowed[value as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+impl Cron {
+    fn parse(expression: &str) -> Result<Cron, String> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err("A schedule needs five fields: minute hour day month weekday".to_string());
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday
+        weekdays[0] |= weekdays[7];
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    fn day_matches(&self, date: &NaiveDateTime) -> bool {
+        let day = self.days[date.day() as usize];
+        let weekday = self.weekdays[date.weekday().num_days_from_sunday() as usize];
+        // As in cron, restricting both means either may match
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    // First matching minute strictly after `after`
+    fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let mut t = start;
+        // Five years covers any expression that can match at all (Feb 29)
+        while t < start + ChronoDuration::days(5 * 366) {
+            if !self.months[t.month() as usize] {
+                let (year, month) = if t.month() == 12 { (t.year() + 1, 1) } else { (t.year(), t.month() + 1) };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.day_matches(&t) {
+                t = (t.date() + ChronoDuration::days(1)).and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.hours[t.hour() as usize] {
+                t = t.with_minute(0)? + ChronoDuration::hours(1);
+                continue;
+            }
+            if !self.minutes[t.minute() as usize] {
+                t += ChronoDuration::minutes(1);
+                continue;
+            }
+            // Times skipped by a daylight-saving jump don't exist locally
+            match Local.from_local_datetime(&t).earliest() {
+                Some(local) => return Some(local),
+                None => t += ChronoDuration::minutes(1),
+            }
+        }
+        None
+    }
+}
+
+fn next_run(schedule: &str, after: i64) -> Result<Option<i64>, String> {
+    let cron = Cron::parse(schedule)?;
+    let after = Local.timestamp_opt(after, 0).single().ok_or("Invalid time")?;
+    Ok(cron.next_after(after).map(|t| t.timestamp()))
+}
+
+#[derive(serde::Serialize)]
+pub struct ScheduledTask {
+    id: i64,
+    name: String,
+    // "rescan", "analysis_backfill", "vacuum" or "sync"
+    action: String,
+    params: serde_json::Value,
+    schedule: String,
+    only_when_idle: bool,
+    enabled: bool,
+    next_run_at: Option<i64>,
+    last_run_at: Option<i64>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TaskInput {
+    id: Option<i64>,
+    name: String,
+    action: String,
+    // rescan: {root_id}; analysis_backfill: {limit}; sync: {path, format}
+    params: Option<serde_json::Value>,
+    schedule: String,
+    only_when_idle: Option<bool>,
+    enabled: Option<bool>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct ScheduledRun {
+    id: i64,
+    task_id: i64,
+    task_name: String,
+    // "schedule" or "manual"
+    trigger: String,
+    // "running", "ok", "failed" or "cancelled"
+    status: String,
+    message: Option<String>,
+    started_at: i64,
+    finished_at: Option<i64>,
+}
+
+fn load_tasks(conn: &Connection, where_clause: &str, args: &[&dyn rusqlite::ToSql]) -> Result<Vec<ScheduledTask>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT id, name, action, params, schedule, only_when_idle, enabled, next_run_at, last_run_at
+             FROM scheduled_tasks {} ORDER BY name",
+            where_clause
+        ))
+        .map_err(|e| e.to_string())?;
+    let tasks = stmt
+        .query_map(args, |row| {
+            Ok(ScheduledTask {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                action: row.get(2)?,
+                params: serde_json::from_str(&row.get::<_, String>(3)?).unwrap_or_default(),
+                schedule: row.get(4)?,
+                only_when_idle: row.get(5)?,
+                enabled: row.get(6)?,
+                next_run_at: row.get(7)?,
+                last_run_at: row.get(8)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(tasks)
+}
+
+fn load_task(conn: &Connection, id: i64) -> Result<ScheduledTask, String> {
+    load_tasks(conn, "WHERE id = ?1", &[&id])?
+        .pop()
+        .ok_or_else(|| format!("Unknown scheduled task: {}", id))
+}
+
+fn load_runs(conn: &Connection, where_clause: &str, args: &[&dyn rusqlite::ToSql]) -> Result<Vec<ScheduledRun>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT r.id, r.task_id, COALESCE(t.name, ''), r.trigger, r.status, r.message, r.started_at, r.finished_at
+             FROM scheduled_runs r LEFT JOIN scheduled_tasks t ON t.id = r.task_id {}",
+            where_clause
+        ))
+        .map_err(|e| e.to_string())?;
+    let runs = stmt
+        .query_map(args, |row| {
+            Ok(ScheduledRun {
+                id: row.get(0)?,
+                task_id: row.get(1)?,
+                task_name: row.get(2)?,
+                trigger: row.get(3)?,
+                status: row.get(4)?,
+                message: row.get(5)?,
+                started_at: row.get(6)?,
+                finished_at: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(runs)
+}
+
+// What starting a task produced: jobs to keep an eye on, or a result for
+// work that finished on the spot
+enum Started {
+    Jobs(Vec<u64>),
+    Done(String),
+}
+
+fn backfill_candidates(conn: &Connection, limit: i64) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT f.path FROM files f
+             LEFT JOIN file_hashes h ON h.path = f.path
+             LEFT JOIN analysis_cache a ON a.hash = h.hash
+             WHERE f.file_type = 'audio' AND a.hash IS NULL
+               AND f.path NOT IN (SELECT path FROM quarantine WHERE quarantined_at IS NOT NULL)
+             ORDER BY f.indexed_at DESC LIMIT ?1",
+        )
+        .map_err(|e| e.to_string())?;
+    let paths = stmt
+        .query_map(params![limit], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(paths)
+}
+
+fn start_action(db: &Db, scheduler: &Scheduler, task: &ScheduledTask) -> Result<Started, String> {
+    match task.action.as_str() {
+        "rescan" => {
+            let root_id = task.params.get("root_id").and_then(|v| v.as_i64());
+            let jobs = roots::submit_rescans(scheduler, db, root_id, Priority::Background)?;
+            if jobs.is_empty() {
+                return Ok(Started::Done("No library roots are online".to_string()));
+            }
+            Ok(Started::Jobs(jobs))
+        }
+        "analysis_backfill" => {
+            let limit = task.params.get("limit").and_then(|v| v.as_i64()).unwrap_or(DEFAULT_BACKFILL_LIMIT);
+            let paths = backfill_candidates(&db.lock(), limit)?;
+            if paths.is_empty() {
+                return Ok(Started::Done("Every file is analyzed".to_string()));
+            }
+            Ok(Started::Jobs(vec![analysis::submit_analysis(db, scheduler, paths)]))
+        }
+        "vacuum" => {
+            let (before, after) = db::exclusive(scheduler, || db.vacuum())?;
+            Ok(Started::Done(format!("Database went from {} to {} bytes", before, after)))
+        }
+        "sync" => {
+            let path = task.params.get("path").and_then(|v| v.as_str()).ok_or("Sync needs a library path")?;
+            let source = paths::to_fs(path);
+            let format = imports::resolve_format(&source, task.params.get("format").and_then(|v| v.as_str()).map(str::to_string))?;
+            let db = db.clone();
+            let id = scheduler.submit("sync", format!("Sync {} library", format), Priority::Background, move |ctx| {
+                imports::import(&db, ctx, &source, &format).map(|_| ())
+            });
+            Ok(Started::Jobs(vec![id]))
+        }
+        other => Err(format!("Unknown action: {}", other)),
+    }
+}
+
+// Starts a task and records the run. Scheduled runs also move the task's
+// next run time along, even if starting failed, so a broken task doesn't
+// retry every tick.
+fn run_task(app: &AppHandle, task: &ScheduledTask, trigger: &str) -> Result<ScheduledRun, String> {
+    let db = app.state::<Db>();
+    let scheduler = app.state::<Scheduler>();
+    let started_at = db::now();
+    let started = start_action(&db, &scheduler, task);
+
+    let (status, message, job_ids) = match &started {
+        Ok(Started::Jobs(ids)) => ("running", None, ids.clone()),
+        Ok(Started::Done(message)) => ("ok", Some(message.clone()), Vec::new()),
+        Err(e) => ("failed", Some(e.clone()), Vec::new()),
+    };
+    let finished_at = if status == "running" { None } else { Some(db::now()) };
+    let conn = db.lock();
+    conn.execute(
+        "INSERT INTO scheduled_runs (task_id, trigger, status, message, job_ids, started_at, finished_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![task.id, trigger, status, message, serde_json::to_string(&job_ids).unwrap_or_default(), started_at, finished_at],
+    )
+    .map_err(|e| e.to_string())?;
+    let run_id = conn.last_insert_rowid();
+
+    let next = if trigger == "schedule" { next_run(&task.schedule, started_at)? } else { task.next_run_at };
+    conn.execute(
+        "UPDATE scheduled_tasks SET last_run_at = ?1, next_run_at = ?2 WHERE id = ?3",
+        params![started_at, next, task.id],
+    )
+    .map_err(|e| e.to_string())?;
+
+    let run = load_runs(&conn, "WHERE r.id = ?1", &[&run_id])?.pop().ok_or("Run vanished")?;
+    let _ = app.emit("scheduled-run-updated", &run);
+    Ok(run)
+}
+
+// Closes out runs whose jobs have all finished
+fn settle_runs(app: &AppHandle) -> Result<(), String> {
+    let db = app.state::<Db>();
+    let scheduler = app.state::<Scheduler>();
+    let running: Vec<(i64, String)> = {
+        let conn = db.lock();
+        let mut stmt = conn
+            .prepare("SELECT id, job_ids FROM scheduled_runs WHERE status = 'running'")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        rows
+    };
+
+    for (run_id, job_ids) in running {
+        let ids: Vec<u64> = serde_json::from_str(&job_ids).unwrap_or_default();
+        // Jobs gone from the history (cleared, or lost to a restart) count as finished
+        let states: Vec<(JobState, Option<String>)> = ids.iter().filter_map(|id| scheduler.status(*id)).collect();
+        if states.iter().any(|(state, _)| !state.is_finished()) {
+            continue;
+        }
+        let failure = states.iter().find(|(state, _)| *state == JobState::Failed);
+        let (status, message) = if let Some((_, error)) = failure {
+            ("failed", error.clone())
+        } else if states.iter().any(|(state, _)| *state == JobState::Cancelled) {
+            ("cancelled", None)
+        } else if states.len() < ids.len() {
+            ("ok", Some("Finished while the app was closed or its job history was cleared".to_string()))
+        } else {
+            ("ok", None)
+        };
+        let conn = db.lock();
+        conn.execute(
+            "UPDATE scheduled_runs SET status = ?1, message = ?2, finished_at = ?3 WHERE id = ?4",
+            params![status, message, db::now(), run_id],
+        )
+        .map_err(|e| e.to_string())?;
+        if let Some(run) = load_runs(&conn, "WHERE r.id = ?1", &[&run_id])?.pop() {
+            let _ = app.emit("scheduled-run-updated", &run);
+        }
+    }
+    Ok(())
+}
+
+fn tick(app: &AppHandle) -> Result<(), String> {
+    settle_runs(app)?;
+
+    let db = app.state::<Db>();
+    let now = db::now();
+    let due = load_tasks(&db.lock(), "WHERE enabled = 1 AND next_run_at IS NOT NULL AND next_run_at <= ?1", &[&now])?;
+    if due.is_empty() {
+        return Ok(());
+    }
+    let idle = app.state::<Presence>().is_idle() && !app.state::<Scheduler>().has_foreground_work();
+    for task in due {
+        // Waits for the next idle stretch; a run missed while the app was
+        // closed happens once, not once per missed slot
+        if task.only_when_idle && !idle {
+            continue;
+        }
+        let still_running: bool = db
+            .lock()
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM scheduled_runs WHERE task_id = ?1 AND status = 'running'",
+                params![task.id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if !still_running {
+            run_task(app, &task, "schedule")?;
+        }
+    }
+    Ok(())
+}
+
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+        loop {
+            interval.tick().await;
+            // Vacuum blocks while it rewrites the database
+            let app = app.clone();
+            let _ = tokio::task::spawn_blocking(move || tick(&app)).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn list_scheduled_tasks(db: State<'_, Db>) -> Result<Vec<ScheduledTask>, String> {
+    load_tasks(&db.lock(), "", &[])
+}
+
+// Creates a task, or updates it when `id` is set
+#[tauri::command]
+pub async fn save_scheduled_task(db: State<'_, Db>, task: TaskInput) -> Result<ScheduledTask, String> {
+    if !ACTIONS.contains(&task.action.as_str()) {
+        return Err(format!("Unknown action: {}", task.action));
+    }
+    let next = next_run(&task.schedule, db::now())?;
+    if next.is_none() {
+        return Err("That schedule never runs".to_string());
+    }
+    let params_json = serde_json::to_string(&task.params.unwrap_or_else(|| serde_json::json!({}))).map_err(|e| e.to_string())?;
+    let only_when_idle = task.only_when_idle.unwrap_or(true);
+    let enabled = task.enabled.unwrap_or(true);
+
+    let conn = db.lock();
+    let id = match task.id {
+        Some(id) => {
+            let changed = conn
+                .execute(
+                    "UPDATE scheduled_tasks SET name = ?1, action = ?2, params = ?3, schedule = ?4, only_when_idle = ?5,
+                         enabled = ?6, next_run_at = ?7 WHERE id = ?8",
+                    params![task.name, task.action, params_json, task.schedule, only_when_idle, enabled, next, id],
+                )
+                .map_err(|e| e.to_string())?;
+            if changed == 0 {
+                return Err(format!("Unknown scheduled task: {}", id));
+            }
+            id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO scheduled_tasks (name, action, params, schedule, only_when_idle, enabled, next_run_at, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![task.name, task.action, params_json, task.schedule, only_when_idle, enabled, next, db::now()],
+            )
+            .map_err(|e| format!("Failed to save scheduled task: {}", e))?;
+            conn.last_insert_rowid()
+        }
+    };
+    load_task(&conn, id)
+}
+
+#[tauri::command]
+pub async fn delete_scheduled_task(db: State<'_, Db>, id: i64) -> Result<(), String> {
+    db.lock()
+        .execute("DELETE FROM scheduled_tasks WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Runs a task right away, whatever its schedule says
+#[tauri::command]
+pub async fn run_scheduled_task(app: AppHandle, id: i64) -> Result<ScheduledRun, String> {
+    tokio::task::spawn_blocking(move || {
+        let task = load_task(&app.state::<Db>().lock(), id)?;
+        run_task(&app, &task, "manual")
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn list_scheduled_runs(db: State<'_, Db>, task_id: Option<i64>, limit: Option<i64>) -> Result<Vec<ScheduledRun>, String> {
+    let limit = limit.unwrap_or(100);
+    let conn = db.lock();
+    match task_id {
+        Some(id) => load_runs(&conn, "WHERE r.task_id = ?1 ORDER BY r.id DESC LIMIT ?2", &[&id, &limit]),
+        None => load_runs(&conn, "ORDER BY r.id DESC LIMIT ?1", &[&limit]),
+    }
+}
+
+// Next few times a schedule would fire, so the UI can show what an
+// expression means before it's saved
+#[tauri::command]
+pub async fn preview_schedule(schedule: String, count: Option<usize>) -> Result<Vec<i64>, String> {
+    let mut times = Vec::new();
+    let mut after = db::now();
+    for _ in 0..count.unwrap_or(5) {
+        match next_run(&schedule, after)? {
+            Some(next) => {
+                times.push(next);
+                after = next;
+            }
+            None => break,
+        }
+    }
+    Ok(times)
+}
+
@@ -0,0 +1,114 @@
+use crate::db::{self, Db};
+use rusqlite::params;
+use tauri::State;
+
+#[derive(serde::Serialize)]
+pub struct Marker {
+    id: i64,
+    path: String,
+    time: f64,
+    text: String,
+    label: String,
+    created_at: i64,
+}
+
+// "3:41" style label for display next to the marker text
+fn format_time(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    let (hours, minutes, secs) = (total / 3600, (total % 3600) / 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs)
+    } else {
+        format!("{}:{:02}", minutes, secs)
+    }
+}
+
+fn row_to_marker(row: &rusqlite::Row) -> rusqlite::Result<Marker> {
+    let time: f64 = row.get(2)?;
+    Ok(Marker {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        time,
+        text: row.get(3)?,
+        label: format_time(time),
+        created_at: row.get(4)?,
+    })
+}
+
+fn check_time(time: f64) -> Result<f64, String> {
+    if !time.is_finite() || time < 0.0 {
+        return Err("Marker time must be a positive number of seconds".to_string());
+    }
+    Ok(time)
+}
+
+fn check_text(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("Marker text cannot be empty".to_string());
+    }
+    Ok(text.to_string())
+}
+
+#[tauri::command]
+pub async fn add_marker(db: State<'_, Db>, path: String, time: f64, text: String) -> Result<Marker, String> {
+    let time = check_time(time)?;
+    let text = check_text(&text)?;
+
+    let conn = db.lock();
+    let created_at = db::now();
+    conn.execute(
+        "INSERT INTO markers (path, time, text, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![path, time, text, created_at],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(Marker {
+        id: conn.last_insert_rowid(),
+        path,
+        time,
+        label: format_time(time),
+        text,
+        created_at,
+    })
+}
+
+#[tauri::command]
+pub async fn list_markers(db: State<'_, Db>, path: String) -> Result<Vec<Marker>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare("SELECT id, path, time, text, created_at FROM markers WHERE path = ?1 ORDER BY time")
+        .map_err(|e| e.to_string())?;
+    let markers = stmt
+        .query_map(params![path], row_to_marker)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(markers)
+}
+
+#[tauri::command]
+pub async fn update_marker(db: State<'_, Db>, id: i64, time: Option<f64>, text: Option<String>) -> Result<(), String> {
+    // Both checked before either is written, so a bad label doesn't leave
+    // the marker half-updated
+    let time = time.map(check_time).transpose()?;
+    let text = text.as_deref().map(check_text).transpose()?;
+    let conn = db.lock();
+    if let Some(time) = time {
+        conn.execute("UPDATE markers SET time = ?1 WHERE id = ?2", params![time, id])
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(text) = text {
+        conn.execute("UPDATE markers SET text = ?1 WHERE id = ?2", params![text, id])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_marker(db: State<'_, Db>, id: i64) -> Result<(), String> {
+    db.lock()
+        .execute("DELETE FROM markers WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use crate::db::{self, Db};
+use crate::jobs::{Priority, Scheduler};
+use crate::midi::{self, Note};
+use crate::paths;
+use crate::quarantine;
+use crate::scanner;
+use crate::tuning;
+use rusqlite::{params, OptionalExtension};
+use std::collections::HashSet;
+use tauri::State;
+
+const DEFAULT_LIMIT: usize = 50;
+const DEFAULT_MIN_SIMILARITY: f32 = 0.6;
+// Fewer notes than this match nearly anything
+const MIN_QUERY_NOTES: usize = 4;
+// General MIDI drums, counted from zero; they carry no melody
+const DRUM_CHANNEL: u8 = 9;
+// Leaps are compared up to an octave
+const MAX_INTERVAL: i32 = 12;
+// Interval n-grams a line must share with the query to be compared at all
+const GRAM: usize = 3;
+// Substituting an interval with a near one in the same direction costs this
+// much instead of a whole edit
+const NEAR_COST: f32 = 0.5;
+const NEAR_SEMITONES: i32 = 2;
+
+// One track's top line: a pitch at each note start, highest first
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct Line {
+    track: usize,
+    pitches: Vec<u8>,
+    // Note starts in beats
+    beats: Vec<f64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Lines {
+    beats_per_bar: u8,
+    lines: Vec<Line>,
+}
+
+#[derive(serde::Serialize)]
+pub struct MelodyMatch {
+    path: String,
+    // From zero, in file order
+    track: usize,
+    // 1-based, as DAWs count
+    bar: usize,
+    beat: f64,
+    // 1 when the intervals match exactly
+    similarity: f32,
+    // Where the match starts relative to the query's first note
+    semitones: i32,
+}
+
+// The highest note at each start, so chords and doubled parts read as the
+// line on top
+fn top_line(track: usize, notes: &[Note]) -> Option<Line> {
+    let mut notes: Vec<&Note> = notes.iter().filter(|n| n.channel != DRUM_CHANNEL).collect();
+    notes.sort_by(|a, b| a.start.total_cmp(&b.start).then(b.pitch.cmp(&a.pitch)));
+    let mut line = Line { track, pitches: Vec::new(), beats: Vec::new() };
+    for note in notes {
+        if line.beats.last().is_some_and(|last| (note.start - last).abs() < 1e-3) {
+            continue;
+        }
+        line.pitches.push(note.pitch);
+        line.beats.push(note.start);
+    }
+    (line.pitches.len() >= MIN_QUERY_NOTES).then_some(line)
+}
+
+fn intervals(pitches: &[i32]) -> Vec<i32> {
+    pitches.windows(2).map(|w| (w[1] - w[0]).clamp(-MAX_INTERVAL, MAX_INTERVAL)).collect()
+}
+
+fn grams(intervals: &[i32]) -> HashSet<&[i32]> {
+    intervals.windows(GRAM).collect()
+}
+
+fn substitution(a: i32, b: i32) -> f32 {
+    if a == b {
+        0.0
+    } else if a.signum() == b.signum() && (a - b).abs() <= NEAR_SEMITONES {
+        NEAR_COST
+    } else {
+        1.0
+    }
+}
+
+// The whole query against its best-fitting stretch of `target`: an edit
+// distance free to start and end anywhere in the target. Returns the cost
+// and the target interval the stretch starts at.
+fn best_fit(query: &[i32], target: &[i32]) -> (f32, usize) {
+    let (n, m) = (query.len(), target.len());
+    // Each cell carries its cost and where in the target its path began
+    let mut previous: Vec<(f32, usize)> = (0..=m).map(|j| (0.0, j)).collect();
+    let mut current = vec![(0.0, 0); m + 1];
+    for i in 1..=n {
+        current[0] = (i as f32, 0);
+        for j in 1..=m {
+            let diagonal = (previous[j - 1].0 + substitution(query[i - 1], target[j - 1]), previous[j - 1].1);
+            let up = (previous[j].0 + 1.0, previous[j].1);
+            let left = (current[j - 1].0 + 1.0, current[j - 1].1);
+            current[j] = [diagonal, up, left].into_iter().min_by(|a, b| a.0.total_cmp(&b.0)).unwrap_or(diagonal);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous.into_iter().min_by(|a, b| a.0.total_cmp(&b.0)).unwrap_or((n as f32, 0))
+}
+
+// Top lines of an indexed file, found once per version of it
+fn lines_for(db: &Db, path: &str, modified: i64) -> Result<Lines, String> {
+    let cached: Option<String> = db
+        .lock()
+        .query_row("SELECT data FROM melody_lines WHERE path = ?1 AND modified = ?2", params![path, modified], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(lines) = cached.and_then(|json| serde_json::from_str(&json).ok()) {
+        return Ok(lines);
+    }
+    let lines = quarantine::guard(db, path, || -> Result<_, String> {
+        let file = midi::read(&paths::to_fs(path))?;
+        let lines = file.tracks.iter().enumerate().filter_map(|(i, notes)| top_line(i, notes)).collect();
+        Ok(Lines { beats_per_bar: file.beats_per_bar, lines })
+    })?;
+    let json = serde_json::to_string(&lines).map_err(|e| e.to_string())?;
+    db.lock()
+        .execute(
+            "INSERT OR REPLACE INTO melody_lines (path, modified, data, analyzed_at) VALUES (?1, ?2, ?3, ?4)",
+            params![path, modified, json, db::now()],
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(lines)
+}
+
+// The query's pitches: played notes as given, or the top line of a MIDI
+// clip, or the notes sung or hummed in a recording
+fn query_pitches(notes: Option<Vec<u8>>, path: Option<&str>) -> Result<Vec<i32>, String> {
+    let pitches = match (notes, path) {
+        (Some(notes), _) => notes.into_iter().map(i32::from).collect(),
+        (None, Some(path)) => {
+            let fs_path = paths::to_fs(path);
+            if scanner::file_type_for(&fs_path) == Some("midi") {
+                let file = midi::read(&fs_path)?;
+                top_line(0, &file.notes()).map(|l| l.pitches.into_iter().map(i32::from).collect()).unwrap_or_default()
+            } else {
+                tuning::sung_pitches(&fs_path)?
+            }
+        }
+        (None, None) => return Err("Play some notes or pick a clip to search with".to_string()),
+    };
+    if pitches.len() < MIN_QUERY_NOTES {
+        return Err(format!("Give at least {} notes to search with", MIN_QUERY_NOTES));
+    }
+    Ok(pitches)
+}
+
+// Finds MIDI files in the library with a line shaped like the query, in any
+// key. The query is `notes` played in, or a MIDI clip or a hummed recording
+// at `path`. Compares intervals, so rhythm and transposition don't matter.
+#[tauri::command]
+pub async fn search_melody(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    notes: Option<Vec<u8>>,
+    path: Option<String>,
+    min_similarity: Option<f32>,
+    limit: Option<usize>,
+) -> Result<Vec<MelodyMatch>, String> {
+    let min_similarity = min_similarity.unwrap_or(DEFAULT_MIN_SIMILARITY).clamp(0.0, 1.0);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    let db = db.inner().clone();
+    let label = "Search melodies".to_string();
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |ctx| {
+            let pitches = query_pitches(notes, path.as_deref())?;
+            let query = intervals(&pitches);
+            let query_grams = grams(&query);
+            // Enough shared n-grams that the edit distance could pass
+            let needed = (query_grams.len() as f32 * (min_similarity - 0.5)).floor().max(0.0);
+
+            let files: Vec<(String, i64)> = {
+                let conn = db.lock();
+                let mut stmt = conn
+                    .prepare("SELECT path, modified FROM files WHERE file_type = 'midi' ORDER BY path")
+                    .map_err(|e| e.to_string())?;
+                let rows = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                    .map_err(|e| e.to_string())?
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| e.to_string())?;
+                rows
+            };
+            let total = files.len().max(1) as f32;
+            let mut matches = Vec::new();
+            for (i, (file, modified)) in files.iter().enumerate() {
+                if ctx.is_cancelled() {
+                    break;
+                }
+                if path.as_deref() == Some(file.as_str()) {
+                    continue;
+                }
+                ctx.progress(i as f32 / total, Some(file.clone()));
+                // Unreadable files are skipped rather than failing the search
+                let Ok(lines) = lines_for(&db, file, *modified) else { continue };
+                let bar_beats = lines.beats_per_bar.max(1) as f64;
+                for line in &lines.lines {
+                    let line_pitches: Vec<i32> = line.pitches.iter().map(|p| *p as i32).collect();
+                    let target = intervals(&line_pitches);
+                    let shared = grams(&target).intersection(&query_grams).count() as f32;
+                    if shared < needed {
+                        continue;
+                    }
+                    let (cost, start) = best_fit(&query, &target);
+                    let similarity = (1.0 - cost / query.len() as f32).max(0.0);
+                    if similarity < min_similarity {
+                        continue;
+                    }
+                    let beat = line.beats[start];
+                    matches.push(MelodyMatch {
+                        path: file.clone(),
+                        track: line.track,
+                        bar: (beat / bar_beats).floor() as usize + 1,
+                        beat: beat % bar_beats + 1.0,
+                        similarity,
+                        semitones: line_pitches[start] - pitches[0],
+                    });
+                }
+            }
+            matches.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+            matches.truncate(limit);
+            Ok(matches)
+        })
+        .await
+}
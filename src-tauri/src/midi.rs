@@ -0,0 +1,294 @@
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::path::Path;
+
+// Ticks per quarter note for files we write
+pub const PPQ: u16 = 480;
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Note {
+    pub pitch: u8,
+    pub velocity: u8,
+    // In beats from the start of the pattern
+    pub start: f64,
+    pub duration: f64,
+    #[serde(default)]
+    pub channel: u8,
+}
+
+pub fn beats_to_ticks(beats: f64) -> u32 {
+    (beats.max(0.0) * PPQ as f64).round() as u32
+}
+
+// Absolute-time events sorted and turned into the deltas a track stores.
+// Note-offs sort before note-ons at the same tick so repeated notes retrigger.
+pub fn to_track<'a>(mut events: Vec<(u32, TrackEventKind<'a>)>) -> Vec<TrackEvent<'a>> {
+    let order = |kind: &TrackEventKind| match kind {
+        TrackEventKind::Meta(_) => 0,
+        TrackEventKind::Midi { message: MidiMessage::NoteOff { .. }, .. } => 1,
+        _ => 2,
+    };
+    events.sort_by_key(|(tick, kind)| (*tick, order(kind)));
+
+    let mut track = Vec::with_capacity(events.len() + 1);
+    let mut last = 0;
+    for (tick, kind) in events {
+        track.push(TrackEvent { delta: u28::new(tick - last), kind });
+        last = tick;
+    }
+    track.push(TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) });
+    track
+}
+
+pub fn tempo_event(bpm: f64) -> TrackEventKind<'static> {
+    TrackEventKind::Meta(MetaMessage::Tempo(u24::new((60_000_000.0 / bpm.max(1.0)) as u32)))
+}
+
+pub fn time_signature_event(numerator: u8, denominator: u8) -> TrackEventKind<'static> {
+    let denominator_pow2 = denominator.max(1).trailing_zeros() as u8;
+    TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator_pow2, 24, 8))
+}
+
+pub fn note_events(notes: &[Note]) -> Vec<(u32, TrackEventKind<'static>)> {
+    let mut events = Vec::with_capacity(notes.len() * 2);
+    for note in notes {
+        let channel = u4::new(note.channel.min(15));
+        let key = u7::new(note.pitch.min(127));
+        let start = beats_to_ticks(note.start);
+        let end = beats_to_ticks(note.start + note.duration).max(start + 1);
+        events.push((start, TrackEventKind::Midi { channel, message: MidiMessage::NoteOn { key, vel: u7::new(note.velocity.clamp(1, 127)) } }));
+        events.push((end, TrackEventKind::Midi { channel, message: MidiMessage::NoteOff { key, vel: u7::new(0) } }));
+    }
+    events
+}
+
+// Writes a single-track file with tempo and time signature up front
+pub fn write_notes(path: &Path, notes: &[Note], bpm: f64, beats_per_bar: u8) -> Result<(), String> {
+    write_performance(path, notes, Vec::new(), bpm, beats_per_bar)
+}
+
+// Like write_notes, with controllers, bends and other channel events kept
+// alongside the notes
+pub fn write_performance(
+    path: &Path,
+    notes: &[Note],
+    controls: Vec<(u32, TrackEventKind<'static>)>,
+    bpm: f64,
+    beats_per_bar: u8,
+) -> Result<(), String> {
+    let mut events = vec![(0, tempo_event(bpm)), (0, time_signature_event(beats_per_bar, 4))];
+    events.extend(controls);
+    events.extend(note_events(notes));
+
+    let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(u15::new(PPQ))));
+    smf.tracks.push(to_track(events));
+    smf.save(path).map_err(|e| format!("Failed to write MIDI file: {}", e))
+}
+
+pub fn ticks_per_beat(smf: &Smf) -> Result<f64, String> {
+    match smf.header.timing {
+        Timing::Metrical(ppq) => Ok(ppq.as_int().max(1) as f64),
+        Timing::Timecode(..) => Err("MIDI files timed in SMPTE frames aren't supported".to_string()),
+    }
+}
+
+// The file's first time signature as (numerator, denominator), 4/4 when
+// there is none
+pub fn time_signature(smf: &Smf) -> (u8, u8) {
+    smf.tracks
+        .iter()
+        .flatten()
+        .find_map(|event| match event.kind {
+            TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator_pow2, ..)) => {
+                Some((numerator, 1u8.checked_shl(denominator_pow2 as u32).unwrap_or(4)))
+            }
+            _ => None,
+        })
+        .unwrap_or((4, 4))
+}
+
+// The numerator of the file's first time signature, 4 when there is none
+pub fn beats_per_bar(smf: &Smf) -> u8 {
+    time_signature(smf).0
+}
+
+// A track's events at absolute ticks, without its end marker, for edits
+// that must keep everything they don't touch; to_track turns it back
+pub fn absolute<'a>(track: &[TrackEvent<'a>]) -> Vec<(u32, TrackEventKind<'a>)> {
+    let mut tick = 0u32;
+    let mut events = Vec::with_capacity(track.len());
+    for event in track {
+        tick = tick.saturating_add(event.delta.as_int());
+        if !matches!(event.kind, TrackEventKind::Meta(MetaMessage::EndOfTrack)) {
+            events.push((tick, event.kind));
+        }
+    }
+    events
+}
+
+// What transforms do with expression data: pitch bend, pressure, MPE
+// slide (CC 74) and the fine halves of 14-bit controllers
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expression {
+    #[default]
+    Preserve,
+    Strip,
+}
+
+// MPE timbre, sent per note on its member channel
+const SLIDE_CC: u8 = 74;
+// LSBs of 14-bit controllers pair with the MSB 32 below. Bank select and
+// data entry are left alone: dropping those changes what plays.
+const FINE_CCS: std::ops::RangeInclusive<u8> = 33..=63;
+const DATA_ENTRY_LSB: u8 = 38;
+// Fewer one-note channels than this is just a few mono parts
+const MIN_GUESSED_MEMBERS: usize = 3;
+
+pub fn is_expression(kind: &TrackEventKind) -> bool {
+    match kind {
+        TrackEventKind::Midi { message, .. } => match message {
+            MidiMessage::PitchBend { .. } | MidiMessage::ChannelAftertouch { .. } | MidiMessage::Aftertouch { .. } => true,
+            MidiMessage::Controller { controller, .. } => {
+                let controller = controller.as_int();
+                controller == SLIDE_CC || (FINE_CCS.contains(&controller) && controller != DATA_ENTRY_LSB)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+pub fn apply_expression(events: &mut Vec<(u32, TrackEventKind)>, expression: Expression) {
+    if expression == Expression::Strip {
+        events.retain(|(_, kind)| !is_expression(kind));
+    }
+}
+
+// MPE member channels, counted from zero: the zones an MPE configuration
+// message (RPN 6) sets up, or failing that, channels that each play one
+// note at a time with their own pitch bend, as controllers that don't send
+// the message record. Empty for files that aren't MPE.
+pub fn mpe_channels(tracks: &[Vec<(u32, TrackEventKind)>]) -> Vec<u8> {
+    let mut rpn = [(127u8, 127u8); 16];
+    let mut members = Vec::new();
+    let mut sounding = [0usize; 16];
+    let mut overlapped = [false; 16];
+    let mut bent = [false; 16];
+    let mut played = [false; 16];
+    let mut events: Vec<&(u32, TrackEventKind)> = tracks.iter().flatten().collect();
+    events.sort_by_key(|(tick, _)| *tick);
+    for (_, kind) in events {
+        let TrackEventKind::Midi { channel, message } = kind else { continue };
+        let channel = channel.as_int() as usize;
+        match *message {
+            MidiMessage::Controller { controller, value } => match controller.as_int() {
+                101 => rpn[channel].0 = value.as_int(),
+                100 => rpn[channel].1 = value.as_int(),
+                6 if rpn[channel] == (0, 6) => {
+                    let count = value.as_int().min(15);
+                    // The lower zone's manager is channel 1, the upper's 16
+                    match channel {
+                        0 => members.extend(1..=count),
+                        15 => members.extend((15 - count)..15),
+                        _ => {}
+                    }
+                }
+                _ => {}
+            },
+            MidiMessage::PitchBend { .. } => bent[channel] = true,
+            MidiMessage::NoteOn { vel, .. } if vel.as_int() > 0 => {
+                sounding[channel] += 1;
+                played[channel] = true;
+                overlapped[channel] |= sounding[channel] > 1;
+            }
+            MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. } => sounding[channel] = sounding[channel].saturating_sub(1),
+            _ => {}
+        }
+    }
+    if members.is_empty() {
+        let guessed: Vec<u8> = (0..16u8).filter(|c| played[*c as usize] && bent[*c as usize] && !overlapped[*c as usize]).collect();
+        if guessed.len() >= MIN_GUESSED_MEMBERS {
+            members = guessed;
+        }
+    }
+    members.sort_unstable();
+    members.dedup();
+    members
+}
+
+// A file's notes per track, in beats, with its opening tempo and meter
+pub struct MidiFile {
+    pub tracks: Vec<Vec<Note>>,
+    // Every other channel event (program changes, controllers, bends,
+    // pressure) in beats from the start, across all tracks
+    pub controls: Vec<(f64, TrackEventKind<'static>)>,
+    pub bpm: Option<f64>,
+    pub beats_per_bar: u8,
+    // The time signature's denominator: 4 for quarter-note beats
+    pub beat_unit: u8,
+}
+
+impl MidiFile {
+    // Every track's notes in one list, by start
+    pub fn notes(&self) -> Vec<Note> {
+        let mut notes: Vec<Note> = self.tracks.iter().flatten().cloned().collect();
+        notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+        notes
+    }
+}
+
+pub fn read(path: &Path) -> Result<MidiFile, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let smf = Smf::parse(&bytes).map_err(|e| format!("Failed to parse MIDI file: {}", e))?;
+    let ppq = ticks_per_beat(&smf)?;
+    let mut bpm = None;
+    let mut tracks = Vec::with_capacity(smf.tracks.len());
+    let mut controls = Vec::new();
+    for track in &smf.tracks {
+        // Sounding notes: channel, key, start tick, velocity
+        let mut open: Vec<(u8, u8, u32, u8)> = Vec::new();
+        let mut notes = Vec::new();
+        let close = |(channel, key, start, velocity): (u8, u8, u32, u8), end: u32| Note {
+            pitch: key,
+            velocity,
+            start: start as f64 / ppq,
+            duration: end.saturating_sub(start) as f64 / ppq,
+            channel,
+        };
+        let events = absolute(track);
+        for (tick, kind) in &events {
+            match *kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) if bpm.is_none() => {
+                    bpm = Some(60_000_000.0 / tempo.as_int().max(1) as f64);
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    let (key, velocity) = match message {
+                        MidiMessage::NoteOn { key, vel } => (key.as_int(), vel.as_int()),
+                        MidiMessage::NoteOff { key, .. } => (key.as_int(), 0),
+                        _ => {
+                            controls.push((*tick as f64 / ppq, TrackEventKind::Midi { channel, message }));
+                            continue;
+                        }
+                    };
+                    let channel = channel.as_int();
+                    if velocity > 0 {
+                        open.push((channel, key, *tick, velocity));
+                    } else if let Some(i) = open.iter().position(|o| o.0 == channel && o.1 == key) {
+                        // Overlapping repeats of a key end first-in, first-out
+                        notes.push(close(open.remove(i), *tick));
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Notes never released last to the end of the track
+        let end = events.last().map(|(tick, _)| *tick).unwrap_or(0);
+        notes.extend(open.into_iter().map(|o| close(o, end)));
+        notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+        tracks.push(notes);
+    }
+    controls.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (beats_per_bar, beat_unit) = time_signature(&smf);
+    Ok(MidiFile { tracks, controls, bpm, beats_per_bar, beat_unit })
+}
@@ -0,0 +1,357 @@
+use crate::db::Db;
+use crate::midi::{self, Expression, Note};
+use crate::midi_ports;
+use crate::paths;
+use crate::settings;
+use crate::takes;
+use midir::MidiInputConnection;
+use midly::num::{u14, u4, u7};
+use midly::{MidiMessage, PitchBend, TrackEventKind};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+
+const DEFAULT_SECONDS: f64 = 120.0;
+const MAX_SECONDS: f64 = 1800.0;
+// Bounds memory however dense the playing; a minute of busy keyboard with
+// aftertouch is a few thousand messages
+const MAX_EVENTS: usize = 200_000;
+const CAPTURE_FOLDER: &str = "Captures";
+
+struct Event {
+    at: Instant,
+    // Which of the connected ports it came in on
+    port: usize,
+    message: [u8; 3],
+}
+
+struct Buffer {
+    events: VecDeque<Event>,
+    keep: Duration,
+}
+
+impl Buffer {
+    fn push(&mut self, event: Event) {
+        while self.events.len() >= MAX_EVENTS
+            || self.events.front().is_some_and(|e| event.at.duration_since(e.at) > self.keep)
+        {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+struct Listening {
+    ports: Vec<String>,
+    _connections: Vec<MidiInputConnection<()>>,
+    // Filled by a thread of its own: the MIDI callbacks only send events
+    // over, so they never wait on a lock a capture is holding
+    buffer: Arc<Mutex<Buffer>>,
+    seconds: f64,
+}
+
+impl Listening {
+    fn buffer(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Rolling record of everything played on the chosen MIDI inputs
+#[derive(Default)]
+pub struct MidiCapture {
+    listening: Mutex<Option<Listening>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct MidiCaptureStatus {
+    listening: bool,
+    ports: Vec<String>,
+    seconds: f64,
+    buffered_events: usize,
+}
+
+#[derive(serde::Serialize)]
+pub struct CapturedMidi {
+    path: String,
+    notes: usize,
+    bpm: f64,
+}
+
+impl MidiCapture {
+    fn lock(&self) -> MutexGuard<'_, Option<Listening>> {
+        self.listening.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn open(&self, ports: &[String], seconds: f64) -> Result<(), String> {
+        let mut listening = self.lock();
+        *listening = None;
+        let keep = Duration::from_secs_f64(seconds.clamp(1.0, MAX_SECONDS));
+        let buffer = Arc::new(Mutex::new(Buffer { events: VecDeque::new(), keep }));
+        let (sender, incoming) = mpsc::channel::<Event>();
+        let filled = buffer.clone();
+        // Ends once the connections, and the senders they hold, are dropped
+        std::thread::spawn(move || {
+            for event in incoming {
+                filled.lock().unwrap_or_else(|e| e.into_inner()).push(event);
+            }
+        });
+        let mut connections = Vec::with_capacity(ports.len());
+        for (port, name) in ports.iter().enumerate() {
+            let writer = sender.clone();
+            connections.push(midi_ports::connect_input(name, move |bytes| {
+                // Channel messages only; clock and sysex aren't part of a performance
+                if bytes.is_empty() || bytes[0] < 0x80 || bytes[0] >= 0xF0 {
+                    return;
+                }
+                let mut message = [0u8; 3];
+                for (slot, byte) in message.iter_mut().zip(bytes) {
+                    *slot = *byte;
+                }
+                let _ = writer.send(Event { at: Instant::now(), port, message });
+            })?);
+        }
+        *listening = Some(Listening { ports: ports.to_vec(), _connections: connections, buffer, seconds: keep.as_secs_f64() });
+        Ok(())
+    }
+
+    fn status(&self) -> MidiCaptureStatus {
+        match self.lock().as_ref() {
+            Some(l) => MidiCaptureStatus {
+                listening: true,
+                ports: l.ports.clone(),
+                seconds: l.seconds,
+                buffered_events: l.buffer().events.len(),
+            },
+            None => MidiCaptureStatus { listening: false, ports: Vec::new(), seconds: 0.0, buffered_events: 0 },
+        }
+    }
+}
+
+// Pairs note-ons with their note-offs. Times are seconds from `origin`;
+// notes still held at `now` end there.
+fn performed_notes(events: &[&Event], origin: Instant, now: Instant) -> Vec<(f64, f64, u8, u8, u8)> {
+    let seconds = |at: Instant| at.saturating_duration_since(origin).as_secs_f64();
+    let mut held: HashMap<(usize, u8, u8), (f64, u8)> = HashMap::new();
+    let mut notes = Vec::new();
+    for event in events {
+        let [status, key, velocity] = event.message;
+        let channel = status & 0x0F;
+        let slot = (event.port, channel, key);
+        match status & 0xF0 {
+            0x90 if velocity > 0 => {
+                // A retrigger without a note-off ends the earlier note
+                if let Some((start, vel)) = held.insert(slot, (seconds(event.at), velocity)) {
+                    notes.push((start, seconds(event.at), key, vel, channel));
+                }
+            }
+            0x80 | 0x90 => {
+                if let Some((start, vel)) = held.remove(&slot) {
+                    notes.push((start, seconds(event.at), key, vel, channel));
+                }
+            }
+            _ => {}
+        }
+    }
+    for ((_, channel, key), (start, vel)) in held {
+        notes.push((start, seconds(now), key, vel, channel));
+    }
+    notes.sort_by(|a, b| a.0.total_cmp(&b.0));
+    notes
+}
+
+// Everything played besides notes: controllers, bends, pressure and program
+// changes, as (seconds from `origin`, channel, event)
+fn performed_controls(events: &[&Event], origin: Instant) -> Vec<(f64, u8, TrackEventKind<'static>)> {
+    events
+        .iter()
+        .filter_map(|event| {
+            let [status, data1, data2] = event.message;
+            let channel = status & 0x0F;
+            let (a, b) = (u7::new(data1 & 0x7F), u7::new(data2 & 0x7F));
+            let message = match status & 0xF0 {
+                0xA0 => MidiMessage::Aftertouch { key: a, vel: b },
+                0xB0 => MidiMessage::Controller { controller: a, value: b },
+                0xC0 => MidiMessage::ProgramChange { program: a },
+                0xD0 => MidiMessage::ChannelAftertouch { vel: a },
+                0xE0 => MidiMessage::PitchBend { bend: PitchBend(u14::new(a.as_int() as u16 | ((b.as_int() as u16) << 7))) },
+                _ => return None,
+            };
+            let seconds = event.at.saturating_duration_since(origin).as_secs_f64();
+            Some((seconds, channel, TrackEventKind::Midi { channel: u4::new(channel), message }))
+        })
+        .collect()
+}
+
+// Starts a rolling record of `ports` (names from list_midi_inputs) that
+// keeps the last `seconds`, default 120 or "midi_capture.seconds". The
+// choice is remembered across launches.
+#[tauri::command]
+pub async fn start_midi_capture(
+    db: State<'_, Db>,
+    capture: State<'_, MidiCapture>,
+    ports: Vec<String>,
+    seconds: Option<f64>,
+) -> Result<MidiCaptureStatus, String> {
+    if ports.is_empty() {
+        return Err("Pick at least one MIDI input".to_string());
+    }
+    let seconds = match seconds {
+        Some(seconds) => seconds,
+        None => settings::get(&db.lock(), "midi_capture.seconds")?.unwrap_or(DEFAULT_SECONDS),
+    };
+    if seconds.is_nan() || seconds <= 0.0 {
+        return Err("Buffer length must be positive".to_string());
+    }
+    capture.open(&ports, seconds)?;
+    let conn = db.lock();
+    settings::set(&conn, "midi_capture.enabled", &Value::Bool(true))?;
+    settings::set(&conn, "midi_capture.ports", &serde_json::json!(ports))?;
+    settings::set(&conn, "midi_capture.seconds", &serde_json::json!(seconds))?;
+    Ok(capture.status())
+}
+
+#[tauri::command]
+pub async fn stop_midi_capture(db: State<'_, Db>, capture: State<'_, MidiCapture>) -> Result<(), String> {
+    *capture.lock() = None;
+    settings::set(&db.lock(), "midi_capture.enabled", &Value::Bool(false))
+}
+
+#[tauri::command]
+pub async fn get_midi_capture_status(capture: State<'_, MidiCapture>) -> Result<MidiCaptureStatus, String> {
+    Ok(capture.status())
+}
+
+// Resumes the rolling record if the open profile had it on, at launch and
+// after a profile switch
+pub fn reload(app: &AppHandle) {
+    let db = app.state::<Db>();
+    let (enabled, ports, seconds) = {
+        let conn = db.lock();
+        (
+            settings::get::<bool>(&conn, "midi_capture.enabled").ok().flatten().unwrap_or(false),
+            settings::get::<Vec<String>>(&conn, "midi_capture.ports").ok().flatten().unwrap_or_default(),
+            settings::get::<f64>(&conn, "midi_capture.seconds").ok().flatten().unwrap_or(DEFAULT_SECONDS),
+        )
+    };
+    if enabled && !ports.is_empty() {
+        // An unplugged controller shouldn't stop the app
+        let _ = app.state::<MidiCapture>().open(&ports, seconds);
+    } else {
+        *app.state::<MidiCapture>().lock() = None;
+    }
+}
+
+// Writes the notes played in the last `seconds` (the whole buffer when
+// omitted) to a .mid at `bpm` (default 120), with starts and lengths
+// snapped to `grid` beats (default 0.25, a 16th; 0 leaves timing as played).
+// The first note lands on beat one. Bends, pressure and controllers are kept,
+// moving with the note they shape, unless `expression` says to strip them.
+// The file goes to `dest_dir`, or the recordings folder's Captures, and is
+// added to the library.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn capture_recent_midi(
+    app: AppHandle,
+    db: State<'_, Db>,
+    capture: State<'_, MidiCapture>,
+    seconds: Option<f64>,
+    bpm: Option<f64>,
+    grid: Option<f64>,
+    expression: Option<Expression>,
+    dest_dir: Option<String>,
+) -> Result<CapturedMidi, String> {
+    let bpm = bpm.unwrap_or(120.0);
+    if !(20.0..=400.0).contains(&bpm) {
+        return Err("Tempo must be between 20 and 400 BPM".to_string());
+    }
+    let grid = grid.unwrap_or(0.25).max(0.0);
+    let now = Instant::now();
+    let (performed, controls) = {
+        let listening = capture.lock();
+        let l = listening.as_ref().ok_or_else(|| "MIDI capture isn't running".to_string())?;
+        let since = now.checked_sub(Duration::from_secs_f64(seconds.unwrap_or(l.seconds).max(0.0))).unwrap_or(now);
+        let buffer = l.buffer();
+        let recent: Vec<&Event> = buffer.events.iter().filter(|e| e.at >= since).collect();
+        let origin = recent.first().map(|e| e.at).unwrap_or(now);
+        (performed_notes(&recent, origin, now), performed_controls(&recent, origin))
+    };
+    if performed.is_empty() {
+        return Err("No notes were played in that time".to_string());
+    }
+
+    let beats_per_second = bpm / 60.0;
+    let snap = |beats: f64| if grid > 0.0 { (beats / grid).round() * grid } else { beats };
+    let first = snap(performed[0].0 * beats_per_second);
+    let notes: Vec<Note> = performed
+        .iter()
+        .map(|(start, end, pitch, velocity, channel)| {
+            let start_beats = snap(start * beats_per_second) - first;
+            let end_beats = snap(end * beats_per_second) - first;
+            Note {
+                pitch: *pitch,
+                velocity: *velocity,
+                start: start_beats.max(0.0),
+                // Never shorter than one grid step, or a tick when unquantized
+                duration: (end_beats - start_beats).max(if grid > 0.0 { grid } else { 1.0 / midi::PPQ as f64 }),
+                channel: *channel,
+            }
+        })
+        .collect();
+    let snapped = |start: f64| snap(start * beats_per_second) - start * beats_per_second;
+    // The bend set just before a note, since the previous note on its
+    // channel let go, moves with that note so it still leads it
+    let mut leads: Vec<(usize, f64)> = Vec::new();
+    for (start, _, _, _, c) in &performed {
+        let released = performed.iter().filter(|(_, end, _, _, other)| other == c && end <= start).map(|p| p.1).reduce(f64::max);
+        let lead = controls.iter().rposition(|(at, channel, kind)| {
+            channel == c
+                && at < start
+                && released.map_or(true, |r| *at > r)
+                && matches!(kind, TrackEventKind::Midi { message: MidiMessage::PitchBend { .. }, .. })
+        });
+        if let Some(i) = lead {
+            leads.push((i, snapped(*start)));
+        }
+    }
+    // Controls while a note sounds on their channel move as far as it was
+    // snapped
+    let controls: Vec<(u32, TrackEventKind<'static>)> = controls
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (.., kind))| expression.unwrap_or_default() == Expression::Preserve || !midi::is_expression(kind))
+        .map(|(i, (at, channel, kind))| {
+            let shift = leads
+                .iter()
+                .find(|(lead, _)| *lead == i)
+                .map(|(_, shift)| *shift)
+                .or_else(|| performed.iter().find(|(start, end, _, _, c)| *c == channel && *start <= at && at <= *end).map(|(start, ..)| snapped(*start)))
+                .unwrap_or(0.0);
+            (midi::beats_to_ticks(at * beats_per_second + shift - first), kind)
+        })
+        .collect();
+
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let dir = match dest_dir {
+            Some(dir) => paths::to_fs(&dir),
+            None => takes::recordings_folder(&app, &db.lock())?.join(CAPTURE_FOLDER),
+        };
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let stamp = chrono::Local::now().format("%Y-%m-%d %H%M%S");
+        let mut output = dir.join(format!("MIDI capture {}.mid", stamp));
+        let mut n = 2;
+        while output.exists() {
+            output = dir.join(format!("MIDI capture {} {}.mid", stamp, n));
+            n += 1;
+        }
+        midi::write_performance(&output, &notes, controls, bpm, 4)?;
+        takes::index(&db.lock(), &output)?;
+        Ok(CapturedMidi { path: paths::display(&output), notes: notes.len(), bpm })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,291 @@
+use crate::db::Db;
+use crate::midi_ports;
+use crate::playback::Playback;
+use crate::settings;
+use midir::MidiOutputConnection;
+use rusqlite::{params, OptionalExtension};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+
+// MIDI clock runs at 24 pulses per quarter note
+const PPQN: f64 = 24.0;
+const CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const CONTINUE: u8 = 0xFB;
+const STOP: u8 = 0xFC;
+const SONG_POSITION: u8 = 0xF2;
+const DEFAULT_BPM: f64 = 120.0;
+// Playback this close to the top of a file starts rather than continues
+const START_WINDOW: f64 = 0.05;
+// How often the player's transport is looked at, rather than every pulse:
+// it shares a lock with the audio callback
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(25);
+
+struct Running {
+    ports: Vec<String>,
+    follow_playback: bool,
+    // f64 bits, so the UI can change tempo without stopping the clock
+    bpm: Arc<AtomicU64>,
+    manual_bpm: Arc<AtomicU64>,
+    playing: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    transport: mpsc::Sender<u8>,
+    thread: JoinHandle<()>,
+}
+
+// Sends MIDI clock to the chosen output ports so hardware can follow
+#[derive(Default)]
+pub struct MidiClock {
+    running: Mutex<Option<Running>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct MidiClockStatus {
+    running: bool,
+    ports: Vec<String>,
+    bpm: f64,
+    follow_playback: bool,
+    // Whether the followers were last told Start/Continue rather than Stop
+    playing: bool,
+}
+
+fn send(connections: &mut [MidiOutputConnection], message: &[u8]) {
+    for connection in connections.iter_mut() {
+        // A port that vanished mid-run just stops receiving
+        let _ = connection.send(message);
+    }
+}
+
+fn file_bpm(db: &Db, path: &str) -> Option<f64> {
+    db.lock()
+        .query_row(
+            "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = 'bpm'",
+            params![path],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .ok()
+        .flatten()
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|bpm| (20.0..=400.0).contains(bpm))
+}
+
+// What the preview player is doing, as far as the followers need to know
+struct Followed {
+    path: Option<String>,
+    playing: bool,
+}
+
+struct ClockThread {
+    app: AppHandle,
+    connections: Vec<MidiOutputConnection>,
+    follow_playback: bool,
+    bpm: Arc<AtomicU64>,
+    // Tempo set by the user, used when the playing file has none; f64 bits
+    manual_bpm: Arc<AtomicU64>,
+    playing: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    transport: mpsc::Receiver<u8>,
+    followed: Followed,
+    last_followed: Option<Instant>,
+}
+
+impl ClockThread {
+    fn set_playing(&mut self, message: u8) {
+        send(&mut self.connections, &[message]);
+        self.playing.store(message != STOP, Ordering::SeqCst);
+    }
+
+    // Mirrors the player's transport: Start at the top of a file, a song
+    // position and Continue when resuming mid-file, Stop on pause
+    fn follow(&mut self) {
+        if self.last_followed.map_or(false, |at| at.elapsed() < FOLLOW_INTERVAL) {
+            return;
+        }
+        self.last_followed = Some(Instant::now());
+        let state = self.app.state::<Playback>().state();
+        let changed_file = state.path != self.followed.path;
+        if changed_file {
+            if let Some(path) = &state.path {
+                let manual = f64::from_bits(self.manual_bpm.load(Ordering::SeqCst));
+                let bpm = file_bpm(&self.app.state::<Db>(), path).unwrap_or(manual);
+                self.bpm.store(bpm.to_bits(), Ordering::SeqCst);
+            }
+        }
+        if state.playing && (!self.followed.playing || changed_file) {
+            if changed_file && self.followed.playing {
+                self.set_playing(STOP);
+            }
+            if state.position < START_WINDOW {
+                self.set_playing(START);
+            } else {
+                let bpm = f64::from_bits(self.bpm.load(Ordering::SeqCst));
+                // Song position counts 16th notes, 14 bits
+                let sixteenths = ((state.position * bpm / 60.0 * 4.0) as u32).min(0x3FFF);
+                send(&mut self.connections, &[SONG_POSITION, (sixteenths & 0x7F) as u8, (sixteenths >> 7) as u8]);
+                self.set_playing(CONTINUE);
+            }
+        } else if !state.playing && self.followed.playing {
+            self.set_playing(STOP);
+        }
+        self.followed = Followed { path: state.path, playing: state.playing };
+    }
+
+    fn run(mut self) {
+        let mut next = Instant::now();
+        while !self.stop.load(Ordering::SeqCst) {
+            while let Ok(message) = self.transport.try_recv() {
+                self.set_playing(message);
+            }
+            if self.follow_playback {
+                self.follow();
+            }
+            send(&mut self.connections, &[CLOCK]);
+
+            let bpm = f64::from_bits(self.bpm.load(Ordering::SeqCst));
+            // Deadlines are absolute so sleep overshoot doesn't add up to drift
+            next += Duration::from_secs_f64(60.0 / (bpm * PPQN));
+            let now = Instant::now();
+            if next > now {
+                std::thread::sleep(next - now);
+            } else if now - next > Duration::from_millis(100) {
+                // Fell far behind (machine asleep); don't burst to catch up
+                next = now;
+            }
+        }
+        if self.playing.load(Ordering::SeqCst) {
+            send(&mut self.connections, &[STOP]);
+        }
+    }
+}
+
+impl MidiClock {
+    fn stop(&self) {
+        if let Some(running) = self.running.lock().unwrap().take() {
+            running.stop.store(true, Ordering::SeqCst);
+            let _ = running.thread.join();
+        }
+    }
+
+    fn status(&self) -> MidiClockStatus {
+        match self.running.lock().unwrap().as_ref() {
+            Some(r) => MidiClockStatus {
+                running: true,
+                ports: r.ports.clone(),
+                bpm: f64::from_bits(r.bpm.load(Ordering::SeqCst)),
+                follow_playback: r.follow_playback,
+                playing: r.playing.load(Ordering::SeqCst),
+            },
+            None => MidiClockStatus { running: false, ports: Vec::new(), bpm: 0.0, follow_playback: false, playing: false },
+        }
+    }
+}
+
+// Starts sending clock to `ports` (names from list_midi_outputs) at `bpm`.
+// With `follow_playback` (the default) Start/Stop/Continue track the
+// preview player and the tempo follows the playing file's BPM when known.
+#[tauri::command]
+pub async fn start_midi_clock(
+    app: AppHandle,
+    db: State<'_, Db>,
+    clock: State<'_, MidiClock>,
+    ports: Vec<String>,
+    bpm: Option<f64>,
+    follow_playback: Option<bool>,
+) -> Result<MidiClockStatus, String> {
+    if ports.is_empty() {
+        return Err("Pick at least one MIDI output".to_string());
+    }
+    let bpm = bpm.unwrap_or(DEFAULT_BPM);
+    if !(20.0..=400.0).contains(&bpm) {
+        return Err("Tempo must be between 20 and 400 BPM".to_string());
+    }
+    let follow_playback = follow_playback.unwrap_or(true);
+    clock.stop();
+    let connections = ports.iter().map(|name| midi_ports::connect_output(name)).collect::<Result<Vec<_>, _>>()?;
+
+    let shared_bpm = Arc::new(AtomicU64::new(bpm.to_bits()));
+    let manual_bpm = Arc::new(AtomicU64::new(bpm.to_bits()));
+    let playing = Arc::new(AtomicBool::new(false));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (transport, receiver) = mpsc::channel();
+    let worker = ClockThread {
+        app,
+        connections,
+        follow_playback,
+        bpm: shared_bpm.clone(),
+        manual_bpm: manual_bpm.clone(),
+        playing: playing.clone(),
+        stop: stop.clone(),
+        transport: receiver,
+        followed: Followed { path: None, playing: false },
+        last_followed: None,
+    };
+    let thread = std::thread::spawn(move || worker.run());
+    *clock.running.lock().unwrap() = Some(Running {
+        ports: ports.clone(),
+        follow_playback,
+        bpm: shared_bpm,
+        manual_bpm,
+        playing,
+        stop,
+        transport,
+        thread,
+    });
+
+    settings::set(&db.lock(), "midi_clock.ports", &serde_json::json!(ports))?;
+    Ok(clock.status())
+}
+
+// Sends Stop if the followers are playing, then stops the clock
+#[tauri::command]
+pub async fn stop_midi_clock(clock: State<'_, MidiClock>) -> Result<(), String> {
+    clock.stop();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_midi_clock_tempo(clock: State<'_, MidiClock>, bpm: f64) -> Result<MidiClockStatus, String> {
+    if !(20.0..=400.0).contains(&bpm) {
+        return Err("Tempo must be between 20 and 400 BPM".to_string());
+    }
+    match clock.running.lock().unwrap().as_ref() {
+        Some(r) => {
+            // Also the fallback for files without a tempo from here on
+            r.manual_bpm.store(bpm.to_bits(), Ordering::SeqCst);
+            r.bpm.store(bpm.to_bits(), Ordering::SeqCst);
+        }
+        None => return Err("MIDI clock isn't running".to_string()),
+    }
+    Ok(clock.status())
+}
+
+// Sends a transport message by hand: "start", "stop" or "continue"
+#[tauri::command]
+pub async fn send_midi_transport(clock: State<'_, MidiClock>, action: String) -> Result<MidiClockStatus, String> {
+    let message = match action.as_str() {
+        "start" => START,
+        "stop" => STOP,
+        "continue" => CONTINUE,
+        other => return Err(format!("Unknown transport action {}", other)),
+    };
+    match clock.running.lock().unwrap().as_ref() {
+        Some(r) => r.transport.send(message).map_err(|_| "MIDI clock has stopped".to_string())?,
+        None => return Err("MIDI clock isn't running".to_string()),
+    }
+    Ok(clock.status())
+}
+
+#[tauri::command]
+pub async fn get_midi_clock_status(db: State<'_, Db>, clock: State<'_, MidiClock>) -> Result<MidiClockStatus, String> {
+    let mut status = clock.status();
+    if !status.running {
+        // The ports used last time, to preselect in the UI
+        status.ports = settings::get(&db.lock(), "midi_clock.ports")?.unwrap_or_default();
+    }
+    Ok(status)
+}
@@ -0,0 +1,274 @@
+use crate::analysis::NOTE_NAMES;
+use crate::db::{self, Db};
+use crate::jobs::{Priority, Scheduler};
+use crate::midi::{self, Note};
+use crate::paths;
+use crate::quarantine;
+use rusqlite::{params, OptionalExtension};
+use tauri::State;
+
+// Onsets are placed on a 16th grid for syncopation
+const STEPS_PER_BEAT: f64 = 4.0;
+// Pitch classes the summary names
+const SUMMARY_PITCH_CLASSES: usize = 4;
+// General MIDI drums, counted from zero
+const DRUM_CHANNEL: u8 = 9;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TrackFeatures {
+    // From zero, in file order; None for the whole file
+    track: Option<usize>,
+    // The channel most of its notes are on, from zero
+    channel: u8,
+    notes: usize,
+    // Note starts in each bar
+    density: Vec<u32>,
+    mean_density: f32,
+    // Share of notes on each pitch class, C first
+    pitch_classes: [f32; 12],
+    lowest: u8,
+    highest: u8,
+    mean_velocity: f32,
+    // 0 when every note lands on a strong position, toward 1 the more notes
+    // fall on weak ones with nothing on the strong position after them
+    syncopation: f32,
+    // Most notes sounding at once, and the average while any are
+    max_polyphony: usize,
+    mean_polyphony: f32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MidiFeatures {
+    path: String,
+    bpm: Option<f64>,
+    beats_per_bar: u8,
+    beat_unit: u8,
+    bars: usize,
+    overall: TrackFeatures,
+    tracks: Vec<TrackFeatures>,
+    // One paragraph for the assistant
+    summary: String,
+}
+
+fn note_name(pitch: u8) -> String {
+    format!("{}{}", NOTE_NAMES[pitch as usize % 12], pitch as i32 / 12 - 1)
+}
+
+// Metrical weight of a 16th within the bar: 0 for the downbeat, 1 for other
+// beats, 2 for 8ths, 3 for the 16ths between
+fn level(step: i64, steps_per_bar: i64) -> u32 {
+    let within = step.rem_euclid(steps_per_bar);
+    if within == 0 {
+        0
+    } else if within % 4 == 0 {
+        1
+    } else if within % 2 == 0 {
+        2
+    } else {
+        3
+    }
+}
+
+// After Longuet-Higgins and Lee: a note on a weak position counts by how
+// much weaker it is than the next stronger position when nothing starts
+// there, normalized by the most every note could score
+fn syncopation(notes: &[Note], beats_per_bar: f64) -> f32 {
+    let steps_per_bar = (beats_per_bar * STEPS_PER_BEAT).round().max(1.0) as i64;
+    let mut onsets: Vec<i64> = notes.iter().map(|n| (n.start * STEPS_PER_BEAT).round() as i64).collect();
+    onsets.sort_unstable();
+    onsets.dedup();
+    if onsets.is_empty() {
+        return 0.0;
+    }
+    let mut score = 0;
+    for (i, step) in onsets.iter().enumerate() {
+        let weight = level(*step, steps_per_bar);
+        if weight == 0 {
+            continue;
+        }
+        // The next bar's downbeat is always stronger, so this ends
+        let mut next = step + 1;
+        while level(next, steps_per_bar) >= weight {
+            next += 1;
+        }
+        match onsets.get(i + 1) {
+            Some(following) if *following <= next => {}
+            _ => score += weight - level(next, steps_per_bar),
+        }
+    }
+    score as f32 / (onsets.len() * 3) as f32
+}
+
+// Sweeps note starts and ends for the most at once and the time-weighted
+// average while anything sounds
+fn polyphony(notes: &[Note]) -> (usize, f32) {
+    let mut edges: Vec<(f64, i32)> = notes.iter().flat_map(|n| [(n.start, 1), (n.start + n.duration, -1)]).collect();
+    // Ends before starts at the same time, so back-to-back notes aren't stacked
+    edges.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    let (mut sounding, mut most, mut last) = (0i32, 0i32, 0.0);
+    let (mut weighted, mut busy) = (0.0, 0.0);
+    for (time, change) in edges {
+        if sounding > 0 {
+            weighted += sounding as f64 * (time - last);
+            busy += time - last;
+        }
+        sounding += change;
+        most = most.max(sounding);
+        last = time;
+    }
+    (most as usize, if busy > 0.0 { (weighted / busy) as f32 } else { 0.0 })
+}
+
+fn features(track: Option<usize>, notes: &[Note], beats_per_bar: f64, bars: usize) -> TrackFeatures {
+    let mut density = vec![0u32; bars];
+    let mut classes = [0.0f32; 12];
+    let mut channels = [0usize; 16];
+    for note in notes {
+        let bar = ((note.start / beats_per_bar).floor().max(0.0) as usize).min(bars - 1);
+        density[bar] += 1;
+        classes[note.pitch as usize % 12] += 1.0;
+        channels[note.channel as usize % 16] += 1;
+    }
+    let count = notes.len().max(1) as f32;
+    for share in classes.iter_mut() {
+        *share /= count;
+    }
+    let (max_polyphony, mean_polyphony) = polyphony(notes);
+    TrackFeatures {
+        track,
+        channel: (0..16u8).max_by_key(|c| channels[*c as usize]).unwrap_or(0),
+        notes: notes.len(),
+        mean_density: notes.len() as f32 / bars as f32,
+        density,
+        pitch_classes: classes,
+        lowest: notes.iter().map(|n| n.pitch).min().unwrap_or(0),
+        highest: notes.iter().map(|n| n.pitch).max().unwrap_or(0),
+        mean_velocity: notes.iter().map(|n| n.velocity as f32).sum::<f32>() / count,
+        syncopation: syncopation(notes, beats_per_bar),
+        max_polyphony,
+        mean_polyphony,
+    }
+}
+
+fn describe(label: &str, f: &TrackFeatures) -> String {
+    let mut classes: Vec<usize> = (0..12).filter(|c| f.pitch_classes[*c] > 0.0).collect();
+    classes.sort_by(|a, b| f.pitch_classes[*b].total_cmp(&f.pitch_classes[*a]));
+    let classes: Vec<String> = classes
+        .iter()
+        .take(SUMMARY_PITCH_CLASSES)
+        .map(|c| format!("{} {:.0}%", NOTE_NAMES[*c], f.pitch_classes[*c] * 100.0))
+        .collect();
+    format!(
+        "{}{}: {} notes, {:.1} per bar, {}-{}, velocity {:.0} average, syncopation {:.2}, up to {} at once; mostly {}",
+        label,
+        if f.channel == DRUM_CHANNEL { " (drums)" } else { "" },
+        f.notes,
+        f.mean_density,
+        note_name(f.lowest),
+        note_name(f.highest),
+        f.mean_velocity,
+        f.syncopation,
+        f.max_polyphony,
+        classes.join(", "),
+    )
+}
+
+fn analyze(path: &str) -> Result<MidiFeatures, String> {
+    let file = midi::read(&paths::to_fs(path))?;
+    let all = file.notes();
+    if all.is_empty() {
+        return Err("That MIDI file has no notes".to_string());
+    }
+    let beats_per_bar = file.beats_per_bar.max(1) as f64;
+    let end = all.iter().map(|n| n.start + n.duration).fold(0.0, f64::max);
+    let bars = ((end / beats_per_bar).ceil() as usize).max(1);
+
+    let overall = features(None, &all, beats_per_bar, bars);
+    let tracks: Vec<TrackFeatures> = file
+        .tracks
+        .iter()
+        .enumerate()
+        .filter(|(_, notes)| !notes.is_empty())
+        .map(|(i, notes)| features(Some(i), notes, beats_per_bar, bars))
+        .collect();
+
+    let mut parts = vec![format!(
+        "{}{}/{}, {} bars",
+        file.bpm.map(|b| format!("{:.0} BPM, ", b)).unwrap_or_default(),
+        file.beats_per_bar,
+        file.beat_unit,
+        bars
+    )];
+    if tracks.len() > 1 {
+        parts.extend(tracks.iter().map(|t| describe(&format!("track {}", t.track.unwrap_or(0) + 1), t)));
+    } else {
+        parts.push(describe("notes", &overall));
+    }
+    Ok(MidiFeatures {
+        path: path.to_string(),
+        bpm: file.bpm,
+        beats_per_bar: file.beats_per_bar,
+        beat_unit: file.beat_unit,
+        bars,
+        overall,
+        tracks,
+        summary: parts.join("; "),
+    })
+}
+
+// Only while the file is as it was when analyzed
+pub fn summary_for(conn: &rusqlite::Connection, path: &str) -> Result<Option<String>, String> {
+    conn.query_row(
+        "SELECT m.summary FROM midi_features m JOIN files f ON f.path = m.path WHERE m.path = ?1 AND m.modified = f.modified",
+        params![path],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+// Note density per bar, pitch-class spread, syncopation, velocity and
+// polyphony of a MIDI file, per track and overall. Kept per version of an
+// indexed file so the assistant can refer to it.
+#[tauri::command]
+pub async fn analyze_midi_features(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    force: Option<bool>,
+) -> Result<MidiFeatures, String> {
+    let modified: Option<i64> = db
+        .lock()
+        .query_row("SELECT modified FROM files WHERE path = ?1", params![path], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let (Some(modified), false) = (modified, force.unwrap_or(false)) {
+        let cached: Option<String> = db
+            .lock()
+            .query_row("SELECT data FROM midi_features WHERE path = ?1 AND modified = ?2", params![path, modified], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if let Some(features) = cached.and_then(|json| serde_json::from_str(&json).ok()) {
+            return Ok(features);
+        }
+    }
+
+    let db = db.inner().clone();
+    let label = format!("MIDI features {}", path);
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |_| {
+            let features = quarantine::guard(&db, &path, || analyze(&path))?;
+            // Files outside the library have no version to check against
+            if let Some(modified) = modified {
+                let json = serde_json::to_string(&features).map_err(|e| e.to_string())?;
+                db.lock()
+                    .execute(
+                        "INSERT OR REPLACE INTO midi_features (path, modified, data, summary, analyzed_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![path, modified, json, features.summary, db::now()],
+                    )
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(features)
+        })
+        .await
+}
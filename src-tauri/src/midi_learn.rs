@@ -0,0 +1,242 @@
+use crate::db::Db;
+use crate::midi_ports;
+use crate::settings;
+use midir::MidiInputConnection;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+// App actions a controller can drive. They reach the UI as "midi-action"
+// events, since it knows what's selected and what "next" means.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    NextSample,
+    PreviousSample,
+    PlayStop,
+    TagFavorite,
+    // Continuous: the control's position is the volume
+    Volume,
+}
+
+impl Action {
+    fn continuous(self) -> bool {
+        matches!(self, Action::Volume)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlKind {
+    Cc,
+    Note,
+}
+
+// A control on a controller: which port, channel (0-15) and CC or note number
+#[derive(Clone, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Control {
+    port: String,
+    channel: u8,
+    kind: ControlKind,
+    number: u8,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Mapping {
+    control: Control,
+    action: Action,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct ActionEvent {
+    action: Action,
+    // 0-1: the control's position for continuous actions, 1 for presses
+    value: f32,
+}
+
+struct Shared {
+    mappings: Vec<Mapping>,
+    // Set while waiting for the control to assign to this action
+    learning: Option<Action>,
+}
+
+struct Listening {
+    ports: Vec<String>,
+    _connections: Vec<MidiInputConnection<()>>,
+}
+
+// Listens to the chosen controller inputs and turns mapped controls into
+// app actions
+pub struct MidiLearn {
+    shared: Arc<Mutex<Shared>>,
+    listening: Mutex<Option<Listening>>,
+}
+
+fn locked<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Default for MidiLearn {
+    fn default() -> Self {
+        MidiLearn {
+            shared: Arc::new(Mutex::new(Shared { mappings: Vec::new(), learning: None })),
+            listening: Mutex::new(None),
+        }
+    }
+}
+
+// A note-on or CC from `port` as a control and its value (0-127)
+fn parse(port: &str, bytes: &[u8]) -> Option<(Control, u8)> {
+    let (&status, rest) = bytes.split_first()?;
+    let (&number, rest) = rest.split_first()?;
+    let value = rest.first().copied().unwrap_or(0);
+    let kind = match status & 0xF0 {
+        0xB0 => ControlKind::Cc,
+        // Note-offs (and zero-velocity note-ons) release; only presses act
+        0x90 if value > 0 => ControlKind::Note,
+        _ => return None,
+    };
+    Some((Control { port: port.to_string(), channel: status & 0x0F, kind, number }, value))
+}
+
+fn save(app: &AppHandle, mappings: &[Mapping]) {
+    let db = app.state::<Db>();
+    let value = serde_json::to_value(mappings).unwrap_or_default();
+    let _ = settings::set(&db.lock(), "midi_learn.mappings", &value);
+}
+
+fn handle(app: &AppHandle, shared: &Mutex<Shared>, port: &str, bytes: &[u8]) {
+    let Some((control, value)) = parse(port, bytes) else { return };
+    let mut shared = locked(shared);
+    if let Some(action) = shared.learning {
+        // A button's release (CC 0) after the press isn't a second control
+        if control.kind == ControlKind::Cc && value == 0 && !action.continuous() {
+            return;
+        }
+        shared.learning = None;
+        shared.mappings.retain(|m| m.control != control);
+        let mapping = Mapping { control, action };
+        shared.mappings.push(mapping.clone());
+        save(app, &shared.mappings);
+        let _ = app.emit("midi-learned", &mapping);
+        return;
+    }
+    let Some(mapping) = shared.mappings.iter().find(|m| m.control == control) else { return };
+    let event = if mapping.action.continuous() {
+        ActionEvent { action: mapping.action, value: value as f32 / 127.0 }
+    } else if control.kind == ControlKind::Cc && value == 0 {
+        // Button release
+        return;
+    } else {
+        ActionEvent { action: mapping.action, value: 1.0 }
+    };
+    drop(shared);
+    let _ = app.emit("midi-action", &event);
+}
+
+impl MidiLearn {
+    fn open(&self, app: &AppHandle, ports: &[String]) -> Result<(), String> {
+        let mut listening = locked(&self.listening);
+        *listening = None;
+        // Mapping, saving and emitting happen on a thread of their own; the
+        // MIDI callbacks only copy the bytes over. It ends once the
+        // connections, and the senders they hold, are dropped.
+        let (sender, incoming) = mpsc::channel::<(usize, [u8; 3])>();
+        let (app, shared, names) = (app.clone(), self.shared.clone(), ports.to_vec());
+        std::thread::spawn(move || {
+            for (port, bytes) in incoming {
+                handle(&app, &shared, &names[port], &bytes);
+            }
+        });
+        let mut connections = Vec::with_capacity(ports.len());
+        for (port, name) in ports.iter().enumerate() {
+            let sender = sender.clone();
+            connections.push(midi_ports::connect_input(name, move |bytes| {
+                // Notes and CCs are three bytes; a short message reads as value 0
+                let mut message = [0u8; 3];
+                for (slot, byte) in message.iter_mut().zip(bytes) {
+                    *slot = *byte;
+                }
+                let _ = sender.send((port, message));
+            })?);
+        }
+        *listening = Some(Listening { ports: ports.to_vec(), _connections: connections });
+        Ok(())
+    }
+}
+
+// Loads the open profile's mappings and reopens the controller inputs it
+// used last, at launch and after a profile switch
+pub fn reload(app: &AppHandle) {
+    let (mappings, ports) = {
+        let db = app.state::<Db>();
+        let conn = db.lock();
+        (
+            settings::get::<Vec<Mapping>>(&conn, "midi_learn.mappings").ok().flatten().unwrap_or_default(),
+            settings::get::<Vec<String>>(&conn, "midi_learn.ports").ok().flatten().unwrap_or_default(),
+        )
+    };
+    let learn = app.state::<MidiLearn>();
+    {
+        let mut shared = locked(&learn.shared);
+        shared.mappings = mappings;
+        shared.learning = None;
+    }
+    if ports.is_empty() {
+        *locked(&learn.listening) = None;
+    } else {
+        // A controller that isn't plugged in shouldn't stop the app
+        let _ = learn.open(app, &ports);
+    }
+}
+
+// Listens for mapped controls on `ports` (names from list_midi_inputs);
+// an empty list stops listening
+#[tauri::command]
+pub async fn set_midi_control_ports(
+    app: AppHandle,
+    db: State<'_, Db>,
+    learn: State<'_, MidiLearn>,
+    ports: Vec<String>,
+) -> Result<(), String> {
+    if ports.is_empty() {
+        *locked(&learn.listening) = None;
+    } else {
+        learn.open(&app, &ports)?;
+    }
+    settings::set(&db.lock(), "midi_learn.ports", &serde_json::json!(ports))
+}
+
+#[tauri::command]
+pub async fn get_midi_control_ports(learn: State<'_, MidiLearn>) -> Result<Vec<String>, String> {
+    Ok(locked(&learn.listening).as_ref().map(|l| l.ports.clone()).unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn list_midi_mappings(learn: State<'_, MidiLearn>) -> Result<Vec<Mapping>, String> {
+    Ok(locked(&learn.shared).mappings.clone())
+}
+
+// Assigns the next control moved on a listened port to `action`; the
+// result arrives as a "midi-learned" event
+#[tauri::command]
+pub async fn learn_midi_mapping(learn: State<'_, MidiLearn>, action: Action) -> Result<(), String> {
+    if locked(&learn.listening).is_none() {
+        return Err("Choose a MIDI controller input first".to_string());
+    }
+    locked(&learn.shared).learning = Some(action);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn cancel_midi_learn(learn: State<'_, MidiLearn>) -> Result<(), String> {
+    locked(&learn.shared).learning = None;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_midi_mapping(app: AppHandle, learn: State<'_, MidiLearn>, control: Control) -> Result<Vec<Mapping>, String> {
+    let mut shared = locked(&learn.shared);
+    shared.mappings.retain(|m| m.control != control);
+    save(&app, &shared.mappings);
+    Ok(shared.mappings.clone())
+}
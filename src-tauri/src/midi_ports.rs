@@ -0,0 +1,65 @@
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+
+const CLIENT: &str = "Music Organizer";
+
+// Names of the MIDI inputs the system offers right now
+pub fn input_ports() -> Result<Vec<String>, String> {
+    let input = MidiInput::new(CLIENT).map_err(|e| format!("MIDI unavailable: {}", e))?;
+    Ok(input.ports().iter().filter_map(|port| input.port_name(port).ok()).collect())
+}
+
+// Listens on the input called `name`, handing each message's bytes to
+// `on_message` on the MIDI thread. Dropping the connection closes it.
+pub fn connect_input(name: &str, on_message: impl FnMut(&[u8]) + Send + 'static) -> Result<MidiInputConnection<()>, String> {
+    connect(name, Ignore::All, on_message)
+}
+
+// Same as connect_input but with sysex let through, which midir drops by
+// default
+pub fn connect_sysex_input(name: &str, on_message: impl FnMut(&[u8]) + Send + 'static) -> Result<MidiInputConnection<()>, String> {
+    connect(name, Ignore::None, on_message)
+}
+
+fn connect(name: &str, ignore: Ignore, mut on_message: impl FnMut(&[u8]) + Send + 'static) -> Result<MidiInputConnection<()>, String> {
+    let mut input = MidiInput::new(CLIENT).map_err(|e| format!("MIDI unavailable: {}", e))?;
+    input.ignore(ignore);
+    let port = input
+        .ports()
+        .into_iter()
+        .find(|port| input.port_name(port).map(|n| n == name).unwrap_or(false))
+        .ok_or_else(|| format!("MIDI input {} not found", name))?;
+    input
+        .connect(&port, CLIENT, move |_, message, _| on_message(message), ())
+        .map_err(|e| format!("Failed to open MIDI input {}: {}", name, e))
+}
+
+pub fn output_ports() -> Result<Vec<String>, String> {
+    let output = MidiOutput::new(CLIENT).map_err(|e| format!("MIDI unavailable: {}", e))?;
+    Ok(output.ports().iter().filter_map(|port| output.port_name(port).ok()).collect())
+}
+
+pub fn connect_output(name: &str) -> Result<MidiOutputConnection, String> {
+    let output = MidiOutput::new(CLIENT).map_err(|e| format!("MIDI unavailable: {}", e))?;
+    let port = output
+        .ports()
+        .into_iter()
+        .find(|port| output.port_name(port).map(|n| n == name).unwrap_or(false))
+        .ok_or_else(|| format!("MIDI output {} not found", name))?;
+    output
+        .connect(&port, CLIENT)
+        .map_err(|e| format!("Failed to open MIDI output {}: {}", name, e))
+}
+
+#[tauri::command]
+pub async fn list_midi_inputs() -> Result<Vec<String>, String> {
+    tokio::task::spawn_blocking(input_ports)
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn list_midi_outputs() -> Result<Vec<String>, String> {
+    tokio::task::spawn_blocking(output_ports)
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,145 @@
+use crate::db::Db;
+use crate::derived;
+use crate::jobs::{Priority, Scheduler};
+use crate::midi;
+use crate::paths;
+use midly::num::{u4, u7};
+use midly::{MidiMessage, Smf, TrackEventKind};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tauri::State;
+
+const SOURCE: &str = "remap";
+// General MIDI drums, counted from zero
+const DRUM_CHANNEL: u8 = 9;
+
+// Channels are 1-16 as players show them; programs 0-127 as the file
+// stores them
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct MidiMapping {
+    // Moves a channel's events to another channel
+    channels: HashMap<u8, u8>,
+    // Swaps one program for another wherever it's selected
+    programs: HashMap<u8, u8>,
+    // Puts every event on channel 10, as GM drums, and drops program changes
+    drums: bool,
+    // Drops every program change, leaving players on their defaults
+    strip_programs: bool,
+    // Selects a program on a channel from the start, replacing the file's own
+    // program changes there
+    set_programs: HashMap<u8, u8>,
+    // Keep or drop pitch bend, pressure and fine controller data
+    expression: midi::Expression,
+}
+
+#[derive(serde::Serialize)]
+pub struct Remapped {
+    path: String,
+    // Channel events moved to another channel
+    moved: usize,
+    programs_changed: usize,
+    programs_removed: usize,
+}
+
+fn check(mapping: &MidiMapping) -> Result<(), String> {
+    let mut channels = mapping.channels.iter().flat_map(|(from, to)| [from, to]).chain(mapping.set_programs.keys());
+    if let Some(channel) = channels.find(|c| !(1..=16).contains(*c)) {
+        return Err(format!("Channel {} is out of range; channels go from 1 to 16", channel));
+    }
+    let mut programs = mapping.programs.iter().flat_map(|(from, to)| [from, to]).chain(mapping.set_programs.values());
+    if let Some(program) = programs.find(|p| **p > 127) {
+        return Err(format!("Program {} is out of range; programs go from 0 to 127", program));
+    }
+    Ok(())
+}
+
+// Applies the mapping to every track, then puts any set programs at the
+// start of the first
+fn remap<'a>(smf: &Smf<'a>, mapping: &MidiMapping) -> (Smf<'a>, usize, usize, usize) {
+    let (mut moved, mut changed, mut removed) = (0, 0, 0);
+    let target = |channel: u8| {
+        if mapping.drums {
+            DRUM_CHANNEL
+        } else {
+            mapping.channels.get(&(channel + 1)).map(|c| c - 1).unwrap_or(channel)
+        }
+    };
+    let mut tracks: Vec<_> = smf
+        .tracks
+        .iter()
+        .map(|track| {
+            let mut events = midi::absolute(track);
+            midi::apply_expression(&mut events, mapping.expression);
+            events.retain_mut(|(_, kind)| {
+                let TrackEventKind::Midi { channel, message } = kind else { return true };
+                let to = target(channel.as_int());
+                if let MidiMessage::ProgramChange { program } = message {
+                    if mapping.drums || mapping.strip_programs || mapping.set_programs.contains_key(&(to + 1)) {
+                        removed += 1;
+                        return false;
+                    }
+                    if let Some(new) = mapping.programs.get(&program.as_int()) {
+                        *program = u7::new(*new);
+                        changed += 1;
+                    }
+                }
+                if to != channel.as_int() {
+                    *channel = u4::new(to);
+                    moved += 1;
+                }
+                true
+            });
+            events
+        })
+        .collect();
+
+    if !mapping.set_programs.is_empty() {
+        if tracks.is_empty() {
+            tracks.push(Vec::new());
+        }
+        let mut set: Vec<(&u8, &u8)> = mapping.set_programs.iter().collect();
+        set.sort();
+        let programs: Vec<_> = set
+            .into_iter()
+            .map(|(channel, program)| {
+                let message = MidiMessage::ProgramChange { program: u7::new(*program) };
+                (0, TrackEventKind::Midi { channel: u4::new(channel - 1), message })
+            })
+            .collect();
+        changed += programs.len();
+        // At the front, so they sort ahead of notes that also start at tick 0
+        tracks[0].splice(0..0, programs);
+    }
+    let tracks = tracks.into_iter().map(midi::to_track).collect();
+    (Smf { header: smf.header, tracks }, moved, changed, removed)
+}
+
+// Writes a copy of a MIDI file with its channels and program changes
+// rewritten, so old files play right through General MIDI players and
+// DAWs: everything onto the drum channel, programs swapped or stripped, or
+// a program fixed per channel.
+#[tauri::command]
+pub async fn remap_midi(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    mapping: MidiMapping,
+    dest_dir: Option<String>,
+) -> Result<Remapped, String> {
+    check(&mapping)?;
+    let db = db.inner().clone();
+    let label = format!("Remap {}", path);
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |_| {
+            let bytes = fs::read(paths::to_fs(&path)).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            let smf = Smf::parse(&bytes).map_err(|e| format!("Failed to parse MIDI file: {}", e))?;
+            let (remapped, moved, programs_changed, programs_removed) = remap(&smf, &mapping);
+            let save = |out: &Path| remapped.save(out).map_err(|e| format!("Failed to write MIDI file: {}", e));
+            let attributes = derived::carried_attributes(&db, &path)?;
+            let output = derived::write_midi(&db, &path, dest_dir.as_deref(), "remap", save, SOURCE, &attributes)?;
+            Ok(Remapped { path: output, moved, programs_changed, programs_removed })
+        })
+        .await
+}
@@ -0,0 +1,376 @@
+use crate::collections::safe_file_name;
+use crate::db::Db;
+use crate::derived;
+use crate::jobs::{Priority, Scheduler};
+use crate::midi::{self, PPQ};
+use crate::paths;
+use crate::takes;
+use midly::num::u15;
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::fs;
+use std::path::Path;
+use tauri::State;
+
+const SOURCE: &str = "split";
+
+type Events<'a> = Vec<(u32, TrackEventKind<'a>)>;
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitBy {
+    Track,
+    Channel,
+    // Sections between markers, each starting at zero
+    Marker,
+}
+
+#[derive(serde::Serialize)]
+pub struct Merged {
+    path: String,
+    tracks: usize,
+    // Clips whose own tempo differed; they now follow the first clip's
+    retimed: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SplitPart {
+    label: String,
+    path: String,
+    notes: usize,
+}
+
+// Events that make up the tempo map rather than a part
+fn is_tempo_map(kind: &TrackEventKind) -> bool {
+    matches!(
+        kind,
+        TrackEventKind::Meta(MetaMessage::Tempo(_) | MetaMessage::TimeSignature(..) | MetaMessage::KeySignature(..))
+    )
+}
+
+fn is_track_name(kind: &TrackEventKind) -> bool {
+    matches!(kind, TrackEventKind::Meta(MetaMessage::TrackName(_)))
+}
+
+fn is_note_on(kind: &TrackEventKind) -> bool {
+    matches!(kind, TrackEventKind::Midi { message: MidiMessage::NoteOn { vel, .. }, .. } if vel.as_int() > 0)
+}
+
+fn note_count(events: &Events) -> usize {
+    events.iter().filter(|(_, kind)| is_note_on(kind)).count()
+}
+
+fn track_name(events: &Events) -> Option<String> {
+    events.iter().find_map(|(_, kind)| match kind {
+        TrackEventKind::Meta(MetaMessage::TrackName(name)) => Some(String::from_utf8_lossy(name).trim().to_string()),
+        _ => None,
+    })
+}
+
+fn first_bpm(tracks: &[Events]) -> Option<f64> {
+    tracks.iter().flatten().find_map(|(_, kind)| match kind {
+        TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => Some(60_000_000.0 / tempo.as_int().max(1) as f64),
+        _ => None,
+    })
+}
+
+// Every track's tempo, meter and key changes as one conductor track
+fn conductor<'a>(tracks: &[Events<'a>]) -> Events<'a> {
+    tracks.iter().flatten().filter(|(_, kind)| is_tempo_map(kind)).copied().collect()
+}
+
+fn parse(bytes: &[u8]) -> Result<(Smf<'_>, Vec<Events<'_>>), String> {
+    let smf = Smf::parse(bytes).map_err(|e| format!("Failed to parse MIDI file: {}", e))?;
+    let tracks = smf.tracks.iter().map(|t| midi::absolute(t)).collect();
+    Ok((smf, tracks))
+}
+
+fn save(path: &Path, timing: Timing, tracks: Vec<Events>) -> Result<(), String> {
+    let smf = Smf { header: Header::new(Format::Parallel, timing), tracks: tracks.into_iter().map(midi::to_track).collect() };
+    smf.save(path).map_err(|e| format!("Failed to write MIDI file: {}", e))
+}
+
+// Combines MIDI clips into one file, each clip's tracks kept separate and
+// named after it. The first clip's tempo map is used for all of them, so
+// clips line up by beat. An existing `out` is only replaced with `overwrite`.
+#[tauri::command]
+pub async fn merge_midi(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    paths: Vec<String>,
+    out: String,
+    overwrite: Option<bool>,
+) -> Result<Merged, String> {
+    if paths.len() < 2 {
+        return Err("Pick at least two MIDI files to merge".to_string());
+    }
+    let db = db.inner().clone();
+    let label = format!("Merge {} MIDI files", paths.len());
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |_| {
+            let destination = paths::to_fs(&out);
+            if paths.iter().any(|p| paths::to_fs(p) == destination) {
+                return Err("Merge into a new file, not one of the clips".to_string());
+            }
+            if destination.exists() && !overwrite.unwrap_or(false) {
+                return Err(format!("{} already exists", out));
+            }
+            let contents = paths
+                .iter()
+                .map(|p| fs::read(paths::to_fs(p)).map_err(|e| format!("Failed to read {}: {}", p, e)))
+                .collect::<Result<Vec<_>, String>>()?;
+            let mut clips = Vec::with_capacity(contents.len());
+            for bytes in &contents {
+                let (smf, tracks) = parse(bytes)?;
+                clips.push((midi::ticks_per_beat(&smf)?, tracks));
+            }
+
+            let (first_ppq, first_tracks) = &clips[0];
+            let tempo = first_bpm(first_tracks);
+            let mut map: Events = conductor(first_tracks).into_iter().map(|(tick, kind)| (rescale(tick, *first_ppq), kind)).collect();
+            if !map.iter().any(|(_, kind)| matches!(kind, TrackEventKind::Meta(MetaMessage::Tempo(_)))) {
+                map.push((0, midi::tempo_event(120.0)));
+            }
+
+            // Names first, since the events borrow them
+            let names: Vec<Vec<String>> = paths
+                .iter()
+                .zip(&clips)
+                .map(|(path, (_, tracks))| {
+                    let stem = Path::new(path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+                    tracks
+                        .iter()
+                        .map(|events| match track_name(events).filter(|n| !n.is_empty()) {
+                            Some(name) => format!("{} - {}", stem, name),
+                            None => stem.clone(),
+                        })
+                        .collect()
+                })
+                .collect();
+
+            let mut tracks = vec![map];
+            let mut retimed = Vec::new();
+            for ((path, (ppq, clip)), clip_names) in paths.iter().zip(&clips).zip(&names) {
+                if matches!((first_bpm(clip), tempo), (Some(a), Some(b)) if (a - b).abs() > 0.01) {
+                    retimed.push(path.clone());
+                }
+                for (events, name) in clip.iter().zip(clip_names) {
+                    if note_count(events) == 0 {
+                        continue;
+                    }
+                    let mut part: Events = vec![(0, TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())))];
+                    part.extend(
+                        events
+                            .iter()
+                            .filter(|(_, kind)| !is_tempo_map(kind) && !is_track_name(kind))
+                            .map(|(tick, kind)| (rescale(*tick, *ppq), *kind)),
+                    );
+                    tracks.push(part);
+                }
+            }
+            let count = tracks.len() - 1;
+            if count == 0 {
+                return Err("None of those files have notes".to_string());
+            }
+
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            save(&destination, Timing::Metrical(u15::new(PPQ)), tracks)?;
+            takes::index(&db.lock(), &destination)?;
+            Ok(Merged { path: paths::display(&destination), tracks: count, retimed })
+        })
+        .await
+}
+
+// Ticks at `ppq` to ticks at the PPQ we write
+fn rescale(tick: u32, ppq: f64) -> u32 {
+    (tick as f64 * PPQ as f64 / ppq).round() as u32
+}
+
+// Each part with notes and its label
+fn by_track<'a>(tracks: &[Events<'a>]) -> Result<Vec<(String, Events<'a>)>, String> {
+    let parts: Vec<(String, Events)> = tracks
+        .iter()
+        .enumerate()
+        .filter(|(_, events)| note_count(events) > 0)
+        .map(|(i, events)| {
+            let label = track_name(events).filter(|n| !n.is_empty()).unwrap_or_else(|| format!("track {}", i + 1));
+            let part = events.iter().filter(|(_, kind)| !is_tempo_map(kind) && !is_track_name(kind)).copied().collect();
+            (label, part)
+        })
+        .collect();
+    if parts.len() < 2 {
+        return Err("Only one track has notes; try splitting by channel".to_string());
+    }
+    Ok(parts)
+}
+
+// An MPE zone, its manager channel included, stays together as one part:
+// its notes only make sense with each other
+fn by_channel<'a>(tracks: &[Events<'a>]) -> Result<Vec<(String, Events<'a>)>, String> {
+    let members = midi::mpe_channels(tracks);
+    let in_zone = |channel: u8| {
+        members.contains(&channel)
+            || (channel == 0 && members.contains(&1))
+            || (channel == 15 && members.contains(&14))
+    };
+    let mut channels: Vec<Events> = vec![Vec::new(); 16];
+    let mut zone: Events = Vec::new();
+    for (tick, kind) in tracks.iter().flatten() {
+        if let TrackEventKind::Midi { channel, .. } = kind {
+            if in_zone(channel.as_int()) {
+                zone.push((*tick, *kind));
+            } else {
+                channels[channel.as_int() as usize].push((*tick, *kind));
+            }
+        }
+    }
+    let mut parts: Vec<(String, Events)> = channels
+        .into_iter()
+        .enumerate()
+        .filter(|(_, events)| note_count(events) > 0)
+        .map(|(i, events)| (format!("channel {}", i + 1), events))
+        .collect();
+    if note_count(&zone) > 0 {
+        parts.insert(0, ("MPE".to_string(), zone));
+    }
+    if parts.len() < 2 {
+        return Err("All the notes are on one channel; try splitting by track".to_string());
+    }
+    Ok(parts)
+}
+
+// Each section's notes, with their note-offs even past the next marker,
+// and the controller and program settings in force when it starts
+fn by_marker<'a>(tracks: &[Events<'a>]) -> Result<Vec<(String, Events<'a>)>, String> {
+    let mut markers: Vec<(u32, String)> = tracks
+        .iter()
+        .flatten()
+        .filter_map(|(tick, kind)| match kind {
+            TrackEventKind::Meta(MetaMessage::Marker(text)) => Some((*tick, String::from_utf8_lossy(text).trim().to_string())),
+            _ => None,
+        })
+        .collect();
+    if markers.is_empty() {
+        return Err("This file has no markers".to_string());
+    }
+    markers.sort_by_key(|(tick, _)| *tick);
+    if markers[0].0 > 0 {
+        markers.insert(0, (0, "start".to_string()));
+    }
+
+    let mut parts = Vec::new();
+    for (i, (start, name)) in markers.iter().enumerate() {
+        let end = markers.get(i + 1).map(|(tick, _)| *tick).unwrap_or(u32::MAX);
+        let mut part = Vec::new();
+        for events in tracks {
+            // Notes begun in this section, by channel and key
+            let mut open: Vec<(u8, u8)> = Vec::new();
+            // Latest program, controller and tempo values before the section,
+            // which go first so the section's own changes override them
+            let mut setup: Events = Vec::new();
+            let mut section: Events = Vec::new();
+            for (tick, kind) in events {
+                let (tick, kind) = (*tick, *kind);
+                if tick >= end && open.is_empty() {
+                    break;
+                }
+                match kind {
+                    TrackEventKind::Midi { channel, message } => {
+                        let key = match message {
+                            MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => Some((key.as_int(), true)),
+                            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => Some((key.as_int(), false)),
+                            _ => None,
+                        };
+                        match key {
+                            Some((key, true)) if tick >= *start && tick < end => {
+                                open.push((channel.as_int(), key));
+                                section.push((tick - start, kind));
+                            }
+                            Some((key, false)) => {
+                                if let Some(j) = open.iter().position(|o| *o == (channel.as_int(), key)) {
+                                    open.remove(j);
+                                    section.push((tick.saturating_sub(*start), kind));
+                                }
+                            }
+                            Some(_) => {}
+                            None if tick < *start => {
+                                setup.retain(|(_, old)| !same_setting(old, &kind));
+                                setup.push((0, kind));
+                            }
+                            None if tick < end => section.push((tick - start, kind)),
+                            None => {}
+                        }
+                    }
+                    _ if is_tempo_map(&kind) && tick < *start => {
+                        setup.retain(|(_, old)| !same_setting(old, &kind));
+                        setup.push((0, kind));
+                    }
+                    _ if is_tempo_map(&kind) && tick < end => section.push((tick - start, kind)),
+                    _ => {}
+                }
+            }
+            part.extend(setup);
+            part.extend(section);
+        }
+        if note_count(&part) > 0 {
+            parts.push((if name.is_empty() { format!("section {}", i + 1) } else { name.clone() }, part));
+        }
+    }
+    Ok(parts)
+}
+
+// Whether `new` replaces `old` as the current value of the same setting
+fn same_setting(old: &TrackEventKind, new: &TrackEventKind) -> bool {
+    match (old, new) {
+        (TrackEventKind::Midi { channel: a, message: x }, TrackEventKind::Midi { channel: b, message: y }) => {
+            a == b
+                && match (x, y) {
+                    (MidiMessage::Controller { controller: c, .. }, MidiMessage::Controller { controller: d, .. }) => c == d,
+                    _ => std::mem::discriminant(x) == std::mem::discriminant(y),
+                }
+        }
+        (TrackEventKind::Meta(x), TrackEventKind::Meta(y)) => std::mem::discriminant(x) == std::mem::discriminant(y),
+        _ => false,
+    }
+}
+
+// Splits a MIDI file into one file per track, channel or marked section,
+// written next to it or into `dest_dir`. Each part keeps the tempo map, and
+// its expression data unless `expression` says to strip it.
+#[tauri::command]
+pub async fn split_midi(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    by: SplitBy,
+    expression: Option<midi::Expression>,
+    dest_dir: Option<String>,
+) -> Result<Vec<SplitPart>, String> {
+    let db = db.inner().clone();
+    let label = format!("Split {}", path);
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |_| {
+            let bytes = fs::read(paths::to_fs(&path)).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            let (smf, tracks) = parse(&bytes)?;
+            let parts = match by {
+                SplitBy::Track => by_track(&tracks)?,
+                SplitBy::Channel => by_channel(&tracks)?,
+                SplitBy::Marker => by_marker(&tracks)?,
+            };
+            // Sections carry their own slice of the tempo map
+            let map = if by == SplitBy::Marker { Vec::new() } else { conductor(&tracks) };
+            let attributes = derived::carried_attributes(&db, &path)?;
+            let mut written = Vec::with_capacity(parts.len());
+            for (label, mut events) in parts {
+                midi::apply_expression(&mut events, expression.unwrap_or_default());
+                let notes = note_count(&events);
+                let part: Events = vec![(0, TrackEventKind::Meta(MetaMessage::TrackName(label.as_bytes())))].into_iter().chain(events).collect();
+                let write = |out: &Path| save(out, smf.header.timing, vec![map.clone(), part]);
+                let output = derived::write_midi(&db, &path, dest_dir.as_deref(), &safe_file_name(&label), write, SOURCE, &attributes)?;
+                written.push(SplitPart { label, path: output, notes });
+            }
+            Ok(written)
+        })
+        .await
+}
@@ -0,0 +1,150 @@
+use crate::db::{self, Db};
+use reqwest::{Request, Response};
+use rusqlite::params;
+use std::time::Duration;
+use tauri::State;
+
+// Entries older than this are dropped as new ones are written
+const RETENTION_SECONDS: i64 = 90 * 24 * 60 * 60;
+const DEFAULT_LIMIT: usize = 500;
+
+// One request about to leave the machine. Every HTTP call goes through
+// Http::send and is written here, retries included; the connectivity probe
+// only opens a connection and sends nothing.
+pub struct Outbound {
+    provider: String,
+    purpose: String,
+    method: String,
+    // Scheme, host and path; the query is left out since Gemini carries the
+    // API key there
+    endpoint: String,
+    payload_bytes: Option<u64>,
+}
+
+impl Outbound {
+    pub fn describe(provider: &str, purpose: &str, request: &Request) -> Self {
+        let url = request.url();
+        let payload_bytes = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|bytes| bytes.len() as u64)
+            // Multipart bodies are streamed but announce their length
+            .or_else(|| {
+                request
+                    .headers()
+                    .get(reqwest::header::CONTENT_LENGTH)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+            });
+        Outbound {
+            provider: provider.to_string(),
+            purpose: purpose.to_string(),
+            method: request.method().to_string(),
+            endpoint: format!("{}://{}{}", url.scheme(), url.host_str().unwrap_or(""), url.path()),
+            payload_bytes,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditEntry {
+    id: i64,
+    at: i64,
+    provider: String,
+    purpose: String,
+    method: String,
+    endpoint: String,
+    payload_bytes: Option<i64>,
+    // HTTP status, or None when the request never got an answer
+    status: Option<i64>,
+    error: Option<String>,
+    duration_ms: i64,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct AuditRange {
+    // Unix seconds, inclusive
+    from: Option<i64>,
+    to: Option<i64>,
+    provider: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(serde::Serialize)]
+pub struct NetworkAudit {
+    // Newest first, up to the limit
+    entries: Vec<AuditEntry>,
+    // Over the whole range, not just the entries returned
+    requests: i64,
+    payload_bytes: i64,
+}
+
+// Failing to write the log never fails the request it describes
+pub fn record(db: &Db, outbound: &Outbound, result: &Result<Response, reqwest::Error>, elapsed: Duration) {
+    let (status, error) = match result {
+        Ok(response) => (Some(response.status().as_u16() as i64), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let now = db::now();
+    let conn = db.lock();
+    let _ = conn.execute(
+        "INSERT INTO network_audit (at, provider, purpose, method, endpoint, payload_bytes, status, error, duration_ms)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            now,
+            outbound.provider,
+            outbound.purpose,
+            outbound.method,
+            outbound.endpoint,
+            outbound.payload_bytes.map(|b| b as i64),
+            status,
+            error,
+            elapsed.as_millis() as i64,
+        ],
+    );
+    let _ = conn.execute("DELETE FROM network_audit WHERE at < ?1", params![now - RETENTION_SECONDS]);
+}
+
+// Everything the app has sent out over `range`, for checking that nothing
+// unexpected leaves the machine
+#[tauri::command]
+pub async fn get_network_audit(db: State<'_, Db>, range: Option<AuditRange>) -> Result<NetworkAudit, String> {
+    let range = range.unwrap_or_default();
+    let conn = db.lock();
+    let filter = "WHERE (?1 IS NULL OR at >= ?1) AND (?2 IS NULL OR at <= ?2) AND (?3 IS NULL OR provider = ?3)";
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT id, at, provider, purpose, method, endpoint, payload_bytes, status, error, duration_ms
+             FROM network_audit {} ORDER BY at DESC, id DESC LIMIT ?4",
+            filter
+        ))
+        .map_err(|e| e.to_string())?;
+    let limit = range.limit.unwrap_or(DEFAULT_LIMIT) as i64;
+    let entries = stmt
+        .query_map(params![range.from, range.to, range.provider, limit], |row| {
+            Ok(AuditEntry {
+                id: row.get(0)?,
+                at: row.get(1)?,
+                provider: row.get(2)?,
+                purpose: row.get(3)?,
+                method: row.get(4)?,
+                endpoint: row.get(5)?,
+                payload_bytes: row.get(6)?,
+                status: row.get(7)?,
+                error: row.get(8)?,
+                duration_ms: row.get(9)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let (requests, payload_bytes) = conn
+        .query_row(
+            &format!("SELECT COUNT(*), COALESCE(SUM(payload_bytes), 0) FROM network_audit {}", filter),
+            params![range.from, range.to, range.provider],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(NetworkAudit { entries, requests, payload_bytes })
+}
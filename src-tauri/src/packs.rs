@@ -0,0 +1,190 @@
+use crate::collections::safe_file_name;
+use crate::db::{self, Db};
+use crate::jobs::{JobContext, Priority, Scheduler};
+use crate::paths;
+use crate::takes;
+use rusqlite::{params, Connection};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+// Stores and makers whose names turn up in download file and folder names
+const KNOWN_VENDORS: &[&str] = &[
+    "Splice", "Loopmasters", "Native Instruments", "Cymatics", "Producer Loops", "Sample Magic", "Prime Loops",
+    "Black Octopus", "Ghosthack", "Loopcloud", "ADSR", "Vengeance", "Function Loops", "Zero-G",
+];
+
+#[derive(serde::Serialize)]
+pub struct PackImport {
+    folder: String,
+    pack: String,
+    vendor: Option<String>,
+    files: usize,
+    // Entries left out: folders, macOS metadata, names reaching outside
+    skipped: usize,
+}
+
+#[derive(serde::Serialize)]
+pub struct Pack {
+    pack: String,
+    vendor: Option<String>,
+    files: i64,
+    imported_at: i64,
+}
+
+fn words(text: &str) -> String {
+    let words: Vec<&str> = text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+    format!(" {} ", words.join(" ").to_lowercase())
+}
+
+// The first known vendor named in the archive's file name or the folders
+// it was downloaded into, nearest first
+fn vendor_from_path(path: &Path) -> Option<String> {
+    path.ancestors()
+        .filter_map(|p| p.file_name())
+        .map(|name| words(&name.to_string_lossy()))
+        .find_map(|name| KNOWN_VENDORS.iter().find(|v| name.contains(&words(v))))
+        .map(|v| v.to_string())
+}
+
+fn record(conn: &Connection, path: &str, pack: &str, vendor: Option<&str>, archive: Option<&str>, imported_at: i64) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR REPLACE INTO pack_provenance (path, pack, vendor, archive, imported_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![path, pack, vendor, archive, imported_at],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Unpacks every file in the archive under `folder`, returning the files
+// written and the entries skipped
+fn extract(archive: &Path, folder: &Path, ctx: &JobContext) -> Result<(Vec<PathBuf>, usize), String> {
+    let file = fs::File::open(archive).map_err(|e| format!("Failed to open {}: {}", archive.display(), e))?;
+    let mut zip = zip::ZipArchive::new(file).map_err(|e| format!("Failed to read archive: {}", e))?;
+    let (mut written, mut skipped) = (Vec::new(), 0);
+    let total = zip.len().max(1) as f32;
+    for i in 0..zip.len() {
+        if ctx.is_cancelled() {
+            return Err("Import cancelled".to_string());
+        }
+        let mut entry = zip.by_index(i).map_err(|e| format!("Failed to read archive: {}", e))?;
+        // enclosed_name refuses absolute names and ones climbing out with ".."
+        let Some(name) = entry.enclosed_name() else {
+            skipped += 1;
+            continue;
+        };
+        let hidden = name.components().any(|c| {
+            let part = c.as_os_str().to_string_lossy();
+            part == "__MACOSX" || part.starts_with('.')
+        });
+        if entry.is_dir() || hidden {
+            skipped += 1;
+            continue;
+        }
+        ctx.progress(i as f32 / total, Some(name.display().to_string()));
+        let target = folder.join(&name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let mut out = fs::File::create(&target).map_err(|e| format!("Failed to create {}: {}", target.display(), e))?;
+        io::copy(&mut entry, &mut out).map_err(|e| format!("Failed to extract {}: {}", name.display(), e))?;
+        written.push(name);
+    }
+    Ok((written, skipped))
+}
+
+// Unpacks a sample pack's zip into its own folder under `dest`, indexes
+// what's in it and records the pack, vendor and import date on each file.
+// The vendor is guessed from where the archive was downloaded if not given.
+#[tauri::command]
+pub async fn import_pack(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    archive: String,
+    dest: String,
+    pack: Option<String>,
+    vendor: Option<String>,
+) -> Result<PackImport, String> {
+    let archive_path = paths::to_fs(&archive);
+    let pack = pack
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .or_else(|| archive_path.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .ok_or_else(|| format!("No pack name for {}", archive))?;
+    let vendor = vendor.map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).or_else(|| vendor_from_path(&archive_path));
+    let db = db.inner().clone();
+    let label = format!("Import {}", pack);
+
+    scheduler
+        .run("import", label, Priority::Interactive, move |ctx| {
+            let parent = paths::to_fs(&dest);
+            let stem = safe_file_name(&pack);
+            let mut folder = parent.join(&stem);
+            let mut n = 2;
+            while folder.exists() {
+                folder = parent.join(format!("{} {}", stem, n));
+                n += 1;
+            }
+            // Unpacked beside the destination first, so a failed or cancelled
+            // import leaves no half-filled pack in the library
+            let part = parent.join(format!(".{}.part", paths::file_name(&folder)));
+            let _ = fs::remove_dir_all(&part);
+            let (written, skipped) = match extract(&archive_path, &part, ctx) {
+                Ok(result) => result,
+                Err(e) => {
+                    let _ = fs::remove_dir_all(&part);
+                    return Err(e);
+                }
+            };
+            fs::rename(&part, &folder).map_err(|e| format!("Failed to finalize {}: {}", folder.display(), e))?;
+
+            let imported_at = db::now();
+            let conn = db.lock();
+            for name in &written {
+                let path = folder.join(name);
+                takes::index(&conn, &path)?;
+                record(&conn, &paths::display(&path), &pack, vendor.as_deref(), Some(archive.as_str()), imported_at)?;
+            }
+            Ok(PackImport { folder: paths::display(&folder), pack, vendor, files: written.len(), skipped })
+        })
+        .await
+}
+
+// Records pack and vendor for files that were unpacked some other way; the
+// import date is now
+#[tauri::command]
+pub async fn set_pack_provenance(db: State<'_, Db>, files: Vec<String>, pack: String, vendor: Option<String>) -> Result<(), String> {
+    let pack = pack.trim();
+    if pack.is_empty() {
+        return Err("The pack needs a name".to_string());
+    }
+    let vendor = vendor.as_deref().map(str::trim).filter(|v| !v.is_empty());
+    let conn = db.lock();
+    let imported_at = db::now();
+    for file in &files {
+        record(&conn, file, pack, vendor, None, imported_at)?;
+    }
+    Ok(())
+}
+
+// Imported packs, newest first
+#[tauri::command]
+pub async fn list_packs(db: State<'_, Db>, vendor: Option<String>) -> Result<Vec<Pack>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare(
+            "SELECT pack, vendor, COUNT(*), MIN(imported_at) FROM pack_provenance
+             WHERE ?1 IS NULL OR vendor = ?1 COLLATE NOCASE
+             GROUP BY pack, vendor ORDER BY MIN(imported_at) DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let packs = stmt
+        .query_map(params![vendor], |row| {
+            Ok(Pack { pack: row.get(0)?, vendor: row.get(1)?, files: row.get(2)?, imported_at: row.get(3)? })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(packs)
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+// Win32 APIs fail past MAX_PATH (260) unless the path is verbatim; switch a
+// little early so appending a file name doesn't push us over.
+#[cfg(windows)]
+const LONG_PATH_THRESHOLD: usize = 240;
+
+#[cfg(windows)]
+fn with_long_prefix(path: &str) -> PathBuf {
+    if path.len() < LONG_PATH_THRESHOLD || path.starts_with(r"\\?\") {
+        return PathBuf::from(path);
+    }
+    // Verbatim paths skip normalization, so separators must already be backslashes
+    let path = path.replace('/', "\\");
+    if let Some(share) = path.strip_prefix(r"\\") {
+        PathBuf::from(format!(r"\\?\UNC\{}", share))
+    } else if Path::new(&path).is_absolute() {
+        PathBuf::from(format!(r"\\?\{}", path))
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+#[cfg(not(windows))]
+fn with_long_prefix(path: &str) -> PathBuf {
+    PathBuf::from(path)
+}
+
+// Turns a path from the frontend or the index into one the OS will open.
+// Index paths are NFC but a file copied from macOS onto an external drive
+// may be stored NFD (or the reverse), possibly inside a folder that isn't,
+// so a path that doesn't open as given is matched one name at a time.
+pub fn to_fs(path: &str) -> PathBuf {
+    let direct = with_long_prefix(path);
+    if direct.exists() {
+        return direct;
+    }
+    resolve_names(&direct)
+}
+
+// Follows `path` down from its root, taking each name as it's spelled on
+// disk when that differs from ours only by normalization. Names that aren't
+// there at all are kept as given, so a file about to be written lands in the
+// folder that does exist.
+fn resolve_names(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        let name = match component {
+            Component::Normal(name) => name,
+            other => {
+                resolved.push(other.as_os_str());
+                continue;
+            }
+        };
+        let joined = resolved.join(name);
+        if joined.exists() {
+            resolved = joined;
+            continue;
+        }
+        let wanted: String = name.to_string_lossy().nfc().collect();
+        let on_disk = fs::read_dir(&resolved).ok().and_then(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name())
+                .find(|candidate| candidate.to_string_lossy().nfc().eq(wanted.chars()))
+        });
+        resolved.push(on_disk.as_deref().unwrap_or(name));
+    }
+    resolved
+}
+
+// The form a path is stored and shown in: no verbatim prefix, and NFC.
+// macOS hands back NFD names, and drives or sync folders written by a Mac
+// carry them to Windows and Linux too; `to_fs` finds the file either way.
+pub fn display(path: &Path) -> String {
+    let text = path.to_string_lossy();
+    let text = if let Some(share) = text.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", share)
+    } else if let Some(rest) = text.strip_prefix(r"\\?\") {
+        rest.to_string()
+    } else {
+        text.to_string()
+    };
+
+    text.nfc().collect()
+}
+
+pub fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| display(Path::new(name)))
+        .unwrap_or_default()
+}
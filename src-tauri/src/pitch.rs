@@ -0,0 +1,169 @@
+use crate::analysis::NOTE_NAMES;
+use crate::audio;
+use crate::db::Db;
+use crate::dsp;
+use crate::journal::{self, Operation};
+use crate::library;
+use crate::paths;
+use crate::quarantine;
+use std::path::Path;
+use tauri::State;
+
+const SAMPLE_RATE: u32 = 22_050;
+// One-shots say what they are in the first couple of seconds
+const MAX_SECONDS: f64 = 3.0;
+pub const WINDOW: usize = 1024;
+const HOP: usize = 256;
+const MIN_HZ: f32 = 30.0;
+const MAX_HZ: f32 = 2000.0;
+// YIN's aperiodicity threshold; lower is stricter
+const THRESHOLD: f32 = 0.15;
+// Transients are noise as far as pitch goes
+const SKIP_AFTER_PEAK: f64 = 0.03;
+
+#[derive(Clone, serde::Serialize)]
+pub struct RootNote {
+    path: String,
+    // "A1", "C#3" (middle C = C4)
+    note: String,
+    midi: u8,
+    frequency: f32,
+    // How far the fundamental sits from the named note
+    cents: f32,
+    confidence: f32,
+}
+
+// Period estimate for one frame of WINDOW samples plus the longest period
+// searched: YIN's cumulative mean normalized difference with parabolic
+// refinement. Returns (Hz, aperiodicity).
+pub fn yin(frame: &[f32], sample_rate: u32, min_hz: f32, max_hz: f32) -> Option<(f32, f32)> {
+    let tau_min = (sample_rate as f32 / max_hz) as usize;
+    let tau_max = ((sample_rate as f32 / min_hz) as usize).min(frame.len() - WINDOW);
+
+    let mut diff = vec![0.0f32; tau_max + 1];
+    for (tau, slot) in diff.iter_mut().enumerate().skip(1) {
+        *slot = (0..WINDOW).map(|i| (frame[i] - frame[i + tau]).powi(2)).sum();
+    }
+    let mut cmnd = vec![1.0f32; tau_max + 1];
+    let mut running = 0.0;
+    for tau in 1..=tau_max {
+        running += diff[tau];
+        cmnd[tau] = diff[tau] * tau as f32 / running.max(1e-12);
+    }
+
+    // First dip under the threshold, followed down to its local minimum
+    let mut tau = tau_min.max(2);
+    while tau < tau_max {
+        if cmnd[tau] < THRESHOLD {
+            while tau + 1 < tau_max && cmnd[tau + 1] < cmnd[tau] {
+                tau += 1;
+            }
+            break;
+        }
+        tau += 1;
+    }
+    if tau >= tau_max {
+        return None;
+    }
+
+    let (a, b, c) = (cmnd[tau - 1], cmnd[tau], cmnd[tau + 1]);
+    let denominator = a - 2.0 * b + c;
+    let offset = if denominator.abs() > 1e-9 { 0.5 * (a - c) / denominator } else { 0.0 };
+    Some((sample_rate as f32 / (tau as f32 + offset), b))
+}
+
+fn midi_to_name(midi: u8) -> String {
+    format!("{}{}", NOTE_NAMES[midi as usize % 12], midi as i32 / 12 - 1)
+}
+
+pub fn detect(path: &Path) -> Result<Option<(f32, f32)>, String> {
+    let decoded = audio::decode_file(path, Some(MAX_SECONDS))?;
+    let mono = dsp::resample(&decoded.to_mono(), decoded.sample_rate, SAMPLE_RATE);
+    let frame_len = WINDOW + (SAMPLE_RATE as f32 / MIN_HZ) as usize + 1;
+    if mono.len() < frame_len {
+        return Ok(None);
+    }
+
+    let peak_at = mono
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    let start = peak_at + (SKIP_AFTER_PEAK * SAMPLE_RATE as f64) as usize;
+    let loudest = mono.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+
+    let mut estimates = Vec::new();
+    let mut position = start;
+    while position + frame_len <= mono.len() {
+        let frame = &mono[position..position + frame_len];
+        position += HOP;
+        // Stop once the tail has decayed 40 dB below the hit
+        if dsp::rms(&frame[..WINDOW]) < loudest * 0.01 {
+            break;
+        }
+        if let Some((hz, aperiodicity)) = yin(frame, SAMPLE_RATE, MIN_HZ, MAX_HZ) {
+            estimates.push((69.0 + 12.0 * (hz / 440.0).log2(), aperiodicity));
+        }
+    }
+    if estimates.is_empty() {
+        return Ok(None);
+    }
+
+    let mut pitches: Vec<f32> = estimates.iter().map(|e| e.0).collect();
+    pitches.sort_by(f32::total_cmp);
+    let median = pitches[pitches.len() / 2];
+    let agreeing: Vec<&(f32, f32)> = estimates.iter().filter(|e| (e.0 - median).abs() < 0.5).collect();
+    let stability = agreeing.len() as f32 / estimates.len() as f32;
+    let clarity = 1.0 - agreeing.iter().map(|e| e.1).sum::<f32>() / agreeing.len() as f32;
+    let mean = agreeing.iter().map(|e| e.0).sum::<f32>() / agreeing.len() as f32;
+    Ok(Some((mean, (stability * clarity).clamp(0.0, 1.0))))
+}
+
+// Estimates the fundamental of a melodic one-shot and records it as the
+// file's root_note (and a "root:<note>" tag for browsing), undoably.
+// Returns None for unpitched material such as hats and noise.
+#[tauri::command]
+pub async fn detect_root_note(db: State<'_, Db>, path: String, min_confidence: Option<f32>) -> Result<Option<RootNote>, String> {
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let detected = quarantine::guard(&db, &path, || detect(&paths::to_fs(&path)))?;
+        let (pitch, confidence) = match detected {
+            Some(found) if found.1 >= min_confidence.unwrap_or(0.5) => found,
+            _ => return Ok(None),
+        };
+        let midi = pitch.round().clamp(0.0, 127.0) as u8;
+        let root = RootNote {
+            path: path.clone(),
+            note: midi_to_name(midi),
+            midi,
+            frequency: 440.0 * 2f32.powf((pitch - 69.0) / 12.0),
+            cents: (pitch - midi as f32) * 100.0,
+            confidence,
+        };
+
+        let conn = db.lock();
+        let mut ops = Vec::new();
+        let before = library::metadata_value(&conn, &path, "root_note")?;
+        if before.as_deref() != Some(root.note.as_str()) {
+            ops.push(Operation::SetMetadata {
+                path: path.clone(),
+                key: "root_note".to_string(),
+                before,
+                after: Some(root.note.clone()),
+            });
+        }
+        let tags = library::tags_for(&conn, &path)?;
+        let root_tag = format!("root:{}", NOTE_NAMES[midi as usize % 12]);
+        let mut after: Vec<String> = tags.iter().filter(|t| !t.starts_with("root:")).cloned().collect();
+        after.push(root_tag);
+        after.sort();
+        if after != tags {
+            ops.push(Operation::SetTags { path: path.clone(), before: tags, after });
+        }
+        journal::run(&conn, &format!("Set root note of {} to {}", path, root.note), ops)?;
+        Ok(Some(root))
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
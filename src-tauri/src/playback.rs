@@ -0,0 +1,773 @@
+use crate::audio::{self, AudioData};
+use crate::audio_config::{self, AudioConfig, Monitor, StreamInfo, StreamStats};
+use crate::db::Db;
+use crate::decode_cache::DecodeCache;
+use crate::dsp;
+use crate::fx;
+use crate::paths;
+use crate::preview_fx::{PreviewFx, PreviewFxParams};
+use crate::sampler::Sampler;
+use crate::sequencer::Sequencer;
+use crate::settings;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SizedSample};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+// Meter frames per second sent to the UI while something is playing
+const METER_RATE: u64 = 30;
+// Window the meter looks at around the playhead
+const METER_WINDOW: usize = 1024;
+const METER_BANDS: usize = 16;
+const METER_MIN_HZ: f32 = 40.0;
+// Fade-in after an A/B switch so the cut doesn't click, in seconds
+const SWITCH_FADE: f64 = 0.005;
+// Fade-in after playback moves to another output device
+const HOTSWAP_FADE: f64 = 0.05;
+// Output channels the preview effects handle; more are passed through dry
+const MAX_FX_CHANNELS: usize = 8;
+
+// A decoded file held in memory at its own sample rate; the output callback
+// resamples on the fly so switching devices never means decoding again
+pub struct Track {
+    pub path: String,
+    // Shared with the decode cache, so replaying costs no copy
+    pub audio: Arc<AudioData>,
+}
+
+impl Track {
+    pub fn frames(&self) -> usize {
+        self.audio.samples.len() / self.audio.channels.max(1)
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.frames() as f64 / self.audio.sample_rate as f64
+    }
+
+    pub fn loudness(&self) -> f32 {
+        dsp::gated_loudness(&self.audio.to_mono(), self.audio.sample_rate)
+    }
+
+    // Linear interpolation between neighbouring frames, mapped onto
+    // output channels (mono is copied to every output, extra inputs are dropped)
+    fn frame_at(&self, position: f64, out_channel: usize) -> f32 {
+        let channels = self.audio.channels.max(1);
+        let channel = out_channel % channels;
+        let index = position as usize;
+        let frac = (position - index as f64) as f32;
+        let frames = self.frames();
+        if index >= frames {
+            return 0.0;
+        }
+        let a = self.audio.samples[index * channels + channel];
+        let b = if index + 1 < frames { self.audio.samples[(index + 1) * channels + channel] } else { a };
+        a + (b - a) * frac
+    }
+}
+
+#[derive(Default)]
+struct Transport {
+    track: Option<Arc<Track>>,
+    // In frames of the track, fractional because of resampling
+    position: f64,
+    playing: bool,
+    volume: f32,
+    // Level-matching trim for the loaded track, 1.0 outside A/B mode
+    gain: f32,
+    // Output frames left in the post-switch fade-in, and its length in seconds
+    fade_remaining: u32,
+    fade_length: f64,
+    ab: Option<AbPair>,
+    fx: PreviewFx,
+    sequencer: Sequencer,
+    sampler: Sampler,
+}
+
+// Two loaded files with the gains that bring them to the same loudness
+struct AbPair {
+    tracks: [Arc<Track>; 2],
+    loudness: [f32; 2],
+    gains: [f32; 2],
+    active: usize,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct AbState {
+    active: &'static str,
+    path_a: String,
+    path_b: String,
+    loudness_a: f32,
+    loudness_b: f32,
+    // Trim applied to each side, in dB
+    gain_a_db: f32,
+    gain_b_db: f32,
+    position: f64,
+}
+
+struct Output {
+    device: String,
+    // The chosen device was missing and the default stood in
+    fallback: bool,
+    sample_rate: u32,
+    buffer_size: Option<u32>,
+    // Set by the stream's error callback (device unplugged, driver reset)
+    failed: Arc<AtomicBool>,
+    stats: Arc<StreamStats>,
+    // Dropping the sender stops the thread that owns the cpal stream
+    _stop: mpsc::Sender<()>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct PlaybackState {
+    pub path: Option<String>,
+    pub playing: bool,
+    // Seconds
+    pub position: f64,
+    duration: f64,
+    volume: f32,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct MeterFrame {
+    position: f64,
+    // Per channel, linear 0.0..1.0
+    peak: Vec<f32>,
+    rms: Vec<f32>,
+    // Log-spaced bands from 40 Hz to Nyquist, in dBFS
+    bands: Vec<f32>,
+}
+
+// The preview player. cpal streams aren't Send, so each output lives on its
+// own thread and only the transport is shared with the audio callback.
+pub struct Playback {
+    transport: Arc<Mutex<Transport>>,
+    output: Mutex<Option<Output>>,
+    // Rate and buffer size the output opens with
+    config: Mutex<AudioConfig>,
+    // Output device picked by the user, None for the system default
+    preferred: Mutex<Option<String>>,
+}
+
+// What happened when the output device went away, for the UI
+#[derive(Clone, serde::Serialize)]
+pub struct OutputChange {
+    from: String,
+    // None when no output could be opened and playback paused
+    to: Option<String>,
+    fallback: bool,
+    error: Option<String>,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Playback {
+            transport: Arc::new(Mutex::new(Transport { volume: 1.0, gain: 1.0, ..Transport::default() })),
+            output: Mutex::new(None),
+            config: Mutex::new(AudioConfig::default()),
+            preferred: Mutex::new(None),
+        }
+    }
+}
+
+fn write_frames<T: SizedSample + FromSample<f32>>(transport: &Mutex<Transport>, data: &mut [T], channels: usize, device_rate: u32) {
+    // Never wait on the UI thread inside the audio callback; a missed lock is one silent buffer
+    let mut guard = match transport.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            data.fill(T::from_sample(0.0));
+            return;
+        }
+    };
+    let transport = &mut *guard;
+    transport.sampler.poll(device_rate);
+    let track = if transport.playing { transport.track.clone() } else { None };
+    if track.is_none() && !transport.sequencer.active() && !transport.sampler.active() {
+        data.fill(T::from_sample(0.0));
+        return;
+    }
+
+    let step = track.as_ref().map(|t| t.audio.sample_rate as f64 / device_rate as f64).unwrap_or(0.0);
+    let frames = track.as_ref().map(|t| t.frames() as f64).unwrap_or(0.0);
+    let fade_frames = (transport.fade_length.max(SWITCH_FADE) * device_rate as f64).max(1.0) as u32;
+    // A switch asks for a fade without knowing the device rate
+    transport.fade_remaining = transport.fade_remaining.min(fade_frames);
+    for frame in data.chunks_mut(channels) {
+        let processed = frame.len().min(MAX_FX_CHANNELS);
+        let mut values = [0.0f32; MAX_FX_CHANNELS];
+        let mut level = 0.0;
+        let sounding = match &track {
+            Some(_) if transport.position >= frames => {
+                transport.playing = false;
+                None
+            }
+            Some(track) => Some(track),
+            None => None,
+        };
+        if let Some(track) = sounding {
+            level = transport.volume * transport.gain;
+            if transport.fade_remaining > 0 {
+                level *= 1.0 - transport.fade_remaining as f32 / fade_frames as f32;
+                transport.fade_remaining -= 1;
+            }
+            for (channel, value) in values[..processed].iter_mut().enumerate() {
+                *value = track.frame_at(transport.position, channel);
+            }
+            transport.fx.process(&mut values[..processed], device_rate);
+        }
+        // The step sequencer and sampler play alongside the preview, after its effects
+        let mut pattern = [0.0f32; MAX_FX_CHANNELS];
+        transport.sequencer.process(&mut pattern[..processed], device_rate);
+        transport.sampler.process(&mut pattern[..processed], device_rate);
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            let value = match (channel < processed, sounding) {
+                (true, _) => values[channel] * level + pattern[channel] * transport.volume,
+                (false, Some(track)) => track.frame_at(transport.position, channel) * level,
+                (false, None) => 0.0,
+            };
+            *sample = T::from_sample(value);
+        }
+        if sounding.is_some() {
+            transport.position += step;
+        }
+    }
+}
+
+fn open_stream<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    transport: Arc<Mutex<Transport>>,
+    failed: Arc<AtomicBool>,
+    stats: Arc<StreamStats>,
+) -> Result<cpal::Stream, String> {
+    let channels = config.channels as usize;
+    let rate = config.sample_rate.0;
+    let mut monitor = Monitor::new(stats, rate);
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+                monitor.output(info, data.len() / channels.max(1));
+                write_frames(&transport, data, channels, rate)
+            },
+            move |_| failed.store(true, Ordering::SeqCst),
+            None,
+        )
+        .map_err(|e| format!("Failed to open audio output: {}", e))
+}
+
+impl Playback {
+    fn transport(&self) -> MutexGuard<'_, Transport> {
+        self.transport.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Builds the effect chain here and only swaps it in under the lock the
+    // audio callback shares; the old one is freed after the lock is let go
+    fn rebuild_fx(&self, params: PreviewFxParams, rate: u32) -> PreviewFxParams {
+        let fx = PreviewFx::new(params, rate, MAX_FX_CHANNELS);
+        let params = fx.params();
+        let old = std::mem::replace(&mut self.transport().fx, fx);
+        drop(old);
+        params
+    }
+
+    // Opens the chosen output device (the default when it's unset or
+    // missing) the first time something plays, and again after the current
+    // one reports an error
+    fn ensure_output(&self) -> Result<(), String> {
+        let mut output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(current) = output.as_ref() {
+            if !current.failed.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+        }
+        *output = None;
+
+        let (ready_tx, ready_rx) = mpsc::channel::<Result<(cpal::StreamConfig, String), String>>();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let transport = self.transport.clone();
+        let failed = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(StreamStats::default());
+        let (stream_failed, stream_stats) = (failed.clone(), stats.clone());
+        let wanted = self.config.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let preferred = self.preferred.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        std::thread::spawn(move || {
+            let opened = (|| -> Result<(cpal::Stream, cpal::StreamConfig, String), String> {
+                let host = cpal::default_host();
+                let chosen = preferred.as_ref().and_then(|name| {
+                    host.output_devices()
+                        .ok()?
+                        .find(|d| d.name().map(|n| &n == name).unwrap_or(false))
+                });
+                let device = match chosen {
+                    Some(device) => device,
+                    None => host.default_output_device().ok_or_else(|| "No audio output device".to_string())?,
+                };
+                let name = device.name().unwrap_or_default();
+                let (config, format) = audio_config::choose(&device, true, &wanted)?;
+                let stream = match format {
+                    cpal::SampleFormat::I16 => open_stream::<i16>(&device, &config, transport, stream_failed, stream_stats),
+                    cpal::SampleFormat::U16 => open_stream::<u16>(&device, &config, transport, stream_failed, stream_stats),
+                    _ => open_stream::<f32>(&device, &config, transport, stream_failed, stream_stats),
+                }?;
+                stream.play().map_err(|e| format!("Failed to start audio output: {}", e))?;
+                Ok((stream, config, name))
+            })();
+            match opened {
+                Ok((stream, config, name)) => {
+                    let _ = ready_tx.send(Ok((config, name)));
+                    // Keeps the stream alive until the sender is dropped
+                    let _ = stop_rx.recv();
+                    drop(stream);
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                }
+            }
+        });
+
+        let (config, device) = ready_rx.recv().map_err(|_| "Audio output thread exited".to_string())??;
+        let fallback = self.preferred.lock().unwrap_or_else(|e| e.into_inner()).as_ref().is_some_and(|p| *p != device);
+        *output = Some(Output {
+            device,
+            fallback,
+            sample_rate: config.sample_rate.0,
+            buffer_size: audio_config::buffer_frames(&config),
+            failed,
+            stats,
+            _stop: stop_tx,
+        });
+        drop(output);
+        let params = self.transport().fx.params();
+        self.rebuild_fx(params, config.sample_rate.0);
+        Ok(())
+    }
+
+    // Closes the output and, if one was open, opens it again with a short
+    // fade-in so the change doesn't click
+    fn reopen(&self) -> Result<(), String> {
+        let was_open = self.output.lock().unwrap_or_else(|e| e.into_inner()).take().is_some();
+        if was_open {
+            self.ensure_output()?;
+            let mut transport = self.transport();
+            transport.fade_remaining = u32::MAX;
+            transport.fade_length = HOTSWAP_FADE;
+        }
+        Ok(())
+    }
+
+    // Opens the output if it isn't already and returns its sample rate
+    pub fn open_output(&self) -> Result<u32, String> {
+        self.ensure_output()?;
+        let output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        output.as_ref().map(|o| o.sample_rate).ok_or_else(|| "Audio output isn't open".to_string())
+    }
+
+    pub fn with_sequencer<R>(&self, f: impl FnOnce(&mut Sequencer) -> R) -> R {
+        f(&mut self.transport().sequencer)
+    }
+
+    pub fn with_sampler<R>(&self, f: impl FnOnce(&mut Sampler) -> R) -> R {
+        f(&mut self.transport().sampler)
+    }
+
+    // Takes effect at once: an open output is reopened with the new settings
+    pub fn set_config(&self, config: AudioConfig) -> Result<(), String> {
+        *self.config.lock().unwrap_or_else(|e| e.into_inner()) = config;
+        self.reopen()
+    }
+
+    pub fn set_output_device(&self, name: Option<String>) -> Result<(), String> {
+        *self.preferred.lock().unwrap_or_else(|e| e.into_inner()) = name;
+        self.reopen()
+    }
+
+    fn output_failed(&self) -> bool {
+        let output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        output.as_ref().is_some_and(|o| o.failed.load(Ordering::SeqCst))
+    }
+
+    // Moves playback to another device when the current one has failed
+    // (unplugged, interface asleep), fading back in. None when all is well.
+    fn recover(&self) -> Option<OutputChange> {
+        if !self.output_failed() {
+            return None;
+        }
+        let from = self.output.lock().unwrap_or_else(|e| e.into_inner()).take().map(|o| o.device)?;
+        if let Err(e) = self.ensure_output() {
+            self.transport().playing = false;
+            return Some(OutputChange { from, to: None, fallback: false, error: Some(e) });
+        }
+        let mut transport = self.transport();
+        transport.fade_remaining = u32::MAX;
+        transport.fade_length = HOTSWAP_FADE;
+        drop(transport);
+        let output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        let (to, fallback) = output.as_ref().map(|o| (o.device.clone(), o.fallback)).unzip();
+        Some(OutputChange { from, to, fallback: fallback.unwrap_or(false), error: None })
+    }
+
+    pub fn output_info(&self) -> Option<StreamInfo> {
+        let output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        output.as_ref().map(|o| StreamInfo::new(&o.device, o.sample_rate, o.buffer_size, &o.stats))
+    }
+
+    pub fn load(&self, track: Track, start: f64) -> Result<(), String> {
+        self.ensure_output()?;
+        let mut transport = self.transport();
+        transport.position = (start.max(0.0) * track.audio.sample_rate as f64).min(track.frames() as f64);
+        transport.track = Some(Arc::new(track));
+        transport.playing = true;
+        transport.gain = 1.0;
+        transport.ab = None;
+        Ok(())
+    }
+
+    // Loads both files, level-matched to the quieter one so neither clips,
+    // and starts playing A. `loudness` is measured by the caller since it
+    // reads every sample.
+    pub fn load_ab(&self, a: Track, b: Track, loudness: [f32; 2], start: f64) -> Result<AbState, String> {
+        let target = loudness[0].min(loudness[1]);
+        let gains = [db_to_gain(target - loudness[0]), db_to_gain(target - loudness[1])];
+
+        self.ensure_output()?;
+        let mut transport = self.transport();
+        let a = Arc::new(a);
+        transport.position = (start.max(0.0) * a.audio.sample_rate as f64).min(a.frames() as f64);
+        transport.track = Some(a.clone());
+        transport.gain = gains[0];
+        transport.playing = true;
+        transport.ab = Some(AbPair { tracks: [a, Arc::new(b)], loudness, gains, active: 0 });
+        ab_state(&transport).ok_or_else(|| "A/B comparison isn't loaded".to_string())
+    }
+
+    // Jumps to the other side (or `side`) at the same point in time
+    pub fn switch_ab(&self, side: Option<usize>) -> Result<AbState, String> {
+        let mut transport = self.transport();
+        let seconds = match &transport.track {
+            Some(track) => transport.position / track.audio.sample_rate as f64,
+            None => 0.0,
+        };
+        let pair = transport.ab.as_mut().ok_or_else(|| "A/B comparison isn't loaded".to_string())?;
+        let next = side.unwrap_or(1 - pair.active);
+        let switching = next != pair.active;
+        pair.active = next;
+        let (track, gain) = (pair.tracks[next].clone(), pair.gains[next]);
+        transport.position = (seconds * track.audio.sample_rate as f64).min(track.frames() as f64);
+        transport.track = Some(track);
+        transport.gain = gain;
+        if switching {
+            transport.fade_remaining = u32::MAX;
+            transport.fade_length = SWITCH_FADE;
+        }
+        ab_state(&transport).ok_or_else(|| "A/B comparison isn't loaded".to_string())
+    }
+
+    pub fn state(&self) -> PlaybackState {
+        let transport = self.transport();
+        let track = transport.track.as_ref();
+        PlaybackState {
+            path: track.map(|t| t.path.clone()),
+            playing: transport.playing,
+            position: track.map(|t| transport.position / t.audio.sample_rate as f64).unwrap_or(0.0),
+            duration: track.map(|t| t.duration()).unwrap_or(0.0),
+            volume: transport.volume,
+        }
+    }
+
+    // Level and spectrum of the audio just ahead of the playhead
+    fn meter(&self) -> Option<MeterFrame> {
+        let (track, position) = {
+            let transport = self.transport();
+            match (&transport.track, transport.playing) {
+                (Some(track), true) => (track.clone(), transport.position as usize),
+                _ => return None,
+            }
+        };
+        let channels = track.audio.channels.max(1);
+        let start = position.min(track.frames());
+        let end = (start + METER_WINDOW).min(track.frames());
+        let window = &track.audio.samples[start * channels..end * channels];
+
+        let mut peak = vec![0.0f32; channels];
+        let mut sum = vec![0.0f32; channels];
+        for frame in window.chunks(channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                peak[channel] = peak[channel].max(sample.abs());
+                sum[channel] += sample * sample;
+            }
+        }
+        let count = (end - start).max(1) as f32;
+        let rms = sum.iter().map(|s| (s / count).sqrt()).collect();
+
+        let mono: Vec<f32> = window.chunks(channels).map(|f| f.iter().sum::<f32>() / channels as f32).collect();
+        let spectrum = dsp::stft_magnitudes(&mono, METER_WINDOW, METER_WINDOW).into_iter().next().unwrap_or_default();
+        Some(MeterFrame {
+            position: position as f64 / track.audio.sample_rate as f64,
+            peak,
+            rms,
+            bands: bands(&spectrum, track.audio.sample_rate),
+        })
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn ab_state(transport: &Transport) -> Option<AbState> {
+    let pair = transport.ab.as_ref()?;
+    let rate = pair.tracks[pair.active].audio.sample_rate as f64;
+    Some(AbState {
+        active: if pair.active == 0 { "a" } else { "b" },
+        path_a: pair.tracks[0].path.clone(),
+        path_b: pair.tracks[1].path.clone(),
+        loudness_a: pair.loudness[0],
+        loudness_b: pair.loudness[1],
+        gain_a_db: 20.0 * pair.gains[0].log10(),
+        gain_b_db: 20.0 * pair.gains[1].log10(),
+        position: transport.position / rate,
+    })
+}
+
+fn bands(spectrum: &[f32], sample_rate: u32) -> Vec<f32> {
+    if spectrum.is_empty() {
+        return vec![-120.0; METER_BANDS];
+    }
+    let nyquist = sample_rate as f32 / 2.0;
+    let bin_hz = nyquist / (spectrum.len() - 1).max(1) as f32;
+    let ratio = (nyquist / METER_MIN_HZ).powf(1.0 / METER_BANDS as f32);
+    (0..METER_BANDS)
+        .map(|band| {
+            let low = METER_MIN_HZ * ratio.powi(band as i32);
+            let high = low * ratio;
+            let first = (low / bin_hz) as usize;
+            let last = ((high / bin_hz) as usize).max(first + 1).min(spectrum.len());
+            let bins = &spectrum[first.min(last - 1)..last];
+            let energy = bins.iter().map(|m| m * m).sum::<f32>() / bins.len() as f32;
+            // Hann-windowed full-scale sine peaks at n_fft / 4
+            let magnitude = energy.sqrt() / (METER_WINDOW as f32 / 4.0);
+            (20.0 * magnitude.max(1e-6).log10()).max(-120.0)
+        })
+        .collect()
+}
+
+// Applies the saved audio settings, moves playback off a failed output and
+// emits "playback-meter" frames at a fixed rate while something is playing
+pub fn start(app: &AppHandle) {
+    let (config, preferred) = {
+        let db = app.state::<Db>();
+        let conn = db.lock();
+        (audio_config::load(&conn), settings::get::<String>(&conn, "playback.output_device").ok().flatten())
+    };
+    let playback = app.state::<Playback>();
+    *playback.config.lock().unwrap_or_else(|e| e.into_inner()) = config;
+    *playback.preferred.lock().unwrap_or_else(|e| e.into_inner()) = preferred;
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(1000 / METER_RATE));
+        loop {
+            interval.tick().await;
+            if app.state::<Playback>().output_failed() {
+                // Reopening blocks on the device, so it runs off the async thread
+                let handle = app.clone();
+                let change = tokio::task::spawn_blocking(move || handle.state::<Playback>().recover()).await.ok().flatten();
+                if let Some(change) = change {
+                    let _ = app.emit("output-device-changed", &change);
+                }
+            }
+            if let Some(frame) = app.state::<Playback>().meter() {
+                let _ = app.emit("playback-meter", &frame);
+            }
+        }
+    });
+}
+
+// Runs `f` with the player on a blocking thread. Anything that may open the
+// output waits there for the device, which can take a while (or hang), rather
+// than on the async runtime.
+pub async fn blocking<T: Send + 'static>(
+    app: &AppHandle,
+    f: impl FnOnce(&Playback) -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    let app = app.clone();
+    tokio::task::spawn_blocking(move || f(&app.state::<Playback>()))
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
+
+pub fn decode_track(db: &Db, cache: &DecodeCache, path: &str) -> Result<Track, String> {
+    let audio = cache.decode(db, path)?;
+    Ok(Track { path: path.to_string(), audio })
+}
+
+#[tauri::command]
+pub async fn play_file(
+    app: AppHandle,
+    db: State<'_, Db>,
+    cache: State<'_, DecodeCache>,
+    path: String,
+    start: Option<f64>,
+) -> Result<PlaybackState, String> {
+    let (db, cache) = (db.inner().clone(), cache.inner().clone());
+    blocking(&app, move |playback| {
+        let track = decode_track(&db, &cache, &path)?;
+        playback.load(track, start.unwrap_or(0.0))?;
+        Ok(playback.state())
+    })
+    .await
+}
+
+// Plays `sample` through the impulse response `ir`, `mix` (0-1, default
+// 0.5) being the wet share, so a space can be auditioned on a dry sound
+#[tauri::command]
+pub async fn preview_with_ir(
+    app: AppHandle,
+    db: State<'_, Db>,
+    cache: State<'_, DecodeCache>,
+    sample: String,
+    ir: String,
+    mix: Option<f32>,
+) -> Result<PlaybackState, String> {
+    let (db, cache) = (db.inner().clone(), cache.inner().clone());
+    let mix = mix.unwrap_or(0.5).clamp(0.0, 1.0);
+    blocking(&app, move |playback| {
+        let dry = cache.decode(&db, &sample)?;
+        let response = fx::load_ir(&ir, dry.sample_rate)?;
+        let track = Track { path: sample, audio: Arc::new(fx::with_ir(&dry, &response, mix)) };
+        playback.load(track, 0.0)?;
+        Ok(playback.state())
+    })
+    .await
+}
+
+// Low/high-cut filters, tilt EQ and a reverb send on everything the
+// preview player plays, to hear how a sample might sit in a mix.
+// Takes effect immediately, including mid-playback.
+#[tauri::command]
+pub async fn set_preview_fx(playback: State<'_, Playback>, params: PreviewFxParams) -> Result<PreviewFxParams, String> {
+    let rate = playback.output.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map(|o| o.sample_rate).unwrap_or(0);
+    Ok(playback.rebuild_fx(params, rate))
+}
+
+#[tauri::command]
+pub async fn get_preview_fx(playback: State<'_, Playback>) -> Result<PreviewFxParams, String> {
+    Ok(playback.transport().fx.params())
+}
+
+#[tauri::command]
+pub async fn pause_playback(playback: State<'_, Playback>) -> Result<PlaybackState, String> {
+    playback.transport().playing = false;
+    Ok(playback.state())
+}
+
+#[tauri::command]
+pub async fn resume_playback(playback: State<'_, Playback>) -> Result<PlaybackState, String> {
+    {
+        let mut transport = playback.transport();
+        if transport.track.is_none() {
+            return Err("Nothing is loaded".to_string());
+        }
+        transport.playing = true;
+    }
+    Ok(playback.state())
+}
+
+#[tauri::command]
+pub async fn stop_playback(playback: State<'_, Playback>) -> Result<(), String> {
+    let mut transport = playback.transport();
+    transport.playing = false;
+    transport.track = None;
+    transport.ab = None;
+    transport.gain = 1.0;
+    transport.position = 0.0;
+    Ok(())
+}
+
+// Tracks are held fully decoded with encoder delay trimmed, so the whole
+// file is its own seek table and `seconds` maps straight onto a sample, VBR
+// or not
+#[tauri::command]
+pub async fn seek_playback(playback: State<'_, Playback>, seconds: f64) -> Result<PlaybackState, String> {
+    {
+        let mut transport = playback.transport();
+        let track = transport.track.clone().ok_or_else(|| "Nothing is loaded".to_string())?;
+        transport.position = (seconds.max(0.0) * track.audio.sample_rate as f64).min(track.frames() as f64);
+    }
+    Ok(playback.state())
+}
+
+#[tauri::command]
+pub async fn set_playback_volume(playback: State<'_, Playback>, volume: f32) -> Result<(), String> {
+    playback.transport().volume = volume.clamp(0.0, 2.0);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_playback_state(playback: State<'_, Playback>) -> Result<PlaybackState, String> {
+    Ok(playback.state())
+}
+
+// Loads two files for side-by-side listening, loudness-matched so the louder
+// one doesn't win by default. ab_switch flips between them mid-playback.
+#[tauri::command]
+pub async fn ab_compare(
+    app: AppHandle,
+    db: State<'_, Db>,
+    cache: State<'_, DecodeCache>,
+    path_a: String,
+    path_b: String,
+    start: Option<f64>,
+) -> Result<AbState, String> {
+    let (db, cache) = (db.inner().clone(), cache.inner().clone());
+    blocking(&app, move |playback| {
+        let (a, b) = (decode_track(&db, &cache, &path_a)?, decode_track(&db, &cache, &path_b)?);
+        let loudness = [a.loudness(), b.loudness()];
+        playback.load_ab(a, b, loudness, start.unwrap_or(0.0))
+    })
+    .await
+}
+
+// Whether lengths and positions for `path` are exact, for warning about
+// VBR files without an info header and lossy files without gapless data
+#[tauri::command]
+pub async fn get_decode_accuracy(path: String) -> Result<audio::DecodeAccuracy, String> {
+    tokio::task::spawn_blocking(move || audio::decode_accuracy(&paths::to_fs(&path)))
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
+
+// Switches to `side` ("a" or "b"), or to the other one when omitted
+#[tauri::command]
+pub async fn ab_switch(playback: State<'_, Playback>, side: Option<String>) -> Result<AbState, String> {
+    let side = match side.as_deref() {
+        None => None,
+        Some("a") => Some(0),
+        Some("b") => Some(1),
+        Some(other) => return Err(format!("Unknown A/B side: {}", other)),
+    };
+    playback.switch_ab(side)
+}
+
+#[tauri::command]
+pub async fn list_output_devices() -> Result<Vec<String>, String> {
+    tokio::task::spawn_blocking(|| {
+        let devices = cpal::default_host()
+            .output_devices()
+            .map_err(|e| format!("Failed to list audio outputs: {}", e))?;
+        Ok(devices.filter_map(|d| d.name().ok()).collect())
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+// Plays through `name` (None for the system default) from now on. If it
+// disappears later playback falls back to the default and
+// "output-device-changed" is emitted.
+#[tauri::command]
+pub async fn set_output_device(app: AppHandle, db: State<'_, Db>, name: Option<String>) -> Result<(), String> {
+    settings::set(&db.lock(), "playback.output_device", &name.clone().map(serde_json::Value::String).unwrap_or_default())?;
+    blocking(&app, move |playback| playback.set_output_device(name)).await
+}
@@ -0,0 +1,278 @@
+use crate::db::Db;
+use crate::jobs::Scheduler;
+use crate::settings;
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+// Job kinds that are safe to hold back; exports, imports and downloads are
+// things the user is waiting on
+const THROTTLED_KINDS: &[&str] = &["analysis", "scan", "slow-scan", "conversion"];
+// Any CPU zone this hot means the machine is already struggling
+#[cfg(target_os = "linux")]
+const HOT_CELSIUS: f64 = 85.0;
+// Keyboard or mouse input this recent means someone is working
+const DEFAULT_ACTIVE_SECONDS: u64 = 120;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Throttle {
+    Run,
+    Throttle,
+    Pause,
+}
+
+// What the machine is doing. None where the platform gives no answer.
+#[derive(Clone, Default, serde::Serialize)]
+pub struct PowerState {
+    on_battery: Option<bool>,
+    thermal_pressure: Option<bool>,
+    // Seconds since the last keyboard or mouse input anywhere on the system
+    input_idle_seconds: Option<u64>,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct PowerStatus {
+    state: PowerState,
+    level: Throttle,
+    // Why background work is held back, for the status bar
+    reasons: Vec<String>,
+    overridden: bool,
+}
+
+pub struct Power {
+    status: Mutex<PowerStatus>,
+}
+
+impl Default for Power {
+    fn default() -> Self {
+        Power {
+            status: Mutex::new(PowerStatus {
+                state: PowerState::default(),
+                level: Throttle::Run,
+                reasons: Vec::new(),
+                overridden: false,
+            }),
+        }
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let mut command = Command::new(program);
+    command.args(args);
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // CREATE_NO_WINDOW, so no console flashes every check
+        command.creation_flags(0x0800_0000);
+    }
+    let output = command.output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn on_battery() -> Option<bool> {
+    let mut found_battery = false;
+    for entry in std::fs::read_dir("/sys/class/power_supply").ok()?.flatten() {
+        let dir = entry.path();
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).map(|s| s.trim().to_string()).unwrap_or_default();
+        match read("type").as_str() {
+            "Mains" if read("online") == "1" => return Some(false),
+            "Battery" => found_battery = true,
+            _ => {}
+        }
+    }
+    // A battery and no powered adapter
+    found_battery.then_some(true)
+}
+
+#[cfg(target_os = "macos")]
+fn on_battery() -> Option<bool> {
+    let output = command_output("pmset", &["-g", "batt"])?;
+    Some(output.contains("'Battery Power'"))
+}
+
+#[cfg(windows)]
+fn on_battery() -> Option<bool> {
+    // BatteryStatus 1 is "discharging"; desktops have no Win32_Battery at all
+    let output = command_output(
+        "powershell",
+        &["-NoProfile", "-Command", "(Get-CimInstance Win32_Battery).BatteryStatus"],
+    )?;
+    let status = output.trim();
+    if status.is_empty() {
+        return Some(false);
+    }
+    Some(status.lines().any(|line| line.trim() == "1"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn on_battery() -> Option<bool> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn thermal_pressure() -> Option<bool> {
+    let hottest = std::fs::read_dir("/sys/class/thermal")
+        .ok()?
+        .flatten()
+        .filter(|e| e.file_name().to_string_lossy().starts_with("thermal_zone"))
+        .filter_map(|e| std::fs::read_to_string(e.path().join("temp")).ok())
+        .filter_map(|t| t.trim().parse::<f64>().ok())
+        .map(|millidegrees| millidegrees / 1000.0)
+        .fold(None, |max: Option<f64>, t| Some(max.map_or(t, |m| m.max(t))))?;
+    Some(hottest >= HOT_CELSIUS)
+}
+
+#[cfg(target_os = "macos")]
+fn thermal_pressure() -> Option<bool> {
+    // The OS lowers CPU_Speed_Limit below 100 when it throttles for heat
+    let output = command_output("pmset", &["-g", "therm"])?;
+    let limit = output
+        .lines()
+        .find(|line| line.contains("CPU_Speed_Limit"))
+        .and_then(|line| line.split('=').nth(1))
+        .and_then(|value| value.trim().parse::<u32>().ok())?;
+    Some(limit < 100)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn thermal_pressure() -> Option<bool> {
+    None
+}
+
+#[cfg(target_os = "macos")]
+fn input_idle_seconds() -> Option<u64> {
+    let output = command_output("ioreg", &["-c", "IOHIDSystem", "-d", "4"])?;
+    let nanos = output
+        .lines()
+        .find(|line| line.contains("\"HIDIdleTime\""))
+        .and_then(|line| line.split('=').nth(1))
+        .and_then(|value| value.trim().parse::<u64>().ok())?;
+    Some(nanos / 1_000_000_000)
+}
+
+#[cfg(target_os = "linux")]
+fn input_idle_seconds() -> Option<u64> {
+    // Only on X11 with xprintidle installed; Wayland offers nothing portable
+    let output = command_output("xprintidle", &[])?;
+    output.trim().parse::<u64>().ok().map(|ms| ms / 1000)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn input_idle_seconds() -> Option<u64> {
+    None
+}
+
+pub fn probe() -> PowerState {
+    PowerState {
+        on_battery: on_battery(),
+        thermal_pressure: thermal_pressure(),
+        input_idle_seconds: input_idle_seconds(),
+    }
+}
+
+fn setting<T: serde::de::DeserializeOwned>(db: &Db, key: &str, default: T) -> T {
+    settings::get(&db.lock(), key).ok().flatten().unwrap_or(default)
+}
+
+// Turns the machine state into a throttle level using the "power.*"
+// settings. "power.override" set to true runs everything at full speed.
+fn evaluate(db: &Db, state: PowerState) -> PowerStatus {
+    let overridden: bool = setting(db, "power.override", false);
+    let active_seconds: u64 = setting(db, "power.active_seconds", DEFAULT_ACTIVE_SECONDS);
+
+    let mut level = Throttle::Run;
+    let mut reasons = Vec::new();
+    let mut consider = |triggered: bool, key: &str, default: Throttle, reason: &str| {
+        if !triggered {
+            return;
+        }
+        let response: Throttle = setting(db, key, default);
+        if response > Throttle::Run {
+            reasons.push(reason.to_string());
+            level = level.max(response);
+        }
+    };
+    consider(state.on_battery == Some(true), "power.on_battery", Throttle::Throttle, "Running on battery");
+    consider(state.thermal_pressure == Some(true), "power.when_hot", Throttle::Pause, "The machine is running hot");
+    consider(
+        state.input_idle_seconds.map(|idle| idle < active_seconds).unwrap_or(false),
+        "power.when_active",
+        Throttle::Throttle,
+        "You're working",
+    );
+
+    if overridden {
+        level = Throttle::Run;
+    }
+    PowerStatus { state, level, reasons, overridden }
+}
+
+fn caps_for(level: Throttle) -> HashMap<String, usize> {
+    let cap = match level {
+        Throttle::Run => return HashMap::new(),
+        Throttle::Throttle => 1,
+        Throttle::Pause => 0,
+    };
+    THROTTLED_KINDS.iter().map(|kind| (kind.to_string(), cap)).collect()
+}
+
+fn apply(app: &AppHandle, status: PowerStatus) {
+    let power = app.state::<Power>();
+    let mut current = power.status.lock().unwrap_or_else(|e| e.into_inner());
+    let level_changed = current.level != status.level;
+    let changed = level_changed || current.reasons != status.reasons || current.overridden != status.overridden;
+    *current = status.clone();
+    drop(current);
+
+    if level_changed {
+        app.state::<Scheduler>().set_caps(caps_for(status.level));
+    }
+    if changed {
+        let _ = app.emit("power-status", &status);
+    }
+}
+
+fn check(app: &AppHandle) {
+    let db = app.state::<Db>().inner().clone();
+    let status = evaluate(&db, probe());
+    apply(app, status);
+}
+
+// Re-checks power, heat and activity periodically and caps background job
+// pools to match
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let app = app.clone();
+            let _ = tokio::task::spawn_blocking(move || check(&app)).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_power_status(power: State<'_, Power>) -> Result<PowerStatus, String> {
+    Ok(power.status.lock().unwrap_or_else(|e| e.into_inner()).clone())
+}
+
+// Sets "power.override" and re-evaluates straight away, for a "run at full
+// speed" toggle that takes effect without waiting for the next check
+#[tauri::command]
+pub async fn set_power_override(app: AppHandle, enabled: bool) -> Result<PowerStatus, String> {
+    tokio::task::spawn_blocking(move || {
+        let db = app.state::<Db>().inner().clone();
+        settings::set(&db.lock(), "power.override", &serde_json::Value::Bool(enabled))?;
+        check(&app);
+        let status = app.state::<Power>().status.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        Ok(status)
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,279 @@
+use crate::audio::{self, AudioData};
+use crate::audio_config::{self, AudioConfig, StreamInfo};
+use crate::db::Db;
+use crate::input::{self, InputStream};
+use crate::paths;
+use crate::settings;
+use crate::takes;
+use serde_json::Value;
+use std::fs;
+use std::sync::atomic::{fence, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tauri::{AppHandle, Manager, State};
+
+const DEFAULT_SECONDS: f64 = 60.0;
+const MAX_SECONDS: f64 = 600.0;
+// Whatever the length asked for, the buffer never holds more than this;
+// ten minutes of 8 channels at 96 kHz would otherwise be 1.8 GB
+const MAX_BUFFER_MB: usize = 256;
+const CAPTURE_FOLDER: &str = "Captures";
+
+// The last `samples.len()` interleaved samples from the input, oldest
+// overwritten first. The input callback is the only writer and never waits:
+// samples are stored as bits in atomics, so a capture can copy them out while
+// the input keeps writing.
+struct Ring {
+    samples: Box<[AtomicU32]>,
+    // Samples pushed since the start; the next one goes at `written % len`
+    written: AtomicUsize,
+    // Raised before a block is stored, so a reader can tell which samples
+    // changed under it even while the block is half written
+    claimed: AtomicUsize,
+}
+
+impl Ring {
+    fn new(len: usize) -> Ring {
+        Ring {
+            samples: (0..len).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            claimed: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, mut block: &[f32]) {
+        let len = self.samples.len();
+        if len == 0 {
+            return;
+        }
+        let start = self.written.load(Ordering::Relaxed);
+        let end = start + block.len();
+        block = &block[block.len().saturating_sub(len)..];
+        self.claimed.store(end, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (i, sample) in block.iter().enumerate() {
+            self.samples[(end - block.len() + i) % len].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written.store(end, Ordering::Release);
+    }
+
+    fn filled(&self) -> usize {
+        self.written.load(Ordering::Acquire).min(self.samples.len())
+    }
+
+    // The newest `count` samples in order, in whole frames of `channels`.
+    // Any overwritten while they were copied are dropped from the front.
+    fn last(&self, count: usize, channels: usize) -> Vec<f32> {
+        let len = self.samples.len();
+        let end = self.written.load(Ordering::Acquire);
+        let count = count.min(end).min(len);
+        let mut out: Vec<f32> = (end - count..end).map(|i| f32::from_bits(self.samples[i % len].load(Ordering::Relaxed))).collect();
+        fence(Ordering::Acquire);
+        let advanced = self.claimed.load(Ordering::Relaxed) - end;
+        let lost = (count + advanced).saturating_sub(len);
+        let channels = channels.max(1);
+        let lost = (lost + channels - 1) / channels * channels;
+        out.drain(..lost.min(out.len()));
+        out
+    }
+}
+
+struct Listening {
+    stream: InputStream,
+    ring: Arc<Ring>,
+    seconds: f64,
+}
+
+// The always-on input buffer behind capture_last
+#[derive(Default)]
+pub struct PreRoll {
+    listening: Mutex<Option<Listening>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PreRollStatus {
+    listening: bool,
+    device: Option<String>,
+    sample_rate: u32,
+    channels: usize,
+    // Length of the buffer, after the memory cap
+    seconds: f64,
+    // How much of it has audio in it yet
+    buffered: f64,
+    // The input dropped out; start again to pick it back up
+    failed: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct CapturedAudio {
+    path: String,
+    seconds: f64,
+}
+
+impl PreRoll {
+    fn lock(&self) -> MutexGuard<'_, Option<Listening>> {
+        self.listening.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn open(&self, device: Option<&str>, seconds: f64, config: &AudioConfig) -> Result<(), String> {
+        let mut listening = self.lock();
+        // Release the old input before opening what may be the same one
+        *listening = None;
+        // Sized from the format the input will open with, so the callback
+        // never has to wait on a resize
+        let (chosen, _) = audio_config::choose(&input::find_device(device)?, false, config)?;
+        let channels = (chosen.channels as usize).max(1);
+        let max_frames = MAX_BUFFER_MB * 1024 * 1024 / (channels * std::mem::size_of::<f32>());
+        let frames = ((seconds.clamp(1.0, MAX_SECONDS) * chosen.sample_rate.0 as f64) as usize).min(max_frames);
+        let ring = Arc::new(Ring::new(frames * channels));
+        let writer = ring.clone();
+        let stream = input::open(device, config, move |block| writer.push(block))?;
+        let seconds = ring.samples.len() as f64 / (stream.channels.max(1) * stream.sample_rate.max(1) as usize) as f64;
+        *listening = Some(Listening { stream, ring, seconds });
+        Ok(())
+    }
+
+    // Opens the same input again, after the engine's settings change
+    pub fn reopen(&self, config: &AudioConfig) -> Result<(), String> {
+        let current = self.lock().as_ref().map(|l| (l.stream.device.clone(), l.seconds));
+        match current {
+            Some((device, seconds)) => self.open(Some(&device), seconds, config),
+            None => Ok(()),
+        }
+    }
+
+    pub fn input_info(&self) -> Option<StreamInfo> {
+        self.lock().as_ref().map(|l| l.stream.info())
+    }
+
+    fn status(&self) -> PreRollStatus {
+        match self.lock().as_ref() {
+            Some(l) => {
+                let frames = l.ring.filled() / l.stream.channels.max(1);
+                PreRollStatus {
+                    listening: true,
+                    device: Some(l.stream.device.clone()),
+                    sample_rate: l.stream.sample_rate,
+                    channels: l.stream.channels,
+                    seconds: l.seconds,
+                    buffered: frames as f64 / l.stream.sample_rate.max(1) as f64,
+                    failed: l.stream.failed(),
+                }
+            }
+            None => PreRollStatus {
+                listening: false,
+                device: None,
+                sample_rate: 0,
+                channels: 0,
+                seconds: 0.0,
+                buffered: 0.0,
+                failed: false,
+            },
+        }
+    }
+}
+
+// Buffers when the open profile had it on last, at launch and after a
+// profile switch
+pub fn reload(app: &AppHandle) {
+    let db = app.state::<Db>();
+    let (enabled, device, seconds, config) = {
+        let conn = db.lock();
+        (
+            settings::get::<bool>(&conn, "preroll.enabled").ok().flatten().unwrap_or(false),
+            settings::get::<String>(&conn, "preroll.device").ok().flatten(),
+            settings::get::<f64>(&conn, "preroll.seconds").ok().flatten().unwrap_or(DEFAULT_SECONDS),
+            audio_config::load(&conn),
+        )
+    };
+    if enabled {
+        // A missing interface shouldn't stop the app; the status says so
+        let _ = app.state::<PreRoll>().open(device.as_deref(), seconds, &config);
+    } else {
+        *app.state::<PreRoll>().lock() = None;
+    }
+}
+
+// Keeps the last `seconds` (default 60, or "preroll.seconds") of `device`
+// in memory so capture_last can save something played before recording
+// started. The choice is remembered across launches.
+#[tauri::command]
+pub async fn start_preroll(
+    db: State<'_, Db>,
+    preroll: State<'_, PreRoll>,
+    device: Option<String>,
+    seconds: Option<f64>,
+) -> Result<PreRollStatus, String> {
+    let seconds = match seconds {
+        Some(seconds) => seconds,
+        None => settings::get(&db.lock(), "preroll.seconds")?.unwrap_or(DEFAULT_SECONDS),
+    };
+    if seconds.is_nan() || seconds <= 0.0 {
+        return Err("Buffer length must be positive".to_string());
+    }
+    let config = audio_config::load(&db.lock());
+    preroll.open(device.as_deref(), seconds, &config)?;
+    let conn = db.lock();
+    settings::set(&conn, "preroll.enabled", &Value::Bool(true))?;
+    settings::set(&conn, "preroll.device", &device.map(Value::String).unwrap_or(Value::Null))?;
+    settings::set(&conn, "preroll.seconds", &serde_json::json!(seconds))?;
+    Ok(preroll.status())
+}
+
+#[tauri::command]
+pub async fn stop_preroll(db: State<'_, Db>, preroll: State<'_, PreRoll>) -> Result<(), String> {
+    *preroll.lock() = None;
+    settings::set(&db.lock(), "preroll.enabled", &Value::Bool(false))
+}
+
+#[tauri::command]
+pub async fn get_preroll_status(preroll: State<'_, PreRoll>) -> Result<PreRollStatus, String> {
+    Ok(preroll.status())
+}
+
+// Saves the last `seconds` of the buffer (all of it when omitted) as a WAV
+// in `dest_dir`, or the recordings folder's Captures, and indexes it
+#[tauri::command]
+pub async fn capture_last(
+    app: AppHandle,
+    db: State<'_, Db>,
+    preroll: State<'_, PreRoll>,
+    seconds: Option<f64>,
+    dest_dir: Option<String>,
+) -> Result<CapturedAudio, String> {
+    // Only the ring's handle is taken under the lock; the copy, up to the
+    // buffer's full size, happens off the async runtime
+    let (ring, sample_rate, channels, frames) = {
+        let listening = preroll.lock();
+        let l = listening.as_ref().ok_or_else(|| "The pre-roll buffer isn't running".to_string())?;
+        let frames = (seconds.unwrap_or(l.seconds).max(0.0) * l.stream.sample_rate as f64) as usize;
+        (l.ring.clone(), l.stream.sample_rate, l.stream.channels.max(1), frames)
+    };
+
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let captured = AudioData { sample_rate, channels, samples: ring.last(frames * channels, channels) };
+        if captured.samples.is_empty() {
+            return Err("Nothing has come in on the input yet".to_string());
+        }
+        let dir = match dest_dir {
+            Some(dir) => paths::to_fs(&dir),
+            None => takes::recordings_folder(&app, &db.lock())?.join(CAPTURE_FOLDER),
+        };
+        fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        let stamp = chrono::Local::now().format("%Y-%m-%d %H%M%S");
+        let mut output = dir.join(format!("Capture {}.wav", stamp));
+        let mut n = 2;
+        while output.exists() {
+            output = dir.join(format!("Capture {} {}.wav", stamp, n));
+            n += 1;
+        }
+        audio::write_wav(&output, &captured)?;
+        takes::index(&db.lock(), &output)?;
+        Ok(CapturedAudio {
+            path: paths::display(&output),
+            seconds: captured.samples.len() as f64 / (captured.channels * captured.sample_rate.max(1) as usize) as f64,
+        })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,210 @@
+use crate::db::{self, Db};
+use crate::paths;
+use crate::settings;
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::Path;
+use tauri::State;
+
+// Bigger than this it's a bank with samples in it; the header is enough
+const MAX_READ_BYTES: u64 = 4 * 1024 * 1024;
+// Where an .fxp/.fxb keeps the plugin's four-character ID and program name
+const FXP_ID: std::ops::Range<usize> = 16..20;
+const FXP_NAME: std::ops::Range<usize> = 28..56;
+// A .vstpreset's class ID and the offset of its chunk list
+const VST3_CLASS: std::ops::Range<usize> = 8..40;
+const VST3_LIST: std::ops::Range<usize> = 40..48;
+
+// VST2 IDs of plugins common enough to name from the ID alone
+const KNOWN_IDS: &[(&str, &str)] = &[("XfsX", "Serum"), ("syl1", "Sylenth1")];
+
+// Plugin names looked for in folder names when the file doesn't say, since
+// preset packs are nearly always filed under the plugin they're for
+const KNOWN_PLUGINS: &[&str] = &[
+    "Serum", "Vital", "Massive X", "Massive", "Sylenth1", "Spire", "Omnisphere", "Diva", "Pigments", "Phase Plant", "Hive",
+    "Zebra", "Nexus", "Kontakt", "FabFilter", "Valhalla", "Ozone", "Analog Lab", "Repro",
+];
+
+#[derive(Default)]
+struct PresetInfo {
+    plugin: Option<String>,
+    // The four-character VST2 ID, VST3 class ID, or AU manufacturer/subtype
+    plugin_id: Option<String>,
+    preset_name: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct PresetPlugin {
+    plugin: String,
+    presets: i64,
+}
+
+// Whether library scans pick up plugin presets ("scan.plugin_presets")
+pub fn enabled(conn: &Connection) -> bool {
+    settings::get::<bool>(conn, "scan.plugin_presets").ok().flatten().unwrap_or(false)
+}
+
+fn text(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    let text = String::from_utf8_lossy(&bytes[..end]).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+// The value after `<key>name</key>` in an XML plist, whatever its element
+fn plist_value(xml: &str, key: &str) -> Option<String> {
+    let marker = format!("<key>{}</key>", key);
+    let rest = &xml[xml.find(&marker)? + marker.len()..];
+    let start = rest.find('>')? + 1;
+    let end = start + rest[start..].find('<')?;
+    Some(rest[start..end].trim().to_string())
+}
+
+fn fourcc(value: &str) -> Option<String> {
+    let code: i64 = value.parse().ok()?;
+    let bytes = (code as u32).to_be_bytes();
+    bytes.iter().all(|b| b.is_ascii_graphic() || *b == b' ').then(|| String::from_utf8_lossy(&bytes).to_string())
+}
+
+fn read_fxp(bytes: &[u8]) -> PresetInfo {
+    if bytes.get(0..4) != Some(b"CcnK".as_slice()) {
+        return PresetInfo::default();
+    }
+    let plugin_id = bytes.get(FXP_ID).and_then(text);
+    PresetInfo {
+        plugin: plugin_id.as_deref().and_then(|id| KNOWN_IDS.iter().find(|(known, _)| *known == id)).map(|(_, name)| name.to_string()),
+        plugin_id,
+        // Banks have no single program name
+        preset_name: (bytes.get(8..12) != Some(b"FxBk".as_slice()) && bytes.get(8..12) != Some(b"FBCh".as_slice()))
+            .then(|| bytes.get(FXP_NAME).and_then(text))
+            .flatten(),
+    }
+}
+
+// The class ID from the header, and the plugin's name from the Info chunk
+// (XML) that most hosts write. Offsets come from the file, so one pointing
+// past anything addressable is an error rather than an overflow.
+fn read_vstpreset(bytes: &[u8]) -> Result<PresetInfo, String> {
+    if bytes.get(0..4) != Some(b"VST3".as_slice()) {
+        return Ok(PresetInfo::default());
+    }
+    let corrupt = || "The preset's chunk list points outside the file".to_string();
+    let at = |start: usize, offset: usize| start.checked_add(offset).ok_or_else(corrupt);
+    let mut info = PresetInfo { plugin_id: bytes.get(VST3_CLASS).and_then(text), ..PresetInfo::default() };
+    let list = bytes
+        .get(VST3_LIST)
+        .and_then(|b| b.try_into().ok())
+        .map(|b: [u8; 8]| i64::from_le_bytes(b) as usize)
+        .unwrap_or(0);
+    if bytes.get(list..at(list, 4)?) != Some(b"List".as_slice()) {
+        return Ok(info);
+    }
+    let count = bytes.get(at(list, 4)?..at(list, 8)?).and_then(|b| b.try_into().ok()).map(u32::from_le_bytes).unwrap_or(0) as usize;
+    for i in 0..count.min(16) {
+        let entry = at(list, i.checked_mul(20).and_then(|o| o.checked_add(8)).ok_or_else(corrupt)?)?;
+        if bytes.get(entry..at(entry, 4)?) != Some(b"Info".as_slice()) {
+            continue;
+        }
+        let field = |start: usize| -> Result<Option<usize>, String> {
+            Ok(bytes.get(start..at(start, 8)?).and_then(|b| b.try_into().ok()).map(|b: [u8; 8]| i64::from_le_bytes(b) as usize))
+        };
+        let (Some(offset), Some(size)) = (field(at(entry, 4)?)?, field(at(entry, 12)?)?) else { break };
+        let Some(chunk) = bytes.get(offset..at(offset, size)?) else { break };
+        let xml = String::from_utf8_lossy(chunk);
+        let attribute = |id: &str| {
+            let at = xml.find(&format!("id=\"{}\"", id))?;
+            let value = &xml[at..][xml[at..].find("value=\"")? + 7..];
+            Some(value[..value.find('"')?].to_string())
+        };
+        info.plugin = attribute("PlugInName");
+        info.preset_name = attribute("MediaName");
+        break;
+    }
+    Ok(info)
+}
+
+// AU presets are XML property lists naming the component by manufacturer
+// and subtype codes. Binary plists fall back to the folder name.
+fn read_aupreset(bytes: &[u8]) -> PresetInfo {
+    let xml = String::from_utf8_lossy(bytes);
+    if !xml.contains("<plist") {
+        return PresetInfo::default();
+    }
+    let code = |key: &str| plist_value(&xml, key).as_deref().and_then(fourcc);
+    PresetInfo {
+        plugin: None,
+        plugin_id: match (code("manufacturer"), code("subtype")) {
+            (Some(manufacturer), Some(subtype)) => Some(format!("{}/{}", manufacturer, subtype)),
+            _ => None,
+        },
+        preset_name: plist_value(&xml, "name"),
+    }
+}
+
+// The nearest folder named after a plugin we know, or for AU presets, the
+// folder they're filed in (Presets/<maker>/<plugin>/)
+fn plugin_from_path(path: &Path, format: &str) -> Option<String> {
+    let folders: Vec<String> = path.parent()?.components().rev().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+    // Whole words only, so "Hive" isn't found in "Archive"
+    let words = |text: &str| format!(" {} ", text.to_lowercase().split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect::<Vec<_>>().join(" "));
+    for folder in &folders {
+        let folder = words(folder);
+        if let Some(known) = KNOWN_PLUGINS.iter().find(|p| folder.contains(&words(p))) {
+            return Some(known.to_string());
+        }
+    }
+    if format == "aupreset" {
+        return folders.first().cloned();
+    }
+    None
+}
+
+fn read(path: &Path) -> (String, PresetInfo) {
+    let format = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let small = fs::metadata(path).map(|m| m.len() <= MAX_READ_BYTES).unwrap_or(false);
+    let bytes = if small { fs::read(path).unwrap_or_default() } else { Vec::new() };
+    let mut info = match format.as_str() {
+        "fxp" | "fxb" => read_fxp(&bytes),
+        // A corrupt one is named from its path like any other unreadable preset
+        "vstpreset" => read_vstpreset(&bytes).unwrap_or_default(),
+        "aupreset" => read_aupreset(&bytes),
+        _ => PresetInfo::default(),
+    };
+    if info.plugin.is_none() {
+        info.plugin = plugin_from_path(path, &format);
+    }
+    if info.preset_name.is_none() {
+        info.preset_name = path.file_stem().map(|s| s.to_string_lossy().to_string());
+    }
+    (format, info)
+}
+
+// Notes what a preset file is for, alongside its library entry
+pub fn record(conn: &Connection, path: &str, modified: i64) -> Result<(), String> {
+    let (format, info) = read(&paths::to_fs(path));
+    conn.execute(
+        "INSERT OR REPLACE INTO plugin_presets (path, format, plugin, plugin_id, preset_name, modified, indexed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![path, format, info.plugin, info.plugin_id, info.preset_name, modified, db::now()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Plugins the indexed presets are for, most presets first
+#[tauri::command]
+pub async fn list_preset_plugins(db: State<'_, Db>) -> Result<Vec<PresetPlugin>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare(
+            "SELECT plugin, COUNT(*) FROM plugin_presets
+             WHERE plugin IS NOT NULL AND path IN (SELECT path FROM files)
+             GROUP BY plugin COLLATE NOCASE ORDER BY COUNT(*) DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let plugins = stmt
+        .query_map([], |row| Ok(PresetPlugin { plugin: row.get(0)?, presets: row.get(1)? }))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(plugins)
+}
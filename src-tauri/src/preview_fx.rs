@@ -0,0 +1,169 @@
+use crate::dsp::Biquad;
+
+// Freeverb's delay lengths at 44.1 kHz, scaled to the device rate
+const COMBS: [usize; 4] = [1116, 1188, 1277, 1356];
+const ALLPASSES: [usize; 2] = [556, 441];
+// Right channels read slightly longer delays so the tail is wide
+const STEREO_SPREAD: usize = 23;
+const REVERB_INPUT: f32 = 0.015;
+const REVERB_OUTPUT: f32 = 3.0;
+const FEEDBACK: f32 = 0.84;
+const DAMPING: f32 = 0.2;
+// Where the tilt EQ pivots
+const TILT_HZ: f64 = 1000.0;
+// What the controls accept; anything past these is pulled back in
+const CUTOFF_HZ: std::ops::RangeInclusive<f32> = 10.0..=24_000.0;
+const MAX_TILT_DB: f32 = 12.0;
+
+// What the preview player's effect slots are set to. The defaults leave
+// the sound untouched.
+#[derive(Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PreviewFxParams {
+    // High-pass cutoff, None for off
+    #[serde(default)]
+    low_cut_hz: Option<f32>,
+    // Low-pass cutoff, None for off
+    #[serde(default)]
+    high_cut_hz: Option<f32>,
+    // Positive brightens, negative darkens, in dB across the range
+    #[serde(default)]
+    tilt_db: f32,
+    // 0-1 sent to the reverb
+    #[serde(default)]
+    reverb_send: f32,
+}
+
+impl PreviewFxParams {
+    // Within the ranges the chain is built for; a cutoff that isn't a
+    // number is off
+    pub fn clamped(self) -> PreviewFxParams {
+        let cutoff = |hz: Option<f32>| hz.filter(|hz| hz.is_finite()).map(|hz| hz.clamp(*CUTOFF_HZ.start(), *CUTOFF_HZ.end()));
+        let finite = |x: f32| if x.is_finite() { x } else { 0.0 };
+        PreviewFxParams {
+            low_cut_hz: cutoff(self.low_cut_hz),
+            high_cut_hz: cutoff(self.high_cut_hz),
+            tilt_db: finite(self.tilt_db).clamp(-MAX_TILT_DB, MAX_TILT_DB),
+            reverb_send: finite(self.reverb_send).clamp(0.0, 1.0),
+        }
+    }
+
+    fn is_neutral(&self) -> bool {
+        self.low_cut_hz.is_none() && self.high_cut_hz.is_none() && self.tilt_db == 0.0 && self.reverb_send <= 0.0
+    }
+}
+
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    store: f32,
+}
+
+impl Comb {
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.buffer[self.index];
+        self.store = y * (1.0 - DAMPING) + self.store * DAMPING;
+        self.buffer[self.index] = x + self.store * FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+        y
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    fn process(&mut self, x: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = x + delayed * 0.5;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - x
+    }
+}
+
+struct ChannelFx {
+    filters: Vec<Biquad>,
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+// The chain for one output: filters and tilt in series, the reverb fed in
+// parallel. State is per output channel and built for the device rate, off
+// the audio thread: the callback never allocates, it only runs the chain.
+#[derive(Default)]
+pub struct PreviewFx {
+    params: PreviewFxParams,
+    rate: u32,
+    channels: Vec<ChannelFx>,
+}
+
+impl PreviewFx {
+    // `rate` 0 when no output is open yet; it's built again once one is
+    pub fn new(params: PreviewFxParams, rate: u32, count: usize) -> PreviewFx {
+        let mut fx = PreviewFx { params: params.clamped(), rate, channels: Vec::new() };
+        if rate > 0 && !fx.params.is_neutral() {
+            fx.build(count);
+        }
+        fx
+    }
+
+    pub fn params(&self) -> PreviewFxParams {
+        self.params.clone()
+    }
+
+    fn build(&mut self, count: usize) {
+        let rate = self.rate;
+        let nyquist = rate as f32 * 0.45;
+        let scale = rate as f64 / 44_100.0;
+        let delay = |samples: usize| ((samples as f64 * scale) as usize).max(1);
+        self.channels = (0..count)
+            .map(|c| {
+                let mut filters = Vec::new();
+                if let Some(hz) = self.params.low_cut_hz {
+                    filters.push(Biquad::high_pass(rate, hz.clamp(10.0, nyquist) as f64, 0.707));
+                }
+                if let Some(hz) = self.params.high_cut_hz {
+                    filters.push(Biquad::low_pass(rate, hz.clamp(10.0, nyquist) as f64, 0.707));
+                }
+                if self.params.tilt_db != 0.0 {
+                    let half = self.params.tilt_db as f64 / 2.0;
+                    filters.push(Biquad::low_shelf(rate, TILT_HZ, 0.5, -half));
+                    filters.push(Biquad::high_shelf(rate, TILT_HZ, 0.5, half));
+                }
+                let spread = if c % 2 == 1 { STEREO_SPREAD } else { 0 };
+                ChannelFx {
+                    filters,
+                    combs: COMBS
+                        .iter()
+                        .map(|n| Comb { buffer: vec![0.0; delay(n + spread)], index: 0, store: 0.0 })
+                        .collect(),
+                    allpasses: ALLPASSES
+                        .iter()
+                        .map(|n| Allpass { buffer: vec![0.0; delay(n + spread)], index: 0 })
+                        .collect(),
+                }
+            })
+            .collect();
+    }
+
+    // Runs one output frame through the chain in place. Until a chain for
+    // this rate is swapped in (just after the output opens) it stays dry.
+    pub fn process(&mut self, frame: &mut [f32], rate: u32) {
+        if self.params.is_neutral() || self.rate != rate {
+            return;
+        }
+        let send = self.params.reverb_send;
+        for (sample, fx) in frame.iter_mut().zip(self.channels.iter_mut()) {
+            let dry = fx.filters.iter_mut().fold(*sample, |x, f| f.process(x));
+            let wet = if send > 0.0 {
+                let input = dry * send * REVERB_INPUT;
+                let combed: f32 = fx.combs.iter_mut().map(|comb| comb.process(input)).sum();
+                fx.allpasses.iter_mut().fold(combed, |x, allpass| allpass.process(x)) * REVERB_OUTPUT
+            } else {
+                0.0
+            };
+            *sample = dry + wet;
+        }
+    }
+}
@@ -0,0 +1,31 @@
+// Lowers the OS scheduling priority of the calling thread, so heavy
+// background work yields to anything else on the machine, above all a DAW's
+// audio thread. Best effort: returns false where the OS refuses or the
+// platform has no per-thread priority.
+#[cfg(target_os = "linux")]
+pub fn lower_current_thread() -> bool {
+    // On Linux nice values are per thread when addressed by thread id.
+    // Unprivileged processes can't raise them again, which is why low
+    // priority jobs get a thread of their own instead of a pooled one.
+    unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        libc::setpriority(libc::PRIO_PROCESS, tid, 10) == 0
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn lower_current_thread() -> bool {
+    // Background QoS: lowest CPU priority and throttled disk I/O
+    unsafe { libc::setpriority(libc::PRIO_DARWIN_THREAD, 0, libc::PRIO_DARWIN_BG) == 0 }
+}
+
+#[cfg(windows)]
+pub fn lower_current_thread() -> bool {
+    use windows_sys::Win32::System::Threading::{GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_LOWEST};
+    unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_LOWEST) != 0 }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn lower_current_thread() -> bool {
+    false
+}
@@ -0,0 +1,194 @@
+use crate::ai::AiQueue;
+use crate::audio_config;
+use crate::collections::safe_file_name;
+use crate::db::{self, Db};
+use crate::encryption;
+use crate::http::Http;
+use crate::jobs::Scheduler;
+use crate::midi_capture;
+use crate::midi_learn;
+use crate::preroll;
+use crate::proxy;
+use crate::sampler;
+use crate::sandbox::Sandbox;
+use crate::workspace::{self, WorkspaceLock};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, Manager, State};
+
+// The profile using the original library.db in the data folder
+const DEFAULT_PROFILE: &str = "Default";
+// Which profile opens at launch; outside any one database since it picks
+// the database
+const ACTIVE_FILE: &str = "profile.json";
+
+#[derive(serde::Serialize)]
+pub struct Profile {
+    name: String,
+    active: bool,
+    size_bytes: u64,
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct ActiveProfile {
+    name: Option<String>,
+}
+
+fn profiles_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("profiles")
+}
+
+// Each profile has its own database, and so its own library, settings and
+// AI conversations
+pub fn database_path(data_dir: &Path, name: &str) -> PathBuf {
+    if name.eq_ignore_ascii_case(DEFAULT_PROFILE) {
+        data_dir.join("library.db")
+    } else {
+        profiles_dir(data_dir).join(name).join("library.db")
+    }
+}
+
+// The profile last switched to, if it's still there
+pub fn active(data_dir: &Path) -> String {
+    let saved: ActiveProfile = fs::read(data_dir.join(ACTIVE_FILE))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default();
+    saved
+        .name
+        .filter(|name| database_path(data_dir, name).exists())
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+}
+
+fn save_active(data_dir: &Path, name: &str) -> Result<(), String> {
+    let json = serde_json::to_vec(&ActiveProfile { name: Some(name.to_string()) }).map_err(|e| e.to_string())?;
+    fs::write(data_dir.join(ACTIVE_FILE), json).map_err(|e| e.to_string())
+}
+
+fn names(data_dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(profiles_dir(data_dir))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().join("library.db").is_file())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort_by_key(|name| name.to_lowercase());
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    names
+}
+
+fn find(data_dir: &Path, name: &str) -> Result<String, String> {
+    names(data_dir)
+        .into_iter()
+        .find(|n| n.eq_ignore_ascii_case(name.trim()))
+        .ok_or_else(|| format!("No profile called {}", name))
+}
+
+fn data_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    app.path().app_data_dir().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn list_profiles(app: AppHandle, db: State<'_, Db>) -> Result<Vec<Profile>, String> {
+    let data_dir = data_dir(&app)?;
+    let open = db.path();
+    Ok(names(&data_dir)
+        .into_iter()
+        .map(|name| {
+            let path = database_path(&data_dir, &name);
+            Profile {
+                active: path == open,
+                size_bytes: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+                name,
+            }
+        })
+        .collect())
+}
+
+// Creates an empty profile; switch_profile opens it
+#[tauri::command]
+pub async fn create_profile(app: AppHandle, name: String) -> Result<Profile, String> {
+    let data_dir = data_dir(&app)?;
+    let name = safe_file_name(&name);
+    if name.is_empty() {
+        return Err("The profile needs a name".to_string());
+    }
+    if find(&data_dir, &name).is_ok() {
+        return Err(format!("There's already a profile called {}", name));
+    }
+    let path = database_path(&data_dir, &name);
+    // Opening it once creates the file with the current schema
+    Db::open(&path)?;
+    Ok(Profile { name, active: false, size_bytes: fs::metadata(&path).map(|m| m.len()).unwrap_or(0) })
+}
+
+// Closes the open profile and opens `name`: the library, settings and AI
+// conversations all come from its database from now on, and its audio and
+// MIDI setup is reopened. Refuses while a job is running; queued jobs and
+// unsent AI requests belong to the old profile and are cancelled.
+#[tauri::command]
+pub async fn switch_profile(app: AppHandle, db: State<'_, Db>, scheduler: State<'_, Scheduler>, name: String) -> Result<Profile, String> {
+    let data_dir = data_dir(&app)?;
+    let name = find(&data_dir, &name)?;
+    let path = database_path(&data_dir, &name);
+    if path == db.path() {
+        return Err(format!("{} is already open", name));
+    }
+    let lock = app.state::<WorkspaceLock>();
+    lock.check_free(&path)?;
+
+    db::exclusive(&scheduler, || {
+        scheduler.cancel_queued();
+        db.switch_to(&path)
+    })?;
+    lock.claim(&path, true)?;
+    app.state::<AiQueue>().cancel_pending(&app);
+    app.state::<Sandbox>().reset(&db)?;
+    scheduler.configure(&db);
+    // Jobs were paused if the old profile's lock had been lost
+    workspace::resume(&app)?;
+    // Audio and MIDI settings are per profile too. Reopening the devices
+    // can block on a slow interface, so it happens off the async runtime.
+    let handle = app.clone();
+    tokio::task::spawn_blocking(move || {
+        audio_config::reload(&handle);
+        preroll::reload(&handle);
+        midi_capture::reload(&handle);
+        midi_learn::reload(&handle);
+        sampler::reload(&handle);
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?;
+    // Proxy settings are per profile too; a bad certificate path there
+    // leaves the old client in use rather than failing the switch
+    let _ = app.state::<Http>().configure();
+    save_active(&data_dir, &name)?;
+    let _ = app.emit("profile-changed", &name);
+
+    Ok(Profile { active: true, size_bytes: db.size_on_disk(), name })
+}
+
+// Deletes a profile and its database. The open profile and the default
+// one can't be deleted.
+#[tauri::command]
+pub async fn delete_profile(app: AppHandle, db: State<'_, Db>, name: String) -> Result<(), String> {
+    let data_dir = data_dir(&app)?;
+    let name = find(&data_dir, &name)?;
+    if name == DEFAULT_PROFILE {
+        return Err("The default profile can't be deleted".to_string());
+    }
+    let path = database_path(&data_dir, &name);
+    if path == db.path() {
+        return Err(format!("{} is open; switch to another profile first", name));
+    }
+    let dir = profiles_dir(&data_dir).join(&name);
+    fs::remove_dir_all(&dir).map_err(|e| format!("Failed to delete {}: {}", dir.display(), e))?;
+    // A profile made later under the same name starts unencrypted and
+    // without the old one's proxy password
+    let _ = encryption::store_key(&path, None);
+    let _ = proxy::store_password(&path, None);
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use crate::analysis::NOTE_NAMES;
+use crate::db::{self, Db};
+use crate::jobs::{Priority, Scheduler};
+use crate::midi::{self, Note};
+use crate::paths;
+use crate::quarantine;
+use crate::theory::{self, Key};
+use rusqlite::{params, OptionalExtension};
+use tauri::State;
+
+const DEFAULT_LIMIT: usize = 200;
+// General MIDI drums, counted from zero; they have no harmony to read
+const DRUM_CHANNEL: u8 = 9;
+// Share of a segment's sound that has to sit on the chord's tones
+const MIN_FIT: f32 = 0.6;
+// Sound off the chord counts against it at this weight
+const OUTSIDE_PENALTY: f32 = 0.5;
+// Nudge toward the chord the bass line spells
+const BASS_BONUS: f32 = 0.1;
+// Share of the segment a 7th needs before the chord is called a 7th chord
+const SEVENTH_SHARE: f32 = 0.1;
+
+// Triads listened for, by spelling and intervals, with the 7th chords each
+// grows into. Earlier entries win ties.
+const TEMPLATES: &[(&str, [usize; 3], &[(usize, &str)])] = &[
+    ("", [0, 4, 7], &[(10, "7"), (11, "maj7")]),
+    ("m", [0, 3, 7], &[(10, "m7")]),
+    ("dim", [0, 3, 6], &[(10, "m7b5"), (9, "dim7")]),
+    ("aug", [0, 4, 8], &[]),
+    ("sus4", [0, 5, 7], &[(10, "7sus4")]),
+];
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct DetectedChord {
+    // 1-based, as DAWs count
+    bar: usize,
+    beat: f64,
+    symbol: String,
+    root: usize,
+    // From theory::Chord::triad, which is what searches compare
+    triad: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ProgressionMatch {
+    path: String,
+    bar: usize,
+    beat: f64,
+    chords: Vec<String>,
+    // The key the numerals land in here; None for patterns of chord names
+    key: Option<String>,
+    // How far above the pattern as written (numerals read in C)
+    semitones: usize,
+}
+
+// The best chord for a segment's pitch-class weights, if any fits
+fn best_chord(weights: &[f32; 12], bass: Option<usize>) -> Option<theory::Chord> {
+    let total: f32 = weights.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let share = |pc: usize| weights[pc % 12] / total;
+    let mut best: Option<(f32, usize, usize)> = None;
+    for root in 0..12 {
+        for (t, (_, intervals, _)) in TEMPLATES.iter().enumerate() {
+            // The root and what decides the quality have to be heard
+            if share(root) == 0.0 || share(root + intervals[1]) == 0.0 {
+                continue;
+            }
+            let fit: f32 = intervals.iter().map(|i| share(root + i)).sum();
+            if fit < MIN_FIT {
+                continue;
+            }
+            let score = fit - OUTSIDE_PENALTY * (1.0 - fit) + if bass == Some(root) { BASS_BONUS } else { 0.0 };
+            let better = match best {
+                Some((best_score, _, _)) => score > best_score,
+                None => true,
+            };
+            if better {
+                best = Some((score, root, t));
+            }
+        }
+    }
+    let (_, root, t) = best?;
+    let (triad, _, sevenths) = TEMPLATES[t];
+    let spelling = sevenths.iter().find(|(i, _)| share(root + i) >= SEVENTH_SHARE).map(|(_, s)| *s).unwrap_or(triad);
+    theory::parse_chord(&format!("{}{}", NOTE_NAMES[root], spelling)).ok()
+}
+
+// Chords in half-bar steps (whole bars in odd meters), with repeats of the
+// same chord folded into the first
+fn detect(notes: &[Note], beats_per_bar: u8) -> Vec<DetectedChord> {
+    let notes: Vec<&Note> = notes.iter().filter(|n| n.channel != DRUM_CHANNEL).collect();
+    let bar = beats_per_bar.max(1) as f64;
+    let step = if beats_per_bar % 2 == 0 { bar / 2.0 } else { bar };
+    let end = notes.iter().map(|n| n.start + n.duration).fold(0.0, f64::max);
+    let mut chords: Vec<DetectedChord> = Vec::new();
+    let mut from = 0.0;
+    while from < end {
+        let to = from + step;
+        let mut weights = [0.0f32; 12];
+        let mut bass: Option<u8> = None;
+        for note in &notes {
+            let overlap = (note.start + note.duration).min(to) - note.start.max(from);
+            if overlap <= 0.0 {
+                continue;
+            }
+            weights[note.pitch as usize % 12] += overlap as f32;
+            bass = Some(bass.map_or(note.pitch, |b| b.min(note.pitch)));
+        }
+        if let Some(chord) = best_chord(&weights, bass.map(|b| b as usize % 12)) {
+            let symbol = chord.symbol();
+            match chords.last() {
+                Some(last) if last.symbol == symbol => {}
+                _ => chords.push(DetectedChord {
+                    bar: (from / bar).floor() as usize + 1,
+                    beat: from % bar + 1.0,
+                    symbol,
+                    root: chord.root,
+                    triad: chord.triad().to_string(),
+                }),
+            }
+        }
+        from = to;
+    }
+    chords
+}
+
+// Chords of an indexed file, detected once per version of it
+fn chords_for(db: &Db, path: &str, modified: Option<i64>) -> Result<Vec<DetectedChord>, String> {
+    if let Some(modified) = modified {
+        let cached: Option<String> = db
+            .lock()
+            .query_row("SELECT data FROM midi_chords WHERE path = ?1 AND modified = ?2", params![path, modified], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if let Some(chords) = cached.and_then(|json| serde_json::from_str(&json).ok()) {
+            return Ok(chords);
+        }
+    }
+    let chords = quarantine::guard(db, path, || -> Result<_, String> {
+        let file = midi::read(&paths::to_fs(path))?;
+        Ok(detect(&file.notes(), file.beats_per_bar))
+    })?;
+    if let Some(modified) = modified {
+        let json = serde_json::to_string(&chords).map_err(|e| e.to_string())?;
+        db.lock()
+            .execute(
+                "INSERT OR REPLACE INTO midi_chords (path, modified, data, analyzed_at) VALUES (?1, ?2, ?3, ?4)",
+                params![path, modified, json, db::now()],
+            )
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(chords)
+}
+
+fn is_numeral(token: &str) -> bool {
+    token.trim_start_matches(['b', '#']).starts_with(['I', 'V', 'i', 'v'])
+}
+
+// Chords of a query as (root, triad), plus the key numerals were read in.
+// Numerals are read in C, minor when the tonic is written "i".
+fn parse_pattern(pattern: &str) -> Result<(Vec<(usize, &'static str)>, Option<Key>), String> {
+    let tokens: Vec<&str> = pattern.split([' ', ',', '-', '–', '—']).filter(|t| !t.is_empty()).collect();
+    if tokens.len() < 2 {
+        return Err("Give at least two chords, like \"i VI III VII\" or \"Am F C G\"".to_string());
+    }
+    let numerals = tokens.iter().any(|t| is_numeral(t));
+    let minor = tokens.iter().any(|t| t.trim_start_matches(['b', '#']).trim_end_matches(|c: char| !c.is_alphabetic()) == "i");
+    let key = numerals.then_some(Key { tonic: 0, minor });
+    let chords = tokens
+        .iter()
+        .map(|token| {
+            let chord = match key {
+                Some(key) if is_numeral(token) => theory::from_numeral(token, &key).ok_or_else(|| format!("{} isn't a numeral we know", token))?,
+                _ => theory::parse_chord(token)?,
+            };
+            Ok((chord.root, chord.triad()))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok((chords, key))
+}
+
+// Every place the pattern's shape occurs, at any transposition
+fn find(path: &str, chords: &[DetectedChord], pattern: &[(usize, &str)], key: Option<Key>) -> Vec<ProgressionMatch> {
+    chords
+        .windows(pattern.len())
+        .filter_map(|window| {
+            let semitones = (window[0].root + 12 - pattern[0].0) % 12;
+            let fits = window.iter().zip(pattern).all(|(c, (root, triad))| (root + semitones) % 12 == c.root && c.triad == *triad);
+            fits.then(|| ProgressionMatch {
+                path: path.to_string(),
+                bar: window[0].bar,
+                beat: window[0].beat,
+                chords: window.iter().map(|c| c.symbol.clone()).collect(),
+                key: key.map(|k| Key { tonic: (k.tonic + semitones) % 12, minor: k.minor }.name()),
+                semitones,
+            })
+        })
+        .collect()
+}
+
+// Chords of a MIDI file by bar and beat, from its notes
+#[tauri::command]
+pub async fn detect_midi_chords(db: State<'_, Db>, scheduler: State<'_, Scheduler>, path: String) -> Result<Vec<DetectedChord>, String> {
+    let db = db.inner().clone();
+    let label = format!("Detect chords {}", path);
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |_| {
+            let modified: Option<i64> = db
+                .lock()
+                .query_row("SELECT modified FROM files WHERE path = ?1", params![path], |row| row.get(0))
+                .optional()
+                .map_err(|e| e.to_string())?;
+            chords_for(&db, &path, modified)
+        })
+        .await
+}
+
+// Finds a progression anywhere in the library's MIDI files, in any key.
+// `pattern` is numerals ("i VI III VII") or chord names ("Am F C G");
+// 7ths and other extensions match their plain triad.
+#[tauri::command]
+pub async fn search_progressions(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    pattern: String,
+    limit: Option<usize>,
+) -> Result<Vec<ProgressionMatch>, String> {
+    let (wanted, key) = parse_pattern(&pattern)?;
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    let db = db.inner().clone();
+    let label = format!("Search progressions {}", pattern);
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |ctx| {
+            let files: Vec<(String, i64)> = {
+                let conn = db.lock();
+                let mut stmt = conn
+                    .prepare("SELECT path, modified FROM files WHERE file_type = 'midi' ORDER BY path")
+                    .map_err(|e| e.to_string())?;
+                let rows = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                    .map_err(|e| e.to_string())?
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| e.to_string())?;
+                rows
+            };
+            let total = files.len().max(1) as f32;
+            let mut matches = Vec::new();
+            for (i, (path, modified)) in files.iter().enumerate() {
+                if ctx.is_cancelled() || matches.len() >= limit {
+                    break;
+                }
+                ctx.progress(i as f32 / total, Some(path.clone()));
+                // Unreadable files are skipped rather than failing the search
+                let Ok(chords) = chords_for(&db, path, Some(*modified)) else { continue };
+                matches.extend(find(path, &chords, &wanted, key));
+            }
+            matches.truncate(limit);
+            Ok(matches)
+        })
+        .await
+}
@@ -0,0 +1,162 @@
+use crate::collections::safe_file_name;
+use crate::db::Db;
+use crate::paths;
+use crate::scanner;
+use crate::settings;
+use crate::takes;
+use std::fs;
+use std::path::Path;
+use tauri::State;
+
+const DEFAULT_NAME: &str = "New song";
+
+// How a new song's folder is laid out. Templates in "project.templates"
+// are offered alongside the built-in ones and replace any of the same name.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ProjectTemplate {
+    name: String,
+    subfolders: Vec<String>,
+    // Where starter audio and MIDI are copied, relative to the project
+    samples_folder: String,
+    midi_folder: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ProjectSkeleton {
+    path: String,
+    folders: Vec<String>,
+    // Starter files as copied into the project
+    copied: Vec<String>,
+    readme: String,
+}
+
+fn built_in() -> Vec<ProjectTemplate> {
+    let template = |name: &str, subfolders: &[&str], samples: &str, midi: &str| ProjectTemplate {
+        name: name.to_string(),
+        subfolders: subfolders.iter().map(|s| s.to_string()).collect(),
+        samples_folder: samples.to_string(),
+        midi_folder: midi.to_string(),
+    };
+    vec![
+        // Ableton keeps imported audio under Samples/Imported in the project
+        template("Ableton", &["Samples/Imported", "Bounces", "Stems", "Ideas"], "Samples/Imported", "Ideas"),
+        template("Logic", &["Audio Files", "Samples", "Bounces", "Stems", "Ideas", "MIDI"], "Samples", "MIDI"),
+        template("Basic", &["Samples", "Bounces", "Stems", "Ideas"], "Samples", "Ideas"),
+    ]
+}
+
+fn templates(db: &Db) -> Result<Vec<ProjectTemplate>, String> {
+    let configured: Vec<ProjectTemplate> = settings::get(&db.lock(), "project.templates")?.unwrap_or_default();
+    let mut all: Vec<ProjectTemplate> = built_in()
+        .into_iter()
+        .filter(|t| !configured.iter().any(|c| c.name.eq_ignore_ascii_case(&t.name)))
+        .collect();
+    all.extend(configured);
+    Ok(all)
+}
+
+// Folder names from a template, kept inside the project
+fn relative(folder: &str) -> Result<String, String> {
+    if folder.split(['/', '\\']).any(|p| p == "..") {
+        return Err(format!("{} isn't a folder inside the project", folder));
+    }
+    let parts: Vec<String> = folder.split(['/', '\\']).map(safe_file_name).filter(|p| !p.is_empty()).collect();
+    if parts.is_empty() {
+        return Err(format!("{} isn't a folder inside the project", folder));
+    }
+    Ok(parts.join("/"))
+}
+
+fn readme(name: &str, date: &str, template: &ProjectTemplate, folders: &[String], starters: &[(String, String)]) -> String {
+    let mut text = format!("# {}\n\nStarted {} from the {} template.\n\n## Folders\n\n", name, date, template.name);
+    for folder in folders {
+        text.push_str(&format!("- {}\n", folder));
+    }
+    if !starters.is_empty() {
+        text.push_str("\n## Starter files\n\n");
+        for (copy, source) in starters {
+            text.push_str(&format!("- {} (from {})\n", copy, source));
+        }
+    }
+    text
+}
+
+#[tauri::command]
+pub async fn list_project_templates(db: State<'_, Db>) -> Result<Vec<ProjectTemplate>, String> {
+    templates(&db)
+}
+
+// Starts a new song: a dated folder under `dest` laid out by `template`,
+// with `starters` (audio and MIDI from the library) copied in and a README
+// listing what's where
+#[tauri::command]
+pub async fn create_project_skeleton(
+    db: State<'_, Db>,
+    template: String,
+    dest: String,
+    name: Option<String>,
+    starters: Option<Vec<String>>,
+) -> Result<ProjectSkeleton, String> {
+    let template = templates(&db)?
+        .into_iter()
+        .find(|t| t.name.eq_ignore_ascii_case(&template))
+        .ok_or_else(|| format!("No project template called {}", template))?;
+    let name = name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).unwrap_or_else(|| DEFAULT_NAME.to_string());
+    let starters = starters.unwrap_or_default();
+    let mut folders = template.subfolders.iter().map(|f| relative(f)).collect::<Result<Vec<_>, _>>()?;
+    let samples_folder = relative(&template.samples_folder)?;
+    let midi_folder = relative(&template.midi_folder)?;
+    for folder in [&samples_folder, &midi_folder] {
+        if !starters.is_empty() && !folders.contains(folder) {
+            folders.push(folder.clone());
+        }
+    }
+
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+        let parent = paths::to_fs(&dest);
+        let stem = safe_file_name(&format!("{} {}", date, name));
+        let mut project = parent.join(&stem);
+        let mut n = 2;
+        while project.exists() {
+            project = parent.join(format!("{} {}", stem, n));
+            n += 1;
+        }
+        for folder in &folders {
+            let dir = project.join(folder);
+            fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+
+        let mut copied = Vec::new();
+        let mut listed = Vec::new();
+        for source in &starters {
+            let from = paths::to_fs(source);
+            let folder = match scanner::file_type_for(&from) {
+                Some("midi") => &midi_folder,
+                Some(_) => &samples_folder,
+                None => return Err(format!("{} isn't an audio or MIDI file", source)),
+            };
+            let file_name = paths::file_name(&from);
+            let mut to = project.join(folder).join(&file_name);
+            let mut n = 2;
+            while to.exists() {
+                let stem = Path::new(&file_name).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+                let extension = Path::new(&file_name).extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+                to = project.join(folder).join(format!("{} {}{}", stem, n, extension));
+                n += 1;
+            }
+            fs::copy(&from, &to).map_err(|e| format!("Failed to copy {}: {}", source, e))?;
+            takes::index(&db.lock(), &to)?;
+            listed.push((format!("{}/{}", folder, paths::file_name(&to)), source.clone()));
+            copied.push(paths::display(&to));
+        }
+
+        let readme_path = project.join("README.md");
+        fs::write(&readme_path, readme(&name, &date, &template, &folders, &listed))
+            .map_err(|e| format!("Failed to write {}: {}", readme_path.display(), e))?;
+        Ok(ProjectSkeleton { path: paths::display(&project), folders, copied, readme: paths::display(&readme_path) })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,158 @@
+use crate::db::Db;
+use crate::encryption::KEYCHAIN_SERVICE;
+use crate::http::Http;
+use crate::paths;
+use crate::settings;
+use keyring::Entry;
+use reqwest::{Certificate, ClientBuilder, NoProxy, Proxy, Url};
+use rusqlite::Connection;
+use std::path::Path;
+use tauri::State;
+
+const SETTINGS_KEY: &str = "network.proxy";
+// The password is kept in the OS keychain rather than the settings table,
+// one entry per profile's database like its passphrase
+const KEYCHAIN_ACCOUNT: &str = "network-proxy";
+const SOCKS_PORT: u16 = 1080;
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ProxySettings {
+    // http://, https:// or socks5:// address. Unset falls back to the
+    // HTTP_PROXY / HTTPS_PROXY environment variables.
+    url: Option<String>,
+    username: Option<String>,
+    // Hosts that skip the proxy, comma separated ("localhost, .corp.example")
+    no_proxy: Option<String>,
+    // PEM or DER files trusted on top of the system's certificates, for
+    // proxies that inspect TLS with their own CA
+    ca_certificates: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ProxyStatus {
+    settings: ProxySettings,
+    has_password: bool,
+}
+
+pub fn load(conn: &Connection) -> Result<ProxySettings, String> {
+    Ok(settings::get(conn, SETTINGS_KEY)?.unwrap_or_default())
+}
+
+fn entry(database: &Path) -> keyring::Result<Entry> {
+    Entry::new(KEYCHAIN_SERVICE, &format!("{}:{}", KEYCHAIN_ACCOUNT, paths::display(database)))
+}
+
+pub fn stored_password(database: &Path) -> Option<String> {
+    entry(database).ok()?.get_password().ok()
+}
+
+// Saves the proxy password for the profile using `database`, or forgets it
+// with None
+pub fn store_password(database: &Path, password: Option<&str>) -> Result<(), String> {
+    let entry = entry(database).map_err(|e| format!("The OS keychain isn't available: {}", e))?;
+    match password {
+        Some(password) => entry
+            .set_password(password)
+            .map_err(|e| format!("Failed to save the proxy password to the keychain: {}", e)),
+        None => match entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Failed to remove the proxy password from the keychain: {}", e)),
+        },
+    }
+}
+
+fn proxy_url(settings: &ProxySettings) -> Result<Option<Url>, String> {
+    let Some(url) = settings.url.as_deref().map(str::trim).filter(|u| !u.is_empty()) else {
+        return Ok(None);
+    };
+    let url = Url::parse(url).map_err(|e| format!("Invalid proxy address {}: {}", url, e))?;
+    if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(format!("Unsupported proxy type: {}", url.scheme()));
+    }
+    Ok(Some(url))
+}
+
+// The proxy reqwest picks up from the environment when none is set
+fn environment_url() -> Option<Url> {
+    let value = ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty()))?;
+    // "proxy.corp:3128" with no scheme is taken as http
+    Url::parse(&value).ok().filter(|url| url.has_host()).or_else(|| Url::parse(&format!("http://{}", value)).ok())
+}
+
+// host:port of the proxy in use, set here or in the environment, which is
+// all the connectivity check can reach directly when there is one
+pub fn address(settings: &ProxySettings) -> Option<String> {
+    let url = match proxy_url(settings).ok()? {
+        Some(url) => url,
+        None => environment_url()?,
+    };
+    let port = url.port_or_known_default().unwrap_or(SOCKS_PORT);
+    Some(format!("{}:{}", url.host_str()?, port))
+}
+
+// A bundle file can hold several certificates
+fn certificates(path: &str) -> Result<Vec<Certificate>, String> {
+    let bytes = std::fs::read(paths::to_fs(path)).map_err(|e| format!("Failed to read certificate {}: {}", path, e))?;
+    let parsed = if bytes.starts_with(b"-----BEGIN") {
+        Certificate::from_pem_bundle(&bytes)
+    } else {
+        Certificate::from_der(&bytes).map(|cert| vec![cert])
+    };
+    parsed.map_err(|e| format!("Invalid certificate {}: {}", path, e))
+}
+
+// Adds the proxy and extra CAs to a client. `password` is the stored one
+// unless the caller is checking new settings before saving them.
+pub fn apply(mut builder: ClientBuilder, settings: &ProxySettings, password: Option<&str>) -> Result<ClientBuilder, String> {
+    if let Some(url) = proxy_url(settings)? {
+        let mut proxy = Proxy::all(url.as_str()).map_err(|e| format!("Invalid proxy: {}", e))?;
+        if let Some(username) = settings.username.as_deref().filter(|u| !u.is_empty()) {
+            proxy = proxy.basic_auth(username, password.unwrap_or(""));
+        }
+        proxy = proxy.no_proxy(settings.no_proxy.as_deref().and_then(NoProxy::from_string));
+        builder = builder.proxy(proxy);
+    }
+    for path in &settings.ca_certificates {
+        for certificate in certificates(path)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    Ok(builder)
+}
+
+#[tauri::command]
+pub async fn get_proxy_settings(db: State<'_, Db>) -> Result<ProxyStatus, String> {
+    Ok(ProxyStatus { settings: load(&db.lock())?, has_password: stored_password(&db.path()).is_some() })
+}
+
+// Saves proxy and certificate settings and rebuilds the client every
+// request goes through. `password` replaces the stored one when given; an
+// empty one removes it. Nothing is saved if the settings don't build.
+#[tauri::command]
+pub async fn set_proxy_settings(
+    db: State<'_, Db>,
+    http: State<'_, Http>,
+    proxy: ProxySettings,
+    password: Option<String>,
+) -> Result<ProxyStatus, String> {
+    let database = db.path();
+    let password = match password {
+        Some(p) if p.is_empty() => None,
+        Some(p) => Some(p),
+        None => stored_password(&database),
+    };
+    apply(reqwest::Client::builder(), &proxy, password.as_deref())?
+        .build()
+        .map_err(|e| format!("Failed to set up the network client: {}", e))?;
+
+    let value = serde_json::to_value(&proxy).map_err(|e| e.to_string())?;
+    settings::set(&db.lock(), SETTINGS_KEY, &value)?;
+    if password != stored_password(&database) {
+        store_password(&database, password.as_deref())?;
+    }
+    http.configure()?;
+    Ok(ProxyStatus { settings: proxy, has_password: password.is_some() })
+}
@@ -0,0 +1,185 @@
+use crate::analysis;
+use crate::db::{self, Db};
+use crate::jobs::{self, Scheduler};
+use crate::paths;
+use rusqlite::{params, Connection, OptionalExtension};
+use tauri::State;
+
+// A file that crashes a worker this many times is skipped from then on
+const CRASH_LIMIT: i64 = 2;
+// And one that fails to decode or parse this many times
+const FAILURE_LIMIT: i64 = 3;
+
+pub fn is_quarantined(conn: &Connection, path: &str) -> Result<bool, String> {
+    let quarantined: Option<Option<i64>> = conn
+        .query_row(
+            "SELECT quarantined_at FROM quarantine WHERE path = ?1",
+            params![path],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    Ok(matches!(quarantined, Some(Some(_))))
+}
+
+#[derive(serde::Serialize)]
+pub struct QuarantinedFile {
+    path: String,
+    // "crash" when a worker panicked on the file, "error" when it failed to decode or parse
+    kind: String,
+    reason: String,
+    crashes: i64,
+    failures: i64,
+    quarantined_at: i64,
+}
+
+fn record_crash(conn: &Connection, path: &str, reason: &str) -> Result<(), String> {
+    let now = db::now();
+    conn.execute(
+        "INSERT INTO quarantine (path, kind, reason, crashes, updated_at) VALUES (?1, 'crash', ?2, 1, ?3)
+         ON CONFLICT(path) DO UPDATE SET kind = 'crash', reason = excluded.reason, crashes = crashes + 1,
+             updated_at = excluded.updated_at",
+        params![path, reason, now],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE quarantine SET quarantined_at = ?2 WHERE path = ?1 AND crashes >= ?3 AND quarantined_at IS NULL",
+        params![path, now, CRASH_LIMIT],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Decoding a broken file fails the same way every time, so after a few
+// tries it's quarantined instead of being retried on every batch
+fn record_failure(conn: &Connection, path: &str, reason: &str) -> Result<(), String> {
+    let now = db::now();
+    conn.execute(
+        "INSERT INTO quarantine (path, kind, reason, failures, updated_at) VALUES (?1, 'error', ?2, 1, ?3)
+         ON CONFLICT(path) DO UPDATE SET reason = excluded.reason, failures = failures + 1,
+             updated_at = excluded.updated_at",
+        params![path, reason, now],
+    )
+    .map_err(|e| e.to_string())?;
+    conn.execute(
+        "UPDATE quarantine SET quarantined_at = ?2 WHERE path = ?1 AND failures >= ?3 AND quarantined_at IS NULL",
+        params![path, now, FAILURE_LIMIT],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Why per-file work failed. Only `Content` says something about the file
+// itself; the others may well succeed next time.
+pub enum Failure {
+    // The file was read but couldn't be decoded or parsed
+    Content(String),
+    // Reading failed for a reason outside the file: a drive unplugged, a
+    // permission, an I/O error on the way
+    Io(String),
+}
+
+impl From<String> for Failure {
+    fn from(e: String) -> Self {
+        Failure::Content(e)
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Self {
+        Failure::Io(e.to_string())
+    }
+}
+
+impl From<Failure> for String {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::Content(e) | Failure::Io(e) => e,
+        }
+    }
+}
+
+// Clears failures once the file goes through, so occasional errors don't
+// add up to a quarantine over time
+fn record_success(conn: &Connection, path: &str) -> Result<(), String> {
+    conn.execute(
+        "UPDATE quarantine SET failures = 0 WHERE path = ?1 AND failures > 0 AND quarantined_at IS NULL",
+        params![path],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Runs per-file work with panics contained. Crashes and decode or parse
+// errors are recorded against the file and reported as an error for that
+// file only, so the batch moves on; quarantined files are refused up front.
+pub fn guard<T, E: Into<Failure>>(db: &Db, path: &str, work: impl FnOnce() -> Result<T, E>) -> Result<T, String> {
+    if is_quarantined(&db.lock(), path)? {
+        return Err(format!("Skipped {}: quarantined", path));
+    }
+    match jobs::isolate(work) {
+        Ok(Ok(value)) => {
+            record_success(&db.lock(), path)?;
+            Ok(value)
+        }
+        Ok(Err(e)) => {
+            let failure = e.into();
+            // A file that can't even be opened now is a volume or permission
+            // problem, whatever the decoder made of it
+            let readable = std::fs::File::open(paths::to_fs(path)).is_ok();
+            if let (Failure::Content(reason), true) = (&failure, readable) {
+                record_failure(&db.lock(), path, reason)?;
+            }
+            Err(failure.into())
+        }
+        Err(panic) => {
+            record_crash(&db.lock(), path, &panic)?;
+            Err(format!("Crashed while processing {}: {}", path, panic))
+        }
+    }
+}
+
+pub fn quarantined_count(conn: &Connection) -> Result<i64, String> {
+    conn.query_row("SELECT COUNT(*) FROM quarantine WHERE quarantined_at IS NOT NULL", [], |row| row.get(0))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn list_quarantined(db: State<'_, Db>) -> Result<Vec<QuarantinedFile>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare(
+            "SELECT path, kind, reason, crashes, failures, quarantined_at FROM quarantine
+             WHERE quarantined_at IS NOT NULL ORDER BY quarantined_at DESC, path",
+        )
+        .map_err(|e| e.to_string())?;
+    let files = stmt
+        .query_map([], |row| {
+            Ok(QuarantinedFile {
+                path: row.get(0)?,
+                kind: row.get(1)?,
+                reason: row.get(2)?,
+                crashes: row.get(3)?,
+                failures: row.get(4)?,
+                quarantined_at: row.get(5)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(files)
+}
+
+// Releases files (say, after re-downloading them) and queues them for
+// analysis again. Returns the analysis job id.
+#[tauri::command]
+pub async fn retry_quarantined(db: State<'_, Db>, scheduler: State<'_, Scheduler>, paths: Vec<String>) -> Result<u64, String> {
+    {
+        let conn = db.lock();
+        for path in &paths {
+            conn.execute("DELETE FROM quarantine WHERE path = ?1", params![path])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(analysis::submit_analysis(&db, &scheduler, paths))
+}
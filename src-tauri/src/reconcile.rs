@@ -0,0 +1,330 @@
+use crate::analysis::{self, NOTE_NAMES};
+use crate::db::{self, Db};
+use crate::jobs::{Priority, Scheduler};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use tauri::State;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct ParsedValue {
+    value: String,
+    confidence: f64,
+}
+
+#[derive(Default, serde::Serialize)]
+pub struct FilenameMetadata {
+    bpm: Option<ParsedValue>,
+    key: Option<ParsedValue>,
+}
+
+#[derive(serde::Serialize)]
+pub struct AttributeSource {
+    source: String,
+    value: String,
+    confidence: f64,
+}
+
+#[derive(serde::Serialize)]
+pub struct AttributeConflict {
+    path: String,
+    attribute: String,
+    resolved: String,
+    sources: Vec<AttributeSource>,
+}
+
+pub fn parse_note(token: &str) -> Option<(usize, &str)> {
+    let mut chars = token.chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let base = match letter {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = &token[1..];
+    let lower = rest.to_lowercase();
+    for (prefix, shift) in [("sharp", 1), ("flat", 11), ("#", 1), ("b", 11)] {
+        if lower.starts_with(prefix) {
+            // "Bb" is B flat, but "Bbm"/"bmin" need the quality parsed after it
+            return Some(((base + shift) % 12, &rest[prefix.len()..]));
+        }
+    }
+    Some((base, rest))
+}
+
+fn parse_quality(text: &str) -> Option<&'static str> {
+    match text.to_lowercase().as_str() {
+        "m" | "min" | "minor" | "mi" => Some("m"),
+        "maj" | "major" | "ma" => Some(""),
+        _ => None,
+    }
+}
+
+// Reads "120bpm_Amin", "Bass 90 BPM F minor", "Loop_C#m_128" and friends.
+// Explicit markers ("bpm", "min") score higher than bare numbers/letters.
+pub fn parse_filename(name: &str) -> FilenameMetadata {
+    let stem = Path::new(name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| name.to_string());
+    let tokens: Vec<&str> = stem
+        .split(|c: char| !(c.is_alphanumeric() || c == '#'))
+        .filter(|t| !t.is_empty())
+        .collect();
+
+    let mut result = FilenameMetadata::default();
+    let is_tempo = |n: u32| (50..=220).contains(&n);
+
+    for (i, token) in tokens.iter().enumerate() {
+        let lower = token.to_lowercase();
+
+        // BPM: "120bpm", "bpm120", "120 bpm"
+        let explicit = lower
+            .strip_suffix("bpm")
+            .or_else(|| lower.strip_prefix("bpm"))
+            .and_then(|n| n.parse::<u32>().ok())
+            .or_else(|| {
+                let next_is_bpm = tokens.get(i + 1).map(|t| t.eq_ignore_ascii_case("bpm")).unwrap_or(false);
+                if next_is_bpm {
+                    lower.parse::<u32>().ok()
+                } else {
+                    None
+                }
+            });
+        if let Some(bpm) = explicit.filter(|n| is_tempo(*n)) {
+            result.bpm = Some(ParsedValue { value: bpm.to_string(), confidence: 0.95 });
+        } else if result.bpm.is_none() && (2..=3).contains(&lower.len()) {
+            if let Some(bpm) = lower.parse::<u32>().ok().filter(|n| is_tempo(*n) && *n >= 60) {
+                result.bpm = Some(ParsedValue { value: bpm.to_string(), confidence: 0.5 });
+            }
+        }
+
+        // Key: "Amin", "C#m", "Ebmaj", "F" + "minor"
+        if result.key.as_ref().map(|k| k.confidence >= 0.9).unwrap_or(false) || token.len() > 7 {
+            continue;
+        }
+        if let Some((pitch, rest)) = parse_note(token) {
+            let next_quality = tokens.get(i + 1).and_then(|t| parse_quality(t));
+            let (quality, confidence) = if rest.is_empty() {
+                match next_quality {
+                    Some(q) => (q, 0.9),
+                    // A lone capital letter is weak evidence of a major key
+                    None if token.len() == 1 && token.chars().all(|c| c.is_ascii_uppercase()) => ("", 0.4),
+                    None => continue,
+                }
+            } else {
+                match parse_quality(rest) {
+                    Some(q) if rest.len() > 1 || token.chars().next().map(|c| c.is_ascii_uppercase()).unwrap_or(false) => (q, 0.9),
+                    // Lowercase "am"/"em" could just be a word
+                    Some(q) => (q, 0.6),
+                    None => continue,
+                }
+            };
+            let candidate = ParsedValue { value: format!("{}{}", NOTE_NAMES[pitch], quality), confidence };
+            if result.key.as_ref().map(|k| k.confidence < confidence).unwrap_or(true) {
+                result.key = Some(candidate);
+            }
+        }
+    }
+
+    result
+}
+
+fn bpm_agrees(a: f64, b: f64) -> bool {
+    // Half/double time is the same groove as far as browsing goes
+    [1.0, 2.0, 0.5].iter().any(|ratio| ((a * ratio) - b).abs() / b <= 0.02)
+}
+
+fn store_value(conn: &Connection, path: &str, attribute: &str, source: &str, value: &str, confidence: f64) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR REPLACE INTO attribute_values (path, attribute, source, value, confidence, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![path, attribute, source, value, confidence, db::now()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn user_decided(conn: &Connection, path: &str, attribute: &str) -> Result<bool, String> {
+    let count: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM attribute_values WHERE path = ?1 AND attribute = ?2 AND source = 'user'",
+            params![path, attribute],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(count > 0)
+}
+
+fn values_agree(attribute: &str, a: &str, b: &str) -> bool {
+    match attribute {
+        "bpm" => match (a.parse::<f64>(), b.parse::<f64>()) {
+            (Ok(a), Ok(b)) => bpm_agrees(a, b),
+            _ => false,
+        },
+        _ => a == b,
+    }
+}
+
+// "Am", "F#min", "Dbmaj" as other apps write them, in our spelling
+pub fn normalize_key(text: &str) -> Option<String> {
+    let (pitch, rest) = parse_note(text.trim())?;
+    let quality = if rest.is_empty() { "" } else { parse_quality(rest)? };
+    Some(format!("{}{}", NOTE_NAMES[pitch], quality))
+}
+
+// Pitch class and minor-ness of a key in any spelling normalize_key accepts
+pub fn parse_key(text: &str) -> Option<(usize, bool)> {
+    let key = normalize_key(text)?;
+    let (note, minor) = match key.strip_suffix('m') {
+        Some(note) => (note, true),
+        None => (key.as_str(), false),
+    };
+    NOTE_NAMES.iter().position(|n| *n == note).map(|pitch| (pitch, minor))
+}
+
+// A value from another app's collection. DJs fix BPM and key by hand in
+// those apps, so an import outranks filename and analysis guesses; it is
+// flagged when it overturns a different value already resolved.
+pub fn import_value(conn: &Connection, path: &str, attribute: &str, source: &str, value: &str) -> Result<(), String> {
+    store_value(conn, path, attribute, source, value, 0.98)?;
+    if user_decided(conn, path, attribute)? {
+        return Ok(());
+    }
+    let current: Option<String> = conn
+        .query_row(
+            "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = ?2",
+            params![path, attribute],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let conflict = current.map(|c| !values_agree(attribute, &c, value)).unwrap_or(false);
+    store_resolution(conn, path, attribute, value, source, conflict)
+}
+
+fn store_resolution(conn: &Connection, path: &str, attribute: &str, value: &str, source: &str, conflict: bool) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR REPLACE INTO attribute_resolution (path, attribute, value, source, conflict) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![path, attribute, value, source, conflict],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Filename and analysis both stored; the resolved value prefers a confident
+// filename, and disagreements are flagged unless the user already decided.
+fn reconcile_attribute(
+    conn: &Connection,
+    path: &str,
+    attribute: &str,
+    from_name: Option<ParsedValue>,
+    analyzed: Option<(String, f64)>,
+) -> Result<(), String> {
+    if let Some(parsed) = &from_name {
+        store_value(conn, path, attribute, "filename", &parsed.value, parsed.confidence)?;
+    }
+    if let Some((value, confidence)) = &analyzed {
+        store_value(conn, path, attribute, "analysis", value, *confidence)?;
+    }
+
+    if user_decided(conn, path, attribute)? {
+        return Ok(());
+    }
+
+    match (from_name, analyzed) {
+        (Some(parsed), Some((value, confidence))) => {
+            let agrees = values_agree(attribute, &parsed.value, &value);
+            let (resolved, source) = if agrees || parsed.confidence >= confidence {
+                (parsed.value, "filename")
+            } else {
+                (value, "analysis")
+            };
+            store_resolution(conn, path, attribute, &resolved, source, !agrees)
+        }
+        (Some(parsed), None) => store_resolution(conn, path, attribute, &parsed.value, "filename", false),
+        (None, Some((value, _))) => store_resolution(conn, path, attribute, &value, "analysis", false),
+        (None, None) => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub async fn parse_filename_metadata(name: String) -> Result<FilenameMetadata, String> {
+    Ok(parse_filename(&name))
+}
+
+#[tauri::command]
+pub async fn reconcile_metadata(db: State<'_, Db>, scheduler: State<'_, Scheduler>, paths: Vec<String>) -> Result<u64, String> {
+    let db = db.inner().clone();
+    let label = format!("Reconcile BPM/key for {} files", paths.len());
+
+    Ok(scheduler.submit("analysis", label, Priority::Background, move |ctx| {
+        let total = paths.len().max(1) as f32;
+        for (i, path) in paths.iter().enumerate() {
+            if ctx.is_cancelled() {
+                break;
+            }
+            ctx.progress(i as f32 / total, Some(path.clone()));
+
+            let parsed = parse_filename(path);
+            let analyzed = analysis::get_or_analyze(&db, path).ok();
+
+            let conn = db.lock();
+            reconcile_attribute(
+                &conn,
+                path,
+                "bpm",
+                parsed.bpm,
+                analyzed.as_ref().and_then(|a| a.bpm.map(|b| (format!("{}", b.round()), 0.7))),
+            )?;
+            reconcile_attribute(
+                &conn,
+                path,
+                "key",
+                parsed.key,
+                analyzed.as_ref().and_then(|a| a.key.clone().map(|k| (k, a.key_confidence))),
+            )?;
+        }
+        Ok(())
+    }))
+}
+
+#[tauri::command]
+pub async fn list_metadata_conflicts(db: State<'_, Db>) -> Result<Vec<AttributeConflict>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare("SELECT path, attribute, value FROM attribute_resolution WHERE conflict = 1 ORDER BY path")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut sources_stmt = conn
+        .prepare("SELECT source, value, confidence FROM attribute_values WHERE path = ?1 AND attribute = ?2")
+        .map_err(|e| e.to_string())?;
+    let mut conflicts = Vec::new();
+    for (path, attribute, resolved) in rows {
+        let sources = sources_stmt
+            .query_map(params![path, attribute], |row| {
+                Ok(AttributeSource { source: row.get(0)?, value: row.get(1)?, confidence: row.get(2)? })
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        conflicts.push(AttributeConflict { path, attribute, resolved, sources });
+    }
+    Ok(conflicts)
+}
+
+#[tauri::command]
+pub async fn resolve_metadata_conflict(db: State<'_, Db>, path: String, attribute: String, value: String) -> Result<(), String> {
+    let conn = db.lock();
+    store_value(&conn, &path, &attribute, "user", &value, 1.0)?;
+    store_resolution(&conn, &path, &attribute, &value, "user", false)
+}
@@ -0,0 +1,300 @@
+use crate::ai::{self, ChatMessage, ModelParams};
+use crate::db::Db;
+use crate::settings;
+use rusqlite::Connection;
+use tauri::State;
+
+const SETTINGS_KEY: &str = "privacy.redaction";
+// Shorter names would blank out ordinary words
+const MIN_NAME_LEN: usize = 3;
+
+// What's stripped from text before it goes to a cloud provider; all on by
+// default
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RedactionSettings {
+    enabled: bool,
+    // Absolute paths, cut down to the file name
+    paths: bool,
+    // The account name on this machine
+    user_names: bool,
+    emails: bool,
+    // Anything else to keep private: collaborators, label, project names
+    terms: Vec<String>,
+}
+
+impl Default for RedactionSettings {
+    fn default() -> Self {
+        RedactionSettings { enabled: true, paths: true, user_names: true, emails: true, terms: Vec::new() }
+    }
+}
+
+#[derive(Clone, PartialEq, serde::Serialize)]
+pub struct Redaction {
+    // "path", "user", "email" or "term"
+    kind: &'static str,
+    original: String,
+    replacement: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct RedactionPreview {
+    // Exactly what the provider would receive
+    messages: Vec<ChatMessage>,
+    redactions: Vec<Redaction>,
+}
+
+pub struct Redactor {
+    settings: RedactionSettings,
+    // Longest first, so a root inside the home folder is matched as the root
+    prefixes: Vec<(String, &'static str)>,
+    names: Vec<(String, &'static str)>,
+}
+
+fn home_dir() -> Option<String> {
+    std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")).ok().filter(|home| home.len() > 1)
+}
+
+fn user_names() -> Vec<String> {
+    let mut names: Vec<String> = ["USER", "USERNAME", "LOGNAME"].iter().filter_map(|var| std::env::var(var).ok()).collect();
+    // The home folder is often named differently from the login
+    if let Some(home) = home_dir() {
+        names.extend(home.rsplit(['/', '\\']).next().map(str::to_string));
+    }
+    names
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Replaces whole-word, case-insensitive occurrences of `needle`. ASCII
+// lowercasing keeps byte offsets lined up with the original.
+fn replace_word(text: &str, needle: &str, with: &str) -> (String, bool) {
+    let lower = text.to_ascii_lowercase();
+    let needle = needle.to_ascii_lowercase();
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, _) in lower.match_indices(&needle) {
+        let end = start + needle.len();
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+        if start < last || before.is_some_and(is_word_char) || after.is_some_and(is_word_char) {
+            continue;
+        }
+        out.push_str(&text[last..start]);
+        out.push_str(with);
+        last = end;
+    }
+    out.push_str(&text[last..]);
+    (out, last > 0)
+}
+
+// How a path is compared: either separator, and case-insensitive where the
+// filesystem is. Byte offsets stay lined up with the original.
+fn path_form(text: &str) -> String {
+    let text = text.replace('\\', "/");
+    if cfg!(any(windows, target_os = "macos")) {
+        text.to_ascii_lowercase()
+    } else {
+        text
+    }
+}
+
+// Same as replace_word but for a path prefix, which only has to end at a
+// separator or the end of the path
+fn replace_prefix(text: &str, prefix: &str, with: &str) -> (String, bool) {
+    let (compared, prefix) = (path_form(text), path_form(prefix));
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, _) in compared.match_indices(prefix.as_str()) {
+        let end = start + prefix.len();
+        let after = text[end..].chars().next();
+        if start < last || after.is_some_and(|c| c != '/' && c != '\\' && !c.is_whitespace() && c != '"' && c != '\'') {
+            continue;
+        }
+        out.push_str(&text[last..start]);
+        out.push_str(with);
+        last = end;
+    }
+    out.push_str(&text[last..]);
+    (out, last > 0)
+}
+
+fn is_absolute_path(token: &str) -> bool {
+    let bytes = token.as_bytes();
+    let unix = token.starts_with('/') && token.matches('/').count() >= 2;
+    let drive = bytes.len() > 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && (bytes[2] == b'\\' || bytes[2] == b'/');
+    let unc = token.starts_with("\\\\");
+    unix || drive || unc
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '"' | '\'' | '`' | '(' | ')' | '<' | '>' | ',' | ';')
+}
+
+// Any other absolute path, cut to its last component. Paths with spaces
+// lose the part up to the first space, which is the part that names the
+// machine's folders.
+fn redact_paths(text: &str, found: &mut Vec<Redaction>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut token_start = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        if !is_delimiter(c) {
+            token_start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = token_start.take() {
+            let token = &text[start..i];
+            // Trailing sentence punctuation isn't part of the path
+            let path = token.trim_end_matches(['.', ':', '!', '?', ']', '}']);
+            if is_absolute_path(path) {
+                let name = path.trim_end_matches(['/', '\\']).rsplit(['/', '\\']).next().unwrap_or("");
+                let replacement = format!("[path]/{}", name);
+                out.push_str(&replacement);
+                out.push_str(&token[path.len()..]);
+                found.push(Redaction { kind: "path", original: path.to_string(), replacement });
+            } else {
+                out.push_str(token);
+            }
+        }
+        if i < text.len() {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn is_local_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-')
+}
+
+fn is_domain_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-')
+}
+
+fn redact_emails(text: &str, found: &mut Vec<Redaction>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for (at, _) in text.match_indices('@') {
+        if at < last {
+            continue;
+        }
+        let start = text[..at].char_indices().rev().take_while(|(_, c)| is_local_char(*c)).last().map(|(i, _)| i).unwrap_or(at);
+        let domain_len: usize = text[at + 1..].chars().take_while(|c| is_domain_char(*c)).map(char::len_utf8).sum();
+        let domain = text[at + 1..at + 1 + domain_len].trim_end_matches(['.', '-']);
+        let dotted = domain.contains('.') && !domain.starts_with('.');
+        if start == at || start < last || !dotted {
+            continue;
+        }
+        let end = at + 1 + domain.len();
+        out.push_str(&text[last..start]);
+        out.push_str("[email]");
+        found.push(Redaction { kind: "email", original: text[start..end].to_string(), replacement: "[email]".to_string() });
+        last = end;
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+impl Redactor {
+    pub fn load(conn: &Connection) -> Result<Self, String> {
+        let settings: RedactionSettings = settings::get(conn, SETTINGS_KEY)?.unwrap_or_default();
+
+        let mut prefixes = Vec::new();
+        if settings.paths {
+            let mut stmt = conn.prepare("SELECT path FROM library_roots").map_err(|e| e.to_string())?;
+            let roots = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(|e| e.to_string())?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.to_string())?;
+            prefixes.extend(roots.into_iter().map(|root| (root, "[library]")));
+            prefixes.extend(home_dir().map(|home| (home, "~")));
+            prefixes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        }
+
+        let mut names = Vec::new();
+        if settings.user_names {
+            names.extend(user_names().into_iter().map(|name| (name, "user")));
+        }
+        names.extend(settings.terms.iter().map(|term| (term.trim().to_string(), "term")));
+        names.retain(|(name, _)| name.chars().count() >= MIN_NAME_LEN);
+        names.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        names.dedup_by(|a, b| a.0.eq_ignore_ascii_case(&b.0));
+
+        Ok(Redactor { settings, prefixes, names })
+    }
+
+    // Paths go first since they usually hold the user name, then emails,
+    // whose local part often does too
+    pub fn redact(&self, text: &str, found: &mut Vec<Redaction>) -> String {
+        if !self.settings.enabled {
+            return text.to_string();
+        }
+        let mut text = text.to_string();
+        if self.settings.paths {
+            for (prefix, with) in &self.prefixes {
+                let (replaced, hit) = replace_prefix(&text, prefix, with);
+                if hit {
+                    found.push(Redaction { kind: "path", original: prefix.clone(), replacement: with.to_string() });
+                }
+                text = replaced;
+            }
+            text = redact_paths(&text, found);
+        }
+        if self.settings.emails {
+            text = redact_emails(&text, found);
+        }
+        for (name, kind) in &self.names {
+            let replacement = if *kind == "user" { "[user]" } else { "[redacted]" };
+            let (replaced, hit) = replace_word(&text, name, replacement);
+            if hit {
+                found.push(Redaction { kind: *kind, original: name.clone(), replacement: replacement.to_string() });
+            }
+            text = replaced;
+        }
+        text
+    }
+
+    pub fn redact_messages(&self, messages: &[ChatMessage], found: &mut Vec<Redaction>) -> Vec<ChatMessage> {
+        messages
+            .iter()
+            .map(|m| ChatMessage { role: m.role.clone(), content: self.redact(&m.content, found) })
+            .collect()
+    }
+}
+
+#[tauri::command]
+pub async fn get_redaction_settings(db: State<'_, Db>) -> Result<RedactionSettings, String> {
+    Ok(settings::get(&db.lock(), SETTINGS_KEY)?.unwrap_or_default())
+}
+
+#[tauri::command]
+pub async fn set_redaction_settings(db: State<'_, Db>, redaction: RedactionSettings) -> Result<(), String> {
+    let value = serde_json::to_value(&redaction).map_err(|e| e.to_string())?;
+    settings::set(&db.lock(), SETTINGS_KEY, &value)
+}
+
+// Shows what ai_chat would send for these messages: the conversation's
+// system prompt filled in and everything redacted, with a list of what was
+// replaced
+#[tauri::command]
+pub async fn preview_ai_request(
+    db: State<'_, Db>,
+    messages: Vec<ChatMessage>,
+    params: Option<ModelParams>,
+    conversation_id: Option<String>,
+) -> Result<RedactionPreview, String> {
+    let conn = db.lock();
+    let (_, messages) = ai::prepare_messages(&conn, conversation_id.as_deref(), &params.unwrap_or_default(), &messages)?;
+    let mut redactions = Vec::new();
+    let messages = Redactor::load(&conn)?.redact_messages(&messages, &mut redactions);
+    let mut unique = Vec::new();
+    for redaction in redactions {
+        if !unique.contains(&redaction) {
+            unique.push(redaction);
+        }
+    }
+    Ok(RedactionPreview { messages, redactions: unique })
+}
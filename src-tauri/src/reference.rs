@@ -0,0 +1,276 @@
+use crate::audio;
+use crate::db::Db;
+use crate::dsp::{self, Biquad};
+use crate::jobs::{Priority, Scheduler};
+use crate::paths;
+use crate::quarantine;
+use tauri::State;
+
+const N_FFT: usize = 4096;
+const HOP: usize = 2048;
+// BS.1770 gating blocks are 400 ms with 75% overlap, built from 100 ms steps
+const STEP_SECONDS: f64 = 0.1;
+const STEPS_PER_BLOCK: usize = 4;
+// (name, low Hz, high Hz)
+const BANDS: [(&str, f32, f32); 6] = [
+    ("sub", 20.0, 60.0),
+    ("low", 60.0, 250.0),
+    ("low_mid", 250.0, 500.0),
+    ("mid", 500.0, 2000.0),
+    ("high_mid", 2000.0, 6000.0),
+    ("high", 6000.0, 20000.0),
+];
+// Differences smaller than these aren't worth mentioning in the summary
+const NOTABLE_LU: f32 = 1.0;
+const NOTABLE_BAND_DB: f32 = 2.0;
+const NOTABLE_CREST_DB: f32 = 1.5;
+const NOTABLE_WIDTH: f32 = 0.05;
+
+#[derive(serde::Serialize)]
+pub struct BandLevel {
+    name: &'static str,
+    low_hz: f32,
+    high_hz: f32,
+    // Share of the spectrum's energy, in dB; independent of overall level
+    db: f32,
+}
+
+#[derive(serde::Serialize)]
+pub struct MixProfile {
+    path: String,
+    duration: f64,
+    // Integrated loudness, BS.1770 (K-weighted, gated)
+    lufs: f32,
+    peak_db: f32,
+    rms_db: f32,
+    // Peak over RMS; low means heavily limited
+    crest_db: f32,
+    // Left/right correlation, 1 = mono, below 0 = phase trouble
+    correlation: f32,
+    // Side energy over mid plus side: 0 mono, 0.5 wide, 1 out of phase
+    width: f32,
+    bands: Vec<BandLevel>,
+}
+
+#[derive(serde::Serialize)]
+pub struct BandDifference {
+    name: &'static str,
+    mix_db: f32,
+    reference_db: f32,
+    // Mix minus reference; positive means the mix has more there
+    difference_db: f32,
+}
+
+#[derive(serde::Serialize)]
+pub struct ReferenceComparison {
+    mix: MixProfile,
+    reference: MixProfile,
+    // All mix minus reference
+    loudness_lu: f32,
+    crest_db: f32,
+    correlation: f32,
+    width: f32,
+    bands: Vec<BandDifference>,
+    // Plain-language findings for the mix feedback prompt
+    summary: String,
+}
+
+fn db(power: f64) -> f32 {
+    (10.0 * power.max(1e-12).log10()) as f32
+}
+
+// BS.1770 integrated loudness from 100 ms mean-square steps (already
+// summed over channels): absolute gate at -70 LUFS, then relative 10 LU
+// below the loudness of what passed
+fn integrated_lufs(steps: &[f64]) -> f32 {
+    let lufs = |power: f64| -0.691 + db(power);
+    let blocks: Vec<f64> = if steps.len() < STEPS_PER_BLOCK {
+        vec![steps.iter().sum::<f64>() / steps.len().max(1) as f64]
+    } else {
+        steps.windows(STEPS_PER_BLOCK).map(|w| w.iter().sum::<f64>() / STEPS_PER_BLOCK as f64).collect()
+    };
+    let audible: Vec<f64> = blocks.into_iter().filter(|p| lufs(*p) > -70.0).collect();
+    if audible.is_empty() {
+        return -70.0;
+    }
+    let relative = lufs(audible.iter().sum::<f64>() / audible.len() as f64) - 10.0;
+    let gated: Vec<f64> = audible.iter().copied().filter(|p| lufs(*p) > relative).collect();
+    lufs(gated.iter().sum::<f64>() / gated.len().max(1) as f64)
+}
+
+// Everything the comparison needs, gathered in one streaming pass
+pub fn profile(path: &str) -> Result<MixProfile, String> {
+    let mut filters: Vec<[Biquad; 2]> = Vec::new();
+    let mut rate = 0u32;
+    let mut stft = dsp::Stft::new(N_FFT, HOP);
+    let mut band_power = [0.0f64; BANDS.len()];
+    let mut mono = Vec::new();
+    let (mut frames, mut samples, mut peak, mut sum_sq) = (0u64, 0u64, 0.0f32, 0.0f64);
+    let (mut ll, mut rr, mut lr, mut mid, mut side) = (0.0f64, 0.0f64, 0.0f64, 0.0f64, 0.0f64);
+    let (mut steps, mut step_sum, mut step_frames) = (Vec::new(), 0.0f64, 0usize);
+
+    audio::stream_file(&paths::to_fs(path), |block, block_rate, channels| {
+        let channels = channels.max(1);
+        if filters.len() != channels || rate != block_rate {
+            rate = block_rate;
+            filters = vec![dsp::k_weighting(rate); channels];
+        }
+        let step_len = (rate as f64 * STEP_SECONDS) as usize;
+        mono.clear();
+        for frame in block.chunks(channels) {
+            for (sample, stages) in frame.iter().zip(filters.iter_mut()) {
+                peak = peak.max(sample.abs());
+                sum_sq += (*sample as f64).powi(2);
+                let weighted = stages.iter_mut().fold(*sample, |x, stage| stage.process(x));
+                step_sum += (weighted as f64).powi(2);
+            }
+            let (l, r) = (frame[0] as f64, frame[frame.len().min(2) - 1] as f64);
+            ll += l * l;
+            rr += r * r;
+            lr += l * r;
+            mid += ((l + r) / 2.0).powi(2);
+            side += ((l - r) / 2.0).powi(2);
+            mono.push(frame.iter().sum::<f32>() / channels as f32);
+            frames += 1;
+            samples += frame.len() as u64;
+            step_frames += 1;
+            if step_frames >= step_len {
+                steps.push(step_sum / step_frames as f64);
+                step_sum = 0.0;
+                step_frames = 0;
+            }
+        }
+        stft.push(&mono, |spectrum| {
+            for (k, magnitude) in spectrum.iter().enumerate() {
+                let hz = k as f32 * rate as f32 / N_FFT as f32;
+                if let Some(band) = BANDS.iter().position(|(_, low, high)| hz >= *low && hz < *high) {
+                    band_power[band] += (*magnitude as f64).powi(2);
+                }
+            }
+        });
+        true
+    })?;
+    if frames == 0 {
+        return Err("No audio".to_string());
+    }
+    if step_frames > 0 {
+        steps.push(step_sum / step_frames as f64);
+    }
+
+    let total: f64 = band_power.iter().sum();
+    let rms_db = db(sum_sq / samples as f64);
+    let peak_db = 20.0 * peak.max(1e-6).log10();
+    Ok(MixProfile {
+        path: path.to_string(),
+        duration: frames as f64 / rate.max(1) as f64,
+        lufs: integrated_lufs(&steps),
+        peak_db,
+        rms_db,
+        crest_db: peak_db - rms_db,
+        correlation: if ll > 0.0 && rr > 0.0 { (lr / (ll * rr).sqrt()) as f32 } else { 1.0 },
+        width: if mid + side > 0.0 { (side / (mid + side)) as f32 } else { 0.0 },
+        bands: BANDS
+            .iter()
+            .zip(band_power)
+            .map(|((name, low_hz, high_hz), power)| BandLevel {
+                name,
+                low_hz: *low_hz,
+                high_hz: *high_hz,
+                db: db(power / total.max(1e-12)),
+            })
+            .collect(),
+    })
+}
+
+fn band_label(name: &str) -> &str {
+    match name {
+        "sub" => "sub bass",
+        "low" => "low end",
+        "low_mid" => "low mids",
+        "mid" => "mids",
+        "high_mid" => "upper mids",
+        _ => "top end",
+    }
+}
+
+fn summarize(comparison: &ReferenceComparison) -> String {
+    let mut findings = Vec::new();
+    if comparison.loudness_lu.abs() >= NOTABLE_LU {
+        let direction = if comparison.loudness_lu > 0.0 { "louder" } else { "quieter" };
+        findings.push(format!(
+            "The mix is {:.1} LU {} than the reference ({:.1} vs {:.1} LUFS).",
+            comparison.loudness_lu.abs(),
+            direction,
+            comparison.mix.lufs,
+            comparison.reference.lufs
+        ));
+    }
+    if comparison.crest_db.abs() >= NOTABLE_CREST_DB {
+        let character = if comparison.crest_db > 0.0 { "more dynamic (less limited)" } else { "more compressed" };
+        findings.push(format!("It is {} by {:.1} dB of crest factor.", character, comparison.crest_db.abs()));
+    }
+    for band in &comparison.bands {
+        if band.difference_db.abs() >= NOTABLE_BAND_DB {
+            let direction = if band.difference_db > 0.0 { "more" } else { "less" };
+            findings.push(format!("The mix has {:.1} dB {} {} than the reference.", band.difference_db.abs(), direction, band_label(band.name)));
+        }
+    }
+    if comparison.width.abs() >= NOTABLE_WIDTH {
+        let direction = if comparison.width > 0.0 { "wider" } else { "narrower" };
+        findings.push(format!("The stereo image is {}.", direction));
+    }
+    if comparison.mix.correlation < 0.0 {
+        findings.push("Left and right are partly out of phase; check the mix in mono.".to_string());
+    }
+    if findings.is_empty() {
+        return "The mix is close to the reference in loudness, dynamics, tonal balance and width.".to_string();
+    }
+    findings.join(" ")
+}
+
+pub fn compare(mix: MixProfile, reference: MixProfile) -> ReferenceComparison {
+    let bands = mix
+        .bands
+        .iter()
+        .zip(&reference.bands)
+        .map(|(m, r)| BandDifference { name: m.name, mix_db: m.db, reference_db: r.db, difference_db: m.db - r.db })
+        .collect();
+    let mut comparison = ReferenceComparison {
+        loudness_lu: mix.lufs - reference.lufs,
+        crest_db: mix.crest_db - reference.crest_db,
+        correlation: mix.correlation - reference.correlation,
+        width: mix.width - reference.width,
+        bands,
+        mix,
+        reference,
+        summary: String::new(),
+    };
+    comparison.summary = summarize(&comparison);
+    comparison
+}
+
+// Measures a mix against a reference track: loudness (LUFS), crest factor,
+// tonal balance per band and stereo correlation/width, with the
+// differences and a short written summary for the mix feedback prompt
+#[tauri::command]
+pub async fn compare_to_reference(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    mix_path: String,
+    ref_path: String,
+) -> Result<ReferenceComparison, String> {
+    for path in [&mix_path, &ref_path] {
+        if !paths::to_fs(path).exists() {
+            return Err(format!("File not found: {}", path));
+        }
+    }
+    let db = db.inner().clone();
+    let label = format!("Compare {} to {}", mix_path, ref_path);
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |_| {
+            let mix = quarantine::guard(&db, &mix_path, || profile(&mix_path))?;
+            let reference = quarantine::guard(&db, &ref_path, || profile(&ref_path))?;
+            Ok(compare(mix, reference))
+        })
+        .await
+}
@@ -0,0 +1,175 @@
+use crate::analysis;
+use crate::db::Db;
+use crate::journal::{self, Operation};
+use crate::library;
+use crate::paths;
+use rusqlite::{params, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tauri::State;
+
+// Characters no supported filesystem accepts in a name
+const FORBIDDEN: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+#[derive(serde::Serialize)]
+pub struct RenamePreview {
+    from: String,
+    to: String,
+    // Why this rename can't go ahead, if it can't
+    collision: Option<String>,
+    // Tokens with no value for this file; they render as nothing
+    missing: Vec<String>,
+}
+
+// bpm and key as the library shows them: the reconciled value when there
+// is one, otherwise whatever analysis found
+fn attribute(db: &Db, path: &str, name: &str) -> Result<Option<String>, String> {
+    let resolved: Option<String> = db
+        .lock()
+        .query_row(
+            "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = ?2",
+            params![path, name],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if resolved.is_some() {
+        return Ok(resolved);
+    }
+    let analyzed = analysis::load(db, path)?;
+    Ok(match name {
+        "bpm" => analyzed.and_then(|a| a.bpm).map(|bpm| format!("{}", bpm.round())),
+        _ => analyzed.and_then(|a| a.key),
+    })
+}
+
+fn token_value(db: &Db, path: &str, token: &str, index: usize, width: usize) -> Result<Option<String>, String> {
+    let fs_path = paths::to_fs(path);
+    if let Some(category) = token.strip_prefix("tag:") {
+        // "genre:house" answers {tag:genre}
+        let prefix = format!("{}:", category);
+        let tags = library::tags_for(&db.lock(), path)?;
+        return Ok(tags.iter().find_map(|t| t.strip_prefix(prefix.as_str()).map(str::to_string)));
+    }
+    if let Some(digits) = token.strip_prefix("index:") {
+        let width = digits.parse::<usize>().map_err(|_| format!("Invalid padding in {{{}}}", token))?;
+        return Ok(Some(format!("{:0width$}", index, width = width)));
+    }
+    Ok(match token {
+        "index" => Some(format!("{:0width$}", index, width = width)),
+        "name" => fs_path.file_stem().map(|s| s.to_string_lossy().to_string()),
+        "parentdir" => fs_path.parent().and_then(|p| p.file_name()).map(|s| s.to_string_lossy().to_string()),
+        "bpm" | "key" => attribute(db, path, token)?,
+        other => return Err(format!("Unknown token {{{}}}", other)),
+    })
+}
+
+// The new file name for one file; the extension is always kept
+fn render(db: &Db, path: &str, pattern: &str, index: usize, width: usize, missing: &mut Vec<String>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let close = rest[open..].find('}').ok_or("Unclosed { in pattern")? + open;
+        let token = &rest[open + 1..close];
+        match token_value(db, path, token, index, width)? {
+            Some(value) => out.push_str(&value),
+            None => missing.push(token.to_string()),
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+
+    let mut stem: String = out.chars().map(|c| if FORBIDDEN.contains(&c) || c.is_control() { '_' } else { c }).collect();
+    // Empty tokens leave stray separators behind
+    stem = stem.split_whitespace().collect::<Vec<_>>().join(" ");
+    let stem = stem.trim_matches(|c: char| c == '_' || c == '-' || c == '.' || c == ' ');
+    let ext = Path::new(path).extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    Ok(format!("{}{}", stem, ext))
+}
+
+fn build_previews(db: &Db, paths: &[String], pattern: &str, start_index: usize) -> Result<Vec<RenamePreview>, String> {
+    if pattern.trim().is_empty() {
+        return Err("The pattern is empty".to_string());
+    }
+    let width = (start_index + paths.len()).saturating_sub(1).max(1).to_string().len();
+    let mut previews = Vec::with_capacity(paths.len());
+    for (i, path) in paths.iter().enumerate() {
+        let mut missing = Vec::new();
+        let name = render(db, path, pattern, start_index + i, width, &mut missing)?;
+        let to = paths::display(&Path::new(path).with_file_name(&name));
+        previews.push(RenamePreview { from: path.clone(), to, collision: None, missing });
+    }
+
+    // Targets are compared case-insensitively since the default filesystems
+    // on macOS and Windows are
+    let sources: HashSet<String> = paths.iter().map(|p| p.to_lowercase()).collect();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for preview in &previews {
+        *counts.entry(preview.to.to_lowercase()).or_default() += 1;
+    }
+    for preview in &mut previews {
+        let target = preview.to.to_lowercase();
+        if preview.to == preview.from {
+            continue;
+        }
+        if Path::new(&preview.to).file_stem().map(|s| s.is_empty()).unwrap_or(true) {
+            preview.collision = Some("The pattern produces an empty name".to_string());
+        } else if counts[&target] > 1 {
+            preview.collision = Some("Another file in the batch gets the same name".to_string());
+        } else if target != preview.from.to_lowercase() && !sources.contains(&target) && paths::to_fs(&preview.to).exists() {
+            preview.collision = Some("A file with this name already exists".to_string());
+        }
+    }
+    Ok(previews)
+}
+
+// Shows what a batch rename would do without touching anything. Tokens:
+// {name}, {bpm}, {key}, {index} (or {index:3} for fixed padding),
+// {tag:<category>} and {parentdir}.
+#[tauri::command]
+pub async fn preview_rename(db: State<'_, Db>, paths: Vec<String>, pattern: String, start_index: Option<usize>) -> Result<Vec<RenamePreview>, String> {
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || build_previews(&db, &paths, &pattern, start_index.unwrap_or(1)))
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
+
+// Renames the batch as one undoable step. Refuses to run if any rename in
+// the preview collides.
+#[tauri::command]
+pub async fn apply_rename(db: State<'_, Db>, paths: Vec<String>, pattern: String, start_index: Option<usize>) -> Result<Vec<RenamePreview>, String> {
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let previews = build_previews(&db, &paths, &pattern, start_index.unwrap_or(1))?;
+        if let Some(bad) = previews.iter().find(|p| p.collision.is_some()) {
+            return Err(format!("Cannot rename {}: {}", bad.from, bad.collision.as_deref().unwrap_or_default()));
+        }
+        let changes: Vec<&RenamePreview> = previews.iter().filter(|p| p.from != p.to).collect();
+
+        // A target that is another file's current name (swaps, shifted
+        // numbering, case-only changes) is freed first by parking the file
+        // under a temporary name
+        let sources: HashSet<String> = changes.iter().map(|p| p.from.to_lowercase()).collect();
+        let mut ops = Vec::new();
+        let mut direct = Vec::new();
+        let mut unpark = Vec::new();
+        for (i, preview) in changes.iter().enumerate() {
+            if sources.contains(&preview.to.to_lowercase()) {
+                let parked = paths::display(&Path::new(&preview.from).with_file_name(format!(".renaming-{}-{}", std::process::id(), i)));
+                ops.push(Operation::Move { from: preview.from.clone(), to: parked.clone() });
+                unpark.push(Operation::Move { from: parked, to: preview.to.clone() });
+            } else {
+                direct.push(Operation::Move { from: preview.from.clone(), to: preview.to.clone() });
+            }
+        }
+        // Direct moves vacate the names the parked files are waiting for
+        ops.extend(direct);
+        ops.extend(unpark);
+
+        journal::run(&db.lock(), &format!("Rename {} files", changes.len()), ops)?;
+        Ok(previews)
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
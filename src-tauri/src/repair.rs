@@ -0,0 +1,260 @@
+use crate::audio::{self, AudioData};
+use crate::db::Db;
+use crate::derived;
+use crate::jobs::{Priority, Scheduler};
+use crate::paths;
+use crate::quarantine;
+use std::f32::consts::PI;
+use tauri::State;
+
+const SOURCE: &str = "repair";
+// A flat top this long at the file's peak is clipping, not a waveform
+// that happens to touch its maximum
+const MIN_CLIP_RUN: usize = 3;
+// Clipped then turned down still has flat tops, just lower; below this
+// peak a file is too quiet for that to be likely
+const CLIP_FLOOR: f32 = 0.5;
+const CLIP_TOLERANCE: f32 = 0.999;
+// DC below -60 dBFS is inaudible and not worth fixing
+const DC_THRESHOLD: f32 = 0.001;
+// Peak level a repaired file is brought down to if rebuilding the
+// clipped tops took it over, -1 dBTP
+const CEILING: f32 = 0.891;
+// 4x oversampling with a windowed sinc, as BS.1770 true peak metering does
+const OVERSAMPLE: usize = 4;
+const SINC_TAPS: i64 = 8;
+// Region list cap; a heavily clipped master has thousands
+const MAX_REGIONS: usize = 500;
+
+#[derive(serde::Serialize)]
+pub struct ClipRegion {
+    // Seconds
+    start: f64,
+    channel: usize,
+    samples: usize,
+}
+
+#[derive(serde::Serialize)]
+pub struct SignalStats {
+    peak_db: f32,
+    // Highest level between samples, which a DAC or a lossy encoder sees
+    true_peak_db: f32,
+    // Per channel, as a fraction of full scale
+    dc_offset: Vec<f32>,
+    has_dc: bool,
+    clipped_samples: usize,
+    clipped_regions: usize,
+    regions: Vec<ClipRegion>,
+}
+
+#[derive(serde::Serialize)]
+pub struct RepairResult {
+    source: String,
+    path: String,
+    before: SignalStats,
+    after: SignalStats,
+    // Level change applied to keep rebuilt peaks under the ceiling
+    gain_db: f32,
+}
+
+fn to_db(level: f32) -> f32 {
+    20.0 * level.max(1e-6).log10()
+}
+
+fn channel(audio: &AudioData, c: usize) -> impl Iterator<Item = f32> + '_ {
+    audio.samples.iter().skip(c).step_by(audio.channels.max(1)).copied()
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Highest absolute value of the band-limited signal between samples
+fn true_peak(signal: &[f32]) -> f32 {
+    let kernels: Vec<Vec<f32>> = (1..OVERSAMPLE)
+        .map(|phase| {
+            let frac = phase as f32 / OVERSAMPLE as f32;
+            (-SINC_TAPS + 1..=SINC_TAPS)
+                .map(|k| {
+                    let x = k as f32 - frac;
+                    // Hann window over the kernel span
+                    let w = 0.5 + 0.5 * (PI * x / SINC_TAPS as f32).cos();
+                    sinc(x) * w
+                })
+                .collect()
+        })
+        .collect();
+    let at = |i: i64| if i < 0 { 0.0 } else { signal.get(i as usize).copied().unwrap_or(0.0) };
+    let mut peak = signal.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    for i in 0..signal.len() as i64 {
+        for kernel in &kernels {
+            let value: f32 = kernel.iter().zip(-SINC_TAPS + 1..).map(|(w, k)| at(i + k) * w).sum();
+            peak = peak.max(value.abs());
+        }
+    }
+    peak
+}
+
+// Runs of samples sitting at the channel's peak, as (start, length)
+fn clipped_runs(signal: &[f32], peak: f32) -> Vec<(usize, usize)> {
+    if peak < CLIP_FLOOR {
+        return Vec::new();
+    }
+    let threshold = peak * CLIP_TOLERANCE;
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < signal.len() {
+        if signal[i].abs() >= threshold {
+            let sign = signal[i].signum();
+            let start = i;
+            while i < signal.len() && signal[i].abs() >= threshold && signal[i].signum() == sign {
+                i += 1;
+            }
+            if i - start >= MIN_CLIP_RUN {
+                runs.push((start, i - start));
+            }
+        } else {
+            i += 1;
+        }
+    }
+    runs
+}
+
+pub fn stats(audio: &AudioData) -> SignalStats {
+    let channels = audio.channels.max(1);
+    let peak = audio.samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    let mut result = SignalStats {
+        peak_db: to_db(peak),
+        true_peak_db: to_db(peak),
+        dc_offset: Vec::with_capacity(channels),
+        has_dc: false,
+        clipped_samples: 0,
+        clipped_regions: 0,
+        regions: Vec::new(),
+    };
+    let mut true_max = 0.0f32;
+    for c in 0..channels {
+        let signal: Vec<f32> = channel(audio, c).collect();
+        let dc = signal.iter().map(|s| *s as f64).sum::<f64>() / signal.len().max(1) as f64;
+        result.dc_offset.push(dc as f32);
+        result.has_dc |= (dc as f32).abs() > DC_THRESHOLD;
+        true_max = true_max.max(true_peak(&signal));
+        for (start, length) in clipped_runs(&signal, peak) {
+            result.clipped_samples += length;
+            result.clipped_regions += 1;
+            if result.regions.len() < MAX_REGIONS {
+                result.regions.push(ClipRegion {
+                    start: start as f64 / audio.sample_rate.max(1) as f64,
+                    channel: c,
+                    samples: length,
+                });
+            }
+        }
+    }
+    result.true_peak_db = to_db(true_max);
+    result
+}
+
+// Rebuilds each flat top with a cubic through the samples either side of
+// it, following the slopes going in and coming out, so the peak that was
+// cut off comes back instead of staying flat
+fn declip(signal: &mut [f32], peak: f32) {
+    for (start, length) in clipped_runs(signal, peak) {
+        let end = start + length;
+        if start < 2 || end + 1 >= signal.len() {
+            continue;
+        }
+        let (a, b) = (signal[start - 1], signal[end]);
+        let span = (end - (start - 1)) as f32;
+        let slope_in = (signal[start - 1] - signal[start - 2]) * span;
+        let slope_out = (signal[end + 1] - signal[end]) * span;
+        let floor = signal[start].abs();
+        let sign = signal[start].signum();
+        for (i, sample) in signal[start..end].iter_mut().enumerate() {
+            let t = (i + 1) as f32 / span;
+            let (t2, t3) = (t * t, t * t * t);
+            let value = (2.0 * t3 - 3.0 * t2 + 1.0) * a
+                + (t3 - 2.0 * t2 + t) * slope_in
+                + (-2.0 * t3 + 3.0 * t2) * b
+                + (t3 - t2) * slope_out;
+            // Never lower than the clipped value it replaces
+            *sample = if value.abs() > floor && value.signum() == sign { value } else { floor * sign };
+        }
+    }
+}
+
+// Returns the gain applied to stay under CEILING, in dB
+pub fn repair(audio: &mut AudioData, fix_clipping: bool, remove_dc: bool) -> f32 {
+    let channels = audio.channels.max(1);
+    let peak = audio.samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    for c in 0..channels {
+        let mut signal: Vec<f32> = channel(audio, c).collect();
+        // Before DC removal, which would move the flat tops off the peak
+        if fix_clipping {
+            declip(&mut signal, peak);
+        }
+        if remove_dc {
+            let dc = (signal.iter().map(|s| *s as f64).sum::<f64>() / signal.len().max(1) as f64) as f32;
+            if dc.abs() > DC_THRESHOLD {
+                signal.iter_mut().for_each(|s| *s -= dc);
+            }
+        }
+        for (slot, value) in audio.samples.iter_mut().skip(c).step_by(channels).zip(signal) {
+            *slot = value;
+        }
+    }
+
+    let repaired_peak = audio.samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    if repaired_peak > 1.0 || (fix_clipping && repaired_peak > peak) {
+        let gain = (CEILING / repaired_peak).min(1.0);
+        audio.samples.iter_mut().for_each(|s| *s *= gain);
+        return to_db(gain);
+    }
+    0.0
+}
+
+// Clipped regions, true (inter-sample) peak and DC offset of `path`
+#[tauri::command]
+pub async fn analyze_clipping(db: State<'_, Db>, path: String) -> Result<SignalStats, String> {
+    let db = db.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let decoded = quarantine::guard(&db, &path, || audio::decode_file(&paths::to_fs(&path), None))?;
+        Ok(stats(&decoded))
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+// Writes "<name> repaired.wav" with clipped peaks rebuilt and DC removed
+// (both on unless turned off), and returns stats from before and after
+#[tauri::command]
+pub async fn repair_audio(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    declip: Option<bool>,
+    remove_dc: Option<bool>,
+    dest_dir: Option<String>,
+) -> Result<RepairResult, String> {
+    let (fix_clipping, remove_dc) = (declip.unwrap_or(true), remove_dc.unwrap_or(true));
+    if !fix_clipping && !remove_dc {
+        return Err("Nothing to repair".to_string());
+    }
+    let db = db.inner().clone();
+    let label = format!("Repair {}", path);
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |_| {
+            let mut decoded = quarantine::guard(&db, &path, || audio::decode_file(&paths::to_fs(&path), None))?;
+            let before = stats(&decoded);
+            let gain_db = repair(&mut decoded, fix_clipping, remove_dc);
+            let after = stats(&decoded);
+            let attributes = derived::carried_attributes(&db, &path)?;
+            let written = derived::write(&db, &path, dest_dir.as_deref(), "repaired", &decoded, SOURCE, &attributes)?;
+            Ok(RepairResult { source: path, path: written, before, after, gain_db })
+        })
+        .await
+}
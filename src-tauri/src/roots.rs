@@ -0,0 +1,546 @@
+use crate::db::{self, Db};
+use crate::ignore::IgnoreRules;
+use crate::jobs::{Priority, Scheduler};
+use crate::library;
+use crate::paths;
+use crate::presets;
+use crate::sandbox::Sandbox;
+use crate::scanner::{self, ScanOptions};
+use crate::volumes::{self, VolumeKind};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use rusqlite::{params, Connection};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+
+// How often the background loop looks for roots that are due a rescan
+const SCHEDULE_TICK: Duration = Duration::from_secs(60);
+// Slow-volume scans save a resume point this often
+const CHECKPOINT_DIRS: usize = 20;
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, serde::Serialize)]
+pub struct LibraryRoot {
+    id: i64,
+    path: String,
+    name: String,
+    scan_interval_minutes: Option<i64>,
+    watch: bool,
+    follow_symlinks: bool,
+    last_scan_at: Option<i64>,
+    volume_kind: String,
+    online: bool,
+    file_count: i64,
+    // Glob patterns, one per line; added to the root's .aistudioignore
+    ignore_patterns: String,
+    // On a locked or read-only volume: browsing, tags and metadata work, but
+    // nothing on it is moved, renamed or rewritten
+    read_only: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RootSettings {
+    name: Option<String>,
+    // None or 0 disables scheduled rescans
+    scan_interval_minutes: Option<i64>,
+    watch: Option<bool>,
+    follow_symlinks: Option<bool>,
+    ignore_patterns: Option<String>,
+}
+
+// A root is online when its folder is present and listable; unplugged drives
+// and unmounted shares fail the read_dir.
+pub fn is_online(path: &str) -> bool {
+    let path = paths::to_fs(path);
+    path.is_dir() && std::fs::read_dir(&path).is_ok()
+}
+
+pub fn online_map(conn: &Connection) -> Result<HashMap<i64, bool>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, path FROM library_roots")
+        .map_err(|e| e.to_string())?;
+    let roots = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(roots.into_iter().map(|(id, path)| (id, is_online(&path))).collect())
+}
+
+// The library root a path lives under, the innermost one if roots nest
+pub fn root_containing(conn: &Connection, path: &str) -> Result<Option<i64>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, path FROM library_roots")
+        .map_err(|e| e.to_string())?;
+    let roots = stmt
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(roots
+        .into_iter()
+        .filter(|(_, root)| std::path::Path::new(path).starts_with(root))
+        .max_by_key(|(_, root)| root.len())
+        .map(|(id, _)| id))
+}
+
+// Refuses when any of `paths` is under a read-only root, before anything
+// is touched, so a batch doesn't get partway through and stop. Tags and
+// metadata live in the database and aren't affected.
+pub fn ensure_writable(conn: &Connection, paths: &[&str]) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare("SELECT path, name FROM library_roots WHERE read_only = 1")
+        .map_err(|e| e.to_string())?;
+    let locked = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    for path in paths {
+        if let Some((_, name)) = locked.iter().find(|(root, _)| std::path::Path::new(path).starts_with(root)) {
+            return Err(format!(
+                "{} is on {}, which is read-only; files there can't be moved, renamed or rewritten. Tags and metadata still save to the library.",
+                path, name
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn load_roots(conn: &Connection) -> Result<Vec<LibraryRoot>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT r.id, r.path, r.name, r.scan_interval_minutes, r.watch, r.last_scan_at,
+                    (SELECT COUNT(*) FROM files f WHERE f.root_id = r.id), r.volume_kind, r.follow_symlinks,
+                    r.ignore_patterns, r.read_only
+             FROM library_roots r ORDER BY r.name",
+        )
+        .map_err(|e| e.to_string())?;
+    let roots = stmt
+        .query_map([], |row| {
+            let path: String = row.get(1)?;
+            Ok(LibraryRoot {
+                id: row.get(0)?,
+                online: is_online(&path),
+                path,
+                name: row.get(2)?,
+                scan_interval_minutes: row.get(3)?,
+                watch: row.get(4)?,
+                last_scan_at: row.get(5)?,
+                file_count: row.get(6)?,
+                volume_kind: row.get(7)?,
+                follow_symlinks: row.get(8)?,
+                ignore_patterns: row.get(9)?,
+                read_only: row.get(10)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(roots)
+}
+
+fn load_root(conn: &Connection, id: i64) -> Result<LibraryRoot, String> {
+    load_roots(conn)?
+        .into_iter()
+        .find(|r| r.id == id)
+        .ok_or_else(|| format!("Unknown library root: {}", id))
+}
+
+fn load_checkpoint(db: &Db, root_id: i64) -> Option<(PathBuf, i64)> {
+    db.lock()
+        .query_row(
+            "SELECT last_dir, stamp FROM scan_checkpoints WHERE root_id = ?1",
+            params![root_id],
+            |row| Ok((PathBuf::from(row.get::<_, String>(0)?), row.get::<_, i64>(1)?)),
+        )
+        .ok()
+}
+
+// Marks a root as scanning while alive. Dropped on every way out of a scan
+// (done, failed, cancelled), so a root never looks busy after its scan ends.
+struct Scanning<'a> {
+    db: &'a Db,
+    root_id: i64,
+}
+
+impl<'a> Scanning<'a> {
+    // Also records what the volume was detected as this time
+    fn start(db: &'a Db, root_id: i64, kind: VolumeKind, read_only: bool) -> Result<Scanning<'a>, String> {
+        db.lock()
+            .execute(
+                "UPDATE library_roots SET scan_started_at = ?1, volume_kind = ?2, read_only = ?3 WHERE id = ?4",
+                params![db::now(), kind.as_str(), read_only, root_id],
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(Scanning { db, root_id })
+    }
+}
+
+impl Drop for Scanning<'_> {
+    fn drop(&mut self) {
+        let _ = self.db.lock().execute("UPDATE library_roots SET scan_started_at = NULL WHERE id = ?1", params![self.root_id]);
+    }
+}
+
+// Rescans a root into the files table. Files not seen during a completed
+// scan are dropped; a cancelled or failed scan leaves the index alone.
+// Network shares and spinning drives scan one at a time, throttled, and
+// checkpoint as they go so an interrupted scan picks up where it stopped.
+pub fn submit_scan(scheduler: &Scheduler, db: &Db, root: &LibraryRoot, priority: Priority) -> u64 {
+    let db = db.clone();
+    let (root_id, root_path) = (root.id, root.path.clone());
+    let label = format!("Scan {}", root.name);
+    let kind = VolumeKind::parse(&root.volume_kind);
+    let follow_symlinks = root.follow_symlinks;
+    let ignore_patterns = root.ignore_patterns.clone();
+    let pool = if kind.is_slow() { "slow-scan" } else { "scan" };
+
+    scheduler.submit_on(pool, label, priority, vec![root.path.clone()], move |ctx| {
+        if !is_online(&root_path) {
+            return Err(format!("{} is offline", root_path));
+        }
+
+        // Drives get moved between machines; re-check what we're scanning
+        let kind = volumes::detect(&paths::to_fs(&root_path));
+        let read_only = !volumes::is_writable(&paths::to_fs(&root_path));
+        let checkpoint = if kind.is_slow() { load_checkpoint(&db, root_id) } else { None };
+        let stamp = checkpoint.as_ref().map(|c| c.1).unwrap_or_else(db::now);
+        // Declared before anything that locks the database, so it's dropped
+        // after them
+        let _scanning = Scanning::start(&db, root_id, kind, read_only)?;
+
+        let options = ScanOptions {
+            throttle: kind.throttle(),
+            read_timeout: kind.read_timeout(),
+            resume_after: checkpoint.map(|c| c.0),
+            follow_symlinks,
+            ignore: IgnoreRules::load(&paths::to_fs(&root_path), &ignore_patterns),
+            include_presets: presets::enabled(&db.lock()),
+            ..ScanOptions::default()
+        };
+
+        let batch = RefCell::new(Vec::new());
+        let flush = || -> Result<(), String> {
+            let mut pending = batch.borrow_mut();
+            let mut conn = db.lock();
+            let tx = conn.transaction().map_err(|e| e.to_string())?;
+            for file in pending.drain(..) {
+                library::upsert_file(&tx, Some(root_id), &file, stamp)?;
+            }
+            tx.commit().map_err(|e| e.to_string())
+        };
+
+        let mut dirs_since_checkpoint = 0;
+        let mut last_checkpoint = Instant::now();
+        let result = scanner::scan_resumable(
+            &paths::to_fs(&root_path),
+            &options,
+            ctx,
+            &mut |file| {
+                batch.borrow_mut().push(file);
+                if batch.borrow().len() >= 500 {
+                    flush()?;
+                }
+                Ok(())
+            },
+            &mut |dir| {
+                if !kind.is_slow() {
+                    return Ok(());
+                }
+                dirs_since_checkpoint += 1;
+                if dirs_since_checkpoint >= CHECKPOINT_DIRS || last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                    // Files must be on disk before the checkpoint claims their folder is done
+                    flush()?;
+                    db.lock()
+                        .execute(
+                            "INSERT OR REPLACE INTO scan_checkpoints (root_id, last_dir, stamp) VALUES (?1, ?2, ?3)",
+                            params![root_id, dir.to_string_lossy(), stamp],
+                        )
+                        .map_err(|e| e.to_string())?;
+                    dirs_since_checkpoint = 0;
+                    last_checkpoint = Instant::now();
+                }
+                Ok(())
+            },
+        );
+        flush()?;
+        result?;
+
+        let conn = db.lock();
+        conn.execute("DELETE FROM files WHERE root_id = ?1 AND indexed_at < ?2", params![root_id, stamp])
+            .map_err(|e| e.to_string())?;
+        conn.execute("DELETE FROM scan_checkpoints WHERE root_id = ?1", params![root_id])
+            .map_err(|e| e.to_string())?;
+        conn.execute("UPDATE library_roots SET last_scan_at = ?1 WHERE id = ?2", params![db::now(), root_id])
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    })
+}
+
+// Queues a rescan of one root, or of every root that's online. Returns the
+// job ids.
+pub fn submit_rescans(scheduler: &Scheduler, db: &Db, root_id: Option<i64>, priority: Priority) -> Result<Vec<u64>, String> {
+    let roots = load_roots(&db.lock())?;
+    Ok(roots
+        .iter()
+        .filter(|root| root.online && root_id.map(|id| id == root.id).unwrap_or(true))
+        .map(|root| submit_scan(scheduler, db, root, priority))
+        .collect())
+}
+
+#[derive(Default)]
+pub struct Watchers {
+    active: Mutex<HashMap<i64, RecommendedWatcher>>,
+}
+
+impl Watchers {
+    fn set(&self, db: &Db, root: &LibraryRoot) -> Result<(), String> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        active.remove(&root.id);
+        if !root.watch || !root.online {
+            return Ok(());
+        }
+
+        let db = db.clone();
+        let root_id = root.id;
+        let root_dir = paths::to_fs(&root.path);
+        let ignore = IgnoreRules::load(&root_dir, &root.ignore_patterns);
+        let include_presets = presets::enabled(&db.lock());
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(_) => return,
+            };
+            let conn = db.lock();
+            for path in &event.paths {
+                let ignored = path
+                    .strip_prefix(&root_dir)
+                    .map(|relative| ignore.is_ignored_path(relative, path.is_dir()))
+                    .unwrap_or(false);
+                if ignored {
+                    continue;
+                }
+                // Creates, edits and both halves of a rename all resolve to
+                // "index it if it's there, drop it if it isn't"
+                let _ = if path.is_file() {
+                    match scanner::scanned_file(path, include_presets) {
+                        Ok(Some(file)) => library::upsert_file(&conn, Some(root_id), &file, db::now()),
+                        _ => Ok(()),
+                    }
+                } else if !path.exists() {
+                    library::remove_path(&conn, &paths::display(path))
+                } else {
+                    Ok(())
+                };
+            }
+        })
+        .map_err(|e| format!("Failed to start watcher: {}", e))?;
+
+        watcher
+            .watch(&paths::to_fs(&root.path), RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", root.path, e))?;
+        active.insert(root.id, watcher);
+        Ok(())
+    }
+
+    // Restarts every watcher from the roots currently in the database
+    pub fn reload(&self, db: &Db) -> Result<(), String> {
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        for root in load_roots(&db.lock())? {
+            let _ = self.set(db, &root);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self) {
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    pub fn remove(&self, id: i64) {
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+    }
+}
+
+// A root's drive is back: watch it again and, if it's watched, rescan to
+// catch anything that changed while it was away
+pub fn reconnected(db: &Db, scheduler: &Scheduler, watchers: &Watchers, id: i64) -> Result<(), String> {
+    let root = load_root(&db.lock(), id)?;
+    watchers.set(db, &root)?;
+    if root.watch {
+        submit_scan(scheduler, db, &root, Priority::Background);
+    }
+    Ok(())
+}
+
+// Takes up the roots in a database just opened: scans it was left in the
+// middle of are over, and each root gets its watcher
+pub fn reopen(db: &Db, watchers: &Watchers) -> Result<(), String> {
+    // Scans interrupted by a quit shouldn't look like they're still running
+    db.lock()
+        .execute("UPDATE library_roots SET scan_started_at = NULL", [])
+        .map_err(|e| e.to_string())?;
+    // An offline root just starts without a watcher
+    watchers.reload(db)
+}
+
+// Starts watchers and the loop that kicks off scheduled rescans
+pub fn start(app: &AppHandle) -> Result<(), String> {
+    let db = app.state::<Db>().inner().clone();
+    let watchers = app.state::<Watchers>();
+
+    reopen(&db, &watchers)?;
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(SCHEDULE_TICK);
+        loop {
+            interval.tick().await;
+            let scheduler = app.state::<Scheduler>();
+            let roots = match load_roots(&db.lock()) {
+                Ok(roots) => roots,
+                Err(_) => continue,
+            };
+            let now = db::now();
+            for root in roots {
+                let interval = match root.scan_interval_minutes {
+                    Some(minutes) if minutes > 0 => minutes * 60,
+                    _ => continue,
+                };
+                let running: Option<i64> = db
+                    .lock()
+                    .query_row(
+                        "SELECT scan_started_at FROM library_roots WHERE id = ?1",
+                        params![root.id],
+                        |row| row.get::<_, Option<i64>>(0),
+                    )
+                    .ok()
+                    .flatten();
+                let due = root.last_scan_at.map(|last| now - last >= interval).unwrap_or(true);
+                if due && running.is_none() && root.online {
+                    submit_scan(&scheduler, &db, &root, Priority::Background);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_library_roots(db: State<'_, Db>) -> Result<Vec<LibraryRoot>, String> {
+    load_roots(&db.lock())
+}
+
+#[tauri::command]
+pub async fn add_library_root(
+    db: State<'_, Db>,
+    sandbox: State<'_, Sandbox>,
+    scheduler: State<'_, Scheduler>,
+    watchers: State<'_, Watchers>,
+    path: String,
+    settings: Option<RootSettings>,
+) -> Result<LibraryRoot, String> {
+    let dir = paths::to_fs(&path);
+    if !dir.is_dir() {
+        return Err("Path is not a directory".to_string());
+    }
+    sandbox.allow(&db, &dir)?;
+    let path = paths::display(&dir);
+
+    let settings = settings.unwrap_or(RootSettings {
+        name: None,
+        scan_interval_minutes: None,
+        watch: None,
+        follow_symlinks: None,
+        ignore_patterns: None,
+    });
+    let name = settings.name.unwrap_or_else(|| {
+        dir.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.clone())
+    });
+
+    let root = {
+        let conn = db.lock();
+        conn.execute(
+            "INSERT INTO library_roots (path, name, scan_interval_minutes, watch, follow_symlinks, volume_kind, ignore_patterns, read_only, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                path,
+                name,
+                settings.scan_interval_minutes,
+                settings.watch.unwrap_or(false),
+                settings.follow_symlinks.unwrap_or(true),
+                volumes::detect(&dir).as_str(),
+                settings.ignore_patterns.unwrap_or_default(),
+                !volumes::is_writable(&dir),
+                db::now()
+            ],
+        )
+        .map_err(|e| format!("Failed to add library root: {}", e))?;
+        load_root(&conn, conn.last_insert_rowid())?
+    };
+
+    watchers.set(&db, &root)?;
+    submit_scan(&scheduler, &db, &root, Priority::Normal);
+    Ok(root)
+}
+
+#[tauri::command]
+pub async fn update_library_root(db: State<'_, Db>, watchers: State<'_, Watchers>, id: i64, settings: RootSettings) -> Result<LibraryRoot, String> {
+    let root = {
+        let conn = db.lock();
+        if let Some(name) = &settings.name {
+            conn.execute("UPDATE library_roots SET name = ?1 WHERE id = ?2", params![name, id])
+                .map_err(|e| e.to_string())?;
+        }
+        if let Some(minutes) = settings.scan_interval_minutes {
+            conn.execute(
+                "UPDATE library_roots SET scan_interval_minutes = ?1 WHERE id = ?2",
+                params![if minutes > 0 { Some(minutes) } else { None }, id],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        if let Some(watch) = settings.watch {
+            conn.execute("UPDATE library_roots SET watch = ?1 WHERE id = ?2", params![watch, id])
+                .map_err(|e| e.to_string())?;
+        }
+        if let Some(follow) = settings.follow_symlinks {
+            conn.execute("UPDATE library_roots SET follow_symlinks = ?1 WHERE id = ?2", params![follow, id])
+                .map_err(|e| e.to_string())?;
+        }
+        if let Some(patterns) = &settings.ignore_patterns {
+            conn.execute("UPDATE library_roots SET ignore_patterns = ?1 WHERE id = ?2", params![patterns, id])
+                .map_err(|e| e.to_string())?;
+        }
+        load_root(&conn, id)?
+    };
+
+    watchers.set(&db, &root)?;
+    Ok(root)
+}
+
+#[tauri::command]
+pub async fn remove_library_root(db: State<'_, Db>, watchers: State<'_, Watchers>, id: i64) -> Result<(), String> {
+    watchers.remove(id);
+    let conn = db.lock();
+    conn.execute("DELETE FROM files WHERE root_id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM scan_checkpoints WHERE root_id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM library_roots WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn scan_library_root(db: State<'_, Db>, scheduler: State<'_, Scheduler>, id: i64) -> Result<u64, String> {
+    let root = load_root(&db.lock(), id)?;
+    if !root.online {
+        return Err(format!("{} is offline", root.name));
+    }
+    Ok(submit_scan(&scheduler, &db, &root, Priority::Normal))
+}
@@ -0,0 +1,374 @@
+use crate::audio::AudioData;
+use crate::db::Db;
+use crate::decode_cache::DecodeCache;
+use crate::midi_ports;
+use crate::playback::{self, Playback};
+use crate::settings;
+use midir::MidiInputConnection;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, State};
+
+// Voices sounding at once; the oldest is cut when a new one needs room
+const MAX_VOICES: usize = 64;
+// Shortest fade a voice is cut with, so stealing one doesn't click
+const MIN_RELEASE: f64 = 0.003;
+
+fn default_level() -> f32 {
+    1.0
+}
+
+fn default_sustain() -> f32 {
+    1.0
+}
+
+fn default_release() -> f64 {
+    0.05
+}
+
+// Envelope times in seconds, sustain as a level 0-1
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct Adsr {
+    #[serde(default)]
+    attack: f64,
+    #[serde(default)]
+    decay: f64,
+    #[serde(default = "default_sustain")]
+    sustain: f32,
+    #[serde(default = "default_release")]
+    release: f64,
+}
+
+impl Default for Adsr {
+    fn default() -> Self {
+        Adsr { attack: 0.0, decay: 0.0, sustain: 1.0, release: default_release() }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+// One sounding sample, resampled to the device rate, shaped by its envelope
+pub struct Voice {
+    audio: Arc<AudioData>,
+    position: f64,
+    // Source frames per output frame before the device rate is applied
+    speed: f64,
+    gain: f32,
+    adsr: Adsr,
+    stage: Stage,
+    level: f32,
+    // Level per frame lost in release, fixed when the release starts
+    release_step: f32,
+    // The key holding it, for note-offs; None for one-shots
+    note: Option<u8>,
+}
+
+impl Voice {
+    pub fn new(audio: Arc<AudioData>, speed: f64, gain: f32, adsr: Adsr, note: Option<u8>) -> Voice {
+        Voice { audio, position: 0.0, speed, gain, adsr, stage: Stage::Attack, level: 0.0, release_step: 0.0, note }
+    }
+
+    fn release(&mut self, seconds: f64, rate: u32) {
+        self.stage = Stage::Release;
+        self.release_step = self.level / (seconds.max(MIN_RELEASE) * rate as f64) as f32;
+    }
+
+    fn envelope(&mut self, rate: u32) -> f32 {
+        let per_frame = |seconds: f64| if seconds <= 0.0 { 1.0 } else { (1.0 / (seconds * rate as f64)) as f32 };
+        match self.stage {
+            Stage::Attack => {
+                self.level += per_frame(self.adsr.attack);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                let sustain = self.adsr.sustain.clamp(0.0, 1.0);
+                self.level -= per_frame(self.adsr.decay) * (1.0 - sustain);
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => {}
+            Stage::Release => self.level = (self.level - self.release_step).max(0.0),
+        }
+        self.level
+    }
+
+    // Adds this voice's next frame into `frame`; false once it has finished
+    fn mix(&mut self, frame: &mut [f32], rate: u32) -> bool {
+        let channels = self.audio.channels.max(1);
+        let frames = self.audio.samples.len() / channels;
+        let index = self.position as usize;
+        if index >= frames || (self.stage == Stage::Release && self.level <= 0.0) {
+            return false;
+        }
+        let gain = self.gain * self.envelope(rate);
+        let frac = (self.position - index as f64) as f32;
+        for (out, value) in frame.iter_mut().enumerate() {
+            let channel = out % channels;
+            let a = self.audio.samples[index * channels + channel];
+            let b = if index + 1 < frames { self.audio.samples[(index + 1) * channels + channel] } else { a };
+            *value += (a + (b - a) * frac) * gain;
+        }
+        self.position += self.speed * self.audio.sample_rate as f64 / rate.max(1) as f64;
+        true
+    }
+}
+
+// A fixed-size pool of voices, mixed a frame at a time
+#[derive(Default)]
+pub struct Voices {
+    voices: Vec<Voice>,
+    // Voices cut to make room, fading out
+    stolen: Vec<Voice>,
+}
+
+impl Voices {
+    pub fn is_empty(&self) -> bool {
+        self.voices.is_empty() && self.stolen.is_empty()
+    }
+
+    pub fn start(&mut self, voice: Voice, rate: u32) {
+        if self.voices.len() >= MAX_VOICES {
+            let mut oldest = self.voices.remove(0);
+            oldest.release(MIN_RELEASE, rate);
+            self.stolen.push(oldest);
+        }
+        self.voices.push(voice);
+    }
+
+    fn release(&mut self, note: u8, rate: u32) {
+        for voice in self.voices.iter_mut().filter(|v| v.note == Some(note) && v.stage != Stage::Release) {
+            voice.release(voice.adsr.release, rate);
+        }
+    }
+
+    pub fn process(&mut self, frame: &mut [f32], rate: u32) {
+        self.voices.retain_mut(|voice| voice.mix(frame, rate));
+        self.stolen.retain_mut(|voice| voice.mix(frame, rate));
+    }
+}
+
+// A key range mapped to a library sample. The sample plays at its own pitch
+// on `root` and is transposed for the other keys in the range.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Zone {
+    path: String,
+    low: u8,
+    high: u8,
+    // Defaults to `low`, so a single-key zone plays untransposed
+    #[serde(default)]
+    root: Option<u8>,
+    // Extra transposition in semitones
+    #[serde(default)]
+    pitch: f32,
+    #[serde(default = "default_level")]
+    level: f32,
+    #[serde(default)]
+    adsr: Adsr,
+    // Plays to the end regardless of note-off, as drums usually want
+    #[serde(default)]
+    one_shot: bool,
+}
+
+#[derive(Clone)]
+struct LoadedZone {
+    zone: Zone,
+    audio: Arc<AudioData>,
+}
+
+enum NoteEvent {
+    On { note: u8, velocity: u8 },
+    Off { note: u8 },
+}
+
+// Plays the keymap from MIDI notes inside the audio callback. Notes arrive
+// over a channel so the MIDI thread never waits on the audio thread.
+pub struct Sampler {
+    zones: Vec<LoadedZone>,
+    voices: Voices,
+    events: mpsc::Receiver<NoteEvent>,
+    sender: mpsc::Sender<NoteEvent>,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        let (sender, events) = mpsc::channel();
+        Sampler { zones: Vec::new(), voices: Voices::default(), events, sender }
+    }
+}
+
+impl Sampler {
+    pub fn active(&self) -> bool {
+        !self.voices.is_empty()
+    }
+
+    // A silent copy with the same keymap, for rendering offline
+    pub fn offline(&self) -> Sampler {
+        Sampler { zones: self.zones.clone(), ..Sampler::default() }
+    }
+
+    pub fn note_on(&mut self, note: u8, velocity: u8, rate: u32) {
+        for loaded in self.zones.iter().filter(|z| (z.zone.low..=z.zone.high).contains(&note)) {
+            let zone = &loaded.zone;
+            let semitones = note as f64 - zone.root.unwrap_or(zone.low) as f64 + zone.pitch as f64;
+            let gain = zone.level * velocity as f32 / 127.0;
+            let key = if zone.one_shot { None } else { Some(note) };
+            self.voices.start(Voice::new(loaded.audio.clone(), 2f64.powf(semitones / 12.0), gain, zone.adsr, key), rate);
+        }
+    }
+
+    // Starts and releases voices for notes that came in since the last buffer
+    pub fn poll(&mut self, rate: u32) {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                NoteEvent::On { note, velocity } => self.note_on(note, velocity, rate),
+                NoteEvent::Off { note } => self.note_off(note, rate),
+            }
+        }
+    }
+
+    pub fn note_off(&mut self, note: u8, rate: u32) {
+        self.voices.release(note, rate);
+    }
+
+    pub fn process(&mut self, frame: &mut [f32], rate: u32) {
+        self.voices.process(frame, rate);
+    }
+}
+
+// Keeps the MIDI inputs feeding the sampler open
+#[derive(Default)]
+pub struct SamplerInputs {
+    connections: Mutex<Vec<(String, MidiInputConnection<()>)>>,
+}
+
+fn forward(sender: &mpsc::Sender<NoteEvent>, bytes: &[u8]) {
+    if let [status, note, velocity, ..] = *bytes {
+        let event = match status & 0xF0 {
+            0x90 if velocity > 0 => NoteEvent::On { note, velocity },
+            0x80 | 0x90 => NoteEvent::Off { note },
+            _ => return,
+        };
+        let _ = sender.send(event);
+    }
+}
+
+fn load(db: &Db, cache: &DecodeCache, zones: Vec<Zone>) -> Result<Vec<LoadedZone>, String> {
+    zones.into_iter().map(|zone| Ok(LoadedZone { audio: cache.decode(db, &zone.path)?, zone })).collect()
+}
+
+impl SamplerInputs {
+    fn open(&self, playback: &Playback, ports: &[String]) -> Result<(), String> {
+        let mut connections = self.connections.lock().unwrap();
+        connections.clear();
+        if ports.is_empty() {
+            return Ok(());
+        }
+        playback.open_output()?;
+        let sender = playback.with_sampler(|sampler| sampler.sender.clone());
+        for name in ports {
+            let sender = sender.clone();
+            let connection = midi_ports::connect_input(name, move |bytes| forward(&sender, bytes))?;
+            connections.push((name.clone(), connection));
+        }
+        Ok(())
+    }
+}
+
+// Loads the open profile's keymap and reconnects the keyboards it used
+// last, at launch and after a profile switch
+pub fn reload(app: &AppHandle) {
+    let (zones, ports) = {
+        let db = app.state::<Db>();
+        let conn = db.lock();
+        (
+            settings::get::<Vec<Zone>>(&conn, "sampler.keymap").ok().flatten().unwrap_or_default(),
+            settings::get::<Vec<String>>(&conn, "sampler.inputs").ok().flatten().unwrap_or_default(),
+        )
+    };
+    if zones.is_empty() && ports.is_empty() {
+        app.state::<SamplerInputs>().connections.lock().unwrap().clear();
+        app.state::<Playback>().with_sampler(|sampler| sampler.zones = Vec::new());
+        return;
+    }
+    let app = app.clone();
+    std::thread::spawn(move || {
+        // Samples moved or deleted since are left out rather than failing the lot
+        let (db, cache) = (app.state::<Db>(), app.state::<DecodeCache>());
+        let zones = zones.into_iter().filter_map(|zone| Some(LoadedZone { audio: cache.decode(&db, &zone.path).ok()?, zone })).collect();
+        let playback = app.state::<Playback>();
+        playback.with_sampler(|sampler| sampler.zones = zones);
+        // A keyboard that isn't plugged in shouldn't stop the app
+        let _ = app.state::<SamplerInputs>().open(&playback, &ports);
+    });
+}
+
+// Maps key ranges to library samples, replacing the current keymap.
+// Sounding voices play on.
+#[tauri::command]
+pub async fn set_sampler_keymap(
+    db: State<'_, Db>,
+    cache: State<'_, DecodeCache>,
+    playback: State<'_, Playback>,
+    zones: Vec<Zone>,
+) -> Result<(), String> {
+    if let Some(zone) = zones.iter().find(|z| z.low > z.high || z.high > 127) {
+        return Err(format!("Key range {}-{} for {} is invalid", zone.low, zone.high, zone.path));
+    }
+    let value = serde_json::to_value(&zones).unwrap_or_default();
+    let (db, cache) = (db.inner().clone(), cache.inner().clone());
+    let loaded = {
+        let db = db.clone();
+        tokio::task::spawn_blocking(move || load(&db, &cache, zones))
+            .await
+            .map_err(|e| format!("Task failed: {}", e))??
+    };
+    playback.with_sampler(|sampler| sampler.zones = loaded);
+    settings::set(&db.lock(), "sampler.keymap", &value)
+}
+
+#[tauri::command]
+pub async fn get_sampler_keymap(playback: State<'_, Playback>) -> Result<Vec<Zone>, String> {
+    Ok(playback.with_sampler(|sampler| sampler.zones.iter().map(|z| z.zone.clone()).collect()))
+}
+
+// Plays the keymap from `ports` (names from list_midi_inputs); an empty
+// list disconnects
+#[tauri::command]
+pub async fn set_sampler_inputs(app: AppHandle, db: State<'_, Db>, ports: Vec<String>) -> Result<(), String> {
+    let (handle, opening) = (app.clone(), ports.clone());
+    playback::blocking(&app, move |playback| handle.state::<SamplerInputs>().open(playback, &opening)).await?;
+    settings::set(&db.lock(), "sampler.inputs", &serde_json::json!(ports))
+}
+
+#[tauri::command]
+pub async fn get_sampler_inputs(inputs: State<'_, SamplerInputs>) -> Result<Vec<String>, String> {
+    Ok(inputs.connections.lock().unwrap().iter().map(|(name, _)| name.clone()).collect())
+}
+
+// Plays or releases a key from the UI, as if from a keyboard
+#[tauri::command]
+pub async fn sampler_note(app: AppHandle, note: u8, velocity: Option<u8>) -> Result<(), String> {
+    if note > 127 {
+        return Err("Notes run from 0 to 127".to_string());
+    }
+    playback::blocking(&app, |playback| playback.open_output()).await?;
+    let playback = app.state::<Playback>();
+    let event = match velocity.unwrap_or(100).min(127) {
+        0 => NoteEvent::Off { note },
+        velocity => NoteEvent::On { note, velocity },
+    };
+    playback
+        .with_sampler(|sampler| sampler.sender.send(event))
+        .map_err(|_| "Sampler isn't running".to_string())
+}
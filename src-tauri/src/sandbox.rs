@@ -0,0 +1,198 @@
+use crate::db::Db;
+use crate::paths;
+use rusqlite::params;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tauri::State;
+
+// Largest range a single read_file call may return
+const MAX_READ: u64 = 16 * 1024 * 1024;
+
+// Folders the frontend may read from through read_file: the app's own data
+// and cache dirs plus every folder the user has pointed the scanner at.
+pub struct Sandbox {
+    roots: RwLock<Vec<PathBuf>>,
+    app_roots: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    pub fn load(db: &Db, app_roots: Vec<PathBuf>) -> Result<Self, String> {
+        let roots = app_roots
+            .iter()
+            .cloned()
+            .chain(stored_roots(db)?)
+            .map(|p| p.canonicalize().unwrap_or(p))
+            .collect();
+        Ok(Sandbox { roots: RwLock::new(roots), app_roots })
+    }
+
+    // Drops every granted folder and takes them from `db` instead, for a
+    // switch to another profile's database or one just restored
+    pub fn reset(&self, db: &Db) -> Result<(), String> {
+        let stored = stored_roots(db)?;
+        let roots = self.app_roots.iter().cloned().chain(stored).map(|p| p.canonicalize().unwrap_or(p)).collect();
+        *self.roots.write().unwrap_or_else(|e| e.into_inner()) = roots;
+        Ok(())
+    }
+
+    pub fn allow(&self, db: &Db, root: &Path) -> Result<(), String> {
+        let root = root.canonicalize().map_err(|e| e.to_string())?;
+        let mut roots = self.roots.write().unwrap_or_else(|e| e.into_inner());
+        if roots.iter().any(|r| root.starts_with(r)) {
+            return Ok(());
+        }
+        db.lock()
+            .execute(
+                "INSERT OR IGNORE INTO allowed_roots (path) VALUES (?1)",
+                params![root.to_string_lossy()],
+            )
+            .map_err(|e| e.to_string())?;
+        roots.push(root);
+        Ok(())
+    }
+
+    // Resolves symlinks and ".." before checking, so a path can't escape its root
+    pub fn check(&self, path: &Path) -> Result<PathBuf, String> {
+        let resolved = path
+            .canonicalize()
+            .map_err(|e| format!("Cannot access {}: {}", paths::display(path), e))?;
+        let roots = self.roots.read().unwrap_or_else(|e| e.into_inner());
+        if roots.iter().any(|root| resolved.starts_with(root)) {
+            Ok(resolved)
+        } else {
+            Err(format!("Access denied: {} is outside the library", paths::display(path)))
+        }
+    }
+
+    // Like check, for a file that may not exist yet: its nearest existing
+    // folder is checked, and the names below that can't climb back out
+    pub fn check_dest(&self, path: &Path) -> Result<PathBuf, String> {
+        let denied = || format!("Access denied: {} is outside the library", paths::display(path));
+        let mut existing = path;
+        let mut missing = Vec::new();
+        while !existing.exists() {
+            missing.push(existing.file_name().ok_or_else(denied)?);
+            existing = existing.parent().ok_or_else(denied)?;
+        }
+        let mut resolved = self.check(existing)?;
+        for name in missing.into_iter().rev() {
+            resolved.push(name);
+        }
+        Ok(resolved)
+    }
+}
+
+fn stored_roots(db: &Db) -> Result<Vec<PathBuf>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare("SELECT path FROM allowed_roots")
+        .map_err(|e| e.to_string())?;
+    let stored = stmt
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(stored.into_iter().map(PathBuf::from).collect())
+}
+
+pub fn detect_mime(header: &[u8], path: &Path) -> &'static str {
+    let starts = |magic: &[u8]| header.starts_with(magic);
+
+    if header.len() >= 12 && starts(b"RIFF") && &header[8..12] == b"WAVE" {
+        return "audio/wav";
+    }
+    if header.len() >= 12 && starts(b"RIFF") && &header[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    if header.len() >= 12 && starts(b"FORM") && (&header[8..12] == b"AIFF" || &header[8..12] == b"AIFC") {
+        return "audio/aiff";
+    }
+    if header.len() >= 12 && &header[4..8] == b"ftyp" {
+        return match &header[8..12] {
+            b"M4A " | b"M4B " | b"M4P " => "audio/mp4",
+            _ => "video/mp4",
+        };
+    }
+    if starts(b"fLaC") {
+        return "audio/flac";
+    }
+    if starts(b"OggS") {
+        return "audio/ogg";
+    }
+    if starts(b"ID3") || (header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0) {
+        return "audio/mpeg";
+    }
+    if starts(b"MThd") {
+        return "audio/midi";
+    }
+    if starts(b"\x89PNG\r\n\x1a\n") {
+        return "image/png";
+    }
+    if starts(&[0xFF, 0xD8, 0xFF]) {
+        return "image/jpeg";
+    }
+    if starts(b"GIF87a") || starts(b"GIF89a") {
+        return "image/gif";
+    }
+    if starts(b"%PDF") {
+        return "application/pdf";
+    }
+
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "json" => "application/json",
+        "txt" | "lrc" => "text/plain",
+        "svg" => "image/svg+xml",
+        "wav" => "audio/wav",
+        "mp3" => "audio/mpeg",
+        "mid" | "midi" => "audio/midi",
+        _ => "application/octet-stream",
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct FileRange {
+    data: Vec<u8>,
+    mime: String,
+    offset: u64,
+    length: u64,
+    total_size: u64,
+}
+
+#[tauri::command]
+pub async fn read_file(
+    sandbox: State<'_, Sandbox>,
+    path: String,
+    offset: Option<u64>,
+    length: Option<u64>,
+) -> Result<FileRange, String> {
+    let resolved = sandbox.check(&paths::to_fs(&path))?;
+
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::open(&resolved).map_err(|e| format!("Failed to open file: {}", e))?;
+        let total_size = file.metadata().map_err(|e| e.to_string())?.len();
+
+        let mut header = [0u8; 16];
+        let header_len = file.read(&mut header).map_err(|e| e.to_string())?;
+        let mime = detect_mime(&header[..header_len], &resolved).to_string();
+
+        let offset = offset.unwrap_or(0).min(total_size);
+        let length = length
+            .unwrap_or(total_size - offset)
+            .min(total_size - offset)
+            .min(MAX_READ);
+
+        file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+        let mut data = vec![0u8; length as usize];
+        file.read_exact(&mut data).map_err(|e| format!("Failed to read file: {}", e))?;
+
+        Ok(FileRange { data, mime, offset, length, total_size })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,344 @@
+use crate::ignore::IgnoreRules;
+use crate::ir;
+use crate::jobs::JobContext;
+use crate::paths;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+pub const AUDIO_EXTENSIONS: &[&str] = &["wav", "mp3", "aiff", "flac", "m4a", "aac", "ogg", "wma"];
+pub const MIDI_EXTENSIONS: &[&str] = &["mid", "midi"];
+// Plugin presets, indexed only when "scan.plugin_presets" is on
+pub const PRESET_EXTENSIONS: &[&str] = &["fxp", "fxb", "vstpreset", "aupreset"];
+
+// Attempts per directory before a slow-volume scan gives up
+const READ_RETRIES: u32 = 3;
+
+#[derive(Clone, serde::Serialize)]
+pub struct ScannedFile {
+    pub name: String,
+    pub path: String,
+    pub file_type: String,
+    // What kind of that type it is, when that matters: "ir" for impulse
+    // responses, which are still audio
+    pub sub_kind: Option<String>,
+    pub size: u64,
+    pub modified: i64,
+}
+
+pub struct ScanOptions {
+    // Stop after this many files; the ad-hoc folder scan caps itself so the UI stays responsive
+    pub limit: Option<usize>,
+    // Sleep between directory reads (network shares, spinning drives)
+    pub throttle: Option<Duration>,
+    // Give up on a directory read that hangs this long, after retries
+    pub read_timeout: Option<Duration>,
+    // Skip directories already finished by an interrupted scan
+    pub resume_after: Option<PathBuf>,
+    // Descend into symlinked folders and index symlinked files
+    pub follow_symlinks: bool,
+    // Matched against paths relative to the scanned folder
+    pub ignore: IgnoreRules,
+    // Index plugin preset files as well as audio and MIDI
+    pub include_presets: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            limit: None,
+            throttle: None,
+            read_timeout: None,
+            resume_after: None,
+            // Cycles are caught by the visited set, so following is safe
+            follow_symlinks: true,
+            ignore: IgnoreRules::default(),
+            include_presets: false,
+        }
+    }
+}
+
+// Identity of a file or folder independent of the path used to reach it
+type FileId = (u64, u64);
+
+#[cfg(unix)]
+fn file_id(_path: &Path, metadata: &fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// Volume serial and file index, which hard links share. Metadata doesn't
+// expose them on stable, so this opens the file without read access.
+#[cfg(windows)]
+fn file_id(path: &Path, _metadata: &fs::Metadata) -> Option<FileId> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS,
+    };
+    // Folders only open with backup semantics
+    let file = fs::OpenOptions::new()
+        .access_mode(0)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+        .ok()?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+        return None;
+    }
+    let index = (u64::from(info.nFileIndexHigh) << 32) | u64::from(info.nFileIndexLow);
+    Some((u64::from(info.dwVolumeSerialNumber), index))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn file_id(_path: &Path, _metadata: &fs::Metadata) -> Option<FileId> {
+    None
+}
+
+// Only hardlinked files (link count > 1) can be reached twice by path
+#[cfg(unix)]
+fn may_be_linked(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() > 1
+}
+
+// Metadata has no link count here, so any file may be
+#[cfg(windows)]
+fn may_be_linked(_metadata: &fs::Metadata) -> bool {
+    true
+}
+
+#[cfg(not(any(unix, windows)))]
+fn may_be_linked(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+struct DirEntry {
+    path: PathBuf,
+    is_dir: bool,
+    is_symlink: bool,
+}
+
+pub fn file_type_for(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    if AUDIO_EXTENSIONS.contains(&ext.as_str()) {
+        Some("audio")
+    } else if MIDI_EXTENSIONS.contains(&ext.as_str()) {
+        Some("midi")
+    } else {
+        None
+    }
+}
+
+// Impulse responses are WAVs that look like one by name and folder
+pub fn sub_kind_for(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    (ext == "wav" && ir::looks_like_ir(path)).then_some("ir")
+}
+
+// The type a file is indexed as: file_type_for, or "preset" for plugin
+// presets when those are included
+fn indexed_type(path: &Path, include_presets: bool) -> Option<&'static str> {
+    file_type_for(path).or_else(|| {
+        let ext = path.extension()?.to_string_lossy().to_lowercase();
+        (include_presets && PRESET_EXTENSIONS.contains(&ext.as_str())).then_some("preset")
+    })
+}
+
+pub fn scanned_file(path: &Path, include_presets: bool) -> Result<Option<ScannedFile>, String> {
+    if indexed_type(path, include_presets).is_none() {
+        return Ok(None);
+    }
+    let metadata = fs::metadata(path).map_err(|e| format!("Failed to get file metadata: {}", e))?;
+    Ok(scanned_file_with(path, &metadata, include_presets))
+}
+
+fn scanned_file_with(path: &Path, metadata: &fs::Metadata, include_presets: bool) -> Option<ScannedFile> {
+    let file_type = indexed_type(path, include_presets)?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    Some(ScannedFile {
+        name: paths::file_name(path),
+        path: paths::display(path),
+        file_type: file_type.to_string(),
+        sub_kind: sub_kind_for(path).map(str::to_string),
+        size: metadata.len(),
+        modified,
+    })
+}
+
+fn list_dir(dir: &Path) -> Result<Vec<DirEntry>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read directory: {}", e))?;
+    let mut listed = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read entry: {}", e))?;
+        let path = entry.path();
+        // file_type() doesn't follow links; is_dir() does
+        let is_symlink = entry.file_type().map(|t| t.is_symlink()).unwrap_or(false);
+        listed.push(DirEntry { is_dir: path.is_dir(), is_symlink, path });
+    }
+    // Sorted so an interrupted scan can resume from a checkpoint
+    listed.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(listed)
+}
+
+// A hung SMB/NFS mount can block read_dir indefinitely, so slow volumes
+// read on a helper thread and retry with backoff before failing.
+fn list_dir_with_timeout(dir: &Path, timeout: Duration) -> Result<Vec<DirEntry>, String> {
+    for attempt in 0..READ_RETRIES {
+        let (tx, rx) = mpsc::channel();
+        let target = dir.to_path_buf();
+        std::thread::spawn(move || {
+            let _ = tx.send(list_dir(&target));
+        });
+
+        match rx.recv_timeout(timeout) {
+            Ok(result) => return result,
+            Err(_) => std::thread::sleep(Duration::from_secs(2u64.pow(attempt + 1))),
+        }
+    }
+    Err(format!("Timed out reading {}", dir.display()))
+}
+
+// True when `dir` was fully scanned before the checkpoint was written.
+// Sorted depth-first order matches component-wise path order, so every
+// directory that sorts at or before the checkpoint, other than its
+// ancestors, is finished.
+fn already_scanned(dir: &Path, checkpoint: &Path) -> bool {
+    dir == checkpoint || (dir < checkpoint && !checkpoint.starts_with(dir))
+}
+
+// Walks `dir` recursively, handing every audio/MIDI file (and plugin preset,
+// when included) to `on_file`.
+// Returns the number of files found.
+pub fn scan(dir: &Path, options: &ScanOptions, ctx: &JobContext, on_file: &mut dyn FnMut(ScannedFile) -> Result<(), String>) -> Result<usize, String> {
+    scan_resumable(dir, options, ctx, on_file, &mut |_| Ok(()))
+}
+
+// Like `scan`, calling `on_dir_done` after each directory is finished so the
+// caller can persist a resume checkpoint.
+pub fn scan_resumable(
+    dir: &Path,
+    options: &ScanOptions,
+    ctx: &JobContext,
+    on_file: &mut dyn FnMut(ScannedFile) -> Result<(), String>,
+    on_dir_done: &mut dyn FnMut(&Path) -> Result<(), String>,
+) -> Result<usize, String> {
+    let mut walker = Walker {
+        root: dir,
+        options,
+        ctx,
+        on_file,
+        on_dir_done,
+        found: 0,
+        visited_dirs: HashSet::new(),
+        seen_files: HashSet::new(),
+        seen_paths: HashSet::new(),
+    };
+    if let Some(id) = fs::metadata(dir).ok().and_then(|m| file_id(dir, &m)) {
+        walker.visited_dirs.insert(id);
+    }
+    walker.walk(dir, false)?;
+    Ok(walker.found)
+}
+
+struct Walker<'a> {
+    root: &'a Path,
+    options: &'a ScanOptions,
+    ctx: &'a JobContext,
+    on_file: &'a mut dyn FnMut(ScannedFile) -> Result<(), String>,
+    on_dir_done: &'a mut dyn FnMut(&Path) -> Result<(), String>,
+    found: usize,
+    // Folders already entered, so symlink loops and bind mounts are walked once
+    visited_dirs: HashSet<FileId>,
+    // Files already indexed, by identity, when they can be reached twice
+    seen_files: HashSet<FileId>,
+    // Resolved paths of files reached through a link that had no identity
+    seen_paths: HashSet<PathBuf>,
+}
+
+impl Walker<'_> {
+    // `linked` is set under a symlinked folder
+    fn walk(&mut self, dir: &Path, linked: bool) -> Result<(), String> {
+        if self.ctx.is_cancelled() {
+            return Err("Scan cancelled".to_string());
+        }
+
+        if let Some(throttle) = self.options.throttle {
+            std::thread::sleep(throttle);
+        }
+        let entries = match self.options.read_timeout {
+            Some(timeout) => list_dir_with_timeout(dir, timeout)?,
+            None => list_dir(dir)?,
+        };
+
+        for entry in entries {
+            if self.options.limit.map(|limit| self.found >= limit).unwrap_or(false) {
+                return Ok(());
+            }
+            if entry.is_symlink && !self.options.follow_symlinks {
+                continue;
+            }
+            if let Ok(relative) = entry.path.strip_prefix(self.root) {
+                if self.options.ignore.is_ignored(relative, entry.is_dir) {
+                    continue;
+                }
+            }
+
+            // Broken links and files that vanished mid-scan are skipped
+            let metadata = match fs::metadata(&entry.path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            if entry.is_dir {
+                if let Some(checkpoint) = &self.options.resume_after {
+                    if already_scanned(&entry.path, checkpoint) {
+                        continue;
+                    }
+                }
+                match file_id(&entry.path, &metadata) {
+                    Some(id) if !self.visited_dirs.insert(id) => continue,
+                    _ => {}
+                }
+                self.walk(&entry.path, linked || entry.is_symlink)?;
+            } else if let Some(file) = scanned_file_with(&entry.path, &metadata, self.options.include_presets) {
+                if !self.first_sighting(&entry, &metadata, linked) {
+                    continue;
+                }
+                (self.on_file)(file)?;
+                self.found += 1;
+                if self.found % 250 == 0 {
+                    self.ctx.progress(0.0, Some(format!("{} files found", self.found)));
+                }
+            }
+        }
+
+        (self.on_dir_done)(dir)
+    }
+
+    // Whether the walk reaches this file for the first time. With links
+    // followed, a link and its target are both plain entries, so every file
+    // is checked; otherwise only files with other hard links can repeat.
+    fn first_sighting(&mut self, entry: &DirEntry, metadata: &fs::Metadata, linked: bool) -> bool {
+        if !self.options.follow_symlinks && !may_be_linked(metadata) {
+            return true;
+        }
+        match file_id(&entry.path, metadata) {
+            Some(id) => self.seen_files.insert(id),
+            // Only a path through a link can resolve to one already seen
+            None if linked || entry.is_symlink => {
+                let resolved = entry.path.canonicalize().unwrap_or_else(|_| entry.path.clone());
+                self.seen_paths.insert(resolved)
+            }
+            None => true,
+        }
+    }
+}
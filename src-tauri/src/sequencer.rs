@@ -0,0 +1,405 @@
+use crate::audio::AudioData;
+use crate::db::Db;
+use crate::decode_cache::DecodeCache;
+use crate::midi_ports;
+use crate::playback::{self, Playback};
+use crate::sampler::{Adsr, Sampler, Voice, Voices};
+use std::sync::mpsc;
+use std::sync::Arc;
+use tauri::{AppHandle, State};
+
+const DEFAULT_BPM: f64 = 120.0;
+
+fn default_steps() -> usize {
+    16
+}
+
+fn default_step_beats() -> f64 {
+    0.25
+}
+
+fn default_level() -> f32 {
+    1.0
+}
+
+fn default_velocity() -> u8 {
+    100
+}
+
+fn default_gate() -> f64 {
+    0.5
+}
+
+// What a lane plays on its steps
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Target {
+    // A library file, repitched by `pitch` semitones
+    Sample {
+        path: String,
+        #[serde(default)]
+        pitch: f32,
+        #[serde(default = "default_level")]
+        level: f32,
+    },
+    // A note sent to a MIDI output; `gate` is the note length in steps
+    Midi {
+        port: String,
+        #[serde(default)]
+        channel: u8,
+        note: u8,
+        #[serde(default = "default_gate")]
+        gate: f64,
+    },
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Hit {
+    step: usize,
+    #[serde(default = "default_velocity")]
+    velocity: u8,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Lane {
+    #[serde(default)]
+    name: Option<String>,
+    target: Target,
+    hits: Vec<Hit>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Pattern {
+    #[serde(default = "default_steps")]
+    steps: usize,
+    // Length of one step in beats, 0.25 for 16ths
+    #[serde(default = "default_step_beats")]
+    step_beats: f64,
+    lanes: Vec<Lane>,
+}
+
+// A lane with its sample decoded, or its MIDI port resolved
+#[derive(Clone)]
+enum LoadedTarget {
+    Sample { audio: Arc<AudioData>, speed: f64, level: f32 },
+    Midi { port: usize, channel: u8, note: u8, gate: f64 },
+}
+
+#[derive(Clone)]
+struct LoadedLane {
+    name: Option<String>,
+    target: LoadedTarget,
+    // Velocity per step, 0 for none
+    velocities: Vec<u8>,
+}
+
+// A MIDI message for the sender thread, by index into its connections
+type MidiEvent = (usize, [u8; 3]);
+
+// Plays a step pattern inside the audio callback, so steps land on exact
+// output frames rather than on the webview's timers
+pub struct Sequencer {
+    lanes: Vec<LoadedLane>,
+    steps: usize,
+    step_beats: f64,
+    bpm: f64,
+    playing: bool,
+    // The step that fired last, and output frames since it did
+    step: usize,
+    elapsed: f64,
+    voices: Voices,
+    // Note-offs still to send: frames left, port, status, note
+    pending_offs: Vec<(f64, usize, u8, u8)>,
+    // MIDI goes out from its own thread; the audio callback never blocks on it
+    midi: Option<mpsc::Sender<MidiEvent>>,
+    // Set on offline copies, which play MIDI lanes through the sampler's
+    // keymap instead of sending them
+    instrument: Option<Sampler>,
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Sequencer {
+            lanes: Vec::new(),
+            steps: default_steps(),
+            step_beats: default_step_beats(),
+            bpm: DEFAULT_BPM,
+            playing: false,
+            step: 0,
+            elapsed: 0.0,
+            voices: Voices::default(),
+            pending_offs: Vec::new(),
+            midi: None,
+            instrument: None,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct SequencerState {
+    playing: bool,
+    bpm: f64,
+    step: usize,
+    steps: usize,
+    lanes: usize,
+}
+
+impl Sequencer {
+    // True while there's anything to render: a running pattern or voices
+    // still ringing out after stop
+    pub fn active(&self) -> bool {
+        self.playing
+            || !self.voices.is_empty()
+            || !self.pending_offs.is_empty()
+            || self.instrument.as_ref().is_some_and(Sampler::active)
+    }
+
+    fn frames_per_step(&self, rate: u32) -> f64 {
+        self.step_beats * 60.0 / self.bpm * rate as f64
+    }
+
+    fn send_midi(&self, event: MidiEvent) {
+        if let Some(midi) = &self.midi {
+            let _ = midi.send(event);
+        }
+    }
+
+    fn fire(&mut self, step: usize, rate: u32) {
+        let step_frames = self.frames_per_step(rate);
+        for lane in &self.lanes {
+            let velocity = lane.velocities.get(step).copied().unwrap_or(0);
+            if velocity == 0 {
+                continue;
+            }
+            match &lane.target {
+                LoadedTarget::Sample { audio, speed, level } => {
+                    let gain = level * velocity as f32 / 127.0;
+                    self.voices.start(Voice::new(audio.clone(), *speed, gain, Adsr::default(), None), rate);
+                }
+                LoadedTarget::Midi { port, channel, note, gate } => {
+                    let channel = channel & 0x0F;
+                    if let Some(instrument) = &mut self.instrument {
+                        instrument.note_on(*note, velocity, rate);
+                    } else if let Some(midi) = &self.midi {
+                        let _ = midi.send((*port, [0x90 | channel, *note, velocity]));
+                    }
+                    self.pending_offs.push(((gate * step_frames).max(1.0), *port, 0x80 | channel, *note));
+                }
+            }
+        }
+    }
+
+    // Renders one output frame: advances the pattern, fires any step that
+    // starts on this frame and adds the sounding voices into `frame`
+    pub fn process(&mut self, frame: &mut [f32], rate: u32) {
+        if self.playing && !self.lanes.is_empty() {
+            if self.elapsed >= self.frames_per_step(rate) {
+                self.elapsed -= self.frames_per_step(rate);
+                self.step = (self.step + 1) % self.steps.max(1);
+                self.fire(self.step, rate);
+            }
+            self.elapsed += 1.0;
+        }
+        let (midi, instrument) = (&self.midi, &mut self.instrument);
+        self.pending_offs.retain_mut(|(left, port, status, note)| {
+            *left -= 1.0;
+            if *left > 0.0 {
+                return true;
+            }
+            if let Some(instrument) = instrument {
+                instrument.note_off(*note, rate);
+            } else if let Some(midi) = midi {
+                let _ = midi.send((*port, [*status, *note, 0]));
+            }
+            false
+        });
+        self.voices.process(frame, rate);
+        if let Some(instrument) = &mut self.instrument {
+            instrument.process(frame, rate);
+        }
+    }
+
+    // A copy for rendering offline: the same lanes and tempo, stopped, with
+    // MIDI lanes going to `keymap`. `lane` keeps just that lane, for stems.
+    pub fn offline(&self, keymap: &Sampler, lane: Option<usize>) -> Sequencer {
+        let lanes = match lane {
+            Some(index) => self.lanes.get(index).cloned().into_iter().collect(),
+            None => self.lanes.clone(),
+        };
+        Sequencer {
+            lanes,
+            steps: self.steps,
+            step_beats: self.step_beats,
+            bpm: self.bpm,
+            instrument: Some(keymap.offline()),
+            ..Sequencer::default()
+        }
+    }
+
+    pub fn lane_names(&self) -> Vec<String> {
+        self.lanes.iter().enumerate().map(|(i, lane)| lane.name.clone().unwrap_or_else(|| format!("Lane {}", i + 1))).collect()
+    }
+
+    // Output frames in one pass of the pattern
+    pub fn loop_frames(&self, rate: u32) -> f64 {
+        self.steps as f64 * self.frames_per_step(rate)
+    }
+
+    // Stops firing steps but lets sounding voices and held notes finish
+    pub fn let_ring(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn start(&mut self, rate_hint: u32) {
+        self.playing = true;
+        self.step = 0;
+        self.elapsed = 0.0;
+        self.fire(0, rate_hint);
+    }
+
+    // Notes left hanging on hardware would drone
+    fn release_midi_notes(&mut self) {
+        for (_, port, status, note) in std::mem::take(&mut self.pending_offs) {
+            self.send_midi((port, [status, note, 0]));
+        }
+    }
+
+    fn stop(&mut self) {
+        self.playing = false;
+        self.release_midi_notes();
+    }
+
+    fn state(&self) -> SequencerState {
+        SequencerState { playing: self.playing, bpm: self.bpm, step: self.step, steps: self.steps, lanes: self.lanes.len() }
+    }
+}
+
+// Decodes the pattern's samples and opens its MIDI ports, off the audio thread
+fn load(db: &Db, cache: &DecodeCache, pattern: &Pattern) -> Result<(Vec<LoadedLane>, Option<mpsc::Sender<MidiEvent>>), String> {
+    let mut ports: Vec<String> = Vec::new();
+    let mut lanes = Vec::with_capacity(pattern.lanes.len());
+    for lane in &pattern.lanes {
+        let target = match &lane.target {
+            Target::Sample { path, pitch, level } => LoadedTarget::Sample {
+                audio: cache.decode(db, path)?,
+                speed: 2f64.powf(*pitch as f64 / 12.0),
+                level: level.clamp(0.0, 2.0),
+            },
+            Target::Midi { port, channel, note, gate } => {
+                let index = match ports.iter().position(|p| p == port) {
+                    Some(index) => index,
+                    None => {
+                        ports.push(port.clone());
+                        ports.len() - 1
+                    }
+                };
+                LoadedTarget::Midi { port: index, channel: *channel, note: (*note).min(127), gate: gate.max(0.01) }
+            }
+        };
+        let mut velocities = vec![0u8; pattern.steps];
+        for hit in &lane.hits {
+            if let Some(slot) = velocities.get_mut(hit.step) {
+                *slot = hit.velocity.min(127);
+            }
+        }
+        lanes.push(LoadedLane { name: lane.name.clone(), target, velocities });
+    }
+
+    if ports.is_empty() {
+        return Ok((lanes, None));
+    }
+    let mut connections = ports.iter().map(|name| midi_ports::connect_output(name)).collect::<Result<Vec<_>, _>>()?;
+    let (tx, rx) = mpsc::channel::<MidiEvent>();
+    // Ends, closing the ports, when the sequencer drops its sender
+    std::thread::spawn(move || {
+        for (port, message) in rx {
+            if let Some(connection) = connections.get_mut(port) {
+                let _ = connection.send(&message);
+            }
+        }
+    });
+    Ok((lanes, Some(tx)))
+}
+
+fn check_bpm(bpm: f64) -> Result<(), String> {
+    if (20.0..=400.0).contains(&bpm) {
+        Ok(())
+    } else {
+        Err("Tempo must be between 20 and 400 BPM".to_string())
+    }
+}
+
+// Replaces the pattern. A running sequencer carries on from the same step
+// with the new lanes.
+#[tauri::command]
+pub async fn set_pattern(
+    db: State<'_, Db>,
+    cache: State<'_, DecodeCache>,
+    playback: State<'_, Playback>,
+    pattern: Pattern,
+) -> Result<SequencerState, String> {
+    if pattern.steps == 0 || pattern.steps > 256 {
+        return Err("A pattern has 1 to 256 steps".to_string());
+    }
+    if pattern.step_beats.is_nan() || pattern.step_beats <= 0.0 {
+        return Err("Step length must be positive".to_string());
+    }
+    let (db, cache) = (db.inner().clone(), cache.inner().clone());
+    let loaded = {
+        let pattern = pattern.clone();
+        tokio::task::spawn_blocking(move || load(&db, &cache, &pattern))
+            .await
+            .map_err(|e| format!("Task failed: {}", e))??
+    };
+    Ok(playback.with_sequencer(|sequencer| {
+        sequencer.release_midi_notes();
+        let (lanes, midi) = loaded;
+        sequencer.lanes = lanes;
+        sequencer.midi = midi;
+        sequencer.steps = pattern.steps;
+        sequencer.step_beats = pattern.step_beats;
+        sequencer.step %= pattern.steps;
+        sequencer.state()
+    }))
+}
+
+#[tauri::command]
+pub async fn start_sequencer(app: AppHandle, bpm: Option<f64>) -> Result<SequencerState, String> {
+    if let Some(bpm) = bpm {
+        check_bpm(bpm)?;
+    }
+    playback::blocking(&app, move |playback| {
+        let rate = playback.open_output()?;
+        Ok(playback.with_sequencer(|sequencer| {
+            if let Some(bpm) = bpm {
+                sequencer.bpm = bpm;
+            }
+            sequencer.start(rate);
+            sequencer.state()
+        }))
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn stop_sequencer(playback: State<'_, Playback>) -> Result<SequencerState, String> {
+    Ok(playback.with_sequencer(|sequencer| {
+        sequencer.stop();
+        sequencer.state()
+    }))
+}
+
+// Changes tempo on the next step, without restarting the pattern
+#[tauri::command]
+pub async fn set_bpm(playback: State<'_, Playback>, bpm: f64) -> Result<SequencerState, String> {
+    check_bpm(bpm)?;
+    Ok(playback.with_sequencer(|sequencer| {
+        sequencer.bpm = bpm;
+        sequencer.state()
+    }))
+}
+
+#[tauri::command]
+pub async fn get_sequencer_state(playback: State<'_, Playback>) -> Result<SequencerState, String> {
+    Ok(playback.with_sequencer(|sequencer| sequencer.state()))
+}
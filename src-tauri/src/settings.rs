@@ -0,0 +1,67 @@
+use crate::conversations;
+use crate::db::{self, Db};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use tauri::State;
+
+// App-wide preferences as JSON values under dotted keys ("ai.temperature").
+// Features read their own keys and fall back to a built-in default when unset.
+pub fn get<T: DeserializeOwned>(conn: &Connection, key: &str) -> Result<Option<T>, String> {
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM settings WHERE key = ?1", params![key], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    match value {
+        Some(json) => serde_json::from_str(&json)
+            .map(Some)
+            .map_err(|e| format!("Setting {} is invalid: {}", key, e)),
+        None => Ok(None),
+    }
+}
+
+pub fn set(conn: &Connection, key: &str, value: &Value) -> Result<(), String> {
+    if value.is_null() {
+        conn.execute("DELETE FROM settings WHERE key = ?1", params![key])
+            .map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO settings (key, value, updated_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+        params![key, value.to_string(), db::now()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_settings(db: State<'_, Db>) -> Result<HashMap<String, Value>, String> {
+    let conn = db.lock();
+    let mut stmt = conn
+        .prepare("SELECT key, value FROM settings")
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(key, json)| serde_json::from_str(&json).ok().map(|value| (key, value)))
+        .collect())
+}
+
+// A null value clears the setting back to its default. Model defaults
+// ("ai.*") are checked before they're stored.
+#[tauri::command]
+pub async fn set_setting(db: State<'_, Db>, key: String, value: Value) -> Result<(), String> {
+    if key.trim().is_empty() {
+        return Err("Setting key is empty".to_string());
+    }
+    if key.starts_with("ai.") {
+        conversations::check_setting(&key, &value)?;
+    }
+    set(&db.lock(), &key, &value)
+}
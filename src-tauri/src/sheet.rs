@@ -0,0 +1,430 @@
+use crate::paths;
+use printpdf::path::{PaintMode, WindingOrder};
+use printpdf::*;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 18.0;
+const BODY_SIZE: f32 = 11.0;
+const CHORD_SIZE: f32 = 10.0;
+const SECTION_SIZE: f32 = 13.0;
+const LINE_GAP: f32 = 5.5;
+
+// Courier glyphs are 0.6em wide, which lets chords sit over the right syllable
+const COURIER_ADVANCE: f32 = 0.6;
+const PT_TO_MM: f32 = 0.352_778;
+
+enum SheetLine {
+    Section(String),
+    Lyric { chords: Vec<(usize, String)>, text: String },
+    Blank,
+}
+
+// Accepts ChordPro-style input ("[Am]Hello [F]world") as well as plain
+// chord-over-lyric text and "[Verse 1]" / "Chorus:" section headers.
+fn parse_sheet(content: &str) -> Vec<SheetLine> {
+    let mut lines = Vec::new();
+    let mut pending_chords: Option<Vec<(usize, String)>> = None;
+
+    for raw in content.lines() {
+        let line = raw.trim_end();
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            if let Some(chords) = pending_chords.take() {
+                lines.push(SheetLine::Lyric { chords, text: String::new() });
+            }
+            lines.push(SheetLine::Blank);
+            continue;
+        }
+
+        if let Some(section) = parse_section_header(trimmed) {
+            if let Some(chords) = pending_chords.take() {
+                lines.push(SheetLine::Lyric { chords, text: String::new() });
+            }
+            lines.push(SheetLine::Section(section));
+            continue;
+        }
+
+        if is_chord_only_line(trimmed) {
+            if let Some(chords) = pending_chords.take() {
+                lines.push(SheetLine::Lyric { chords, text: String::new() });
+            }
+            pending_chords = Some(chord_columns(line));
+            continue;
+        }
+
+        let (inline_chords, text) = split_inline_chords(line);
+        let chords = match pending_chords.take() {
+            Some(chords) if inline_chords.is_empty() => chords,
+            _ => inline_chords,
+        };
+        lines.push(SheetLine::Lyric { chords, text });
+    }
+
+    if let Some(chords) = pending_chords.take() {
+        lines.push(SheetLine::Lyric { chords, text: String::new() });
+    }
+
+    lines
+}
+
+fn parse_section_header(line: &str) -> Option<String> {
+    let known = ["intro", "verse", "pre-chorus", "prechorus", "chorus", "bridge", "outro", "hook", "refrain", "interlude", "solo", "breakdown", "drop"];
+
+    let candidate = if line.starts_with('[') && line.ends_with(']') {
+        &line[1..line.len() - 1]
+    } else if let Some(stripped) = line.strip_suffix(':') {
+        stripped
+    } else {
+        return None;
+    };
+
+    let lower = candidate.trim().to_lowercase();
+    if known.iter().any(|k| lower.starts_with(k)) {
+        Some(candidate.trim().to_string())
+    } else {
+        None
+    }
+}
+
+pub fn is_chord_symbol(token: &str) -> bool {
+    let mut chars = token.chars();
+    match chars.next() {
+        Some('A'..='G') => {}
+        _ => return false,
+    }
+    let rest: String = chars.collect();
+    let rest = rest.trim_start_matches(|c| c == '#' || c == 'b');
+    let allowed = ["", "m", "min", "maj", "maj7", "m7", "7", "9", "11", "13", "sus", "sus2", "sus4", "dim", "dim7", "aug", "add9", "6", "m6", "m9", "maj9", "7sus4", "m7b5", "5"];
+    let (quality, bass) = match rest.split_once('/') {
+        Some((q, b)) => (q, Some(b)),
+        None => (rest, None),
+    };
+    let bass_ok = bass.map(|b| {
+        let mut c = b.chars();
+        matches!(c.next(), Some('A'..='G')) && c.all(|ch| ch == '#' || ch == 'b')
+    }).unwrap_or(true);
+    allowed.contains(&quality) && bass_ok
+}
+
+fn is_chord_only_line(line: &str) -> bool {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    !tokens.is_empty() && tokens.iter().all(|t| is_chord_symbol(t) || *t == "|" || *t == "-")
+}
+
+fn chord_columns(line: &str) -> Vec<(usize, String)> {
+    let mut chords = Vec::new();
+    let mut column = 0;
+    let mut current = String::new();
+    let mut start = 0;
+
+    for ch in line.chars() {
+        if ch.is_whitespace() {
+            if !current.is_empty() && is_chord_symbol(&current) {
+                chords.push((start, std::mem::take(&mut current)));
+            }
+            current.clear();
+        } else {
+            if current.is_empty() {
+                start = column;
+            }
+            current.push(ch);
+        }
+        column += 1;
+    }
+    if !current.is_empty() && is_chord_symbol(&current) {
+        chords.push((start, current));
+    }
+    chords
+}
+
+fn split_inline_chords(line: &str) -> (Vec<(usize, String)>, String) {
+    let mut chords = Vec::new();
+    let mut text = String::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        if ch == '[' {
+            let mut symbol = String::new();
+            let mut closed = false;
+            while let Some(&next) = chars.peek() {
+                chars.next();
+                if next == ']' {
+                    closed = true;
+                    break;
+                }
+                symbol.push(next);
+            }
+            if closed && is_chord_symbol(&symbol) {
+                let column = text.chars().count();
+                // Keep adjacent chords from overprinting each other
+                let column = match chords.last() {
+                    Some((prev_col, prev)) => column.max(prev_col + prev.chars().count() + 1),
+                    None => column,
+                };
+                chords.push((column, symbol));
+            } else {
+                text.push('[');
+                text.push_str(&symbol);
+                if closed {
+                    text.push(']');
+                }
+            }
+        } else {
+            text.push(ch);
+        }
+    }
+
+    (chords, text)
+}
+
+// Guitar voicings, low E to high e; -1 = muted, 0 = open
+fn chord_shape(symbol: &str) -> Option<[i8; 6]> {
+    let base = symbol.split('/').next().unwrap_or(symbol);
+    let shape = match base {
+        "C" => [-1, 3, 2, 0, 1, 0],
+        "Cm" => [-1, 3, 5, 5, 4, 3],
+        "C7" => [-1, 3, 2, 3, 1, 0],
+        "Cmaj7" => [-1, 3, 2, 0, 0, 0],
+        "D" => [-1, -1, 0, 2, 3, 2],
+        "Dm" => [-1, -1, 0, 2, 3, 1],
+        "D7" => [-1, -1, 0, 2, 1, 2],
+        "Dm7" => [-1, -1, 0, 2, 1, 1],
+        "Dsus4" => [-1, -1, 0, 2, 3, 3],
+        "E" => [0, 2, 2, 1, 0, 0],
+        "Em" => [0, 2, 2, 0, 0, 0],
+        "E7" => [0, 2, 0, 1, 0, 0],
+        "Em7" => [0, 2, 0, 0, 0, 0],
+        "F" => [1, 3, 3, 2, 1, 1],
+        "Fm" => [1, 3, 3, 1, 1, 1],
+        "Fmaj7" => [-1, -1, 3, 2, 1, 0],
+        "G" => [3, 2, 0, 0, 0, 3],
+        "Gm" => [3, 5, 5, 3, 3, 3],
+        "G7" => [3, 2, 0, 0, 0, 1],
+        "A" => [-1, 0, 2, 2, 2, 0],
+        "Am" => [-1, 0, 2, 2, 1, 0],
+        "A7" => [-1, 0, 2, 0, 2, 0],
+        "Am7" => [-1, 0, 2, 0, 1, 0],
+        "Asus4" => [-1, 0, 2, 2, 3, 0],
+        "B" => [-1, 2, 4, 4, 4, 2],
+        "Bm" => [-1, 2, 4, 4, 3, 2],
+        "B7" => [-1, 2, 1, 2, 0, 2],
+        "Bb" => [-1, 1, 3, 3, 3, 1],
+        "Eb" => [-1, 6, 5, 3, 4, 3],
+        "F#m" => [2, 4, 4, 2, 2, 2],
+        "C#m" => [-1, 4, 6, 6, 5, 4],
+        "G#m" => [4, 6, 6, 4, 4, 4],
+        _ => return None,
+    };
+    Some(shape)
+}
+
+struct PdfWriter {
+    doc: PdfDocumentReference,
+    layer: PdfLayerReference,
+    body_font: IndirectFontRef,
+    bold_font: IndirectFontRef,
+    cursor: f32,
+}
+
+impl PdfWriter {
+    fn new(title: &str) -> Result<Self, String> {
+        let (doc, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+        let body_font = doc.add_builtin_font(BuiltinFont::Courier).map_err(|e| e.to_string())?;
+        let bold_font = doc.add_builtin_font(BuiltinFont::HelveticaBold).map_err(|e| e.to_string())?;
+        let layer = doc.get_page(page).get_layer(layer);
+
+        Ok(PdfWriter {
+            doc,
+            layer,
+            body_font,
+            bold_font,
+            cursor: PAGE_HEIGHT - MARGIN,
+        })
+    }
+
+    fn ensure_space(&mut self, height: f32) {
+        if self.cursor - height < MARGIN {
+            let (page, layer) = self.doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Layer 1");
+            self.layer = self.doc.get_page(page).get_layer(layer);
+            self.cursor = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn text(&self, text: &str, size: f32, x: f32, y: f32, bold: bool) {
+        let font = if bold { &self.bold_font } else { &self.body_font };
+        self.layer.use_text(text, size, Mm(x), Mm(y), font);
+    }
+
+    fn line(&self, points: &[(f32, f32)], thickness: f32) {
+        self.layer.set_outline_thickness(thickness);
+        self.layer.add_line(Line {
+            points: points.iter().map(|(x, y)| (Point::new(Mm(*x), Mm(*y)), false)).collect(),
+            is_closed: false,
+        });
+    }
+
+    fn dot(&self, cx: f32, cy: f32, radius: f32) {
+        let ring: Vec<(Point, bool)> = (0..12)
+            .map(|i| {
+                let angle = i as f32 / 12.0 * std::f32::consts::TAU;
+                (Point::new(Mm(cx + radius * angle.cos()), Mm(cy + radius * angle.sin())), false)
+            })
+            .collect();
+        self.layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+        self.layer.add_polygon(Polygon {
+            rings: vec![ring],
+            mode: PaintMode::Fill,
+            winding_order: WindingOrder::NonZero,
+        });
+    }
+
+    fn chord_diagram(&self, symbol: &str, shape: [i8; 6], x: f32, y: f32) {
+        let string_gap = 3.2;
+        let fret_gap = 4.0;
+        let width = string_gap * 5.0;
+
+        let fretted: Vec<i8> = shape.iter().copied().filter(|f| *f > 0).collect();
+        let max_fret = fretted.iter().copied().max().unwrap_or(0);
+        let base_fret = if max_fret > 4 { fretted.iter().copied().min().unwrap_or(1) } else { 1 };
+
+        self.text(symbol, CHORD_SIZE, x, y + 8.0, true);
+        if base_fret > 1 {
+            self.text(&format!("{}fr", base_fret), 7.0, x + width + 1.5, y - fret_gap + 1.0, false);
+        }
+
+        for s in 0..6 {
+            let sx = x + s as f32 * string_gap;
+            self.line(&[(sx, y), (sx, y - fret_gap * 4.0)], 0.3);
+        }
+        for f in 0..5 {
+            let fy = y - f as f32 * fret_gap;
+            let thickness = if f == 0 && base_fret == 1 { 1.2 } else { 0.3 };
+            self.line(&[(x, fy), (x + width, fy)], thickness);
+        }
+
+        for (s, fret) in shape.iter().enumerate() {
+            let sx = x + s as f32 * string_gap;
+            match *fret {
+                -1 => self.text("x", 7.0, sx - 0.9, y + 1.2, false),
+                0 => self.text("o", 7.0, sx - 0.9, y + 1.2, false),
+                f => {
+                    let row = (f - base_fret) as f32 + 0.5;
+                    self.dot(sx, y - row * fret_gap, 1.1);
+                }
+            }
+        }
+    }
+
+    fn save(self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("Failed to create PDF: {}", e))?;
+        self.doc
+            .save(&mut BufWriter::new(file))
+            .map_err(|e| format!("Failed to write PDF: {}", e))
+    }
+}
+
+fn render_sheet(title: &str, lines: &[SheetLine], with_chords: bool, path: &Path) -> Result<(), String> {
+    let mut pdf = PdfWriter::new(title)?;
+    let char_width = BODY_SIZE * COURIER_ADVANCE * PT_TO_MM;
+
+    pdf.text(title, 18.0, MARGIN, pdf.cursor, true);
+    pdf.cursor -= 12.0;
+
+    let mut used_chords: Vec<String> = Vec::new();
+
+    for line in lines {
+        match line {
+            SheetLine::Blank => {
+                pdf.cursor -= LINE_GAP * 0.6;
+            }
+            SheetLine::Section(name) => {
+                pdf.ensure_space(LINE_GAP * 3.0);
+                pdf.cursor -= LINE_GAP * 0.5;
+                pdf.text(name, SECTION_SIZE, MARGIN, pdf.cursor, true);
+                pdf.cursor -= LINE_GAP * 1.4;
+            }
+            SheetLine::Lyric { chords, text } => {
+                let show_chords = with_chords && !chords.is_empty();
+                pdf.ensure_space(if show_chords { LINE_GAP * 2.0 } else { LINE_GAP });
+
+                if show_chords {
+                    for (column, symbol) in chords {
+                        pdf.text(symbol, CHORD_SIZE, MARGIN + *column as f32 * char_width, pdf.cursor, true);
+                        if !used_chords.contains(symbol) {
+                            used_chords.push(symbol.clone());
+                        }
+                    }
+                    pdf.cursor -= LINE_GAP;
+                }
+                if !text.trim().is_empty() {
+                    pdf.text(text, BODY_SIZE, MARGIN, pdf.cursor, false);
+                    pdf.cursor -= LINE_GAP;
+                }
+            }
+        }
+    }
+
+    if with_chords {
+        let diagrams: Vec<(String, [i8; 6])> = used_chords
+            .iter()
+            .filter_map(|c| chord_shape(c).map(|shape| (c.clone(), shape)))
+            .collect();
+
+        if !diagrams.is_empty() {
+            let cell_width = 30.0;
+            let cell_height = 32.0;
+            let per_row = ((PAGE_WIDTH - MARGIN * 2.0) / cell_width) as usize;
+
+            pdf.ensure_space(cell_height + LINE_GAP * 2.0);
+            pdf.cursor -= LINE_GAP;
+            pdf.text("Chords", SECTION_SIZE, MARGIN, pdf.cursor, true);
+            pdf.cursor -= LINE_GAP * 3.0;
+
+            for row in diagrams.chunks(per_row) {
+                pdf.ensure_space(cell_height);
+                for (i, (symbol, shape)) in row.iter().enumerate() {
+                    pdf.chord_diagram(symbol, *shape, MARGIN + i as f32 * cell_width, pdf.cursor - 6.0);
+                }
+                pdf.cursor -= cell_height;
+            }
+        }
+    }
+
+    pdf.save(path)
+}
+
+#[tauri::command]
+pub async fn export_sheet(content: String, format: String, path: String, title: Option<String>) -> Result<String, String> {
+    let with_chords = match format.to_lowercase().as_str() {
+        "lyrics" => false,
+        "chords" | "chordpro" | "chart" => true,
+        other => return Err(format!("Unsupported sheet format: {}", other)),
+    };
+
+    let output = paths::to_fs(&path).with_extension("pdf");
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+
+    let title = title.unwrap_or_else(|| {
+        output
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "Untitled".to_string())
+    });
+
+    let output_clone = output.clone();
+    tokio::task::spawn_blocking(move || {
+        let lines = parse_sheet(&content);
+        render_sheet(&title, &lines, with_chords, &output_clone)
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))??;
+
+    Ok(paths::display(&output))
+}
@@ -0,0 +1,255 @@
+use crate::audio::{self, AudioData};
+use crate::collections;
+use crate::db::{self, Db};
+use crate::hashes;
+use crate::jobs::{JobContext, Priority, Scheduler};
+use crate::paths;
+use crate::quarantine;
+use crate::scanner;
+use rusqlite::{params, OptionalExtension};
+use rustysynth::{MidiFile, MidiFileSequencer, SoundFont, Synthesizer, SynthesizerSettings};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::{AppHandle, Manager, State};
+
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+const MIN_SAMPLE_RATE: u32 = 22050;
+const MAX_SAMPLE_RATE: u32 = 96000;
+// Rendered past the last event so released notes ring out
+const TAIL_SECONDS: f64 = 1.5;
+// Previews stop here; long files are usually whole songs in one take
+const MAX_SECONDS: f64 = 300.0;
+// Samples rendered per call into the synthesizer
+const BLOCK: usize = 4096;
+
+#[derive(serde::Serialize)]
+pub struct MidiRender {
+    path: String,
+    // The rendered WAV, when there is one
+    preview: Option<String>,
+    seconds: Option<f64>,
+    // True when an earlier render of the same file and SoundFont was reused
+    cached: bool,
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct MidiPreview {
+    path: String,
+    preview: String,
+    sample_rate: u32,
+    seconds: f64,
+}
+
+fn render_cache_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_cache_dir().map_err(|e| e.to_string())?.join("midi_renders");
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+fn check_rate(sample_rate: Option<u32>) -> Result<u32, String> {
+    let rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&rate) {
+        return Err(format!("Sample rate has to be between {} and {} Hz", MIN_SAMPLE_RATE, MAX_SAMPLE_RATE));
+    }
+    Ok(rate)
+}
+
+fn load_soundfont(path: &str) -> Result<Arc<SoundFont>, String> {
+    let file = File::open(paths::to_fs(path)).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let sound_font = SoundFont::new(&mut BufReader::new(file)).map_err(|e| format!("Failed to load SoundFont: {}", e))?;
+    Ok(Arc::new(sound_font))
+}
+
+// Plays the whole file through the SoundFont into stereo samples
+fn synthesize(path: &str, sound_font: &Arc<SoundFont>, sample_rate: u32) -> Result<AudioData, String> {
+    let file = File::open(paths::to_fs(path)).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let midi_file = MidiFile::new(&mut BufReader::new(file)).map_err(|e| format!("Failed to parse MIDI file: {}", e))?;
+    let midi_file = Arc::new(midi_file);
+    let settings = SynthesizerSettings::new(sample_rate as i32);
+    let synthesizer = Synthesizer::new(sound_font, &settings).map_err(|e| format!("Failed to start synthesizer: {}", e))?;
+    let mut sequencer = MidiFileSequencer::new(synthesizer);
+    sequencer.play(&midi_file, false);
+
+    let seconds = (midi_file.get_length() + TAIL_SECONDS).min(MAX_SECONDS);
+    let frames = (seconds * sample_rate as f64) as usize;
+    let mut samples = Vec::with_capacity(frames * 2);
+    let mut left = vec![0.0f32; BLOCK];
+    let mut right = vec![0.0f32; BLOCK];
+    let mut done = 0;
+    while done < frames {
+        let count = BLOCK.min(frames - done);
+        sequencer.render(&mut left[..count], &mut right[..count]);
+        for i in 0..count {
+            samples.push(left[i]);
+            samples.push(right[i]);
+        }
+        done += count;
+    }
+    Ok(AudioData { sample_rate, channels: 2, samples })
+}
+
+fn cached_render(db: &Db, midi_hash: &str, soundfont_hash: &str, sample_rate: u32) -> Result<Option<(String, f64)>, String> {
+    let cached: Option<(String, f64)> = db
+        .lock()
+        .query_row(
+            "SELECT output, seconds FROM midi_renders WHERE midi_hash = ?1 AND soundfont_hash = ?2 AND sample_rate = ?3",
+            params![midi_hash, soundfont_hash, sample_rate],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    // A cleared cache folder leaves rows behind; those render again
+    Ok(cached.filter(|(output, _)| paths::to_fs(output).exists()))
+}
+
+fn render_one(
+    db: &Db,
+    dir: &Path,
+    path: &str,
+    midi_hash: &str,
+    sound_font: &Arc<SoundFont>,
+    soundfont_hash: &str,
+    sample_rate: u32,
+) -> Result<(String, f64), String> {
+    let audio = quarantine::guard(db, path, || synthesize(path, sound_font, sample_rate))?;
+    let seconds = audio.samples.len() as f64 / 2.0 / sample_rate as f64;
+    let out = dir.join(format!("{}_{}_{}.wav", midi_hash, &soundfont_hash[..16], sample_rate));
+    audio::write_wav(&out, &audio)?;
+    let output = paths::display(&out);
+    db.lock()
+        .execute(
+            "INSERT OR REPLACE INTO midi_renders (midi_hash, soundfont_hash, sample_rate, output, seconds, rendered_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![midi_hash, soundfont_hash, sample_rate, output, seconds, db::now()],
+        )
+        .map_err(|e| e.to_string())?;
+    Ok((output, seconds))
+}
+
+// Renders each file, or hands back the earlier render of the same contents
+// through the same SoundFont at the same rate
+fn render_all(ctx: &JobContext, db: &Db, dir: &Path, files: Vec<String>, soundfont: &str, sample_rate: u32) -> Result<Vec<MidiRender>, String> {
+    let soundfont_hash = hashes::content_hash(db, soundfont)?;
+    // Loaded on first use, so a batch that's all cached never reads it
+    let mut sound_font: Option<Arc<SoundFont>> = None;
+    let total = files.len().max(1) as f32;
+    let mut renders = Vec::new();
+    for (i, path) in files.into_iter().enumerate() {
+        if ctx.is_cancelled() {
+            break;
+        }
+        ctx.progress(i as f32 / total, Some(path.clone()));
+        let outcome = hashes::content_hash(db, &path).and_then(|midi_hash| {
+            if let Some((output, seconds)) = cached_render(db, &midi_hash, &soundfont_hash, sample_rate)? {
+                return Ok((output, seconds, true));
+            }
+            let loaded = match sound_font.clone() {
+                Some(loaded) => loaded,
+                None => sound_font.insert(load_soundfont(soundfont)?).clone(),
+            };
+            let (output, seconds) = render_one(db, dir, &path, &midi_hash, &loaded, &soundfont_hash, sample_rate)?;
+            Ok((output, seconds, false))
+        });
+        renders.push(match outcome {
+            Ok((output, seconds, cached)) => MidiRender { path, preview: Some(output), seconds: Some(seconds), cached, error: None },
+            Err(e) => MidiRender { path, preview: None, seconds: None, cached: false, error: Some(e) },
+        });
+    }
+    Ok(renders)
+}
+
+// Renders a MIDI file to a WAV preview through a SoundFont (.sf2). Renders
+// are kept by file contents, SoundFont and sample rate, so asking again is
+// instant.
+#[tauri::command]
+pub async fn render_midi_preview(
+    app: AppHandle,
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    soundfont: String,
+    sample_rate: Option<u32>,
+) -> Result<MidiRender, String> {
+    let sample_rate = check_rate(sample_rate)?;
+    let dir = render_cache_dir(&app)?;
+    let db = db.inner().clone();
+    let label = format!("Render {}", path);
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |ctx| {
+            let mut renders = render_all(ctx, &db, &dir, vec![path], &soundfont, sample_rate)?;
+            let render = renders.pop().ok_or("Render was cancelled")?;
+            match render.error {
+                Some(e) => Err(e),
+                None => Ok(render),
+            }
+        })
+        .await
+}
+
+// Renders every MIDI file in a collection through a SoundFont, skipping ones
+// already rendered with it. Failures are reported per file.
+#[tauri::command]
+pub async fn render_collection_previews(
+    app: AppHandle,
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    collection_id: i64,
+    soundfont: String,
+    sample_rate: Option<u32>,
+) -> Result<Vec<MidiRender>, String> {
+    let sample_rate = check_rate(sample_rate)?;
+    let dir = render_cache_dir(&app)?;
+    let files: Vec<String> = collections::items(&db.lock(), collection_id)?
+        .into_iter()
+        .filter(|path| scanner::file_type_for(&paths::to_fs(path)) == Some("midi"))
+        .collect();
+    if files.is_empty() {
+        return Err("That collection has no MIDI files".to_string());
+    }
+    let db = db.inner().clone();
+    let label = format!("Render {} MIDI files", files.len());
+    scheduler
+        .run("conversion", label, Priority::Background, move |ctx| render_all(ctx, &db, &dir, files, &soundfont, sample_rate))
+        .await
+}
+
+// The newest audio preview of each MIDI file that has one, through
+// `soundfont` when given or any SoundFont otherwise
+#[tauri::command]
+pub async fn get_midi_previews(db: State<'_, Db>, files: Vec<String>, soundfont: Option<String>) -> Result<Vec<MidiPreview>, String> {
+    let db = db.inner().clone();
+    // Hashing reads every file that isn't cached yet
+    tokio::task::spawn_blocking(move || {
+        let soundfont_hash = match &soundfont {
+            Some(soundfont) => Some(hashes::content_hash(&db, soundfont)?),
+            None => None,
+        };
+        let mut previews = Vec::new();
+        for path in files {
+            // Files that can't be read have no preview to show
+            let Ok(midi_hash) = hashes::content_hash(&db, &path) else { continue };
+            let found: Option<(String, u32, f64)> = db
+                .lock()
+                .query_row(
+                    "SELECT output, sample_rate, seconds FROM midi_renders
+                     WHERE midi_hash = ?1 AND (?2 IS NULL OR soundfont_hash = ?2)
+                     ORDER BY rendered_at DESC LIMIT 1",
+                    params![midi_hash, soundfont_hash],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()
+                .map_err(|e| e.to_string())?;
+            if let Some((preview, sample_rate, seconds)) = found {
+                if paths::to_fs(&preview).exists() {
+                    previews.push(MidiPreview { path, preview, sample_rate, seconds });
+                }
+            }
+        }
+        Ok(previews)
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,233 @@
+use crate::audio;
+use crate::db::Db;
+use crate::dsp::Biquad;
+use crate::jobs::{Priority, Scheduler};
+use crate::journal::{self, Operation};
+use crate::library;
+use crate::paths;
+use crate::quarantine;
+use tauri::State;
+
+// Resolution of the correlation-over-time curve
+const WINDOW_SECONDS: f64 = 0.2;
+// Below here a club system's mono sub sees it; out of phase bass cancels
+const LOW_HZ: f64 = 120.0;
+// Windows quieter than this, relative to the loudest, don't count
+const SILENCE_DB: f32 = -40.0;
+// Correlation under this is treated as out of phase
+const OUT_OF_PHASE: f32 = -0.2;
+// Summing to mono losing more than this much level is audible as a hole
+const MAX_FOLD_LOSS_DB: f32 = 3.0;
+// More than this share of out-of-phase windows fails the check
+const MAX_OUT_OF_PHASE_SHARE: f32 = 0.1;
+const UNSAFE_TAG: &str = "mono-unsafe";
+
+#[derive(serde::Serialize)]
+pub struct StereoWindow {
+    time: f64,
+    // -1 out of phase, 0 unrelated, 1 identical
+    correlation: f32,
+    // Side over mid plus side energy
+    side: f32,
+    // Level lost summing this window to mono, dB
+    fold_loss_db: f32,
+}
+
+#[derive(serde::Serialize)]
+pub struct StereoReport {
+    path: String,
+    // False for mono files; everything else is then trivially safe
+    stereo: bool,
+    correlation: f32,
+    // Correlation of the bass alone, where phase problems hurt most
+    low_correlation: f32,
+    side: f32,
+    fold_loss_db: f32,
+    out_of_phase_seconds: f64,
+    mono_compatible: bool,
+    warnings: Vec<String>,
+    windows: Vec<StereoWindow>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Sums {
+    ll: f64,
+    rr: f64,
+    lr: f64,
+}
+
+impl Sums {
+    fn add(&mut self, l: f64, r: f64) {
+        self.ll += l * l;
+        self.rr += r * r;
+        self.lr += l * r;
+    }
+
+    fn correlation(&self) -> f32 {
+        if self.ll <= 0.0 || self.rr <= 0.0 {
+            return 1.0;
+        }
+        (self.lr / (self.ll * self.rr).sqrt()) as f32
+    }
+
+    // (L+R)/2 energy against the average of L and R on their own
+    fn fold_loss_db(&self) -> f32 {
+        let mono = (self.ll + self.rr + 2.0 * self.lr) / 4.0;
+        let average = (self.ll + self.rr) / 2.0;
+        if average <= 0.0 {
+            return 0.0;
+        }
+        (10.0 * (average / mono.max(1e-12)).log10()) as f32
+    }
+
+    fn side(&self) -> f32 {
+        let mid = self.ll + self.rr + 2.0 * self.lr;
+        let side = self.ll + self.rr - 2.0 * self.lr;
+        if mid + side <= 0.0 {
+            0.0
+        } else {
+            (side / (mid + side)) as f32
+        }
+    }
+
+    fn power(&self) -> f64 {
+        self.ll + self.rr
+    }
+}
+
+pub fn analyze(path: &str) -> Result<StereoReport, String> {
+    let mut total = Sums::default();
+    let mut low_total = Sums::default();
+    let mut windows: Vec<(Sums, u64)> = Vec::new();
+    let mut current = Sums::default();
+    let mut current_frames = 0u64;
+    let mut low: Option<(u32, Biquad, Biquad)> = None;
+    let mut stereo = false;
+    let mut rate = 0u32;
+
+    audio::stream_file(&paths::to_fs(path), |block, block_rate, channels| {
+        if channels < 2 {
+            return false;
+        }
+        stereo = true;
+        rate = block_rate;
+        if low.as_ref().map(|(r, _, _)| *r != block_rate).unwrap_or(true) {
+            low = Some((block_rate, Biquad::low_pass(block_rate, LOW_HZ, 0.707), Biquad::low_pass(block_rate, LOW_HZ, 0.707)));
+        }
+        let window = ((block_rate as f64 * WINDOW_SECONDS) as u64).max(1);
+        for frame in block.chunks(channels) {
+            let (l, r) = (frame[0], frame[1]);
+            total.add(l as f64, r as f64);
+            current.add(l as f64, r as f64);
+            if let Some((_, low_l, low_r)) = low.as_mut() {
+                low_total.add(low_l.process(l) as f64, low_r.process(r) as f64);
+            }
+            current_frames += 1;
+            if current_frames >= window {
+                windows.push((current, current_frames));
+                current = Sums::default();
+                current_frames = 0;
+            }
+        }
+        true
+    })?;
+    if current_frames > 0 {
+        windows.push((current, current_frames));
+    }
+
+    if !stereo {
+        return Ok(StereoReport {
+            path: path.to_string(),
+            stereo: false,
+            correlation: 1.0,
+            low_correlation: 1.0,
+            side: 0.0,
+            fold_loss_db: 0.0,
+            out_of_phase_seconds: 0.0,
+            mono_compatible: true,
+            warnings: Vec::new(),
+            windows: Vec::new(),
+        });
+    }
+
+    let loudest = windows.iter().map(|(s, frames)| s.power() / *frames as f64).fold(0.0f64, f64::max);
+    let gate = loudest * 10f64.powf(SILENCE_DB as f64 / 10.0);
+    let mut out_of_phase_seconds = 0.0;
+    let mut audible_seconds = 0.0;
+    let mut time = 0.0;
+    let mut curve = Vec::with_capacity(windows.len());
+    for (sums, frames) in &windows {
+        let seconds = *frames as f64 / rate.max(1) as f64;
+        if sums.power() / *frames as f64 >= gate {
+            audible_seconds += seconds;
+            if sums.correlation() < OUT_OF_PHASE {
+                out_of_phase_seconds += seconds;
+            }
+        }
+        curve.push(StereoWindow {
+            time,
+            correlation: sums.correlation(),
+            side: sums.side(),
+            fold_loss_db: sums.fold_loss_db(),
+        });
+        time += seconds;
+    }
+
+    let mut warnings = Vec::new();
+    let fold_loss_db = total.fold_loss_db();
+    if fold_loss_db > MAX_FOLD_LOSS_DB {
+        warnings.push(format!("Loses {:.1} dB when summed to mono", fold_loss_db));
+    }
+    let low_correlation = low_total.correlation();
+    if low_correlation < 0.0 {
+        warnings.push("Bass is out of phase between left and right and will cancel on a mono sub".to_string());
+    }
+    let share = (out_of_phase_seconds / audible_seconds.max(1e-9)) as f32;
+    if share > MAX_OUT_OF_PHASE_SHARE {
+        warnings.push(format!("Out of phase for {:.0}% of its length", share * 100.0));
+    }
+
+    Ok(StereoReport {
+        path: path.to_string(),
+        stereo: true,
+        correlation: total.correlation(),
+        low_correlation,
+        side: total.side(),
+        fold_loss_db,
+        out_of_phase_seconds,
+        mono_compatible: warnings.is_empty(),
+        warnings,
+        windows: curve,
+    })
+}
+
+// Correlation over time, side energy and mono fold-down loss for `path`.
+// Files that would thin out or vanish on a mono club system are tagged
+// "mono-unsafe" (undoably); the tag comes off again once a file passes.
+#[tauri::command]
+pub async fn analyze_stereo(db: State<'_, Db>, scheduler: State<'_, Scheduler>, path: String) -> Result<StereoReport, String> {
+    let db = db.inner().clone();
+    let label = format!("Stereo check {}", path);
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |_| {
+            let report = quarantine::guard(&db, &path, || analyze(&path))?;
+
+            let conn = db.lock();
+            let tags = library::tags_for(&conn, &path)?;
+            let mut after: Vec<String> = tags.iter().filter(|t| *t != UNSAFE_TAG).cloned().collect();
+            if !report.mono_compatible {
+                after.push(UNSAFE_TAG.to_string());
+                after.sort();
+            }
+            if after != tags {
+                let label = if report.mono_compatible { "Clear mono warning on" } else { "Flag as mono-unsafe" };
+                journal::run(
+                    &conn,
+                    &format!("{} {}", label, path),
+                    vec![Operation::SetTags { path: path.clone(), before: tags, after }],
+                )?;
+            }
+            Ok(report)
+        })
+        .await
+}
@@ -0,0 +1,274 @@
+use crate::audio::AudioData;
+use crate::dsp;
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::f32::consts::TAU;
+
+// WSOLA grain length and how far each grain may slide to line up with the
+// previous one, in seconds. 40 ms grains keep drum transients mostly intact.
+const WSOLA_WINDOW: f64 = 0.04;
+const WSOLA_TOLERANCE: f64 = 0.01;
+// Correlation only looks at every nth sample; alignment barely suffers
+const WSOLA_STRIDE: usize = 4;
+// Phase vocoder frame; big enough to resolve bass notes, with 75% overlap
+const PV_FFT: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    // Time-domain overlap-add: quick, best on drums and percussive loops
+    Fast,
+    // Phase vocoder with phase locking: smoother on pads, vocals and chords
+    Quality,
+    // Tape-style speed change; pitch moves with tempo, no artifacts at all
+    Repitch,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Result<Algorithm, String> {
+        match name {
+            "fast" => Ok(Algorithm::Fast),
+            "quality" => Ok(Algorithm::Quality),
+            "repitch" => Ok(Algorithm::Repitch),
+            other => Err(format!("Unknown stretch algorithm: {}", other)),
+        }
+    }
+}
+
+fn deinterleave(audio: &AudioData) -> Vec<Vec<f32>> {
+    let channels = audio.channels.max(1);
+    (0..channels)
+        .map(|c| audio.samples.iter().skip(c).step_by(channels).copied().collect())
+        .collect()
+}
+
+fn interleave(channels: &[Vec<f32>]) -> Vec<f32> {
+    let frames = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    let mut samples = Vec::with_capacity(frames * channels.len());
+    for i in 0..frames {
+        for channel in channels {
+            samples.push(channel[i]);
+        }
+    }
+    samples
+}
+
+// Four-point Hermite interpolation at a fractional position
+fn hermite(signal: &[f32], pos: f64) -> f32 {
+    let at = |i: i64| -> f32 {
+        if i < 0 {
+            0.0
+        } else {
+            signal.get(i as usize).copied().unwrap_or(0.0)
+        }
+    };
+    let index = pos.floor() as i64;
+    let t = (pos - index as f64) as f32;
+    let (y0, y1, y2, y3) = (at(index - 1), at(index), at(index + 1), at(index + 2));
+    let c1 = 0.5 * (y2 - y0);
+    let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+    let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+    ((c3 * t + c2) * t + c1) * t + y1
+}
+
+// Plays `signal` `speed` times faster, moving pitch along with tempo
+pub fn varispeed(signal: &[f32], speed: f64) -> Vec<f32> {
+    let out_len = (signal.len() as f64 / speed).round() as usize;
+    (0..out_len).map(|i| hermite(signal, i as f64 * speed)).collect()
+}
+
+// Waveform-similarity overlap-add. Every grain is cut from near where the
+// tempo says it should come from, nudged to wherever it best continues the
+// previous grain so the overlap doesn't phase-cancel. All channels share
+// the nudges, which keeps the stereo image stable.
+fn wsola(channels: &[Vec<f32>], ratio: f64, sample_rate: u32) -> Vec<Vec<f32>> {
+    let len = channels.iter().map(|c| c.len()).min().unwrap_or(0);
+    let n = (((sample_rate as f64 * WSOLA_WINDOW) as usize).max(64)) & !1;
+    let hop_out = n / 2;
+    let hop_in = hop_out as f64 / ratio;
+    let tolerance = (sample_rate as f64 * WSOLA_TOLERANCE) as usize;
+    let out_len = (len as f64 * ratio).round() as usize;
+    let window = dsp::hann(n);
+    let mono: Vec<f32> = (0..len)
+        .map(|i| channels.iter().map(|c| c[i]).sum::<f32>() / channels.len().max(1) as f32)
+        .collect();
+    let at = |signal: &[f32], i: usize| signal.get(i).copied().unwrap_or(0.0);
+
+    let mut out = vec![vec![0.0f32; out_len + n]; channels.len()];
+    let mut norm = vec![0.0f32; out_len + n];
+    let mut previous: Option<usize> = None;
+    let mut grain = 0;
+    while grain * hop_out < out_len {
+        let nominal = (grain as f64 * hop_in).round() as usize;
+        let pos = match previous {
+            None => nominal,
+            Some(prev) => {
+                // What would follow the previous grain if nothing were stretched
+                let natural = prev + hop_out;
+                let score = |candidate: usize| -> f32 {
+                    (0..n).step_by(WSOLA_STRIDE).map(|i| at(&mono, natural + i) * at(&mono, candidate + i)).sum()
+                };
+                (nominal.saturating_sub(tolerance)..=nominal + tolerance)
+                    .map(|candidate| (candidate, score(candidate)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(candidate, _)| candidate)
+                    .unwrap_or(nominal)
+            }
+        };
+        let start = grain * hop_out;
+        for (target, source) in out.iter_mut().zip(channels) {
+            for (i, w) in window.iter().enumerate() {
+                target[start + i] += at(source, pos + i) * w;
+            }
+        }
+        for (weight, w) in norm[start..start + n].iter_mut().zip(&window) {
+            *weight += w;
+        }
+        previous = Some(pos);
+        grain += 1;
+    }
+
+    for channel in &mut out {
+        for (sample, weight) in channel.iter_mut().zip(&norm) {
+            if *weight > 1e-3 {
+                *sample /= weight;
+            }
+        }
+        channel.truncate(out_len);
+    }
+    out
+}
+
+fn wrap_phase(phase: f32) -> f32 {
+    phase - TAU * (phase / TAU).round()
+}
+
+// Phase vocoder with identity phase locking (Laroche & Dolson): peaks
+// advance at their measured frequency, and the bins around each peak keep
+// their phase relative to it, which avoids the usual "phasey" smear.
+fn phase_vocoder(signal: &[f32], ratio: f64) -> Vec<f32> {
+    let n = PV_FFT;
+    let bins = n / 2 + 1;
+    let hop_out = n / 4;
+    let hop_in = hop_out as f64 / ratio;
+    let out_len = (signal.len() as f64 * ratio).round() as usize;
+    let window = dsp::hann(n);
+    let mut planner = FftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(n);
+    let inverse = planner.plan_fft_inverse(n);
+
+    let mut out = vec![0.0f32; out_len + n];
+    let mut norm = vec![0.0f32; out_len + n];
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); n];
+    let mut previous_phase = vec![0.0f32; bins];
+    let mut synth_phase = vec![0.0f32; bins];
+    let mut previous_pos: Option<usize> = None;
+
+    let frames = out_len / hop_out + 1;
+    for frame in 0..frames {
+        let pos = (frame as f64 * hop_in).round() as usize;
+        for (i, slot) in buffer.iter_mut().enumerate() {
+            *slot = Complex::new(signal.get(pos + i).copied().unwrap_or(0.0) * window[i], 0.0);
+        }
+        forward.process(&mut buffer);
+        let magnitudes: Vec<f32> = buffer[..bins].iter().map(|c| c.norm()).collect();
+        let phases: Vec<f32> = buffer[..bins].iter().map(|c| c.arg()).collect();
+
+        match previous_pos {
+            None => synth_phase.copy_from_slice(&phases),
+            Some(prev) => {
+                let hop = (pos - prev).max(1) as f32;
+                let peaks: Vec<usize> = (1..bins - 1)
+                    .filter(|&k| magnitudes[k] > magnitudes[k - 1] && magnitudes[k] >= magnitudes[k + 1])
+                    .collect();
+                let advance = |k: usize| -> f32 {
+                    let omega = TAU * k as f32 / n as f32;
+                    let deviation = wrap_phase(phases[k] - previous_phase[k] - omega * hop);
+                    (omega + deviation / hop) * hop_out as f32
+                };
+                if peaks.is_empty() {
+                    for (k, phase) in synth_phase.iter_mut().enumerate() {
+                        *phase = wrap_phase(*phase + advance(k));
+                    }
+                } else {
+                    let peak_phase: Vec<f32> = peaks.iter().map(|&p| wrap_phase(synth_phase[p] + advance(p))).collect();
+                    let mut nearest = 0;
+                    for (k, phase) in synth_phase.iter_mut().enumerate() {
+                        // Bins belong to the closest peak
+                        while nearest + 1 < peaks.len() && peaks[nearest + 1] + peaks[nearest] <= 2 * k {
+                            nearest += 1;
+                        }
+                        let p = peaks[nearest];
+                        *phase = wrap_phase(peak_phase[nearest] + phases[k] - phases[p]);
+                    }
+                }
+            }
+        }
+        previous_phase = phases;
+        previous_pos = Some(pos);
+
+        for (slot, (magnitude, phase)) in buffer.iter_mut().zip(magnitudes.iter().zip(&synth_phase)) {
+            *slot = Complex::from_polar(*magnitude, *phase);
+        }
+        // Mirror image, so the inverse transform comes out real
+        for k in 1..n / 2 {
+            buffer[n - k] = buffer[k].conj();
+        }
+        inverse.process(&mut buffer);
+        let start = frame * hop_out;
+        for (i, w) in window.iter().enumerate() {
+            out[start + i] += buffer[i].re / n as f32 * w;
+            norm[start + i] += w * w;
+        }
+    }
+
+    for (sample, weight) in out.iter_mut().zip(&norm) {
+        if *weight > 1e-3 {
+            *sample /= weight;
+        }
+    }
+    out.truncate(out_len);
+    out
+}
+
+// Makes `audio` `ratio` times as long (2.0 = half speed). Fast and Quality
+// keep the pitch; Repitch lets it follow the speed like a turntable.
+pub fn time_stretch(audio: &AudioData, ratio: f64, algorithm: Algorithm) -> AudioData {
+    let channels = deinterleave(audio);
+    let stretched: Vec<Vec<f32>> = if (ratio - 1.0).abs() < 1e-6 {
+        channels
+    } else {
+        match algorithm {
+            Algorithm::Fast => wsola(&channels, ratio, audio.sample_rate),
+            Algorithm::Quality => channels.iter().map(|c| phase_vocoder(c, ratio)).collect(),
+            Algorithm::Repitch => channels.iter().map(|c| varispeed(c, 1.0 / ratio)).collect(),
+        }
+    };
+    AudioData { sample_rate: audio.sample_rate, channels: audio.channels.max(1), samples: interleave(&stretched) }
+}
+
+// Moves pitch by `semitones` and keeps the length: stretch by the pitch
+// ratio, then play the result back that much faster
+pub fn pitch_shift(audio: &AudioData, semitones: f64, algorithm: Algorithm) -> AudioData {
+    let ratio = 2f64.powf(semitones / 12.0);
+    let frames = audio.samples.len() / audio.channels.max(1);
+    let stretched = time_stretch(audio, ratio, algorithm);
+    let shifted: Vec<Vec<f32>> = deinterleave(&stretched)
+        .iter()
+        .map(|channel| {
+            let mut resampled = varispeed(channel, ratio);
+            resampled.resize(frames, 0.0);
+            resampled
+        })
+        .collect();
+    AudioData { sample_rate: audio.sample_rate, channels: audio.channels.max(1), samples: interleave(&shifted) }
+}
+
+// Keeps clipping out of files a stretch pushed over full scale; phase
+// vocoding can add a dB or so on transients
+pub fn limit_peaks(audio: &mut AudioData) {
+    let peak = audio.samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    if peak > 1.0 {
+        let gain = 0.999 / peak;
+        audio.samples.iter_mut().for_each(|s| *s *= gain);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::ai::{self, ChatMessage, ChatRequest, ModelParams};
+use crate::conversations;
+use crate::db::Db;
+use crate::midi::{self, Note};
+use crate::paths;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use tauri::{AppHandle, Manager};
+
+const DEFAULT_ATTEMPTS: u32 = 3;
+const MAX_ATTEMPTS: u32 = 5;
+const MAX_NOTES: usize = 4096;
+const MAX_TAGS: usize = 32;
+// A generated pattern is a loop or a phrase; this keeps every note end well
+// inside the MIDI file's 28-bit tick deltas
+const MAX_BEATS: f64 = 4096.0;
+
+const MIDI_PATTERN_SCHEMA: &str = r#"{
+  "type": "object",
+  "required": ["bpm", "notes"],
+  "properties": {
+    "bpm": { "type": "number", "minimum": 20, "maximum": 400 },
+    "beats_per_bar": { "type": "integer", "minimum": 1, "maximum": 16 },
+    "notes": {
+      "type": "array", "minItems": 1,
+      "items": {
+        "type": "object",
+        "required": ["pitch", "velocity", "start", "duration"],
+        "properties": {
+          "pitch": { "type": "integer", "minimum": 0, "maximum": 127 },
+          "velocity": { "type": "integer", "minimum": 1, "maximum": 127 },
+          "start": { "type": "number", "minimum": 0, "maximum": 4096, "description": "beats" },
+          "duration": { "type": "number", "exclusiveMinimum": 0, "maximum": 4096, "description": "beats" },
+          "channel": { "type": "integer", "minimum": 0, "maximum": 15 }
+        }
+      }
+    }
+  }
+}"#;
+
+const TAG_LIST_SCHEMA: &str = r#"{
+  "type": "object",
+  "required": ["tags"],
+  "properties": {
+    "tags": { "type": "array", "minItems": 1, "items": { "type": "string", "minLength": 1, "maxLength": 48 } }
+  }
+}"#;
+
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKind {
+    MidiPattern,
+    TagList,
+}
+
+impl OutputKind {
+    fn schema(self) -> &'static str {
+        match self {
+            OutputKind::MidiPattern => MIDI_PATTERN_SCHEMA,
+            OutputKind::TagList => TAG_LIST_SCHEMA,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MidiPattern {
+    bpm: f64,
+    #[serde(default = "default_beats_per_bar")]
+    beats_per_bar: u8,
+    notes: Vec<Note>,
+}
+
+fn default_beats_per_bar() -> u8 {
+    4
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TagList {
+    tags: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct StructuredResult {
+    path: String,
+    attempts: u32,
+    value: Value,
+}
+
+// Models like to wrap JSON in ```json fences or a sentence of preamble
+fn extract_json(text: &str) -> &str {
+    let start = text.find(['{', '[']);
+    let end = text.rfind(['}', ']']);
+    match (start, end) {
+        (Some(start), Some(end)) if end > start => &text[start..=end],
+        _ => text.trim(),
+    }
+}
+
+fn check_pattern(pattern: &MidiPattern) -> Vec<String> {
+    let mut errors = Vec::new();
+    if !(20.0..=400.0).contains(&pattern.bpm) {
+        errors.push(format!("bpm {} is outside 20-400", pattern.bpm));
+    }
+    if !(1..=16).contains(&pattern.beats_per_bar) {
+        errors.push(format!("beats_per_bar {} is outside 1-16", pattern.beats_per_bar));
+    }
+    if pattern.notes.is_empty() {
+        errors.push("notes is empty".to_string());
+    }
+    if pattern.notes.len() > MAX_NOTES {
+        errors.push(format!("{} notes is more than the {} allowed", pattern.notes.len(), MAX_NOTES));
+    }
+    for (i, note) in pattern.notes.iter().enumerate() {
+        if note.pitch > 127 {
+            errors.push(format!("notes[{}].pitch {} is above 127", i, note.pitch));
+        }
+        if !(1..=127).contains(&note.velocity) {
+            errors.push(format!("notes[{}].velocity {} is outside 1-127", i, note.velocity));
+        }
+        if !note.start.is_finite() || note.start < 0.0 {
+            errors.push(format!("notes[{}].start must be a beat position >= 0", i));
+        }
+        if !note.duration.is_finite() || note.duration <= 0.0 {
+            errors.push(format!("notes[{}].duration must be greater than 0", i));
+        }
+        if note.start + note.duration > MAX_BEATS {
+            errors.push(format!("notes[{}] ends after beat {}, the longest a pattern can be", i, MAX_BEATS));
+        }
+        if note.channel > 15 {
+            errors.push(format!("notes[{}].channel {} is above 15", i, note.channel));
+        }
+    }
+    errors
+}
+
+fn check_tags(list: &TagList) -> Vec<String> {
+    let mut errors = Vec::new();
+    if list.tags.is_empty() {
+        errors.push("tags is empty".to_string());
+    }
+    if list.tags.len() > MAX_TAGS {
+        errors.push(format!("{} tags is more than the {} allowed", list.tags.len(), MAX_TAGS));
+    }
+    for (i, tag) in list.tags.iter().enumerate() {
+        let length = tag.trim().chars().count();
+        if length == 0 || length > 48 {
+            errors.push(format!("tags[{}] must be 1-48 characters", i));
+        }
+    }
+    errors
+}
+
+// Parses and checks a reply against `kind`, returning the normalized value
+// or every problem found so the model can fix them all in one go
+pub fn validate(kind: OutputKind, text: &str) -> Result<Value, Vec<String>> {
+    let json = extract_json(text);
+    match kind {
+        OutputKind::MidiPattern => {
+            let pattern: MidiPattern = serde_json::from_str(json).map_err(|e| vec![e.to_string()])?;
+            let errors = check_pattern(&pattern);
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+            serde_json::to_value(pattern).map_err(|e| vec![e.to_string()])
+        }
+        OutputKind::TagList => {
+            let mut list: TagList = serde_json::from_str(json).map_err(|e| vec![e.to_string()])?;
+            let errors = check_tags(&list);
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+            list.tags = list.tags.iter().map(|t| t.trim().to_lowercase()).collect();
+            list.tags.sort();
+            list.tags.dedup();
+            serde_json::to_value(list).map_err(|e| vec![e.to_string()])
+        }
+    }
+}
+
+// Writes beside the destination and renames, so a failure never leaves a
+// half-written export behind
+fn write_output(kind: OutputKind, value: &Value, destination: &Path) -> Result<(), String> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let temp = destination.with_file_name(format!(".{}.part", paths::file_name(destination)));
+
+    let is_midi = destination
+        .extension()
+        .map(|e| matches!(e.to_string_lossy().to_lowercase().as_str(), "mid" | "midi"))
+        .unwrap_or(false);
+    let written = match kind {
+        OutputKind::MidiPattern if is_midi => {
+            let pattern: MidiPattern = serde_json::from_value(value.clone()).map_err(|e| e.to_string())?;
+            midi::write_notes(&temp, &pattern.notes, pattern.bpm, pattern.beats_per_bar)
+        }
+        _ => {
+            let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+            fs::write(&temp, json).map_err(|e| e.to_string())
+        }
+    };
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    fs::rename(&temp, destination).map_err(|e| format!("Failed to finalize file: {}", e))
+}
+
+// Asks the model for a MIDI pattern or tag list, checks the reply against
+// the schema and feeds any errors back for another try. Only a valid result
+// is written to `output_path` (.mid for patterns, JSON otherwise), and is
+// added to `conversation_id`'s history as an attachment when one is given.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn generate_structured(
+    app: AppHandle,
+    kind: OutputKind,
+    provider: String,
+    model: String,
+    api_key: String,
+    messages: Vec<ChatMessage>,
+    output_path: String,
+    temperature: Option<f32>,
+    max_attempts: Option<u32>,
+    conversation_id: Option<String>,
+) -> Result<StructuredResult, String> {
+    let max_attempts = max_attempts.unwrap_or(DEFAULT_ATTEMPTS).clamp(1, MAX_ATTEMPTS);
+    let mut request = ChatRequest {
+        provider,
+        model,
+        api_key,
+        messages,
+        params: ModelParams { temperature, ..ModelParams::default() },
+        conversation_id: None,
+        context_paths: Vec::new(),
+        bypass_cache: false,
+    };
+    request.messages.push(ChatMessage {
+        role: "system".to_string(),
+        content: format!("Reply with a single JSON object matching this JSON schema and nothing else:\n{}", kind.schema()),
+    });
+
+    let mut last_errors = Vec::new();
+    for attempt in 1..=max_attempts {
+        let reply = ai::run_chat(&app, &request).await?;
+        match validate(kind, &reply.text) {
+            Ok(value) => {
+                let destination = paths::to_fs(&output_path);
+                write_output(kind, &value, &destination)?;
+                let path = paths::display(&destination);
+                if let Some(conversation_id) = &conversation_id {
+                    let db = app.state::<Db>();
+                    conversations::add_message(&db.lock(), conversation_id, "assistant", "", &[path.clone()], Some(&reply.model))?;
+                }
+                return Ok(StructuredResult { path, attempts: attempt, value });
+            }
+            Err(errors) => {
+                request.messages.push(ChatMessage { role: "assistant".to_string(), content: reply.text });
+                request.messages.push(ChatMessage {
+                    role: "user".to_string(),
+                    content: format!(
+                        "That reply doesn't match the schema:\n- {}\nReply again with only the corrected JSON.",
+                        errors.join("\n- ")
+                    ),
+                });
+                last_errors = errors;
+            }
+        }
+    }
+    Err(format!(
+        "Model output was still invalid after {} attempts: {}",
+        max_attempts,
+        last_errors.join("; ")
+    ))
+}
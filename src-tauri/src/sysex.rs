@@ -0,0 +1,405 @@
+use crate::collections::safe_file_name;
+use crate::db::{self, Db};
+use crate::midi_ports;
+use crate::paths;
+use crate::settings;
+use midir::MidiInputConnection;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+
+const DEFAULT_FOLDER: &str = "Sysex";
+// Bounds memory; a bank dump from the biggest workstations is a few hundred KB
+const MAX_BYTES: usize = 16 * 1024 * 1024;
+// Synths drop messages sent faster than they can store them
+const DEFAULT_GAP_MS: u64 = 60;
+const MAX_GAP_MS: u64 = 2000;
+const START: u8 = 0xF0;
+const END: u8 = 0xF7;
+
+// Manufacturer IDs from the MIDI Association list, for the common makers
+const MANUFACTURERS: &[(&[u8], &str)] = &[
+    (&[0x01], "Sequential"),
+    (&[0x04], "Moog"),
+    (&[0x06], "Lexicon"),
+    (&[0x10], "Oberheim"),
+    (&[0x18], "E-mu"),
+    (&[0x3E], "Waldorf"),
+    (&[0x40], "Kawai"),
+    (&[0x41], "Roland"),
+    (&[0x42], "Korg"),
+    (&[0x43], "Yamaha"),
+    (&[0x44], "Casio"),
+    (&[0x47], "Akai"),
+    (&[0x00, 0x01, 0x05], "Sequential"),
+    (&[0x00, 0x20, 0x29], "Novation"),
+    (&[0x00, 0x20, 0x32], "Behringer"),
+    (&[0x00, 0x20, 0x33], "Access"),
+    (&[0x00, 0x20, 0x3C], "Elektron"),
+    (&[0x00, 0x20, 0x6B], "Arturia"),
+    (&[0x7E], "Universal"),
+    (&[0x7F], "Universal"),
+];
+
+// Whole messages, and the one still arriving when a dump is split across
+// callbacks
+#[derive(Default)]
+struct Received {
+    messages: Vec<Vec<u8>>,
+    partial: Vec<u8>,
+    // Set once the dump passed MAX_BYTES; nothing after that is kept
+    overflowed: bool,
+}
+
+struct Receiving {
+    port: String,
+    _connection: MidiInputConnection<()>,
+    // The MIDI thread only sends bytes over; they're assembled here when
+    // status is asked for, so the callback never waits on a lock
+    incoming: Receiver<Vec<u8>>,
+    received: Received,
+}
+
+impl Receiving {
+    fn drain(&mut self) {
+        while let Ok(bytes) = self.incoming.try_recv() {
+            if !self.received.overflowed && collect(&mut self.received, &bytes).is_err() {
+                self.received.overflowed = true;
+            }
+        }
+    }
+}
+
+// The input being listened on for patch dumps, if any
+#[derive(Default)]
+pub struct SysexLibrarian {
+    receiving: Mutex<Option<Receiving>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SysexStatus {
+    receiving: bool,
+    port: Option<String>,
+    messages: usize,
+    bytes: usize,
+    // The dump went past the size limit and can't be saved
+    overflowed: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct SysexDump {
+    id: i64,
+    name: String,
+    model: Option<String>,
+    manufacturer: Option<String>,
+    tags: Vec<String>,
+    path: String,
+    messages: usize,
+    bytes: usize,
+    created_at: i64,
+}
+
+impl SysexLibrarian {
+    fn lock(&self) -> MutexGuard<'_, Option<Receiving>> {
+        self.receiving.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn status(&self) -> SysexStatus {
+        match self.lock().as_mut() {
+            Some(r) => {
+                r.drain();
+                SysexStatus {
+                    receiving: true,
+                    port: Some(r.port.clone()),
+                    messages: r.received.messages.len(),
+                    bytes: r.received.messages.iter().map(Vec::len).sum(),
+                    overflowed: r.received.overflowed,
+                }
+            }
+            None => SysexStatus { receiving: false, port: None, messages: 0, bytes: 0, overflowed: false },
+        }
+    }
+}
+
+fn too_large() -> String {
+    format!("The dump is over {} MB, more than a sysex dump should be", MAX_BYTES / (1024 * 1024))
+}
+
+// Appends a callback's bytes, closing off messages at each end byte.
+// Realtime bytes can arrive in the middle of a dump and are skipped.
+fn collect(received: &mut Received, bytes: &[u8]) -> Result<(), String> {
+    let Received { messages, partial, .. } = received;
+    let total: usize = messages.iter().map(Vec::len).sum::<usize>() + partial.len();
+    if total + bytes.len() > MAX_BYTES {
+        return Err(too_large());
+    }
+    for byte in bytes {
+        match *byte {
+            START => {
+                partial.clear();
+                partial.push(START);
+            }
+            END if !partial.is_empty() => {
+                partial.push(END);
+                messages.push(std::mem::take(partial));
+            }
+            0xF8..=0xFF => {}
+            byte if !partial.is_empty() => partial.push(byte),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// The messages of a saved .syx file
+fn split(bytes: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut received = Received::default();
+    collect(&mut received, bytes)?;
+    Ok(received.messages)
+}
+
+fn manufacturer(message: &[u8]) -> Option<String> {
+    let id = message.get(1..)?;
+    MANUFACTURERS.iter().find(|(prefix, _)| id.starts_with(prefix)).map(|(_, name)| name.to_string())
+}
+
+// Where dumps are kept: "sysex.folder", else a folder in app data
+fn sysex_folder(app: &AppHandle, conn: &Connection) -> Result<PathBuf, String> {
+    Ok(match settings::get::<String>(conn, "sysex.folder")? {
+        Some(folder) => paths::to_fs(&folder),
+        None => app.path().app_data_dir().map_err(|e| e.to_string())?.join(DEFAULT_FOLDER),
+    })
+}
+
+fn clean_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags.into_iter().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn set_tags(conn: &Connection, id: i64, tags: &[String]) -> Result<(), String> {
+    conn.execute("DELETE FROM sysex_tags WHERE dump_id = ?1", params![id]).map_err(|e| e.to_string())?;
+    for tag in tags {
+        conn.execute("INSERT INTO sysex_tags (dump_id, tag) VALUES (?1, ?2)", params![id, tag])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn load_dump(conn: &Connection, id: i64) -> Result<SysexDump, String> {
+    let mut dump = conn
+        .query_row(
+            "SELECT id, name, model, manufacturer, path, messages, bytes, created_at FROM sysex_dumps WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok(SysexDump {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    model: row.get(2)?,
+                    manufacturer: row.get(3)?,
+                    tags: Vec::new(),
+                    path: row.get(4)?,
+                    messages: row.get::<_, i64>(5)? as usize,
+                    bytes: row.get::<_, i64>(6)? as usize,
+                    created_at: row.get(7)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Sysex dump {} not found", id))?;
+    let mut stmt = conn
+        .prepare("SELECT tag FROM sysex_tags WHERE dump_id = ?1 ORDER BY tag")
+        .map_err(|e| e.to_string())?;
+    dump.tags = stmt
+        .query_map(params![id], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(dump)
+}
+
+// Starts listening on `port` for patch dumps. Send the dump from the synth,
+// then save it with save_sysex_dump.
+#[tauri::command]
+pub async fn start_sysex_receive(librarian: State<'_, SysexLibrarian>, port: String) -> Result<SysexStatus, String> {
+    let (sender, incoming) = mpsc::channel();
+    let connection = midi_ports::connect_sysex_input(&port, move |bytes| {
+        let _ = sender.send(bytes.to_vec());
+    })?;
+    *librarian.lock() = Some(Receiving { port, _connection: connection, incoming, received: Received::default() });
+    Ok(librarian.status())
+}
+
+#[tauri::command]
+pub async fn get_sysex_status(librarian: State<'_, SysexLibrarian>) -> Result<SysexStatus, String> {
+    Ok(librarian.status())
+}
+
+// Stops listening and throws away anything received
+#[tauri::command]
+pub async fn stop_sysex_receive(librarian: State<'_, SysexLibrarian>) -> Result<(), String> {
+    *librarian.lock() = None;
+    Ok(())
+}
+
+// Stops listening and keeps what was received as a .syx file in the sysex
+// folder, cataloged under `model` (the synth it came from) and `tags`
+#[tauri::command]
+pub async fn save_sysex_dump(
+    app: AppHandle,
+    db: State<'_, Db>,
+    librarian: State<'_, SysexLibrarian>,
+    name: String,
+    model: Option<String>,
+    tags: Vec<String>,
+) -> Result<SysexDump, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Give the dump a name".to_string());
+    }
+    let status = librarian.status();
+    if status.overflowed {
+        return Err(too_large());
+    }
+    if status.messages == 0 {
+        return Err("Nothing was received; send the dump from the synth first".to_string());
+    }
+    let mut receiving = librarian.lock().take().ok_or_else(|| "Not receiving sysex".to_string())?;
+    receiving.drain();
+    if receiving.received.overflowed {
+        return Err(too_large());
+    }
+    let messages = std::mem::take(&mut receiving.received.messages);
+    drop(receiving);
+    let model = model.map(|m| m.trim().to_string()).filter(|m| !m.is_empty());
+    let bytes: Vec<u8> = messages.concat();
+
+    let conn = db.lock();
+    let folder = sysex_folder(&app, &conn)?;
+    fs::create_dir_all(&folder).map_err(|e| format!("Failed to create {}: {}", folder.display(), e))?;
+    let stem = safe_file_name(&match &model {
+        Some(model) => format!("{} - {}", model, name),
+        None => name.clone(),
+    });
+    let mut output = folder.join(format!("{}.syx", stem));
+    let mut n = 2;
+    while output.exists() {
+        output = folder.join(format!("{} {}.syx", stem, n));
+        n += 1;
+    }
+    fs::write(&output, &bytes).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+
+    conn.execute(
+        "INSERT INTO sysex_dumps (name, model, manufacturer, path, messages, bytes, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            name,
+            model,
+            manufacturer(&messages[0]),
+            paths::display(&output),
+            messages.len() as i64,
+            bytes.len() as i64,
+            db::now()
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    let id = conn.last_insert_rowid();
+    set_tags(&conn, id, &clean_tags(tags))?;
+    load_dump(&conn, id)
+}
+
+// Cataloged dumps, newest first, narrowed to a synth model or tag
+#[tauri::command]
+pub async fn list_sysex_dumps(db: State<'_, Db>, model: Option<String>, tag: Option<String>) -> Result<Vec<SysexDump>, String> {
+    let conn = db.lock();
+    let ids: Vec<i64> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id FROM sysex_dumps
+                 WHERE (?1 IS NULL OR model = ?1 COLLATE NOCASE)
+                   AND (?2 IS NULL OR id IN (SELECT dump_id FROM sysex_tags WHERE tag = ?2))
+                 ORDER BY created_at DESC",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![model, tag.map(|t| t.trim().to_lowercase())], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        rows
+    };
+    ids.into_iter().map(|id| load_dump(&conn, id)).collect()
+}
+
+// Renames a dump or changes its model and tags
+#[tauri::command]
+pub async fn update_sysex_dump(
+    db: State<'_, Db>,
+    id: i64,
+    name: Option<String>,
+    model: Option<String>,
+    tags: Option<Vec<String>>,
+) -> Result<SysexDump, String> {
+    let conn = db.lock();
+    load_dump(&conn, id)?;
+    if let Some(name) = name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()) {
+        conn.execute("UPDATE sysex_dumps SET name = ?1 WHERE id = ?2", params![name, id])
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(model) = model {
+        let model = Some(model.trim().to_string()).filter(|m| !m.is_empty());
+        conn.execute("UPDATE sysex_dumps SET model = ?1 WHERE id = ?2", params![model, id])
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(tags) = tags {
+        set_tags(&conn, id, &clean_tags(tags))?;
+    }
+    load_dump(&conn, id)
+}
+
+// Drops a dump from the catalog, deleting its file too when asked
+#[tauri::command]
+pub async fn delete_sysex_dump(db: State<'_, Db>, id: i64, delete_file: Option<bool>) -> Result<(), String> {
+    let conn = db.lock();
+    let dump = load_dump(&conn, id)?;
+    if delete_file.unwrap_or(false) {
+        let path = paths::to_fs(&dump.path);
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| format!("Failed to delete {}: {}", dump.path, e))?;
+        }
+    }
+    conn.execute("DELETE FROM sysex_dumps WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Sends a saved dump back to the synth on `port`, one message at a time
+// with `gap_ms` (default 60) between them so it can keep up
+#[tauri::command]
+pub async fn send_sysex_dump(db: State<'_, Db>, id: i64, port: String, gap_ms: Option<u64>) -> Result<usize, String> {
+    let dump = load_dump(&db.lock(), id)?;
+    let gap = Duration::from_millis(gap_ms.unwrap_or(DEFAULT_GAP_MS).min(MAX_GAP_MS));
+    tokio::task::spawn_blocking(move || {
+        let bytes = fs::read(paths::to_fs(&dump.path)).map_err(|e| format!("Failed to read {}: {}", dump.path, e))?;
+        let messages = split(&bytes)?;
+        if messages.is_empty() {
+            return Err(format!("{} has no sysex messages", dump.path));
+        }
+        let mut connection = midi_ports::connect_output(&port)?;
+        for (i, message) in messages.iter().enumerate() {
+            if i > 0 {
+                std::thread::sleep(gap);
+            }
+            connection.send(message).map_err(|e| format!("Failed to send to {}: {}", port, e))?;
+        }
+        Ok(messages.len())
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,453 @@
+use crate::audio_config::{self, StreamInfo};
+use crate::collections::safe_file_name;
+use crate::db::{self, Db};
+use crate::input::{self, InputStream};
+use crate::library;
+use crate::paths;
+use crate::roots;
+use crate::scanner;
+use crate::settings;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+
+// Where new lanes get their folder when "recording.folder" isn't set
+const DEFAULT_FOLDER: &str = "Recordings";
+// Samples buffered between the input and the WAV writer: about five seconds
+// of 8 channels at 48 kHz
+const FIFO_SAMPLES: usize = 1 << 21;
+// How long the writer sleeps when it has caught up with the input
+const WRITER_POLL: Duration = Duration::from_millis(10);
+
+// Hands the input's samples to the WAV writer without allocating or locking
+// in the audio callback. One writer, one reader; a block that doesn't fit
+// is dropped and noted rather than waited for.
+struct Fifo {
+    samples: Box<[AtomicU32]>,
+    // Samples pushed and taken since the start
+    written: AtomicUsize,
+    read: AtomicUsize,
+    overrun: AtomicBool,
+    done: AtomicBool,
+}
+
+impl Fifo {
+    fn new(len: usize) -> Fifo {
+        Fifo {
+            samples: (0..len).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            overrun: AtomicBool::new(false),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, block: &[f32]) {
+        let len = self.samples.len();
+        let written = self.written.load(Ordering::Relaxed);
+        if written + block.len() - self.read.load(Ordering::Acquire) > len {
+            self.overrun.store(true, Ordering::Relaxed);
+            return;
+        }
+        for (i, sample) in block.iter().enumerate() {
+            self.samples[(written + i) % len].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written.store(written + block.len(), Ordering::Release);
+    }
+
+    // Everything pushed since the last call, in order
+    fn drain(&self, into: &mut Vec<f32>) {
+        let len = self.samples.len();
+        let (read, written) = (self.read.load(Ordering::Relaxed), self.written.load(Ordering::Acquire));
+        into.clear();
+        into.extend((read..written).map(|i| f32::from_bits(self.samples[i % len].load(Ordering::Relaxed))));
+        self.read.store(written, Ordering::Release);
+    }
+}
+
+struct ActiveTake {
+    lane_id: i64,
+    number: i64,
+    path: PathBuf,
+    stream: InputStream,
+    fifo: Arc<Fifo>,
+    // Drains the input into the WAV; returns the frames written
+    writer: JoinHandle<Result<u64, String>>,
+}
+
+// The take being recorded, if any. One at a time: there is one input.
+#[derive(Default)]
+pub struct Recorder {
+    active: Mutex<Option<ActiveTake>>,
+}
+
+impl Recorder {
+    // The input being recorded from, None when not recording
+    pub fn input_info(&self) -> Option<StreamInfo> {
+        self.active.lock().unwrap().as_ref().map(|take| take.stream.info())
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Take {
+    id: i64,
+    lane_id: i64,
+    number: i64,
+    path: String,
+    // Seconds
+    length: f64,
+    rating: Option<u8>,
+    rejected: bool,
+    keeper: bool,
+    created_at: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct TakeLane {
+    id: i64,
+    name: String,
+    folder: String,
+    keeper_take_id: Option<i64>,
+    created_at: i64,
+    takes: Vec<Take>,
+}
+
+#[derive(serde::Serialize)]
+pub struct RecordedTake {
+    #[serde(flatten)]
+    take: Take,
+    // The input dropped out (interface unplugged) before stop, or the disk
+    // fell behind and some audio was lost; the take holds what was saved
+    interrupted: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct RecordingStarted {
+    lane_id: i64,
+    number: i64,
+    path: String,
+    device: String,
+    sample_rate: u32,
+    channels: usize,
+}
+
+fn load_takes(conn: &Connection, lane_id: i64, keeper: Option<i64>) -> Result<Vec<Take>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, lane_id, number, path, length, rating, rejected, created_at
+             FROM takes WHERE lane_id = ?1 ORDER BY number",
+        )
+        .map_err(|e| e.to_string())?;
+    let takes = stmt
+        .query_map(params![lane_id], |row| {
+            let id: i64 = row.get(0)?;
+            Ok(Take {
+                id,
+                lane_id: row.get(1)?,
+                number: row.get(2)?,
+                path: row.get(3)?,
+                length: row.get(4)?,
+                rating: row.get(5)?,
+                rejected: row.get(6)?,
+                keeper: keeper == Some(id),
+                created_at: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(takes)
+}
+
+fn load_lane(conn: &Connection, id: i64) -> Result<TakeLane, String> {
+    let (name, folder, keeper_take_id, created_at): (String, String, Option<i64>, i64) = conn
+        .query_row(
+            "SELECT name, folder, keeper_take_id, created_at FROM take_lanes WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Take lane {} not found", id))?;
+    let takes = load_takes(conn, id, keeper_take_id)?;
+    Ok(TakeLane { id, name, folder, keeper_take_id, created_at, takes })
+}
+
+fn lane_of_take(conn: &Connection, take_id: i64) -> Result<(i64, String), String> {
+    conn.query_row("SELECT lane_id, path FROM takes WHERE id = ?1", params![take_id], |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Take {} not found", take_id))
+}
+
+// Where recordings go: "recording.folder", else a folder in app data
+pub fn recordings_folder(app: &AppHandle, conn: &Connection) -> Result<PathBuf, String> {
+    Ok(match settings::get::<String>(conn, "recording.folder")? {
+        Some(folder) => paths::to_fs(&folder),
+        None => app.path().app_data_dir().map_err(|e| e.to_string())?.join(DEFAULT_FOLDER),
+    })
+}
+
+fn create_lane(app: &AppHandle, conn: &Connection, name: &str) -> Result<i64, String> {
+    let base = recordings_folder(app, conn)?;
+    let mut folder = base.join(safe_file_name(name));
+    let mut n = 2;
+    // Two lanes with the same name get separate folders
+    while conn
+        .query_row("SELECT 1 FROM take_lanes WHERE folder = ?1", params![paths::display(&folder)], |_| Ok(()))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .is_some()
+    {
+        folder = base.join(format!("{} {}", safe_file_name(name), n));
+        n += 1;
+    }
+    conn.execute(
+        "INSERT INTO take_lanes (name, folder, created_at) VALUES (?1, ?2, ?3)",
+        params![name, paths::display(&folder), db::now()],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(conn.last_insert_rowid())
+}
+
+// The next take number in the lane and a file for it. Numbers keep
+// counting past deleted takes so "take 3" always means the same recording.
+fn next_take(conn: &Connection, lane_id: i64) -> Result<(i64, PathBuf), String> {
+    let (name, folder): (String, String) = conn
+        .query_row("SELECT name, folder FROM take_lanes WHERE id = ?1", params![lane_id], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Take lane {} not found", lane_id))?;
+    let folder = paths::to_fs(&folder);
+    fs::create_dir_all(&folder).map_err(|e| format!("Failed to create {}: {}", folder.display(), e))?;
+    let last: Option<i64> = conn
+        .query_row("SELECT MAX(number) FROM takes WHERE lane_id = ?1", params![lane_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let mut number = last.unwrap_or(0) + 1;
+    loop {
+        let path = folder.join(format!("{} take {}.wav", safe_file_name(&name), number));
+        if !path.exists() {
+            return Ok((number, path));
+        }
+        number += 1;
+    }
+}
+
+// Adds a freshly written recording to the library
+pub fn index(conn: &Connection, path: &Path) -> Result<(), String> {
+    if let Some(file) = scanner::scanned_file(path, false)? {
+        let root_id = roots::root_containing(conn, &file.path)?;
+        library::upsert_file(conn, root_id, &file, db::now())?;
+    }
+    Ok(())
+}
+
+// Starts recording the next take into `lane_id`, or into a new lane called
+// `lane_name`. `device` is an input name from list_input_devices.
+#[tauri::command]
+pub async fn start_take(
+    app: AppHandle,
+    db: State<'_, Db>,
+    recorder: State<'_, Recorder>,
+    lane_id: Option<i64>,
+    lane_name: Option<String>,
+    device: Option<String>,
+) -> Result<RecordingStarted, String> {
+    let mut active = recorder.active.lock().unwrap();
+    if active.is_some() {
+        return Err("Already recording".to_string());
+    }
+    let (lane_id, number, path, config) = {
+        let conn = db.lock();
+        let lane_id = match (lane_id, lane_name) {
+            (Some(id), _) => id,
+            (None, Some(name)) if !name.trim().is_empty() => create_lane(&app, &conn, name.trim())?,
+            _ => return Err("Pick a take lane or name a new one".to_string()),
+        };
+        let (number, path) = next_take(&conn, lane_id)?;
+        (lane_id, number, path, audio_config::load(&conn))
+    };
+
+    let fifo = Arc::new(Fifo::new(FIFO_SAMPLES));
+    let input = fifo.clone();
+    let stream = input::open(device.as_deref(), &config, move |block| input.push(block))?;
+    let spec = hound::WavSpec {
+        channels: stream.channels as u16,
+        sample_rate: stream.sample_rate,
+        bits_per_sample: 24,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut wav = hound::WavWriter::create(&path, spec).map_err(|e| format!("Failed to create WAV: {}", e))?;
+    let channels = stream.channels.max(1) as u64;
+    let output = fifo.clone();
+    let writer = std::thread::spawn(move || {
+        let scale = (1 << 23) as f32 - 1.0;
+        let mut samples = 0u64;
+        let mut block = Vec::new();
+        // Ends once stop_take has closed the input and the rest is written
+        loop {
+            let done = output.done.load(Ordering::Acquire);
+            output.drain(&mut block);
+            for sample in &block {
+                wav.write_sample((sample.clamp(-1.0, 1.0) * scale) as i32)
+                    .map_err(|e| format!("Failed to write WAV: {}", e))?;
+            }
+            samples += block.len() as u64;
+            if done {
+                break;
+            }
+            if block.is_empty() {
+                std::thread::sleep(WRITER_POLL);
+            }
+        }
+        wav.finalize().map_err(|e| format!("Failed to write WAV: {}", e))?;
+        Ok(samples / channels)
+    });
+
+    let started = RecordingStarted {
+        lane_id,
+        number,
+        path: paths::display(&path),
+        device: stream.device.clone(),
+        sample_rate: stream.sample_rate,
+        channels: stream.channels,
+    };
+    *active = Some(ActiveTake { lane_id, number, path, stream, fifo, writer });
+    Ok(started)
+}
+
+// Stops the take being recorded, adds it to its lane and the library
+#[tauri::command]
+pub async fn stop_take(db: State<'_, Db>, recorder: State<'_, Recorder>) -> Result<RecordedTake, String> {
+    let take = recorder.active.lock().unwrap().take().ok_or_else(|| "Not recording".to_string())?;
+    let ActiveTake { lane_id, number, path, stream, fifo, writer } = take;
+    let (sample_rate, failed) = (stream.sample_rate, stream.failed());
+    drop(stream);
+    fifo.done.store(true, Ordering::Release);
+    let frames = tokio::task::spawn_blocking(move || writer.join())
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+        .map_err(|_| "Recording writer panicked".to_string())??;
+
+    let length = frames as f64 / sample_rate.max(1) as f64;
+    let display = paths::display(&path);
+    let created_at = db::now();
+    let conn = db.lock();
+    conn.execute(
+        "INSERT INTO takes (lane_id, number, path, length, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![lane_id, number, display, length, created_at],
+    )
+    .map_err(|e| e.to_string())?;
+    let id = conn.last_insert_rowid();
+    index(&conn, &path)?;
+    let take = Take { id, lane_id, number, path: display, length, rating: None, rejected: false, keeper: false, created_at };
+    Ok(RecordedTake { take, interrupted: failed || fifo.overrun.load(Ordering::Relaxed) })
+}
+
+#[tauri::command]
+pub async fn list_take_lanes(db: State<'_, Db>) -> Result<Vec<TakeLane>, String> {
+    let conn = db.lock();
+    let ids = conn
+        .prepare("SELECT id FROM take_lanes ORDER BY created_at DESC")
+        .map_err(|e| e.to_string())?
+        .query_map([], |row| row.get::<_, i64>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    ids.into_iter().map(|id| load_lane(&conn, id)).collect()
+}
+
+#[tauri::command]
+pub async fn get_take_lane(db: State<'_, Db>, id: i64) -> Result<TakeLane, String> {
+    load_lane(&db.lock(), id)
+}
+
+// Sets a take's star rating (1-5, 0 to clear) and/or marks it a reject.
+// Whatever isn't given is left as it was.
+#[tauri::command]
+pub async fn rate_take(db: State<'_, Db>, id: i64, rating: Option<u8>, rejected: Option<bool>) -> Result<(), String> {
+    if rating.is_some_and(|r| r > 5) {
+        return Err("Ratings run from 1 to 5, or 0 to clear".to_string());
+    }
+    let conn = db.lock();
+    lane_of_take(&conn, id)?;
+    if let Some(rating) = rating {
+        let rating = Some(rating).filter(|r| *r > 0);
+        conn.execute("UPDATE takes SET rating = ?2 WHERE id = ?1", params![id, rating])
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(rejected) = rejected {
+        conn.execute("UPDATE takes SET rejected = ?2 WHERE id = ?1", params![id, rejected])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Makes a take its lane's keeper; a keeper is never a reject
+#[tauri::command]
+pub async fn promote_take(db: State<'_, Db>, id: i64) -> Result<TakeLane, String> {
+    let conn = db.lock();
+    let (lane_id, _) = lane_of_take(&conn, id)?;
+    conn.execute("UPDATE take_lanes SET keeper_take_id = ?2 WHERE id = ?1", params![lane_id, id])
+        .map_err(|e| e.to_string())?;
+    conn.execute("UPDATE takes SET rejected = 0 WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    load_lane(&conn, lane_id)
+}
+
+// Deletes the files of every take marked rejected in the lane and drops
+// them from the lane and the library. Returns the deleted paths.
+#[tauri::command]
+pub async fn delete_rejected_takes(db: State<'_, Db>, lane_id: i64) -> Result<Vec<String>, String> {
+    let conn = db.lock();
+    let lane = load_lane(&conn, lane_id)?;
+    let rejected: Vec<&Take> = lane.takes.iter().filter(|t| t.rejected && !t.keeper).collect();
+    // All or nothing: one take on a read-only root stops the lot
+    roots::ensure_writable(&conn, &rejected.iter().map(|t| t.path.as_str()).collect::<Vec<_>>())?;
+    let mut deleted = Vec::new();
+    for take in rejected {
+        match fs::remove_file(paths::to_fs(&take.path)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Failed to delete {}: {}", take.path, e)),
+        }
+        conn.execute("DELETE FROM takes WHERE id = ?1", params![take.id])
+            .map_err(|e| e.to_string())?;
+        library::remove_path(&conn, &take.path)?;
+        deleted.push(take.path.clone());
+    }
+    Ok(deleted)
+}
+
+// Copies the lane's keeper to `dest_dir` as "<lane name>.wav" and indexes it
+#[tauri::command]
+pub async fn export_keeper(db: State<'_, Db>, lane_id: i64, dest_dir: String) -> Result<String, String> {
+    let conn = db.lock();
+    let lane = load_lane(&conn, lane_id)?;
+    let keeper = lane
+        .takes
+        .iter()
+        .find(|t| t.keeper)
+        .ok_or_else(|| format!("{} has no keeper yet", lane.name))?;
+    let dir = paths::to_fs(&dest_dir);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let stem = safe_file_name(&lane.name);
+    let mut output = dir.join(format!("{}.wav", stem));
+    let mut n = 2;
+    while output.exists() {
+        output = dir.join(format!("{} {}.wav", stem, n));
+        n += 1;
+    }
+    fs::copy(paths::to_fs(&keeper.path), &output).map_err(|e| format!("Failed to export {}: {}", keeper.path, e))?;
+    index(&conn, &output)?;
+    Ok(paths::display(&output))
+}
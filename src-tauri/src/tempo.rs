@@ -0,0 +1,341 @@
+use crate::audio;
+use crate::db::{self, Db};
+use crate::dsp;
+use crate::jobs::{Priority, Scheduler};
+use crate::paths;
+use crate::quarantine;
+use rusqlite::{params, OptionalExtension};
+use std::path::Path;
+use tauri::State;
+
+pub const SAMPLE_RATE: u32 = 22_050;
+pub const N_FFT: usize = 2048;
+pub const HOP: usize = 512;
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+// Bins below ~150 Hz at 22.05 kHz / 2048; kicks mark the downbeat more often than not
+const LOW_BINS: usize = 14;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct TempoSegment {
+    start: f64,
+    end: f64,
+    bpm: f64,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct TempoMap {
+    bpm: f64,
+    beats_per_bar: u32,
+    beats: Vec<f64>,
+    downbeats: Vec<f64>,
+    segments: Vec<TempoSegment>,
+    duration: f64,
+}
+
+fn frames_per_second() -> f32 {
+    SAMPLE_RATE as f32 / HOP as f32
+}
+
+// Autocorrelation of the onset envelope, weighted towards ~120 BPM so
+// half/double tempo candidates don't win on noise.
+fn estimate_period(envelope: &[f32]) -> Option<f32> {
+    let fps = frames_per_second();
+    let min_lag = (60.0 * fps / MAX_BPM) as usize;
+    let max_lag = ((60.0 * fps / MIN_BPM) as usize).min(envelope.len().saturating_sub(1));
+    if min_lag >= max_lag {
+        return None;
+    }
+
+    let mut best: Option<(usize, f32)> = None;
+    for lag in min_lag..=max_lag {
+        let corr: f32 = envelope.iter().zip(&envelope[lag..]).map(|(a, b)| a * b).sum();
+        let bpm = 60.0 * fps / lag as f32;
+        let weight = (-0.5 * ((bpm / 120.0).log2() / 0.9).powi(2)).exp();
+        let score = corr * weight;
+        if best.map(|(_, s)| score > s).unwrap_or(true) {
+            best = Some((lag, score));
+        }
+    }
+
+    // Refine to a fractional lag with a parabola through the neighbours
+    best.map(|(lag, _)| {
+        let at = |l: usize| -> f32 { envelope.iter().zip(&envelope[l..]).map(|(a, b)| a * b).sum() };
+        if lag <= min_lag || lag >= max_lag {
+            return lag as f32;
+        }
+        let (a, b, c) = (at(lag - 1), at(lag), at(lag + 1));
+        let denom = a - 2.0 * b + c;
+        if denom.abs() < 1e-9 {
+            lag as f32
+        } else {
+            lag as f32 + 0.5 * (a - c) / denom
+        }
+    })
+}
+
+// Dynamic-programming beat tracker (Ellis 2007): each beat is rewarded for
+// onset strength and penalized for straying from the expected period, which
+// lets it follow gradual tempo drift.
+fn track_beats(envelope: &[f32], period: f32) -> Vec<usize> {
+    let tightness = 100.0;
+    let n = envelope.len();
+    let mut score = vec![0.0f32; n];
+    let mut backlink = vec![usize::MAX; n];
+
+    for t in 0..n {
+        let lo = (t as f32 - 2.0 * period).round().max(0.0) as usize;
+        let hi = (t as f32 - period / 2.0).round();
+        let mut best = 0.0;
+        if hi >= 0.0 {
+            for p in lo..=(hi as usize).min(t.saturating_sub(1)) {
+                let ratio = (t - p) as f32 / period;
+                let candidate = score[p] - tightness * ratio.ln().powi(2);
+                if backlink[t] == usize::MAX || candidate > best {
+                    best = candidate;
+                    backlink[t] = p;
+                }
+            }
+        }
+        score[t] = envelope[t] + best.max(0.0);
+        if best <= 0.0 {
+            backlink[t] = usize::MAX;
+        }
+    }
+
+    // Start from the best-scoring frame within the final period
+    let tail_start = n.saturating_sub(period.ceil() as usize);
+    let mut current = match (tail_start..n).max_by(|a, b| score[*a].total_cmp(&score[*b])) {
+        Some(t) => t,
+        None => return Vec::new(),
+    };
+
+    let mut beats = vec![current];
+    while backlink[current] != usize::MAX {
+        current = backlink[current];
+        beats.push(current);
+    }
+    beats.reverse();
+    beats
+}
+
+fn tempo_segments(beats: &[f64]) -> Vec<TempoSegment> {
+    if beats.len() < 3 {
+        return Vec::new();
+    }
+
+    let intervals: Vec<f64> = beats.windows(2).map(|w| w[1] - w[0]).collect();
+    // Median over a short window keeps single late beats from splitting segments
+    let smoothed: Vec<f64> = (0..intervals.len())
+        .map(|i| {
+            let lo = i.saturating_sub(4);
+            let hi = (i + 5).min(intervals.len());
+            let mut window = intervals[lo..hi].to_vec();
+            window.sort_by(|a, b| a.total_cmp(b));
+            60.0 / window[window.len() / 2]
+        })
+        .collect();
+
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut pending_change = 0;
+    for i in 1..smoothed.len() {
+        let current = smoothed[start..i].iter().sum::<f64>() / (i - start) as f64;
+        if (smoothed[i] - current).abs() / current > 0.04 {
+            pending_change += 1;
+            // Require a sustained change, not a fill or a flam
+            if pending_change >= 4 {
+                let split = i + 1 - pending_change;
+                segments.push((start, split));
+                start = split;
+                pending_change = 0;
+            }
+        } else {
+            pending_change = 0;
+        }
+    }
+    segments.push((start, smoothed.len()));
+
+    segments
+        .into_iter()
+        .filter(|(s, e)| e > s)
+        .map(|(s, e)| TempoSegment {
+            start: beats[s],
+            end: beats[e],
+            bpm: smoothed[s..e].iter().sum::<f64>() / (e - s) as f64,
+        })
+        .collect()
+}
+
+// Picks the bar phase whose beats carry the most low-frequency onset energy
+fn find_downbeats(beat_frames: &[usize], low_envelope: &[f32], beats_per_bar: usize) -> usize {
+    (0..beats_per_bar)
+        .max_by(|a, b| {
+            let strength = |phase: usize| -> f32 {
+                beat_frames
+                    .iter()
+                    .skip(phase)
+                    .step_by(beats_per_bar)
+                    .map(|f| low_envelope.get(*f).copied().unwrap_or(0.0))
+                    .sum::<f32>()
+            };
+            strength(*a).total_cmp(&strength(*b))
+        })
+        .unwrap_or(0)
+}
+
+// Single BPM estimate from an onset envelope at SAMPLE_RATE / HOP
+pub fn bpm_from_envelope(envelope: &[f32]) -> Option<f64> {
+    estimate_period(envelope).map(|period| 60.0 * frames_per_second() as f64 / period as f64)
+}
+
+// Everything tempo and key detection need from a file, gathered in one pass
+// over the decoded audio without keeping it or its spectrogram around: a few
+// floats per hop, so a three-hour live recording costs a few megabytes.
+pub struct Onsets {
+    pub envelope: Vec<f32>,
+    // Kick-weighted, for finding downbeats
+    pub low_envelope: Vec<f32>,
+    pub chroma: [f32; 12],
+    pub duration: f64,
+}
+
+pub fn stream_onsets(path: &Path) -> Result<Onsets, String> {
+    let mut resampler: Option<(u32, dsp::Resampler)> = None;
+    let mut stft = dsp::Stft::new(N_FFT, HOP);
+    let mut mono = Vec::new();
+    let mut resampled = Vec::new();
+    let mut samples = 0u64;
+    let (mut flux, mut low_flux) = (Vec::new(), Vec::new());
+    let mut chroma = [0.0f32; 12];
+    let mut previous: Option<Vec<f32>> = None;
+
+    let mut on_frame = |spectrum: &[f32]| {
+        let log = dsp::log_compress(spectrum);
+        let (all, low) = match &previous {
+            Some(prev) => (dsp::spectral_flux(&log, prev), dsp::spectral_flux(&log[..LOW_BINS], &prev[..LOW_BINS])),
+            None => (0.0, 0.0),
+        };
+        flux.push(all);
+        low_flux.push(low);
+        dsp::add_chroma(&mut chroma, spectrum, N_FFT, SAMPLE_RATE);
+        previous = Some(log);
+    };
+
+    audio::stream_file(path, |block, rate, channels| {
+        let channels = channels.max(1);
+        mono.clear();
+        mono.extend(block.chunks(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32));
+        // A rate change mid-stream (chained Ogg) starts a fresh resampler
+        if resampler.as_ref().map(|(r, _)| *r != rate).unwrap_or(true) {
+            if let Some((_, mut old)) = resampler.take() {
+                resampled.clear();
+                old.finish(&mut resampled);
+                samples += resampled.len() as u64;
+                stft.push(&resampled, &mut on_frame);
+            }
+            resampler = Some((rate, dsp::Resampler::new(rate, SAMPLE_RATE)));
+        }
+        if let Some((_, r)) = resampler.as_mut() {
+            resampled.clear();
+            r.push(&mono, &mut resampled);
+            samples += resampled.len() as u64;
+            stft.push(&resampled, &mut on_frame);
+        }
+        true
+    })?;
+    if let Some((_, mut r)) = resampler.take() {
+        resampled.clear();
+        r.finish(&mut resampled);
+        samples += resampled.len() as u64;
+        stft.push(&resampled, &mut on_frame);
+    }
+    stft.finish(&mut on_frame);
+
+    Ok(Onsets {
+        envelope: dsp::remove_onset_floor(&flux),
+        low_envelope: dsp::remove_onset_floor(&low_flux),
+        chroma,
+        duration: samples as f64 / SAMPLE_RATE as f64,
+    })
+}
+
+pub fn compute_tempo_map(path: &Path, beats_per_bar: u32) -> Result<TempoMap, String> {
+    let Onsets { envelope, low_envelope, duration, .. } = stream_onsets(path)?;
+
+    let period = estimate_period(&envelope).ok_or_else(|| "File is too short to track a tempo".to_string())?;
+    let beat_frames = track_beats(&envelope, period);
+    if beat_frames.len() < 2 {
+        return Err("No steady beat found".to_string());
+    }
+
+    let fps = frames_per_second() as f64;
+    let beats: Vec<f64> = beat_frames.iter().map(|f| *f as f64 / fps).collect();
+
+    let per_bar = beats_per_bar.max(1) as usize;
+    let phase = find_downbeats(&beat_frames, &low_envelope, per_bar);
+    let downbeats = beats.iter().skip(phase).step_by(per_bar).copied().collect();
+
+    let mut intervals: Vec<f64> = beats.windows(2).map(|w| w[1] - w[0]).collect();
+    intervals.sort_by(|a, b| a.total_cmp(b));
+    let bpm = 60.0 / intervals[intervals.len() / 2];
+
+    Ok(TempoMap {
+        bpm: (bpm * 100.0).round() / 100.0,
+        beats_per_bar,
+        segments: tempo_segments(&beats),
+        beats,
+        downbeats,
+        duration,
+    })
+}
+
+#[tauri::command]
+pub async fn analyze_tempo_map(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    beats_per_bar: Option<u32>,
+    force: Option<bool>,
+) -> Result<TempoMap, String> {
+    let beats_per_bar = beats_per_bar.unwrap_or(4);
+
+    let modified: Option<i64> = db
+        .lock()
+        .query_row("SELECT modified FROM files WHERE path = ?1", params![path], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let (Some(modified), false) = (modified, force.unwrap_or(false)) {
+        let cached: Option<String> = db
+            .lock()
+            .query_row(
+                "SELECT data FROM tempo_maps WHERE path = ?1 AND beats_per_bar = ?2 AND modified = ?3",
+                params![path, beats_per_bar, modified],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if let Some(map) = cached.and_then(|json| serde_json::from_str(&json).ok()) {
+            return Ok(map);
+        }
+    }
+
+    let db = db.inner().clone();
+    let label = format!("Tempo map {}", path);
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |_| {
+            let map = quarantine::guard(&db, &path, || compute_tempo_map(&paths::to_fs(&path), beats_per_bar))?;
+            // Files outside the library have no version to check against
+            if let Some(modified) = modified {
+                let json = serde_json::to_string(&map).map_err(|e| e.to_string())?;
+                db.lock()
+                    .execute(
+                        "INSERT OR REPLACE INTO tempo_maps (path, beats_per_bar, modified, data, analyzed_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![path, beats_per_bar, modified, json, db::now()],
+                    )
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(map)
+        })
+        .await
+}
@@ -0,0 +1,259 @@
+use crate::db::Db;
+use crate::midi;
+use crate::paths;
+use crate::roots;
+use midly::{MetaMessage, Smf, TrackEventKind};
+use std::fs;
+use std::path::Path;
+use tauri::State;
+
+const MIN_BPM: f64 = 10.0;
+const MAX_BPM: f64 = 500.0;
+// Files without a tempo play at this one
+const DEFAULT_BPM: f64 = 120.0;
+// Tempo steps a ramp writes per beat unless told otherwise
+const DEFAULT_RAMP_STEPS: u32 = 4;
+// Finer than this is inaudible and only bloats the file
+const MAX_RAMP_STEPS: u32 = 64;
+// About 1000 bars of 4/4
+const MAX_RAMP_BEATS: f64 = 4096.0;
+// Beats closer than this are the same position
+const SAME_BEAT: f64 = 1e-6;
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MapChange {
+    Tempo { bpm: f64 },
+    TimeSignature { numerator: u8, denominator: u8 },
+}
+
+impl MapChange {
+    fn same_kind(&self, other: &MapChange) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct TempoMapEvent {
+    // In quarter notes from the start of the file
+    beat: f64,
+    // 1-based, as DAWs count
+    bar: usize,
+    seconds: f64,
+    change: MapChange,
+}
+
+#[derive(serde::Serialize)]
+pub struct TempoMap {
+    path: String,
+    // Where the last event of any kind lands, in quarter notes
+    length_beats: f64,
+    events: Vec<TempoMapEvent>,
+}
+
+// Edits are applied in order; indices count the map's events by position
+// as they stand after the edits before
+#[derive(serde::Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TempoEdit {
+    Insert { beat: f64, change: MapChange },
+    Move { index: usize, beat: f64 },
+    Delete { index: usize },
+    // Steps the tempo evenly from one value to another, replacing any tempo
+    // changes in between
+    Ramp { from_beat: f64, to_beat: f64, from_bpm: f64, to_bpm: f64, steps_per_beat: Option<u32> },
+}
+
+fn check_change(change: &MapChange) -> Result<(), String> {
+    match *change {
+        MapChange::Tempo { bpm } if !(MIN_BPM..=MAX_BPM).contains(&bpm) => {
+            Err(format!("Tempo has to be between {} and {} BPM", MIN_BPM, MAX_BPM))
+        }
+        MapChange::TimeSignature { numerator, denominator }
+            if numerator == 0 || numerator > 32 || !denominator.is_power_of_two() || denominator > 32 =>
+        {
+            Err(format!("{}/{} isn't a time signature we can write", numerator, denominator))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Tempo and time-signature events from every track, by position
+fn read_map(smf: &Smf) -> Result<(Vec<(f64, MapChange)>, f64), String> {
+    let ppq = midi::ticks_per_beat(smf)?;
+    let mut map = Vec::new();
+    let mut end = 0u32;
+    for track in &smf.tracks {
+        for (tick, kind) in midi::absolute(track) {
+            end = end.max(tick);
+            let change = match kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(micros)) => MapChange::Tempo { bpm: 60_000_000.0 / micros.as_int().max(1) as f64 },
+                TrackEventKind::Meta(MetaMessage::TimeSignature(numerator, denominator_pow2, ..)) => {
+                    MapChange::TimeSignature { numerator, denominator: 1u8.checked_shl(denominator_pow2 as u32).unwrap_or(4) }
+                }
+                _ => continue,
+            };
+            map.push((tick as f64 / ppq, change));
+        }
+    }
+    map.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok((map, end as f64 / ppq))
+}
+
+// Bars and seconds for each event, walking the meter and tempo in order
+fn describe(path: &str, map: &[(f64, MapChange)], length_beats: f64) -> TempoMap {
+    let (mut bar, mut bar_start, mut bar_beats) = (1usize, 0.0, 4.0);
+    let (mut seconds, mut last_beat, mut bpm) = (0.0, 0.0, DEFAULT_BPM);
+    let events = map
+        .iter()
+        .map(|(beat, change)| {
+            seconds += (beat - last_beat) * 60.0 / bpm;
+            last_beat = *beat;
+            let bars = ((beat - bar_start) / bar_beats).max(0.0);
+            let whole = (bars + SAME_BEAT).floor();
+            let mut at_bar = bar + whole as usize;
+            match *change {
+                MapChange::Tempo { bpm: next } => bpm = next,
+                MapChange::TimeSignature { numerator, denominator } => {
+                    // A new meter mid-bar starts a bar of its own
+                    if bars - whole > SAME_BEAT {
+                        at_bar += 1;
+                    }
+                    bar = at_bar;
+                    bar_start = *beat;
+                    bar_beats = numerator as f64 * 4.0 / denominator as f64;
+                }
+            }
+            TempoMapEvent { beat: *beat, bar: at_bar, seconds, change: *change }
+        })
+        .collect();
+    TempoMap { path: path.to_string(), length_beats, events }
+}
+
+fn event(map: &[(f64, MapChange)], index: usize) -> Result<(f64, MapChange), String> {
+    map.get(index).copied().ok_or_else(|| format!("There's no event {} in a map of {}", index, map.len()))
+}
+
+// Places a change, replacing one of the same kind already at that beat
+fn insert(map: &mut Vec<(f64, MapChange)>, beat: f64, change: MapChange) {
+    map.retain(|(b, c)| !((b - beat).abs() < SAME_BEAT && c.same_kind(&change)));
+    map.push((beat, change));
+    map.sort_by(|a, b| a.0.total_cmp(&b.0));
+}
+
+fn apply(map: &mut Vec<(f64, MapChange)>, edit: TempoEdit) -> Result<(), String> {
+    match edit {
+        TempoEdit::Insert { beat, change } => {
+            check_change(&change)?;
+            insert(map, beat.max(0.0), change);
+        }
+        TempoEdit::Move { index, beat } => {
+            let (_, change) = event(map, index)?;
+            map.remove(index);
+            insert(map, beat.max(0.0), change);
+        }
+        TempoEdit::Delete { index } => {
+            event(map, index)?;
+            map.remove(index);
+        }
+        TempoEdit::Ramp { from_beat, to_beat, from_bpm, to_bpm, steps_per_beat } => {
+            check_change(&MapChange::Tempo { bpm: from_bpm })?;
+            check_change(&MapChange::Tempo { bpm: to_bpm })?;
+            let from_beat = from_beat.max(0.0);
+            if !to_beat.is_finite() || to_beat <= from_beat {
+                return Err("A ramp has to end after it starts".to_string());
+            }
+            if to_beat - from_beat > MAX_RAMP_BEATS {
+                return Err(format!("A ramp can span at most {} beats", MAX_RAMP_BEATS));
+            }
+            let steps_per_beat = steps_per_beat.unwrap_or(DEFAULT_RAMP_STEPS);
+            if !(1..=MAX_RAMP_STEPS).contains(&steps_per_beat) {
+                return Err(format!("A ramp has 1 to {} tempo steps per beat", MAX_RAMP_STEPS));
+            }
+            let steps_per_beat = steps_per_beat as f64;
+            map.retain(|(beat, change)| {
+                !(matches!(change, MapChange::Tempo { .. }) && *beat > from_beat - SAME_BEAT && *beat < to_beat + SAME_BEAT)
+            });
+            let steps = ((to_beat - from_beat) * steps_per_beat).ceil() as usize;
+            for step in 0..=steps {
+                let beat = (from_beat + step as f64 / steps_per_beat).min(to_beat);
+                let through = (beat - from_beat) / (to_beat - from_beat);
+                map.push((beat, MapChange::Tempo { bpm: from_bpm + (to_bpm - from_bpm) * through }));
+            }
+            map.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+    }
+    Ok(())
+}
+
+// Swaps the file's tempo and meter events for `map`, written to the first
+// track where players and DAWs look for them. Notes keep their ticks, so
+// they stay on the same beats.
+fn rewrite<'a>(smf: &Smf<'a>, map: &[(f64, MapChange)], expression: midi::Expression) -> Result<Smf<'a>, String> {
+    let ppq = midi::ticks_per_beat(smf)?;
+    let mut tracks: Vec<_> = smf
+        .tracks
+        .iter()
+        .map(|track| {
+            let mut events = midi::absolute(track);
+            events.retain(|(_, kind)| !matches!(kind, TrackEventKind::Meta(MetaMessage::Tempo(_) | MetaMessage::TimeSignature(..))));
+            midi::apply_expression(&mut events, expression);
+            events
+        })
+        .collect();
+    if tracks.is_empty() {
+        tracks.push(Vec::new());
+    }
+    for (beat, change) in map {
+        let tick = (beat * ppq).round() as u32;
+        let kind = match *change {
+            MapChange::Tempo { bpm } => midi::tempo_event(bpm),
+            MapChange::TimeSignature { numerator, denominator } => midi::time_signature_event(numerator, denominator),
+        };
+        tracks[0].push((tick, kind));
+    }
+    Ok(Smf { header: smf.header, tracks: tracks.into_iter().map(midi::to_track).collect() })
+}
+
+fn save(smf: &Smf, destination: &Path) -> Result<(), String> {
+    let temp = destination.with_file_name(format!(".{}.part", paths::file_name(destination)));
+    if let Err(e) = smf.save(&temp) {
+        let _ = fs::remove_file(&temp);
+        return Err(format!("Failed to write MIDI file: {}", e));
+    }
+    fs::rename(&temp, destination).map_err(|e| format!("Failed to finalize file: {}", e))
+}
+
+// Every tempo and time-signature change in a MIDI file, with the bar and
+// time each lands at
+#[tauri::command]
+pub async fn get_tempo_map(path: String) -> Result<TempoMap, String> {
+    let bytes = fs::read(paths::to_fs(&path)).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let smf = Smf::parse(&bytes).map_err(|e| format!("Failed to parse MIDI file: {}", e))?;
+    let (map, length_beats) = read_map(&smf)?;
+    Ok(describe(&path, &map, length_beats))
+}
+
+// Inserts, moves and deletes tempo and time-signature changes, or ramps the
+// tempo between two points, then rewrites the file in place. Returns the map
+// as it now stands. Bends and pressure are kept unless `expression` is
+// "strip".
+#[tauri::command]
+pub async fn edit_tempo_map(
+    db: State<'_, Db>,
+    path: String,
+    edits: Vec<TempoEdit>,
+    expression: Option<midi::Expression>,
+) -> Result<TempoMap, String> {
+    roots::ensure_writable(&db.lock(), &[&path])?;
+    let fs_path = paths::to_fs(&path);
+    let bytes = fs::read(&fs_path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let smf = Smf::parse(&bytes).map_err(|e| format!("Failed to parse MIDI file: {}", e))?;
+    let (mut map, length_beats) = read_map(&smf)?;
+    for edit in edits {
+        apply(&mut map, edit)?;
+    }
+    save(&rewrite(&smf, &map, expression.unwrap_or_default())?, &fs_path)?;
+    let end = map.last().map(|(beat, _)| *beat).unwrap_or(0.0);
+    Ok(describe(&path, &map, length_beats.max(end)))
+}
@@ -0,0 +1,557 @@
+use crate::analysis::NOTE_NAMES;
+use crate::reconcile;
+
+const ROMAN: [&str; 7] = ["I", "II", "III", "IV", "V", "VI", "VII"];
+const DEFAULT_OCTAVE: i32 = 4;
+// C-1 to C9: every octave that has MIDI notes in it
+const OCTAVE_RANGE: std::ops::RangeInclusive<i32> = -1..=9;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scale {
+    Major,
+    Minor,
+    HarmonicMinor,
+    MelodicMinor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    Chromatic,
+}
+
+const SCALES: [Scale; 13] = [
+    Scale::Major,
+    Scale::Minor,
+    Scale::HarmonicMinor,
+    Scale::MelodicMinor,
+    Scale::Dorian,
+    Scale::Phrygian,
+    Scale::Lydian,
+    Scale::Mixolydian,
+    Scale::Locrian,
+    Scale::MajorPentatonic,
+    Scale::MinorPentatonic,
+    Scale::Blues,
+    Scale::Chromatic,
+];
+
+impl Scale {
+    // Semitones above the root, ascending, within one octave
+    pub fn intervals(self) -> &'static [usize] {
+        match self {
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Scale::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Scale::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Scale::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Blues => &[0, 3, 5, 6, 7, 10],
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+}
+
+// Which of the twelve pitch classes belong to the scale on `root`
+pub fn pitch_classes(root: usize, scale: Scale) -> [bool; 12] {
+    let mut tones = [false; 12];
+    for step in scale.intervals() {
+        tones[(root + step) % 12] = true;
+    }
+    tones
+}
+
+pub fn parse_root(text: &str) -> Result<usize, String> {
+    match reconcile::parse_note(text.trim()) {
+        Some((pitch, "")) => Ok(pitch),
+        _ => Err(format!("{} isn't a note name", text)),
+    }
+}
+
+fn note_name(midi: i32) -> String {
+    format!("{}{}", NOTE_NAMES[midi.rem_euclid(12) as usize], midi.div_euclid(12) - 1)
+}
+
+// Chord qualities: the spellings we read, the intervals, and the suffix a
+// Roman numeral carries for it. The first spelling is the one we write.
+const QUALITIES: &[(&[&str], &[i32], &str)] = &[
+    (&["", "maj", "M"], &[0, 4, 7], ""),
+    (&["m", "min", "-"], &[0, 3, 7], ""),
+    (&["dim", "°", "o"], &[0, 3, 6], "°"),
+    (&["aug", "+"], &[0, 4, 8], "+"),
+    (&["sus2"], &[0, 2, 7], "sus2"),
+    (&["sus4", "sus"], &[0, 5, 7], "sus4"),
+    (&["5"], &[0, 7], "5"),
+    (&["6"], &[0, 4, 7, 9], "6"),
+    (&["m6"], &[0, 3, 7, 9], "6"),
+    (&["7"], &[0, 4, 7, 10], "7"),
+    (&["maj7", "M7", "Δ7", "Δ"], &[0, 4, 7, 11], "maj7"),
+    (&["m7", "min7", "-7"], &[0, 3, 7, 10], "7"),
+    (&["mmaj7", "mM7"], &[0, 3, 7, 11], "maj7"),
+    (&["m7b5", "ø7", "ø"], &[0, 3, 6, 10], "ø7"),
+    (&["dim7", "°7", "o7"], &[0, 3, 6, 9], "°7"),
+    (&["7sus4"], &[0, 5, 7, 10], "7sus4"),
+    (&["add9"], &[0, 4, 7, 14], "add9"),
+    (&["9"], &[0, 4, 7, 10, 14], "9"),
+    (&["maj9"], &[0, 4, 7, 11, 14], "maj9"),
+    (&["m9"], &[0, 3, 7, 10, 14], "9"),
+    (&["11"], &[0, 4, 7, 10, 14, 17], "11"),
+    (&["13"], &[0, 4, 7, 10, 14, 21], "13"),
+];
+
+pub struct Chord {
+    pub root: usize,
+    quality: usize,
+    // Slash chords: the pitch class under the root
+    pub bass: Option<usize>,
+}
+
+impl Chord {
+    pub fn intervals(&self) -> &'static [i32] {
+        QUALITIES[self.quality].1
+    }
+
+    fn minor(&self) -> bool {
+        self.intervals().contains(&3) && !self.intervals().contains(&4)
+    }
+
+    // The triad under any extensions, for comparing chords loosely:
+    // "major", "minor", "diminished", "augmented" or "other" (sus, power)
+    pub fn triad(&self) -> &'static str {
+        match QUALITIES[self.quality].2 {
+            "°" | "°7" | "ø7" => "diminished",
+            "+" => "augmented",
+            "sus2" | "sus4" | "7sus4" | "5" => "other",
+            _ if self.minor() => "minor",
+            _ => "major",
+        }
+    }
+
+    pub fn symbol(&self) -> String {
+        let mut symbol = format!("{}{}", NOTE_NAMES[self.root], QUALITIES[self.quality].0[0]);
+        if let Some(bass) = self.bass {
+            symbol.push('/');
+            symbol.push_str(NOTE_NAMES[bass]);
+        }
+        symbol
+    }
+
+    // MIDI notes with the root in `octave`; a slash bass goes below it
+    pub fn notes(&self, octave: i32) -> Vec<u8> {
+        let root = (octave + 1) * 12 + self.root as i32;
+        let mut notes: Vec<i32> = self.intervals().iter().map(|i| root + i).collect();
+        if let Some(bass) = self.bass {
+            let below = root - (self.root as i32 - bass as i32).rem_euclid(12);
+            notes.retain(|n| n.rem_euclid(12) != bass as i32);
+            notes.insert(0, below);
+        }
+        notes.into_iter().filter(|n| (0..=127).contains(n)).map(|n| n as u8).collect()
+    }
+}
+
+// "C", "F#m7", "Bbmaj7", "Dm7b5", "G/B"
+pub fn parse_chord(symbol: &str) -> Result<Chord, String> {
+    let unknown = || format!("{} isn't a chord we know", symbol);
+    let (main, bass) = match symbol.trim().split_once('/') {
+        Some((main, bass)) => (main, Some(parse_root(bass).map_err(|_| unknown())?)),
+        None => (symbol.trim(), None),
+    };
+    let (root, rest) = reconcile::parse_note(main).ok_or_else(unknown)?;
+    let quality = QUALITIES.iter().position(|(spellings, _, _)| spellings.contains(&rest)).ok_or_else(unknown)?;
+    Ok(Chord { root, quality, bass: bass.filter(|b| *b != root) })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Key {
+    pub tonic: usize,
+    pub minor: bool,
+}
+
+impl Key {
+    pub fn parse(text: &str) -> Result<Key, String> {
+        reconcile::parse_key(text).map(|(tonic, minor)| Key { tonic, minor }).ok_or_else(|| format!("{} isn't a key", text))
+    }
+
+    pub fn name(&self) -> String {
+        format!("{}{}", NOTE_NAMES[self.tonic], if self.minor { "m" } else { "" })
+    }
+
+    pub fn scale(&self) -> Scale {
+        if self.minor {
+            Scale::Minor
+        } else {
+            Scale::Major
+        }
+    }
+}
+
+// The key a progression most likely sits in: the one holding the most chord
+// tones, with a nudge for starting or ending on its tonic chord
+pub fn guess_key(chords: &[Chord]) -> Option<Key> {
+    if chords.is_empty() {
+        return None;
+    }
+    let is_tonic = |chord: &Chord, key: &Key| chord.root == key.tonic && chord.minor() == key.minor;
+    let mut best: Option<(f32, Key)> = None;
+    for minor in [false, true] {
+        for tonic in 0..12 {
+            let key = Key { tonic, minor };
+            let tones = pitch_classes(tonic, key.scale());
+            let mut score: f32 = chords
+                .iter()
+                .map(|c| {
+                    let inside = c.intervals().iter().filter(|i| tones[(c.root as i32 + *i).rem_euclid(12) as usize]).count();
+                    inside as f32 / c.intervals().len() as f32
+                })
+                .sum();
+            if is_tonic(&chords[0], &key) {
+                score += 1.0;
+            }
+            if is_tonic(&chords[chords.len() - 1], &key) {
+                score += 1.0;
+            }
+            // Strictly greater, so a relative major wins a tie with its minor
+            let better = match best {
+                Some((best_score, _)) => score > best_score,
+                None => true,
+            };
+            if better {
+                best = Some((score, key));
+            }
+        }
+    }
+    best.map(|(_, key)| key)
+}
+
+#[derive(serde::Serialize)]
+pub struct RomanChord {
+    symbol: String,
+    // "V7", "ii", "bVII"; uppercase major, lowercase minor
+    numeral: String,
+    // Whether every chord tone is in the key
+    diatonic: bool,
+    // "tonic", "subdominant" or "dominant" for chords on a scale degree
+    function: Option<&'static str>,
+}
+
+// Degree-and-quality part of a chord's numeral, without extensions, as the
+// suggestion tables are keyed
+fn base_numeral(chord: &Chord, key: &Key) -> (String, Option<usize>) {
+    let steps = key.scale().intervals();
+    let rel = (chord.root + 12 - key.tonic) % 12;
+    let (accidental, degree) = match steps.iter().position(|s| *s == rel) {
+        Some(degree) => ("", degree),
+        None => match steps.iter().position(|s| *s == (rel + 1) % 12) {
+            Some(degree) => ("b", degree),
+            None => ("#", steps.iter().position(|s| *s == (rel + 11) % 12).unwrap_or(0)),
+        },
+    };
+    let letters = if chord.minor() { ROMAN[degree].to_lowercase() } else { ROMAN[degree].to_string() };
+    let triad = match QUALITIES[chord.quality].2 {
+        "°" | "°7" | "ø7" => "°",
+        "+" => "+",
+        _ => "",
+    };
+    let on_degree = if accidental.is_empty() { Some(degree) } else { None };
+    (format!("{}{}{}", accidental, letters, triad), on_degree)
+}
+
+pub fn analyze(chord: &Chord, key: &Key) -> RomanChord {
+    let (base, degree) = base_numeral(chord, key);
+    let suffix = QUALITIES[chord.quality].2;
+    let numeral = match suffix {
+        // Already carried by the base
+        "°" | "+" => base,
+        "°7" | "ø7" => format!("{}{}", base.trim_end_matches('°'), suffix),
+        _ => format!("{}{}", base, suffix),
+    };
+    let tones = pitch_classes(key.tonic, key.scale());
+    let diatonic = chord.intervals().iter().all(|i| tones[(chord.root as i32 + i).rem_euclid(12) as usize]);
+    let function = degree.map(|d| match d {
+        0 | 2 | 5 => "tonic",
+        1 | 3 => "subdominant",
+        _ => "dominant",
+    });
+    RomanChord { symbol: chord.symbol(), numeral, diatonic, function }
+}
+
+// The chord a numeral names in `key`: "V7" in C is G7, "bVII" is A#
+pub fn from_numeral(numeral: &str, key: &Key) -> Option<Chord> {
+    let (shift, rest) = match numeral.strip_prefix('b') {
+        Some(rest) => (11, rest),
+        None => match numeral.strip_prefix('#') {
+            Some(rest) => (1, rest),
+            None => (0, numeral),
+        },
+    };
+    let letters: String = rest.chars().take_while(|c| matches!(*c, 'I' | 'V' | 'i' | 'v')).collect();
+    let suffix = &rest[letters.len()..];
+    let degree = ROMAN.iter().position(|r| *r == letters.to_uppercase())?;
+    let minor = letters.chars().all(|c| c.is_lowercase());
+    let spelling = match (minor, suffix) {
+        (false, "") => "",
+        (true, "") => "m",
+        (_, "°") => "dim",
+        (_, "+") => "aug",
+        (_, "ø7") => "m7b5",
+        (_, "°7") => "dim7",
+        (true, "7") => "m7",
+        (false, "7") => "7",
+        (true, "9") => "m9",
+        (false, "9") => "9",
+        (_, "maj7") => "maj7",
+        _ => return None,
+    };
+    let root = (key.tonic + key.scale().intervals()[degree] + shift) % 12;
+    let quality = QUALITIES.iter().position(|(spellings, _, _)| spellings.contains(&spelling))?;
+    Some(Chord { root, quality, bass: None })
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Style {
+    #[default]
+    Pop,
+    Jazz,
+    Blues,
+    Classical,
+}
+
+// Where each chord tends to go next, by base numeral, with relative weights
+type Moves = &'static [(&'static str, &'static [(&'static str, u32)])];
+
+fn moves(style: Style, minor: bool) -> Moves {
+    match (style, minor) {
+        (Style::Pop, false) => &[
+            ("I", &[("V", 3), ("IV", 3), ("vi", 3), ("ii", 1)]),
+            ("ii", &[("V", 4), ("IV", 1), ("vi", 1)]),
+            ("iii", &[("vi", 3), ("IV", 2)]),
+            ("IV", &[("I", 3), ("V", 3), ("vi", 2), ("ii", 1)]),
+            ("V", &[("I", 3), ("vi", 3), ("IV", 2)]),
+            ("vi", &[("IV", 4), ("V", 2), ("ii", 2), ("I", 1)]),
+            ("vii°", &[("I", 4), ("iii", 1)]),
+            ("bVII", &[("I", 3), ("IV", 2)]),
+        ],
+        (Style::Pop, true) => &[
+            ("i", &[("VI", 3), ("VII", 3), ("iv", 3), ("v", 1)]),
+            ("ii°", &[("V", 3), ("v", 1)]),
+            ("III", &[("VI", 3), ("VII", 2), ("iv", 2)]),
+            ("iv", &[("i", 3), ("VII", 2), ("V", 2), ("VI", 1)]),
+            ("v", &[("i", 3), ("VI", 2)]),
+            ("V", &[("i", 4), ("VI", 1)]),
+            ("VI", &[("VII", 4), ("III", 2), ("iv", 2), ("i", 1)]),
+            ("VII", &[("i", 3), ("III", 3), ("VI", 1)]),
+        ],
+        (Style::Jazz, false) => &[
+            ("I", &[("ii7", 3), ("vi7", 3), ("IVmaj7", 2), ("iii7", 1)]),
+            ("ii", &[("V7", 5), ("viiø7", 1)]),
+            ("iii", &[("vi7", 4), ("ii7", 1)]),
+            ("IV", &[("V7", 2), ("iii7", 2), ("ii7", 2), ("Imaj7", 1)]),
+            ("V", &[("Imaj7", 5), ("vi7", 2)]),
+            ("vi", &[("ii7", 5), ("IVmaj7", 1)]),
+            ("vii°", &[("iii7", 2), ("Imaj7", 2)]),
+        ],
+        (Style::Jazz, true) => &[
+            ("i", &[("iiø7", 3), ("iv7", 2), ("VImaj7", 2)]),
+            ("ii°", &[("V7", 5)]),
+            ("III", &[("VImaj7", 2), ("iiø7", 2)]),
+            ("iv", &[("V7", 2), ("VII7", 2), ("i7", 1)]),
+            ("V", &[("i7", 5)]),
+            ("VI", &[("iiø7", 3), ("V7", 2)]),
+            ("VII", &[("IIImaj7", 4)]),
+        ],
+        (Style::Blues, false) => &[
+            ("I", &[("IV7", 4), ("V7", 2)]),
+            ("IV", &[("I7", 4), ("V7", 1)]),
+            ("V", &[("IV7", 3), ("I7", 3)]),
+        ],
+        (Style::Blues, true) => &[
+            ("i", &[("iv7", 4), ("v7", 2)]),
+            ("iv", &[("i7", 4), ("v7", 1)]),
+            ("v", &[("iv7", 3), ("i7", 3)]),
+        ],
+        (Style::Classical, false) => &[
+            ("I", &[("IV", 3), ("V", 3), ("ii", 2), ("vi", 2)]),
+            ("ii", &[("V", 4), ("vii°", 2)]),
+            ("iii", &[("vi", 3), ("IV", 2)]),
+            ("IV", &[("V", 3), ("I", 2), ("ii", 2), ("vii°", 1)]),
+            ("V", &[("I", 5), ("vi", 2)]),
+            ("vi", &[("ii", 3), ("IV", 3), ("V", 1)]),
+            ("vii°", &[("I", 5)]),
+        ],
+        (Style::Classical, true) => &[
+            ("i", &[("iv", 3), ("V", 3), ("ii°", 2), ("VI", 2)]),
+            ("ii°", &[("V", 5)]),
+            ("III", &[("VI", 3), ("iv", 2)]),
+            ("iv", &[("V", 4), ("i", 2), ("ii°", 1)]),
+            ("V", &[("i", 5), ("VI", 2)]),
+            ("VI", &[("ii°", 3), ("iv", 2), ("V", 2)]),
+            ("VII", &[("III", 4)]),
+        ],
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Suggestion {
+    symbol: String,
+    numeral: String,
+    // Share of the suggestions' total, highest first
+    weight: f32,
+}
+
+// Likely next chords after `chords` in `key`, from the last chord's place
+// in it. Chords outside the table get what follows the tonic.
+pub fn suggest(chords: &[Chord], key: &Key, style: Style) -> Vec<Suggestion> {
+    let table = moves(style, key.minor);
+    let tonic = if key.minor { "i" } else { "I" };
+    let last = chords.last().map(|c| base_numeral(c, key).0).unwrap_or_else(|| tonic.to_string());
+    let options = table
+        .iter()
+        .find(|(from, _)| *from == last)
+        .or_else(|| table.iter().find(|(from, _)| *from == tonic))
+        .map(|(_, options)| *options)
+        .unwrap_or(&[]);
+    let total: u32 = options.iter().map(|(_, w)| w).sum();
+    let mut suggestions: Vec<Suggestion> = options
+        .iter()
+        .filter_map(|(numeral, weight)| {
+            let chord = from_numeral(numeral, key)?;
+            Some(Suggestion { symbol: chord.symbol(), numeral: numeral.to_string(), weight: *weight as f32 / total.max(1) as f32 })
+        })
+        .collect();
+    suggestions.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+    suggestions
+}
+
+#[derive(serde::Serialize)]
+pub struct ScaleNotes {
+    root: String,
+    scale: Scale,
+    // Pitch classes 0-11 (C = 0), for marking a keyboard
+    pitch_classes: Vec<usize>,
+    // One octave from the root in `octave`
+    notes: Vec<u8>,
+    names: Vec<String>,
+    // Triads on each degree, for seven-note scales
+    chords: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ChordNotes {
+    symbol: String,
+    root: String,
+    bass: Option<String>,
+    notes: Vec<u8>,
+    names: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ProgressionAnalysis {
+    key: String,
+    chords: Vec<RomanChord>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ChordSuggestions {
+    key: String,
+    suggestions: Vec<Suggestion>,
+}
+
+fn parse_progression(progression: &[String]) -> Result<Vec<Chord>, String> {
+    progression.iter().map(|s| parse_chord(s)).collect()
+}
+
+fn key_for(chords: &[Chord], key: Option<&str>) -> Result<Key, String> {
+    match key {
+        Some(key) => Key::parse(key),
+        None => guess_key(chords).ok_or_else(|| "Give a key or at least one chord".to_string()),
+    }
+}
+
+#[tauri::command]
+pub async fn list_scales() -> Result<Vec<Scale>, String> {
+    Ok(SCALES.to_vec())
+}
+
+fn check_octave(octave: Option<i32>) -> Result<i32, String> {
+    let octave = octave.unwrap_or(DEFAULT_OCTAVE);
+    if !OCTAVE_RANGE.contains(&octave) {
+        return Err(format!("Octave must be between {} and {}", OCTAVE_RANGE.start(), OCTAVE_RANGE.end()));
+    }
+    Ok(octave)
+}
+
+#[tauri::command]
+pub async fn get_scale_notes(root: String, scale: Scale, octave: Option<i32>) -> Result<ScaleNotes, String> {
+    let tonic = parse_root(&root)?;
+    let start = (check_octave(octave)? + 1) * 12 + tonic as i32;
+    let steps = scale.intervals();
+    let notes: Vec<u8> = steps.iter().map(|s| start + *s as i32).filter(|n| (0..=127).contains(n)).map(|n| n as u8).collect();
+    let chords = if steps.len() == 7 {
+        (0..7)
+            .filter_map(|d| {
+                let third = (steps[(d + 2) % 7] + 12 - steps[d]) % 12;
+                let fifth = (steps[(d + 4) % 7] + 12 - steps[d]) % 12;
+                let spelling = match (third, fifth) {
+                    (4, 7) => "",
+                    (3, 7) => "m",
+                    (3, 6) => "dim",
+                    (4, 8) => "aug",
+                    _ => return None,
+                };
+                Some(format!("{}{}", NOTE_NAMES[(tonic + steps[d]) % 12], spelling))
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    Ok(ScaleNotes {
+        root: NOTE_NAMES[tonic].to_string(),
+        scale,
+        pitch_classes: steps.iter().map(|s| (tonic + s) % 12).collect(),
+        names: notes.iter().map(|n| note_name(*n as i32)).collect(),
+        notes,
+        chords,
+    })
+}
+
+#[tauri::command]
+pub async fn get_chord_notes(symbol: String, octave: Option<i32>) -> Result<ChordNotes, String> {
+    let chord = parse_chord(&symbol)?;
+    let notes = chord.notes(check_octave(octave)?);
+    Ok(ChordNotes {
+        symbol: chord.symbol(),
+        root: NOTE_NAMES[chord.root].to_string(),
+        bass: chord.bass.map(|b| NOTE_NAMES[b].to_string()),
+        names: notes.iter().map(|n| note_name(*n as i32)).collect(),
+        notes,
+    })
+}
+
+// Roman numerals for each chord, in `key` or the key the chords suggest
+#[tauri::command]
+pub async fn analyze_progression(progression: Vec<String>, key: Option<String>) -> Result<ProgressionAnalysis, String> {
+    let chords = parse_progression(&progression)?;
+    let key = key_for(&chords, key.as_deref())?;
+    Ok(ProgressionAnalysis { key: key.name(), chords: chords.iter().map(|c| analyze(c, &key)).collect() })
+}
+
+#[tauri::command]
+pub async fn suggest_next_chords(
+    progression: Vec<String>,
+    style: Option<Style>,
+    key: Option<String>,
+) -> Result<ChordSuggestions, String> {
+    let chords = parse_progression(&progression)?;
+    let key = key_for(&chords, key.as_deref())?;
+    Ok(ChordSuggestions { key: key.name(), suggestions: suggest(&chords, &key, style.unwrap_or_default()) })
+}
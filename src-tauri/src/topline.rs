@@ -0,0 +1,286 @@
+use crate::db::Db;
+use crate::midi::{self, Note};
+use crate::paths;
+use crate::takes;
+use std::fs;
+use tauri::State;
+
+const DEFAULT_BPM: f64 = 100.0;
+const DEFAULT_BARS_PER_LINE: u32 = 2;
+// C4, a placeholder pitch for the rhythm
+const DEFAULT_PITCH: u8 = 60;
+// The line's first stress, other stresses, everything else
+const DOWNBEAT_VELOCITY: u8 = 112;
+const STRESSED_VELOCITY: u8 = 100;
+const UNSTRESSED_VELOCITY: u8 = 72;
+const GATE: f64 = 0.9;
+// Lines that don't fit are sung faster, down to 32nds
+const MIN_GRID: f64 = 0.125;
+
+// Monosyllables that are sung through rather than leaned on
+const FUNCTION_WORDS: &[&str] = &[
+    "a", "an", "the", "and", "or", "but", "nor", "so", "to", "of", "in", "on", "at", "for", "with", "from", "by", "as", "is",
+    "am", "are", "was", "were", "be", "been", "it", "its", "it's", "i", "i'm", "you", "he", "she", "we", "they", "me", "my",
+    "your", "our", "their", "his", "her", "them", "us", "that", "this", "if", "than", "then", "do", "does", "can", "will",
+    "just", "not", "no", "all", "up",
+];
+// Two-syllable words starting with these usually stress the second (be-LIEVE)
+const UNSTRESSED_PREFIXES: &[&str] = &["a", "be", "de", "re", "un", "con", "com", "for", "ex", "pre"];
+// Endings that put the stress just before them (e-MO-tion)
+const PENULTIMATE_SUFFIXES: &[&str] = &["tion", "sion", "cian", "ic"];
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Density {
+    // Stresses on quarters, the rest on 8ths
+    Sparse,
+    // Stresses on 8ths, the rest on 16ths
+    Medium,
+    // Stresses on 16ths, the rest on 32nds
+    Busy,
+}
+
+impl Density {
+    fn grid(self) -> f64 {
+        match self {
+            Density::Sparse => 0.5,
+            Density::Medium => 0.25,
+            Density::Busy => 0.125,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Syllable {
+    text: String,
+    stressed: bool,
+    // In beats from the start of the rhythm
+    start: f64,
+    duration: f64,
+}
+
+#[derive(serde::Serialize)]
+pub struct RhythmLine {
+    text: String,
+    syllables: Vec<Syllable>,
+}
+
+#[derive(serde::Serialize)]
+pub struct VocalRhythm {
+    lines: Vec<RhythmLine>,
+    notes: Vec<Note>,
+    beats: f64,
+    // Set when the result was written out
+    path: Option<String>,
+}
+
+fn is_vowel(chars: &[char], i: usize) -> bool {
+    match chars[i] {
+        'a' | 'e' | 'i' | 'o' | 'u' => true,
+        // "yes" starts on a consonant, "rhythm" and "day" don't
+        'y' => i > 0,
+        _ => false,
+    }
+}
+
+// Splits a word at its vowel groups, leaving one consonant to start each
+// following syllable (ba-by, hap-py). A final silent "e" or "ed" doesn't
+// count as a syllable.
+fn syllables(word: &str) -> Vec<String> {
+    let original: Vec<char> = word.chars().filter(|c| c.is_alphabetic() || *c == '\'').collect();
+    let chars: Vec<char> = original.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for i in 0..chars.len() {
+        if !is_vowel(&chars, i) {
+            continue;
+        }
+        match groups.last_mut() {
+            Some(last) if last.1 == i => last.1 = i + 1,
+            _ => groups.push((i, i + 1)),
+        }
+    }
+    let n = chars.len();
+    if groups.len() > 1 {
+        let last = groups[groups.len() - 1];
+        let silent_e = last == (n - 1, n) && chars[n - 1] == 'e' && !(n >= 3 && chars[n - 2] == 'l' && !is_vowel(&chars, n - 3));
+        let silent_ed = last == (n - 2, n - 1) && chars[n - 2..] == ['e', 'd'] && !matches!(chars[n - 3], 't' | 'd');
+        if silent_e || silent_ed {
+            groups.pop();
+        }
+    }
+    if groups.len() <= 1 {
+        return vec![original.iter().collect()];
+    }
+
+    let mut cuts = Vec::with_capacity(groups.len() - 1);
+    for pair in groups.windows(2) {
+        let consonants = pair[1].0 - pair[0].1;
+        cuts.push(if consonants <= 1 { pair[1].0 - consonants } else { pair[0].1 + 1 });
+    }
+    let mut pieces = Vec::with_capacity(groups.len());
+    let mut from = 0;
+    for cut in cuts.into_iter().chain(std::iter::once(n)) {
+        pieces.push(original[from..cut].iter().collect());
+        from = cut;
+    }
+    pieces
+}
+
+// Which syllable of a word carries the stress, or None for a function word
+fn stress(word: &str, pieces: &[String]) -> Option<usize> {
+    let lower = word.to_lowercase();
+    if pieces.len() == 1 {
+        return if FUNCTION_WORDS.contains(&lower.as_str()) { None } else { Some(0) };
+    }
+    if PENULTIMATE_SUFFIXES.iter().any(|s| lower.ends_with(s)) {
+        return Some(pieces.len() - 2);
+    }
+    let first = pieces[0].to_lowercase();
+    if pieces.len() == 2 && UNSTRESSED_PREFIXES.contains(&first.as_str()) {
+        return Some(1);
+    }
+    Some(0)
+}
+
+fn line_syllables(line: &str) -> Vec<(String, bool)> {
+    line.split_whitespace()
+        .map(|token| token.chars().filter(|c| c.is_alphabetic() || *c == '\'').collect::<String>())
+        .filter(|word| word.chars().any(char::is_alphabetic))
+        .flat_map(|word| {
+            let pieces = syllables(&word);
+            let stressed = stress(&word, &pieces);
+            pieces.into_iter().enumerate().map(move |(i, text)| (text, Some(i) == stressed)).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Grid units per syllable: stresses get two, the line's last syllable is
+// held for three, and `flat` gives everything one to squeeze a line in
+fn lengths(syllables: &[(String, bool)], flat: bool) -> Vec<u32> {
+    let last = syllables.len() - 1;
+    syllables
+        .iter()
+        .enumerate()
+        .map(|(i, (_, stressed))| match (flat, i == last, *stressed) {
+            (true, _, _) => 1,
+            (false, true, _) => 3,
+            (false, false, true) => 2,
+            (false, false, false) => 1,
+        })
+        .collect()
+}
+
+// Lays one line out within `line_beats`, leaving at least a step to breathe,
+// and moves it so its first stress lands on a beat. Returns (start, length)
+// per syllable, in beats from the line's start.
+fn place(syllables: &[(String, bool)], grid: f64, line_beats: f64) -> Option<Vec<(f64, f64)>> {
+    let mut grid = grid;
+    loop {
+        for flat in [false, true] {
+            let units = lengths(syllables, flat);
+            let total = units.iter().sum::<u32>() as f64 * grid;
+            if total > line_beats - grid {
+                continue;
+            }
+            let mut at = 0.0;
+            let mut timing: Vec<(f64, f64)> = units
+                .iter()
+                .map(|u| {
+                    let length = *u as f64 * grid;
+                    at += length;
+                    (at - length, length)
+                })
+                .collect();
+            // Syllables before the first stress become a pickup into it
+            let anchor = syllables.iter().position(|(_, stressed)| *stressed).map(|i| timing[i].0).unwrap_or(0.0);
+            let lead = anchor.ceil() - anchor;
+            if total + lead <= line_beats - grid {
+                for t in timing.iter_mut() {
+                    t.0 += lead;
+                }
+            }
+            return Some(timing);
+        }
+        grid /= 2.0;
+        if grid < MIN_GRID {
+            return None;
+        }
+    }
+}
+
+// Turns lyric lines into a one-note vocal rhythm with a note per syllable,
+// accented on stressed syllables, each line taking `bars_per_line` bars of
+// 4/4. With `output_path` it is also written there as a MIDI file and added
+// to the library; an existing file is only replaced with `overwrite`.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn generate_vocal_rhythm(
+    db: State<'_, Db>,
+    lyrics: String,
+    density: Density,
+    bars_per_line: Option<u32>,
+    bpm: Option<f64>,
+    pitch: Option<u8>,
+    output_path: Option<String>,
+    overwrite: Option<bool>,
+) -> Result<VocalRhythm, String> {
+    let bpm = bpm.unwrap_or(DEFAULT_BPM);
+    if !(20.0..=400.0).contains(&bpm) {
+        return Err("Tempo must be between 20 and 400 BPM".to_string());
+    }
+    let bars_per_line = bars_per_line.unwrap_or(DEFAULT_BARS_PER_LINE);
+    if !(1..=8).contains(&bars_per_line) {
+        return Err("Give each line 1 to 8 bars".to_string());
+    }
+    let pitch = pitch.unwrap_or(DEFAULT_PITCH).min(127);
+    let line_beats = bars_per_line as f64 * 4.0;
+
+    let mut lines = Vec::new();
+    let mut notes = Vec::new();
+    let mut at = 0.0;
+    for (number, text) in lyrics.lines().map(str::trim).filter(|l| !l.is_empty()).enumerate() {
+        let syllables = line_syllables(text);
+        if syllables.is_empty() {
+            continue;
+        }
+        let timing = place(&syllables, density.grid(), line_beats)
+            .ok_or_else(|| format!("Line {} has too many syllables for {} bars; give lines more bars", number + 1, bars_per_line))?;
+        let mut downbeat = true;
+        let syllables = syllables
+            .into_iter()
+            .zip(timing)
+            .map(|((text, stressed), (start, duration))| {
+                let velocity = match (stressed, downbeat) {
+                    (true, true) => DOWNBEAT_VELOCITY,
+                    (true, false) => STRESSED_VELOCITY,
+                    _ => UNSTRESSED_VELOCITY,
+                };
+                downbeat &= !stressed;
+                notes.push(Note { pitch, velocity, start: at + start, duration: duration * GATE, channel: 0 });
+                Syllable { text, stressed, start: at + start, duration }
+            })
+            .collect();
+        lines.push(RhythmLine { text: text.to_string(), syllables });
+        at += line_beats;
+    }
+    if notes.is_empty() {
+        return Err("There are no lyrics to set".to_string());
+    }
+
+    let path = match output_path {
+        Some(output_path) => {
+            let destination = paths::to_fs(&output_path);
+            if destination.exists() && !overwrite.unwrap_or(false) {
+                return Err(format!("{} already exists", output_path));
+            }
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            midi::write_notes(&destination, &notes, bpm, 4)?;
+            takes::index(&db.lock(), &destination)?;
+            Some(paths::display(&destination))
+        }
+        None => None,
+    };
+    Ok(VocalRhythm { lines, notes, beats: at, path })
+}
@@ -0,0 +1,376 @@
+use crate::analysis::NOTE_NAMES;
+use crate::audio;
+use crate::db::{self, Db};
+use crate::dsp;
+use crate::jobs::{Priority, Scheduler};
+use crate::paths;
+use crate::pitch;
+use crate::quarantine;
+use crate::reconcile;
+use crate::theory::{self, Scale};
+use rusqlite::{params, OptionalExtension};
+use std::path::Path;
+use tauri::State;
+
+const SAMPLE_RATE: u32 = 22_050;
+// 20 ms between readings: fine enough to show scoops and vibrato
+const HOP: usize = 441;
+// Low bass to high soprano
+const MIN_HZ: f32 = 70.0;
+const MAX_HZ: f32 = 1100.0;
+// Frames this far below the loudest are breaths, room and headphone bleed
+const SILENCE: f32 = 0.03;
+// Shorter runs on one tone are slides and scoops, not sung notes
+const MIN_NOTE_SECONDS: f64 = 0.08;
+// Inside this counts as in tune; most listeners can't hear less
+const IN_TUNE_CENTS: f32 = 20.0;
+// How many of the furthest-out notes the report lists
+const WORST_NOTES: usize = 8;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct PitchPoint {
+    time: f64,
+    // Fractional MIDI note; None where nothing is sung
+    pitch: Option<f32>,
+    // From the nearest scale tone
+    cents: Option<f32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SungNote {
+    start: f64,
+    end: f64,
+    // Scale tone it was heard as, "A3"
+    note: String,
+    // Median distance from that tone; positive is sharp
+    cents: f32,
+    // Spread within the note (vibrato, wavering), in cents
+    wobble: f32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct TuningReport {
+    path: String,
+    // Key the deviations are measured against; None means every semitone
+    key: Option<String>,
+    duration: f64,
+    // Share of the take with a voice in it
+    voiced: f32,
+    // Share of sung notes within IN_TUNE_CENTS
+    in_tune: f32,
+    mean_abs_cents: f32,
+    // Overall lean; positive is sharp
+    bias_cents: f32,
+    // How the lean moves over the take, cents per minute; a singer going
+    // flat as they tire shows up here
+    drift_cents_per_minute: f32,
+    notes: Vec<SungNote>,
+    // Indexes into `notes`, furthest out first
+    worst: Vec<usize>,
+    curve: Vec<PitchPoint>,
+    // One paragraph for people and for the assistant's context
+    summary: String,
+}
+
+fn midi_to_name(midi: i32) -> String {
+    format!("{}{}", NOTE_NAMES[midi.rem_euclid(12) as usize], midi.div_euclid(12) - 1)
+}
+
+// Every note of the key, or all twelve without one
+fn scale(key: Option<(usize, bool)>) -> [bool; 12] {
+    match key {
+        None => [true; 12],
+        Some((tonic, minor)) => theory::pitch_classes(tonic, if minor { Scale::Minor } else { Scale::Major }),
+    }
+}
+
+fn nearest_tone(pitch: f32, tones: &[bool; 12]) -> i32 {
+    let base = pitch.round() as i32;
+    [0, -1, 1, -2, 2]
+        .iter()
+        .map(|offset| base + offset)
+        .filter(|midi| tones[midi.rem_euclid(12) as usize])
+        .min_by(|a, b| (pitch - *a as f32).abs().total_cmp(&(pitch - *b as f32).abs()))
+        .unwrap_or(base)
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_by(f32::total_cmp);
+    values[values.len() / 2]
+}
+
+// Pitch readings every HOP samples with each frame's level, streamed so a
+// full-length vocal never sits in memory at once
+fn pitch_track(path: &Path) -> Result<Vec<(Option<f32>, f32)>, String> {
+    let frame_len = pitch::WINDOW + (SAMPLE_RATE as f32 / MIN_HZ) as usize + 1;
+    let mut resampler: Option<(u32, dsp::Resampler)> = None;
+    let mut mono = Vec::new();
+    let mut pending: Vec<f32> = Vec::new();
+    let mut frames = Vec::new();
+
+    let mut on_samples = |pending: &mut Vec<f32>, resampled: &[f32]| {
+        pending.extend_from_slice(resampled);
+        let mut offset = 0;
+        while offset + frame_len <= pending.len() {
+            let frame = &pending[offset..offset + frame_len];
+            let hz = pitch::yin(frame, SAMPLE_RATE, MIN_HZ, MAX_HZ).map(|(hz, _)| hz);
+            frames.push((hz.map(|hz| 69.0 + 12.0 * (hz / 440.0).log2()), dsp::rms(&frame[..pitch::WINDOW])));
+            offset += HOP;
+        }
+        pending.drain(..offset);
+    };
+
+    let mut resampled = Vec::new();
+    audio::stream_file(path, |block, rate, channels| {
+        let channels = channels.max(1);
+        mono.clear();
+        mono.extend(block.chunks(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32));
+        if resampler.as_ref().map(|(r, _)| *r != rate).unwrap_or(true) {
+            if let Some((_, mut old)) = resampler.take() {
+                resampled.clear();
+                old.finish(&mut resampled);
+                on_samples(&mut pending, &resampled);
+            }
+            resampler = Some((rate, dsp::Resampler::new(rate, SAMPLE_RATE)));
+        }
+        if let Some((_, r)) = resampler.as_mut() {
+            resampled.clear();
+            r.push(&mono, &mut resampled);
+            on_samples(&mut pending, &resampled);
+        }
+        true
+    })?;
+    if let Some((_, mut r)) = resampler.take() {
+        resampled.clear();
+        r.finish(&mut resampled);
+        on_samples(&mut pending, &resampled);
+    }
+    Ok(frames)
+}
+
+// Splits the voiced frames into notes, each with the tone it was heard as:
+// runs on the same scale tone, long enough to be sung rather than slid through
+fn find_notes(curve: &[PitchPoint], tones: &[bool; 12]) -> Vec<(i32, SungNote)> {
+    let seconds = HOP as f64 / SAMPLE_RATE as f64;
+    let mut notes = Vec::new();
+    let mut run: Vec<(f64, f32, i32)> = Vec::new();
+    let mut flush = |run: &mut Vec<(f64, f32, i32)>| {
+        if run.len() as f64 * seconds >= MIN_NOTE_SECONDS {
+            let tone = run[0].2;
+            let mut cents: Vec<f32> = run.iter().map(|(_, pitch, _)| (pitch - tone as f32) * 100.0).collect();
+            let mean = cents.iter().sum::<f32>() / cents.len() as f32;
+            let wobble = (cents.iter().map(|c| (c - mean).powi(2)).sum::<f32>() / cents.len() as f32).sqrt();
+            notes.push((
+                tone,
+                SungNote {
+                    start: run[0].0,
+                    end: run[run.len() - 1].0 + seconds,
+                    note: midi_to_name(tone),
+                    cents: median(&mut cents),
+                    wobble,
+                },
+            ));
+        }
+        run.clear();
+    };
+    for point in curve {
+        match point.pitch {
+            Some(pitch) => {
+                let tone = nearest_tone(pitch, tones);
+                if run.last().map(|last| last.2 != tone).unwrap_or(false) {
+                    flush(&mut run);
+                }
+                run.push((point.time, pitch, tone));
+            }
+            None => flush(&mut run),
+        }
+    }
+    flush(&mut run);
+    notes
+}
+
+// Least-squares slope of each note's deviation against its time
+fn drift_per_minute(notes: &[SungNote]) -> f32 {
+    if notes.len() < 2 {
+        return 0.0;
+    }
+    let n = notes.len() as f64;
+    let times: Vec<f64> = notes.iter().map(|note| (note.start + note.end) / 2.0).collect();
+    let mean_t = times.iter().sum::<f64>() / n;
+    let mean_c = notes.iter().map(|note| note.cents as f64).sum::<f64>() / n;
+    let covariance: f64 = times.iter().zip(notes).map(|(t, note)| (t - mean_t) * (note.cents as f64 - mean_c)).sum();
+    let variance: f64 = times.iter().map(|t| (t - mean_t).powi(2)).sum();
+    if variance < 1e-9 {
+        0.0
+    } else {
+        (covariance / variance * 60.0) as f32
+    }
+}
+
+fn summarize(report: &TuningReport) -> String {
+    if report.notes.is_empty() {
+        return "No sung notes found; this may not be a vocal take.".to_string();
+    }
+    let against = report.key.as_ref().map(|k| format!("the {} scale", k)).unwrap_or_else(|| "the nearest semitone".to_string());
+    let mut text = format!(
+        "{} notes measured against {}. {:.0}% are within {:.0} cents; average error {:.0} cents.",
+        report.notes.len(),
+        against,
+        report.in_tune * 100.0,
+        IN_TUNE_CENTS,
+        report.mean_abs_cents
+    );
+    if report.bias_cents.abs() >= 5.0 {
+        let lean = if report.bias_cents > 0.0 { "sharp" } else { "flat" };
+        text.push_str(&format!(" Leans {:.0} cents {} overall.", report.bias_cents.abs(), lean));
+    }
+    let total = report.drift_cents_per_minute * report.duration as f32 / 60.0;
+    if total.abs() >= 10.0 {
+        let direction = if total > 0.0 { "sharp" } else { "flat" };
+        text.push_str(&format!(" Drifts {:.0} cents {} from start to end.", total.abs(), direction));
+    }
+    let worst: Vec<String> = report
+        .worst
+        .iter()
+        .take(3)
+        .map(|&i| &report.notes[i])
+        .filter(|note| note.cents.abs() > IN_TUNE_CENTS)
+        .map(|note| format!("{} at {:.1}s ({:+.0} cents)", note.note, note.start, note.cents))
+        .collect();
+    if !worst.is_empty() {
+        text.push_str(&format!(" Furthest out: {}.", worst.join(", ")));
+    }
+    text
+}
+
+// The pitch track with quiet frames unvoiced and deviations from `tones`
+fn voiced_curve(frames: &[(Option<f32>, f32)], tones: &[bool; 12]) -> Vec<PitchPoint> {
+    let loudest = frames.iter().fold(0.0f32, |m, f| m.max(f.1));
+    let seconds = HOP as f64 / SAMPLE_RATE as f64;
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, (pitch, level))| {
+            let pitch = pitch.filter(|_| *level >= loudest * SILENCE);
+            PitchPoint {
+                time: i as f64 * seconds,
+                pitch,
+                cents: pitch.map(|p| (p - nearest_tone(p, tones) as f32) * 100.0),
+            }
+        })
+        .collect()
+}
+
+// The notes of a sung or hummed phrase as MIDI pitches, for melody searches
+pub fn sung_pitches(path: &Path) -> Result<Vec<i32>, String> {
+    let tones = scale(None);
+    let curve = voiced_curve(&pitch_track(path)?, &tones);
+    Ok(find_notes(&curve, &tones).into_iter().map(|(tone, _)| tone).collect())
+}
+
+pub fn analyze(path: &str, key: Option<String>) -> Result<TuningReport, String> {
+    let frames = pitch_track(&paths::to_fs(path))?;
+    let tones = scale(key.as_deref().and_then(reconcile::parse_key));
+    let seconds = HOP as f64 / SAMPLE_RATE as f64;
+    let curve = voiced_curve(&frames, &tones);
+    let notes: Vec<SungNote> = find_notes(&curve, &tones).into_iter().map(|(_, note)| note).collect();
+
+    let count = notes.len().max(1) as f32;
+    let mut worst: Vec<usize> = (0..notes.len()).collect();
+    worst.sort_by(|a, b| notes[*b].cents.abs().total_cmp(&notes[*a].cents.abs()));
+    worst.truncate(WORST_NOTES);
+    let mut report = TuningReport {
+        path: path.to_string(),
+        key,
+        duration: curve.len() as f64 * seconds,
+        voiced: curve.iter().filter(|p| p.pitch.is_some()).count() as f32 / curve.len().max(1) as f32,
+        in_tune: notes.iter().filter(|n| n.cents.abs() <= IN_TUNE_CENTS).count() as f32 / count,
+        mean_abs_cents: notes.iter().map(|n| n.cents.abs()).sum::<f32>() / count,
+        bias_cents: notes.iter().map(|n| n.cents).sum::<f32>() / count,
+        drift_cents_per_minute: drift_per_minute(&notes),
+        notes,
+        worst,
+        curve,
+        summary: String::new(),
+    };
+    report.summary = summarize(&report);
+    Ok(report)
+}
+
+// Summary of the latest report for `path`, for the assistant's context,
+// while the file is as it was when analyzed
+pub fn summary_for(conn: &rusqlite::Connection, path: &str) -> Result<Option<String>, String> {
+    conn.query_row(
+        "SELECT t.summary FROM tuning_reports t JOIN files f ON f.path = t.path WHERE t.path = ?1 AND t.modified = f.modified",
+        params![path],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+// Pitch curve of a vocal take and how far each sung note sits from the
+// nearest tone of `key` (default: the file's key, else any semitone), with
+// overall lean and drift. Kept per version of an indexed file so the
+// assistant can refer to it.
+#[tauri::command]
+pub async fn analyze_tuning(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    path: String,
+    key: Option<String>,
+    force: Option<bool>,
+) -> Result<TuningReport, String> {
+    let key = match key {
+        Some(key) => Some(reconcile::normalize_key(&key).ok_or_else(|| format!("Unknown key: {}", key))?),
+        None => db
+            .lock()
+            .query_row(
+                "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = 'key'",
+                params![path],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .and_then(|k| reconcile::normalize_key(&k)),
+    };
+
+    let modified: Option<i64> = db
+        .lock()
+        .query_row("SELECT modified FROM files WHERE path = ?1", params![path], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let (Some(modified), false) = (modified, force.unwrap_or(false)) {
+        let cached: Option<String> = db
+            .lock()
+            .query_row(
+                "SELECT data FROM tuning_reports WHERE path = ?1 AND key = ?2 AND modified = ?3",
+                params![path, key.clone().unwrap_or_default(), modified],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if let Some(report) = cached.and_then(|json| serde_json::from_str(&json).ok()) {
+            return Ok(report);
+        }
+    }
+
+    let db = db.inner().clone();
+    let label = format!("Tuning report {}", path);
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |_| {
+            let report = quarantine::guard(&db, &path, || analyze(&path, key.clone()))?;
+            // Files outside the library have no version to check against
+            if let Some(modified) = modified {
+                let json = serde_json::to_string(&report).map_err(|e| e.to_string())?;
+                db.lock()
+                    .execute(
+                        "INSERT OR REPLACE INTO tuning_reports (path, key, modified, data, summary, analyzed_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        params![path, key.unwrap_or_default(), modified, json, report.summary, db::now()],
+                    )
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(report)
+        })
+        .await
+}
@@ -0,0 +1,232 @@
+use crate::db::Db;
+use crate::paths;
+use crate::roots;
+use crate::sandbox::Sandbox;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tauri::State;
+
+// Large saves (bounces, stems) arrive in chunks instead of one Vec<u8> so
+// the IPC bridge never has to hold a whole multi-hundred-MB file.
+struct Upload {
+    destination: PathBuf,
+    temp_path: PathBuf,
+    file: File,
+    next_index: u64,
+    bytes_written: u64,
+    hasher: Sha256,
+}
+
+// Each upload has its own lock, so writing one chunk doesn't hold up the
+// others or anything asking for the list
+#[derive(Default)]
+pub struct Uploads {
+    active: Mutex<HashMap<String, Arc<Mutex<Upload>>>>,
+    next_id: AtomicU64,
+}
+
+fn lock_upload(upload: &Mutex<Upload>) -> MutexGuard<'_, Upload> {
+    upload.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Uploads {
+    fn active(&self) -> MutexGuard<'_, HashMap<String, Arc<Mutex<Upload>>>> {
+        self.active.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn get(&self, id: &str) -> Result<Arc<Mutex<Upload>>, String> {
+        self.active().get(id).cloned().ok_or_else(|| format!("Unknown upload: {}", id))
+    }
+
+    // Takes an upload out once no chunk is being written to it
+    fn take(&self, id: &str) -> Result<Upload, String> {
+        let upload = self.active().remove(id).ok_or_else(|| format!("Unknown upload: {}", id))?;
+        Arc::try_unwrap(upload)
+            .map(|upload| upload.into_inner().unwrap_or_else(|e| e.into_inner()))
+            .map_err(|_| "A chunk is still being written".to_string())
+    }
+
+    // Unique per save, so two saves to the same file never share a temp file
+    fn next_id(&self) -> String {
+        format!("{}-{}", crate::db::now(), self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct SaveResult {
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+fn temp_path_for(destination: &Path, id: &str) -> PathBuf {
+    // Stay next to the destination so the final rename never crosses volumes
+    let name = destination
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    destination.with_file_name(format!(".{}.{}.part", name, id))
+}
+
+// Where a save may go: inside the library's folders and not on a
+// read-only root
+fn destination_for(sandbox: &Sandbox, db: &Db, path: &str) -> Result<PathBuf, String> {
+    let destination = sandbox.check_dest(&paths::to_fs(path))?;
+    roots::ensure_writable(&db.lock(), &[&paths::display(&destination)])?;
+    Ok(destination)
+}
+
+#[tauri::command]
+pub async fn begin_save(sandbox: State<'_, Sandbox>, db: State<'_, Db>, uploads: State<'_, Uploads>, path: String) -> Result<String, String> {
+    let destination = destination_for(&sandbox, &db, &path)?;
+    let id = uploads.next_id();
+    let temp_path = temp_path_for(&destination, &id);
+
+    let upload = tokio::task::spawn_blocking(move || {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let file = File::create(&temp_path).map_err(|e| format!("Failed to create temp file: {}", e))?;
+        Ok::<_, String>(Upload {
+            destination,
+            temp_path,
+            file,
+            next_index: 0,
+            bytes_written: 0,
+            hasher: Sha256::new(),
+        })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))??;
+
+    uploads.active().insert(id.clone(), Arc::new(Mutex::new(upload)));
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn append_chunk(
+    uploads: State<'_, Uploads>,
+    upload_id: String,
+    index: u64,
+    data: Vec<u8>,
+    checksum: String,
+) -> Result<u64, String> {
+    let upload = uploads.get(&upload_id)?;
+    tokio::task::spawn_blocking(move || {
+        let mut upload = lock_upload(&upload);
+        if index != upload.next_index {
+            return Err(format!("Expected chunk {}, got {}", upload.next_index, index));
+        }
+
+        let actual = hex::encode(Sha256::digest(&data));
+        if !actual.eq_ignore_ascii_case(checksum.trim()) {
+            // The frontend can resend the same index
+            return Err(format!("Checksum mismatch for chunk {}", index));
+        }
+
+        upload.file.write_all(&data).map_err(|e| format!("Failed to write chunk: {}", e))?;
+        upload.hasher.update(&data);
+        upload.next_index += 1;
+        upload.bytes_written += data.len() as u64;
+        Ok(upload.bytes_written)
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn finish_save(uploads: State<'_, Uploads>, upload_id: String, checksum: Option<String>) -> Result<SaveResult, String> {
+    let upload = uploads.take(&upload_id)?;
+    tokio::task::spawn_blocking(move || {
+        let Upload { destination, temp_path, mut file, bytes_written, hasher, .. } = upload;
+        let sha256 = hex::encode(hasher.finalize());
+
+        if let Some(expected) = checksum {
+            if !sha256.eq_ignore_ascii_case(expected.trim()) {
+                drop(file);
+                let _ = fs::remove_file(&temp_path);
+                return Err("Checksum mismatch for completed file".to_string());
+            }
+        }
+
+        file.flush().map_err(|e| e.to_string())?;
+        file.sync_all().map_err(|e| e.to_string())?;
+        drop(file);
+
+        fs::rename(&temp_path, &destination).map_err(|e| format!("Failed to finalize file: {}", e))?;
+
+        Ok(SaveResult {
+            path: paths::display(&destination),
+            size: bytes_written,
+            sha256,
+        })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
+
+#[tauri::command]
+pub async fn abort_save(uploads: State<'_, Uploads>, upload_id: String) -> Result<(), String> {
+    let Some(upload) = uploads.active().remove(&upload_id) else { return Ok(()) };
+    // Waits out a chunk still being written before the file goes
+    tokio::task::spawn_blocking(move || {
+        let temp_path = lock_upload(&upload).temp_path.clone();
+        drop(upload);
+        let _ = fs::remove_file(&temp_path);
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))
+}
+
+// For files the frontend already has on disk (e.g. a recorder temp file):
+// copy in the backend instead of shipping the bytes over IPC.
+#[tauri::command]
+pub async fn save_file_from_path(
+    sandbox: State<'_, Sandbox>,
+    db: State<'_, Db>,
+    uploads: State<'_, Uploads>,
+    source: String,
+    path: String,
+) -> Result<SaveResult, String> {
+    let source = sandbox.check(&paths::to_fs(&source))?;
+    let destination = destination_for(&sandbox, &db, &path)?;
+    let id = uploads.next_id();
+    tokio::task::spawn_blocking(move || {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+
+        let temp_path = temp_path_for(&destination, &id);
+        let mut input = File::open(&source).map_err(|e| format!("Failed to open source: {}", e))?;
+        let mut output = File::create(&temp_path).map_err(|e| format!("Failed to create temp file: {}", e))?;
+
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 1 << 20];
+        let mut size = 0u64;
+        loop {
+            let read = input.read(&mut buffer).map_err(|e| e.to_string())?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            output.write_all(&buffer[..read]).map_err(|e| e.to_string())?;
+            size += read as u64;
+        }
+        output.sync_all().map_err(|e| e.to_string())?;
+        drop(output);
+
+        fs::rename(&temp_path, &destination).map_err(|e| format!("Failed to finalize file: {}", e))?;
+        Ok(SaveResult {
+            path: paths::display(&destination),
+            size,
+            sha256: hex::encode(hasher.finalize()),
+        })
+    })
+    .await
+    .map_err(|e| format!("Task failed: {}", e))?
+}
@@ -0,0 +1,142 @@
+use crate::ai::{ChatMessage, ChatReply};
+use crate::db::{self, Db};
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tauri::State;
+use tiktoken_rs::CoreBPE;
+
+// USD per million tokens (input, output). Longest matching prefix wins, so
+// "gpt-4o-mini" isn't billed as "gpt-4o".
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("gemini-2.0-flash", 0.10, 0.40),
+    ("gemini-1.5-flash", 0.075, 0.30),
+    ("gemini-1.5-pro", 1.25, 5.00),
+];
+
+// Chat formats wrap every message in a few role/separator tokens, and the
+// reply is primed with a few more
+const TOKENS_PER_MESSAGE: usize = 3;
+const TOKENS_PER_REPLY: usize = 3;
+
+fn prices(model: &str) -> Option<(f64, f64)> {
+    PRICES
+        .iter()
+        .filter(|(prefix, _, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _, _)| prefix.len())
+        .map(|(_, input, output)| (*input, *output))
+}
+
+pub fn cost(model: &str, prompt_tokens: u64, completion_tokens: u64) -> Option<f64> {
+    prices(model).map(|(input, output)| (prompt_tokens as f64 * input + completion_tokens as f64 * output) / 1_000_000.0)
+}
+
+// Loaded BPE tables, which take a moment to build, and whether each is the
+// model's own or the cl100k stand-in
+#[derive(Default)]
+pub struct Tokenizers {
+    loaded: Mutex<HashMap<String, (Arc<CoreBPE>, bool)>>,
+}
+
+impl Tokenizers {
+    fn for_model(&self, model: &str) -> Result<(Arc<CoreBPE>, bool), String> {
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = loaded.get(model) {
+            return Ok(entry.clone());
+        }
+        let (bpe, exact) = match tiktoken_rs::get_bpe_from_model(model) {
+            Ok(bpe) => (bpe, true),
+            Err(_) => (tiktoken_rs::cl100k_base().map_err(|e| e.to_string())?, false),
+        };
+        let entry = (Arc::new(bpe), exact);
+        loaded.insert(model.to_string(), entry.clone());
+        Ok(entry)
+    }
+
+    // Exact for models tiktoken knows (OpenAI's). Gemini's tokenizer isn't
+    // published, so its counts use cl100k as a stand-in, which lands within
+    // ~10% for English.
+    pub fn count(&self, model: &str, messages: &[ChatMessage]) -> Result<(u64, bool), String> {
+        let (bpe, exact) = self.for_model(model)?;
+        let tokens: usize = messages
+            .iter()
+            .map(|m| TOKENS_PER_MESSAGE + bpe.encode_with_special_tokens(&m.role).len() + bpe.encode_with_special_tokens(&m.content).len())
+            .sum();
+        Ok(((tokens + TOKENS_PER_REPLY) as u64, exact))
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct TokenEstimate {
+    prompt_tokens: u64,
+    exact: bool,
+    // Input cost only; the reply's length isn't known yet
+    estimated_cost: Option<f64>,
+    input_price_per_million: Option<f64>,
+    output_price_per_million: Option<f64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ConversationUsage {
+    conversation_id: String,
+    requests: i64,
+    prompt_tokens: i64,
+    completion_tokens: i64,
+    cost: f64,
+}
+
+pub fn record(conn: &Connection, conversation_id: &str, reply: &ChatReply, prompt_tokens: u64, completion_tokens: u64) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO ai_usage (conversation_id, provider, model, prompt_tokens, completion_tokens, cost, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            conversation_id,
+            reply.provider,
+            reply.model,
+            prompt_tokens as i64,
+            completion_tokens as i64,
+            cost(&reply.model, prompt_tokens, completion_tokens).unwrap_or(0.0),
+            db::now()
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn estimate_tokens(tokenizers: State<'_, Tokenizers>, messages: Vec<ChatMessage>, model: String) -> Result<TokenEstimate, String> {
+    let (prompt_tokens, exact) = tokenizers.count(&model, &messages)?;
+    let prices = prices(&model);
+    Ok(TokenEstimate {
+        prompt_tokens,
+        exact,
+        estimated_cost: cost(&model, prompt_tokens, 0),
+        input_price_per_million: prices.map(|p| p.0),
+        output_price_per_million: prices.map(|p| p.1),
+    })
+}
+
+#[tauri::command]
+pub async fn get_conversation_usage(db: State<'_, Db>, conversation_id: String) -> Result<ConversationUsage, String> {
+    db.lock()
+        .query_row(
+            "SELECT COUNT(*), COALESCE(SUM(prompt_tokens), 0), COALESCE(SUM(completion_tokens), 0), COALESCE(SUM(cost), 0)
+             FROM ai_usage WHERE conversation_id = ?1",
+            params![conversation_id],
+            |row| {
+                Ok(ConversationUsage {
+                    conversation_id: conversation_id.clone(),
+                    requests: row.get(0)?,
+                    prompt_tokens: row.get(1)?,
+                    completion_tokens: row.get(2)?,
+                    cost: row.get(3)?,
+                })
+            },
+        )
+        .map_err(|e| e.to_string())
+}
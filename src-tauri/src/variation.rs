@@ -0,0 +1,239 @@
+use crate::analysis;
+use crate::db::Db;
+use crate::derived;
+use crate::jobs::{Priority, Scheduler};
+use crate::midi::{self, Note};
+use crate::paths;
+use crate::random::Rng;
+use crate::theory::Key;
+use midly::TrackEventKind;
+use rusqlite::{params, OptionalExtension};
+use tauri::State;
+
+const SOURCE: &str = "variation";
+const MAX_VARIATIONS: usize = 16;
+// Displacement moves the line by whole steps of this many beats
+const DISPLACE_GRID: f64 = 0.25;
+// Share of notes mutate nudges to a neighbouring scale tone
+const MUTATE_SHARE: f64 = 0.3;
+// Tries per wanted variation before settling for fewer distinct ones
+const ATTEMPTS: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    // Mirror the intervals around the first note, in the key
+    Invert,
+    // Play it backwards
+    Retrograde,
+    // Shift the rhythm against the bar, wrapping around the clip
+    Displace,
+    // Move the whole line up or down the scale
+    Transpose,
+    // Nudge some notes to neighbouring scale tones
+    Mutate,
+}
+
+#[derive(serde::Serialize)]
+pub struct Variation {
+    path: String,
+    // What was done, in order: "invert", "transpose +2", ...
+    steps: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Variations {
+    // The key transformations stayed in, detected or given
+    key: String,
+    variations: Vec<Variation>,
+}
+
+// A pitch as a scale degree counted from the tonic, plus any chromatic
+// alteration above that degree
+fn to_degree(pitch: i32, key: &Key) -> (i32, i32) {
+    let steps = key.scale().intervals();
+    let relative = pitch - key.tonic as i32;
+    let within = relative.rem_euclid(12);
+    let index = steps.iter().rposition(|s| *s as i32 <= within).unwrap_or(0);
+    (relative.div_euclid(12) * 7 + index as i32, within - steps[index] as i32)
+}
+
+fn from_degree(degree: i32, alter: i32, key: &Key) -> u8 {
+    let steps = key.scale().intervals();
+    let pitch = key.tonic as i32 + degree.div_euclid(7) * 12 + steps[degree.rem_euclid(7) as usize] as i32 + alter;
+    pitch.clamp(0, 127) as u8
+}
+
+fn detect_key(db: &Db, path: &str, notes: &[Note]) -> Option<Key> {
+    let resolved: Option<String> = db
+        .lock()
+        .query_row(
+            "SELECT value FROM attribute_resolution WHERE path = ?1 AND attribute = 'key'",
+            params![path],
+            |row| row.get(0),
+        )
+        .optional()
+        .ok()
+        .flatten();
+    if let Some(key) = resolved.and_then(|k| Key::parse(&k).ok()) {
+        return Some(key);
+    }
+    // Pitch classes weighted by how long they sound
+    let mut profile = [0.0f32; 12];
+    for note in notes {
+        profile[note.pitch as usize % 12] += note.duration as f32;
+    }
+    analysis::estimate_key(&profile).and_then(|(name, _)| Key::parse(&name).ok())
+}
+
+// Bends, pressure and slides move in time with the notes they shape; program
+// changes and other controllers stay where they were
+fn move_expression(controls: &mut [(f64, TrackEventKind<'static>)], to: impl Fn(f64) -> f64) {
+    for (beat, kind) in controls.iter_mut() {
+        if midi::is_expression(kind) {
+            *beat = to(*beat);
+        }
+    }
+}
+
+fn apply(
+    operation: Operation,
+    notes: &mut [Note],
+    controls: &mut [(f64, TrackEventKind<'static>)],
+    key: &Key,
+    length: f64,
+    rng: &mut Rng,
+) -> String {
+    match operation {
+        Operation::Invert => {
+            let (axis, _) = to_degree(notes[0].pitch as i32, key);
+            for note in notes.iter_mut() {
+                let (degree, alter) = to_degree(note.pitch as i32, key);
+                note.pitch = from_degree(2 * axis - degree, -alter, key);
+            }
+            "invert".to_string()
+        }
+        Operation::Retrograde => {
+            for note in notes.iter_mut() {
+                note.start = (length - note.start - note.duration).max(0.0);
+            }
+            move_expression(controls, |beat| (length - beat).max(0.0));
+            "retrograde".to_string()
+        }
+        Operation::Displace => {
+            let steps = 1 + rng.below(3) as i32;
+            let steps = if rng.unit() < 0.5 { -steps } else { steps };
+            for note in notes.iter_mut() {
+                note.start = (note.start + steps as f64 * DISPLACE_GRID).rem_euclid(length);
+            }
+            move_expression(controls, |beat| (beat + steps as f64 * DISPLACE_GRID).rem_euclid(length));
+            format!("displace {:+} steps", steps)
+        }
+        Operation::Transpose => {
+            let amount = 1 + rng.below(4) as i32;
+            let amount = if rng.unit() < 0.5 { -amount } else { amount };
+            for note in notes.iter_mut() {
+                let (degree, alter) = to_degree(note.pitch as i32, key);
+                note.pitch = from_degree(degree + amount, alter, key);
+            }
+            format!("transpose {:+}", amount)
+        }
+        Operation::Mutate => {
+            let mut changed = 0;
+            for note in notes.iter_mut() {
+                if rng.unit() < MUTATE_SHARE {
+                    let (degree, _) = to_degree(note.pitch as i32, key);
+                    let step = [-2, -1, 1, 2][rng.below(4)];
+                    // Lands on a scale tone, dropping any alteration
+                    note.pitch = from_degree(degree + step, 0, key);
+                    changed += 1;
+                }
+            }
+            format!("mutate {} notes", changed)
+        }
+    }
+}
+
+// Writes up to `count` different variations of a melodic MIDI clip next to
+// it, each from some of `operations` with randomized amounts, staying in the
+// clip's key (its resolved key, or one detected from its notes). `seed`
+// makes the set repeatable. Program changes and controllers carry over;
+// bends and pressure follow the notes unless `expression` is "strip".
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn vary_melody(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    midi_path: String,
+    operations: Vec<Operation>,
+    count: usize,
+    key: Option<String>,
+    seed: Option<u64>,
+    expression: Option<midi::Expression>,
+    dest_dir: Option<String>,
+) -> Result<Variations, String> {
+    if operations.is_empty() {
+        return Err("Pick at least one operation".to_string());
+    }
+    if count == 0 || count > MAX_VARIATIONS {
+        return Err(format!("Make 1 to {} variations", MAX_VARIATIONS));
+    }
+    let given = key.map(|k| Key::parse(&k)).transpose()?;
+    let db = db.inner().clone();
+    let label = format!("Vary {}", midi_path);
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |_| {
+            let file = midi::read(&paths::to_fs(&midi_path))?;
+            let notes = file.notes();
+            if notes.is_empty() {
+                return Err("That MIDI file has no notes".to_string());
+            }
+            let key = match given {
+                Some(key) => key,
+                None => detect_key(&db, &midi_path, &notes).ok_or_else(|| "Couldn't tell the clip's key; pass one".to_string())?,
+            };
+            // Whole bars, so displacement and retrograde keep the downbeat
+            let bar = file.beats_per_bar.max(1) as f64;
+            let end = notes.iter().map(|n| n.start + n.duration).fold(0.0, f64::max);
+            let length = ((end / bar).ceil() * bar).max(bar);
+
+            let mut rng = Rng::new(seed);
+            let mut made: Vec<(Vec<Note>, Vec<(f64, TrackEventKind<'static>)>, Vec<String>)> = Vec::new();
+            for _ in 0..count * ATTEMPTS {
+                if made.len() == count {
+                    break;
+                }
+                // A random non-empty subset of the operations, in the order given
+                let mut chosen: Vec<Operation> = operations.iter().copied().filter(|_| rng.unit() < 0.5).collect();
+                if chosen.is_empty() {
+                    chosen.push(operations[rng.below(operations.len())]);
+                }
+                let mut varied = notes.clone();
+                let mut controls = file.controls.clone();
+                let steps = chosen.iter().map(|op| apply(*op, &mut varied, &mut controls, &key, length, &mut rng)).collect();
+                varied.sort_by(|a, b| a.start.total_cmp(&b.start));
+                let same = |other: &[Note]| other.iter().zip(&varied).all(|(a, b)| a.pitch == b.pitch && (a.start - b.start).abs() < 1e-6);
+                if same(&notes) || made.iter().any(|(other, ..)| same(other)) {
+                    continue;
+                }
+                made.push((varied, controls, steps));
+            }
+
+            let bpm = file.bpm.unwrap_or(120.0);
+            let attributes = derived::carried_attributes(&db, &midi_path)?;
+            let variations = made
+                .into_iter()
+                .enumerate()
+                .map(|(i, (varied, controls, steps))| {
+                    let mut controls: Vec<_> = controls.into_iter().map(|(beat, kind)| (midi::beats_to_ticks(beat), kind)).collect();
+                    midi::apply_expression(&mut controls, expression.unwrap_or_default());
+                    let save = |path: &std::path::Path| midi::write_performance(path, &varied, controls.clone(), bpm, file.beats_per_bar);
+                    let suffix = format!("variation {}", i + 1);
+                    let path = derived::write_midi(&db, &midi_path, dest_dir.as_deref(), &suffix, save, SOURCE, &attributes)?;
+                    Ok(Variation { path, steps })
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(Variations { key: key.name(), variations })
+        })
+        .await
+}
@@ -0,0 +1,288 @@
+use crate::db::Db;
+use crate::derived;
+use crate::jobs::{Priority, Scheduler};
+use crate::midi;
+use crate::paths;
+use crate::random::Rng;
+use midly::num::u7;
+use midly::{MidiMessage, Smf, TrackEventKind};
+use std::fs;
+use std::path::Path;
+use tauri::State;
+
+const SOURCE: &str = "velocity";
+// Compressed pulls velocities this far toward the file's average
+const COMPRESS_RATIO: f32 = 0.5;
+// Accent on beat: the bar's first beat, other beats, everything between
+const DOWNBEAT_GAIN: f32 = 1.25;
+const BEAT_GAIN: f32 = 1.1;
+const OFFBEAT_GAIN: f32 = 0.85;
+// Notes this close to a beat, in beats, count as on it
+const BEAT_TOLERANCE: f64 = 0.05;
+// Crescendo per bar swells from the first gain to the second across each bar
+const CRESCENDO_FROM: f32 = 0.75;
+const CRESCENDO_TO: f32 = 1.15;
+// At full humanize velocities wander up to this far
+const HUMANIZE_VELOCITY: f64 = 12.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VelocityCurve {
+    // Evens the dynamics out toward the average
+    Compressed,
+    // Leans on the beats, hardest on the bar's first
+    AccentOnBeat,
+    // Builds across every bar
+    CrescendoPerBar,
+}
+
+#[derive(serde::Serialize)]
+pub struct VelocityStats {
+    notes: usize,
+    min: u8,
+    max: u8,
+    mean: f32,
+    // Standard deviation: how much the dynamics move
+    spread: f32,
+}
+
+#[derive(serde::Serialize)]
+pub struct VelocityResult {
+    path: String,
+    before: Option<VelocityStats>,
+    after: Option<VelocityStats>,
+    // Set when the reshaped copy was written
+    output: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct VelocityBatch {
+    // Pass back to apply to get the humanize wander the preview showed
+    seed: u64,
+    files: Vec<VelocityResult>,
+}
+
+fn stats(velocities: &[u8]) -> Option<VelocityStats> {
+    if velocities.is_empty() {
+        return None;
+    }
+    let n = velocities.len() as f32;
+    let mean = velocities.iter().map(|v| *v as f32).sum::<f32>() / n;
+    let variance = velocities.iter().map(|v| (*v as f32 - mean).powi(2)).sum::<f32>() / n;
+    Some(VelocityStats {
+        notes: velocities.len(),
+        min: *velocities.iter().min().unwrap_or(&0),
+        max: *velocities.iter().max().unwrap_or(&0),
+        mean,
+        spread: variance.sqrt(),
+    })
+}
+
+fn is_midi(path: &Path) -> bool {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    extension == "mid" || extension == "midi"
+}
+
+fn midi_files(dir: &Path, found: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            midi_files(&path, found)?;
+        } else if is_midi(&path) {
+            found.push(paths::display(&path));
+        }
+    }
+    Ok(())
+}
+
+// Files as given, with folders replaced by the MIDI files anywhere inside
+fn expand(inputs: &[String]) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = paths::to_fs(input);
+        if path.is_dir() {
+            let mut found = Vec::new();
+            midi_files(&path, &mut found).map_err(|e| format!("Failed to read {}: {}", input, e))?;
+            found.sort();
+            files.extend(found);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    files.dedup();
+    Ok(files)
+}
+
+// The curve's velocity for a note at `beat`, before blending
+fn target(curve: VelocityCurve, velocity: f32, beat: f64, beats_per_bar: f64, mean: f32) -> f32 {
+    let in_bar = beat.rem_euclid(beats_per_bar);
+    match curve {
+        VelocityCurve::Compressed => mean + (velocity - mean) * COMPRESS_RATIO,
+        VelocityCurve::AccentOnBeat => {
+            let nearest = in_bar.round();
+            let gain = if (in_bar - nearest).abs() > BEAT_TOLERANCE {
+                OFFBEAT_GAIN
+            } else if nearest == 0.0 || nearest == beats_per_bar {
+                DOWNBEAT_GAIN
+            } else {
+                BEAT_GAIN
+            };
+            velocity * gain
+        }
+        VelocityCurve::CrescendoPerBar => {
+            let through = (in_bar / beats_per_bar) as f32;
+            velocity * (CRESCENDO_FROM + (CRESCENDO_TO - CRESCENDO_FROM) * through)
+        }
+    }
+}
+
+// Reshapes every note-on in the file, returning the edited file and the
+// velocities before and after
+fn reshape<'a>(
+    smf: &Smf<'a>,
+    curve: VelocityCurve,
+    amount: f32,
+    humanize: f64,
+    expression: midi::Expression,
+    rng: &mut Rng,
+) -> Result<(Smf<'a>, Vec<u8>, Vec<u8>), String> {
+    let ppq = midi::ticks_per_beat(smf)?;
+    let beats_per_bar = midi::beats_per_bar(smf).max(1) as f64;
+    let mut tracks: Vec<_> = smf.tracks.iter().map(|t| midi::absolute(t)).collect();
+    for events in &mut tracks {
+        midi::apply_expression(events, expression);
+    }
+    let mut before = Vec::new();
+    for (_, kind) in tracks.iter().flatten() {
+        if let TrackEventKind::Midi { message: MidiMessage::NoteOn { vel, .. }, .. } = kind {
+            if vel.as_int() > 0 {
+                before.push(vel.as_int());
+            }
+        }
+    }
+    let mean = before.iter().map(|v| *v as f32).sum::<f32>() / before.len().max(1) as f32;
+
+    let mut after = Vec::with_capacity(before.len());
+    for (tick, kind) in tracks.iter_mut().flatten() {
+        let TrackEventKind::Midi { message: MidiMessage::NoteOn { vel, .. }, .. } = kind else { continue };
+        if vel.as_int() == 0 {
+            continue;
+        }
+        let velocity = vel.as_int() as f32;
+        let shaped = velocity + (target(curve, velocity, *tick as f64 / ppq, beats_per_bar, mean) - velocity) * amount;
+        let shaped = (shaped as f64 + rng.signed() * humanize * HUMANIZE_VELOCITY).round().clamp(1.0, 127.0) as u8;
+        *vel = u7::new(shaped);
+        after.push(shaped);
+    }
+    let tracks = tracks.into_iter().map(midi::to_track).collect();
+    Ok((Smf { header: smf.header, tracks }, before, after))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_batch(
+    db: &Db,
+    inputs: &[String],
+    curve: VelocityCurve,
+    amount: f32,
+    humanize: f64,
+    expression: midi::Expression,
+    seed: u64,
+    write: bool,
+    dest_dir: Option<&str>,
+) -> Result<VelocityBatch, String> {
+    let files = expand(inputs)?;
+    if files.is_empty() {
+        return Err("No MIDI files to reshape".to_string());
+    }
+    let results = files
+        .into_iter()
+        .map(|path| {
+            // Every file starts the stream over, so preview and apply agree
+            // file by file
+            let mut rng = Rng::new(Some(seed));
+            let outcome = fs::read(paths::to_fs(&path))
+                .map_err(|e| format!("Failed to read {}: {}", path, e))
+                .and_then(|bytes| {
+                    let smf = Smf::parse(&bytes).map_err(|e| format!("Failed to parse MIDI file: {}", e))?;
+                    let (edited, before, after) = reshape(&smf, curve, amount, humanize, expression, &mut rng)?;
+                    let output = if write {
+                        let save = |out: &Path| edited.save(out).map_err(|e| format!("Failed to write MIDI file: {}", e));
+                        let attributes = derived::carried_attributes(db, &path)?;
+                        Some(derived::write_midi(db, &path, dest_dir, "velocity", save, SOURCE, &attributes)?)
+                    } else {
+                        None
+                    };
+                    Ok((before, after, output))
+                });
+            match outcome {
+                Ok((before, after, output)) => VelocityResult { path, before: stats(&before), after: stats(&after), output, error: None },
+                Err(e) => VelocityResult { path, before: None, after: None, output: None, error: Some(e) },
+            }
+        })
+        .collect();
+    Ok(VelocityBatch { seed, files: results })
+}
+
+fn check(amount: Option<f64>, humanize: Option<f64>) -> Result<(f32, f64), String> {
+    let amount = amount.unwrap_or(1.0);
+    let humanize = humanize.unwrap_or(0.0);
+    if !(0.0..=1.0).contains(&amount) || !(0.0..=1.0).contains(&humanize) {
+        return Err("Amount and humanize go from 0 to 1".to_string());
+    }
+    Ok((amount as f32, humanize))
+}
+
+// Shows what a velocity curve would do to MIDI files (folders are searched
+// for them) without writing anything: the velocity spread of each file
+// before and after
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn preview_velocity_curve(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    paths: Vec<String>,
+    curve: VelocityCurve,
+    amount: Option<f64>,
+    humanize: Option<f64>,
+    expression: Option<midi::Expression>,
+    seed: Option<u64>,
+) -> Result<VelocityBatch, String> {
+    let (amount, humanize) = check(amount, humanize)?;
+    let seed = seed.unwrap_or_else(|| Rng::new(None).next_u64());
+    let expression = expression.unwrap_or_default();
+    let db = db.inner().clone();
+    let label = format!("Preview velocity curve on {} items", paths.len());
+    scheduler
+        .run("analysis", label, Priority::Interactive, move |_| run_batch(&db, &paths, curve, amount, humanize, expression, seed, false, None))
+        .await
+}
+
+// Writes a copy of each MIDI file with its velocities reshaped by `curve`.
+// `amount` 0-1 (default 1) blends toward the curve and `humanize` 0-1 adds a
+// random wander on top; the preview's seed repeats the wander it showed.
+// `expression` "strip" drops bends and pressure; they're kept by default.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn apply_velocity_curve(
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    paths: Vec<String>,
+    curve: VelocityCurve,
+    amount: Option<f64>,
+    humanize: Option<f64>,
+    expression: Option<midi::Expression>,
+    seed: Option<u64>,
+    dest_dir: Option<String>,
+) -> Result<VelocityBatch, String> {
+    let (amount, humanize) = check(amount, humanize)?;
+    let seed = seed.unwrap_or_else(|| Rng::new(None).next_u64());
+    let expression = expression.unwrap_or_default();
+    let db = db.inner().clone();
+    let label = format!("Apply velocity curve to {} items", paths.len());
+    scheduler
+        .run("conversion", label, Priority::Interactive, move |_| {
+            run_batch(&db, &paths, curve, amount, humanize, expression, seed, true, dest_dir.as_deref())
+        })
+        .await
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const NETWORK_FS: &[&str] = &["cifs", "smb3", "smbfs", "nfs", "nfs4", "afpfs", "webdav", "davfs", "fuse.sshfs", "9p"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeKind {
+    Local,
+    // Spinning disk, usually an external USB drive
+    Rotational,
+    Network,
+}
+
+impl VolumeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VolumeKind::Local => "local",
+            VolumeKind::Rotational => "rotational",
+            VolumeKind::Network => "network",
+        }
+    }
+
+    pub fn parse(value: &str) -> VolumeKind {
+        match value {
+            "rotational" => VolumeKind::Rotational,
+            "network" => VolumeKind::Network,
+            _ => VolumeKind::Local,
+        }
+    }
+
+    pub fn is_slow(self) -> bool {
+        self != VolumeKind::Local
+    }
+
+    // Pause between directory reads so a scan doesn't saturate a NAS or
+    // keep a spinning drive seeking flat out
+    pub fn throttle(self) -> Option<Duration> {
+        match self {
+            VolumeKind::Local => None,
+            VolumeKind::Rotational => Some(Duration::from_millis(5)),
+            VolumeKind::Network => Some(Duration::from_millis(25)),
+        }
+    }
+
+    pub fn read_timeout(self) -> Option<Duration> {
+        match self {
+            VolumeKind::Local => None,
+            VolumeKind::Rotational => Some(Duration::from_secs(30)),
+            VolumeKind::Network => Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+// (mount point, device, filesystem type) of the mount containing `path`
+#[cfg(target_os = "linux")]
+fn find_mount(path: &Path) -> Option<(String, String, String)> {
+    let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = fields.next()?.to_string();
+            // /proc/mounts escapes spaces as \040
+            let mount_point = fields.next()?.replace("\\040", " ");
+            let fs_type = fields.next()?.to_string();
+            Some((mount_point, device, fs_type))
+        })
+        .filter(|(mount_point, _, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _, _)| mount_point.len())
+}
+
+#[cfg(target_os = "macos")]
+fn find_mount(path: &Path) -> Option<(String, String, String)> {
+    // "//user@nas/Samples on /Volumes/Samples (smbfs, nodev, nosuid, mounted by user)"
+    let output = std::process::Command::new("mount").output().ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (device, rest) = line.split_once(" on ")?;
+            let (mount_point, options) = rest.rsplit_once(" (")?;
+            let fs_type = options.split(',').next()?.trim().to_string();
+            Some((mount_point.to_string(), device.to_string(), fs_type))
+        })
+        .filter(|(mount_point, _, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _, _)| mount_point.len())
+}
+
+#[cfg(target_os = "linux")]
+fn is_rotational(device: &str) -> bool {
+    let name = match device.strip_prefix("/dev/") {
+        Some(name) => name,
+        None => return false,
+    };
+    // Partitions keep their queue settings on the parent disk
+    [
+        format!("/sys/class/block/{}/queue/rotational", name),
+        format!("/sys/class/block/{}/../queue/rotational", name),
+    ]
+    .iter()
+    .find_map(|p| std::fs::read_to_string(p).ok())
+    .map(|v| v.trim() == "1")
+    .unwrap_or(false)
+}
+
+#[cfg(target_os = "macos")]
+fn is_rotational(mount_point: &str) -> bool {
+    std::process::Command::new("diskutil")
+        .args(["info", mount_point])
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .any(|line| line.trim().starts_with("Solid State:") && line.trim().ends_with("No"))
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(target_os = "windows")]
+pub fn detect(path: &Path) -> VolumeKind {
+    let text = path.to_string_lossy();
+    if (text.starts_with("\\\\") && !text.starts_with("\\\\?\\")) || text.starts_with("\\\\?\\UNC\\") {
+        return VolumeKind::Network;
+    }
+
+    // Mapped network drives show up in `net use`
+    let drive = match text.get(..2) {
+        Some(drive) if drive.ends_with(':') => drive.to_uppercase(),
+        _ => return VolumeKind::Local,
+    };
+    let mapped = std::process::Command::new("net")
+        .arg("use")
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .any(|line| line.split_whitespace().any(|field| field.eq_ignore_ascii_case(&drive)))
+        })
+        .unwrap_or(false);
+    if mapped {
+        VolumeKind::Network
+    } else {
+        VolumeKind::Local
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn detect(path: &Path) -> VolumeKind {
+    let resolved = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let (mount_point, device, fs_type) = match find_mount(&resolved) {
+        Some(mount) => mount,
+        None => return VolumeKind::Local,
+    };
+
+    if NETWORK_FS.contains(&fs_type.as_str()) || device.starts_with("//") {
+        return VolumeKind::Network;
+    }
+
+    let rotational = if cfg!(target_os = "linux") {
+        is_rotational(&device)
+    } else {
+        is_rotational(&mount_point)
+    };
+    if rotational {
+        VolumeKind::Rotational
+    } else {
+        VolumeKind::Local
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub fn detect(_path: &Path) -> VolumeKind {
+    VolumeKind::Local
+}
+
+// Whether files can be created in `dir`. Locked SD cards, read-only mounts
+// and folders without write permission all fail the same way, so try it.
+pub fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".aistudio-write-check-{}", std::process::id()));
+    match std::fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            true
+        }
+        Err(e) => e.kind() == std::io::ErrorKind::AlreadyExists,
+    }
+}
@@ -0,0 +1,295 @@
+use crate::db::Db;
+use crate::jobs::Scheduler;
+use crate::roots::{self, Watchers};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+// A holder that hasn't touched the lock for this long has crashed, been
+// killed, or lost the drive the library is on
+const STALE_AFTER_SECONDS: i64 = 120;
+
+// Who has a library database open, written next to it as <db>.lock. Sync
+// tools carry the file along, so another machine sees it too.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct LockHolder {
+    // Tells this instance's lock from another's on the same machine
+    token: String,
+    pid: u32,
+    host: String,
+    started_at: i64,
+    heartbeat_at: i64,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct LockStatus {
+    held: bool,
+    database: String,
+    // The other instance holding it, when this one doesn't
+    holder: Option<LockHolder>,
+    // The holder looks gone, so taking over is safe
+    stale: bool,
+}
+
+struct LockState {
+    path: PathBuf,
+    held: bool,
+    holder: Option<LockHolder>,
+}
+
+pub struct WorkspaceLock {
+    token: String,
+    started_at: i64,
+    state: Mutex<LockState>,
+    // Launch holds off the background work until the lock is this one's
+    background_started: AtomicBool,
+}
+
+fn lock_path(database: &Path) -> PathBuf {
+    let mut name = database.as_os_str().to_owned();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+fn host_name() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| {
+            let output = std::process::Command::new("hostname").output().ok()?;
+            Some(String::from_utf8_lossy(&output.stdout).to_string())
+        })
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    // Signal 0 checks the process exists without touching it; EPERM means it
+    // does but belongs to someone else
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+    use windows_sys::Win32::System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle == 0 {
+            return false;
+        }
+        let mut code = 0u32;
+        let alive = GetExitCodeProcess(handle, &mut code) != 0 && code == STILL_ACTIVE as u32;
+        CloseHandle(handle);
+        alive
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn process_alive(_pid: u32) -> bool {
+    // Left to the heartbeat
+    true
+}
+
+fn is_stale(holder: &LockHolder) -> bool {
+    if crate::db::now() - holder.heartbeat_at > STALE_AFTER_SECONDS {
+        return true;
+    }
+    holder.host == host_name() && !process_alive(holder.pid)
+}
+
+fn read(path: &Path) -> Option<LockHolder> {
+    fs::read(path).ok().and_then(|bytes| serde_json::from_slice(&bytes).ok())
+}
+
+impl WorkspaceLock {
+    // Takes the lock on `database` if it's free or stale; otherwise notes
+    // who has it, for the frontend to offer a takeover
+    pub fn acquire(database: &Path) -> Self {
+        let now = crate::db::now();
+        let lock = WorkspaceLock {
+            token: format!("{}-{}", std::process::id(), now),
+            started_at: now,
+            state: Mutex::new(LockState { path: lock_path(database), held: false, holder: None }),
+            background_started: AtomicBool::new(false),
+        };
+        let _ = lock.claim(database, false);
+        lock
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self, path: &Path) -> Result<(), String> {
+        let holder = LockHolder {
+            token: self.token.clone(),
+            pid: std::process::id(),
+            host: host_name(),
+            started_at: self.started_at,
+            heartbeat_at: crate::db::now(),
+        };
+        let json = serde_json::to_vec_pretty(&holder).map_err(|e| e.to_string())?;
+        // The first launch locks the library before it's created
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let temp = path.with_file_name(format!(".{}.part", crate::paths::file_name(path)));
+        fs::write(&temp, json).map_err(|e| format!("Failed to write lock file: {}", e))?;
+        fs::rename(&temp, path).map_err(|e| format!("Failed to write lock file: {}", e))
+    }
+
+    // Locks `database`, releasing any other database this instance held.
+    // Returns false, leaving the lock alone, when another live instance has
+    // it and `force` isn't set.
+    pub fn claim(&self, database: &Path, force: bool) -> Result<bool, String> {
+        let path = lock_path(database);
+        let existing = read(&path).filter(|holder| holder.token != self.token);
+        if let Some(holder) = existing.filter(|holder| !force && !is_stale(holder)) {
+            let mut state = self.lock();
+            if state.path == path {
+                state.held = false;
+                state.holder = Some(holder);
+            }
+            return Ok(false);
+        }
+        self.write(&path)?;
+        let mut state = self.lock();
+        if state.held && state.path != path {
+            let _ = fs::remove_file(&state.path);
+        }
+        *state = LockState { path, held: true, holder: None };
+        Ok(true)
+    }
+
+    // Refuses when another live instance holds `database`
+    pub fn check_free(&self, database: &Path) -> Result<(), String> {
+        match read(&lock_path(database)).filter(|holder| holder.token != self.token && !is_stale(holder)) {
+            Some(holder) => Err(format!("That library is open in another instance (on {}, process {})", holder.host, holder.pid)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn release(&self) {
+        let mut state = self.lock();
+        if state.held && read(&state.path).map(|holder| holder.token == self.token).unwrap_or(false) {
+            let _ = fs::remove_file(&state.path);
+        }
+        state.held = false;
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.lock().held
+    }
+
+    pub fn status(&self, database: &Path) -> LockStatus {
+        let state = self.lock();
+        LockStatus {
+            held: state.held,
+            database: crate::paths::display(database),
+            stale: state.holder.as_ref().map(is_stale).unwrap_or(false),
+            holder: state.holder.clone(),
+        }
+    }
+
+    // Refreshes the heartbeat while held. Returns true when another instance
+    // has taken the lock since the last beat.
+    fn beat(&self) -> bool {
+        let path = self.lock().path.clone();
+        if !self.is_held() {
+            return false;
+        }
+        match read(&path) {
+            Some(holder) if holder.token != self.token => {
+                let mut state = self.lock();
+                state.held = false;
+                state.holder = Some(holder);
+                true
+            }
+            _ => {
+                let _ = self.write(&path);
+                false
+            }
+        }
+    }
+}
+
+// While the lock is someone else's, this instance starts no jobs and
+// watches nothing, so only one of them writes to the database
+fn stand_down(app: &AppHandle) {
+    let scheduler = app.state::<Scheduler>();
+    scheduler.cancel_queued();
+    let _ = scheduler.pause();
+    app.state::<Watchers>().clear();
+}
+
+// Picks up again once this instance has the lock: resumes jobs and
+// reopens the watchers, or starts the background work launch held off
+pub fn resume(app: &AppHandle) -> Result<(), String> {
+    app.state::<Scheduler>().resume();
+    if app.state::<WorkspaceLock>().background_started.swap(true, Ordering::SeqCst) {
+        roots::reopen(&app.state::<Db>(), &app.state::<Watchers>())
+    } else {
+        crate::start_background(app)
+    }
+}
+
+// Starts the background work if this instance got the lock at launch,
+// holds everything off if not, and keeps the heartbeat going
+pub fn start(app: &AppHandle) -> Result<(), String> {
+    if app.state::<WorkspaceLock>().is_held() {
+        resume(app)?;
+    } else {
+        stand_down(app);
+    }
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let lock = app.state::<WorkspaceLock>();
+            if lock.beat() {
+                stand_down(&app);
+                let database = app.state::<Db>().path();
+                let _ = app.emit("workspace-lock-lost", lock.status(&database));
+            }
+        }
+    });
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_workspace_lock(lock: State<'_, WorkspaceLock>, db: State<'_, Db>) -> Result<LockStatus, String> {
+    Ok(lock.status(&db.path()))
+}
+
+// Takes the library over from another instance. A stale lock is taken
+// straight away; one that's still live needs `force`, after the user has
+// confirmed the other instance is closed or can be cut off.
+#[tauri::command]
+pub async fn take_over_workspace(
+    app: AppHandle,
+    lock: State<'_, WorkspaceLock>,
+    db: State<'_, Db>,
+    scheduler: State<'_, Scheduler>,
+    force: Option<bool>,
+) -> Result<LockStatus, String> {
+    let database = db.path();
+    if !lock.claim(&database, force.unwrap_or(false))? {
+        return Err("The library is still open in another instance; close it there, or take over anyway".to_string());
+    }
+    // Reopening migrates a library that was left as it was at launch
+    db.switch_to(&database)?;
+    scheduler.configure(&db);
+    resume(&app)?;
+    let status = lock.status(&database);
+    let _ = app.emit("workspace-lock-changed", &status);
+    Ok(status)
+}